@@ -0,0 +1,110 @@
+#![feature(test)]
+
+extern crate test;
+
+use mimir::objects::{Admin, Coord, Street};
+use mimir::rubber::{read_places_from_slice, read_raw_places, read_suggestions_from_slice};
+use std::sync::Arc;
+
+// body of an ES response with `nb` streets, each with its admins
+fn search_response(nb: usize) -> Vec<u8> {
+    let coord = Coord::new(2.376_379, 48.846_495);
+    let admins: Vec<_> = ["Paris", "Île-de-France", "France"]
+        .iter()
+        .map(|name| {
+            Arc::new(Admin {
+                id: format!("admin:{}", name),
+                name: name.to_string(),
+                label: name.to_string(),
+                coord,
+                zip_codes: vec!["75012".to_string()],
+                ..Default::default()
+            })
+        })
+        .collect();
+    let hits: Vec<_> = (0..nb)
+        .map(|i| {
+            let street = Street {
+                id: format!("street:{}", i),
+                name: "Rue de Bercy".to_string(),
+                label: "Rue de Bercy (Paris)".to_string(),
+                coord,
+                administrative_regions: admins.clone(),
+                zip_codes: vec!["75012".to_string()],
+                ..Default::default()
+            };
+            serde_json::json!({
+                "_index": "munin_street",
+                "_type": "street",
+                "_id": street.id,
+                "_score": 1.5,
+                "_source": street,
+            })
+        })
+        .collect();
+    serde_json::json!({ "took": 3, "hits": { "total": nb, "max_score": 1.5, "hits": hits } })
+        .to_string()
+        .into_bytes()
+}
+
+// body of the same ES response, fetching only the fields of the suggestions
+fn suggestions_response(nb: usize) -> Vec<u8> {
+    let hits: Vec<_> = (0..nb)
+        .map(|i| {
+            serde_json::json!({
+                "_index": "munin_street",
+                "_type": "street",
+                "_id": format!("street:{}", i),
+                "_score": 1.5,
+                "_source": {
+                    "id": format!("street:{}", i),
+                    "label": "Rue de Bercy (Paris)",
+                    "coord": { "lat": 48.846_495, "lon": 2.376_379 },
+                },
+            })
+        })
+        .collect();
+    serde_json::json!({ "took": 3, "hits": { "total": nb, "max_score": 1.5, "hits": hits } })
+        .to_string()
+        .into_bytes()
+}
+
+#[bench]
+fn read_places_from_json_value(b: &mut test::Bencher) {
+    let body = search_response(10);
+    b.iter(|| {
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        read_raw_places(response, None).unwrap()
+    });
+}
+
+#[bench]
+fn read_places_from_response_body(b: &mut test::Bencher) {
+    let body = search_response(10);
+    b.iter(|| read_places_from_slice(&body, None).unwrap());
+}
+
+// ES response with a place of each type (also read by the tests of `read_places_from_slice`)
+const GOLDEN_SEARCH_RESPONSE: &[u8] =
+    include_bytes!("../../../tests/fixtures/es_search_response.json");
+
+#[bench]
+fn read_golden_places_from_json_value(b: &mut test::Bencher) {
+    let coord = Coord::new(2.3767, 48.8462);
+    b.iter(|| {
+        let response: serde_json::Value = serde_json::from_slice(GOLDEN_SEARCH_RESPONSE).unwrap();
+        read_raw_places(response, Some(&coord)).unwrap()
+    });
+}
+
+#[bench]
+fn read_golden_places_from_response_body(b: &mut test::Bencher) {
+    let coord = Coord::new(2.3767, 48.8462);
+    b.iter(|| read_places_from_slice(GOLDEN_SEARCH_RESPONSE, Some(&coord)).unwrap());
+}
+
+#[bench]
+fn read_suggestions_from_response_body(b: &mut test::Bencher) {
+    let body = suggestions_response(10);
+    b.iter(|| read_suggestions_from_slice(&body, None).unwrap());
+}
@@ -0,0 +1,24 @@
+#![feature(test)]
+
+extern crate test;
+
+use bragi::QuerySettings;
+use std::sync::Arc;
+
+fn query_settings() -> QuerySettings {
+    QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap()
+}
+
+// what each request used to do
+#[bench]
+fn bench_clone_query_settings(b: &mut test::Bencher) {
+    let settings = query_settings();
+    b.iter(|| test::black_box(settings.clone()));
+}
+
+// what each worker does now, the requests only borrow the settings
+#[bench]
+fn bench_share_query_settings(b: &mut test::Bencher) {
+    let settings = Arc::new(query_settings());
+    b.iter(|| test::black_box(Arc::clone(&settings)));
+}
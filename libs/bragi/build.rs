@@ -0,0 +1,58 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// output of a command, empty if it cannot be run (for example outside of a git checkout)
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn main() {
+    // Embeds the build information shown in /status, read with `env!` like `CARGO_PKG_VERSION`
+    let commit = command_output("git", &["rev-parse", "HEAD"]);
+    println!("cargo:rustc-env=BRAGI_GIT_COMMIT={}", commit);
+
+    // SOURCE_DATE_EPOCH gives the same build date to reproducible builds
+    let build_timestamp = env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            .to_string()
+    });
+    println!("cargo:rustc-env=BRAGI_BUILD_TIMESTAMP={}", build_timestamp);
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    println!(
+        "cargo:rustc-env=BRAGI_RUSTC_VERSION={}",
+        command_output(&rustc, &["--version"])
+    );
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BRAGI_FEATURES={}", features.join(","));
+
+    // the build information is updated on each commit, not on each build
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let git_dir = command_output("git", &["rev-parse", "--absolute-git-dir"]);
+    if !git_dir.is_empty() {
+        // a missing path would re-run the build script on each build
+        for path in &["HEAD", "refs", "packed-refs"] {
+            let path = Path::new(&git_dir).join(path);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+}
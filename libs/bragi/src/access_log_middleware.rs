@@ -0,0 +1,124 @@
+// Request line of the access logs, without the api key of the request.
+//
+// The `%r` of actix's `Logger` is the raw request line, which would write the `apikey` parameter
+// of the requests in the logs. This middleware, wrapping the `Logger`, gives the request line with
+// the api key redacted in a request header, logged by `ACCESS_LOG_FORMAT` in place of `%r`.
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::future::{ok, FutureResult};
+use futures::Poll;
+
+/// Header of the requests giving their redacted request line to the access logs
+pub const REQUEST_LINE_HEADER: &str = "x-bragi-request-line";
+
+// the parameters not written in the logs
+const REDACTED_PARAMS: [&str; 1] = ["apikey"];
+
+/// The query string with the value of the parameters giving an api key replaced by `***`
+pub fn redact_query(query_string: &str) -> String {
+    query_string
+        .split('&')
+        .map(|param| {
+            let name = param.split('=').next().unwrap_or_default();
+            if REDACTED_PARAMS.contains(&name) {
+                format!("{}=***", name)
+            } else {
+                param.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn request_line(req: &ServiceRequest) -> String {
+    let query = req.query_string();
+    if query.is_empty() {
+        format!("{} {} {:?}", req.method(), req.path(), req.version())
+    } else {
+        format!(
+            "{} {}?{} {:?}",
+            req.method(),
+            req.path(),
+            redact_query(query),
+            req.version()
+        )
+    }
+}
+
+#[derive(Clone, Default)]
+#[must_use = "must be set up as middleware for actix-web"]
+/// Middleware giving the redacted request line to the access logs, it must wrap the `Logger`
+pub struct RedactedRequestLine;
+
+impl<S, B> Transform<S> for RedactedRequestLine
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RedactedRequestLineMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RedactedRequestLineMiddleware { service })
+    }
+}
+
+#[doc(hidden)]
+/// Middleware service for RedactedRequestLine
+pub struct RedactedRequestLineMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RedactedRequestLineMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        // the header of the client is always replaced, so that it cannot forge the logs
+        let line = HeaderValue::from_str(&request_line(&req))
+            .unwrap_or_else(|_| HeaderValue::from_static("-"));
+        req.headers_mut()
+            .insert(HeaderName::from_static(REQUEST_LINE_HEADER), line);
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn api_keys_are_redacted() {
+        assert_eq!(
+            redact_query("q=rue%20hector%20malot&apikey=secret&limit=5"),
+            "q=rue%20hector%20malot&apikey=***&limit=5"
+        );
+        assert_eq!(redact_query("q=apikey&myapikey=1"), "q=apikey&myapikey=1");
+
+        let req = TestRequest::with_uri("/autocomplete?apikey=secret&q=paris").to_srv_request();
+        assert_eq!(
+            request_line(&req),
+            "GET /autocomplete?apikey=***&q=paris HTTP/1.1"
+        );
+    }
+}
@@ -0,0 +1,516 @@
+// Authentication of the requests with api keys, to restrict the partners to some places.
+//
+// The keys are read from the file given by `[api_keys]` in the settings. Each key gives the
+// profile of a partner: the types and datasets it can search, the shape its places must be in,
+// and its rate limit and daily quota. The file is read again after a SIGHUP.
+//
+// Since the shapes of the profiles can be large, the profiles are cached within the memory budget
+// of the caches (see `memory`): only the keys are always kept, the profiles evicted are read again
+// from the file when their key is used. The approximate memory footprint of all the keys of the
+// file is logged when they are (re)loaded.
+
+use crate::memory::{Lru, MemoryBudget};
+use crate::model::BragiError;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::HttpRequest;
+use geojson::{GeoJson, Geometry};
+use serde::{Deserialize, Deserializer};
+use slog_scope::{error, info};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+lazy_static::lazy_static! {
+    static ref API_KEY_REQUESTS_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_api_key_requests_total",
+        "Total number of requests by profile of api key, and by outcome (accepted, unauthorized, rate_limited or over_quota).",
+        &["profile", "outcome"]
+    )
+    .unwrap();
+}
+
+// set by the SIGHUP handler, the keys are read again by the next request
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What a partner can search with its api key
+#[derive(Debug, Deserialize)]
+pub struct Profile {
+    /// Name of the partner, shown in the logs and the metrics instead of its key
+    pub name: String,
+    /// Types of places (as given in `type[]`) the partner can search, all the types if empty
+    #[serde(default)]
+    pub types: Vec<String>,
+    /// Datasets of the places the partner can search, all the datasets if empty
+    #[serde(default)]
+    pub datasets: Vec<String>,
+    /// GeoJSON (a geometry or a feature) of the shape containing all the places of the partner
+    #[serde(default, deserialize_with = "deserialize_shape")]
+    pub shape: Option<Geometry>,
+    /// Maximum number of requests per second
+    pub rate_limit: Option<u64>,
+    /// Maximum number of requests per day (in UTC)
+    pub daily_quota: Option<u64>,
+}
+
+impl Profile {
+    /// A restricted profile can only use the apis able to apply its restrictions
+    pub fn is_restricted(&self) -> bool {
+        !self.types.is_empty() || !self.datasets.is_empty() || self.shape.is_some()
+    }
+
+    /// Approximate number of bytes used by the profile, mostly by its shape
+    fn memory_footprint(&self) -> usize {
+        let strings: usize = std::iter::once(&self.name)
+            .chain(&self.types)
+            .chain(&self.datasets)
+            .map(|value| size_of::<String>() + value.capacity())
+            .sum();
+        size_of::<Profile>() + strings + self.shape.as_ref().map_or(0, |s| geometry_size(&s.value))
+    }
+}
+
+// the positions of the geojson are vectors of coordinates
+fn positions_size(positions: &[geojson::Position]) -> usize {
+    positions
+        .iter()
+        .map(|position| size_of::<geojson::Position>() + position.capacity() * size_of::<f64>())
+        .sum()
+}
+
+fn geometry_size(value: &geojson::Value) -> usize {
+    use geojson::Value;
+    match value {
+        Value::Point(position) => positions_size(std::slice::from_ref(position)),
+        Value::MultiPoint(positions) | Value::LineString(positions) => positions_size(positions),
+        Value::MultiLineString(lines) | Value::Polygon(lines) => lines
+            .iter()
+            .map(|line| size_of::<Vec<geojson::Position>>() + positions_size(line))
+            .sum(),
+        Value::MultiPolygon(polygons) => polygons
+            .iter()
+            .flatten()
+            .map(|line| size_of::<Vec<geojson::Position>>() + positions_size(line))
+            .sum(),
+        Value::GeometryCollection(geometries) => geometries
+            .iter()
+            .map(|geometry| size_of::<Geometry>() + geometry_size(&geometry.value))
+            .sum(),
+    }
+}
+
+// Approximate number of bytes used by a profile and its key
+fn profile_size(key: &str, profile: &Profile) -> usize {
+    size_of::<String>() + key.len() + profile.memory_footprint()
+}
+
+/// Approximate number of bytes used by the profiles and their keys
+fn profiles_size(profiles: &HashMap<String, Arc<Profile>>) -> usize {
+    profiles
+        .iter()
+        .map(|(key, profile)| profile_size(key, profile))
+        .sum()
+}
+
+// logs the keys (re)loaded, with their memory footprint
+fn report_loaded(profiles: &HashMap<String, Arc<Profile>>, file: &Path, action: &str) {
+    let size = profiles_size(profiles);
+    info!(
+        "{} api keys {} from `{}`, using about {} KiB",
+        profiles.len(),
+        action,
+        file.display(),
+        size / 1024
+    );
+}
+
+fn deserialize_shape<'de, D>(deserializer: D) -> Result<Option<Geometry>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|shape| match shape.parse::<GeoJson>() {
+            Ok(GeoJson::Geometry(geometry)) => Ok(geometry),
+            Ok(GeoJson::Feature(feature)) => feature
+                .geometry
+                .ok_or_else(|| serde::de::Error::custom("the shape feature has no geometry")),
+            Ok(GeoJson::FeatureCollection(_)) => Err(serde::de::Error::custom(
+                "the shape should be a geometry or a feature",
+            )),
+            Err(e) => Err(serde::de::Error::custom(format!("invalid shape: {}", e))),
+        })
+        .transpose()
+}
+
+#[derive(Debug, Deserialize)]
+struct KeysFile {
+    #[serde(default)]
+    keys: HashMap<String, Profile>,
+}
+
+fn read_profiles(file: &Path) -> Result<HashMap<String, Arc<Profile>>, String> {
+    let content =
+        read_to_string(file).map_err(|e| format!("Failed to read `{}`: {}", file.display(), e))?;
+    let keys: KeysFile = toml::from_str(&content)
+        .map_err(|e| format!("failed to parse `{}`: {}", file.display(), e))?;
+    Ok(keys
+        .keys
+        .into_iter()
+        .map(|(key, mut profile)| {
+            // like the filters of the requests, they are matched whatever their case
+            for value in profile.types.iter_mut().chain(profile.datasets.iter_mut()) {
+                *value = mimir::canonical_case(value);
+            }
+            (key, Arc::new(profile))
+        })
+        .collect())
+}
+
+// requests of a key in the current second and in the current day
+#[derive(Debug, Default)]
+struct Usage {
+    second: u64,
+    nb_in_second: u64,
+    day: u64,
+    nb_in_day: u64,
+}
+
+impl Usage {
+    // counts a request made at `now` (in seconds since the epoch) if the profile allows it
+    fn record(&mut self, profile: &Profile, now: u64) -> Result<(), BragiError> {
+        if self.second != now {
+            self.second = now;
+            self.nb_in_second = 0;
+        }
+        let day = now / SECONDS_PER_DAY;
+        if self.day != day {
+            self.day = day;
+            self.nb_in_day = 0;
+        }
+        if profile
+            .rate_limit
+            .map_or(false, |limit| self.nb_in_second >= limit)
+        {
+            return Err(BragiError::RateLimited);
+        }
+        if profile
+            .daily_quota
+            .map_or(false, |quota| self.nb_in_day >= quota)
+        {
+            return Err(BragiError::QuotaExceeded {
+                retry_after: Duration::from_secs(SECONDS_PER_DAY - now % SECONDS_PER_DAY),
+            });
+        }
+        self.nb_in_second += 1;
+        self.nb_in_day += 1;
+        Ok(())
+    }
+}
+
+/// The api keys, with the usage of each key
+#[derive(Debug)]
+pub struct ApiKeys {
+    file: PathBuf,
+    keys: RwLock<HashSet<String>>,
+    // the profiles of the keys, the evicted ones are read again from the file
+    profiles: Mutex<Lru<String, Arc<Profile>>>,
+    usages: Mutex<HashMap<String, Usage>>,
+}
+
+impl ApiKeys {
+    pub fn new(file: &Path, budget: Arc<MemoryBudget>) -> Result<Self, String> {
+        let api_keys = ApiKeys {
+            file: file.to_owned(),
+            keys: RwLock::default(),
+            profiles: Mutex::new(Lru::new("api_keys", budget)),
+            usages: Mutex::default(),
+        };
+        let profiles = read_profiles(file)?;
+        report_loaded(&profiles, file, "loaded");
+        api_keys.set_profiles(profiles);
+        Ok(api_keys)
+    }
+
+    /// Reads the keys file again, the current keys are kept if it is invalid
+    pub fn reload(&self) {
+        match read_profiles(&self.file) {
+            Ok(profiles) => {
+                report_loaded(&profiles, &self.file, "reloaded");
+                self.set_profiles(profiles);
+            }
+            Err(e) => error!("the api keys have not been reloaded: {}", e),
+        }
+    }
+
+    // the profiles are cached while they fit in the budget
+    fn set_profiles(&self, profiles: HashMap<String, Arc<Profile>>) {
+        let mut cache = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        cache.clear();
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = profiles.keys().cloned().collect();
+        for (key, profile) in profiles {
+            let size = profile_size(&key, &profile);
+            cache.insert(key, profile, size);
+        }
+    }
+
+    // the profile of a valid key, read again from the file if it has been evicted
+    fn profile(&self, key: &str) -> Option<Arc<Profile>> {
+        if !self
+            .keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(key)
+        {
+            return None;
+        }
+        let key = key.to_string();
+        let cached = self
+            .profiles
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .cloned();
+        cached.or_else(|| {
+            let profile = read_profiles(&self.file)
+                .map_err(|e| error!("the evicted api key cannot be read again: {}", e))
+                .ok()?
+                .remove(&key)?;
+            let size = profile_size(&key, &profile);
+            self.profiles
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key, profile.clone(), size);
+            Some(profile)
+        })
+    }
+
+    /// Profile of the key, if the key is valid and its limits are not reached
+    pub fn authenticate(&self, key: Option<&str>) -> Result<Arc<Profile>, BragiError> {
+        if RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+            self.reload();
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.authenticate_at(key, now)
+    }
+
+    fn authenticate_at(&self, key: Option<&str>, now: u64) -> Result<Arc<Profile>, BragiError> {
+        let profile = key.and_then(|key| self.profile(key));
+        let (key, profile) = match (key, profile) {
+            (Some(key), Some(profile)) => (key, profile),
+            _ => {
+                API_KEY_REQUESTS_COUNTER
+                    .with_label_values(&["", "unauthorized"])
+                    .inc();
+                return Err(BragiError::Unauthorized);
+            }
+        };
+        let res = self
+            .usages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.to_string())
+            .or_default()
+            .record(&profile, now);
+        let outcome = match res {
+            Ok(()) => "accepted",
+            Err(BragiError::RateLimited) => "rate_limited",
+            Err(_) => "over_quota",
+        };
+        API_KEY_REQUESTS_COUNTER
+            .with_label_values(&[&profile.name, outcome])
+            .inc();
+        res.map(|_| profile)
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyParam {
+    apikey: Option<String>,
+}
+
+/// Api key of the request, given in its `Authorization` header (optionally as a bearer token)
+/// or in its `apikey` parameter
+pub fn request_key(req: &HttpRequest) -> Option<String> {
+    let header = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .map(|header| header.trim_start_matches("Bearer ").trim().to_string());
+    header.filter(|key| !key.is_empty()).or_else(|| {
+        serde_qs::Config::new(5, false)
+            .deserialize_str::<KeyParam>(req.query_string())
+            .ok()
+            .and_then(|param| param.apikey)
+    })
+}
+
+/// Reads the api keys again at the next request after a SIGHUP
+pub fn reload_on_sighup() -> Result<(), String> {
+    // Safety: the handler only sets an atomic flag, which is safe in a signal handler
+    unsafe {
+        signal_hook_registry::register(libc::SIGHUP, || {
+            RELOAD_REQUESTED.store(true, Ordering::Relaxed)
+        })
+    }
+    .map(|_| ())
+    .map_err(|e| format!("failed to handle SIGHUP: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(rate_limit: Option<u64>, daily_quota: Option<u64>) -> Profile {
+        Profile {
+            name: "partner".to_string(),
+            types: vec![],
+            datasets: vec![],
+            shape: None,
+            rate_limit,
+            daily_quota,
+        }
+    }
+
+    #[test]
+    fn requests_are_limited_per_second() {
+        let profile = profile(Some(2), None);
+        let mut usage = Usage::default();
+        assert!(usage.record(&profile, 1000).is_ok());
+        assert!(usage.record(&profile, 1000).is_ok());
+        assert!(matches!(
+            usage.record(&profile, 1000),
+            Err(BragiError::RateLimited)
+        ));
+        // the rejected requests are not counted
+        assert!(usage.record(&profile, 1001).is_ok());
+    }
+
+    #[test]
+    fn requests_are_limited_per_day() {
+        let profile = profile(None, Some(2));
+        let mut usage = Usage::default();
+        let day = 18_000 * SECONDS_PER_DAY;
+        assert!(usage.record(&profile, day).is_ok());
+        assert!(usage.record(&profile, day + 10).is_ok());
+        match usage.record(&profile, day + 20) {
+            Err(BragiError::QuotaExceeded { retry_after }) => {
+                assert_eq!(retry_after, Duration::from_secs(SECONDS_PER_DAY - 20))
+            }
+            res => panic!("the quota should be exceeded: {:?}", res),
+        }
+        assert!(usage.record(&profile, day + SECONDS_PER_DAY).is_ok());
+    }
+
+    #[test]
+    fn keys_file_is_parsed() {
+        let keys: KeysFile = toml::from_str(
+            r#"
+            [keys.secret]
+            name = "partner"
+            types = ["poi"]
+            shape = '{"type": "Polygon", "coordinates": [[[2.0, 48.0], [3.0, 48.0], [3.0, 49.0], [2.0, 48.0]]]}'
+            daily_quota = 1000
+            "#,
+        )
+        .unwrap();
+        let profile = &keys.keys["secret"];
+        assert_eq!(profile.name, "partner");
+        assert!(profile.shape.is_some());
+        assert!(profile.is_restricted());
+        assert_eq!(profile.rate_limit, None);
+
+        let invalid: Result<KeysFile, _> = toml::from_str(
+            r#"
+            [keys.secret]
+            name = "partner"
+            shape = '{"type": "FeatureCollection", "features": []}'
+            "#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn memory_footprint_grows_with_the_shapes() {
+        let small = profile(None, None);
+        // a large synthetic shape, like the detailed boundary of a region
+        let nb_vertices = 100_000;
+        let ring: Vec<Vec<f64>> = (0..=nb_vertices)
+            .map(|i| {
+                let angle = f64::from(i) * 2. * std::f64::consts::PI / f64::from(nb_vertices);
+                vec![2.35 + angle.cos(), 48.85 + angle.sin()]
+            })
+            .collect();
+        let large = Profile {
+            shape: Some(Geometry::new(geojson::Value::Polygon(vec![ring]))),
+            ..profile(None, None)
+        };
+        assert!(small.memory_footprint() < 1024);
+        let vertices_size = nb_vertices as usize * 2 * size_of::<f64>();
+        assert!(large.memory_footprint() > vertices_size);
+        assert!(large.memory_footprint() < 4 * vertices_size);
+
+        let profiles: HashMap<String, Arc<Profile>> = vec![
+            ("small".to_string(), Arc::new(small)),
+            ("large".to_string(), Arc::new(large)),
+        ]
+        .into_iter()
+        .collect();
+        assert!(profiles_size(&profiles) > vertices_size);
+    }
+
+    #[test]
+    fn evicted_profiles_are_read_again() {
+        let dir = std::env::temp_dir().join("bragi_api_keys_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // large synthetic shapes, a single one fits in the budget
+        let ring: Vec<String> = (0..=20_000)
+            .map(|i| {
+                let angle = f64::from(i) * 2. * std::f64::consts::PI / 20_000.;
+                format!("[{}, {}]", 2.35 + angle.cos(), 48.85 + angle.sin())
+            })
+            .collect();
+        let shape = format!(
+            r#"{{"type": "Polygon", "coordinates": [[{}]]}}"#,
+            ring.join(", ")
+        );
+        let file = dir.join("keys.toml");
+        std::fs::write(
+            &file,
+            format!(
+                "[keys.first]\nname = \"first\"\nshape = '{0}'\n\
+                 [keys.second]\nname = \"second\"\nshape = '{0}'\n",
+                shape
+            ),
+        )
+        .unwrap();
+        let profiles = read_profiles(&file).unwrap();
+        let budget_size = profile_size("first", &profiles["first"]) * 3 / 2;
+        let budget = Arc::new(MemoryBudget::new(budget_size));
+        let api_keys = ApiKeys::new(&file, budget.clone()).unwrap();
+        assert!(budget.used() <= budget_size);
+        // the second profile has evicted the first one
+        assert_eq!(budget.usages()["api_keys"].evictions, 1);
+
+        let now = 18_000 * SECONDS_PER_DAY;
+        for key in &["first", "second", "first", "second"] {
+            let profile = api_keys.authenticate_at(Some(key), now).unwrap();
+            assert_eq!(profile.name, *key);
+            assert!(budget.used() <= budget_size);
+        }
+        assert!(budget.usages()["api_keys"].evictions >= 4);
+        assert!(matches!(
+            api_keys.authenticate_at(Some("third"), now),
+            Err(BragiError::Unauthorized)
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
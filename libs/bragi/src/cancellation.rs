@@ -0,0 +1,95 @@
+// Cancellation of the ES searches of the requests whose client has disconnected.
+//
+// An impatient client often aborts its request (typically an autocomplete, cancelled by the next
+// keystroke). The searches of a request run on a blocking thread, and actix drops the future of
+// the request when its client disconnects: the guard held by this future then cancels the request.
+// The searches not sent yet to ES are skipped, and the concurrent searches by type are no longer
+// waited for. The searches already sent are aborted: their connection to ES is closed without
+// waiting for the response (ES 2 has no task cancellation, the search itself ends at its ES timeout).
+
+use crate::model::BragiError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+lazy_static::lazy_static! {
+    static ref CANCELLED_REQUESTS_COUNTER: prometheus::Counter = prometheus::register_counter!(
+        "bragi_cancelled_requests_total",
+        "Total number of requests cancelled because their client disconnected before the response."
+    )
+    .unwrap();
+}
+
+/// Cancellation state of a request, shared between the request and its searches
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Flag set once the request is cancelled, to abort its searches (see `Rubber::with_abort`)
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Fails with a `BragiError::Cancelled` if the request has been cancelled
+    pub fn check(&self) -> Result<(), BragiError> {
+        if self.is_cancelled() {
+            Err(BragiError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Guard cancelling the request if it is dropped before being disarmed
+    pub fn guard(&self) -> CancelOnDrop {
+        CancelOnDrop {
+            cancellation: Some(self.clone()),
+        }
+    }
+}
+
+#[must_use = "the request is cancelled as soon as the guard is dropped"]
+pub struct CancelOnDrop {
+    cancellation: Option<Cancellation>,
+}
+
+impl CancelOnDrop {
+    /// The request has been answered, dropping the guard no longer cancels it
+    pub fn disarm(mut self) {
+        self.cancellation = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellation) = self.cancellation.take() {
+            cancellation.cancelled.store(true, Ordering::Relaxed);
+            CANCELLED_REQUESTS_COUNTER.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_guard_cancels_the_request() {
+        let cancellation = Cancellation::default();
+        let guard = cancellation.guard();
+        assert!(cancellation.check().is_ok());
+        drop(guard);
+        assert!(matches!(cancellation.check(), Err(BragiError::Cancelled)));
+    }
+
+    #[test]
+    fn disarmed_guard_does_not_cancel_the_request() {
+        let cancellation = Cancellation::default();
+        cancellation.guard().disarm();
+        assert!(!cancellation.is_cancelled());
+    }
+}
@@ -0,0 +1,472 @@
+// Circuit breaker protecting bragi's latency during the ES outages.
+//
+// After `max_failures` consecutive ES failures (connection errors, timeouts, 429 or 5xx responses),
+// the circuit is opened and the requests are rejected right away (with a 503, an
+// `ES_CIRCUIT_OPEN` code and a Retry-After header) instead of waiting for the ES timeout,
+// so that an overloaded ES is not loaded even more.
+// After the cooldown, one request is let through to probe ES:
+// the circuit is closed if it succeeds, and opened again for a cooldown if it fails.
+//
+// There is one circuit breaker for each type of index (addr, admin, poi, stop, street),
+// shared by all the workers: an overloaded index does not reject the requests on the other ones.
+// A request querying several types is rejected if one of their circuits is open,
+// and its outcome is recorded on all of them.
+
+use crate::model::BragiError;
+use rs_es::error::EsError;
+use serde::{Deserialize, Serialize};
+use slog_scope::{info, warn};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref REJECTED_REQUESTS_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_circuit_breaker_rejected_requests_total",
+        "Total number of requests rejected because the ES circuit breaker of a type of index was open.",
+        &["index_type"]
+    )
+    .unwrap();
+    static ref STATE_GAUGE: prometheus::GaugeVec = prometheus::register_gauge_vec!(
+        "bragi_circuit_breaker_state",
+        "State of the ES circuit breaker of a type of index: 0 if closed, 1 if half open, 2 if open.",
+        &["index_type"]
+    )
+    .unwrap();
+}
+
+// delay advertised to the clients while a probe is running
+const PROBE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// The types of indexes having their own circuit breaker
+pub const INDEX_TYPES: [&str; 5] = ["addr", "admin", "poi", "stop", "street"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed { nb_failures: u32 },
+    Open { until: Instant },
+    // a request has been let through to check if ES is back
+    HalfOpen,
+}
+
+/// State of a circuit breaker, as shown in /status
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    HalfOpen,
+    Open,
+}
+
+impl State {
+    fn circuit_state(self) -> CircuitState {
+        match self {
+            State::Closed { .. } => CircuitState::Closed,
+            State::HalfOpen => CircuitState::HalfOpen,
+            State::Open { .. } => CircuitState::Open,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    index_type: &'static str,
+    max_failures: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+// only the errors showing that ES is unreachable or overloaded are counted,
+// an invalid query (rejected by ES with a 4xx) does not mean that ES is down
+fn is_es_failure(error: &BragiError) -> bool {
+    match error {
+        BragiError::Es {
+            error: EsError::HttpError { .. },
+            ..
+        } => true,
+        BragiError::Es {
+            status: Some(status),
+            ..
+        } => *status == 429 || *status >= 500,
+        _ => false,
+    }
+}
+
+/// The types of the indexes queried for the types of places of a request
+/// (as given to the autocomplete: `house`, `city`...), all of them if none is given
+pub fn index_types(types: &[&str]) -> Vec<&'static str> {
+    let index_types: Vec<_> = INDEX_TYPES
+        .iter()
+        .copied()
+        .filter(|index_type| {
+            types.iter().any(|t| match *t {
+                "house" => *index_type == "addr",
+                "city" | "zone" => *index_type == "admin",
+                "public_transport:stop_area" => *index_type == "stop",
+                t => t == *index_type,
+            })
+        })
+        .collect();
+    if index_types.is_empty() {
+        INDEX_TYPES.to_vec()
+    } else {
+        index_types
+    }
+}
+
+/// The circuit breakers of each type of index
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    breakers: BTreeMap<String, CircuitBreaker>,
+}
+
+impl CircuitBreakers {
+    /// Circuit breakers with `max_failures` set to 0 never open
+    pub fn new(max_failures: u32, cooldown: Duration) -> Self {
+        CircuitBreakers {
+            breakers: INDEX_TYPES
+                .iter()
+                .map(|index_type| {
+                    (
+                        index_type.to_string(),
+                        CircuitBreaker::new(*index_type, max_failures, cooldown),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Calls `f` (that queries the ES indexes of `index_types`) if all their circuits are closed,
+    /// and records its outcome on each of them.
+    ///
+    /// If one of the circuits is open, `f` is not called and a `BragiError::EsUnavailable` is returned.
+    pub fn call<T, F>(&self, index_types: &[&str], f: F) -> Result<T, BragiError>
+    where
+        F: FnOnce() -> Result<T, BragiError>,
+    {
+        let breakers: Vec<_> = index_types
+            .iter()
+            .filter_map(|index_type| self.breakers.get(*index_type))
+            .filter(|breaker| breaker.max_failures > 0)
+            .collect();
+        let now = Instant::now();
+        let mut acquired = Vec::with_capacity(breakers.len());
+        for breaker in breakers {
+            match breaker.acquire(now) {
+                Ok(probing) => acquired.push((breaker, probing)),
+                Err(retry_after) => {
+                    // the probes that will not be sent are let through to the next requests
+                    for (acquired_breaker, probing) in acquired {
+                        if probing {
+                            acquired_breaker.release_probe(now);
+                        }
+                    }
+                    REJECTED_REQUESTS_COUNTER
+                        .with_label_values(&[breaker.index_type])
+                        .inc();
+                    return Err(BragiError::EsUnavailable { retry_after });
+                }
+            }
+        }
+        let res = f();
+        let failed = match &res {
+            Err(e) => is_es_failure(e),
+            Ok(_) => false,
+        };
+        let now = Instant::now();
+        for (breaker, _) in acquired {
+            breaker.record(!failed, now);
+        }
+        res
+    }
+
+    /// The state of the circuit breaker of each type of index
+    pub fn states(&self) -> BTreeMap<String, CircuitState> {
+        self.breakers
+            .iter()
+            .map(|(index_type, breaker)| (index_type.clone(), breaker.state()))
+            .collect()
+    }
+}
+
+impl CircuitBreaker {
+    fn new(index_type: &'static str, max_failures: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            index_type,
+            max_failures,
+            cooldown,
+            state: Mutex::new(State::Closed { nb_failures: 0 }),
+        }
+    }
+
+    fn state(&self) -> CircuitState {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .circuit_state()
+    }
+
+    // checks if a request can be sent to ES (true if it is the probe),
+    // and otherwise returns the delay before retrying
+    fn acquire(&self, now: Instant) -> Result<bool, Duration> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let res = match *state {
+            State::Closed { .. } => Ok(false),
+            State::Open { until } if now < until => Err(until - now),
+            State::Open { .. } => {
+                info!(
+                    "circuit breaker of {} half open, probing ES",
+                    self.index_type
+                );
+                *state = State::HalfOpen;
+                Ok(true)
+            }
+            State::HalfOpen => Err(PROBE_RETRY_AFTER),
+        };
+        self.set_state_gauge(*state);
+        res
+    }
+
+    // the probe has not been sent, the next request will be the probe
+    fn release_probe(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if *state == State::HalfOpen {
+            *state = State::Open { until: now };
+        }
+        self.set_state_gauge(*state);
+    }
+
+    fn record(&self, success: bool, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = match (*state, success) {
+            (State::HalfOpen, true) => {
+                info!(
+                    "ES is back, closing the circuit breaker of {}",
+                    self.index_type
+                );
+                State::Closed { nb_failures: 0 }
+            }
+            (_, true) => State::Closed { nb_failures: 0 },
+            (State::Closed { nb_failures }, false) if nb_failures + 1 < self.max_failures => {
+                State::Closed {
+                    nb_failures: nb_failures + 1,
+                }
+            }
+            (State::Closed { .. }, false) | (State::HalfOpen, false) => {
+                warn!(
+                    "ES is unavailable, rejecting the requests on {} for {:?}",
+                    self.index_type, self.cooldown
+                );
+                State::Open {
+                    until: now + self.cooldown,
+                }
+            }
+            // a request started before the opening of the circuit
+            (State::Open { until }, false) => State::Open { until },
+        };
+        self.set_state_gauge(*state);
+    }
+
+    fn set_state_gauge(&self, state: State) {
+        STATE_GAUGE
+            .with_label_values(&[self.index_type])
+            .set(match state.circuit_state() {
+                CircuitState::Closed => 0.,
+                CircuitState::HalfOpen => 1.,
+                CircuitState::Open => 2.,
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaker_trips_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new("addr", 3, Duration::from_secs(10));
+        let now = Instant::now();
+
+        breaker.record(false, now);
+        breaker.record(false, now);
+        // a success resets the number of failures
+        breaker.record(true, now);
+        breaker.record(false, now);
+        breaker.record(false, now);
+        assert_eq!(breaker.acquire(now), Ok(false));
+
+        breaker.record(false, now);
+        assert_eq!(
+            breaker.acquire(now + Duration::from_secs(4)),
+            Err(Duration::from_secs(6))
+        );
+    }
+
+    #[test]
+    fn breaker_probes_after_cooldown() {
+        let breaker = CircuitBreaker::new("addr", 1, Duration::from_secs(10));
+        let now = Instant::now();
+        breaker.record(false, now);
+
+        // after the cooldown, only one request is let through
+        let later = now + Duration::from_secs(10);
+        assert_eq!(breaker.acquire(later), Ok(true));
+        assert_eq!(breaker.acquire(later), Err(PROBE_RETRY_AFTER));
+
+        // the probe fails, the circuit is opened again
+        breaker.record(false, later);
+        assert_eq!(
+            breaker.acquire(later + Duration::from_secs(1)),
+            Err(Duration::from_secs(9))
+        );
+
+        // the next probe succeeds, the circuit is closed
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(breaker.acquire(much_later), Ok(true));
+        breaker.record(true, much_later);
+        assert_eq!(breaker.acquire(much_later), Ok(false));
+        assert_eq!(breaker.acquire(much_later), Ok(false));
+    }
+
+    #[test]
+    fn open_breaker_does_not_call_es() {
+        let breakers = CircuitBreakers::new(1, Duration::from_secs(10));
+        breakers.breakers["poi"].record(false, Instant::now());
+
+        let mut called = false;
+        let res = breakers.call(&["addr", "poi"], || {
+            called = true;
+            Ok(())
+        });
+        assert!(!called);
+        assert!(matches!(res, Err(BragiError::EsUnavailable { .. })));
+    }
+
+    #[test]
+    fn open_breaker_only_rejects_its_type() {
+        let breakers = CircuitBreakers::new(1, Duration::from_secs(10));
+        assert!(breakers.call(&["poi"], || query_es(&mut 0, true)).is_err());
+        assert_eq!(breakers.states()["poi"], CircuitState::Open);
+        assert_eq!(breakers.states()["addr"], CircuitState::Closed);
+
+        let mut nb_queries = 0;
+        assert!(breakers
+            .call(&["addr", "street"], || query_es(&mut nb_queries, false))
+            .is_ok());
+        assert!(matches!(
+            breakers.call(&index_types(&[]), || query_es(&mut nb_queries, false)),
+            Err(BragiError::EsUnavailable { .. })
+        ));
+        assert_eq!(nb_queries, 1);
+    }
+
+    #[test]
+    fn unsent_probe_is_released() {
+        let breakers = CircuitBreakers::new(1, Duration::from_millis(10));
+        let now = Instant::now();
+        breakers.breakers["addr"].record(false, now);
+        breakers.breakers["poi"].record(false, now + Duration::from_secs(10));
+
+        // addr could be probed, but the request is rejected by poi
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(breakers.call(&["addr", "poi"], || Ok(())).is_err());
+        assert_eq!(breakers.states()["addr"], CircuitState::Open);
+        assert!(breakers.call(&["addr"], || Ok(())).is_ok());
+        assert_eq!(breakers.states()["addr"], CircuitState::Closed);
+    }
+
+    #[test]
+    fn types_of_places_are_mapped_to_index_types() {
+        assert_eq!(index_types(&["house", "city"]), vec!["addr", "admin"]);
+        assert_eq!(
+            index_types(&["public_transport:stop_area", "zone", "poi"]),
+            vec!["admin", "poi", "stop"]
+        );
+        assert_eq!(index_types(&[]), INDEX_TYPES.to_vec());
+    }
+
+    // ES mocked by a closure counting the queries, and failing while `es_down` is set
+    fn query_es(nb_queries: &mut u32, es_down: bool) -> Result<(), BragiError> {
+        *nb_queries += 1;
+        if es_down {
+            Err(EsError::EsServerError(
+                "429 Too Many Requests - es_rejected_execution_exception".to_string(),
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn breaker_transitions_with_mock_es() {
+        let breakers = CircuitBreakers::new(2, Duration::from_millis(50));
+        let mut nb_queries = 0;
+
+        // closed: the failures are returned as is until the circuit opens
+        assert!(matches!(
+            breakers.call(&["addr"], || query_es(&mut nb_queries, true)),
+            Err(BragiError::Es { .. })
+        ));
+        assert_eq!(breakers.states()["addr"], CircuitState::Closed);
+        assert!(breakers
+            .call(&["addr"], || query_es(&mut nb_queries, true))
+            .is_err());
+        assert_eq!(breakers.states()["addr"], CircuitState::Open);
+
+        // open: ES is not queried anymore
+        assert!(matches!(
+            breakers.call(&["addr"], || query_es(&mut nb_queries, false)),
+            Err(BragiError::EsUnavailable { .. })
+        ));
+        assert_eq!(nb_queries, 2);
+
+        // half open: after the cooldown, the probe is sent to ES, the other requests are rejected
+        std::thread::sleep(Duration::from_millis(60));
+        let res = breakers.call(&["addr"], || {
+            assert_eq!(breakers.states()["addr"], CircuitState::HalfOpen);
+            assert!(matches!(
+                breakers.call(&["addr"], || query_es(&mut 0, false)),
+                Err(BragiError::EsUnavailable { .. })
+            ));
+            query_es(&mut nb_queries, false)
+        });
+        assert!(res.is_ok());
+        assert_eq!(nb_queries, 3);
+
+        // closed again
+        assert_eq!(breakers.states()["addr"], CircuitState::Closed);
+        assert!(breakers
+            .call(&["addr"], || query_es(&mut nb_queries, false))
+            .is_ok());
+        assert_eq!(nb_queries, 4);
+    }
+
+    #[test]
+    fn invalid_queries_do_not_open_the_breaker() {
+        let breakers = CircuitBreakers::new(1, Duration::from_secs(10));
+        let rejected_query = || -> Result<(), BragiError> {
+            Err(EsError::EsServerError(
+                "400 Bad Request - {\"error\": {\"type\": \"parse_exception\"}}".to_string(),
+            )
+            .into())
+        };
+        assert!(breakers.call(&["addr"], rejected_query).is_err());
+        assert!(breakers.call(&["addr"], rejected_query).is_err());
+        assert_eq!(breakers.states()["addr"], CircuitState::Closed);
+
+        // but an overloaded ES does
+        let unavailable = || -> Result<(), BragiError> {
+            Err(EsError::EsServerError("503 Service Unavailable - ".to_string()).into())
+        };
+        assert!(breakers.call(&["addr"], unavailable).is_err());
+        assert_eq!(breakers.states()["addr"], CircuitState::Open);
+    }
+
+    #[test]
+    fn disabled_breaker_never_opens() {
+        let breakers = CircuitBreakers::new(0, Duration::from_secs(10));
+        breakers.breakers["addr"].record(false, Instant::now());
+        assert!(breakers.call(&["addr"], || Ok(())).is_ok());
+    }
+}
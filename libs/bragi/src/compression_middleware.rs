@@ -0,0 +1,143 @@
+// Compression of the responses, negotiated with the Accept-Encoding header of the request.
+//
+// The compression itself is done by actix's `Compress` middleware, which compresses every response.
+// This middleware must be wrapped by `Compress`: it opts the responses out of the compression
+// when it is disabled or when they are too small to be worth it.
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::{BodyEncoding, ServiceRequest, ServiceResponse},
+    http::ContentEncoding,
+    Error,
+};
+use futures::future::{ok, FutureResult};
+use futures::{Async, Future, Poll};
+
+/// Responses smaller than this size (in bytes) are not compressed by default
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+#[derive(Clone, Debug)]
+#[must_use = "must be set up as middleware for actix-web, wrapped by actix_web::middleware::Compress"]
+/// Middleware selecting the responses compressed by actix's `Compress` middleware.
+///
+/// If no threshold is given, the compression is disabled.
+pub struct CompressionThreshold {
+    threshold: Option<usize>,
+}
+
+impl CompressionThreshold {
+    pub fn new(threshold: Option<usize>) -> Self {
+        CompressionThreshold { threshold }
+    }
+}
+
+impl<S, B> Transform<S> for CompressionThreshold
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CompressionThresholdMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CompressionThresholdMiddleware {
+            service,
+            threshold: self.threshold,
+        })
+    }
+}
+
+#[doc(hidden)]
+/// Middleware service for CompressionThreshold
+pub struct CompressionThresholdMiddleware<S> {
+    service: S,
+    threshold: Option<usize>,
+}
+
+impl<S, B> Service for CompressionThresholdMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = CompressionThresholdResponse<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        CompressionThresholdResponse {
+            fut: self.service.call(req),
+            threshold: self.threshold,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct CompressionThresholdResponse<S>
+where
+    S: Service,
+{
+    fut: S::Future,
+    threshold: Option<usize>,
+}
+
+impl<S, B> Future for CompressionThresholdResponse<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Item = ServiceResponse<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut res = futures::try_ready!(self.fut.poll());
+        if !should_compress(res.response().body().size(), self.threshold) {
+            res.response_mut().encoding(ContentEncoding::Identity);
+        }
+        Ok(Async::Ready(res))
+    }
+}
+
+// the streamed bodies (of unknown size) are compressed, they are expected to be big
+fn should_compress(size: BodySize, threshold: Option<usize>) -> bool {
+    match (size, threshold) {
+        (_, None) => false,
+        (BodySize::Sized(size), Some(threshold)) => size >= threshold,
+        (BodySize::Sized64(size), Some(threshold)) => size >= threshold as u64,
+        (BodySize::Stream, Some(_)) => true,
+        (BodySize::None, _) | (BodySize::Empty, _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_big_enough_responses_are_compressed() {
+        assert!(should_compress(BodySize::Sized(2048), Some(1024)));
+        assert!(should_compress(BodySize::Sized(1024), Some(1024)));
+        assert!(!should_compress(BodySize::Sized(1023), Some(1024)));
+        assert!(should_compress(BodySize::Sized64(2048), Some(1024)));
+        assert!(should_compress(BodySize::Stream, Some(1024)));
+        assert!(!should_compress(BodySize::Empty, Some(0)));
+        assert!(!should_compress(BodySize::None, Some(0)));
+    }
+
+    #[test]
+    fn nothing_is_compressed_when_disabled() {
+        assert!(!should_compress(BodySize::Sized(1_000_000), None));
+        assert!(!should_compress(BodySize::Stream, None));
+    }
+}
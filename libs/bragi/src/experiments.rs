@@ -0,0 +1,247 @@
+// A/B experiments on the settings of the autocomplete, to evaluate them on the live traffic.
+//
+// Each experiment of `[[query.experiments]]` in the settings gives the settings merged into the
+// base settings for its share of the requests. The experiment of a request is chosen from its id,
+// so that the successive requests of a user sending the same id (for example all the keystrokes
+// of a search) are all searched with the same settings. The `experiment` parameter of a request
+// chooses its experiment, to test it.
+
+use crate::model::BragiError;
+use crate::query_settings::{merge_settings, Experiment, QuerySettings};
+
+lazy_static::lazy_static! {
+    static ref EXPERIMENT_REQUESTS_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_experiment_requests_total",
+        "Total number of autocomplete requests searched with the settings of an experiment.",
+        &["experiment"]
+    )
+    .unwrap();
+}
+
+/// The settings of the queries of an experiment
+#[derive(Debug)]
+pub struct ExperimentSettings {
+    pub name: String,
+    traffic: f64,
+    pub settings: QuerySettings,
+}
+
+/// All the experiments of the settings
+#[derive(Debug, Default)]
+pub struct Experiments {
+    experiments: Vec<ExperimentSettings>,
+}
+
+impl Experiments {
+    /// Reads the settings of the `experiments`, merged into the base `settings`
+    pub fn new(settings: &toml::Value, experiments: &[Experiment]) -> Result<Self, String> {
+        let mut base_settings = settings.clone();
+        if let Some(query) = base_settings
+            .get_mut("query")
+            .and_then(toml::Value::as_table_mut)
+        {
+            query.remove("experiments");
+        }
+        let mut total_traffic = 0.;
+        let mut names = std::collections::HashSet::new();
+        let experiments = experiments
+            .iter()
+            .map(|experiment| {
+                if !names.insert(experiment.name.as_str()) {
+                    return Err(format!(
+                        "the experiment `{}` is duplicated",
+                        experiment.name
+                    ));
+                }
+                if experiment.traffic < 0. || experiment.traffic > 100. {
+                    return Err(format!(
+                        "the traffic of the experiment `{}` is not a percentage",
+                        experiment.name
+                    ));
+                }
+                total_traffic += experiment.traffic;
+                let mut settings = base_settings.clone();
+                merge_settings(&mut settings, experiment.settings.clone());
+                let settings = QuerySettings::from_toml(settings).map_err(|err| {
+                    format!(
+                        "invalid settings of the experiment `{}`: {}",
+                        experiment.name, err
+                    )
+                })?;
+                Ok(ExperimentSettings {
+                    name: experiment.name.clone(),
+                    traffic: experiment.traffic,
+                    settings,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if total_traffic > 100. {
+            return Err(format!(
+                "the experiments have {}% of the traffic, more than 100%",
+                total_traffic
+            ));
+        }
+        Ok(Experiments { experiments })
+    }
+
+    /// The experiment of a request, given by its `experiment` parameter if any,
+    /// else chosen from its id. The requests without id are never part of an experiment.
+    pub fn select(
+        &self,
+        request_id: Option<&str>,
+        name: Option<&str>,
+    ) -> Result<Option<&ExperimentSettings>, BragiError> {
+        let experiment = match (name, request_id) {
+            (Some(name), _) => Some(
+                self.experiments
+                    .iter()
+                    .find(|experiment| experiment.name == name)
+                    .ok_or(BragiError::InvalidParam(
+                        "the 'experiment' parameter is not an experiment of the settings",
+                    ))?,
+            ),
+            (None, Some(request_id)) if !self.experiments.is_empty() => {
+                let bucket = bucket(request_id);
+                let mut traffic = 0.;
+                self.experiments.iter().find(|experiment| {
+                    traffic += experiment.traffic;
+                    bucket < traffic
+                })
+            }
+            (None, _) => None,
+        };
+        if let Some(experiment) = experiment {
+            EXPERIMENT_REQUESTS_COUNTER
+                .with_label_values(&[&experiment.name])
+                .inc();
+        }
+        Ok(experiment)
+    }
+}
+
+/// Bucket of a request id, between 0 and 100 (excluded).
+///
+/// The id is hashed with FNV-1a, which (unlike the hasher of the std) is the same
+/// for all the instances and all the versions of bragi. Its high bits are the best mixed.
+fn bucket(request_id: &str) -> f64 {
+    let hash = request_id
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    ((hash >> 32) % 10_000) as f64 / 100.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiments(experiments: &str) -> Result<Experiments, String> {
+        let mut settings: toml::Value =
+            toml::from_str(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        merge_settings(&mut settings, toml::from_str(experiments).unwrap());
+        let query_settings = QuerySettings::from_toml(settings.clone())?;
+        Experiments::new(&settings, &query_settings.query.experiments)
+    }
+
+    fn two_experiments() -> Experiments {
+        experiments(
+            r#"
+            [[query.experiments]]
+            name = "more_fuzzy"
+            traffic = 10
+            [query.experiments.settings.fuzzy_query]
+            fuzziness = 2
+
+            [[query.experiments]]
+            name = "no_label_boost"
+            traffic = 25.5
+            [query.experiments.settings.string_query.boosts]
+            label = 0.0
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn experiments_settings_are_merged_into_base_settings() {
+        let experiments = two_experiments();
+        let experiment = experiments
+            .select(None, Some("no_label_boost"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(experiment.settings.string_query.boosts.label, 0.);
+        // the other settings are the base ones
+        assert_eq!(experiment.settings.fuzzy_query.fuzziness, None);
+        assert!(experiment.settings.query.experiments.is_empty());
+    }
+
+    #[test]
+    fn traffic_is_split_by_request_id() {
+        let experiments = two_experiments();
+        let nb_requests = 10_000u32;
+        let mut nb_more_fuzzy = 0;
+        let mut nb_no_label_boost = 0;
+        for i in 0..nb_requests {
+            // like the generated ids, a timestamp and a counter
+            let timestamp = 1_600_000_000_000_000_000u128 + u128::from(i) * 7919;
+            let request_id = format!("{:x}-{:x}", timestamp, i);
+            match experiments.select(Some(&request_id), None).unwrap() {
+                Some(e) if e.name == "more_fuzzy" => nb_more_fuzzy += 1,
+                Some(_) => nb_no_label_boost += 1,
+                None => {}
+            }
+        }
+        let share = |nb: u32| f64::from(nb) * 100. / f64::from(nb_requests);
+        assert!((share(nb_more_fuzzy) - 10.).abs() < 1.5);
+        assert!((share(nb_no_label_boost) - 25.5).abs() < 1.5);
+    }
+
+    #[test]
+    fn requests_with_the_same_id_have_the_same_experiment() {
+        let experiments = two_experiments();
+        let name = |request_id| {
+            experiments
+                .select(Some(request_id), None)
+                .unwrap()
+                .map(|e| e.name.clone())
+        };
+        for request_id in &["user-1", "user-2", "6f2a-13", "session:42"] {
+            assert_eq!(name(request_id), name(request_id));
+        }
+        assert!(experiments.select(None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn experiment_can_be_requested() {
+        let experiments = two_experiments();
+        let experiment = experiments.select(Some("user-1"), Some("more_fuzzy"));
+        assert_eq!(experiment.unwrap().unwrap().name, "more_fuzzy");
+        assert!(experiments.select(Some("user-1"), Some("unknown")).is_err());
+    }
+
+    #[test]
+    fn invalid_experiments_are_rejected() {
+        let too_much_traffic = experiments(
+            r#"
+            [[query.experiments]]
+            name = "a"
+            traffic = 60
+            [[query.experiments]]
+            name = "b"
+            traffic = 50
+            "#,
+        );
+        assert!(too_much_traffic.is_err());
+        let invalid_settings = experiments(
+            r#"
+            [[query.experiments]]
+            name = "a"
+            traffic = 10
+            [query.experiments.settings.fuzzy_query]
+            fuzziness = "a lot"
+            "#,
+        );
+        assert!(invalid_settings.is_err());
+    }
+}
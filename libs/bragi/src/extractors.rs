@@ -5,10 +5,14 @@
 ///
 /// Note: we use serde_qs instead of the actix's default serde_urlencoded because serde_qs is more flexible
 /// (cf https://github.com/nox/serde_urlencoded/issues/6)
-use crate::model::ApiError;
+use crate::api_keys::{request_key, Profile};
+use crate::model::{ApiError, BragiError};
+use crate::Context;
+use actix_web::web::Data;
 use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use failure::Fail;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 #[derive(Fail, Debug)]
 pub enum ActixError {
@@ -18,31 +22,34 @@ pub enum ActixError {
     InvalidQueryParam(String),
     #[fail(display = "route '{}' does not exists", _0)]
     RouteNotFound(String),
+    #[fail(display = "payload too large: {}", _0)]
+    PayloadTooLarge(String),
 }
 
 impl actix_web::error::ResponseError for ActixError {
     fn render_response(&self) -> actix_web::HttpResponse {
         match *self {
-            ActixError::InvalidJson(_) => actix_web::HttpResponse::BadRequest().json(ApiError {
-                short: "validation error".to_owned(),
-                long: format!("{}", self),
-            }),
+            ActixError::InvalidJson(_) => actix_web::HttpResponse::BadRequest()
+                .json(ApiError::new("validation error", format!("{}", self))),
             ActixError::InvalidQueryParam(_) => actix_web::HttpResponse::BadRequest()
                 .header(actix_web::http::header::CONTENT_TYPE, "application/json")
-                .json(ApiError {
-                    short: "validation error".to_owned(),
-                    long: format!("{}", self),
-                }),
-            ActixError::RouteNotFound(_) => actix_web::HttpResponse::NotFound().json(ApiError {
-                short: "no route".to_owned(),
-                long: format!("{}", self),
-            }),
+                .json(ApiError::new("validation error", format!("{}", self))),
+            ActixError::RouteNotFound(_) => actix_web::HttpResponse::NotFound()
+                .json(ApiError::new("no route", format!("{}", self))),
+            ActixError::PayloadTooLarge(_) => actix_web::HttpResponse::PayloadTooLarge()
+                .json(ApiError::new("payload too large", format!("{}", self))),
         }
     }
 }
 
 pub struct BragiQuery<T>(T);
 
+impl<T> BragiQuery<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
 impl<T> Deref for BragiQuery<T> {
     type Target = T;
 
@@ -74,3 +81,45 @@ where
             .map(BragiQuery)
     }
 }
+
+/// Profile of the api key of the request, None if the api keys are disabled.
+///
+/// The requests without a valid key, or over the limits of their key, are rejected.
+pub struct ApiProfile(Option<Arc<Profile>>);
+
+impl ApiProfile {
+    pub fn into_inner(self) -> Option<Arc<Profile>> {
+        self.0
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.0.as_deref()
+    }
+
+    /// Rejects the restricted profiles, for the apis unable to apply their restrictions
+    pub fn check_unrestricted(&self) -> Result<(), BragiError> {
+        match self.profile() {
+            Some(profile) if profile.is_restricted() => Err(BragiError::Forbidden(
+                "this api is not available with a restricted api key",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromRequest for ApiProfile {
+    type Error = BragiError;
+    type Future = Result<Self, BragiError>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let state = Data::<Context>::from_request(req, payload)
+            .expect("the context should be given to the app");
+        match state.get_api_keys() {
+            Some(api_keys) => api_keys
+                .authenticate(request_key(req).as_deref())
+                .map(|profile| ApiProfile(Some(profile))),
+            None => Ok(ApiProfile(None)),
+        }
+    }
+}
@@ -0,0 +1,454 @@
+// Responses in the geocodejson format (https://github.com/geocoders/geocodejson-spec),
+// for the clients which only speak the spec.
+//
+// The usual responses of bragi are close to geocodejson, but they have their own types of places
+// and their own fields. With `format=geocodejson`, `/autocomplete` and `/reverse` give instead
+// the types of the spec (`house`, `street`, `locality`, `city`, `region`, `country`, and `poi`
+// for the pois and the stops) and its fields, the admins of the places being given by their
+// zone type (`district`, `county`, `state`, `country`) and by their level (`admin`).
+
+use crate::model::{ApiWarning, Autocomplete, Feature, Geocoding, GeocodingResponse};
+use heck::SnakeCase;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Format of the responses of the forward and reverse geocoding
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Format {
+    #[serde(rename = "bragi")]
+    Bragi,
+    #[serde(rename = "geocodejson")]
+    GeocodeJson,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Bragi
+    }
+}
+
+/// The geocoding block of a feature, with the fields of the spec
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct GeocodeJsonGeocoding {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub place_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub housenumber: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub street: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postcode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub district: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub county: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Names of the admins of the place by their level (`level2` for the country...)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub admin: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GeocodeJsonProperties {
+    pub geocoding: GeocodeJsonGeocoding,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GeocodeJsonFeature {
+    #[serde(rename = "type")]
+    pub feature_type: &'static str,
+    pub geometry: geojson::Geometry,
+    pub properties: GeocodeJsonProperties,
+}
+
+/// Response returned with `format=geocodejson`
+#[derive(Serialize, Debug)]
+pub struct GeocodeJson {
+    #[serde(rename = "type")]
+    pub format_type: &'static str,
+    pub geocoding: Geocoding,
+    pub features: Vec<GeocodeJsonFeature>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partial: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
+}
+
+impl From<Autocomplete> for GeocodeJson {
+    fn from(other: Autocomplete) -> GeocodeJson {
+        GeocodeJson {
+            format_type: "FeatureCollection",
+            geocoding: other.geocoding,
+            features: other.features.into_iter().map(Into::into).collect(),
+            partial: other.partial,
+            experiment: other.experiment,
+            warnings: other.warnings,
+        }
+    }
+}
+
+impl From<Feature> for GeocodeJsonFeature {
+    fn from(other: Feature) -> GeocodeJsonFeature {
+        GeocodeJsonFeature {
+            feature_type: "Feature",
+            geometry: other.geometry,
+            properties: GeocodeJsonProperties {
+                geocoding: other.properties.geocoding.into(),
+            },
+        }
+    }
+}
+
+/// The type of the spec of a zone, from its zone type
+fn zone_place_type(zone_type: Option<&str>) -> &'static str {
+    match zone_type {
+        Some("country") | Some("country_region") => "country",
+        Some("state") | Some("state_district") => "region",
+        Some("city") => "city",
+        _ => "locality",
+    }
+}
+
+impl From<GeocodingResponse> for GeocodeJsonGeocoding {
+    fn from(other: GeocodingResponse) -> GeocodeJsonGeocoding {
+        let place_type = match other.place_type.as_str() {
+            "house" => "house",
+            "street" => "street",
+            "zone" => zone_place_type(other.zone_type.as_deref()),
+            // the stops have no type in the spec
+            _ => "poi",
+        };
+        // the zone itself, then its admins from the smallest to the largest
+        let own_zone = match other.place_type.as_str() {
+            "zone" => other
+                .name
+                .clone()
+                .map(|name| (other.zone_type.clone(), name, other.level.unwrap_or(0))),
+            _ => None,
+        };
+        let zones: Vec<(Option<String>, String, u32)> = own_zone
+            .into_iter()
+            .chain(other.administrative_regions.into_iter().map(|admin| {
+                let zone_type = admin.zone_type.map(|z| z.as_str().to_snake_case());
+                (zone_type, admin.name, admin.level)
+            }))
+            .collect();
+        let zone_name = |zone_types: &[&str]| {
+            zones
+                .iter()
+                .find(|(zone_type, _, _)| {
+                    zone_type
+                        .as_deref()
+                        .map_or(false, |zone_type| zone_types.contains(&zone_type))
+                })
+                .map(|(_, name, _)| name.clone())
+        };
+        let mut admin = BTreeMap::new();
+        for (_, name, level) in &zones {
+            // the zones without admin level (like the non administrative ones) are not admins
+            if *level > 0 {
+                admin
+                    .entry(format!("level{}", level))
+                    .or_insert_with(|| name.clone());
+            }
+        }
+        GeocodeJsonGeocoding {
+            id: other.id,
+            place_type,
+            locality: match place_type {
+                "locality" => other.name.clone(),
+                _ => None,
+            },
+            label: other.label,
+            name: other.name,
+            housenumber: other.housenumber,
+            street: other.street,
+            // the spec has a single postcode, the first of the place
+            postcode: other
+                .postcode
+                .and_then(|postcode| postcode.split(';').next().map(str::to_string))
+                .filter(|postcode| !postcode.is_empty()),
+            city: other.city.or_else(|| zone_name(&["city"])),
+            district: zone_name(&["city_district", "suburb"]),
+            county: zone_name(&["state_district"]),
+            state: zone_name(&["state"]),
+            country: zone_name(&["country"]).or(other.country),
+            admin,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::FromWithLang;
+    use mimir::objects::{Addr, Admin, Coord, Place, Poi, Stop, Street};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn admin(id: &str, name: &str, level: u32, zone_type: cosmogony::ZoneType) -> Arc<Admin> {
+        Arc::new(Admin {
+            id: id.to_string(),
+            name: name.to_string(),
+            label: name.to_string(),
+            level,
+            zone_type: Some(zone_type),
+            ..Default::default()
+        })
+    }
+
+    fn admins() -> Vec<Arc<Admin>> {
+        vec![
+            admin(
+                "admin:arr",
+                "Paris 13e Arrondissement",
+                9,
+                cosmogony::ZoneType::CityDistrict,
+            ),
+            admin("admin:paris", "Paris", 8, cosmogony::ZoneType::City),
+            admin("admin:idf", "Île-de-France", 4, cosmogony::ZoneType::State),
+            admin("admin:fr", "France", 2, cosmogony::ZoneType::Country),
+        ]
+    }
+
+    fn street() -> Street {
+        Street {
+            id: "street:malot".to_string(),
+            name: "Rue Hector Malot".to_string(),
+            label: "Rue Hector Malot (Paris)".to_string(),
+            administrative_regions: admins(),
+            zip_codes: vec!["75012".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn addr(zip_codes: Vec<String>) -> Addr {
+        Addr {
+            id: "addr:malot:20".to_string(),
+            name: "20 Rue Hector Malot".to_string(),
+            house_number: "20".to_string(),
+            house_number_numeric: Some(20),
+            house_number_suffix: None,
+            street: street(),
+            label: "20 Rue Hector Malot (Paris)".to_string(),
+            coord: Coord::new(2.37, 48.84),
+            approx_coord: None,
+            weight: 0.,
+            zip_codes,
+            country_codes: vec!["fr".to_string()],
+            city: Some("Paris".to_string()),
+            country: None,
+            distance: None,
+            score: None,
+            old_ids: vec![],
+            flags: Default::default(),
+            context: None,
+        }
+    }
+
+    fn geocoding(place: Place) -> GeocodeJsonGeocoding {
+        GeocodeJsonFeature::from(Feature::from_with_lang(place, None))
+            .properties
+            .geocoding
+    }
+
+    fn levels(levels: &[(u32, &str)]) -> BTreeMap<String, String> {
+        levels
+            .iter()
+            .map(|(level, name)| (format!("level{}", level), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn addresses() {
+        let geocoding = geocoding(Place::Addr(addr(vec!["75013".to_string()])));
+        assert_eq!(
+            geocoding,
+            GeocodeJsonGeocoding {
+                id: "addr:malot:20".to_string(),
+                place_type: "house",
+                label: Some("20 Rue Hector Malot (Paris)".to_string()),
+                name: Some("20 Rue Hector Malot".to_string()),
+                housenumber: Some("20".to_string()),
+                street: Some("Rue Hector Malot".to_string()),
+                locality: None,
+                postcode: Some("75013".to_string()),
+                city: Some("Paris".to_string()),
+                district: Some("Paris 13e Arrondissement".to_string()),
+                county: None,
+                state: Some("Île-de-France".to_string()),
+                country: Some("France".to_string()),
+                admin: levels(&[
+                    (2, "France"),
+                    (4, "Île-de-France"),
+                    (8, "Paris"),
+                    (9, "Paris 13e Arrondissement"),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn addresses_without_postcode_or_admins() {
+        let mut addr = addr(vec![]);
+        addr.street.administrative_regions = vec![];
+        addr.city = None;
+        addr.country = Some("France".to_string());
+        let geocoding = geocoding(Place::Addr(addr));
+        assert_eq!(geocoding.place_type, "house");
+        assert_eq!(geocoding.postcode, None);
+        assert_eq!(geocoding.city, None);
+        // the country of the label is kept without the admin of the country
+        assert_eq!(geocoding.country, Some("France".to_string()));
+        assert!(geocoding.admin.is_empty());
+        let json = serde_json::to_value(&geocoding).unwrap();
+        assert_eq!(json.get("postcode"), None);
+        assert_eq!(json.get("admin"), None);
+    }
+
+    #[test]
+    fn addresses_with_several_postcodes() {
+        let zip_codes = vec!["75013".to_string(), "75012".to_string()];
+        let geocoding = geocoding(Place::Addr(addr(zip_codes)));
+        assert_eq!(geocoding.postcode, Some("75013".to_string()));
+    }
+
+    #[test]
+    fn streets() {
+        let geocoding = geocoding(Place::Street(street()));
+        assert_eq!(geocoding.place_type, "street");
+        assert_eq!(geocoding.name, Some("Rue Hector Malot".to_string()));
+        assert_eq!(geocoding.street, Some("Rue Hector Malot".to_string()));
+        assert_eq!(geocoding.housenumber, None);
+        assert_eq!(geocoding.postcode, Some("75012".to_string()));
+        assert_eq!(geocoding.city, Some("Paris".to_string()));
+        assert_eq!(geocoding.admin.len(), 4);
+    }
+
+    #[test]
+    fn zones() {
+        let zone = |name: &str, level: u32, zone_type, parents: &[Arc<Admin>]| {
+            Place::Admin(Admin {
+                id: format!("admin:{}", name),
+                name: name.to_string(),
+                label: name.to_string(),
+                level,
+                zone_type,
+                administrative_regions: parents.to_vec(),
+                zip_codes: vec!["75001".to_string(), "75002".to_string()],
+                ..Default::default()
+            })
+        };
+        let admins = admins();
+
+        let city = geocoding(zone(
+            "Paris",
+            8,
+            Some(cosmogony::ZoneType::City),
+            &admins[2..],
+        ));
+        assert_eq!(city.place_type, "city");
+        // the zone is its own city, and one of the admins
+        assert_eq!(city.city, Some("Paris".to_string()));
+        assert_eq!(city.locality, None);
+        assert_eq!(city.postcode, Some("75001".to_string()));
+        assert_eq!(
+            city.admin,
+            levels(&[(2, "France"), (4, "Île-de-France"), (8, "Paris")])
+        );
+
+        let suburb = geocoding(zone(
+            "Plaisance",
+            10,
+            Some(cosmogony::ZoneType::Suburb),
+            &admins,
+        ));
+        assert_eq!(suburb.place_type, "locality");
+        assert_eq!(suburb.locality, Some("Plaisance".to_string()));
+        assert_eq!(suburb.district, Some("Plaisance".to_string()));
+        assert_eq!(suburb.city, Some("Paris".to_string()));
+
+        let state = geocoding(zone(
+            "Île-de-France",
+            4,
+            Some(cosmogony::ZoneType::State),
+            &admins[3..],
+        ));
+        assert_eq!(state.place_type, "region");
+        assert_eq!(state.state, Some("Île-de-France".to_string()));
+        assert_eq!(state.city, None);
+
+        let country = geocoding(zone("France", 2, Some(cosmogony::ZoneType::Country), &[]));
+        assert_eq!(country.place_type, "country");
+        assert_eq!(country.country, Some("France".to_string()));
+        assert_eq!(country.admin, levels(&[(2, "France")]));
+
+        // the zones without zone type or admin level are localities, and not admins
+        let zone = geocoding(zone("Marais", 0, None, &admins[1..]));
+        assert_eq!(zone.place_type, "locality");
+        assert_eq!(zone.locality, Some("Marais".to_string()));
+        assert!(!zone.admin.values().any(|name| name == "Marais"));
+    }
+
+    #[test]
+    fn pois_and_stops() {
+        let poi = geocoding(Place::Poi(Poi {
+            id: "poi:osm:node:1".to_string(),
+            name: "Bibliothèque".to_string(),
+            label: "Bibliothèque (Paris)".to_string(),
+            administrative_regions: admins(),
+            ..Default::default()
+        }));
+        assert_eq!(poi.place_type, "poi");
+        assert_eq!(poi.name, Some("Bibliothèque".to_string()));
+        assert_eq!(poi.city, Some("Paris".to_string()));
+        assert_eq!(poi.country, Some("France".to_string()));
+        assert_eq!(poi.housenumber, None);
+
+        let stop = geocoding(Place::Stop(Stop {
+            id: "stop_area:gare_de_lyon".to_string(),
+            name: "Gare de Lyon".to_string(),
+            label: "Gare de Lyon (Paris)".to_string(),
+            administrative_regions: admins()[1..].to_vec(),
+            ..Default::default()
+        }));
+        assert_eq!(stop.place_type, "poi");
+        assert_eq!(stop.id, "stop_area:gare_de_lyon");
+        assert_eq!(stop.district, None);
+        assert_eq!(stop.admin.len(), 3);
+    }
+
+    #[test]
+    fn responses() {
+        let autocomplete = Autocomplete::new(
+            "rue hector malot".to_string(),
+            vec![Feature::from_with_lang(Place::Street(street()), None)],
+        );
+        let response = serde_json::to_value(GeocodeJson::from(autocomplete)).unwrap();
+        assert_eq!(response["type"], json!("FeatureCollection"));
+        assert_eq!(response["geocoding"]["query"], json!("rue hector malot"));
+        let feature = &response["features"][0];
+        assert_eq!(feature["type"], json!("Feature"));
+        assert_eq!(feature["geometry"]["type"], json!("Point"));
+        let geocoding = &feature["properties"]["geocoding"];
+        assert_eq!(geocoding["type"], json!("street"));
+        assert_eq!(geocoding["admin"]["level2"], json!("France"));
+        // the fields of bragi are not in the spec
+        assert_eq!(geocoding.get("administrative_regions"), None);
+        assert_eq!(geocoding.get("citycode"), None);
+    }
+}
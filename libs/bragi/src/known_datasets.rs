@@ -0,0 +1,154 @@
+// Cache of the aliases of the indexes of the places, from which the autocomplete tells the
+// requested datasets having no index.
+//
+// The aliases are read from ES at most once every `max_age` (and again after an
+// `/admin/refresh`), instead of once by autocomplete asking for datasets.
+// If they cannot be read, the previous ones are kept. They are counted in the memory budget of
+// the caches, and read every time if they do not fit in it.
+
+use crate::memory::{Lru, MemoryBudget};
+use crate::model::BragiError;
+use slog_scope::warn;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Delay after which the known datasets are read again from ES
+pub const KNOWN_DATASETS_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// The aliases of each index
+pub type Aliases = BTreeMap<String, Vec<String>>;
+
+// Approximate number of bytes used by the aliases
+fn aliases_size(aliases: &Aliases) -> usize {
+    aliases
+        .iter()
+        .map(|(index, aliases)| {
+            let aliases: usize = aliases
+                .iter()
+                .map(|alias| size_of::<String>() + alias.capacity())
+                .sum();
+            size_of::<(String, Vec<String>)>() + index.capacity() + aliases
+        })
+        .sum()
+}
+
+#[derive(Debug)]
+pub struct KnownDatasets {
+    max_age: Duration,
+    // the aliases, and when they have been read (the only entry of the cache)
+    aliases: Mutex<Lru<(), (Instant, Arc<Aliases>)>>,
+}
+
+impl KnownDatasets {
+    pub fn new(max_age: Duration, budget: Arc<MemoryBudget>) -> Self {
+        KnownDatasets {
+            max_age,
+            aliases: Mutex::new(Lru::new("known_datasets", budget)),
+        }
+    }
+
+    /// The cached aliases, read with `read` if they are missing or older than `max_age`.
+    ///
+    /// None if they have never been read.
+    pub fn get<F>(&self, read: F) -> Option<Arc<Aliases>>
+    where
+        F: FnOnce() -> Result<Aliases, BragiError>,
+    {
+        let cached = self
+            .aliases
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&())
+            .cloned();
+        match cached {
+            Some((read_at, aliases)) if read_at.elapsed() < self.max_age => Some(aliases),
+            cached => match read() {
+                Ok(aliases) => {
+                    let size = aliases_size(&aliases);
+                    let aliases = Arc::new(aliases);
+                    self.aliases
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert((), (Instant::now(), aliases.clone()), size);
+                    Some(aliases)
+                }
+                Err(e) => {
+                    warn!("impossible to get the aliases of the datasets: {}", e);
+                    cached.map(|(_, aliases)| aliases)
+                }
+            },
+        }
+    }
+
+    /// The aliases are read again by the next `get`
+    pub fn invalidate(&self) {
+        self.aliases
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::DEFAULT_CACHE_BUDGET;
+    use rs_es::error::EsError;
+
+    fn budget() -> Arc<MemoryBudget> {
+        Arc::new(MemoryBudget::new(DEFAULT_CACHE_BUDGET))
+    }
+
+    fn aliases(dataset: &str) -> Aliases {
+        vec![(
+            format!("munin_stop_{}_20200101", dataset),
+            vec![format!("munin_stop_{}", dataset)],
+        )]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn aliases_are_read_once_until_invalidated() {
+        let known_datasets = KnownDatasets::new(KNOWN_DATASETS_MAX_AGE, budget());
+        let first = known_datasets.get(|| Ok(aliases("fr")));
+        assert_eq!(first.as_deref(), Some(&aliases("fr")));
+        let cached = known_datasets.get(|| panic!("the aliases must be cached"));
+        assert_eq!(cached.as_deref(), Some(&aliases("fr")));
+
+        known_datasets.invalidate();
+        let refreshed = known_datasets.get(|| Ok(aliases("be")));
+        assert_eq!(refreshed.as_deref(), Some(&aliases("be")));
+    }
+
+    #[test]
+    fn stale_aliases_are_read_again_and_kept_on_failure() {
+        let known_datasets = KnownDatasets::new(Duration::from_secs(0), budget());
+        known_datasets.get(|| Ok(aliases("fr")));
+        let refreshed = known_datasets.get(|| Ok(aliases("be")));
+        assert_eq!(refreshed.as_deref(), Some(&aliases("be")));
+
+        let kept = known_datasets.get(|| Err(EsError::EsServerError("down".to_string()).into()));
+        assert_eq!(kept.as_deref(), Some(&aliases("be")));
+    }
+
+    #[test]
+    fn no_aliases_if_never_read() {
+        let known_datasets = KnownDatasets::new(KNOWN_DATASETS_MAX_AGE, budget());
+        let none = known_datasets.get(|| Err(EsError::EsServerError("down".to_string()).into()));
+        assert!(none.is_none());
+    }
+
+    #[test]
+    fn aliases_beyond_the_budget_are_read_every_time() {
+        let budget = Arc::new(MemoryBudget::new(aliases_size(&aliases("fr")) - 1));
+        let known_datasets = KnownDatasets::new(KNOWN_DATASETS_MAX_AGE, budget.clone());
+        let first = known_datasets.get(|| Ok(aliases("fr")));
+        assert_eq!(first.as_deref(), Some(&aliases("fr")));
+        assert_eq!(budget.used(), 0);
+        let again = known_datasets.get(|| Ok(aliases("be")));
+        assert_eq!(again.as_deref(), Some(&aliases("be")));
+    }
+}
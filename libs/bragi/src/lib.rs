@@ -31,20 +31,42 @@
 #[macro_use]
 extern crate prometheus;
 
-use mimir::rubber::Rubber;
+use api_keys::ApiKeys;
+use circuit_breaker::{CircuitBreakers, INDEX_TYPES};
+use experiments::Experiments;
+use known_datasets::{Aliases, KnownDatasets, KNOWN_DATASETS_MAX_AGE};
+use memory::MemoryBudget;
+use mimir::rubber::{Rubber, TransportSettings};
+use model::BragiError;
+use query_recorder::QueryRecorder;
+use routes::EffectiveSettings;
 use slog_scope::debug;
 use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use structopt::StructOpt;
 
 use std::fs::read_to_string;
 
+pub mod access_log_middleware;
+mod api_keys;
+mod cancellation;
+mod circuit_breaker;
+pub mod compression_middleware;
+mod experiments;
 mod extractors;
+mod geocodejson;
+mod known_datasets;
+mod memory;
 mod model;
 pub mod prometheus_middleware;
 pub(crate) mod query;
+pub mod query_recorder;
 mod query_settings;
+pub mod request_id_middleware;
 mod routes;
+mod search_pool;
 pub mod server;
 
 pub use query::make_place as query_make_place;
@@ -110,6 +132,101 @@ pub struct Args {
     pub http_cache_duration: u32,
     #[structopt(long = "weight-config-file")]
     pub weight_config_file: Option<String>,
+    /// Directory of the layered settings, replacing `--weight-config-file`:
+    /// its `default.toml` is overlaid by the `<run mode>.toml` of the run mode.
+    #[structopt(long = "config-dir", env = "BRAGI_CONFIG_DIR", parse(from_os_str))]
+    pub config_dir: Option<PathBuf>,
+    /// Run mode (eg dev, staging or prod) selecting the settings overlay of `--config-dir`.
+    /// The command line arguments and environment variables still override the settings.
+    #[structopt(long = "run-mode", env = "BRAGI_RUN_MODE")]
+    pub run_mode: Option<String>,
+    /// Header used to propagate a request id, to correlate the logs with the upstream services
+    /// (X-Request-Id if not set). The id is read from the request (or generated if missing), added
+    /// to the logs, and sent back in the response header and in the error bodies.
+    /// With `none`, no request id is propagated.
+    #[structopt(long = "request-id-header", env = "BRAGI_REQUEST_ID_HEADER")]
+    pub request_id_header: Option<String>,
+    /// Requests taking longer than this threshold (in ms) are logged at warn level
+    /// with their query and duration. If not set, the slow requests are not logged.
+    #[structopt(long = "slow-query-threshold", env = "BRAGI_SLOW_QUERY_THRESHOLD")]
+    pub slow_query_threshold: Option<u64>,
+    /// The searches sent to ES are logged at debug level with their indexes, their query
+    /// truncated to this length (in bytes), the time taken by ES and their number of hits.
+    /// If not set, the searches are not logged.
+    #[structopt(long = "es-query-log-length", env = "BRAGI_ES_QUERY_LOG_LENGTH")]
+    pub es_query_log_length: Option<usize>,
+    /// Maximum size (in bytes) of the body of a POST /autocomplete (ie of the shape).
+    /// Bigger bodies are rejected with a 413 before being parsed. Defaults to 32 KiB.
+    #[structopt(long = "max-shape-size", env = "BRAGI_MAX_SHAPE_SIZE")]
+    pub max_shape_size: Option<usize>,
+    /// Maximum number of vertices of the shape of a POST /autocomplete.
+    /// Shapes with more vertices are rejected with a 400. Defaults to 10000.
+    #[structopt(long = "max-shape-vertices", env = "BRAGI_MAX_SHAPE_VERTICES")]
+    pub max_shape_vertices: Option<usize>,
+    /// Maximum time (in ms) of the validation of the shape of a POST /autocomplete,
+    /// made on the blocking threads. Slower shapes are rejected with a 400. Defaults to 100.
+    #[structopt(
+        long = "max-shape-validation-time",
+        env = "BRAGI_MAX_SHAPE_VALIDATION_TIME"
+    )]
+    pub max_shape_validation_time: Option<u64>,
+    /// Number of consecutive ES failures after which the requests are rejected right away
+    /// with a 503, instead of waiting for the ES timeout. 0 disables it.
+    /// Overrides `elasticsearch.circuit_breaker.max_failures` of the settings (5 by default).
+    #[structopt(
+        long = "circuit-breaker-max-failures",
+        env = "BRAGI_CIRCUIT_BREAKER_MAX_FAILURES"
+    )]
+    pub circuit_breaker_max_failures: Option<u32>,
+    /// Duration (in ms) during which the requests are rejected once the ES failures
+    /// have opened the circuit breaker. After it, one request is sent to ES to check if it is back.
+    /// Overrides `elasticsearch.circuit_breaker.cooldown` of the settings (10000 by default).
+    #[structopt(
+        long = "circuit-breaker-cooldown",
+        env = "BRAGI_CIRCUIT_BREAKER_COOLDOWN"
+    )]
+    pub circuit_breaker_cooldown: Option<u64>,
+    /// Minimum size (in bytes) of the responses compressed (with gzip, deflate or brotli)
+    /// for the clients accepting it. Defaults to 1 KiB.
+    #[structopt(long = "compression-threshold", env = "BRAGI_COMPRESSION_THRESHOLD")]
+    pub compression_threshold: Option<usize>,
+    /// Disables the compression of the responses.
+    #[structopt(long = "disable-compression", env = "BRAGI_DISABLE_COMPRESSION")]
+    pub disable_compression: bool,
+    /// Exposes the effective settings in /settings (without their secrets), for debugging.
+    #[structopt(long = "expose-settings")]
+    pub expose_settings: bool,
+    /// Enables POST /admin/refresh, refreshing the indexes so that the documents just imported
+    /// can be found, for the tests and the tools. Not for production, a refresh is costly.
+    #[structopt(long = "enable-refresh")]
+    pub enable_refresh: bool,
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum Command {
+    /// Prints the settings resolved from the settings files and the run mode, and exits.
+    Config,
+}
+
+// the default limit of the json bodies of actix
+const DEFAULT_MAX_SHAPE_SIZE: usize = 32 * 1024;
+const DEFAULT_MAX_SHAPE_VERTICES: usize = 10_000;
+const DEFAULT_MAX_SHAPE_VALIDATION_TIME: Duration = Duration::from_millis(100);
+
+impl Args {
+    /// Minimum size of the compressed responses, None if the compression is disabled
+    pub fn compression_threshold(&self) -> Option<usize> {
+        if self.disable_compression {
+            None
+        } else {
+            Some(
+                self.compression_threshold
+                    .unwrap_or(compression_middleware::DEFAULT_COMPRESSION_THRESHOLD),
+            )
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -119,8 +236,31 @@ pub struct Context {
     autocomplete_rubber: Rubber,
     pub cnx_string: String,
     pub http_cache_duration: u32,
+    pub max_shape_size: usize,
+    pub max_shape_vertices: usize,
+    pub max_shape_validation_time: Duration,
+    // shared by all the workers, so that they all stop querying ES during its outages
+    circuit_breakers: Arc<CircuitBreakers>,
+    // the datasets having an index, shared by all the workers
+    known_datasets: Arc<KnownDatasets>,
+    // the memory budget shared by the caches
+    memory_budget: Arc<MemoryBudget>,
     // pub rubber: Rubber,
-    query_settings: QuerySettings,
+    // parsed once at startup, the context is cloned for each worker
+    query_settings: Arc<QuerySettings>,
+    // the alternative settings used for a share of the requests
+    experiments: Arc<Experiments>,
+    // only set if the settings are exposed
+    effective_settings: Option<Arc<EffectiveSettings>>,
+    // the indexes can only be refreshed on demand if enabled
+    refresh_enabled: bool,
+    // only set if the requests are authenticated with api keys
+    api_keys: Option<Arc<ApiKeys>>,
+    // only set if the queries are recorded
+    query_recorder: Option<Arc<QueryRecorder>>,
+    // shown in /status to tell apart the instances running with different settings
+    pub settings_hash: String,
+    pub run_mode: Option<String>,
 }
 
 impl TryFrom<&Args> for Context {
@@ -140,39 +280,132 @@ impl TryFrom<&Args> for Context {
                 .or(max_es_timeout)
         };
 
-        let content = match args.weight_config_file {
-            Some(ref file_path) => read_to_string(&file_path)
-                .map_err(|e| format!("Failed to read `{}`: {}", file_path, e))?,
-            None => include_str!("../../../config/bragi-settings.toml").to_owned(),
+        let settings = read_settings(args)?;
+        let effective_settings =
+            EffectiveSettings::new(&args.connection_string, args.run_mode.clone(), &settings)?;
+        let settings_hash = effective_settings.hash();
+        let effective_settings = if args.expose_settings {
+            Some(Arc::new(effective_settings))
+        } else {
+            None
         };
+        let query_settings = QuerySettings::from_toml(settings.clone())
+            .map_err(|err| format!("invalid settings: {}", err))?;
+        query_settings.service.check_endpoints()?;
+        let experiments = Experiments::new(&settings, &query_settings.query.experiments)?;
+        let memory_budget = Arc::new(MemoryBudget::new(
+            query_settings.service.memory.cache_budget,
+        ));
+        let api_keys = query_settings
+            .api_keys
+            .as_ref()
+            .map(|settings| ApiKeys::new(&settings.file, memory_budget.clone()).map(Arc::new))
+            .transpose()?;
+        let query_recorder = query_settings
+            .query_recorder
+            .as_ref()
+            .map(|settings| QueryRecorder::new(settings).map(Arc::new))
+            .transpose()?;
+        let transport = TransportSettings::from(&query_settings.elasticsearch.transport);
+        let circuit_breaker_settings = &query_settings.elasticsearch.circuit_breaker;
+        let circuit_breakers = CircuitBreakers::new(
+            args.circuit_breaker_max_failures
+                .unwrap_or(circuit_breaker_settings.max_failures),
+            Duration::from_millis(
+                args.circuit_breaker_cooldown
+                    .unwrap_or(circuit_breaker_settings.cooldown),
+            ),
+        );
+        let query_log_length = args.es_query_log_length.unwrap_or(0);
         Ok(Self {
             reverse_rubber: Rubber::new_with_timeout(
                 &args.connection_string,
                 bounded_timeout(args.max_es_reverse_timeout),
-            ),
+            )
+            .with_transport(transport.clone())
+            .with_query_log_length(query_log_length),
             features_rubber: Rubber::new_with_timeout(
                 &args.connection_string,
                 bounded_timeout(args.max_es_features_timeout),
-            ),
+            )
+            .with_transport(transport.clone())
+            .with_query_log_length(query_log_length),
             autocomplete_rubber: Rubber::new_with_timeout(
                 &args.connection_string,
                 bounded_timeout(args.max_es_autocomplete_timeout),
-            ),
+            )
+            .with_transport(transport.clone())
+            .with_query_log_length(query_log_length),
             cnx_string: args.connection_string.clone(),
             http_cache_duration: args.http_cache_duration,
-            query_settings: QuerySettings::new(&content).map_err(|err| {
-                format!(
-                    "failed to parse `{}`: {}",
-                    args.weight_config_file
-                        .as_deref()
-                        .unwrap_or("config/bragi-settings.toml"),
-                    err
-                )
-            })?,
+            max_shape_size: args.max_shape_size.unwrap_or(DEFAULT_MAX_SHAPE_SIZE),
+            max_shape_vertices: args
+                .max_shape_vertices
+                .unwrap_or(DEFAULT_MAX_SHAPE_VERTICES),
+            max_shape_validation_time: args
+                .max_shape_validation_time
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_MAX_SHAPE_VALIDATION_TIME),
+            circuit_breakers: Arc::new(circuit_breakers),
+            known_datasets: Arc::new(KnownDatasets::new(
+                KNOWN_DATASETS_MAX_AGE,
+                memory_budget.clone(),
+            )),
+            memory_budget,
+            query_settings: Arc::new(query_settings),
+            experiments: Arc::new(experiments),
+            effective_settings,
+            refresh_enabled: args.enable_refresh,
+            api_keys,
+            query_recorder,
+            settings_hash,
+            run_mode: args.run_mode.clone(),
         })
     }
 }
 
+fn read_toml(path: &Path) -> Result<toml::Value, String> {
+    let content =
+        read_to_string(path).map_err(|e| format!("Failed to read `{}`: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))
+}
+
+/// Reads the settings (without parsing them, so that they can be printed as is).
+///
+/// With `--config-dir`, they are read from its `default.toml`, on which the `<run mode>.toml`
+/// overlay is merged. Otherwise, they are read from `--weight-config-file`,
+/// or are the default settings of `config/bragi-settings.toml`.
+pub fn read_settings(args: &Args) -> Result<toml::Value, String> {
+    let config_dir = match (&args.config_dir, &args.weight_config_file) {
+        (Some(_), Some(_)) => {
+            return Err("--config-dir and --weight-config-file cannot be used together".into())
+        }
+        (Some(config_dir), None) => config_dir,
+        (None, _) if args.run_mode.is_some() => {
+            return Err("a run mode can only be used with --config-dir".into())
+        }
+        (None, Some(file_path)) => return read_toml(Path::new(file_path)),
+        (None, None) => {
+            return toml::from_str(include_str!("../../../config/bragi-settings.toml"))
+                .map_err(|e| format!("failed to parse `config/bragi-settings.toml`: {}", e))
+        }
+    };
+    let mut settings = read_toml(&config_dir.join("default.toml"))?;
+    if let Some(run_mode) = &args.run_mode {
+        let overlay_path = config_dir.join(format!("{}.toml", run_mode));
+        // a typo in the run mode must not silently run with the base settings
+        if !overlay_path.is_file() {
+            return Err(format!(
+                "no settings for the run mode `{}`: `{}` not found",
+                run_mode,
+                overlay_path.display()
+            ));
+        }
+        query_settings::merge_settings(&mut settings, read_toml(&overlay_path)?);
+    }
+    Ok(settings)
+}
+
 impl Context {
     pub fn get_rubber_for_reverse(&self, timeout: Option<Duration>) -> Rubber {
         clone_or_create(&self.reverse_rubber, timeout)
@@ -186,16 +419,93 @@ impl Context {
     pub fn get_query_settings(&self) -> &QuerySettings {
         &self.query_settings
     }
+    pub(crate) fn get_experiments(&self) -> &Experiments {
+        &self.experiments
+    }
+    pub(crate) fn get_api_keys(&self) -> Option<&ApiKeys> {
+        self.api_keys.as_deref()
+    }
+    /// Whether the requests are authenticated with api keys
+    pub(crate) fn has_api_keys(&self) -> bool {
+        self.api_keys.is_some()
+    }
+    pub(crate) fn get_query_recorder(&self) -> Option<&QueryRecorder> {
+        self.query_recorder.as_deref()
+    }
+    pub(crate) fn get_effective_settings(&self) -> Option<&EffectiveSettings> {
+        self.effective_settings.as_deref()
+    }
+    pub(crate) fn is_refresh_enabled(&self) -> bool {
+        self.refresh_enabled
+    }
+    pub(crate) fn get_circuit_breakers(&self) -> &CircuitBreakers {
+        &self.circuit_breakers
+    }
+    /// The aliases of the indexes of the places, read from ES through the circuit breaker
+    /// when the cached ones are too old
+    pub(crate) fn get_known_datasets(&self) -> Option<Arc<Aliases>> {
+        self.known_datasets.get(|| {
+            let rubber = self.get_rubber_for_autocomplete(None);
+            self.circuit_breakers.call(&INDEX_TYPES, || {
+                rubber.get_aliases("munin").map_err(BragiError::from)
+            })
+        })
+    }
+    /// The known datasets are read again from ES by the next request
+    pub(crate) fn invalidate_known_datasets(&self) {
+        self.known_datasets.invalidate();
+    }
+    pub(crate) fn get_memory_budget(&self) -> &Arc<MemoryBudget> {
+        &self.memory_budget
+    }
 }
 
 fn clone_or_create(rubber: &Rubber, timeout: Option<Duration>) -> Rubber {
     if rubber.timeout == timeout {
-        // we clone the rs_es_client, reusing the reqwest connection pool
+        // we clone the http client, reusing the reqwest connection pool
         rubber.clone()
     } else {
         // if the timeout is different, since there as no easy way to change the timeout for the moment
         // we build a new Rubber (and thus a new connection)
         debug!("creating a new rubber for timeout {:?}", &timeout);
         Rubber::new_with_timeout(&rubber.cnx_string, timeout)
+            .with_transport(rubber.transport.clone())
+            .with_query_log_length(rubber.query_log_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rubbers_are_built_with_the_transport_of_the_settings() {
+        let ctx = Context::try_from(&Args {
+            connection_string: "http://localhost:9200/munin".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let expected = TransportSettings {
+            keep_alive: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(50)),
+        };
+        assert_eq!(ctx.get_rubber_for_autocomplete(None).transport, expected);
+        // the rubbers created for another timeout keep the transport
+        let rubber = ctx.get_rubber_for_reverse(Some(Duration::from_millis(42)));
+        assert_eq!(rubber.timeout, Some(Duration::from_millis(42)));
+        assert_eq!(rubber.transport, expected);
+    }
+
+    #[test]
+    fn rubbers_log_the_searches_if_asked() {
+        let ctx = Context::try_from(&Args {
+            connection_string: "http://localhost:9200/munin".to_string(),
+            es_query_log_length: Some(500),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(ctx.get_rubber_for_features(None).query_log_length, 500);
+        let rubber = ctx.get_rubber_for_autocomplete(Some(Duration::from_millis(42)));
+        assert_eq!(rubber.query_log_length, 500);
     }
 }
@@ -0,0 +1,320 @@
+// Memory budget of the in-process caches (the known datasets, the profiles of the api keys).
+//
+// All the caches share the byte budget given by `service.memory.cache_budget`. Each cache is an
+// `Lru` evicting its least recently used entries until its new entry fits in what the caches
+// use all together: an entry larger than the budget, or than what the other caches leave, is
+// not cached. The bytes used by each cache are given in `bragi_memory_bytes` of the metrics,
+// its evictions in `bragi_cache_evictions_total`, and both are logged periodically.
+
+use slog_scope::info;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Bytes shared by the caches if not given in the settings
+pub const DEFAULT_CACHE_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Interval between two summaries of the memory used by the caches in the logs
+pub const MEMORY_SUMMARY_INTERVAL: Duration = Duration::from_secs(300);
+
+lazy_static::lazy_static! {
+    static ref MEMORY_GAUGE: prometheus::GaugeVec = prometheus::register_gauge_vec!(
+        "bragi_memory_bytes",
+        "Approximate memory footprint (in bytes) of the structures held by bragi.",
+        &["structure"]
+    )
+    .unwrap();
+    static ref EVICTIONS_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_cache_evictions_total",
+        "Total number of entries evicted from the caches (or not cached) to stay within their memory budget.",
+        &["cache"]
+    )
+    .unwrap();
+}
+
+// bytes used by a cache, and its evictions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheUsage {
+    pub bytes: usize,
+    pub evictions: u64,
+}
+
+/// The budget shared by the caches, with what each of them uses
+#[derive(Debug)]
+pub struct MemoryBudget {
+    budget: usize,
+    usages: Mutex<BTreeMap<&'static str, CacheUsage>>,
+}
+
+impl MemoryBudget {
+    pub fn new(budget: usize) -> Self {
+        MemoryBudget {
+            budget,
+            usages: Mutex::default(),
+        }
+    }
+
+    /// Bytes used by all the caches
+    pub fn used(&self) -> usize {
+        self.usages().values().map(|usage| usage.bytes).sum()
+    }
+
+    /// What each cache uses
+    pub fn usages(&self) -> BTreeMap<&'static str, CacheUsage> {
+        self.usages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// One line summing up the usage of the caches
+    pub fn summary(&self) -> String {
+        let usages = self.usages();
+        let caches: Vec<String> = usages
+            .iter()
+            .map(|(cache, usage)| {
+                format!(
+                    "{}: {} KiB ({} evictions)",
+                    cache,
+                    usage.bytes / 1024,
+                    usage.evictions
+                )
+            })
+            .collect();
+        format!(
+            "{} KiB used of {} KiB by the caches [{}]",
+            self.used() / 1024,
+            self.budget / 1024,
+            caches.join(", ")
+        )
+    }
+
+    // reserves `bytes` for `cache` if they fit in the budget
+    fn try_grow(&self, cache: &'static str, bytes: usize) -> bool {
+        let mut usages = self.usages.lock().unwrap_or_else(|e| e.into_inner());
+        let used: usize = usages.values().map(|usage| usage.bytes).sum();
+        if used + bytes > self.budget {
+            return false;
+        }
+        let usage = usages.entry(cache).or_default();
+        usage.bytes += bytes;
+        MEMORY_GAUGE
+            .with_label_values(&[cache])
+            .set(usage.bytes as f64);
+        true
+    }
+
+    fn shrink(&self, cache: &'static str, bytes: usize, evicted: bool) {
+        let mut usages = self.usages.lock().unwrap_or_else(|e| e.into_inner());
+        let usage = usages.entry(cache).or_default();
+        usage.bytes = usage.bytes.saturating_sub(bytes);
+        MEMORY_GAUGE
+            .with_label_values(&[cache])
+            .set(usage.bytes as f64);
+        if evicted {
+            usage.evictions += 1;
+            EVICTIONS_COUNTER.with_label_values(&[cache]).inc();
+        }
+    }
+}
+
+/// Logs the summary of the memory used by the caches every `interval`
+pub fn log_usage_periodically(budget: Arc<MemoryBudget>, interval: Duration) -> Result<(), String> {
+    thread::Builder::new()
+        .name("bragi-memory".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            info!("memory of the caches: {}", budget.summary());
+        })
+        .map(|_| ())
+        .map_err(|e| format!("failed to spawn the memory summary thread: {}", e))
+}
+
+// a cached value, with its size and when it has been used for the last time
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    size: usize,
+    used_at: u64,
+}
+
+/// Cache evicting its least recently used entries to stay within the memory budget
+#[derive(Debug)]
+pub struct Lru<K: Clone + Eq + Hash, V> {
+    name: &'static str,
+    budget: Arc<MemoryBudget>,
+    entries: HashMap<K, Entry<V>>,
+    // the keys by last use, the least recently used first
+    by_use: BTreeMap<u64, K>,
+    clock: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> Lru<K, V> {
+    pub fn new(name: &'static str, budget: Arc<MemoryBudget>) -> Self {
+        Lru {
+            name,
+            budget,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// The cached value of `key`, which becomes the most recently used one
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(key)?;
+        self.by_use.remove(&entry.used_at);
+        self.by_use.insert(clock, key.clone());
+        entry.used_at = clock;
+        Some(&entry.value)
+    }
+
+    /// Caches `value` (using about `size` bytes) for `key`, after evicting the least recently
+    /// used entries if needed. False if it does not fit in the budget, and is not cached.
+    pub fn insert(&mut self, key: K, value: V, size: usize) -> bool {
+        self.remove(&key);
+        while !self.budget.try_grow(self.name, size) {
+            if !self.evict_least_recently_used() {
+                self.budget.shrink(self.name, 0, true);
+                return false;
+            }
+        }
+        self.clock += 1;
+        self.by_use.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                used_at: self.clock,
+            },
+        );
+        true
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.by_use.remove(&entry.used_at);
+        self.budget.shrink(self.name, entry.size, false);
+        Some(entry.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.by_use.clear();
+        for (_, entry) in self.entries.drain() {
+            self.budget.shrink(self.name, entry.size, false);
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) -> bool {
+        let used_at = match self.by_use.keys().next() {
+            Some(used_at) => *used_at,
+            None => return false,
+        };
+        if let Some(key) = self.by_use.remove(&used_at) {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.budget.shrink(self.name, entry.size, true);
+            }
+        }
+        true
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Drop for Lru<K, V> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let budget = Arc::new(MemoryBudget::new(300));
+        let mut lru = Lru::new("test", budget.clone());
+        assert!(lru.insert("a", 1, 100));
+        assert!(lru.insert("b", 2, 100));
+        assert!(lru.insert("c", 3, 100));
+        // `a` becomes more recent than `b`
+        assert_eq!(lru.get(&"a"), Some(&1));
+
+        assert!(lru.insert("d", 4, 150));
+        assert_eq!(lru.get(&"b"), None);
+        assert_eq!(lru.get(&"c"), None);
+        assert_eq!(lru.get(&"a"), Some(&1));
+        assert_eq!(lru.get(&"d"), Some(&4));
+        assert_eq!(budget.used(), 250);
+        assert_eq!(budget.usages()["test"].evictions, 2);
+
+        // too large for the whole budget
+        assert!(!lru.insert("e", 5, 301));
+        assert!(lru.entries.is_empty());
+        assert_eq!(budget.used(), 0);
+
+        // replacing an entry frees its previous size
+        assert!(lru.insert("a", 1, 200));
+        assert!(lru.insert("a", 2, 250));
+        assert_eq!(lru.entries.len(), 1);
+        assert_eq!(budget.used(), 250);
+    }
+
+    #[test]
+    fn caches_share_the_budget() {
+        let budget = Arc::new(MemoryBudget::new(1000));
+        let mut first = Lru::new("first", budget.clone());
+        let mut second = Lru::new("second", budget.clone());
+        assert!(first.insert(1, (), 600));
+        // a cache only evicts its own entries
+        assert!(!second.insert(1, (), 500));
+        assert!(second.insert(2, (), 400));
+        assert_eq!(budget.used(), 1000);
+        assert!(budget.summary().contains("first: 0 KiB (0 evictions)"));
+        assert!(budget.summary().contains("second: 0 KiB (1 evictions)"));
+
+        drop(first);
+        assert_eq!(budget.used(), 400);
+        assert!(second.insert(1, (), 500));
+    }
+
+    #[test]
+    fn large_shapes_stay_within_the_budget() {
+        // synthetic shapes of 10k to 100k vertices, like the detailed boundaries of regions
+        let shape = |nb_vertices: usize| -> Vec<(f64, f64)> {
+            (0..nb_vertices)
+                .map(|i| {
+                    let angle = i as f64 * 2. * std::f64::consts::PI / nb_vertices as f64;
+                    (2.35 + angle.cos(), 48.85 + angle.sin())
+                })
+                .collect()
+        };
+        let budget_size = 4 * 1024 * 1024;
+        let budget = Arc::new(MemoryBudget::new(budget_size));
+        let mut lru = Lru::new("shapes", budget.clone());
+        let mut total = 0;
+        for i in 0..50 {
+            let shape = shape(10_000 * (1 + i % 10));
+            let size = shape.len() * std::mem::size_of::<(f64, f64)>();
+            total += size;
+            assert!(lru.insert(i, shape, size));
+            assert!(budget.used() <= budget_size);
+            assert_eq!(
+                budget.used(),
+                lru.entries.values().map(|entry| entry.size).sum::<usize>()
+            );
+            // the last shape is never the one evicted
+            assert!(lru.get(&i).is_some());
+        }
+        assert!(total > 10 * budget_size);
+        assert!(budget.usages()["shapes"].evictions > 0);
+        assert_eq!(
+            lru.entries.len() as u64 + budget.usages()["shapes"].evictions,
+            50
+        );
+    }
+}
@@ -28,12 +28,20 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use crate::query::PlaceCluster;
+use crate::request_id_middleware::current_request_id;
 use failure::Fail;
 use heck::SnakeCase;
+use mimir::rubber::{es_error_status, SuggestedPlace};
 use rs_es::error::EsError;
 use serde::{Deserialize, Serialize};
 use slog_scope::error;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
+
+// non standard status (from nginx) of the requests whose client has disconnected
+const CLIENT_CLOSED_REQUEST: u16 = 499;
 
 #[derive(Fail, Debug)]
 pub enum BragiError {
@@ -41,16 +49,162 @@ pub enum BragiError {
     ObjectNotFound,
     #[fail(display = "Invalid parameter: {}", _0)]
     InvalidParam(&'static str),
-    #[fail(display = "invalid query {}", _0)]
-    Es(EsError),
+    #[fail(display = "Invalid parameters: {}", _0)]
+    InvalidParams(ValidationError),
+    /// An error of ES, with the http status of its response if it has answered
+    #[fail(display = "invalid query {}", error)]
+    Es { error: EsError, status: Option<u16> },
     #[fail(display = "invalid shape: {}", _0)]
     InvalidShape(&'static str),
+    #[fail(
+        display = "shape too complex: {} vertices, the maximum is {}",
+        nb_vertices, max_vertices
+    )]
+    ShapeTooComplex {
+        nb_vertices: usize,
+        max_vertices: usize,
+    },
+    #[fail(
+        display = "the validation of the shape took more than {:?}, it is too complex",
+        max_time
+    )]
+    ShapeValidationTimeout { max_time: Duration },
+    #[fail(display = "ES is unavailable, retry after {:?}", retry_after)]
+    EsUnavailable { retry_after: Duration },
+    #[fail(display = "the request has been cancelled by its client")]
+    Cancelled,
+    #[fail(display = "missing or invalid api key")]
+    Unauthorized,
+    #[fail(display = "forbidden: {}", _0)]
+    Forbidden(&'static str),
+    #[fail(display = "too many requests for this api key, retry later")]
+    RateLimited,
+    #[fail(display = "the daily quota of this api key is exceeded")]
+    QuotaExceeded { retry_after: Duration },
+    #[fail(
+        display = "the search template '{}' of the {} places is missing in ES",
+        template, doc_type
+    )]
+    MissingSearchTemplate {
+        template: String,
+        doc_type: &'static str,
+    },
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ApiError {
     pub short: String,
     pub long: String,
+    /// Machine readable code of the error, for the errors the clients may want to handle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    /// An error of the current request, without code
+    pub fn new(short: &str, long: String) -> Self {
+        ApiError {
+            short: short.to_owned(),
+            long,
+            code: None,
+            request_id: current_request_id(),
+        }
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_owned());
+        self
+    }
+}
+
+/// A non fatal problem of a request, given with its response
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ApiWarning {
+    /// Machine readable code of the warning
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiWarning {
+    /// The datasets of the request without any index, their places cannot be found
+    pub fn unknown_datasets(datasets: &[&str]) -> Self {
+        ApiWarning {
+            code: "UNKNOWN_DATASET".to_owned(),
+            message: format!("unknown datasets: {}", datasets.join(", ")),
+        }
+    }
+
+    /// The query has been cut to its first `max_length` characters
+    pub fn truncated_query(max_length: usize) -> Self {
+        ApiWarning {
+            code: "QUERY_TRUNCATED".to_owned(),
+            message: format!(
+                "the query has been truncated to its first {} characters",
+                max_length
+            ),
+        }
+    }
+
+    /// The places of these document types are missing, their search exceeded its deadline
+    pub fn partial_response(doc_types: &[&str]) -> Self {
+        ApiWarning {
+            code: "PARTIAL_RESPONSE".to_owned(),
+            message: format!(
+                "the places of these types are missing, their search took too long: {}",
+                doc_types.join(", ")
+            ),
+        }
+    }
+}
+
+/// All the problems found in the parameters of a request
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ValidationError {
+    pub problems: Vec<String>,
+}
+
+impl ValidationError {
+    /// Returns the value if it is valid, and records the problem otherwise
+    pub fn check<T>(&mut self, res: Result<T, BragiError>) -> Option<T> {
+        match res {
+            Ok(value) => Some(value),
+            Err(BragiError::InvalidParam(problem)) => {
+                self.problems.push(problem.to_owned());
+                None
+            }
+            Err(e) => {
+                self.problems.push(e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Returns the value if it is valid, and records all its problems otherwise
+    pub fn merge<T>(&mut self, res: Result<T, ValidationError>) -> Option<T> {
+        res.map_err(|e| self.problems.extend(e.problems)).ok()
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.problems.join(", "))
+    }
+}
+
+impl From<ValidationError> for BragiError {
+    fn from(e: ValidationError) -> Self {
+        BragiError::InvalidParams(e)
+    }
+}
+
+/// 400 of the invalid parameters, with all the problems found
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ValidationApiError {
+    #[serde(flatten)]
+    pub error: ApiError,
+    pub problems: Vec<String>,
 }
 
 // Q: It would be better to move it to ::v1 as it depends on the api interface
@@ -58,31 +212,89 @@ pub struct ApiError {
 impl actix_web::error::ResponseError for BragiError {
     fn render_response(&self) -> actix_web::HttpResponse {
         match *self {
-            BragiError::ObjectNotFound => actix_web::HttpResponse::NotFound().json(ApiError {
-                short: "query error".to_owned(),
-                long: format!("{}", self),
-            }),
-            BragiError::InvalidShape(_) => actix_web::HttpResponse::BadRequest().json(ApiError {
-                short: "validation error".to_owned(),
-                long: format!("{}", self),
-            }),
-            BragiError::InvalidParam(_) => actix_web::HttpResponse::BadRequest().json(ApiError {
-                short: "validation error".to_owned(),
-                long: format!("{}", self),
-            }),
-            BragiError::Es(ref es_error) => {
+            BragiError::ObjectNotFound => actix_web::HttpResponse::NotFound()
+                .json(ApiError::new("query error", format!("{}", self))),
+            BragiError::InvalidShape(_) => actix_web::HttpResponse::BadRequest()
+                .json(ApiError::new("validation error", format!("{}", self))),
+            BragiError::ShapeTooComplex { .. } => actix_web::HttpResponse::BadRequest()
+                .json(ApiError::new("shape too complex", format!("{}", self))),
+            BragiError::ShapeValidationTimeout { .. } => actix_web::HttpResponse::BadRequest()
+                .json(
+                    ApiError::new("shape too complex", format!("{}", self))
+                        .with_code("SHAPE_VALIDATION_TIMEOUT"),
+                ),
+            BragiError::InvalidParam(_) => actix_web::HttpResponse::BadRequest()
+                .json(ApiError::new("validation error", format!("{}", self))),
+            BragiError::InvalidParams(ref validation) => actix_web::HttpResponse::BadRequest()
+                .json(ValidationApiError {
+                    error: ApiError::new("validation error", format!("{}", self))
+                        .with_code("INVALID_PARAMS"),
+                    problems: validation.problems.clone(),
+                }),
+            BragiError::EsUnavailable { retry_after } => {
+                // the Retry-After header is in seconds, rounded up
+                let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                actix_web::HttpResponse::ServiceUnavailable()
+                    .header(
+                        actix_web::http::header::RETRY_AFTER,
+                        retry_after.to_string(),
+                    )
+                    .json(
+                        ApiError::new("query error", "service unavailable".to_owned())
+                            .with_code("ES_CIRCUIT_OPEN"),
+                    )
+            }
+            BragiError::Unauthorized => actix_web::HttpResponse::Unauthorized().json(
+                ApiError::new("authentication error", format!("{}", self))
+                    .with_code("UNAUTHORIZED"),
+            ),
+            BragiError::Forbidden(_) => actix_web::HttpResponse::Forbidden().json(
+                ApiError::new("authentication error", format!("{}", self)).with_code("FORBIDDEN"),
+            ),
+            BragiError::RateLimited => actix_web::HttpResponse::TooManyRequests()
+                .header(actix_web::http::header::RETRY_AFTER, "1")
+                .json(
+                    ApiError::new("too many requests", format!("{}", self))
+                        .with_code("RATE_LIMITED"),
+                ),
+            BragiError::QuotaExceeded { retry_after } => actix_web::HttpResponse::TooManyRequests()
+                .header(
+                    actix_web::http::header::RETRY_AFTER,
+                    retry_after.as_secs().to_string(),
+                )
+                .json(
+                    ApiError::new("too many requests", format!("{}", self))
+                        .with_code("QUOTA_EXCEEDED"),
+                ),
+            // nobody reads it, the client is gone, but it is seen by the metrics and the logs
+            BragiError::Cancelled => actix_web::HttpResponse::build(
+                actix_web::http::StatusCode::from_u16(CLIENT_CLOSED_REQUEST).unwrap(),
+            )
+            .json(ApiError::new("query error", format!("{}", self)).with_code("CANCELLED")),
+            // a misconfiguration, but it is detailed to be fixed quickly
+            BragiError::MissingSearchTemplate { .. } => {
+                error!("{}", self);
+                actix_web::HttpResponse::InternalServerError().json(
+                    ApiError::new("query error", format!("{}", self))
+                        .with_code("SEARCH_TEMPLATE_MISSING"),
+                )
+            }
+            BragiError::Es {
+                error: ref es_error,
+                ..
+            } => {
                 error!("es error on query: {}", &es_error);
                 match es_error {
                     EsError::HttpError { source: _ } => {
-                        actix_web::HttpResponse::ServiceUnavailable().json(ApiError {
-                            short: "query error".to_owned(),
-                            long: "service unavailable".to_owned(),
-                        })
+                        actix_web::HttpResponse::ServiceUnavailable().json(ApiError::new(
+                            "query error",
+                            "service unavailable".to_owned(),
+                        ))
                     }
-                    _ => actix_web::HttpResponse::InternalServerError().json(ApiError {
-                        short: "query error".to_owned(),
-                        long: "internal server error".to_owned(),
-                    }),
+                    _ => actix_web::HttpResponse::InternalServerError().json(ApiError::new(
+                        "query error",
+                        "internal server error".to_owned(),
+                    )),
                 }
             }
         }
@@ -91,7 +303,10 @@ impl actix_web::error::ResponseError for BragiError {
 
 impl From<EsError> for BragiError {
     fn from(e: EsError) -> Self {
-        BragiError::Es(e)
+        BragiError::Es {
+            status: es_error_status(&e).map(|status| status.as_u16()),
+            error: e,
+        }
     }
 }
 
@@ -99,6 +314,12 @@ impl From<EsError> for BragiError {
 pub struct Geocoding {
     version: String,
     query: Option<String>,
+    /// language of the labels, if not the default one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    /// how the query has been understood, if not as a text (eg `coordinate`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query_interpreted_as: Option<&'static str>,
 }
 
 #[derive(Serialize, Debug)]
@@ -107,10 +328,23 @@ pub struct Feature {
     pub feature_type: String,
     pub geometry: geojson::Geometry,
     pub properties: Properties,
+    /// Bounding box of the zones (to zoom on them),
+    /// only given if requested with `include_bbox`
+    #[serde(
+        serialize_with = "mimir::objects::serialize_rect",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bbox: Option<geo_types::Rect<f64>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub distance: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context: Option<mimir::Context>,
+    /// Score given by ES, only returned in the envelope of the feature
+    #[serde(skip)]
+    pub score: Option<f64>,
+    /// Raw score given by ES, only given if requested with `include_score` (to debug the relevance)
+    #[serde(rename = "_score", skip_serializing_if = "Option::is_none")]
+    pub shown_score: Option<f64>,
 }
 
 #[derive(Serialize, Debug)]
@@ -184,6 +418,8 @@ pub struct GeocodingResponse {
     pub city: Option<String>,
     pub citycode: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<u32>,
     // pub accuracy: Option<i32>,
     // pub district: Option<String>,
@@ -220,6 +456,29 @@ pub struct GeocodingResponse {
     pub bbox: Option<geo_types::Rect<f64>>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub country_codes: Vec<String>,
+    /// distance (in meters) along the street from its start to the returned coord,
+    /// only given by the reverse geocoding of the streets with a geometry
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub along_street_distance: Option<u32>,
+    /// alternative names of the place, only given if requested with `include_alt_names`
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub alt_names: Vec<String>,
+    /// population of the zones which have one, only given if requested with `include_demographics`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub population: Option<u64>,
+    /// area of the zones in km², only given if requested with `include_demographics`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub area: Option<f64>,
+    /// id that has been requested, when the place has been found through one of its old ids
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub moved_from: Option<String>,
+    /// name of the line of the query, for the stops found through the line they are served by,
+    /// or alternative name of the query, for the places found through one of their alternative names
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub matched_name: Option<String>,
+    /// flags given to the dataset of the place at its import (for example `verified`)
+    #[serde(skip_serializing_if = "mimir::Flags::is_empty", default)]
+    pub flags: mimir::Flags,
 }
 
 trait ToGeom {
@@ -248,6 +507,7 @@ impl FromWithLang<mimir::Place> for Feature {
     fn from_with_lang(other: mimir::Place, lang: Option<&str>) -> Feature {
         let geom = other.to_geom();
         let distance = other.distance();
+        let score = other.score();
         let context = other.context();
         let geocoding = match other {
             mimir::Place::Admin(admin) => GeocodingResponse::from_with_lang(admin, lang),
@@ -260,8 +520,11 @@ impl FromWithLang<mimir::Place> for Feature {
             feature_type: "Feature".to_string(),
             geometry: geom,
             properties: Properties { geocoding },
+            bbox: None,
             distance,
             context,
+            score,
+            shown_score: None,
         }
     }
 }
@@ -317,6 +580,10 @@ impl FromWithLang<mimir::Admin> for GeocodingResponse {
             codes: other.codes,
             country_codes: other.country_codes,
             administrative_regions: associated_admins,
+            alt_names: other.alt_names,
+            population: other.population,
+            area: other.area,
+            flags: other.flags,
             ..Default::default()
         }
     }
@@ -329,6 +596,19 @@ fn get_city_name(admins: &[Arc<mimir::Admin>]) -> Option<String> {
         .map(|admin| admin.name.clone())
 }
 
+// the address of a poi or a stop, as a nested response
+fn get_address(
+    address: Option<mimir::Address>,
+    lang: Option<&str>,
+) -> Option<Box<GeocodingResponse>> {
+    match address? {
+        mimir::Address::Addr(addr) => Some(Box::new(GeocodingResponse::from_with_lang(addr, lang))),
+        mimir::Address::Street(street) => {
+            Some(Box::new(GeocodingResponse::from_with_lang(street, lang)))
+        }
+    }
+}
+
 fn get_citycode(admins: &[Arc<mimir::Admin>]) -> Option<String> {
     admins
         .iter()
@@ -366,6 +646,9 @@ impl FromWithLang<mimir::Street> for GeocodingResponse {
             city,
             administrative_regions: associated_admins,
             country_codes: other.country_codes,
+            along_street_distance: other.along_street_distance,
+            alt_names: other.alt_names,
+            flags: other.flags,
             ..Default::default()
         }
     }
@@ -379,7 +662,7 @@ impl FromWithLang<mimir::Addr> for GeocodingResponse {
         let street_name = Some(other.street.name.to_string());
         let name = Some(other.name.to_string());
         let admins = other.street.administrative_regions;
-        let city = get_city_name(&admins);
+        let city = other.city.or_else(|| get_city_name(&admins));
         let postcode = if other.zip_codes.is_empty() {
             None
         } else {
@@ -402,8 +685,10 @@ impl FromWithLang<mimir::Addr> for GeocodingResponse {
             housenumber,
             street: street_name,
             city,
+            country: other.country,
             administrative_regions: associated_admins,
             country_codes: other.country_codes,
+            flags: other.flags,
             ..Default::default()
         }
     }
@@ -447,16 +732,10 @@ impl FromWithLang<mimir::Poi> for GeocodingResponse {
             administrative_regions: associated_admins,
             poi_types: vec![other.poi_type],
             properties: other.properties,
-            address: match other.address {
-                Some(mimir::Address::Addr(addr)) => {
-                    Some(Box::new(GeocodingResponse::from_with_lang(addr, lang)))
-                }
-                Some(mimir::Address::Street(street)) => {
-                    Some(Box::new(GeocodingResponse::from_with_lang(street, lang)))
-                }
-                _ => None,
-            },
+            address: get_address(other.address, lang),
             country_codes: other.country_codes,
+            alt_names: other.alt_names,
+            flags: other.flags,
             ..Default::default()
         }
     }
@@ -499,6 +778,8 @@ impl FromWithLang<mimir::Stop> for GeocodingResponse {
             properties: other.properties,
             feed_publishers: other.feed_publishers,
             country_codes: other.country_codes,
+            address: get_address(other.address, lang),
+            flags: other.flags,
             ..Default::default()
         }
     }
@@ -510,6 +791,18 @@ pub struct Autocomplete {
     pub format_type: String,
     pub geocoding: Geocoding,
     pub features: Vec<Feature>,
+    /// document types whose places are missing because their search exceeded its deadline
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partial: Vec<String>,
+    /// cursor of the next page of the browsed places, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// name of the experiment whose settings have been used for the request, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    /// non fatal problems of the request
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
 }
 
 impl Autocomplete {
@@ -520,20 +813,500 @@ impl Autocomplete {
             geocoding: Geocoding {
                 version: "0.1.0".to_string(),
                 query: Some(q),
+                lang: None,
+                query_interpreted_as: None,
             },
             features,
+            partial: vec![],
+            cursor: None,
+            experiment: None,
+            warnings: vec![],
+        }
+    }
+
+    /// Groups the features by their type, the groups being in the order of their best feature.
+    pub fn into_groups(self) -> GroupedAutocomplete {
+        let mut groups: Vec<PlaceGroup> = vec![];
+        for feature in self.features {
+            let place_type = &feature.properties.geocoding.place_type;
+            match groups.iter_mut().find(|g| &g.place_type == place_type) {
+                Some(group) => group.features.push(feature),
+                None => groups.push(PlaceGroup {
+                    place_type: place_type.clone(),
+                    features: vec![feature],
+                }),
+            }
+        }
+        GroupedAutocomplete {
+            geocoding: self.geocoding,
+            groups,
+            partial: self.partial,
+            cursor: self.cursor,
+            experiment: self.experiment,
+            warnings: self.warnings,
+        }
+    }
+
+    /// Wraps each feature in a `Hit` holding the values computed for the request.
+    ///
+    /// The confidence of a hit is its score relative to the best score of the response.
+    pub fn into_envelope(self) -> EnvelopedAutocomplete {
+        let max_score = self
+            .features
+            .iter()
+            .filter_map(|f| f.score)
+            .fold(None, |max: Option<f64>, score| {
+                Some(max.map_or(score, |max| max.max(score)))
+            });
+        EnvelopedAutocomplete {
+            format_type: self.format_type,
+            geocoding: self.geocoding,
+            partial: self.partial,
+            cursor: self.cursor,
+            experiment: self.experiment,
+            warnings: self.warnings,
+            features: self
+                .features
+                .into_iter()
+                .map(|mut feature| {
+                    let score = feature.score;
+                    let confidence = match (score, max_score) {
+                        (Some(score), Some(max_score)) if max_score > 0. => Some(score / max_score),
+                        _ => None,
+                    };
+                    Hit {
+                        score: score.unwrap_or(0.),
+                        distance: feature.distance.take(),
+                        confidence,
+                        source: feature,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn without_alt_names(mut self) -> Autocomplete {
+        for feature in &mut self.features {
+            feature.properties.geocoding.alt_names.clear();
+        }
+        self
+    }
+
+    /// Removes the population and the area of the zones
+    pub fn without_demographics(mut self) -> Autocomplete {
+        for feature in &mut self.features {
+            let geocoding = &mut feature.properties.geocoding;
+            geocoding.population = None;
+            geocoding.area = None;
+        }
+        self
+    }
+
+    /// Gives the raw ES score of each feature
+    pub fn with_scores(mut self) -> Autocomplete {
+        for feature in &mut self.features {
+            feature.shown_score = feature.score;
+        }
+        self
+    }
+
+    /// Adds their bbox to the features of the zones, the other places have no bbox
+    pub fn with_bbox(mut self) -> Autocomplete {
+        for feature in &mut self.features {
+            feature.bbox = feature.properties.geocoding.bbox;
+        }
+        self
+    }
+
+    /// Gives the name of the line of the query to the stops served by this line
+    /// (the query is matched against the code and the name of the lines)
+    pub fn with_matched_line(mut self, q: &str, line_code: &str) -> Autocomplete {
+        let q = q.trim().to_lowercase();
+        for feature in &mut self.features {
+            let geocoding = &mut feature.properties.geocoding;
+            geocoding.matched_name = geocoding
+                .lines
+                .iter()
+                .find(|line| {
+                    line.code
+                        .as_deref()
+                        .map_or(false, |code| code.eq_ignore_ascii_case(line_code))
+                        || line.name.to_lowercase() == q
+                })
+                .map(|line| line.name.clone());
+        }
+        self
+    }
+
+    /// Gives the alternative name matching the query to the places found through it
+    /// (the words of the query are prefixes of the words of the alternative name, but not of the name).
+    /// It must be called before the alternative names are removed from the response.
+    pub fn with_matched_alt_name(mut self, q: &str) -> Autocomplete {
+        let words: Vec<String> = q.split_whitespace().map(str::to_lowercase).collect();
+        let matches = |name: &str| {
+            let name = name.to_lowercase();
+            !words.is_empty()
+                && words.iter().all(|word| {
+                    name.split_whitespace()
+                        .any(|w| w.starts_with(word.as_str()))
+                })
+        };
+        for feature in &mut self.features {
+            let geocoding = &mut feature.properties.geocoding;
+            if geocoding.matched_name.is_some() || geocoding.name.as_deref().map_or(false, matches)
+            {
+                continue;
+            }
+            geocoding.matched_name = geocoding
+                .alt_names
+                .iter()
+                .find(|alt_name| matches(alt_name))
+                .cloned();
+        }
+        self
+    }
+
+    /// Tells how the query has been understood, if not as a text
+    pub fn with_query_interpreted_as(mut self, interpretation: &'static str) -> Autocomplete {
+        self.geocoding.query_interpreted_as = Some(interpretation);
+        self
+    }
+
+    /// Lists the document types dropped from the response because of their deadline
+    pub fn with_partial(mut self, partial: &[&str]) -> Autocomplete {
+        self.partial = partial.iter().map(|t| t.to_string()).collect();
+        if !partial.is_empty() {
+            self.warnings.push(ApiWarning::partial_response(partial));
+        }
+        self
+    }
+
+    /// Gives the cursor of the next page
+    pub fn with_cursor(mut self, cursor: Option<String>) -> Autocomplete {
+        self.cursor = cursor;
+        self
+    }
+
+    /// Gives the experiment whose settings have been used
+    pub fn with_experiment(mut self, experiment: Option<String>) -> Autocomplete {
+        self.experiment = experiment;
+        self
+    }
+
+    /// Adds the warnings of the request, before the ones of the search
+    pub fn with_warnings(mut self, mut warnings: Vec<ApiWarning>) -> Autocomplete {
+        warnings.append(&mut self.warnings);
+        self.warnings = warnings;
+        self
+    }
+}
+
+/// A feature along with the values computed for the request
+#[derive(Serialize, Debug)]
+pub struct Hit {
+    pub source: Feature,
+    pub score: f64,
+    /// distance in meters to the coord of the request, if any
+    pub distance: Option<u32>,
+    /// score normalized between 0 and 1
+    pub confidence: Option<f64>,
+}
+
+/// Response returned with `envelope=true`, where the features are wrapped in `Hit`s
+#[derive(Serialize, Debug)]
+pub struct EnvelopedAutocomplete {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    pub geocoding: Geocoding,
+    pub features: Vec<Hit>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partial: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
+}
+
+/// The features of a type (`house`, `street`, `zone`, `poi`...), in their order
+#[derive(Serialize, Debug)]
+pub struct PlaceGroup {
+    #[serde(rename = "type")]
+    pub place_type: String,
+    pub features: Vec<Feature>,
+}
+
+/// Response returned with `group_by_type=true`, where the features are grouped by their type
+#[derive(Serialize, Debug)]
+pub struct GroupedAutocomplete {
+    pub geocoding: Geocoding,
+    pub groups: Vec<PlaceGroup>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partial: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
+}
+
+/// Response of an autocomplete made with `count_only`
+#[derive(Serialize, Debug)]
+pub struct Count {
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
+}
+
+/// A place suggested while typing, with only its id, label, type and coordinates
+#[derive(Serialize, Debug)]
+pub struct Suggestion {
+    pub id: String,
+    pub label: String,
+    #[serde(rename = "type")]
+    pub place_type: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl From<SuggestedPlace> for Suggestion {
+    fn from(other: SuggestedPlace) -> Suggestion {
+        // the types of the features
+        let place_type = match other.doc_type.as_str() {
+            "addr" => "house",
+            "admin" => "zone",
+            "stop" => "public_transport:stop_area",
+            doc_type => doc_type,
+        };
+        Suggestion {
+            place_type: place_type.to_string(),
+            id: other.id,
+            label: other.label,
+            lat: other.coord.lat(),
+            lon: other.coord.lon(),
         }
     }
 }
 
+/// Response of `/autocomplete/suggest`
+#[derive(Serialize, Debug)]
+pub struct Suggestions {
+    pub suggestions: Vec<Suggestion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
+}
+
+/// Places of a cell of a grid, returned with `cluster=true`
+#[derive(Serialize, Debug)]
+pub struct Cluster {
+    /// Geohash of the cell of the cluster
+    pub geohash: String,
+    pub count: u64,
+    /// Centroid of the places of the cluster
+    pub centroid: geojson::Geometry,
+    /// Bounding box of the places of the cluster
+    #[serde(
+        serialize_with = "mimir::objects::serialize_rect",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bbox: Option<geo_types::Rect<f64>>,
+    /// The place of the cluster, only given when it is alone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature: Option<Feature>,
+}
+
+impl FromWithLang<PlaceCluster> for Cluster {
+    fn from_with_lang(other: PlaceCluster, lang: Option<&str>) -> Cluster {
+        Cluster {
+            geohash: other.geohash,
+            count: other.count,
+            centroid: other.centroid.to_geom(),
+            bbox: other.bbox,
+            feature: other
+                .place
+                .map(|place| Feature::from_with_lang(place, lang)),
+        }
+    }
+}
+
+/// Response of an autocomplete made with `cluster=true`
+#[derive(Serialize, Debug)]
+pub struct Clusters {
+    pub clusters: Vec<Cluster>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ApiWarning>,
+}
+
 impl FromWithLang<Vec<mimir::Place>> for Autocomplete {
     fn from_with_lang(places: Vec<mimir::Place>, lang: Option<&str>) -> Autocomplete {
-        Autocomplete::new(
+        let mut autocomplete = Autocomplete::new(
             "".to_string(),
             places
                 .into_iter()
                 .map(|p| Feature::from_with_lang(p, lang))
                 .collect(),
+        );
+        autocomplete.geocoding.lang = lang.map(str::to_string);
+        autocomplete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn make_response() -> Autocomplete {
+        let make_feature = |id: &str, score: f64, distance: Option<u32>| Feature {
+            feature_type: "Feature".to_string(),
+            geometry: geojson::Geometry::new(geojson::Value::Point(vec![2.0, 48.0])),
+            properties: Properties {
+                geocoding: GeocodingResponse {
+                    id: id.to_string(),
+                    place_type: "poi".to_string(),
+                    ..Default::default()
+                },
+            },
+            bbox: None,
+            distance,
+            context: None,
+            score: Some(score),
+            shown_score: None,
+        };
+        Autocomplete::new(
+            "bob".to_string(),
+            vec![make_feature("a", 4., Some(10)), make_feature("b", 1., None)],
         )
     }
+
+    #[test]
+    fn flat_response() {
+        let response = serde_json::to_value(make_response()).unwrap();
+        let first = &response["features"][0];
+        assert_eq!(first["type"], json!("Feature"));
+        assert_eq!(first["properties"]["geocoding"]["id"], json!("a"));
+        assert_eq!(first["distance"], json!(10));
+        assert_eq!(first.get("score"), None);
+        assert_eq!(first.get("_score"), None);
+        assert_eq!(response["features"][1].get("distance"), None);
+        // the existing clients see no change without warnings
+        assert_eq!(response.get("warnings"), None);
+        assert_eq!(response.get("partial"), None);
+    }
+
+    #[test]
+    fn response_with_warnings() {
+        let response = make_response()
+            .with_partial(&["addr", "poi"])
+            .with_warnings(vec![ApiWarning::unknown_datasets(&["fr", "be"])]);
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["partial"], json!(["addr", "poi"]));
+        assert_eq!(
+            response["warnings"],
+            json!([
+                { "code": "UNKNOWN_DATASET", "message": "unknown datasets: fr, be" },
+                {
+                    "code": "PARTIAL_RESPONSE",
+                    "message": "the places of these types are missing, their search took too long: addr, poi"
+                },
+            ])
+        );
+        let envelope = serde_json::to_value(make_response().into_envelope()).unwrap();
+        assert_eq!(envelope.get("warnings"), None);
+        let count = serde_json::to_value(Count {
+            count: 3,
+            experiment: None,
+            warnings: vec![],
+        })
+        .unwrap();
+        assert_eq!(count, json!({ "count": 3 }));
+        let count = serde_json::to_value(Count {
+            count: 3,
+            experiment: None,
+            warnings: vec![ApiWarning::truncated_query(200)],
+        })
+        .unwrap();
+        assert_eq!(count["warnings"][0]["code"], json!("QUERY_TRUNCATED"));
+    }
+
+    #[test]
+    fn response_with_scores() {
+        let response = serde_json::to_value(make_response().with_scores()).unwrap();
+        assert_eq!(response["features"][0]["_score"], json!(4.));
+        assert_eq!(response["features"][1]["_score"], json!(1.));
+    }
+
+    #[test]
+    fn enveloped_response() {
+        let response = serde_json::to_value(make_response().into_envelope()).unwrap();
+        assert_eq!(response["type"], json!("FeatureCollection"));
+        assert_eq!(response["geocoding"]["query"], json!("bob"));
+        let first = &response["features"][0];
+        assert_eq!(first["score"], json!(4.));
+        assert_eq!(first["distance"], json!(10));
+        assert_eq!(first["confidence"], json!(1.));
+        assert_eq!(first["source"]["properties"]["geocoding"]["id"], json!("a"));
+        // the distance is only given in the envelope
+        assert_eq!(first["source"].get("distance"), None);
+        let second = &response["features"][1];
+        assert_eq!(second["score"], json!(1.));
+        assert_eq!(second["distance"], Value::Null);
+        assert_eq!(second["confidence"], json!(0.25));
+    }
+
+    #[test]
+    fn grouped_response() {
+        let mut response = make_response();
+        let mut street = make_response().features.remove(1);
+        street.properties.geocoding.id = "c".to_string();
+        street.properties.geocoding.place_type = "street".to_string();
+        response.features.insert(1, street);
+        let response = serde_json::to_value(response.into_groups()).unwrap();
+        assert_eq!(response["geocoding"]["query"], json!("bob"));
+        assert_eq!(response.get("features"), None);
+        // the groups are in the order of their best feature, the features keep their order
+        let groups = response["groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0]["type"], json!("poi"));
+        let ids = |group: &Value| -> Vec<Value> {
+            group["features"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| f["properties"]["geocoding"]["id"].clone())
+                .collect()
+        };
+        assert_eq!(ids(&groups[0]), vec![json!("a"), json!("b")]);
+        assert_eq!(groups[1]["type"], json!("street"));
+        assert_eq!(ids(&groups[1]), vec![json!("c")]);
+    }
+
+    #[test]
+    fn matched_alt_name() {
+        let mut response = make_response();
+        for (feature, name) in response
+            .features
+            .iter_mut()
+            .zip(&["Rue Louis Blanc", "Rue Neuve"])
+        {
+            let geocoding = &mut feature.properties.geocoding;
+            geocoding.name = Some(name.to_string());
+            geocoding.alt_names = vec!["Rue du Faubourg".to_string(), "Rue Blanche".to_string()];
+        }
+        let response = response
+            .with_matched_alt_name("rue bla")
+            .without_alt_names();
+        // the name of the first place matches, the old name of the second one
+        let geocoding = &response.features[0].properties.geocoding;
+        assert_eq!(geocoding.matched_name, None);
+        let geocoding = &response.features[1].properties.geocoding;
+        assert_eq!(geocoding.matched_name.as_deref(), Some("Rue Blanche"));
+        assert!(geocoding.alt_names.is_empty());
+    }
 }
@@ -3,6 +3,7 @@
 // because  we want to use the name of the endpoint for retrocompatibility
 // (and as a side effect we also added the 'in flight' queries (but for this we could have used the Registry))
 
+use crate::access_log_middleware::redact_query;
 use actix_service::{Service, Transform};
 use actix_web::{
     dev::{Body, BodySize, MessageBody, ResponseBody, ServiceRequest, ServiceResponse},
@@ -13,9 +14,10 @@ use actix_web::{
 use futures::future::{ok, FutureResult};
 use futures::{Async, Future, Poll};
 use prometheus::{self, Encoder, TextEncoder};
+use slog_scope::warn;
 use std::marker::PhantomData;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 lazy_static::lazy_static! {
     static ref PATH_TO_NAME: std::collections::HashMap<&'static str, &'static str> = {
@@ -83,6 +85,7 @@ lazy_static::lazy_static! {
 pub struct PrometheusMetrics {
     pub(crate) namespace: String,
     pub(crate) endpoint: String,
+    pub(crate) slow_query_threshold: Option<Duration>,
 }
 
 impl PrometheusMetrics {
@@ -92,9 +95,17 @@ impl PrometheusMetrics {
         PrometheusMetrics {
             namespace: namespace.to_string(),
             endpoint: endpoint.to_string(),
+            slow_query_threshold: None,
         }
     }
 
+    /// Log at warn level the requests taking longer than the threshold.
+    /// If no threshold is given, the slow requests are not logged.
+    pub fn with_slow_query_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_query_threshold = threshold;
+        self
+    }
+
     fn metrics(&self) -> String {
         let mut buffer = vec![];
         TextEncoder::new()
@@ -111,6 +122,7 @@ impl PrometheusMetrics {
         &self,
         handler: &str,
         method: &Method,
+        uri: &str,
         status: StatusCode,
         clock: SystemTime,
     ) {
@@ -123,6 +135,16 @@ impl PrometheusMetrics {
             HTTP_REQ_HISTOGRAM
                 .with_label_values(&[&handler, &method])
                 .observe(duration);
+
+            if self.slow_query_threshold.map_or(false, |t| elapsed > t) {
+                warn!(
+                    "slow query on {}: {} {} took {} ms",
+                    handler,
+                    method,
+                    uri,
+                    elapsed.as_millis()
+                );
+            }
         }
 
         HTTP_COUNTER
@@ -215,6 +237,11 @@ where
         let inner = self.inner.clone();
         let method = req.method().clone();
         let path = req.path().to_string();
+        // the api keys are not logged with the slow queries
+        let uri = match req.query_string() {
+            "" => path.clone(),
+            query => format!("{}?{}", path, redact_query(query)),
+        };
         let handler = get_ressource_name(&path);
 
         Ok(Async::Ready(res.map_body(move |mut head, mut body| {
@@ -238,6 +265,7 @@ where
                 status: head.status,
                 handler,
                 method,
+                uri,
             })
         })))
     }
@@ -252,13 +280,19 @@ pub struct StreamLog<B> {
     status: StatusCode,
     handler: String,
     method: Method,
+    uri: String,
 }
 
 impl<B> Drop for StreamLog<B> {
     fn drop(&mut self) {
         // update the metrics for this request at the very end of responding
-        self.inner
-            .update_metrics(&self.handler, &self.method, self.status, self.clock);
+        self.inner.update_metrics(
+            &self.handler,
+            &self.method,
+            &self.uri,
+            self.status,
+            self.clock,
+        );
     }
 }
 
@@ -277,3 +311,91 @@ impl<B: MessageBody> MessageBody for StreamLog<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use slog::Drain;
+    use std::sync::Mutex;
+
+    // drain keeping the logged messages
+    struct Recorder(Arc<Mutex<Vec<(slog::Level, String)>>>);
+
+    impl Drain for Recorder {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record<'_>, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.level(), record.msg().to_string()));
+            Ok(())
+        }
+    }
+
+    fn slow_handler() -> HttpResponse {
+        std::thread::sleep(Duration::from_millis(50));
+        HttpResponse::Ok().finish()
+    }
+
+    fn call_slow_handler(threshold: Option<Duration>) -> Vec<(slog::Level, String)> {
+        call_slow_handler_with_uri(threshold, "/autocomplete?q=slow")
+    }
+
+    fn call_slow_handler_with_uri(
+        threshold: Option<Duration>,
+        uri: &str,
+    ) -> Vec<(slog::Level, String)> {
+        let records = Arc::new(Mutex::new(vec![]));
+        let logger = slog::Logger::root(Recorder(records.clone()).fuse(), slog::o!());
+        slog_scope::scope(&logger, || {
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(
+                        PrometheusMetrics::new("bragi", "/metrics")
+                            .with_slow_query_threshold(threshold),
+                    )
+                    .route("/autocomplete", web::get().to(slow_handler)),
+            );
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req);
+            assert!(resp.status().is_success());
+            // the request is logged when the response has been sent
+            drop(resp);
+        });
+        let records = records.lock().unwrap().clone();
+        records
+    }
+
+    #[test]
+    fn slow_query_logged() {
+        let records = call_slow_handler(Some(Duration::from_millis(10)));
+        assert_eq!(records.len(), 1);
+        let (level, msg) = &records[0];
+        assert_eq!(*level, slog::Level::Warning);
+        assert!(msg.starts_with("slow query on autocomplete: GET /autocomplete?q=slow took "));
+
+        // without the api key of the request
+        let records = call_slow_handler_with_uri(
+            Some(Duration::from_millis(10)),
+            "/autocomplete?apikey=secret&q=slow",
+        );
+        assert_eq!(records.len(), 1);
+        let (_, msg) = &records[0];
+        assert!(msg
+            .starts_with("slow query on autocomplete: GET /autocomplete?apikey=***&q=slow took "));
+        assert!(!msg.contains("secret"));
+    }
+
+    #[test]
+    fn fast_query_not_logged() {
+        assert!(call_slow_handler(Some(Duration::from_secs(10))).is_empty());
+    }
+
+    #[test]
+    fn slow_query_log_disabled_by_default() {
+        assert!(call_slow_handler(None).is_empty());
+    }
+}
@@ -28,19 +28,34 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 use super::model::{self, BragiError};
-use crate::query_settings::{BuildWeight, Proximity, QuerySettings, Types};
+use crate::cancellation::Cancellation;
+use crate::query_settings::{
+    BuildWeight, Deadlines, Diversity, FuzzyQuery, LineQuery, Proximity, QuerySettings, Recency,
+    SortOrder, TieBreaker, Types,
+};
+use crate::search_pool::search_pool;
+use geo_types::Rect;
 use geojson::Geometry;
+use mimir::house_number::HouseNumber;
 use mimir::objects::{Addr, Admin, Coord, MimirObject, PlaceDocType, Poi, Stop, Street};
-use mimir::rubber::{get_indexes, read_places, Rubber};
+use mimir::rubber::{get_indexes, read_places_from_slice, read_raw_places, Rubber};
+use mimir::rubber::{read_suggestions_from_slice, suggestion_source_fields, SuggestedPlace};
 use prometheus::{self, exponential_buckets, histogram_opts, register_histogram_vec, HistogramVec};
 use rs_es::error::EsError;
-use rs_es::operations::search::Source;
+use rs_es::operations::search::{GeoDistance, Order, Sort, SortBy, SortField};
 use rs_es::query::compound::BoostMode;
 use rs_es::query::functions::{DecayOptions, FilteredFunction, Function, Modifier};
-use rs_es::query::Query;
+use rs_es::query::{CombinationMinimumShouldMatch, Fuzziness, MinimumShouldMatch, Query};
 use rs_es::units as rs_u;
+use rs_es::units::Location;
+use serde::{Deserialize, Serialize};
 use slog_scope::{debug, error, info, warn};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use std::{fmt, iter};
+use unicode_segmentation::UnicodeSegmentation;
 
 lazy_static::lazy_static! {
     static ref ES_REQ_HISTOGRAM: HistogramVec = register_histogram_vec!(
@@ -50,8 +65,36 @@ lazy_static::lazy_static! {
         exponential_buckets(0.001, 1.5, 25).unwrap()
     )
     .unwrap();
+    static ref PARTIAL_RESPONSES_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_partial_responses_total",
+        "Total number of autocomplete responses without the places of a type, dropped because of its deadline.",
+        &["dropped_type"]
+    )
+    .unwrap();
 }
 
+// interval between two checks of the cancellation of a request, while waiting for its searches
+const CANCELLATION_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// number of clusters along the width of the map, the precision of their grid is chosen for it
+const CLUSTERS_ACROSS_MAP: f64 = 8.;
+
+// maximum precision of the geohashes in ES
+const MAX_GEOHASH_PRECISION: u8 = 12;
+
+// maximum number of clusters returned, the ones with the most places are kept
+const MAX_CLUSTERS: u64 = 1000;
+
+// the document types of the places of the munin_geo_data alias
+const GEO_DATA_TYPES: [&str; 4] = ["addr", "street", "admin", "poi"];
+
+// fields of the documents not used in the responses, and very large in some documents:
+// the boundaries of the admins (also the ones of the admins of the other places)
+// and the geometries of the streets. They are still fetched in debug.
+const UNUSED_SOURCE_FIELDS: [&str; 3] = ["boundary", "*.boundary", "coord_line"];
+// when the geometries of the places are requested, only the boundaries of their admins are unused
+const UNUSED_SOURCE_FIELDS_WITH_GEOMETRY: [&str; 1] = ["*.boundary"];
+
 /// takes a ES json blob and build a Place from it
 /// it uses the _type field of ES to know which type of the Place enum to fill
 pub fn make_place(doc_type: String, value: Option<Box<serde_json::Value>>) -> Option<mimir::Place> {
@@ -80,7 +123,7 @@ pub fn make_place(doc_type: String, value: Option<Box<serde_json::Value>>) -> Op
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum MatchType {
+pub(crate) enum MatchType {
     Prefix,
     Fuzzy,
 }
@@ -95,6 +138,177 @@ impl fmt::Display for MatchType {
     }
 }
 
+/// What is searched: the places matching a query string,
+/// or, when there is no query string, all the places matching the filters
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SearchMode {
+    Text(MatchType),
+    Browse,
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SearchMode::Text(match_type) => write!(f, "{}", match_type),
+            SearchMode::Browse => write!(f, "browse"),
+        }
+    }
+}
+
+// Elasticsearch 2.4 cannot collapse the results, so when a collapse or a diversification
+// is requested, more documents are fetched and they are filtered afterward.
+const POST_FILTER_WINDOW_FACTOR: u64 = 5;
+
+/// The default `index.max_result_window` of Elasticsearch: the searches
+/// beyond it (`from + size`) are rejected.
+pub const MAX_RESULT_WINDOW: u64 = 10_000;
+
+fn street_id(place: &mimir::Place) -> Option<&str> {
+    match place {
+        mimir::Place::Street(o) => Some(&o.id),
+        mimir::Place::Addr(o) => Some(&o.street.id),
+        _ => None,
+    }
+}
+
+fn city_id(place: &mimir::Place) -> Option<String> {
+    match place {
+        mimir::Place::Admin(o) if o.is_city() => Some(o.id.clone()),
+        _ => place
+            .admins()
+            .iter()
+            .find(|a| a.is_city())
+            .map(|a| a.id.clone()),
+    }
+}
+
+/// Field on which the results can be collapsed, to keep only one place per distinct value
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum CollapseField {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "street")]
+    Street,
+    #[serde(rename = "city")]
+    City,
+}
+
+impl CollapseField {
+    // the places without a value for the field are never collapsed
+    fn value(self, place: &mimir::Place) -> Option<String> {
+        match self {
+            CollapseField::Name => Some(match place {
+                mimir::Place::Admin(o) => o.name.clone(),
+                mimir::Place::Street(o) => o.name.clone(),
+                mimir::Place::Addr(o) => o.name.clone(),
+                mimir::Place::Poi(o) => o.name.clone(),
+                mimir::Place::Stop(o) => o.name.clone(),
+            }),
+            // the streets of the same name in different cities are different streets
+            CollapseField::Street => street_id(place).map(str::to_string),
+            CollapseField::City => city_id(place),
+        }
+    }
+}
+
+/// Keep only the first (hence the best) place for each distinct value of the field
+fn collapse(places: Vec<mimir::Place>, field: CollapseField) -> Vec<mimir::Place> {
+    let mut seen = HashSet::new();
+    places
+        .into_iter()
+        .filter(|place| match field.value(place) {
+            Some(value) => seen.insert(value),
+            None => true,
+        })
+        .collect()
+}
+
+/// Keep at most `max_per_street` places on the same street and `max_per_admin` places
+/// in the same city, the next best places taking the place of the removed ones
+fn diversify(places: Vec<mimir::Place>, diversity: &Diversity) -> Vec<mimir::Place> {
+    fn is_full<K: std::hash::Hash + Eq>(
+        counts: &mut HashMap<K, usize>,
+        key: Option<K>,
+        max: Option<usize>,
+    ) -> bool {
+        match (key, max) {
+            (Some(key), Some(max)) => {
+                let count = counts.entry(key).or_insert(0);
+                *count += 1;
+                *count > max
+            }
+            _ => false,
+        }
+    }
+    let mut nb_by_street = HashMap::new();
+    let mut nb_by_admin = HashMap::new();
+    places
+        .into_iter()
+        .filter(|place| {
+            let street_full = is_full(
+                &mut nb_by_street,
+                street_id(place).map(str::to_string),
+                diversity.max_per_street,
+            );
+            let admin_full = is_full(&mut nb_by_admin, city_id(place), diversity.max_per_admin);
+            !street_full && !admin_full
+        })
+        .collect()
+}
+
+// The addresses and the streets often come from different sources (with different ids),
+// so an address is matched with its street by the name of the street and its city
+fn street_key(place: &mimir::Place) -> Option<(String, Option<String>)> {
+    match place {
+        mimir::Place::Street(o) => Some((o.name.to_lowercase(), city_id(place))),
+        mimir::Place::Addr(o) => Some((o.street.name.to_lowercase(), city_id(place))),
+        _ => None,
+    }
+}
+
+/// Merge the streets into their addresses found with them (for "12 Rue X", the street and the house),
+/// so that they are returned as a single result: the address takes the best rank and score of both
+fn merge_streets(places: Vec<mimir::Place>) -> Vec<mimir::Place> {
+    let address_streets: HashSet<_> = places
+        .iter()
+        .filter(|place| matches!(place, mimir::Place::Addr(_)))
+        .filter_map(street_key)
+        .collect();
+    // rank and score of the best street merged into each street of the addresses
+    let mut merged_streets = HashMap::new();
+    for (rank, place) in places.iter().enumerate() {
+        if let mimir::Place::Street(street) = place {
+            if let Some(key) = street_key(place).filter(|key| address_streets.contains(key)) {
+                merged_streets.entry(key).or_insert((rank, street.score));
+            }
+        }
+    }
+    if merged_streets.is_empty() {
+        return places;
+    }
+    let mut ranked_places: Vec<(usize, mimir::Place)> = places
+        .into_iter()
+        .enumerate()
+        .filter_map(|(rank, place)| {
+            let merged_street = street_key(&place).and_then(|key| merged_streets.get(&key));
+            match (place, merged_street) {
+                (mimir::Place::Street(_), Some(_)) => None,
+                (mimir::Place::Addr(mut addr), Some(&(street_rank, street_score))) => {
+                    addr.score = match (addr.score, street_score) {
+                        (Some(score), Some(street_score)) => Some(score.max(street_score)),
+                        (score, street_score) => score.or(street_score),
+                    };
+                    Some((rank.min(street_rank), mimir::Place::Addr(addr)))
+                }
+                (place, _) => Some((rank, place)),
+            }
+        })
+        .collect();
+    // the sort is stable, the addresses of the same street keep their order
+    ranked_places.sort_by_key(|(rank, _)| *rank);
+    ranked_places.into_iter().map(|(_, place)| place).collect()
+}
+
 // filter to handle PT coverages
 // we either want:
 // * to get objects with no coverage at all (non-PT objects)
@@ -112,6 +326,30 @@ fn build_coverage_condition(pt_datasets: &[&str]) -> Query {
         .build()
 }
 
+// filter to handle the validity period of the stops
+// we either want:
+// * to get objects with no validity period (non-PT objects, or stops without schedules)
+// * or the objects whose validity period contains the date
+fn build_validity_condition(date: &str) -> Query {
+    Query::build_bool()
+        .with_should(vec![
+            Query::build_bool()
+                .with_must_not(Query::build_exists("validity_period.start_date").build())
+                .build(),
+            Query::build_bool()
+                .with_must(vec![
+                    Query::build_range("validity_period.start_date")
+                        .with_lte(date)
+                        .build(),
+                    Query::build_range("validity_period.end_date")
+                        .with_gte(date)
+                        .build(),
+                ])
+                .build(),
+        ])
+        .build()
+}
+
 /// Create a `rs_es::Query` that boosts results according to the
 /// distance to `coord`.
 fn build_proximity_with_boost(coord: &Coord, infos: &Proximity, is_fuzzy: bool) -> Query {
@@ -147,6 +385,37 @@ fn build_proximity_with_boost(coord: &Coord, infos: &Proximity, is_fuzzy: bool)
         .build()
 }
 
+/// Boosts the places of the dataset by their recency: the score decays exponentially
+/// with the age of their last update in their source, down to half of `weight` at `half_life`.
+/// The places without a time of update are not boosted.
+fn build_recency_with_boost(dataset: &str, recency: &Recency, now: i64) -> Query {
+    let half_life = (recency.half_life * 3_600_000.) as i64;
+    Query::build_function_score()
+        .with_query(
+            Query::build_bool()
+                .with_must(Query::build_term("dataset", dataset).build())
+                .with_filter(Query::build_exists("updated_at").build())
+                .build(),
+        )
+        .with_functions(vec![
+            FilteredFunction::build_filtered_function(
+                None,
+                DecayOptions::new(now, half_life)
+                    .with_decay(0.5)
+                    .build("updated_at")
+                    .build_exp(),
+                None,
+            ),
+            FilteredFunction::build_filtered_function(
+                None,
+                Function::build_weight(recency.weight).build(),
+                None,
+            ),
+        ])
+        .with_boost_mode(BoostMode::Replace)
+        .build()
+}
+
 fn build_with_weight(build_weight: &BuildWeight, types: &Types) -> Query {
     let weighted = |doc_type, weight| {
         FilteredFunction::build_filtered_function(
@@ -171,15 +440,352 @@ fn build_with_weight(build_weight: &BuildWeight, types: &Types) -> Query {
         .build()
 }
 
+// If there is a shape, all the places listed in shape_scope are restricted to the shape.
+// and the places that are not listed are not restricted.
+// So if shape_scope = {A, B}, we should end up with something like
+// should {
+//   must [               => filwer_w_shape
+//     should [
+//       term _type = A
+//       term _type = B
+//     ]
+//     geoshape
+//  ],
+//  must_not [            => filter_wo_shape
+//    term _type = A
+//    term _type = B
+//  ]
+//
+fn build_shape_filter(shape: Geometry, shape_scope: &[PlaceDocType]) -> Query {
+    let filter_w_shape_term = Query::build_bool()
+        .with_should(
+            shape_scope
+                .iter()
+                .map(PlaceDocType::as_str)
+                .map(|x| Query::build_term("_type", x).build())
+                .collect::<Vec<_>>(),
+        )
+        .build();
+    let filter_w_shape_geo = Query::build_geo_shape("approx_coord")
+        .with_geojson(shape)
+        .build();
+    let filter_w_shape = Query::build_bool()
+        .with_must(vec![filter_w_shape_term, filter_w_shape_geo])
+        .build();
+    let filter_wo_shape = shape_scope
+        .iter()
+        .map(PlaceDocType::as_str)
+        .map(|x| Query::build_term("_type", x).build())
+        .collect::<Vec<_>>();
+    let filter_wo_shape = Query::build_bool().with_must_not(filter_wo_shape).build();
+
+    Query::build_bool()
+        .with_should(vec![filter_wo_shape, filter_w_shape])
+        .build()
+}
+
+/// The line code of a query looking like a transit line: a mode keyword followed by the code
+/// ("metro 14", "RER A"), or a query matching the line code regex of the settings.
+pub(crate) fn detect_line_code<'a>(q: &'a str, settings: &LineQuery) -> Option<&'a str> {
+    let q = q.trim();
+    let mut words = q.splitn(2, char::is_whitespace);
+    let keyword = words.next().unwrap_or("").to_lowercase();
+    let code = words.next().map(str::trim).unwrap_or("");
+    if !code.is_empty()
+        && settings
+            .mode_keywords
+            .iter()
+            .any(|k| k.to_lowercase() == keyword)
+    {
+        Some(code)
+    } else if settings
+        .code_regex
+        .as_ref()
+        .map_or(false, |r| r.is_match(q))
+    {
+        Some(q)
+    } else {
+        None
+    }
+}
+
+/// Query of the stops served by the line of the query, matching the code or the name of the line
+#[allow(clippy::too_many_arguments)]
+fn build_line_query(
+    q: &str,
+    line_code: &str,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    pt_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    settings: &LineQuery,
+) -> Query {
+    let mut filters = vec![
+        Query::build_term("_type", Stop::doc_type()).build(),
+        build_validity_condition(date),
+    ];
+    if !all_data {
+        filters.push(build_coverage_condition(pt_datasets));
+    }
+    if let Some(s) = shape {
+        filters.push(build_shape_filter(s, shape_scope));
+    }
+    let line_condition = Query::build_bool()
+        .with_should(vec![
+            Query::build_match("lines.code", line_code).build(),
+            Query::build_match("lines.name", q)
+                .with_operator("and")
+                .build(),
+        ])
+        .build();
+    Query::build_bool()
+        .with_must(line_condition)
+        .with_filter(Query::build_bool().with_must(filters).build())
+        .with_boost(settings.boost)
+        .build()
+}
+
+// shorter prefixes would match too many postcodes (and a number like "18" is rather a street name)
+const MIN_POSTCODE_PREFIX_LENGTH: usize = 3;
+
+/// Whether the query looks like a partial postcode ("SW1", "1011"): a single word of
+/// letters and digits, with at least one digit
+pub(crate) fn is_postcode_prefix(q: &str) -> bool {
+    let q = q.trim();
+    q.chars().count() >= MIN_POSTCODE_PREFIX_LENGTH
+        && q.chars().all(|c| c.is_ascii_alphanumeric())
+        && q.chars().any(|c| c.is_ascii_digit())
+}
+
+// a typo in a short word would match almost anything,
+// so the number of edits tolerated in a word depends on its length
+const MIN_LENGTH_ONE_EDIT: usize = 4;
+const MIN_LENGTH_TWO_EDITS: usize = 8;
+
+// the ideographs and the kanas, whose words are not separated by spaces
+// (ES tokenizes them character by character)
+fn is_ideographic(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // hiragana and katakana
+        | '\u{31F0}'..='\u{31FF}' // katakana phonetic extensions
+        | '\u{3400}'..='\u{4DBF}' // CJK unified ideographs extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK unified ideographs
+        | '\u{F900}'..='\u{FAFF}' // CJK compatibility ideographs
+        | '\u{FF66}'..='\u{FF9F}' // halfwidth katakana
+        | '\u{20000}'..='\u{2FA1F}' // the other CJK extensions
+    )
+}
+
+/// Number of edits tolerated in `word` by the fuzzy search,
+/// depending on its length if not set in the settings (or in the request).
+///
+/// The length is counted in graphemes, so that the vowel marks of the arabic or hebrew words
+/// do not make them more fuzzy. The ideographic words are never fuzzy: ES splits them into
+/// single characters, which one typo would turn into any other character.
+pub(crate) fn word_fuzziness(word: &str, settings: &FuzzyQuery) -> i64 {
+    if word.chars().any(is_ideographic) {
+        return 0;
+    }
+    if let Some(fuzziness) = settings.fuzziness {
+        return fuzziness.into();
+    }
+    match word.graphemes(true).count() {
+        len if len < MIN_LENGTH_ONE_EDIT => 0,
+        len if len < MIN_LENGTH_TWO_EDITS => 1,
+        _ => 2,
+    }
+}
+
+// the words of the query must match with some typos, but some words can be missing
+// in the longer queries.
+// The values defined here are empirical,
+// it's supposed to be able to manage cases BOTH missspelt one-word
+// www.elastic.co/guide/en/elasticsearch/guide/current/match-multi-word.html#match-precision
+// requests AND very long requests.
+// Missspelt one-word request:
+//     Vaureaaal (instead of Vaureal)
+// Very long requests:
+//     Caisse Primaire d'Assurance Maladie de Haute Garonne, 33 Rue du Lot, 31100 Toulouse
+fn build_fuzzy_words_condition(q: &str, settings: &FuzzyQuery) -> Query {
+    Query::build_bool()
+        .with_should(
+            q.split_whitespace()
+                .map(|word| {
+                    Query::build_match("full_label", word)
+                        .with_fuzziness(Fuzziness::LevenshteinDistance(word_fuzziness(
+                            word, settings,
+                        )))
+                        .build()
+                })
+                .collect::<Vec<_>>(),
+        )
+        .with_minimum_should_match(MinimumShouldMatch::from(vec![
+            CombinationMinimumShouldMatch::new(1i64, -1i64),
+            CombinationMinimumShouldMatch::new(3i64, -2i64),
+            CombinationMinimumShouldMatch::new(9i64, -4i64),
+            CombinationMinimumShouldMatch::new(20i64, 25f64),
+        ]))
+        .build()
+}
+
+// all the words of the query must be a prefix of the words of the place.
+// The house numbers are only analyzed with their suffix in the `house_number` field:
+// a house number written differently ("12bis" for "12 bis") is found there,
+// and then all the other words must still match.
+// The places without house number (the streets, admins...) match without the house numbers
+// of the query: "24 rue hector malot" still finds the street, below its address.
+fn build_prefix_words_condition(q: &str) -> Query {
+    let all_words = Query::build_match("full_label.prefix", q)
+        .with_operator("and")
+        .build();
+    let house_number_words = Query::build_bool()
+        .with_must(vec![
+            Query::build_match("house_number", q).build(),
+            Query::build_match("full_label.prefix", q)
+                .with_minimum_should_match(MinimumShouldMatch::from(vec![
+                    CombinationMinimumShouldMatch::new(1i64, -1i64),
+                ]))
+                .build(),
+        ])
+        .build();
+    let mut conditions = vec![all_words, house_number_words];
+    let words: Vec<&str> = q
+        .split_whitespace()
+        .filter(|word| house_number_of_word(word).is_none())
+        .collect();
+    if !words.is_empty() && words.len() < q.split_whitespace().count() {
+        conditions.push(
+            Query::build_bool()
+                .with_must(
+                    Query::build_match("full_label.prefix", words.join(" "))
+                        .with_operator("and")
+                        .build(),
+                )
+                .with_must_not(Query::build_exists("house_number").build())
+                .build(),
+        );
+    }
+    Query::build_bool().with_should(conditions).build()
+}
+
+/// Boost the places inside `bbox`, without filtering the other ones.
+///
+/// The score of `query` is multiplied by `boost` (the other boosts are applied before),
+/// and its filters (shape, types, ...) are applied first.
+fn build_focus_bbox_boost(query: Query, bbox: &Rect<f64>, boost: f64) -> Query {
+    let inside_bbox = build_bbox_query(bbox);
+    Query::build_function_score()
+        .with_query(query)
+        .with_functions(vec![FilteredFunction::build_filtered_function(
+            inside_bbox,
+            Function::build_weight(boost).build(),
+            None,
+        )])
+        .with_boost_mode(BoostMode::Multiply)
+        .build()
+}
+
+// the places inside `bbox`
+fn build_bbox_query(bbox: &Rect<f64>) -> Query {
+    Query::build_geo_bounding_box(
+        "coord",
+        rs_u::GeoBox::Corners(
+            Location::LatLon(bbox.max().y, bbox.min().x),
+            Location::LatLon(bbox.min().y, bbox.max().x),
+        ),
+    )
+    .build()
+}
+
+/// Only keeps the places inside `focus_bbox`, the map displayed by the clusters
+pub(crate) fn build_focus_bbox_filter(query: Query, focus_bbox: Option<Rect<f64>>) -> Query {
+    match focus_bbox {
+        Some(bbox) => Query::build_bool()
+            .with_must(query)
+            .with_filter(build_bbox_query(&bbox))
+            .build(),
+        None => query,
+    }
+}
+
+/// The numbers of the words of the query that can be house numbers
+fn detect_house_numbers(q: &str) -> Vec<i64> {
+    q.split_whitespace()
+        .filter_map(house_number_of_word)
+        .map(i64::from)
+        .collect()
+}
+
+/// The number of a word looking like a house number: a number, alone or with a suffix
+/// (`12`, `12b`, `12bis`, `3-5`), but not the other words starting with a number (`1er`, `4x4`)
+fn house_number_of_word(word: &str) -> Option<u32> {
+    let house_number = HouseNumber::parse(word.trim_end_matches(|c: char| c == ',' || c == '.'));
+    let is_suffix = |suffix: &str| match suffix {
+        "bis" | "ter" | "quater" => true,
+        _ if suffix.chars().count() == 1 => suffix.chars().all(char::is_alphabetic),
+        _ => suffix
+            .strip_prefix(|c: char| c == '-' || c == '/')
+            .map_or(false, |end| {
+                !end.is_empty() && end.chars().all(|c| c.is_ascii_digit())
+            }),
+    };
+    match house_number.suffix {
+        Some(ref suffix) if !is_suffix(suffix) => None,
+        _ => house_number.numeric,
+    }
+}
+
+/// Filter of the places on the house number of the query.
+///
+/// When the query has a house number and the match is not strict, the places are not filtered,
+/// the house number only boosts the addresses matching it.
+fn build_house_number_condition(q: &str, strict: bool) -> Query {
+    if q.split_whitespace().count() <= 1 {
+        // If the query contains a single word, we don't exect any house number in the result.
+        return Query::build_bool()
+            .with_must_not(Query::build_exists("house_number").build())
+            .build();
+    }
+    let numbers = detect_house_numbers(q);
+    if !strict && !numbers.is_empty() {
+        return Query::build_match_all().build();
+    }
+    // Filter to handle house number.
+    // We either want:
+    // * to exactly match the document house_number
+    // * or to match its number, with another suffix ("12" finds "12 bis")
+    // * or that the document has no house_number
+    let mut house_number_conditions = vec![
+        Query::build_bool()
+            .with_must_not(Query::build_exists("house_number").build())
+            .build(),
+        Query::build_match("house_number", q.to_string()).build(),
+    ];
+    if !numbers.is_empty() {
+        house_number_conditions.push(
+            Query::build_terms("house_number_numeric")
+                .with_values(&numbers[..])
+                .build(),
+        );
+    }
+    Query::build_bool()
+        .with_should(house_number_conditions)
+        .build()
+}
+
 #[allow(clippy::too_many_arguments)]
-fn build_query<'a>(
+pub(crate) fn build_query<'a>(
     q: &str,
     match_type: MatchType,
+    strict_house_number: bool,
     coord: Option<Coord>,
     shape: Option<Geometry>,
     shape_scope: &[PlaceDocType],
+    focus_bbox: Option<Rect<f64>>,
     pt_datasets: &[&str],
     all_data: bool,
+    date: &str,
     langs: &'a [&'a str],
     zone_types: &[&str],
     poi_types: &[&str],
@@ -220,6 +826,9 @@ fn build_query<'a>(
         build_multi_match("name", &format_names_field)
             .with_boost(query_settings.string_query.boosts.name)
             .build(),
+        Query::build_multi_match(vec!["alt_names".into(), "alt_names.prefix".into()], q)
+            .with_boost(query_settings.string_query.boosts.alt_names)
+            .build(),
         build_multi_match("label", &format_labels_field)
             .with_boost(query_settings.string_query.boosts.label)
             .build(),
@@ -233,6 +842,50 @@ fn build_query<'a>(
             .with_boost(query_settings.string_query.boosts.house_number)
             .build(),
     ];
+    // the names matching exactly the query, by type so that the exact name of a poi
+    // is not above the exact name of a city unless the boosts say so
+    fn match_exact_name<T: MimirObject>(q: &str, boost: f64) -> Query {
+        Query::build_bool()
+            .with_must(Query::build_match("name.exact", q).build())
+            .with_filter(Query::build_term("_type", T::doc_type()).build())
+            .with_boost(boost)
+            .build()
+    }
+    let exact_name = &query_settings.string_query.exact_name;
+    string_should.extend(vec![
+        match_exact_name::<Addr>(q, exact_name.address),
+        match_exact_name::<Admin>(q, exact_name.admin),
+        match_exact_name::<Stop>(q, exact_name.stop),
+        match_exact_name::<Poi>(q, exact_name.poi),
+        match_exact_name::<Street>(q, exact_name.street),
+    ]);
+    // the complete zip codes also match their prefix, so they are still ranked first
+    if is_postcode_prefix(q) {
+        string_should.push(
+            Query::build_match("zip_codes.prefix", q)
+                .with_boost(query_settings.string_query.boosts.zip_codes_prefix)
+                .build(),
+        );
+    }
+    let word_order = &query_settings.query.word_order_tolerance;
+    if word_order.enabled {
+        // most of the words of the query, in any order
+        string_should.push(
+            Query::build_match("label", q)
+                .with_minimum_should_match(MinimumShouldMatch::from(vec![
+                    CombinationMinimumShouldMatch::new(2i64, -1i64),
+                    CombinationMinimumShouldMatch::new(5i64, 75f64),
+                ]))
+                .with_boost(word_order.boost)
+                .build(),
+        );
+        // the pairs of consecutive words of the query, found in the same order
+        string_should.push(
+            Query::build_match("full_label.shingle", q)
+                .with_boost(word_order.in_order_boost)
+                .build(),
+        );
+    }
     if let MatchType::Fuzzy = match_type {
         let format_labels_ngram_field = |lang| format!("labels.{}.ngram", lang);
         string_should.push(if coord.is_some() {
@@ -286,7 +939,16 @@ fn build_query<'a>(
     // Priorization by importance
     let mut importance_queries = vec![build_with_weight(&weights, &settings.types)];
 
-    if let Some(ref coord) = coord {
+    // without coordinates, the results lean toward the default focus point, if any
+    // (it is only used for the proximity, not for the weights or the distances)
+    let proximity_coord = coord.or_else(|| {
+        query_settings
+            .importance_query
+            .proximity
+            .default_coord
+            .map(|c| Coord::new(c.lon, c.lat))
+    });
+    if let Some(ref coord) = proximity_coord {
         importance_queries.push(build_proximity_with_boost(
             coord,
             &query_settings.importance_query.proximity,
@@ -294,6 +956,16 @@ fn build_query<'a>(
         ))
     }
 
+    let now = chrono::Utc::now().timestamp_millis();
+    importance_queries.extend(
+        query_settings
+            .importance_query
+            .recency
+            .iter()
+            .filter(|(_, recency)| recency.enabled)
+            .map(|(dataset, recency)| build_recency_with_boost(dataset, recency, now)),
+    );
+
     match match_type {
         MatchType::Prefix => {
             let admin_importance_query = Query::build_function_score()
@@ -313,6 +985,13 @@ fn build_query<'a>(
                         Function::build_weight(weights.admin).build(),
                         None,
                     ),
+                    FilteredFunction::build_filtered_function(
+                        None,
+                        Function::build_field_value_factor("zone_type_weight")
+                            .with_missing(1.)
+                            .build(),
+                        None,
+                    ),
                 ])
                 .with_boost_mode(BoostMode::Replace)
                 .build();
@@ -321,30 +1000,7 @@ fn build_query<'a>(
         MatchType::Fuzzy => {}
     };
 
-    let house_number_condition = {
-        if q.split_whitespace().count() > 1 {
-            // Filter to handle house number.
-            // We either want:
-            // * to exactly match the document house_number
-            // * or that the document has no house_number
-            Query::build_bool()
-                .with_should(vec![
-                    Query::build_bool()
-                        .with_must_not(Query::build_exists("house_number").build())
-                        .build(),
-                    Query::build_match("house_number", q.to_string()).build(),
-                ])
-                .build()
-        } else {
-            // If the query contains a single word, we don't exect any house number in the result.
-            Query::build_bool()
-                .with_must_not(Query::build_exists("house_number").build())
-                .build()
-        }
-    };
-
-    use rs_es::query::CombinationMinimumShouldMatch;
-    use rs_es::query::MinimumShouldMatch;
+    let house_number_condition = build_house_number_condition(q, strict_house_number);
 
     let matching_condition = match match_type {
         // When the match type is Prefix, we want to use every possible information even though
@@ -352,80 +1008,39 @@ fn build_query<'a>(
         // The field full_label contains all of them and will do the trick.
         // The query must at least match with elision activated, matching without elision will
         // provide extra score bellow.
-        MatchType::Prefix => Query::build_match("full_label.prefix".to_string(), q.to_string())
-            .with_operator("and")
-            .build(),
-        // for fuzzy search we lower our expectation & we accept a certain percentage of token match
-        // on full_label.ngram
-        // The values defined here are empirical,
-        // it's supposed to be able to manage cases BOTH missspelt one-word
-        // www.elastic.co/guide/en/elasticsearch/guide/current/match-multi-word.html#match-precision
-        // requests AND very long requests.
-        // Missspelt one-word request:
-        //     Vaureaaal (instead of Vaureal)
-        // Very long requests:
-        //     Caisse Primaire d'Assurance Maladie de Haute Garonne, 33 Rue du Lot, 31100 Toulouse
-        MatchType::Fuzzy => Query::build_match("full_label.ngram".to_string(), q.to_string())
-            .with_minimum_should_match(MinimumShouldMatch::from(vec![
-                CombinationMinimumShouldMatch::new(1i64, -1i64),
-                CombinationMinimumShouldMatch::new(3i64, -2i64),
-                CombinationMinimumShouldMatch::new(9i64, -4i64),
-                CombinationMinimumShouldMatch::new(20i64, 25f64),
-            ]))
-            .build(),
+        MatchType::Prefix => build_prefix_words_condition(q),
+        // for fuzzy search we lower our expectation: the words can have typos, depending on
+        // their length, and we accept a certain percentage of them to match
+        MatchType::Fuzzy => build_fuzzy_words_condition(q, &query_settings.fuzzy_query),
     };
 
-    let mut filters = vec![house_number_condition, matching_condition];
+    let mut filters = vec![
+        house_number_condition,
+        matching_condition,
+        build_validity_condition(date),
+    ];
 
     // if searching through all data, no coverage filter
     if !all_data {
         filters.push(build_coverage_condition(pt_datasets));
     }
 
-    // If there is a shape, all the places listed in shape_scope are restricted to the shape.
-    // and the places that are not listed are not restricted.
-    // So if shape_scope = {A, B}, we should end up with something like
-    // should {
-    //   must [               => filwer_w_shape
-    //     should [
-    //       term _type = A
-    //       term _type = B
-    //     ]
-    //     geoshape
-    //  ],
-    //  must_not [            => filter_wo_shape
-    //    term _type = A
-    //    term _type = B
-    //  ]
-    //
+    // the queries looking like a line also search the stops served by this line
+    let line_query = detect_line_code(q, &query_settings.line_query).map(|line_code| {
+        build_line_query(
+            q,
+            line_code,
+            shape.clone(),
+            shape_scope,
+            pt_datasets,
+            all_data,
+            date,
+            &query_settings.line_query,
+        )
+    });
 
     if let Some(s) = shape {
-        let filter_w_shape_term = Query::build_bool()
-            .with_should(
-                shape_scope
-                    .iter()
-                    .map(PlaceDocType::as_str)
-                    .map(|x| Query::build_term("_type", x).build())
-                    .collect::<Vec<_>>(),
-            )
-            .build();
-        let filter_w_shape_geo = Query::build_geo_shape("approx_coord")
-            .with_geojson(s)
-            .build();
-        let filter_w_shape = Query::build_bool()
-            .with_must(vec![filter_w_shape_term, filter_w_shape_geo])
-            .build();
-        let filter_wo_shape = shape_scope
-            .iter()
-            .map(PlaceDocType::as_str)
-            .map(|x| Query::build_term("_type", x).build())
-            .collect::<Vec<_>>();
-        let filter_wo_shape = Query::build_bool().with_must_not(filter_wo_shape).build();
-
-        let geo_filter = Query::build_bool()
-            .with_should(vec![filter_wo_shape, filter_w_shape])
-            .build();
-        filters.push(geo_filter);
+        filters.push(build_shape_filter(s, shape_scope));
     }
 
     let mut query = Query::build_bool()
@@ -446,19 +1061,322 @@ fn build_query<'a>(
         );
     }
     if !poi_types.is_empty() {
-        query = query.with_filter(
+        query = query.with_filter(build_poi_types_condition(poi_types));
+    }
+
+    let query = match line_query {
+        Some(line_query) => Query::build_bool()
+            .with_should(vec![query.build(), line_query])
+            .build(),
+        None => query.build(),
+    };
+
+    match focus_bbox {
+        Some(bbox) => build_focus_bbox_boost(
+            query,
+            &bbox,
+            query_settings.importance_query.focus_bbox.boost,
+        ),
+        None => query,
+    }
+}
+
+/// Only the pois of one of the given poi types
+fn build_poi_types_condition(poi_types: &[&str]) -> Query {
+    Query::build_bool()
+        .with_should(
+            poi_types
+                .iter()
+                .map(|x| Query::build_match("poi_type.id", *x).build())
+                .collect::<Vec<_>>(),
+        )
+        .build()
+}
+
+/// Build a query matching all the places satisfying the filters, without any query string
+fn build_browse_query(
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    pt_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    zone_types: &[&str],
+    poi_types: &[&str],
+) -> Query {
+    let mut filters = vec![build_validity_condition(date)];
+
+    // if searching through all data, no coverage filter
+    if !all_data {
+        filters.push(build_coverage_condition(pt_datasets));
+    }
+    if let Some(s) = shape {
+        filters.push(build_shape_filter(s, shape_scope));
+    }
+    if !zone_types.is_empty() {
+        filters.push(
             Query::build_bool()
                 .with_should(
-                    poi_types
+                    zone_types
                         .iter()
-                        .map(|x| Query::build_match("poi_type.id", *x).build())
+                        .map(|x| Query::build_match("zone_type", *x).build())
                         .collect::<Vec<_>>(),
                 )
                 .build(),
         );
     }
+    if !poi_types.is_empty() {
+        filters.push(build_poi_types_condition(poi_types));
+    }
 
-    query.build()
+    Query::build_bool()
+        .with_filter(Query::build_bool().with_must(filters).build())
+        .build()
+}
+
+/// The places removed from the results, whatever the other filters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Exclusions<'a> {
+    pub ids: Vec<&'a str>,
+    /// Only the zones of these types are removed, not the places inside them
+    pub zone_types: Vec<&'a str>,
+    /// Like the `datasets` filter, the stops are still selected by the pt datasets
+    pub datasets: Vec<&'a str>,
+}
+
+impl Exclusions<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.zone_types.is_empty() && self.datasets.is_empty()
+    }
+}
+
+/// Remove the excluded places from the places matching the query
+pub(crate) fn build_exclusions_filter(query: Query, exclusions: &Exclusions<'_>) -> Query {
+    if exclusions.is_empty() {
+        return query;
+    }
+    let mut excluded = vec![];
+    if !exclusions.ids.is_empty() {
+        let ids = exclusions
+            .ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        excluded.push(Query::build_ids(ids).build());
+    }
+    excluded.extend(
+        exclusions
+            .zone_types
+            .iter()
+            .map(|x| Query::build_match("zone_type", *x).build()),
+    );
+    if !exclusions.datasets.is_empty() {
+        excluded.push(
+            Query::build_bool()
+                .with_must(
+                    Query::build_terms("dataset")
+                        .with_values(exclusions.datasets.as_slice())
+                        .build(),
+                )
+                .with_must_not(Query::build_term("_type", Stop::doc_type()).build())
+                .build(),
+        );
+    }
+    Query::build_bool()
+        .with_must(query)
+        .with_must_not(excluded)
+        .build()
+}
+
+/// Keep only the places inside the admin of the given id: the admins below it,
+/// and the places having it in their admins (the admins of their street for the addresses).
+pub(crate) fn build_within_filter(query: Query, within: Option<&str>) -> Query {
+    let admin_id = match within {
+        Some(admin_id) => admin_id,
+        None => return query,
+    };
+    Query::build_bool()
+        .with_must(query)
+        .with_filter(
+            Query::build_bool()
+                .with_should(vec![
+                    Query::build_term("administrative_regions.id", admin_id).build(),
+                    Query::build_term("street.administrative_regions.id", admin_id).build(),
+                ])
+                .build(),
+        )
+        .build()
+}
+
+/// Flag required on the places: `name:value`, or only `name` for a boolean flag set to true
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequiredFlag<'a> {
+    pub name: &'a str,
+    pub value: Option<&'a str>,
+}
+
+/// Keep only the places having all the given flags (see `mimir::objects::Flags`).
+pub(crate) fn build_flags_filter(query: Query, flags: &[RequiredFlag<'_>]) -> Query {
+    if flags.is_empty() {
+        return query;
+    }
+    Query::build_bool()
+        .with_must(query)
+        .with_filter(
+            Query::build_bool()
+                .with_must(
+                    flags
+                        .iter()
+                        .map(|flag| {
+                            let field = format!("flags.{}", flag.name);
+                            match flag.value {
+                                Some(value) => Query::build_term(field, value).build(),
+                                None => Query::build_term(field, true).build(),
+                            }
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .build(),
+        )
+        .build()
+}
+
+/// Keep only the places imported with one of the given datasets.
+///
+/// The stops are still selected by the pt datasets.
+pub(crate) fn build_datasets_filter(query: Query, datasets: &[&str]) -> Query {
+    if datasets.is_empty() {
+        return query;
+    }
+    Query::build_bool()
+        .with_must(query)
+        .with_filter(
+            Query::build_bool()
+                .with_should(vec![
+                    Query::build_terms("dataset").with_values(datasets).build(),
+                    Query::build_term("_type", Stop::doc_type()).build(),
+                ])
+                .build(),
+        )
+        .build()
+}
+
+fn es_order(order: SortOrder) -> Order {
+    match order {
+        SortOrder::Asc => Order::Asc,
+        SortOrder::Desc => Order::Desc,
+    }
+}
+
+/// The results are sorted by score, and the results with the same score by the tie breakers,
+/// so that their order does not change between two calls
+fn build_tie_breaker_sort(tie_breaker: &TieBreaker) -> SortBy {
+    let sort_field = SortField::new(
+        tie_breaker.field.as_str(),
+        Some(es_order(tie_breaker.order)),
+    );
+    match &tie_breaker.unmapped_type {
+        Some(unmapped_type) => sort_field.with_unmapped_type(unmapped_type.as_str()),
+        None => sort_field,
+    }
+    .build()
+}
+
+fn build_score_sort(tie_breakers: &[TieBreaker]) -> Option<Sort> {
+    if tie_breakers.is_empty() {
+        // without tie breakers, the default sort by score is enough
+        return None;
+    }
+    let sort_by = std::iter::once(SortField::new("_score", Some(Order::Desc)).build())
+        .chain(tie_breakers.iter().map(build_tie_breaker_sort))
+        .collect();
+    Some(Sort::new(sort_by))
+}
+
+/// Since there is no relevance without query string, the browsed places are sorted by weight,
+/// and then by distance to `coord` if given, and then by the tie breakers
+fn build_browse_sort(coord: Option<&Coord>, tie_breakers: &[TieBreaker]) -> Sort {
+    let mut sort_by = vec![SortField::new("weight", Some(Order::Desc)).build()];
+    if let Some(coord) = coord {
+        sort_by.push(
+            GeoDistance::new("coord")
+                .with_location(Location::LatLon(coord.lat(), coord.lon()))
+                .with_order(Order::Asc)
+                .build(),
+        );
+    }
+    sort_by.extend(tie_breakers.iter().map(build_tie_breaker_sort));
+    Sort::new(sort_by)
+}
+
+/// The query, the sort and the indexes of the search of the places matching `q`,
+/// shared by the searches of the places and of their suggestions
+#[allow(clippy::too_many_arguments)]
+fn build_search(
+    q: &str,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    mode: SearchMode,
+    strict_house_number: bool,
+    coord: Option<Coord>,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    focus_bbox: Option<Rect<f64>>,
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    langs: &[&str],
+    query_settings: &QuerySettings,
+) -> (Query, Option<Sort>, Vec<String>) {
+    let (query, sort) = match mode {
+        SearchMode::Text(match_type) => (
+            build_query(
+                q,
+                match_type,
+                strict_house_number,
+                coord,
+                shape,
+                shape_scope,
+                focus_bbox,
+                pt_datasets,
+                all_data,
+                date,
+                langs,
+                zone_types,
+                poi_types,
+                query_settings,
+            ),
+            build_score_sort(&query_settings.tie_breakers),
+        ),
+        SearchMode::Browse => (
+            build_browse_query(
+                shape,
+                shape_scope,
+                pt_datasets,
+                all_data,
+                date,
+                zone_types,
+                poi_types,
+            ),
+            Some(build_browse_sort(
+                coord.as_ref(),
+                &query_settings.tie_breakers,
+            )),
+        ),
+    };
+    let query = build_exclusions_filter(query, exclusions);
+    let query = build_within_filter(query, within);
+    let query = build_datasets_filter(query, datasets);
+    let query = build_flags_filter(query, flags);
+
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, types);
+    debug!("ES indexes: {:?}", indexes);
+    (query, sort, indexes)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -467,51 +1385,86 @@ fn query(
     pt_datasets: &[&str],
     poi_datasets: &[&str],
     all_data: bool,
+    date: &str,
     rubber: &mut Rubber,
-    match_type: MatchType,
+    mode: SearchMode,
+    strict_house_number: bool,
     offset: u64,
     limit: u64,
     coord: Option<Coord>,
     shape: Option<Geometry>,
     shape_scope: &[PlaceDocType],
+    focus_bbox: Option<Rect<f64>>,
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
     types: &[&str],
     zone_types: &[&str],
     poi_types: &[&str],
     langs: &[&str],
     debug: bool,
+    with_geometry: bool,
     query_settings: &QuerySettings,
     request_id: Option<&str>,
-) -> Result<Vec<mimir::Place>, EsError> {
+    preference: Option<&str>,
+    cancellation: &Cancellation,
+) -> Result<(Vec<mimir::Place>, Vec<&'static str>), BragiError> {
     if let Some(id) = request_id {
         info!("query::autocomplete - enter - {} - ({})", id, q);
     }
 
-    let query_type = match_type.to_string();
-    let query = build_query(
+    let query_type = mode.to_string();
+    // the filters of the request are given to the search templates
+    let templates = &query_settings.query.templates;
+    let template_filter = if templates.is_empty() || debug {
+        None
+    } else {
+        let filter = build_browse_query(
+            shape.clone(),
+            shape_scope,
+            pt_datasets,
+            all_data,
+            date,
+            zone_types,
+            poi_types,
+        );
+        let filter = build_exclusions_filter(filter, exclusions);
+        let filter = build_within_filter(filter, within);
+        let filter = build_datasets_filter(filter, datasets);
+        Some(build_flags_filter(filter, flags))
+    };
+    let (query, sort, indexes) = build_search(
         q,
-        match_type,
+        pt_datasets,
+        poi_datasets,
+        all_data,
+        date,
+        mode,
+        strict_house_number,
         coord,
         shape,
         shape_scope,
-        pt_datasets,
-        all_data,
-        langs,
+        focus_bbox,
+        exclusions,
+        within,
+        datasets,
+        flags,
+        types,
         zone_types,
         poi_types,
+        langs,
         query_settings,
     );
-
-    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, types);
     let indexes = indexes
         .iter()
         .map(|index| index.as_str())
         .collect::<Vec<&str>>();
-    debug!("ES indexes: {:?}", indexes);
 
     if indexes.is_empty() {
         // if there is no indexes, rs_es search with index "_all"
         // but we want to return empty response in this case.
-        return Ok(vec![]);
+        return Ok((vec![], vec![]));
     }
     let timer = ES_REQ_HISTOGRAM
         .get_metric_with_label_values(&[query_type.as_str()])
@@ -521,35 +1474,99 @@ fn query(
         )
         .ok();
 
+    // the document types with a search template are searched with it, the others with the query
+    if let (SearchMode::Text(match_type), Some(filter)) = (mode, &template_filter) {
+        let has_template = split_indexes_by_type(&indexes)
+            .keys()
+            .any(|doc_type| templates.contains_key(*doc_type));
+        if has_template {
+            let places = search_with_templates(
+                rubber,
+                &query,
+                sort.as_ref(),
+                filter,
+                q,
+                match_type,
+                &indexes,
+                offset,
+                limit,
+                coord.as_ref(),
+                langs,
+                with_geometry,
+                preference,
+                query_settings,
+            );
+            if let Some(t) = timer {
+                t.observe_duration();
+            }
+            return places.map(|places| (places, vec![]));
+        }
+    }
+
+    let deadlines = &query_settings.query.deadlines;
+    if deadlines.enabled && mode != SearchMode::Browse {
+        let res = search_by_type(
+            rubber,
+            query,
+            sort,
+            &indexes,
+            offset,
+            limit,
+            coord,
+            debug,
+            with_geometry,
+            preference,
+            deadlines,
+            &query_settings.tie_breakers,
+            cancellation,
+        );
+        if let Some(t) = timer {
+            t.observe_duration();
+        }
+        return res.map_err(BragiError::from);
+    }
+
     let timeout = rubber.timeout.map(|t| format!("{:?}", t));
-    let mut search_query = rubber.es_client.search_query();
-
-    let search_query = search_query
-        .with_ignore_unavailable(true)
-        .with_indexes(&indexes)
-        .with_query(&query)
-        .with_from(offset)
-        .with_size(limit)
-        // No need to fetch "boundary" as it's not used in the geocoding response
-        // and is very large in some documents (countries...)
-        .with_source(Source::exclude(&["boundary"]));
-
-    // We don't want to clutter the Query URL, so we only add an explanation if the option is used
-    let search_query = if debug {
-        search_query.with_explain(true)
-    } else {
-        search_query
-    };
 
-    if let Some(timeout) = &timeout {
-        search_query.with_timeout(timeout.as_str());
+    // the debug requests give the reasons of the match of each place
+    if debug {
+        let places = search_with_match_reasons(
+            rubber,
+            &query,
+            sort.as_ref(),
+            &indexes,
+            offset,
+            limit,
+            timeout.as_deref(),
+            preference,
+            coord.as_ref(),
+            with_geometry,
+        );
+        if let Some(t) = timer {
+            t.observe_duration();
+        }
+        return places
+            .map(|places| (places, vec![]))
+            .map_err(BragiError::from);
     }
 
     if let Some(id) = request_id {
         info!("query::autocomplete - es - enter - {} - ({})", id, q);
     }
 
-    let result = search_query.send()?;
+    // the repeated requests of a client are sent to the same shards, for a consistent pagination
+    let places = search_places(
+        rubber,
+        &query,
+        sort.as_ref(),
+        &indexes,
+        offset,
+        limit,
+        timeout.as_deref(),
+        preference,
+        coord.as_ref(),
+        with_geometry,
+    );
 
     if let Some(id) = request_id {
         info!("query::autocomplete - es - exit - {} - ({})", id, q);
@@ -559,7 +1576,9 @@ fn query(
         t.observe_duration();
     }
 
-    let res = read_places(result, coord.as_ref());
+    let res = places
+        .map(|places| (places, vec![]))
+        .map_err(BragiError::from);
 
     if let Some(id) = request_id {
         info!("query::autocomplete - exit - {} - ({})", id, q);
@@ -568,91 +1587,694 @@ fn query(
     res
 }
 
-pub fn features(
-    pt_datasets: &[&str],
-    poi_datasets: &[&str],
-    all_data: bool,
-    id: &str,
-    mut rubber: Rubber,
-) -> Result<Vec<mimir::Place>, BragiError> {
-    let val = rs_es::units::JsonVal::String(id.into());
-    let mut filters = vec![Query::build_ids(vec![val]).build()];
-
-    // if searching through all data, no coverage filter
-    if !all_data {
-        filters.push(build_coverage_condition(pt_datasets));
+/// Reason given for the places matching a clause of the query, if it explains a match
+fn match_reason(clause: &serde_json::Value) -> Option<&'static str> {
+    if let Some(multi_match) = clause.get("multi_match") {
+        // the first field is the default one, the others are its translations
+        return match multi_match["fields"][0].as_str()? {
+            "name" => Some("name"),
+            "alt_names" => Some("alt_name"),
+            "label" => Some("label"),
+            "label.prefix" => Some("prefix"),
+            "label.ngram" => Some("ngram"),
+            _ => None,
+        };
     }
-    let filter = Query::build_bool().with_must(filters).build();
-    let query = Query::build_bool().with_filter(filter).build();
+    if let Some(fields) = clause.get("match").and_then(|m| m.as_object()) {
+        return match fields.keys().next()?.as_str() {
+            "zip_codes" => Some("zip_code"),
+            "house_number" => Some("house_number"),
+            "label" => Some("label"),
+            "full_label.shingle" => Some("word_order"),
+            "lines.code" | "lines.name" => Some("line"),
+            _ => None,
+        };
+    }
+    if let Some(functions) = clause["function_score"]["functions"].as_array() {
+        let decays_with = |field: &str| {
+            functions
+                .iter()
+                .any(|f| f["exp"][field].is_object() || f["gauss"][field].is_object())
+        };
+        return if decays_with("coord") {
+            Some("proximity")
+        } else if decays_with("updated_at") {
+            Some("recency")
+        } else {
+            None
+        };
+    }
+    if clause["geo_bounding_box"]["coord"].is_object() {
+        return Some("focus_bbox");
+    }
+    None
+}
 
-    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, &[]);
-    let indexes = indexes
-        .iter()
-        .map(|index| index.as_str())
-        .collect::<Vec<&str>>();
+/// Name the clauses of the query explaining why a place matches (see `match_reason`),
+/// so that ES gives the names of the clauses matched by each place.
+///
+/// rs_es does not support the named queries, so the clauses are named in the json of the query:
+/// each one is wrapped in a named bool query, which does not change its score.
+fn name_match_reasons(query: &mut serde_json::Value) {
+    match query {
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(name_match_reasons),
+        serde_json::Value::Array(values) => values.iter_mut().for_each(name_match_reasons),
+        _ => return,
+    }
+    if let Some(reason) = match_reason(query) {
+        let clause = query.take();
+        *query = serde_json::json!({ "bool": { "must": [clause], "_name": reason } });
+    }
+}
 
-    debug!("ES indexes: {:?}", indexes);
+/// Search the places with the reasons of their match in their context.
+///
+/// The search is sent as raw json, since rs_es supports neither the named queries
+/// nor the names of the matched queries in the hits.
+#[allow(clippy::too_many_arguments)]
+fn search_with_match_reasons(
+    rubber: &Rubber,
+    query: &Query,
+    sort: Option<&Sort>,
+    indexes: &[&str],
+    offset: u64,
+    limit: u64,
+    timeout: Option<&str>,
+    preference: Option<&str>,
+    coord: Option<&Coord>,
+    with_geometry: bool,
+) -> Result<Vec<mimir::Place>, EsError> {
+    let mut query = serde_json::to_value(query)?;
+    name_match_reasons(&mut query);
+    let excluded_fields: &[&str] = if with_geometry {
+        &UNUSED_SOURCE_FIELDS_WITH_GEOMETRY
+    } else {
+        &["boundary"]
+    };
+    let mut body = serde_json::json!({
+        "query": query,
+        "from": offset,
+        "size": limit,
+        "explain": true,
+        "_source": { "exclude": excluded_fields },
+    });
+    if let Some(sort) = sort {
+        body["sort"] = serde_json::to_value(sort)?;
+    }
+    if let Some(timeout) = timeout {
+        body["timeout"] = timeout.into();
+    }
+    let result = rubber.raw_search(indexes, &body, preference)?;
+    read_raw_places(result, coord)
+}
 
-    if indexes.is_empty() {
-        // if there is no indexes, rs_es search with index "_all"
-        // but we want to return an error in this case.
-        return Err(BragiError::ObjectNotFound);
+/// Search the places matching `query`.
+///
+/// The fields never returned in the responses (and the geometries, unless `with_geometry`)
+/// are not fetched, and the places are read directly from the body of the ES response.
+#[allow(clippy::too_many_arguments)]
+fn search_places(
+    rubber: &Rubber,
+    query: &Query,
+    sort: Option<&Sort>,
+    indexes: &[&str],
+    offset: u64,
+    limit: u64,
+    timeout: Option<&str>,
+    preference: Option<&str>,
+    coord: Option<&Coord>,
+    with_geometry: bool,
+) -> Result<Vec<mimir::Place>, EsError> {
+    let excluded_fields: &[&str] = if with_geometry {
+        &UNUSED_SOURCE_FIELDS_WITH_GEOMETRY
+    } else {
+        &UNUSED_SOURCE_FIELDS
+    };
+    let mut body = serde_json::json!({
+        "query": serde_json::to_value(query)?,
+        "from": offset,
+        "size": limit,
+        "_source": { "exclude": excluded_fields },
+    });
+    if let Some(sort) = sort {
+        body["sort"] = serde_json::to_value(sort)?;
     }
+    if let Some(timeout) = timeout {
+        body["timeout"] = timeout.into();
+    }
+    let response = rubber.raw_search_bytes(indexes, &body, preference)?;
+    read_places_from_slice(&response, coord)
+}
 
-    let timer = ES_REQ_HISTOGRAM
-        .get_metric_with_label_values(&["features"])
-        .map(|h| h.start_timer())
-        .map_err(
-            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
-        )
-        .ok();
+/// Parameters of the search template of a document type.
+///
+/// The filters of the request are given as the json of a query in `filter`,
+/// to be inserted as is in the template (with `{{{filter}}}`).
+#[allow(clippy::too_many_arguments)]
+fn template_params(
+    doc_type: &str,
+    q: &str,
+    match_type: MatchType,
+    size: u64,
+    coord: Option<&Coord>,
+    langs: &[&str],
+    filter: &Query,
+    query_settings: &QuerySettings,
+) -> Result<serde_json::Value, EsError> {
+    let types = &query_settings.type_query.boosts;
+    let type_boost = match doc_type {
+        "addr" => types.address,
+        "admin" => types.admin,
+        "poi" => types.poi,
+        "stop" => types.stop,
+        "street" => types.street,
+        _ => 1.,
+    };
+    let boosts = &query_settings.string_query.boosts;
+    let mut params = serde_json::json!({
+        "q": q,
+        "match_type": match_type.to_string(),
+        "from": 0,
+        "size": size,
+        "langs": langs,
+        "boosts": {
+            "type": type_boost,
+            "name": boosts.name,
+            "alt_names": boosts.alt_names,
+            "label": boosts.label,
+            "label_prefix": boosts.label_prefix,
+            "zip_codes": boosts.zip_codes,
+            "house_number": boosts.house_number,
+            "label_ngram": boosts.label_ngram,
+        },
+        "filter": serde_json::to_value(filter)?.to_string(),
+    });
+    if let Some(coord) = coord {
+        params["lat"] = coord.lat().into();
+        params["lon"] = coord.lon().into();
+    }
+    Ok(params)
+}
 
+/// Search the places of the document types having a search template with their template,
+/// and the places of the other types with `query`.
+///
+/// As with the deadlines, the places of all the types are then sorted by score.
+/// The templates choose the fields of the places they fetch.
+#[allow(clippy::too_many_arguments)]
+fn search_with_templates(
+    rubber: &Rubber,
+    query: &Query,
+    sort: Option<&Sort>,
+    filter: &Query,
+    q: &str,
+    match_type: MatchType,
+    indexes: &[&str],
+    offset: u64,
+    limit: u64,
+    coord: Option<&Coord>,
+    langs: &[&str],
+    with_geometry: bool,
+    preference: Option<&str>,
+    query_settings: &QuerySettings,
+) -> Result<Vec<mimir::Place>, BragiError> {
     let timeout = rubber.timeout.map(|t| format!("{:?}", t));
-    let mut search_query = rubber.es_client.search_query();
+    let templates = &query_settings.query.templates;
+    let mut places = vec![];
+    let mut other_indexes = vec![];
+    for (doc_type, type_indexes) in split_indexes_by_type(indexes) {
+        let template = match templates.get(doc_type) {
+            Some(template) => template,
+            None => {
+                other_indexes.extend(type_indexes);
+                continue;
+            }
+        };
+        let params = template_params(
+            doc_type,
+            q,
+            match_type,
+            offset + limit,
+            coord,
+            langs,
+            filter,
+            query_settings,
+        )?;
+        let type_indexes = type_indexes
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        let response = rubber
+            .search_template_bytes(&type_indexes, template, &params, preference)?
+            .ok_or_else(|| BragiError::MissingSearchTemplate {
+                template: template.clone(),
+                doc_type,
+            })?;
+        places.extend(read_places_from_slice(&response, coord)?);
+    }
+    if !other_indexes.is_empty() {
+        let other_indexes = other_indexes
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        places.extend(search_places(
+            rubber,
+            query,
+            sort,
+            &other_indexes,
+            0,
+            offset + limit,
+            timeout.as_deref(),
+            preference,
+            coord,
+            with_geometry,
+        )?);
+    }
 
-    let search_query = search_query
-        .with_ignore_unavailable(true)
-        .with_indexes(&indexes)
-        .with_query(&query);
+    places.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal));
+    Ok(places
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect())
+}
 
-    if let Some(timeout) = &timeout {
-        search_query.with_timeout(timeout.as_str());
+/// The indexes of a query, split by the document type of their places
+fn split_indexes_by_type(indexes: &[&str]) -> BTreeMap<&'static str, Vec<String>> {
+    let all_types = GEO_DATA_TYPES.iter().chain(iter::once(&"stop"));
+    let mut indexes_by_type = BTreeMap::new();
+    for index in indexes {
+        let types_and_indexes = match *index {
+            "munin" => all_types
+                .clone()
+                .map(|t| (*t, format!("munin_{}", t)))
+                .collect(),
+            "munin_geo_data" => GEO_DATA_TYPES
+                .iter()
+                .map(|t| (*t, format!("munin_{}", t)))
+                .collect(),
+            "munin_global_stops" => vec![("stop", index.to_string())],
+            _ => all_types
+                .clone()
+                .find(|t| index.starts_with(&format!("munin_{}", t)))
+                .map(|t| vec![(*t, index.to_string())])
+                .unwrap_or_else(|| vec![("other", index.to_string())]),
+        };
+        for (doc_type, index) in types_and_indexes {
+            indexes_by_type
+                .entry(doc_type)
+                .or_insert_with(Vec::new)
+                .push(index);
+        }
     }
+    indexes_by_type
+}
 
-    let result = search_query.send()?;
+/// The value of a sort field in a place, read from its source like ES would
+fn sort_value(
+    place: &mimir::Place,
+    source: &serde_json::Value,
+    field: &str,
+) -> Option<serde_json::Value> {
+    // the source of a place is its only field, named by its type
+    let (doc_type, source) = source.as_object()?.iter().next()?;
+    if field == "_uid" {
+        let uid = format!("{}#{}", doc_type.to_lowercase(), place.id());
+        return Some(serde_json::Value::String(uid));
+    }
+    let pointer = format!("/{}", field.replace('.', "/"));
+    source
+        .pointer(&pointer)
+        .filter(|value| !value.is_null())
+        .cloned()
+}
 
-    if let Some(t) = timer {
-        t.observe_duration()
+// the places missing the field are last whatever the order, like the default `missing` of ES
+fn compare_sort_values(
+    a: Option<&serde_json::Value>,
+    b: Option<&serde_json::Value>,
+    order: SortOrder,
+) -> Ordering {
+    let (a, b) = match (a, b) {
+        (None, None) => return Ordering::Equal,
+        (None, Some(_)) => return Ordering::Greater,
+        (Some(_), None) => return Ordering::Less,
+        (Some(a), Some(b)) => (a, b),
+    };
+    let ordering = match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => a.as_str().unwrap_or("").cmp(b.as_str().unwrap_or("")),
+    };
+    match order {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
     }
+}
 
-    if result.hits.total == 0 {
-        Err(BragiError::ObjectNotFound)
-    } else {
-        read_places(result, None).map_err(model::BragiError::from)
+/// Sorts the places found by several searches like a single search would do:
+/// by score, and the places with the same score by the tie breakers
+fn sort_by_score(places: Vec<mimir::Place>, tie_breakers: &[TieBreaker]) -> Vec<mimir::Place> {
+    let mut places: Vec<(Vec<Option<serde_json::Value>>, mimir::Place)> = places
+        .into_iter()
+        .map(|place| {
+            let source = if tie_breakers.is_empty() {
+                serde_json::Value::Null
+            } else {
+                serde_json::to_value(&place).unwrap_or_default()
+            };
+            let values = tie_breakers
+                .iter()
+                .map(|tie_breaker| sort_value(&place, &source, &tie_breaker.field))
+                .collect();
+            (values, place)
+        })
+        .collect();
+    places.sort_by(|(a_values, a), (b_values, b)| {
+        let by_score = b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal);
+        tie_breakers
+            .iter()
+            .enumerate()
+            .fold(by_score, |ordering, (i, tie_breaker)| {
+                ordering.then_with(|| {
+                    compare_sort_values(
+                        a_values[i].as_ref(),
+                        b_values[i].as_ref(),
+                        tie_breaker.order,
+                    )
+                })
+            })
+    });
+    places.into_iter().map(|(_, place)| place).collect()
+}
+
+/// Search the places of each document type concurrently, on the search pool.
+///
+/// The types whose search has not ended within their deadline are dropped,
+/// and returned with the places found in the other types.
+/// Once the request is cancelled, the searches not ended yet are no longer waited for.
+#[allow(clippy::too_many_arguments)]
+fn search_by_type(
+    rubber: &Rubber,
+    query: Query,
+    sort: Option<Sort>,
+    indexes: &[&str],
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    debug: bool,
+    with_geometry: bool,
+    preference: Option<&str>,
+    deadlines: &Deadlines,
+    tie_breakers: &[TieBreaker],
+    cancellation: &Cancellation,
+) -> Result<(Vec<mimir::Place>, Vec<&'static str>), EsError> {
+    let start = Instant::now();
+    let indexes_by_type = split_indexes_by_type(indexes);
+    let query = Arc::new(query);
+    let sort = Arc::new(sort);
+    let (sender, receiver) = mpsc::channel();
+    for (&doc_type, type_indexes) in &indexes_by_type {
+        let type_indexes = type_indexes.clone();
+        let rubber = rubber.clone();
+        let (query, sort, sender) = (query.clone(), sort.clone(), sender.clone());
+        let preference = preference.map(str::to_string);
+        // ES also stops the search at the deadline of the type
+        let timeout = format!("{}ms", deadlines.type_budget(doc_type).as_millis());
+        search_pool().execute(move || {
+            let type_indexes = type_indexes
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<&str>>();
+            let places = if debug {
+                search_with_match_reasons(
+                    &rubber,
+                    &query,
+                    (*sort).as_ref(),
+                    &type_indexes,
+                    0,
+                    offset + limit,
+                    Some(timeout.as_str()),
+                    preference.as_deref(),
+                    coord.as_ref(),
+                    with_geometry,
+                )
+            } else {
+                search_places(
+                    &rubber,
+                    &query,
+                    (*sort).as_ref(),
+                    &type_indexes,
+                    0,
+                    offset + limit,
+                    Some(timeout.as_str()),
+                    preference.as_deref(),
+                    coord.as_ref(),
+                    with_geometry,
+                )
+            };
+            // the receiver is gone if the overall deadline has passed
+            let _ = sender.send((doc_type, places));
+        });
     }
+    drop(sender);
+
+    let mut pending: BTreeSet<&'static str> = indexes_by_type.keys().copied().collect();
+    let mut places = vec![];
+    let mut dropped = vec![];
+    while !pending.is_empty() {
+        // there is no need to wait once the deadlines of all the pending types have passed
+        let wait_until = pending
+            .iter()
+            .map(|t| start + deadlines.type_budget(t))
+            .max()
+            .unwrap_or(start);
+        let now = Instant::now();
+        if now >= wait_until || cancellation.is_cancelled() {
+            break;
+        }
+        let wait = (wait_until - now).min(CANCELLATION_CHECK_INTERVAL);
+        let (doc_type, type_places) = match receiver.recv_timeout(wait) {
+            Ok(res) => res,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        pending.remove(doc_type);
+        if start.elapsed() > deadlines.type_budget(doc_type) {
+            dropped.push(doc_type);
+        } else {
+            places.extend(type_places?);
+        }
+    }
+    dropped.extend(pending);
+    dropped.sort();
+    if !dropped.is_empty() {
+        warn!("the searches of {:?} have exceeded their deadline", dropped);
+    }
+
+    // the places of all the types are sorted as a single search would do
+    let places = sort_by_score(places, tie_breakers)
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    Ok((places, dropped))
 }
 
 #[allow(clippy::too_many_arguments)]
-pub fn autocomplete(
+fn count(
     q: &str,
     pt_datasets: &[&str],
     poi_datasets: &[&str],
     all_data: bool,
-    offset: u64,
-    limit: u64,
+    date: &str,
+    rubber: &mut Rubber,
+    mode: SearchMode,
+    strict_house_number: bool,
     coord: Option<Coord>,
     shape: Option<Geometry>,
     shape_scope: &[PlaceDocType],
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
     types: &[&str],
     zone_types: &[&str],
     poi_types: &[&str],
     langs: &[&str],
-    mut rubber: Rubber,
-    debug: bool,
     query_settings: &QuerySettings,
-    request_id: Option<&str>,
-) -> Result<Vec<mimir::Place>, BragiError> {
-    // Perform parameters validation.
+) -> Result<u64, EsError> {
+    let query_type = format!("count_{}", mode);
+    let query = match mode {
+        // the focus bbox only changes the scores, not the matching places
+        SearchMode::Text(match_type) => build_query(
+            q,
+            match_type,
+            strict_house_number,
+            coord,
+            shape,
+            shape_scope,
+            None,
+            pt_datasets,
+            all_data,
+            date,
+            langs,
+            zone_types,
+            poi_types,
+            query_settings,
+        ),
+        SearchMode::Browse => build_browse_query(
+            shape,
+            shape_scope,
+            pt_datasets,
+            all_data,
+            date,
+            zone_types,
+            poi_types,
+        ),
+    };
+    let query = build_exclusions_filter(query, exclusions);
+    let query = build_within_filter(query, within);
+    let query = build_datasets_filter(query, datasets);
+    let query = build_flags_filter(query, flags);
+
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, types);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+    debug!("ES indexes: {:?}", indexes);
+
+    if indexes.is_empty() {
+        // if there is no indexes, ES counts on index "_all"
+        // but we want to return an empty count in this case.
+        return Ok(0);
+    }
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&[query_type.as_str()])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+
+    // Only the number of matching documents is computed by ES, no document is fetched.
+    let body = serde_json::json!({
+        "query": serde_json::to_value(&query)?,
+        "size": 0,
+    });
+    let result = rubber.raw_search(&indexes, &body, None)?;
+
+    if let Some(t) = timer {
+        t.observe_duration();
+    }
+
+    Ok(result["hits"]["total"].as_u64().unwrap_or(0))
+}
+
+/// Search the places matching `q` like `query`, fetching only the fields of their suggestions
+#[allow(clippy::too_many_arguments)]
+fn suggest(
+    q: &str,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    rubber: &mut Rubber,
+    mode: SearchMode,
+    strict_house_number: bool,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    focus_bbox: Option<Rect<f64>>,
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    langs: &[&str],
+    query_settings: &QuerySettings,
+    preference: Option<&str>,
+) -> Result<Vec<SuggestedPlace>, EsError> {
+    let query_type = format!("suggest_{}", mode);
+    let (query, sort, indexes) = build_search(
+        q,
+        pt_datasets,
+        poi_datasets,
+        all_data,
+        date,
+        mode,
+        strict_house_number,
+        coord,
+        shape,
+        shape_scope,
+        focus_bbox,
+        exclusions,
+        within,
+        datasets,
+        flags,
+        types,
+        zone_types,
+        poi_types,
+        langs,
+        query_settings,
+    );
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+
+    if indexes.is_empty() {
+        return Ok(vec![]);
+    }
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&[query_type.as_str()])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+
+    // only the few fields of the suggestions are fetched and read, not the whole places
+    let lang = langs.first().copied();
+    let mut body = serde_json::json!({
+        "query": serde_json::to_value(&query)?,
+        "from": offset,
+        "size": limit,
+        "_source": { "include": suggestion_source_fields(lang) },
+    });
+    if let Some(sort) = sort {
+        body["sort"] = serde_json::to_value(&sort)?;
+    }
+    if let Some(timeout) = rubber.timeout {
+        body["timeout"] = format!("{}ms", timeout.as_millis()).into();
+    }
+    let response = rubber.raw_search_bytes(&indexes, &body, preference)?;
+
+    if let Some(t) = timer {
+        t.observe_duration();
+    }
+
+    read_suggestions_from_slice(&response, lang)
+}
+
+/// Are the addresses filtered on the house number of the query
+/// (if not, the query can be run again without this filter)
+fn is_strict_on_house_number(q: &str, query_settings: &QuerySettings) -> bool {
+    query_settings.query.address.strict_housenumber
+        && q.split_whitespace().count() > 1
+        && !detect_house_numbers(q).is_empty()
+}
+
+/// The zone and poi types can only be given with their type
+pub(crate) fn check_type_filters(
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+) -> Result<(), BragiError> {
     if !zone_types.is_empty() && !types.iter().any(|s| *s == "zone") {
         return Err(BragiError::InvalidParam(
             "zone_type[] parameter requires to have 'type[]=zone'",
@@ -663,53 +2285,1483 @@ pub fn autocomplete(
             "poi_type[] parameter requires to have 'type[]=poi'",
         ));
     }
+    Ok(())
+}
+
+// Without query string, the places are only filtered, so at least one filter is needed
+fn initial_search_mode(
+    q: &str,
+    shape: Option<&Geometry>,
+    within: Option<&str>,
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+) -> Result<SearchMode, BragiError> {
+    if !q.trim().is_empty() {
+        Ok(SearchMode::Text(MatchType::Prefix))
+    } else if shape.is_some()
+        || within.is_some()
+        || !types.is_empty()
+        || !zone_types.is_empty()
+        || !poi_types.is_empty()
+    {
+        Ok(SearchMode::Browse)
+    } else {
+        Err(BragiError::InvalidParam(
+            "'q' can only be omitted with a filter (shape, within, type[], zone_type[] or poi_type[])",
+        ))
+    }
+}
+
+pub fn features(
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    id: &str,
+    rubber: Rubber,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    let val = rs_es::units::JsonVal::String(id.into());
+    // the place can also have been re-imported with a new id, the requested id is then one
+    // of its old ids
+    let mut filters = vec![Query::build_bool()
+        .with_should(vec![
+            Query::build_ids(vec![val]).build(),
+            Query::build_term("old_ids", id).build(),
+        ])
+        .build()];
+
+    // if searching through all data, no coverage filter
+    if !all_data {
+        filters.push(build_coverage_condition(pt_datasets));
+    }
+    let filter = Query::build_bool().with_must(filters).build();
+    let query = Query::build_bool().with_filter(filter).build();
+
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, &[]);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+
+    debug!("ES indexes: {:?}", indexes);
+
+    if indexes.is_empty() {
+        // if there is no indexes, rs_es search with index "_all"
+        // but we want to return an error in this case.
+        return Err(BragiError::ObjectNotFound);
+    }
+
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&["features"])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+
+    let mut body = serde_json::json!({
+        "query": serde_json::to_value(&query).map_err(EsError::from)?,
+    });
+    if let Some(timeout) = rubber.timeout {
+        body["timeout"] = format!("{}ms", timeout.as_millis()).into();
+    }
+    let response = rubber.raw_search_bytes(&indexes, &body, None)?;
+
+    if let Some(t) = timer {
+        t.observe_duration()
+    }
+
+    let places = read_places_from_slice(&response, None).map_err(model::BragiError::from)?;
+    if places.is_empty() {
+        Err(BragiError::ObjectNotFound)
+    } else {
+        // a place that still has the requested id takes precedence over the aliases
+        if places.iter().any(|p| p.id() == id) {
+            Ok(places.into_iter().filter(|p| p.id() == id).collect())
+        } else {
+            Ok(places)
+        }
+    }
+}
+
+/// The pois (of one of `poi_types` if given) within `radius` meters of `coord`,
+/// sorted by distance to `coord`
+#[allow(clippy::too_many_arguments)]
+pub fn nearby(
+    poi_datasets: &[&str],
+    all_data: bool,
+    coord: &Coord,
+    radius: f64,
+    poi_types: &[&str],
+    datasets: &[&str],
+    offset: u64,
+    limit: u64,
+    tie_breakers: &[TieBreaker],
+    rubber: Rubber,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    let distance = rs_u::Distance::new(radius, rs_u::DistanceUnit::Meter);
+    let mut filters =
+        vec![Query::build_geo_distance("coord", (coord.lat(), coord.lon()), distance).build()];
+    if !poi_types.is_empty() {
+        filters.push(build_poi_types_condition(poi_types));
+    }
+    let query = Query::build_bool()
+        .with_filter(Query::build_bool().with_must(filters).build())
+        .build();
+    let query = build_datasets_filter(query, datasets);
+
+    let indexes = get_indexes(all_data, &[], &poi_datasets, &[Poi::doc_type()]);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+    debug!("ES indexes: {:?}", indexes);
+    if indexes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let sort_by = std::iter::once(
+        GeoDistance::new("coord")
+            .with_location(Location::LatLon(coord.lat(), coord.lon()))
+            .with_order(Order::Asc)
+            .build(),
+    )
+    .chain(tie_breakers.iter().map(build_tie_breaker_sort))
+    .collect();
+    let sort = Sort::new(sort_by);
+
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&["nearby"])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+
+    let timeout = rubber.timeout.map(|t| format!("{:?}", t));
+    let places = search_places(
+        &rubber,
+        &query,
+        Some(&sort),
+        &indexes,
+        offset,
+        limit,
+        timeout.as_deref(),
+        None,
+        Some(coord),
+        false,
+    );
+    if let Some(t) = timer {
+        t.observe_duration();
+    }
+    places.map_err(model::BragiError::from)
+}
+
+/// The places matching the filters (without query string) sorted by id, after the place
+/// `after` if given.
+///
+/// Unlike `offset`, which ES limits to the first 10000 places, this pages through all the places:
+/// each page starts after the last place of the previous one. The id is a stable sort
+/// (ES 2 has no `search_after`, so the next pages are filtered on the id).
+#[allow(clippy::too_many_arguments)]
+pub fn browse_after(
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    after: Option<&str>,
+    limit: u64,
+    with_geometry: bool,
+    rubber: Rubber,
+) -> Result<Vec<mimir::Place>, BragiError> {
+    // the places are browsed, a filter is needed
+    initial_search_mode("", shape.as_ref(), within, types, zone_types, poi_types)?;
+
+    let query = build_browse_query(
+        shape,
+        shape_scope,
+        pt_datasets,
+        all_data,
+        date,
+        zone_types,
+        poi_types,
+    );
+    let query = match after {
+        Some(id) => Query::build_bool()
+            .with_must(query)
+            .with_filter(Query::build_range("id").with_gt(id).build())
+            .build(),
+        None => query,
+    };
+    let query = build_exclusions_filter(query, exclusions);
+    let query = build_within_filter(query, within);
+    let query = build_datasets_filter(query, datasets);
+    let query = build_flags_filter(query, flags);
+
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, types);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+    debug!("ES indexes: {:?}", indexes);
+    if indexes.is_empty() {
+        return Ok(vec![]);
+    }
+    let sort = Sort::new(vec![SortField::new("id", Some(Order::Asc)).build()]);
+
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&["browse_after"])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+
+    let timeout = rubber.timeout.map(|t| format!("{:?}", t));
+    let places = search_places(
+        &rubber,
+        &query,
+        Some(&sort),
+        &indexes,
+        0,
+        limit,
+        timeout.as_deref(),
+        None,
+        None,
+        with_geometry,
+    );
+    if let Some(t) = timer {
+        t.observe_duration();
+    }
+    places.map_err(model::BragiError::from)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn autocomplete(
+    q: &str,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    focus_bbox: Option<Rect<f64>>,
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    langs: &[&str],
+    rubber: Rubber,
+    debug: bool,
+    with_geometry: bool,
+    query_settings: &QuerySettings,
+    request_id: Option<&str>,
+    preference: Option<&str>,
+    collapse_field: Option<CollapseField>,
+    merge_streets_into_addresses: bool,
+    cancellation: &Cancellation,
+) -> Result<(Vec<mimir::Place>, Vec<&'static str>), BragiError> {
+    let mode = initial_search_mode(q, shape.as_ref(), within, types, zone_types, poi_types)?;
+    // the searches in progress when the client disconnects are aborted
+    let mut rubber = rubber.with_abort(cancellation.flag());
+
+    // When merging, collapsing or diversifying the results,
+    // the offset and limit can only be applied on the filtered results
+    let post_filter = merge_streets_into_addresses
+        || collapse_field.is_some()
+        || query_settings.diversity.is_enabled();
+    // and the window fetched from ES cannot go beyond its max_result_window
+    let (es_offset, es_limit) = if post_filter {
+        (
+            0,
+            ((offset + limit) * POST_FILTER_WINDOW_FACTOR).min(MAX_RESULT_WINDOW),
+        )
+    } else {
+        (offset, limit.min(MAX_RESULT_WINDOW.saturating_sub(offset)))
+    };
+
+    let mut search = |mode, strict_house_number| {
+        cancellation.check()?;
+        query(
+            &q,
+            &pt_datasets,
+            &poi_datasets,
+            all_data,
+            date,
+            &mut rubber,
+            mode,
+            strict_house_number,
+            es_offset,
+            es_limit,
+            coord,
+            shape.clone(),
+            &shape_scope,
+            focus_bbox,
+            exclusions,
+            within,
+            datasets,
+            flags,
+            &types,
+            &zone_types,
+            &poi_types,
+            &langs,
+            debug,
+            with_geometry,
+            query_settings,
+            request_id,
+            preference,
+            cancellation,
+        )
+        // an aborted search fails, the request is cancelled
+        .map_err(|err| {
+            if cancellation.is_cancelled() {
+                BragiError::Cancelled
+            } else {
+                err
+            }
+        })
+    };
+
+    // First we try a pretty exact match on the prefix.
+    // If there are no results then we do a new fuzzy search (matching ngrams)
+    // (there is nothing to fuzzy match when browsing the places without query string)
+    let strict_house_number = query_settings.query.address.strict_housenumber;
+    let (results, partial) = search(mode, strict_house_number)?;
+    let (results, partial) = if results.is_empty() && mode != SearchMode::Browse {
+        search(SearchMode::Text(MatchType::Fuzzy), strict_house_number)?
+    } else {
+        (results, partial)
+    };
+    // the house number of the query may not exist, the addresses of the street are then searched
+    // (fuzzily, since the house number can not match the prefix of another one)
+    let (results, partial) = if results.is_empty() && is_strict_on_house_number(q, query_settings) {
+        search(SearchMode::Text(MatchType::Fuzzy), false)?
+    } else {
+        (results, partial)
+    };
+    // the types not waited for are not partial, the whole response is dropped
+    cancellation.check()?;
+    for doc_type in &partial {
+        PARTIAL_RESPONSES_COUNTER
+            .with_label_values(&[*doc_type])
+            .inc();
+    }
+
+    if !post_filter {
+        return Ok((results, partial));
+    }
+    let results = if merge_streets_into_addresses {
+        merge_streets(results)
+    } else {
+        results
+    };
+    let results = match collapse_field {
+        Some(field) => collapse(results, field),
+        None => results,
+    };
+    let results = diversify(results, &query_settings.diversity)
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    Ok((results, partial))
+}
+
+/// Count the places matching an autocomplete query, without fetching them.
+///
+/// The same prefix-then-fuzzy strategy as `autocomplete` is used, so that the
+/// count is consistent with the number of results a full query would return.
+#[allow(clippy::too_many_arguments)]
+pub fn autocomplete_count(
+    q: &str,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    coord: Option<Coord>,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    langs: &[&str],
+    mut rubber: Rubber,
+    query_settings: &QuerySettings,
+) -> Result<u64, BragiError> {
+    let mode = initial_search_mode(q, shape.as_ref(), within, types, zone_types, poi_types)?;
+
+    let mut count_places = |mode, strict_house_number| {
+        count(
+            &q,
+            &pt_datasets,
+            &poi_datasets,
+            all_data,
+            date,
+            &mut rubber,
+            mode,
+            strict_house_number,
+            coord,
+            shape.clone(),
+            &shape_scope,
+            exclusions,
+            within,
+            datasets,
+            flags,
+            &types,
+            &zone_types,
+            &poi_types,
+            &langs,
+            query_settings,
+        )
+        .map_err(model::BragiError::from)
+    };
+
+    let strict_house_number = query_settings.query.address.strict_housenumber;
+    let nb = count_places(mode, strict_house_number)?;
+    let nb = if nb == 0 && mode != SearchMode::Browse {
+        count_places(SearchMode::Text(MatchType::Fuzzy), strict_house_number)?
+    } else {
+        nb
+    };
+    if nb == 0 && is_strict_on_house_number(q, query_settings) {
+        count_places(SearchMode::Text(MatchType::Fuzzy), false)
+    } else {
+        Ok(nb)
+    }
+}
 
-    // First we try a pretty exact match on the prefix.
-    // If there are no results then we do a new fuzzy search (matching ngrams)
-    let results = query(
-        &q,
-        &pt_datasets,
-        &poi_datasets,
-        all_data,
-        &mut rubber,
-        MatchType::Prefix,
-        offset,
-        limit,
-        coord,
-        shape.clone(),
-        &shape_scope,
-        &types,
-        &zone_types,
-        &poi_types,
-        &langs,
-        debug,
-        query_settings,
-        request_id,
-    )
-    .map_err(model::BragiError::from)?;
-    if results.is_empty() {
-        query(
+/// Suggest the places matching an autocomplete query, for the searches while typing.
+///
+/// The places are searched like by `autocomplete` (with the same prefix-then-fuzzy strategy),
+/// but only their id, label and coordinates are fetched. They are neither merged, collapsed
+/// nor diversified, nor searched with the search templates.
+#[allow(clippy::too_many_arguments)]
+pub fn autocomplete_suggestions(
+    q: &str,
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    offset: u64,
+    limit: u64,
+    coord: Option<Coord>,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    focus_bbox: Option<Rect<f64>>,
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    langs: &[&str],
+    mut rubber: Rubber,
+    query_settings: &QuerySettings,
+    preference: Option<&str>,
+) -> Result<Vec<SuggestedPlace>, BragiError> {
+    let mode = initial_search_mode(q, shape.as_ref(), within, types, zone_types, poi_types)?;
+
+    let mut search = |mode, strict_house_number| {
+        suggest(
             &q,
             &pt_datasets,
             &poi_datasets,
             all_data,
+            date,
             &mut rubber,
-            MatchType::Fuzzy,
+            mode,
+            strict_house_number,
             offset,
             limit,
             coord,
-            shape,
+            shape.clone(),
             &shape_scope,
+            focus_bbox,
+            exclusions,
+            within,
+            datasets,
+            flags,
             &types,
             &zone_types,
             &poi_types,
             &langs,
-            debug,
             query_settings,
-            request_id,
+            preference,
         )
         .map_err(model::BragiError::from)
+    };
+
+    let strict_house_number = query_settings.query.address.strict_housenumber;
+    let suggestions = search(mode, strict_house_number)?;
+    let suggestions = if suggestions.is_empty() && mode != SearchMode::Browse {
+        search(SearchMode::Text(MatchType::Fuzzy), strict_house_number)?
+    } else {
+        suggestions
+    };
+    if suggestions.is_empty() && is_strict_on_house_number(q, query_settings) {
+        search(SearchMode::Text(MatchType::Fuzzy), false)
+    } else {
+        Ok(suggestions)
+    }
+}
+
+/// Width (in degrees of longitude) of the cells of the geohashes of a precision
+fn geohash_cell_width(precision: u8) -> f64 {
+    // the bits of a geohash alternate between the longitude (first) and the latitude
+    360. / 2f64.powi((5 * i32::from(precision) + 1) / 2)
+}
+
+/// Geohash precision of the clusters of a map of `width` degrees of longitude,
+/// so that about `CLUSTERS_ACROSS_MAP` clusters cover its width
+pub fn cluster_precision(width: f64) -> u8 {
+    let cell_width = width / CLUSTERS_ACROSS_MAP;
+    (1..=MAX_GEOHASH_PRECISION)
+        .find(|precision| geohash_cell_width(*precision) <= cell_width)
+        .unwrap_or(MAX_GEOHASH_PRECISION)
+}
+
+/// Width (in degrees of longitude) of a map tile at a zoom level
+pub fn zoom_width(zoom: u8) -> f64 {
+    360. / 2f64.powi(i32::from(zoom))
+}
+
+/// The places of a cell of the grid of the clusters
+#[derive(Debug)]
+pub struct PlaceCluster {
+    /// Geohash of the cell
+    pub geohash: String,
+    pub count: u64,
+    pub centroid: Coord,
+    pub bbox: Option<Rect<f64>>,
+    /// The place of the cluster, when it is alone in its cell
+    pub place: Option<mimir::Place>,
+}
+
+fn read_cluster(mut bucket: serde_json::Value) -> Option<PlaceCluster> {
+    let point = |value: &serde_json::Value| Some((value["lon"].as_f64()?, value["lat"].as_f64()?));
+    let count = bucket["doc_count"].as_u64()?;
+    let (lon, lat) = point(&bucket["centroid"]["location"])?;
+    let bbox = match (
+        point(&bucket["bbox"]["bounds"]["top_left"]),
+        point(&bucket["bbox"]["bounds"]["bottom_right"]),
+    ) {
+        (Some((min_lon, max_lat)), Some((max_lon, min_lat))) => {
+            Some(Rect::new((min_lon, min_lat), (max_lon, max_lat)))
+        }
+        _ => None,
+    };
+    let place = if count == 1 {
+        let mut hit = bucket["place"]["hits"]["hits"][0].take();
+        hit["_type"]
+            .as_str()
+            .map(str::to_string)
+            .and_then(|doc_type| make_place(doc_type, Some(Box::new(hit["_source"].take()))))
     } else {
-        Ok(results)
+        None
+    };
+    Some(PlaceCluster {
+        geohash: bucket["key"].as_str()?.to_string(),
+        count,
+        centroid: Coord::new(lon, lat),
+        bbox,
+        place,
+    })
+}
+
+/// Clusters of the places of an ES response, from its geohash grid aggregation
+fn read_clusters(mut result: serde_json::Value) -> Vec<PlaceCluster> {
+    match result["aggregations"]["clusters"]["buckets"].take() {
+        serde_json::Value::Array(buckets) => buckets.into_iter().filter_map(read_cluster).collect(),
+        _ => vec![],
+    }
+}
+
+/// Cluster the places browsed without query string on a geohash grid of `precision`,
+/// instead of returning them one by one (to display a large area on a map).
+#[allow(clippy::too_many_arguments)]
+pub fn autocomplete_clusters(
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    date: &str,
+    shape: Option<Geometry>,
+    shape_scope: &[PlaceDocType],
+    exclusions: &Exclusions<'_>,
+    within: Option<&str>,
+    datasets: &[&str],
+    flags: &[RequiredFlag<'_>],
+    types: &[&str],
+    zone_types: &[&str],
+    poi_types: &[&str],
+    focus_bbox: Option<Rect<f64>>,
+    rubber: Rubber,
+    precision: u8,
+) -> Result<Vec<PlaceCluster>, BragiError> {
+    // the places are browsed, a filter is needed
+    initial_search_mode("", shape.as_ref(), within, types, zone_types, poi_types)?;
+
+    let query = build_browse_query(
+        shape,
+        shape_scope,
+        pt_datasets,
+        all_data,
+        date,
+        zone_types,
+        poi_types,
+    );
+    let query = build_exclusions_filter(query, exclusions);
+    let query = build_within_filter(query, within);
+    let query = build_datasets_filter(query, datasets);
+    let query = build_flags_filter(query, flags);
+    let query = build_focus_bbox_filter(query, focus_bbox);
+
+    let indexes = get_indexes(all_data, &pt_datasets, &poi_datasets, types);
+    let indexes = indexes
+        .iter()
+        .map(|index| index.as_str())
+        .collect::<Vec<&str>>();
+    if indexes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut body = serde_json::json!({
+        "size": 0,
+        "query": query,
+        "aggs": {
+            "clusters": {
+                "geohash_grid": { "field": "coord", "precision": precision, "size": MAX_CLUSTERS },
+                "aggs": {
+                    "centroid": { "geo_centroid": { "field": "coord" } },
+                    "bbox": { "geo_bounds": { "field": "coord" } },
+                    // the place of the clusters with a single place is returned inline
+                    "place": { "top_hits": { "size": 1, "_source": { "exclude": ["boundary"] } } }
+                }
+            }
+        }
+    });
+    if let Some(timeout) = rubber.timeout {
+        body["timeout"] = format!("{}ms", timeout.as_millis()).into();
+    }
+
+    let timer = ES_REQ_HISTOGRAM
+        .get_metric_with_label_values(&["cluster"])
+        .map(|h| h.start_timer())
+        .map_err(
+            |err| error!("impossible to get ES_REQ_HISTOGRAM metrics"; "err" => err.to_string()),
+        )
+        .ok();
+    let result = rubber.raw_search(&indexes, &body, None)?;
+    if let Some(t) = timer {
+        t.observe_duration();
+    }
+    Ok(read_clusters(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn indexes_are_split_by_type() {
+        let indexes_by_type =
+            split_indexes_by_type(&["munin_geo_data", "munin_global_stops", "munin_poi_private"]);
+        let expected: BTreeMap<_, _> = vec![
+            ("addr", vec!["munin_addr".to_string()]),
+            ("admin", vec!["munin_admin".to_string()]),
+            (
+                "poi",
+                vec!["munin_poi".to_string(), "munin_poi_private".to_string()],
+            ),
+            ("stop", vec!["munin_global_stops".to_string()]),
+            ("street", vec!["munin_street".to_string()]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(indexes_by_type, expected);
+    }
+
+    #[test]
+    fn only_the_queries_looking_like_a_line_are_searched_as_lines() {
+        let settings = LineQuery {
+            mode_keywords: vec!["metro".to_string(), "RER".to_string()],
+            code_regex: Some(regex::Regex::new("^T[0-9]+$").unwrap()),
+            boost: 10.,
+        };
+        assert_eq!(detect_line_code("metro 14", &settings), Some("14"));
+        assert_eq!(detect_line_code(" rer  A ", &settings), Some("A"));
+        assert_eq!(detect_line_code("T3", &settings), Some("T3"));
+        // a bare number is a house number
+        assert_eq!(detect_line_code("14", &settings), None);
+        assert_eq!(detect_line_code("metro", &settings), None);
+        assert_eq!(detect_line_code("14 rue de la gare", &settings), None);
+    }
+
+    #[test]
+    fn fuzziness_depends_on_the_word_length() {
+        let settings = FuzzyQuery::default();
+        assert_eq!(word_fuzziness("la", &settings), 0);
+        assert_eq!(word_fuzziness("rue", &settings), 0);
+        assert_eq!(word_fuzziness("gare", &settings), 1);
+        assert_eq!(word_fuzziness("nantes", &settings), 1);
+        assert_eq!(word_fuzziness("hopital", &settings), 1);
+        assert_eq!(word_fuzziness("toulouse", &settings), 2);
+        assert_eq!(word_fuzziness("marseillaise", &settings), 2);
+        // the characters are counted, not the bytes
+        assert_eq!(word_fuzziness("été", &settings), 0);
+        assert_eq!(word_fuzziness("hôpital", &settings), 1);
+    }
+
+    #[test]
+    fn words_of_other_scripts_are_as_fuzzy_as_their_graphemes() {
+        let settings = FuzzyQuery::default();
+        // the vowel marks are part of the graphemes of their letters
+        assert_eq!(word_fuzziness("القاهرة", &settings), 1);
+        assert_eq!(word_fuzziness("القَاهِرَة", &settings), 1);
+        assert_eq!(word_fuzziness("ירושלים", &settings), 1);
+        assert_eq!(word_fuzziness("יְרוּשָׁלַיִם", &settings), 1);
+        // the ideographic words are never fuzzy
+        assert_eq!(word_fuzziness("東京スカイツリー", &settings), 0);
+        let settings = FuzzyQuery { fuzziness: Some(2) };
+        assert_eq!(word_fuzziness("東京tower", &settings), 0);
+        assert_eq!(word_fuzziness("tower", &settings), 2);
+    }
+
+    #[test]
+    fn queries_of_other_scripts_are_searched_untouched() {
+        let settings =
+            QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        let queries = [
+            "شارع الملك فهد",
+            "רחוב הרצל 12",
+            "東京都庁",
+            "東京 tower",
+            "12 rue 北京",
+        ];
+        for q in queries.iter() {
+            for match_type in vec![MatchType::Prefix, MatchType::Fuzzy] {
+                let query = build_query(
+                    q,
+                    match_type,
+                    true,
+                    None,
+                    None,
+                    &[],
+                    None,
+                    &[],
+                    false,
+                    "2021-01-01",
+                    &["fr"],
+                    &[],
+                    &[],
+                    &settings,
+                );
+                let json = serde_json::to_string(&query).unwrap();
+                assert!(json.contains(q), "{} is not searched in {}", q, json);
+            }
+        }
+    }
+
+    #[test]
+    fn explicit_fuzziness_overrides_the_word_length() {
+        let settings = FuzzyQuery { fuzziness: Some(1) };
+        assert_eq!(word_fuzziness("la", &settings), 1);
+        assert_eq!(word_fuzziness("toulouse", &settings), 1);
+        let settings = FuzzyQuery { fuzziness: Some(0) };
+        assert_eq!(word_fuzziness("marseillaise", &settings), 0);
+    }
+
+    #[test]
+    fn empty_queries_need_a_filter() {
+        let shape: Geometry = serde_json::from_str(
+            r#"{"type": "Polygon", "coordinates": [[[2.0, 48.0], [3.0, 48.0], [3.0, 49.0], [2.0, 48.0]]]}"#,
+        )
+        .unwrap();
+        assert!(initial_search_mode("", None, None, &[], &[], &[]).is_err());
+        assert!(initial_search_mode("  \t ", None, None, &[], &[], &[]).is_err());
+        assert!(matches!(
+            initial_search_mode(" ", Some(&shape), None, &[], &[], &[]),
+            Ok(SearchMode::Browse)
+        ));
+        assert!(matches!(
+            initial_search_mode("", None, None, &["poi"], &[], &[]),
+            Ok(SearchMode::Browse)
+        ));
+        assert!(matches!(
+            initial_search_mode("gare", None, None, &[], &[], &[]),
+            Ok(SearchMode::Text(_))
+        ));
+        // the places inside an admin can be browsed
+        assert!(matches!(
+            initial_search_mode("", None, Some("admin:fr:75056"), &[], &[], &[]),
+            Ok(SearchMode::Browse)
+        ));
+    }
+
+    #[test]
+    fn places_within_an_admin_are_filtered_by_their_admins() {
+        let query = Query::build_match_all().build();
+        assert_eq!(
+            serde_json::to_value(build_within_filter(query.clone(), None)).unwrap(),
+            serde_json::to_value(&query).unwrap()
+        );
+        let filtered =
+            serde_json::to_value(build_within_filter(query, Some("admin:fr:75056"))).unwrap();
+        assert_eq!(
+            filtered["bool"]["filter"]["bool"]["should"],
+            serde_json::json!([
+                { "term": { "administrative_regions.id": "admin:fr:75056" } },
+                { "term": { "street.administrative_regions.id": "admin:fr:75056" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn excluded_places_are_must_not_clauses() {
+        let query = Query::build_match_all().build();
+        assert_eq!(
+            serde_json::to_value(build_exclusions_filter(
+                query.clone(),
+                &Exclusions::default()
+            ))
+            .unwrap(),
+            serde_json::to_value(&query).unwrap()
+        );
+        let exclusions = Exclusions {
+            ids: vec!["admin:fr:75101"],
+            zone_types: vec!["city_district", "suburb"],
+            datasets: vec!["test"],
+        };
+        let filtered = serde_json::to_value(build_exclusions_filter(query, &exclusions)).unwrap();
+        let excluded = filtered["bool"]["must_not"].as_array().unwrap();
+        assert_eq!(excluded.len(), 4);
+        assert!(excluded[0].to_string().contains("admin:fr:75101"));
+        // the places of the excluded zones are not concerned, they have no zone type
+        assert!(excluded[1].to_string().contains("city_district"));
+        assert!(excluded[2].to_string().contains("suburb"));
+        // the stops are still selected by the pt datasets
+        assert!(excluded[3]["bool"]["must_not"]
+            .to_string()
+            .contains(r#"{"term":{"_type":"stop"}}"#));
+    }
+
+    #[test]
+    fn partial_postcodes() {
+        assert!(is_postcode_prefix("SW1"));
+        assert!(is_postcode_prefix("sw1a"));
+        assert!(is_postcode_prefix("1011"));
+        assert!(is_postcode_prefix(" 750 "));
+        assert!(!is_postcode_prefix("18"));
+        assert!(!is_postcode_prefix("paris"));
+        assert!(!is_postcode_prefix("SW1A 1AA"));
+        assert!(!is_postcode_prefix("12 rue"));
+    }
+
+    fn make_street(id: &str, name: &str, city: &Arc<Admin>) -> Street {
+        Street {
+            id: id.to_string(),
+            name: name.to_string(),
+            label: format!("{} ({})", name, city.name),
+            administrative_regions: vec![city.clone()],
+            ..Default::default()
+        }
+    }
+
+    fn make_addr(house_number: &str, street: Street, score: f64) -> mimir::Place {
+        mimir::Place::Addr(Addr {
+            id: format!("addr:{}:{}", street.id, house_number),
+            name: format!("{} {}", house_number, street.name),
+            house_number: house_number.to_string(),
+            house_number_numeric: None,
+            house_number_suffix: None,
+            label: format!("{} {}", house_number, street.label),
+            coord: street.coord,
+            approx_coord: None,
+            weight: 0.,
+            zip_codes: vec![],
+            country_codes: vec![],
+            city: None,
+            country: None,
+            distance: None,
+            score: Some(score),
+            old_ids: vec![],
+            flags: Default::default(),
+            context: None,
+            street,
+        })
+    }
+
+    #[test]
+    fn streets_are_merged_into_their_addresses() {
+        let paris = Arc::new(Admin {
+            id: "admin:paris".to_string(),
+            name: "Paris".to_string(),
+            zone_type: Some(cosmogony::ZoneType::City),
+            ..Default::default()
+        });
+        let lyon = Arc::new(Admin {
+            id: "admin:lyon".to_string(),
+            name: "Lyon".to_string(),
+            zone_type: Some(cosmogony::ZoneType::City),
+            ..Default::default()
+        });
+        // the street comes from osm, its address from another source
+        let street = make_street("street:osm:1", "Rue Hector Malot", &paris);
+        let places = vec![
+            mimir::Place::Street(Street {
+                score: Some(10.),
+                ..street.clone()
+            }),
+            mimir::Place::Street(make_street("street:osm:2", "Rue Hector Malot", &lyon)),
+            make_addr(
+                "12",
+                make_street("street:bano:1", "rue hector malot", &paris),
+                5.,
+            ),
+            make_addr(
+                "14",
+                make_street("street:bano:1", "rue hector malot", &paris),
+                4.,
+            ),
+        ];
+        let merged = merge_streets(places);
+        let ids: Vec<_> = merged.iter().map(|place| place.id()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "addr:street:bano:1:12",
+                "addr:street:bano:1:14",
+                // the street of another city is not merged
+                "street:osm:2",
+            ]
+        );
+        assert_eq!(merged[0].score(), Some(10.));
+    }
+
+    #[test]
+    fn streets_without_address_are_kept() {
+        let paris = Arc::new(Admin {
+            id: "admin:paris".to_string(),
+            zone_type: Some(cosmogony::ZoneType::City),
+            ..Default::default()
+        });
+        let places = vec![
+            mimir::Place::Street(make_street("street:1", "Rue Hector Malot", &paris)),
+            make_addr("12", make_street("street:2", "Rue de Bercy", &paris), 5.),
+        ];
+        let merged = merge_streets(places);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].id(), "street:1");
+    }
+
+    #[test]
+    fn places_are_collapsed_by_street() {
+        let paris = Arc::new(Admin {
+            id: "admin:paris".to_string(),
+            name: "Paris".to_string(),
+            zone_type: Some(cosmogony::ZoneType::City),
+            ..Default::default()
+        });
+        let lyon = Arc::new(Admin {
+            id: "admin:lyon".to_string(),
+            name: "Lyon".to_string(),
+            zone_type: Some(cosmogony::ZoneType::City),
+            ..Default::default()
+        });
+        let places = vec![
+            make_addr("12", make_street("street:1", "Rue de la Gare", &paris), 5.),
+            make_addr("14", make_street("street:1", "Rue de la Gare", &paris), 4.),
+            make_addr("3", make_street("street:2", "Rue de la Gare", &lyon), 3.),
+            mimir::Place::Street(make_street("street:2", "Rue de la Gare", &lyon)),
+        ];
+        let collapsed = collapse(places, CollapseField::Street);
+        let ids: Vec<_> = collapsed.iter().map(|place| place.id()).collect();
+        // the street of the same name in another city is kept
+        assert_eq!(ids, vec!["addr:street:1:12", "addr:street:2:3"]);
+    }
+
+    #[test]
+    fn fuzzy_search_matches_the_words_with_typos() {
+        let condition = serde_json::to_value(build_fuzzy_words_condition(
+            "rue du louvre",
+            &FuzzyQuery::default(),
+        ))
+        .unwrap();
+        // a clause by word, instead of the ngrams of the label
+        assert_eq!(condition["bool"]["should"].as_array().unwrap().len(), 3);
+        assert!(!condition["bool"]["minimum_should_match"].is_null());
+        assert!(!condition.to_string().contains("ngram"));
+    }
+
+    #[test]
+    fn clusters_are_filtered_by_the_focus_bbox() {
+        let query = Query::build_match_all().build();
+        let bbox = Rect::new((2.6, 48.52), (2.75, 48.6));
+        let filtered = serde_json::to_value(build_focus_bbox_filter(query, Some(bbox))).unwrap();
+        assert_eq!(
+            filtered["bool"]["must"],
+            serde_json::json!({ "match_all": {} })
+        );
+        assert!(filtered["bool"]["filter"]["geo_bounding_box"]["coord"].is_object());
+        let query = Query::build_match_all().build();
+        assert_eq!(
+            serde_json::to_value(build_focus_bbox_filter(query, None)).unwrap(),
+            serde_json::json!({ "match_all": {} })
+        );
+    }
+
+    #[test]
+    fn cluster_precision_depends_on_the_width_of_the_map() {
+        assert_eq!(cluster_precision(360.), 1);
+        assert_eq!(cluster_precision(zoom_width(1)), 2);
+        assert_eq!(cluster_precision(zoom_width(11)), 6);
+        // a street
+        assert_eq!(cluster_precision(0.001), 9);
+        assert_eq!(cluster_precision(0.), MAX_GEOHASH_PRECISION);
+    }
+
+    #[test]
+    fn single_places_are_returned_inline() {
+        let result = serde_json::json!({
+            "aggregations": { "clusters": { "buckets": [
+                {
+                    "key": "u09uh7",
+                    "doc_count": 3,
+                    "centroid": { "location": { "lat": 48.5368, "lon": 2.658 } },
+                    "bbox": { "bounds": {
+                        "top_left": { "lat": 48.5369, "lon": 2.6579 },
+                        "bottom_right": { "lat": 48.5367, "lon": 2.6581 }
+                    } },
+                    "place": { "hits": { "hits": [] } }
+                },
+                {
+                    "key": "u09gv7",
+                    "doc_count": 1,
+                    "centroid": { "location": { "lat": 48.4896, "lon": 2.6937 } },
+                    "bbox": { "bounds": {
+                        "top_left": { "lat": 48.4896, "lon": 2.6937 },
+                        "bottom_right": { "lat": 48.4896, "lon": 2.6937 }
+                    } },
+                    "place": { "hits": { "hits": [{
+                        "_type": "poi",
+                        "_source": { "id": "poi:1", "label": "Mairie", "name": "Mairie",
+                            "coord": { "lat": 48.4896, "lon": 2.6937 }, "weight": 0.,
+                            "administrative_regions": [], "zip_codes": [], "poi_type": { "id": "amenity:townhall", "name": "Mairie" },
+                            "properties": [] }
+                    }] } }
+                }
+            ] } }
+        });
+        let clusters = read_clusters(result);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].count, 3);
+        assert!(clusters[0].place.is_none());
+        let bbox = clusters[0].bbox.unwrap();
+        assert_eq!((bbox.min().x, bbox.min().y), (2.6579, 48.5367));
+        assert_eq!((bbox.max().x, bbox.max().y), (2.6581, 48.5369));
+        assert_eq!(clusters[1].geohash, "u09gv7");
+        assert_eq!(clusters[1].place.as_ref().map(|p| p.id()), Some("poi:1"));
+    }
+
+    #[test]
+    fn cancelled_searches_are_not_waited_for() {
+        // a backend accepting the connections, but never answering
+        let backend = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let rubber = Rubber::new(&format!("http://{}", backend.local_addr().unwrap()));
+        let deadlines = Deadlines {
+            enabled: true,
+            overall: 60_000,
+            per_type: BTreeMap::new(),
+        };
+        let cancellation = Cancellation::default();
+        let guard = cancellation.guard();
+        // the client disconnects while ES is searching
+        let client = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+        });
+
+        let start = Instant::now();
+        let (places, dropped) = search_by_type(
+            &rubber,
+            Query::build_match_all().build(),
+            None,
+            &["munin_addr", "munin_street"],
+            0,
+            10,
+            None,
+            false,
+            false,
+            None,
+            &deadlines,
+            &[],
+            &cancellation,
+        )
+        .unwrap();
+        client.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(places.is_empty());
+        assert_eq!(dropped, vec!["addr", "street"]);
+    }
+
+    #[test]
+    fn in_flight_searches_of_cancelled_requests_are_aborted() {
+        use std::io::Read;
+
+        // a backend never answering, telling when the client closes its connections
+        let backend = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", backend.local_addr().unwrap());
+        let (closed_sender, closed) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in backend.incoming() {
+                let mut stream = stream.unwrap();
+                let closed_sender = closed_sender.clone();
+                thread::spawn(move || {
+                    let mut buf = [0; 1024];
+                    while stream.read(&mut buf).map(|n| n > 0).unwrap_or(false) {}
+                    let _ = closed_sender.send(Instant::now());
+                });
+            }
+        });
+        let deadlines = Deadlines {
+            enabled: true,
+            overall: 60_000,
+            per_type: BTreeMap::new(),
+        };
+        let cancellation = Cancellation::default();
+        let rubber = Rubber::new(&url).with_abort(cancellation.flag());
+        let guard = cancellation.guard();
+        // the client disconnects while ES is searching
+        let client = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let cancelled_at = Instant::now();
+            drop(guard);
+            cancelled_at
+        });
+
+        // the searches are not waited for (see `cancelled_searches_are_not_waited_for`),
+        // they can also end with the error of their abort
+        let _ = search_by_type(
+            &rubber,
+            Query::build_match_all().build(),
+            None,
+            &["munin_addr", "munin_street"],
+            0,
+            10,
+            None,
+            false,
+            false,
+            None,
+            &deadlines,
+            &[],
+            &cancellation,
+        );
+        let cancelled_at = client.join().unwrap();
+        // the connections of both searches are closed, the backend has never answered
+        for _ in 0..2 {
+            let closed_at = closed
+                .recv_timeout(Duration::from_secs(5))
+                .expect("the search in progress has not been aborted");
+            assert!(closed_at >= cancelled_at);
+        }
+    }
+
+    #[test]
+    fn places_of_several_searches_are_tie_broken() {
+        let settings =
+            QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        let admin = |id: &str, weight: f64, score: f64| {
+            mimir::Place::Admin(mimir::Admin {
+                id: id.to_string(),
+                weight,
+                score: Some(score),
+                ..Default::default()
+            })
+        };
+        let places = vec![
+            admin("admin:b", 0.1, 2.),
+            admin("admin:c", 0.5, 2.),
+            admin("admin:a", 0.1, 2.),
+            admin("admin:d", 1., 3.),
+        ];
+        let ids = |places: Vec<mimir::Place>| {
+            places
+                .iter()
+                .map(|place| place.id().to_string())
+                .collect::<Vec<_>>()
+        };
+        // by score, then by weight, then by uid
+        assert_eq!(
+            ids(sort_by_score(places.clone(), &settings.tie_breakers)),
+            vec!["admin:d", "admin:c", "admin:a", "admin:b"]
+        );
+        // without tie breakers, only the score is sorted
+        assert_eq!(
+            ids(sort_by_score(places, &[])),
+            vec!["admin:d", "admin:b", "admin:c", "admin:a"]
+        );
+    }
+
+    #[test]
+    fn house_numbers_are_tie_broken_as_numbers() {
+        let settings =
+            QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        let sort = serde_json::to_value(build_score_sort(&settings.tie_breakers)).unwrap();
+        assert_eq!(
+            sort,
+            serde_json::json!([
+                { "_score": { "order": "desc" } },
+                { "weight": { "order": "desc" } },
+                { "house_number_numeric": { "order": "asc", "unmapped_type": "integer" } },
+                { "_uid": { "order": "asc" } },
+            ])
+        );
+    }
+
+    // the names of the named clauses of a query
+    fn clause_names(query: &serde_json::Value, names: &mut BTreeSet<String>) {
+        match query {
+            serde_json::Value::Object(fields) => {
+                if let Some(name) = fields.get("_name").and_then(|n| n.as_str()) {
+                    names.insert(name.to_string());
+                }
+                fields.values().for_each(|v| clause_names(v, names));
+            }
+            serde_json::Value::Array(values) => values.iter().for_each(|v| clause_names(v, names)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn house_numbers_are_only_required_when_strict() {
+        let condition =
+            |q, strict| serde_json::to_value(&build_house_number_condition(q, strict)).unwrap();
+        let strict = condition("24 rue de la paix", true);
+        assert_eq!(
+            strict["bool"]["should"][2],
+            serde_json::json!({ "terms": { "house_number_numeric": [24] } })
+        );
+        assert_eq!(
+            condition("24 rue de la paix", false),
+            serde_json::json!({ "match_all": {} })
+        );
+        // without house number, the condition does not depend on the strictness
+        assert_eq!(
+            condition("rue de la paix", false),
+            condition("rue de la paix", true)
+        );
+        assert_eq!(condition("paix", false), condition("paix", true));
+    }
+
+    #[test]
+    fn places_without_house_number_match_the_other_words() {
+        let condition = |q| serde_json::to_value(&build_prefix_words_condition(q)).unwrap();
+        let with_number = condition("24 rue hector malot");
+        let should = with_number["bool"]["should"].as_array().unwrap();
+        assert_eq!(should.len(), 3);
+        // the streets match all the words but the house number
+        let without_number = should[2].to_string();
+        assert!(without_number.contains("\"rue hector malot\""));
+        assert!(without_number.contains("house_number"));
+        // without house number in the query, all the places match all the words
+        let should = condition("rue hector malot")["bool"]["should"].clone();
+        assert_eq!(should.as_array().unwrap().len(), 2);
+        let should = condition("24")["bool"]["should"].clone();
+        assert_eq!(should.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn only_numeric_words_are_house_numbers() {
+        assert_eq!(detect_house_numbers("12 rue de la paix"), vec![12]);
+        assert_eq!(detect_house_numbers("12bis, rue de la paix"), vec![12]);
+        assert_eq!(detect_house_numbers("12 b rue de la paix"), vec![12]);
+        assert_eq!(detect_house_numbers("3-5 rue de la paix"), vec![3]);
+        assert!(detect_house_numbers("rue du 1er mai").is_empty());
+        assert!(detect_house_numbers("garage 4x4 paris").is_empty());
+        assert!(detect_house_numbers("route A1").is_empty());
+    }
+
+    #[test]
+    fn search_templates_are_given_the_request() {
+        let settings =
+            QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        let filter = build_datasets_filter(Query::build_match_all().build(), &["fr"]);
+        let params = |doc_type, coord: Option<&Coord>| {
+            template_params(
+                doc_type,
+                "20 rue hector malot",
+                MatchType::Fuzzy,
+                10,
+                coord,
+                &["fr"],
+                &filter,
+                &settings,
+            )
+            .unwrap()
+        };
+        let addr_params = params("addr", Some(&Coord::new(2.6937, 48.4896)));
+        assert_eq!(addr_params["q"], "20 rue hector malot");
+        assert_eq!(addr_params["match_type"], "fuzzy");
+        assert_eq!(addr_params["size"], 10);
+        assert_eq!(addr_params["lat"], 48.4896);
+        assert_eq!(addr_params["lon"], 2.6937);
+        assert_eq!(addr_params["langs"], serde_json::json!(["fr"]));
+        assert_eq!(
+            addr_params["boosts"]["type"],
+            settings.type_query.boosts.address
+        );
+        // the filter is inserted as is in the template
+        let rendered_filter: serde_json::Value =
+            serde_json::from_str(addr_params["filter"].as_str().unwrap()).unwrap();
+        assert_eq!(rendered_filter, serde_json::to_value(&filter).unwrap());
+
+        let poi_params = params("poi", None);
+        assert!(poi_params.get("lat").is_none());
+        assert_eq!(poi_params["boosts"]["type"], settings.type_query.boosts.poi);
+    }
+
+    #[test]
+    fn recent_places_of_a_dataset_are_boosted() {
+        let recency = Recency {
+            enabled: true,
+            half_life: 2.,
+            weight: 0.4,
+        };
+        let now = 1_600_000_000_000;
+        let query =
+            serde_json::to_value(build_recency_with_boost("live_pois", &recency, now)).unwrap();
+        let function_score = &query["function_score"];
+        assert_eq!(
+            function_score["query"]["bool"]["must"],
+            serde_json::json!({ "term": { "dataset": "live_pois" } })
+        );
+        assert_eq!(
+            function_score["query"]["bool"]["filter"],
+            serde_json::json!({ "exists": { "field": "updated_at" } })
+        );
+        let decay = &function_score["functions"][0]["exp"]["updated_at"];
+        assert_eq!(decay["origin"], now);
+        assert_eq!(decay["scale"], 7_200_000);
+        assert_eq!(decay["decay"], 0.5);
+        assert_eq!(function_score["functions"][1]["weight"], 0.4);
+        assert_eq!(match_reason(&query), Some("recency"));
+    }
+
+    #[test]
+    fn reordered_words_are_searched_in_any_order() {
+        let mut settings =
+            QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        let clauses = |settings: &QuerySettings| {
+            let query = build_query(
+                "lyon part dieu gare",
+                MatchType::Prefix,
+                true,
+                None,
+                None,
+                &[],
+                None,
+                &[],
+                false,
+                "2021-01-01",
+                &["fr"],
+                &[],
+                &[],
+                settings,
+            );
+            let query = serde_json::to_value(&query).unwrap();
+            query["bool"]["must"][1]["bool"]["should"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|clause| clause["match"].as_object())
+                .flat_map(|fields| fields.keys().cloned())
+                .collect::<Vec<_>>()
+        };
+        let fields = clauses(&settings);
+        assert!(fields.contains(&"label".to_string()));
+        assert!(fields.contains(&"full_label.shingle".to_string()));
+
+        settings.query.word_order_tolerance.enabled = false;
+        let fields = clauses(&settings);
+        assert!(!fields.contains(&"label".to_string()));
+        assert!(!fields.contains(&"full_label.shingle".to_string()));
+    }
+
+    #[test]
+    fn proximity_boosted_places_give_their_match_reasons() {
+        let settings =
+            QuerySettings::new(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        let query = build_query(
+            "20 rue hector malot",
+            MatchType::Prefix,
+            true,
+            Some(Coord::new(2.6937, 48.4896)),
+            None,
+            &[],
+            None,
+            &[],
+            false,
+            "2021-01-01",
+            &["fr"],
+            &[],
+            &[],
+            &settings,
+        );
+        let mut query = serde_json::to_value(&query).unwrap();
+        name_match_reasons(&mut query);
+        let mut names = BTreeSet::new();
+        clause_names(&query, &mut names);
+        assert_eq!(
+            names.iter().map(String::as_str).collect::<Vec<_>>(),
+            vec![
+                "alt_name",
+                "house_number",
+                "label",
+                "name",
+                "prefix",
+                "proximity",
+                "word_order",
+                "zip_code"
+            ]
+        );
+
+        // ES gives the names of the matched clauses in any order, once per match
+        let result = serde_json::json!({ "hits": { "hits": [{
+            "_type": "poi",
+            "_score": 3.5,
+            "_source": { "id": "poi:1", "label": "Mairie", "name": "Mairie",
+                "coord": { "lat": 48.4896, "lon": 2.6937 }, "weight": 0.,
+                "administrative_regions": [], "zip_codes": [], "poi_type": { "id": "amenity:townhall", "name": "Mairie" },
+                "properties": [] },
+            "matched_queries": ["proximity", "prefix", "name", "proximity"]
+        }] } });
+        let places = read_raw_places(result, Some(&Coord::new(2.6937, 48.4896))).unwrap();
+        assert_eq!(places.len(), 1);
+        assert_eq!(places[0].score(), Some(3.5));
+        assert_eq!(places[0].distance(), Some(0));
+        assert_eq!(
+            places[0].context().unwrap().match_reasons,
+            vec!["name", "prefix", "proximity"]
+        );
     }
 }
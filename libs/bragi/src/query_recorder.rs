@@ -0,0 +1,236 @@
+// Recording of the autocomplete queries, to replay them with other settings before changing them
+// (see the `replay` command of `mimir_admin`).
+//
+// When `[query_recorder]` is set in the settings, the parameters of each autocomplete made with GET
+// and the ids of its first places are appended as a line of NDJSON to a file. Once the file exceeds
+// `max_size` bytes, it is renamed with a `.1` suffix (replacing the previous one) and a new file is
+// started. The parameters identifying the users are never recorded, and the coordinates are rounded
+// to `coord_precision` decimals if set.
+
+use crate::query_settings::QueryRecorderSettings;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use slog_scope::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref RECORDED_QUERIES_COUNTER: prometheus::Counter = prometheus::register_counter!(
+        "bragi_recorded_queries_total",
+        "Total number of autocomplete queries recorded."
+    )
+    .unwrap();
+}
+
+// the parameters identifying the users, or not changing the places found
+const UNRECORDED_PARAMS: [&str; 6] = [
+    "apikey",
+    "request_id",
+    "experiment",
+    "preference",
+    "timeout",
+    "_debug",
+];
+
+// the parameters made of coordinates, rounded if `coord_precision` is set
+const COORD_PARAMS: [&str; 3] = ["lat", "lon", "focus_bbox"];
+
+/// An autocomplete query and the ids of its first places, a line of the recordings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedQuery {
+    /// UTC date of the query, formatted as RFC 3339
+    pub date: String,
+    /// Query string of the request, without the parameters identifying the user
+    pub query: String,
+    /// Ids of the first places found, in their order
+    pub ids: Vec<String>,
+}
+
+struct Recording {
+    file: File,
+    size: u64,
+}
+
+/// Appends the autocomplete queries to the file of `[query_recorder]`
+pub struct QueryRecorder {
+    settings: QueryRecorderSettings,
+    recording: Mutex<Recording>,
+}
+
+impl std::fmt::Debug for QueryRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryRecorder")
+            .field("settings", &self.settings)
+            .finish()
+    }
+}
+
+fn open_recording(path: &Path) -> Result<Recording, String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("impossible to open `{}`: {}", path.display(), e))?;
+    let size = file
+        .metadata()
+        .map_err(|e| format!("impossible to read `{}`: {}", path.display(), e))?
+        .len();
+    Ok(Recording { file, size })
+}
+
+/// Rounds the coordinates of a parameter to `precision` decimals, the bbox being a list of them
+fn round_coords(value: &str, precision: usize) -> Option<String> {
+    let coords = value
+        .replace("%2C", ",")
+        .replace("%2c", ",")
+        .split(',')
+        .map(|coord| {
+            coord
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .map(|coord| format!("{:.*}", precision, coord))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(coords.join(","))
+}
+
+/// The query string without the parameters identifying the user,
+/// and with its coordinates rounded to `coord_precision` decimals
+pub fn sanitize_query(query_string: &str, coord_precision: Option<u8>) -> String {
+    query_string
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .filter_map(|param| {
+            let (name, value) = match param.find('=') {
+                Some(i) => (&param[..i], &param[i + 1..]),
+                None => (param, ""),
+            };
+            if UNRECORDED_PARAMS.contains(&name) {
+                return None;
+            }
+            match coord_precision {
+                Some(precision) if COORD_PARAMS.contains(&name) => {
+                    // the coordinates which cannot be rounded are not kept as they are
+                    let value = round_coords(value, usize::from(precision))?;
+                    Some(format!("{}={}", name, value))
+                }
+                _ => Some(param.to_string()),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+impl QueryRecorder {
+    pub fn new(settings: &QueryRecorderSettings) -> Result<Self, String> {
+        Ok(QueryRecorder {
+            settings: settings.clone(),
+            recording: Mutex::new(open_recording(&settings.file)?),
+        })
+    }
+
+    /// Records the query of the request and the ids of its first places.
+    ///
+    /// The recording never fails the request, its errors are only logged.
+    pub fn record<'a>(&self, query_string: &str, ids: impl IntoIterator<Item = &'a str>) {
+        let query = RecordedQuery {
+            date: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            query: sanitize_query(query_string, self.settings.coord_precision),
+            ids: ids
+                .into_iter()
+                .take(self.settings.top_n)
+                .map(str::to_string)
+                .collect(),
+        };
+        let res = serde_json::to_string(&query)
+            .map_err(|e| e.to_string())
+            .and_then(|line| self.append(format!("{}\n", line).as_bytes()));
+        match res {
+            Ok(()) => RECORDED_QUERIES_COUNTER.inc(),
+            Err(e) => warn!("impossible to record the query: {}", e),
+        }
+    }
+
+    fn append(&self, line: &[u8]) -> Result<(), String> {
+        let mut recording = self
+            .recording
+            .lock()
+            .map_err(|_| "the recording has been poisoned".to_string())?;
+        if recording.size > 0 && recording.size + line.len() as u64 > self.settings.max_size {
+            let path = &self.settings.file;
+            let mut rotated = path.clone().into_os_string();
+            rotated.push(".1");
+            fs::rename(path, &rotated)
+                .map_err(|e| format!("impossible to rotate `{}`: {}", path.display(), e))?;
+            *recording = open_recording(path)?;
+        }
+        recording
+            .file
+            .write_all(line)
+            .map_err(|e| format!("impossible to write the query: {}", e))?;
+        recording.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn users_are_not_recorded() {
+        let query = "q=rue%20de%20la%20paix&lat=48.869183&lon=2.331523&request_id=bob\
+                     &pt_dataset%5B%5D=fr&focus_bbox=2.314%2C48.853%2C2.351%2C48.882&_debug=true";
+        assert_eq!(
+            sanitize_query(query, Some(2)),
+            "q=rue%20de%20la%20paix&lat=48.87&lon=2.33&pt_dataset%5B%5D=fr\
+             &focus_bbox=2.31,48.85,2.35,48.88"
+        );
+        assert_eq!(
+            sanitize_query(query, None),
+            "q=rue%20de%20la%20paix&lat=48.869183&lon=2.331523&pt_dataset%5B%5D=fr\
+             &focus_bbox=2.314%2C48.853%2C2.351%2C48.882"
+        );
+        // the coordinates which cannot be rounded are not recorded
+        assert_eq!(sanitize_query("q=rue&lat=north", Some(2)), "q=rue");
+        // nor the api keys, which the replay would send to another bragi
+        assert_eq!(sanitize_query("apikey=secret&q=rue&apikey=", None), "q=rue");
+    }
+
+    #[test]
+    fn recordings_are_rotated() {
+        let dir = std::env::temp_dir().join("bragi_query_recorder_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let settings = QueryRecorderSettings {
+            file: dir.join("queries.ndjson"),
+            max_size: 200,
+            top_n: 2,
+            coord_precision: None,
+        };
+        let recorder = QueryRecorder::new(&settings).unwrap();
+        for i in 0..3 {
+            let query = format!("q=gare&limit={}", i);
+            recorder.record(&query, vec!["stop_area:1", "stop_area:2", "stop_area:3"]);
+        }
+
+        let read = |path: &Path| -> Vec<RecordedQuery> {
+            BufReader::new(File::open(path).unwrap())
+                .lines()
+                .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                .collect()
+        };
+        let recorded = read(&settings.file);
+        let rotated = read(&dir.join("queries.ndjson.1"));
+        assert_eq!(rotated.len() + recorded.len(), 3);
+        assert!(!rotated.is_empty());
+        let last = recorded.last().unwrap();
+        assert_eq!(last.query, "q=gare&limit=2");
+        assert_eq!(last.ids, vec!["stop_area:1", "stop_area:2"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,11 @@
-use serde::Deserialize;
+use mimir::rubber::TransportSettings;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Types {
     pub address: f64,
     pub admin: f64,
@@ -15,12 +20,27 @@ pub struct TypeQueryBoosts {
     pub boosts: Types,
 }
 
+fn default_zip_codes_prefix() -> f64 {
+    0.5
+}
+
+fn default_alt_names() -> f64 {
+    1.5
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct StringQueryBoosts {
     pub name: f64,
+    /// Boost of the alternative names of the streets and the pois (old names, translations, ...),
+    /// lower than the one of the name
+    #[serde(default = "default_alt_names")]
+    pub alt_names: f64,
     pub label: f64,
     pub label_prefix: f64,
     pub zip_codes: f64,
+    /// Boost of the zip codes starting with a query looking like a partial postcode ("SW1", "1011")
+    #[serde(default = "default_zip_codes_prefix")]
+    pub zip_codes_prefix: f64,
     pub house_number: f64,
     pub label_ngram_with_coord: f64,
     pub label_ngram: f64,
@@ -30,6 +50,11 @@ pub struct StringQueryBoosts {
 pub struct StringQuery {
     pub global: f64,
     pub boosts: StringQueryBoosts,
+    /// Boosts, by type of place, of the names matching exactly the query
+    /// (lowercased and without diacritics). Not boosted if not given, for the settings files
+    /// written before it.
+    #[serde(default)]
+    pub exact_name: Types,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize)]
@@ -37,6 +62,17 @@ pub struct Proximity {
     pub weight: f64,
     pub weight_fuzzy: f64,
     pub gaussian: Gaussian,
+    /// Focus point of the requests without coordinates
+    #[serde(default)]
+    pub default_coord: Option<DefaultCoord>,
+}
+
+/// Focus point (eg the centroid of the country of the deployment) used for the proximity
+/// when the request does not give its coordinates
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct DefaultCoord {
+    pub lon: f64,
+    pub lat: f64,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize)]
@@ -63,10 +99,516 @@ pub struct Weights {
     pub types: Types,
 }
 
+/// Boost of the places inside the `focus_bbox` of the request
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FocusBbox {
+    /// Factor multiplying the score of the places inside the box
+    pub boost: f64,
+}
+
+impl Default for FocusBbox {
+    fn default() -> Self {
+        FocusBbox { boost: 2.0 }
+    }
+}
+
+fn default_recency_enabled() -> bool {
+    true
+}
+
+fn default_recency_weight() -> f64 {
+    0.4
+}
+
+/// Boost of the most recently updated places of a dataset (like live pois updated often),
+/// so that the fresher places rank higher among similar matches
+#[derive(Clone, Debug, Deserialize)]
+pub struct Recency {
+    /// The boost can be disabled without removing its settings
+    #[serde(default = "default_recency_enabled")]
+    pub enabled: bool,
+    /// Age (in hours) of the places getting half of the boost of the places updated just now
+    #[serde(deserialize_with = "deserialize_half_life")]
+    pub half_life: f64,
+    /// Boost of the places updated just now
+    #[serde(default = "default_recency_weight")]
+    pub weight: f64,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ImportanceQueryBoosts {
     pub proximity: Proximity,
     pub weights: Weights,
+    #[serde(default)]
+    pub focus_bbox: FocusBbox,
+    /// Recency boost of the places by dataset
+    #[serde(default)]
+    pub recency: BTreeMap<String, Recency>,
+}
+
+/// Limits on the number of results sharing the same street or the same city,
+/// so that a few streets do not crowd out all the other results
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Diversity {
+    pub max_per_street: Option<usize>,
+    pub max_per_admin: Option<usize>,
+}
+
+impl Diversity {
+    pub fn is_enabled(&self) -> bool {
+        self.max_per_street.is_some() || self.max_per_admin.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Secondary sort of the results having the same score,
+/// so that their order (and thus the pagination) is stable
+#[derive(Clone, Debug, Deserialize)]
+pub struct TieBreaker {
+    pub field: String,
+    pub order: SortOrder,
+    /// Type of the field, needed if some indexes do not have it (like `house_number_numeric`,
+    /// only in the addresses): their places are sorted as missing the field
+    #[serde(default)]
+    pub unmapped_type: Option<String>,
+}
+
+/// Thresholds of the circuit breaker on the ES queries
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    /// Number of consecutive ES failures opening the circuit, 0 disables it
+    pub max_failures: u32,
+    /// Duration (in ms) during which the requests are rejected once the circuit is open
+    pub cooldown: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        CircuitBreakerSettings {
+            max_failures: 5,
+            cooldown: 10_000,
+        }
+    }
+}
+
+/// Keep-alive and idle timeout (in ms) of the connections to ES
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Transport {
+    /// Interval of the TCP keep-alive probes of the connections, 0 disables them
+    pub keep_alive: u64,
+    /// Duration after which an idle connection of the pool is closed.
+    /// It should be shorter than the idle timeout of the load balancers in front of ES.
+    pub idle_timeout: u64,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport {
+            keep_alive: 30_000,
+            idle_timeout: 50_000,
+        }
+    }
+}
+
+impl From<&Transport> for TransportSettings {
+    fn from(transport: &Transport) -> Self {
+        TransportSettings {
+            keep_alive: Some(transport.keep_alive)
+                .filter(|keep_alive| *keep_alive > 0)
+                .map(Duration::from_millis),
+            idle_timeout: Some(Duration::from_millis(transport.idle_timeout)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Elasticsearch {
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+    #[serde(default)]
+    pub transport: Transport,
+}
+
+/// Known query expected to return a known document,
+/// to check that the search works (and not only that ES is reachable)
+#[derive(Clone, Debug, Deserialize)]
+pub struct CanarySettings {
+    pub query: String,
+    pub expected_id: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ReadinessSettings {
+    pub canary: Option<CanarySettings>,
+}
+
+/// The endpoints which can be disabled by `service.endpoints`,
+/// the entry point and the status are always mounted
+pub const ENDPOINTS: [&str; 9] = [
+    "autocomplete",
+    "features",
+    "nearby",
+    "ready",
+    "refresh",
+    "reverse",
+    "reverse_batch",
+    "settings",
+    "suggest",
+];
+
+/// Types of places expected in ES, whose indexes are checked by /status and /ready,
+/// and the endpoints mounted
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServiceSettings {
+    /// Types (`addr`, `street`, `admin`, `poi` or `stop`) whose absence degrades the readiness.
+    /// The other types may have no index (like the stops of a deployment without NTFS).
+    pub required_types: Vec<String>,
+    /// Age (in hours) beyond which the most recent index of a type is stale, never stale if not set
+    pub max_index_age: Option<u64>,
+    /// The endpoints (of `ENDPOINTS`) disabled with `false` are not mounted, they give a 404
+    pub endpoints: BTreeMap<String, bool>,
+    pub memory: MemorySettings,
+}
+
+/// Memory of the in-process caches (the known datasets, the profiles of the api keys)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MemorySettings {
+    /// Bytes shared by all the caches, their least recently used entries are evicted beyond it
+    pub cache_budget: usize,
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        MemorySettings {
+            cache_budget: crate::memory::DEFAULT_CACHE_BUDGET,
+        }
+    }
+}
+
+impl ServiceSettings {
+    pub fn is_enabled(&self, endpoint: &str) -> bool {
+        self.endpoints.get(endpoint).copied().unwrap_or(true)
+    }
+
+    pub fn enabled_endpoints(&self) -> Vec<&'static str> {
+        ENDPOINTS
+            .iter()
+            .copied()
+            .filter(|endpoint| self.is_enabled(endpoint))
+            .collect()
+    }
+
+    /// Rejects the unknown endpoints, a typo would leave an endpoint enabled
+    pub fn check_endpoints(&self) -> Result<(), String> {
+        match self
+            .endpoints
+            .keys()
+            .find(|endpoint| !ENDPOINTS.contains(&endpoint.as_str()))
+        {
+            Some(endpoint) => Err(format!(
+                "unknown endpoint `{}` in service.endpoints, the endpoints are {}",
+                endpoint,
+                ENDPOINTS.join(", ")
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for ServiceSettings {
+    fn default() -> Self {
+        ServiceSettings {
+            required_types: vec![
+                "admin".to_string(),
+                "street".to_string(),
+                "addr".to_string(),
+            ],
+            max_index_age: None,
+            endpoints: BTreeMap::new(),
+            memory: MemorySettings::default(),
+        }
+    }
+}
+
+/// Latency budgets (in ms) of the autocomplete queries.
+///
+/// When enabled, a query with a query string is split into one search per document type,
+/// run concurrently: the places of the types whose search does not end within their budget
+/// are dropped, and the response lists these types in `partial`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Deadlines {
+    pub enabled: bool,
+    /// Budget of the whole query
+    pub overall: u64,
+    /// Budget of the search of a document type (`addr`, `street`, `admin`, `poi` or `stop`),
+    /// the overall budget is used for the types not given
+    pub per_type: BTreeMap<String, u64>,
+}
+
+impl Default for Deadlines {
+    fn default() -> Self {
+        Deadlines {
+            enabled: false,
+            overall: 1000,
+            per_type: BTreeMap::new(),
+        }
+    }
+}
+
+impl Deadlines {
+    pub fn overall_budget(&self) -> Duration {
+        Duration::from_millis(self.overall)
+    }
+
+    /// Budget of the search of a document type, never more than the overall budget
+    pub fn type_budget(&self, doc_type: &str) -> Duration {
+        let budget = self.per_type.get(doc_type).map_or(self.overall, |b| *b);
+        Duration::from_millis(budget.min(self.overall))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct QueryLimits {
+    pub deadlines: Deadlines,
+    /// Languages chosen from the `Accept-Language` header when the request has no `lang`
+    pub supported_langs: Vec<String>,
+    /// Maximum number of results of a request, the bigger limits are clamped to it
+    pub max_limit: u64,
+    /// Maximum number of results of a request with their geometries (the boundaries are heavy)
+    pub max_limit_with_geometry: u64,
+    /// Tolerance (in degrees) of the simplification of the boundaries given with their geometry,
+    /// the boundaries are given as stored if it is 0
+    pub geometry_tolerance: f64,
+    /// Maximum number of characters of the query, the longer queries are truncated (with a warning)
+    pub max_query_length: usize,
+    pub address: AddressQuery,
+    pub word_order_tolerance: WordOrderTolerance,
+    /// Id of the search template stored in ES by document type (`addr`, `street`, `admin`,
+    /// `poi` or `stop`), the text queries of these types are rendered by ES from their template
+    /// instead of being built by bragi
+    pub templates: BTreeMap<String, String>,
+    /// Alternative settings of the autocomplete, each one used for a share of the requests
+    pub experiments: Vec<Experiment>,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        QueryLimits {
+            deadlines: Deadlines::default(),
+            supported_langs: vec![],
+            max_limit: 100,
+            max_limit_with_geometry: 10,
+            geometry_tolerance: 0.001,
+            max_query_length: 200,
+            address: AddressQuery::default(),
+            word_order_tolerance: WordOrderTolerance::default(),
+            templates: BTreeMap::new(),
+            experiments: vec![],
+        }
+    }
+}
+
+/// An A/B experiment: the requests of its share of the traffic are searched with its settings
+#[derive(Clone, Debug, Deserialize)]
+pub struct Experiment {
+    /// Name of the experiment, given in the responses and the metrics
+    pub name: String,
+    /// Percentage (between 0 and 100) of the requests searched with the settings of the experiment
+    pub traffic: f64,
+    /// Settings merged into the base settings (like the settings of a run mode)
+    #[serde(default = "empty_settings")]
+    pub settings: toml::Value,
+}
+
+fn empty_settings() -> toml::Value {
+    toml::Value::Table(toml::value::Table::new())
+}
+
+/// Search of the addresses
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AddressQuery {
+    /// When the query has a house number, the addresses must match it ("24 rue de la paix"
+    /// does not find "2 rue de la paix"), the places without house number still match.
+    /// The house number is only boosted if nothing is found this way.
+    pub strict_housenumber: bool,
+}
+
+impl Default for AddressQuery {
+    fn default() -> Self {
+        AddressQuery {
+            strict_housenumber: true,
+        }
+    }
+}
+
+/// Search of the words of the query in any order ("lyon part dieu gare" finds "Gare Lyon Part Dieu").
+///
+/// The words of the query already match whatever their order, this scores the places having
+/// most of them in their label whatever their order, and the pairs of consecutive words
+/// of the query found in the label, so that the places in the order of the query rank higher.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WordOrderTolerance {
+    pub enabled: bool,
+    /// Boost of the places having most of the words of the query in their label, in any order
+    pub boost: f64,
+    /// Boost of the places having the consecutive words of the query in the same order
+    pub in_order_boost: f64,
+}
+
+impl Default for WordOrderTolerance {
+    fn default() -> Self {
+        WordOrderTolerance {
+            enabled: true,
+            boost: 0.3,
+            in_order_boost: 0.3,
+        }
+    }
+}
+
+/// Search of the stops by their lines, for the queries looking like a transit line.
+///
+/// Only the queries made of a mode keyword followed by the line code ("metro 14", "RER A"),
+/// or matching `code_regex`, are searched as lines, so that a bare number remains a house number.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LineQuery {
+    /// Words announcing a line (matched without case)
+    pub mode_keywords: Vec<String>,
+    /// The queries matching this regex are searched as line codes (for example `^T[0-9]+$`)
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub code_regex: Option<Regex>,
+    /// Boost of the stops served by the line
+    pub boost: f64,
+}
+
+impl Default for LineQuery {
+    fn default() -> Self {
+        LineQuery {
+            mode_keywords: vec![],
+            code_regex: None,
+            boost: 10.,
+        }
+    }
+}
+
+/// Limits of the reverse geocoding of the batches of points (`POST /reverse/batch`)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReverseBatch {
+    /// Maximum number of points of a batch
+    pub max_size: usize,
+    /// Number of points of a batch looked up concurrently
+    pub concurrency: usize,
+    /// Distance (in meters) under which a point reuses the result of the point looked up before it,
+    /// when the batch is simplified
+    pub simplify_distance: f64,
+}
+
+impl Default for ReverseBatch {
+    fn default() -> Self {
+        ReverseBatch {
+            max_size: 10_000,
+            concurrency: 4,
+            simplify_distance: 20.,
+        }
+    }
+}
+
+/// Maximum number of edits of the fuzziness, ES does not support more
+pub const MAX_FUZZINESS: u8 = 2;
+
+/// Typos tolerated by the fuzzy search, in each word of the query
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FuzzyQuery {
+    /// Number of edits (0, 1 or 2) tolerated in all the words.
+    /// If not set, it depends on the length of each word.
+    #[serde(deserialize_with = "deserialize_fuzziness")]
+    pub fuzziness: Option<u8>,
+}
+
+/// Authentication of the requests with api keys, disabled if not set
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApiKeysSettings {
+    /// File giving the profile of each key, read again after a SIGHUP
+    pub file: PathBuf,
+}
+
+fn default_recorded_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_recorded_ids() -> usize {
+    10
+}
+
+/// Recording of the autocomplete queries, to replay them with other settings, disabled if not set
+#[derive(Clone, Debug, Deserialize)]
+pub struct QueryRecorderSettings {
+    /// NDJSON file where the queries are appended
+    pub file: PathBuf,
+    /// Size (in bytes) beyond which the file is renamed with a `.1` suffix and a new one started
+    #[serde(default = "default_recorded_size")]
+    pub max_size: u64,
+    /// Number of ids of the first places recorded with each query
+    #[serde(default = "default_recorded_ids")]
+    pub top_n: usize,
+    /// Number of decimals the coordinates of the queries are rounded to, not rounded if not set
+    pub coord_precision: Option<u8>,
+}
+
+// a null half life would give a null scale to ES, failing the queries
+fn deserialize_half_life<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let half_life = f64::deserialize(deserializer)?;
+    if half_life > 0. {
+        Ok(half_life)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "the half life of the recency should be positive, not {}",
+            half_life
+        )))
+    }
+}
+
+// ES fails the queries with more edits
+fn deserialize_fuzziness<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<u8>::deserialize(deserializer)? {
+        Some(fuzziness) if fuzziness > MAX_FUZZINESS => Err(serde::de::Error::custom(format!(
+            "the fuzziness should be 0, 1 or 2, not {}",
+            fuzziness
+        ))),
+        fuzziness => Ok(fuzziness),
+    }
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|regex| Regex::new(&regex).map_err(serde::de::Error::custom))
+        .transpose()
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -74,10 +616,184 @@ pub struct QuerySettings {
     pub type_query: TypeQueryBoosts,
     pub string_query: StringQuery,
     pub importance_query: ImportanceQueryBoosts,
+    #[serde(default)]
+    pub diversity: Diversity,
+    #[serde(default)]
+    pub tie_breakers: Vec<TieBreaker>,
+    #[serde(default)]
+    pub elasticsearch: Elasticsearch,
+    #[serde(default)]
+    pub readiness: ReadinessSettings,
+    #[serde(default)]
+    pub service: ServiceSettings,
+    #[serde(default)]
+    pub query: QueryLimits,
+    #[serde(default)]
+    pub line_query: LineQuery,
+    #[serde(default)]
+    pub fuzzy_query: FuzzyQuery,
+    #[serde(default)]
+    pub reverse_batch: ReverseBatch,
+    #[serde(default)]
+    pub api_keys: Option<ApiKeysSettings>,
+    #[serde(default)]
+    pub query_recorder: Option<QueryRecorderSettings>,
 }
 
 impl QuerySettings {
     pub fn new(settings: &str) -> Result<QuerySettings, String> {
         toml::from_str(settings).map_err(|e| e.to_string())
     }
+
+    pub fn from_toml(settings: toml::Value) -> Result<QuerySettings, String> {
+        settings.try_into().map_err(|e| e.to_string())
+    }
+}
+
+/// Merges the `overlay` settings into `base`: the tables are merged recursively,
+/// and the other values (including the arrays) of `overlay` replace the ones of `base`
+pub fn merge_settings(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_settings(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recency_half_life_must_be_positive() {
+        let settings = |half_life: &str| {
+            let mut settings: toml::Value =
+                toml::from_str(include_str!("../../../config/bragi-settings.toml")).unwrap();
+            let overlay: toml::Value = toml::from_str(&format!(
+                "[importance_query.recency.live_pois]\nhalf_life = {}",
+                half_life
+            ))
+            .unwrap();
+            merge_settings(&mut settings, overlay);
+            QuerySettings::from_toml(settings)
+        };
+        assert_eq!(
+            settings("24.0").unwrap().importance_query.recency["live_pois"].half_life,
+            24.
+        );
+        assert!(settings("0.0")
+            .unwrap_err()
+            .contains("the half life of the recency should be positive, not 0"));
+        assert!(settings("-1.0").is_err());
+    }
+
+    #[test]
+    fn fuzziness_must_be_supported_by_es() {
+        let settings = |fuzziness: &str| {
+            let mut settings: toml::Value =
+                toml::from_str(include_str!("../../../config/bragi-settings.toml")).unwrap();
+            let overlay: toml::Value =
+                toml::from_str(&format!("[fuzzy_query]\nfuzziness = {}", fuzziness)).unwrap();
+            merge_settings(&mut settings, overlay);
+            QuerySettings::from_toml(settings)
+        };
+        assert_eq!(settings("2").unwrap().fuzzy_query.fuzziness, Some(2));
+        assert!(settings("3")
+            .unwrap_err()
+            .contains("the fuzziness should be 0, 1 or 2, not 3"));
+        assert!(settings("-1").is_err());
+    }
+
+    #[test]
+    fn overlay_is_merged_into_base() {
+        let mut settings: toml::Value = toml::from_str(
+            r#"
+            [elasticsearch.circuit_breaker]
+            max_failures = 5
+            cooldown = 10_000
+
+            [[tie_breakers]]
+            field = "weight"
+            order = "desc"
+            "#,
+        )
+        .unwrap();
+        let overlay: toml::Value = toml::from_str(
+            r#"
+            [elasticsearch.circuit_breaker]
+            max_failures = 1
+
+            [readiness.canary]
+            query = "paris"
+            expected_id = "admin:paris"
+            "#,
+        )
+        .unwrap();
+        merge_settings(&mut settings, overlay);
+
+        let circuit_breaker = &settings["elasticsearch"]["circuit_breaker"];
+        assert_eq!(circuit_breaker["max_failures"].as_integer(), Some(1));
+        assert_eq!(circuit_breaker["cooldown"].as_integer(), Some(10_000));
+        assert_eq!(
+            settings["tie_breakers"][0]["field"].as_str(),
+            Some("weight")
+        );
+        assert_eq!(
+            settings["readiness"]["canary"]["query"].as_str(),
+            Some("paris")
+        );
+    }
+
+    #[test]
+    fn exact_names_are_not_boosted_by_the_older_settings() {
+        let mut settings: toml::Value =
+            toml::from_str(include_str!("../../../config/bragi-settings.toml")).unwrap();
+        settings["string_query"]
+            .as_table_mut()
+            .unwrap()
+            .remove("exact_name");
+        let settings = QuerySettings::from_toml(settings).unwrap();
+        assert_eq!(settings.string_query.exact_name.admin, 0.);
+        assert_eq!(settings.string_query.exact_name.poi, 0.);
+    }
+
+    #[test]
+    fn type_budget_is_bounded_by_overall_budget() {
+        let deadlines: Deadlines = toml::from_str(
+            r#"
+            enabled = true
+            overall = 500
+            [per_type]
+            addr = 200
+            poi = 800
+            "#,
+        )
+        .unwrap();
+        assert_eq!(deadlines.type_budget("addr"), Duration::from_millis(200));
+        assert_eq!(deadlines.type_budget("poi"), Duration::from_millis(500));
+        assert_eq!(deadlines.type_budget("admin"), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn endpoints_are_enabled_unless_disabled() {
+        let service: ServiceSettings =
+            toml::from_str("endpoints = { reverse = false, nearby = true }").unwrap();
+        assert!(service.check_endpoints().is_ok());
+        assert!(!service.is_enabled("reverse"));
+        assert!(service.is_enabled("nearby"));
+        assert!(service.is_enabled("autocomplete"));
+        assert!(!service.enabled_endpoints().contains(&"reverse"));
+        assert_eq!(service.enabled_endpoints().len(), ENDPOINTS.len() - 1);
+
+        let typo: ServiceSettings = toml::from_str("endpoints = { revers = false }").unwrap();
+        assert!(typo.check_endpoints().is_err());
+    }
 }
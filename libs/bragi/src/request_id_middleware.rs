@@ -0,0 +1,194 @@
+// Propagation of a request id, used to correlate bragi's logs with the ones of the upstream services.
+//
+// The id is read from the `X-Request-Id` header of the request (or generated if the request has
+// none), it is added to all the logs emitted while serving the request,
+// and it is sent back in the same header of the response and in the error bodies.
+// The header can be renamed, or the propagation disabled.
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::future::{ok, FutureResult};
+use futures::{Async, Future, Poll};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    // id of the request being served by the current thread
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+static NB_GENERATED_IDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Header of the request id if not given
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Header name disabling the request id propagation
+pub const DISABLED_REQUEST_ID_HEADER: &str = "none";
+
+/// Id of the request being served, if the request id propagation is enabled
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+// run `f` with `request_id` as the id of the request being served,
+// both for the error bodies and for the logs
+pub(crate) fn with_request_id<R, F: FnOnce() -> R>(request_id: &str, f: F) -> R {
+    let previous_id = CURRENT_REQUEST_ID.with(|id| id.replace(Some(request_id.to_string())));
+    let logger = slog_scope::logger().new(slog::o!("request_id" => request_id.to_string()));
+    let res = slog_scope::scope(&logger, f);
+    CURRENT_REQUEST_ID.with(|id| *id.borrow_mut() = previous_id);
+    res
+}
+
+fn generate_request_id() -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let nb = NB_GENERATED_IDS.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}", timestamp, nb)
+}
+
+#[derive(Clone)]
+#[must_use = "must be set up as middleware for actix-web"]
+/// Middleware propagating a request id through the given header.
+///
+/// If no header is given, `DEFAULT_REQUEST_ID_HEADER` is used.
+/// With `DISABLED_REQUEST_ID_HEADER`, the request ids are not propagated.
+pub struct RequestId {
+    header: Option<HeaderName>,
+}
+
+impl RequestId {
+    pub fn new(header: Option<&str>) -> Result<Self, String> {
+        let header = match header.unwrap_or(DEFAULT_REQUEST_ID_HEADER) {
+            h if h.eq_ignore_ascii_case(DISABLED_REQUEST_ID_HEADER) => None,
+            h => Some(
+                HeaderName::from_bytes(h.as_bytes())
+                    .map_err(|e| format!("invalid request id header `{}`: {}", h, e))?,
+            ),
+        };
+        Ok(RequestId { header })
+    }
+}
+
+impl<S, B> Transform<S> for RequestId
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdMiddleware {
+            service,
+            header: self.header.clone(),
+        })
+    }
+}
+
+#[doc(hidden)]
+/// Middleware service for RequestId
+pub struct RequestIdMiddleware<S> {
+    service: S,
+    header: Option<HeaderName>,
+}
+
+impl<S, B> Service for RequestIdMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = RequestIdResponse<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let header = match self.header {
+            Some(ref header) => header.clone(),
+            None => {
+                return RequestIdResponse {
+                    fut: self.service.call(req),
+                    request_id: None,
+                }
+            }
+        };
+        let request_id = req
+            .headers()
+            .get(&header)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(generate_request_id);
+
+        let service = &mut self.service;
+        let fut = with_request_id(&request_id, || service.call(req));
+        RequestIdResponse {
+            fut,
+            request_id: Some((header, request_id)),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct RequestIdResponse<S>
+where
+    S: Service,
+{
+    fut: S::Future,
+    request_id: Option<(HeaderName, String)>,
+}
+
+impl<S, B> Future for RequestIdResponse<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Item = ServiceResponse<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (header, request_id) = match self.request_id {
+            Some((ref header, ref request_id)) => (header, request_id),
+            None => return self.fut.poll(),
+        };
+        let fut = &mut self.fut;
+        let mut res = futures::try_ready!(with_request_id(request_id, || fut.poll()));
+
+        if let Ok(value) = HeaderValue::from_str(request_id) {
+            res.headers_mut().insert(header.clone(), value);
+        }
+        Ok(Async::Ready(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_id_header_can_be_renamed_or_disabled() {
+        let header = |header| RequestId::new(header).unwrap().header;
+        assert_eq!(header(None), Some(HeaderName::from_static("x-request-id")));
+        assert_eq!(
+            header(Some("X-Correlation-Id")),
+            Some(HeaderName::from_static("x-correlation-id"))
+        );
+        assert_eq!(header(Some("none")), None);
+        assert!(RequestId::new(Some("not a header")).is_err());
+    }
+}
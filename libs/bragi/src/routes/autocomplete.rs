@@ -1,14 +1,43 @@
-use crate::extractors::BragiQuery;
-use crate::model::{Autocomplete, BragiError, FromWithLang};
+use crate::api_keys::Profile;
+use crate::cancellation::Cancellation;
+use crate::circuit_breaker::{index_types, INDEX_TYPES};
+use crate::extractors::{ActixError, ApiProfile, BragiQuery};
+use crate::geocodejson::{Format, GeocodeJson};
+use crate::model::{
+    ApiWarning, Autocomplete, BragiError, Cluster, Clusters, Count, EnvelopedAutocomplete,
+    FromWithLang, GroupedAutocomplete, Suggestion, Suggestions, ValidationError,
+};
+use crate::query::{CollapseField, Exclusions, RequiredFlag};
+use crate::query_settings::{QuerySettings, MAX_FUZZINESS};
+use crate::request_id_middleware::{current_request_id, with_request_id};
 use crate::routes::params;
 use crate::{model, query, Context};
-use actix_http::http::header::{CacheControl, CacheDirective};
-use actix_web::web::{Data, HttpResponse, Json};
-use geojson::{GeoJson, Geometry};
+use actix_http::http::header::{CacheControl, CacheDirective, ACCEPT_LANGUAGE};
+use actix_web::error::{BlockingError, JsonPayloadError};
+use actix_web::web::{self, Data, HttpResponse, Json, JsonConfig};
+use actix_web::{FromRequest, HttpRequest};
+use chrono::{NaiveDate, Utc};
+use futures::Future;
+use geo_types::Rect;
+use geojson::{GeoJson, Geometry, Value};
 use mimir::objects::{Coord, PlaceDocType};
 use serde::{Deserialize, Serialize};
 use slog_scope::trace;
-use std::time::Duration;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use unicode_segmentation::UnicodeSegmentation;
+
+lazy_static::lazy_static! {
+    static ref REJECTED_SHAPES_COUNTER: prometheus::CounterVec = prometheus::register_counter_vec!(
+        "bragi_rejected_shapes_total",
+        "Total number of shapes rejected because they were too large, too complex or too slow to validate.",
+        &["reason"]
+    )
+    .unwrap();
+}
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 enum Type {
@@ -53,16 +82,46 @@ impl PoiType {
     }
 }
 
+// format of the dates given to and stored in ES
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%d";
+
 fn default_limit() -> u64 {
     10u64
 }
 
+// maximum number of ids in `exclude_ids`, to keep the ES query small
+const MAX_EXCLUDED_IDS: usize = 100;
+
+// maximum length of `preference`, it is given as is in the url of the ES request
+const MAX_PREFERENCE_LENGTH: usize = 64;
+
+// maximum zoom level of the maps
+const MAX_ZOOM: u8 = 22;
+
+// header of the responses giving the experiment of the request (written in the access logs)
+const EXPERIMENT_HEADER: &str = "x-experiment";
+// header of the responses giving the codes of their warnings (written in the access logs)
+const WARNINGS_HEADER: &str = "x-warnings";
+
+// the shape of an api key restricts all the places
+static ALL_DOC_TYPES: [PlaceDocType; 5] = [
+    PlaceDocType::Admin,
+    PlaceDocType::Street,
+    PlaceDocType::Addr,
+    PlaceDocType::Poi,
+    PlaceDocType::Stop,
+];
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Params {
+    // Without query string, all the places matching the filters are browsed, sorted by weight.
+    // It is trimmed and its whitespaces collapsed, so a query of spaces is no query string.
+    #[serde(default, deserialize_with = "params::deserialize_query")]
     q: String,
-    #[serde(default)]
+    // The datasets, types, zone types and poi types are matched whatever their case
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
     pt_dataset: Vec<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
     poi_dataset: Vec<String>,
     #[serde(rename = "_all_data", default)]
     all_data: bool,
@@ -81,11 +140,49 @@ pub struct Params {
     proximity_scale: Option<f64>,
     proximity_offset: Option<f64>,
     proximity_decay: Option<f64>,
-    #[serde(default, rename = "type")]
+    // Typos (0, 1 or 2) tolerated in each word by the fuzzy search,
+    // overrides the fuzziness depending on the length of the words
+    fuzziness: Option<u8>,
+    // Bounding box (usually the viewport of a map) formatted as `min_lon,min_lat,max_lon,max_lat`.
+    // The places inside it are boosted, unlike the shape the places outside of it are not filtered.
+    // Only used with a query string.
+    focus_bbox: Option<String>,
+    // Ids of the places removed from the results (for example the places hidden by the user)
+    #[serde(default)]
+    exclude_ids: Vec<String>,
+    // Zone types removed from the results (all the other places are kept, even inside these zones)
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
+    zone_types_blacklist: Vec<cosmogony::ZoneType>,
+    // Datasets whose places are removed from the results
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
+    datasets_blacklist: Vec<String>,
+    // Id of an admin (for example a city), only the places inside it are searched.
+    // The places of an unknown admin are not found.
+    within: Option<String>,
+    // Datasets of the places (the `--dataset` of their import), all the datasets if empty
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
+    datasets: Vec<String>,
+    // Flags required on the places (given to their datasets at the import):
+    // `name` for a boolean flag, or `name:value`
+    #[serde(default)]
+    flags: Vec<String>,
+    #[serde(
+        default,
+        rename = "type",
+        deserialize_with = "params::deserialize_canonical"
+    )]
     types: Vec<Type>,
-    #[serde(default, rename = "zone_type")]
+    #[serde(
+        default,
+        rename = "zone_type",
+        deserialize_with = "params::deserialize_canonical"
+    )]
     zone_types: Vec<cosmogony::ZoneType>,
-    #[serde(default, rename = "poi_type")]
+    #[serde(
+        default,
+        rename = "poi_type",
+        deserialize_with = "params::deserialize_canonical"
+    )]
     poi_types: Vec<PoiType>,
     lang: Option<String>,
     // The scope is a list of place types on which we apply the shape filter.
@@ -97,12 +194,122 @@ pub struct Params {
     // It is prefixed by an underscore to indicate its not a public parameter.
     #[serde(default, rename = "_debug")]
     debug: Option<bool>,
+    // Only returns the number of places matching the query, without fetching them.
+    // This is much cheaper than a full query when the documents are not needed.
+    count_only: Option<bool>,
+    // Keeps only the best place for each distinct value of this field
+    // (for example one address per street)
+    collapse_field: Option<CollapseField>,
+    // Merges the streets into their addresses found with them ("12 Rue X" finds the street
+    // and the house), so that they are a single result
+    #[serde(default)]
+    merge_streets: bool,
+    // Without query string, returns clusters of the places (on a grid whose precision is given
+    // by `zoom`, else by the width of `focus_bbox` or of the shape) instead of the places
+    #[serde(default)]
+    cluster: bool,
+    // Zoom level of the map of the clusters
+    zoom: Option<u8>,
+    // Only the stops in service at this date (formatted as YYYY-MM-DD) are returned.
+    // Defaults to today.
+    date: Option<String>,
+    // Adds the alternative names (old names, abbreviations, ...) of the places to the response
+    #[serde(default)]
+    include_alt_names: bool,
+    // Adds their bbox to the features of the zones, to zoom on them
+    #[serde(default)]
+    include_bbox: bool,
+    // Adds their population (if known) and their area to the features of the zones
+    #[serde(default)]
+    include_demographics: bool,
+    // Adds the raw score given by ES to the features (as `_score`), to debug the relevance
+    #[serde(default)]
+    include_score: bool,
+    // Adds their geometry to the context of the features: the (simplified) boundary of the zones,
+    // the line of the streets, or else their point. The limit is lower, since boundaries are heavy.
+    #[serde(default)]
+    geometry: bool,
+    // Without query string, pages through all the places matching the filters (for the exports):
+    // empty for the first page, then the `cursor` of the previous response (there is none after
+    // the last page). The places are sorted by id, and unlike `offset` there is no maximum depth.
+    cursor: Option<String>,
+    // Wraps each feature with its score, distance and confidence.
+    // The flat features are returned by default for backward compatibility.
+    #[serde(default)]
+    envelope: bool,
+    // Groups the features by their type (for the UIs showing a section by type),
+    // the groups being in the order of their best place
+    #[serde(default)]
+    group_by_type: bool,
+    // Format of the response, `geocodejson` to follow strictly the geocodejson spec
+    #[serde(default)]
+    format: Format,
+    // Echoes in the response the request as interpreted by bragi (its filters and the defaults
+    // applied), to debug it
+    echo: Option<bool>,
 
     // Embeds a client id into the request to improve tracing
     request_id: Option<String>,
+    // Name of the experiment whose settings are used for the request, to test it
+    // (by default, the experiment is chosen from the request id)
+    experiment: Option<String>,
+    // Shard preference given to ES (for example a session id), so that the repeated requests
+    // of a client hit the same shards and paginate consistently while the index changes
+    preference: Option<String>,
 }
 
 impl Params {
+    // the query, truncated to its first `max_length` graphemes
+    // (a letter is not cut from its accents or vowel marks)
+    fn query(&self, max_length: usize) -> (&str, Option<ApiWarning>) {
+        match self.q.grapheme_indices(true).nth(max_length) {
+            Some((end, _)) => (
+                self.q[..end].trim_end(),
+                Some(ApiWarning::truncated_query(max_length)),
+            ),
+            None => (self.q.as_str(), None),
+        }
+    }
+    // the datasets explicitly requested, each one once
+    fn requested_datasets(&self) -> Vec<&str> {
+        let mut datasets: Vec<&str> = vec![];
+        for dataset in self
+            .datasets
+            .iter()
+            .chain(&self.pt_dataset)
+            .chain(&self.poi_dataset)
+        {
+            if !datasets.contains(&dataset.as_str()) {
+                datasets.push(dataset);
+            }
+        }
+        datasets
+    }
+    // the settings are only copied when the request overrides some of them
+    fn query_settings<'a>(&self, settings: &'a QuerySettings) -> Cow<'a, QuerySettings> {
+        if self.proximity_scale.is_none()
+            && self.proximity_offset.is_none()
+            && self.proximity_decay.is_none()
+            && self.fuzziness.is_none()
+        {
+            return Cow::Borrowed(settings);
+        }
+        let mut settings = settings.clone();
+        let gaussian = &mut settings.importance_query.proximity.gaussian;
+        if let Some(scale) = self.proximity_scale {
+            gaussian.scale = scale;
+        }
+        if let Some(offset) = self.proximity_offset {
+            gaussian.offset = offset;
+        }
+        if let Some(decay) = self.proximity_decay {
+            gaussian.decay = decay;
+        }
+        if let Some(fuzziness) = self.fuzziness {
+            settings.fuzzy_query.fuzziness = Some(fuzziness);
+        }
+        Cow::Owned(settings)
+    }
     fn types_as_str(&self) -> Vec<&str> {
         self.types.iter().map(|t| Type::as_str(t)).collect()
     }
@@ -112,11 +319,159 @@ impl Params {
     fn poi_types_as_str(&self) -> Vec<&str> {
         self.poi_types.iter().map(PoiType::as_str).collect()
     }
+    fn date(&self) -> Result<String, BragiError> {
+        match self.date {
+            Some(ref date) => NaiveDate::parse_from_str(date, DATE_FORMAT)
+                .map(|date| date.format(DATE_FORMAT).to_string())
+                .map_err(|_| {
+                    BragiError::InvalidParam(
+                        "the 'date' parameter should be formatted as YYYY-MM-DD",
+                    )
+                }),
+            None => Ok(Utc::today().format(DATE_FORMAT).to_string()),
+        }
+    }
     fn coord(&self) -> Result<Option<Coord>, BragiError> {
         Self::build_coord(self.lon, self.lat)
     }
-    fn langs(&self) -> Vec<&str> {
-        self.lang.iter().map(|l| l.as_str()).collect()
+    fn focus_bbox(&self) -> Result<Option<Rect<f64>>, BragiError> {
+        self.focus_bbox
+            .as_ref()
+            .map(|bbox| params::make_bbox(bbox))
+            .transpose()
+    }
+    fn exclude_ids(&self) -> Result<Vec<&str>, BragiError> {
+        if self.exclude_ids.len() > MAX_EXCLUDED_IDS {
+            return Err(BragiError::InvalidParam(
+                "the 'exclude_ids' parameter should not contain more than 100 ids",
+            ));
+        }
+        Ok(self.exclude_ids.iter().map(String::as_str).collect())
+    }
+    fn preference(&self) -> Result<Option<&str>, BragiError> {
+        match &self.preference {
+            // a leading '_' would be a routing directive of ES (like `_only_node:x`), which gives
+            // errors opening the circuit breaker for all the clients
+            Some(preference)
+                if preference.is_empty()
+                    || preference.len() > MAX_PREFERENCE_LENGTH
+                    || preference.starts_with('_')
+                    || !preference
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "_-:.,".contains(c)) =>
+            {
+                Err(BragiError::InvalidParam(
+                    "the 'preference' parameter should only contain up to 64 letters, digits, '_', '-', ':', '.' or ',', and not start with '_'",
+                ))
+            }
+            preference => Ok(preference.as_deref()),
+        }
+    }
+    fn flags(&self) -> Result<Vec<RequiredFlag<'_>>, BragiError> {
+        self.flags
+            .iter()
+            .map(|flag| {
+                let (name, value) = match flag.find(':') {
+                    Some(i) => (&flag[..i], Some(&flag[i + 1..])),
+                    None => (flag.as_str(), None),
+                };
+                if name.is_empty()
+                    || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    return Err(BragiError::InvalidParam(
+                        "a flag should be formatted as 'name' or 'name:value', its name only containing letters, digits or '_'",
+                    ));
+                }
+                Ok(RequiredFlag { name, value })
+            })
+            .collect()
+    }
+    fn cluster(&self) -> Result<(), BragiError> {
+        if self.cluster && !self.q.trim().is_empty() {
+            return Err(BragiError::InvalidParam(
+                "the 'cluster' parameter can only be used without 'q'",
+            ));
+        }
+        match self.zoom {
+            Some(zoom) if zoom > MAX_ZOOM => Err(BragiError::InvalidParam(
+                "the 'zoom' parameter should be between 0 and 22",
+            )),
+            _ => Ok(()),
+        }
+    }
+    // geohash precision of the clusters: the zoom wins over the bbox, the whole world by default
+    fn cluster_precision(&self, focus_bbox: Option<Rect<f64>>, shape: Option<&Geometry>) -> u8 {
+        let width = match (
+            self.zoom,
+            focus_bbox,
+            shape.and_then(|s| lon_extent(&s.value)),
+        ) {
+            (Some(zoom), _, _) => query::zoom_width(zoom),
+            (None, Some(bbox), _) => bbox.max().x - bbox.min().x,
+            (None, None, Some((min_lon, max_lon))) => max_lon - min_lon,
+            (None, None, None) => 360.,
+        };
+        query::cluster_precision(width)
+    }
+    fn cursor(&self) -> Result<(), BragiError> {
+        match self.cursor {
+            Some(_) if !self.q.trim().is_empty() => Err(BragiError::InvalidParam(
+                "the 'cursor' parameter can only be used without 'q'",
+            )),
+            Some(_) if self.offset != 0 || self.cluster || self.count_only.unwrap_or(false) => {
+                Err(BragiError::InvalidParam(
+                    "the 'cursor' parameter cannot be used with 'offset', 'cluster' or 'count_only'",
+                ))
+            }
+            Some(ref cursor) => params::decode_cursor(cursor).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+    fn offset(&self) -> Result<(), BragiError> {
+        if self.offset >= query::MAX_RESULT_WINDOW {
+            return Err(BragiError::InvalidParam(
+                "the 'offset' parameter must be lower than 10000, use 'cursor' to page deeper",
+            ));
+        }
+        Ok(())
+    }
+    fn group_by_type(&self) -> Result<(), BragiError> {
+        if self.group_by_type && self.envelope {
+            return Err(BragiError::InvalidParam(
+                "the 'group_by_type' parameter cannot be used with 'envelope'",
+            ));
+        }
+        Ok(())
+    }
+    fn format(&self) -> Result<(), BragiError> {
+        if self.format == Format::GeocodeJson && (self.envelope || self.group_by_type) {
+            return Err(BragiError::InvalidParam(
+                "the 'geocodejson' format cannot be used with 'envelope' or 'group_by_type'",
+            ));
+        }
+        Ok(())
+    }
+    fn fuzziness(&self) -> Result<(), BragiError> {
+        match self.fuzziness {
+            Some(fuzziness) if fuzziness > MAX_FUZZINESS => Err(BragiError::InvalidParam(
+                "the 'fuzziness' parameter should be 0, 1 or 2",
+            )),
+            _ => Ok(()),
+        }
+    }
+    // the explicit `lang` always wins over the `Accept-Language` header
+    fn langs<'a>(
+        &'a self,
+        accept_language: Option<&str>,
+        supported_langs: &'a [String],
+    ) -> Vec<&'a str> {
+        match &self.lang {
+            Some(lang) => vec![lang.as_str()],
+            None => accept_language
+                .and_then(|header| params::best_accept_language(header, supported_langs))
+                .into_iter()
+                .collect(),
+        }
     }
     fn timeout(&self) -> Option<Duration> {
         self.timeout.map(Duration::from_millis)
@@ -132,97 +487,1693 @@ impl Params {
     }
 }
 
+/// The filters of an autocomplete, built from its parameters once they have all been validated
+#[derive(Debug, Clone)]
+pub struct Filters<'a> {
+    pub date: String,
+    pub coord: Option<Coord>,
+    pub focus_bbox: Option<Rect<f64>>,
+    pub exclusions: Exclusions<'a>,
+    pub within: Option<&'a str>,
+    pub pt_datasets: Vec<&'a str>,
+    pub poi_datasets: Vec<&'a str>,
+    pub all_data: bool,
+    pub datasets: Vec<&'a str>,
+    pub flags: Vec<RequiredFlag<'a>>,
+    pub types: Vec<&'a str>,
+    pub zone_types: Vec<&'a str>,
+    pub poi_types: Vec<&'a str>,
+    pub preference: Option<&'a str>,
+}
+
+impl<'a> TryFrom<&'a Params> for Filters<'a> {
+    type Error = ValidationError;
+
+    // all the parameters are checked, to report all the problems at once
+    fn try_from(params: &'a Params) -> Result<Self, ValidationError> {
+        let mut errors = ValidationError::default();
+        let date = errors.check(params.date());
+        let coord = errors.check(params.coord());
+        let focus_bbox = errors.check(params.focus_bbox());
+        let exclude_ids = errors.check(params.exclude_ids());
+        let flags = errors.check(params.flags());
+        let preference = errors.check(params.preference());
+        errors.check(params.fuzziness());
+        errors.check(params.group_by_type());
+        errors.check(params.format());
+        errors.check(params.cluster());
+        errors.check(params.cursor());
+        errors.check(params.offset());
+        let datasets: Vec<&str> = params.datasets.iter().map(String::as_str).collect();
+        let types = params.types_as_str();
+        let zone_types = params.zone_types_as_str();
+        let poi_types = params.poi_types_as_str();
+        errors.check(query::check_type_filters(&types, &zone_types, &poi_types));
+        let zone_types_blacklist = blacklist(
+            &zone_types,
+            params
+                .zone_types_blacklist
+                .iter()
+                .map(|x| x.as_str())
+                .collect(),
+            "zone type",
+            &mut errors,
+        );
+        let datasets_blacklist = blacklist(
+            &datasets,
+            params
+                .datasets_blacklist
+                .iter()
+                .map(String::as_str)
+                .collect(),
+            "dataset",
+            &mut errors,
+        );
+        match (date, coord, focus_bbox, exclude_ids, flags, preference) {
+            (
+                Some(date),
+                Some(coord),
+                Some(focus_bbox),
+                Some(exclude_ids),
+                Some(flags),
+                Some(preference),
+            ) if errors.problems.is_empty() => Ok(Filters {
+                date,
+                coord,
+                focus_bbox,
+                exclusions: Exclusions {
+                    ids: exclude_ids,
+                    zone_types: zone_types_blacklist,
+                    datasets: datasets_blacklist,
+                },
+                within: params.within.as_deref(),
+                pt_datasets: params.pt_dataset.iter().map(String::as_str).collect(),
+                poi_datasets: params.poi_dataset.iter().map(String::as_str).collect(),
+                all_data: params.all_data,
+                datasets,
+                flags,
+                types,
+                zone_types,
+                poi_types,
+                preference,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+impl<'a> Filters<'a> {
+    /// The filters of the parameters, restricted by the profile of the api key if any.
+    ///
+    /// The restrictions broken by the parameters are reported with their other problems.
+    pub fn new(params: &'a Params, profile: Option<&'a Profile>) -> Result<Self, ValidationError> {
+        match (Filters::try_from(params), profile) {
+            (filters, None) => filters,
+            (Ok(filters), Some(profile)) => filters.restrict_to(profile),
+            (Err(mut errors), Some(profile)) => {
+                let datasets = params.datasets.iter().map(String::as_str).collect();
+                restrict(params.types_as_str(), &profile.types, "type", &mut errors);
+                restrict(datasets, &profile.datasets, "dataset", &mut errors);
+                let pt_datasets: Vec<&str> = params.pt_dataset.iter().map(String::as_str).collect();
+                let poi_datasets: Vec<&str> =
+                    params.poi_dataset.iter().map(String::as_str).collect();
+                restrict_public_transport(
+                    &pt_datasets,
+                    &poi_datasets,
+                    params.all_data,
+                    profile,
+                    &mut errors,
+                );
+                Err(errors)
+            }
+        }
+    }
+
+    /// Merges the restrictions of the profile of an api key into the filters:
+    /// the filters of the request can only narrow them, never widen them
+    pub fn restrict_to(mut self, profile: &'a Profile) -> Result<Self, ValidationError> {
+        let mut errors = ValidationError::default();
+        self.types = restrict(self.types, &profile.types, "type", &mut errors);
+        self.datasets = restrict(self.datasets, &profile.datasets, "dataset", &mut errors);
+        restrict_public_transport(
+            &self.pt_datasets,
+            &self.poi_datasets,
+            self.all_data,
+            profile,
+            &mut errors,
+        );
+        if errors.problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// the requested values must all be allowed, and they are all the allowed ones if none is requested
+fn restrict<'a>(
+    requested: Vec<&'a str>,
+    allowed: &'a [String],
+    name: &str,
+    errors: &mut ValidationError,
+) -> Vec<&'a str> {
+    if allowed.is_empty() {
+        return requested;
+    }
+    if requested.is_empty() {
+        return allowed.iter().map(String::as_str).collect();
+    }
+    check_allowed(&requested, allowed, name, errors);
+    requested
+}
+
+fn check_allowed(requested: &[&str], allowed: &[String], name: &str, errors: &mut ValidationError) {
+    for value in requested
+        .iter()
+        .filter(|v| !allowed.iter().any(|a| a == *v))
+    {
+        errors.problems.push(format!(
+            "the {} '{}' is not allowed for this api key",
+            name, value
+        ));
+    }
+}
+
+// the stops and the pois of the datasets of the public transport must also be allowed,
+// but none are searched if none are requested
+fn restrict_public_transport(
+    pt_datasets: &[&str],
+    poi_datasets: &[&str],
+    all_data: bool,
+    profile: &Profile,
+    errors: &mut ValidationError,
+) {
+    if profile.datasets.is_empty() {
+        return;
+    }
+    check_allowed(pt_datasets, &profile.datasets, "pt_dataset", errors);
+    check_allowed(poi_datasets, &profile.datasets, "poi_dataset", errors);
+    if all_data {
+        errors
+            .problems
+            .push("the '_all_data' parameter is not allowed for this api key".to_string());
+    }
+}
+
+// the excluded values cannot also be requested, the filters would be contradictory
+fn blacklist<'a>(
+    requested: &[&str],
+    excluded: Vec<&'a str>,
+    name: &str,
+    errors: &mut ValidationError,
+) -> Vec<&'a str> {
+    for value in excluded.iter().filter(|v| requested.contains(v)) {
+        errors.problems.push(format!(
+            "the {} '{}' cannot be both requested and excluded",
+            name, value
+        ));
+    }
+    excluded
+}
+
+// the shape of an api key cannot be combined with the shape of the request
+fn restrict_shape<'a>(
+    shape: Option<Geometry>,
+    shape_scope: &'a [PlaceDocType],
+    profile: Option<&'a Profile>,
+) -> Result<(Option<Geometry>, &'a [PlaceDocType]), BragiError> {
+    match profile.and_then(|profile| profile.shape.as_ref()) {
+        None => Ok((shape, shape_scope)),
+        Some(_) if shape.is_some() => Err(BragiError::InvalidParam(
+            "the shape is given by the api key, it cannot be given by the request",
+        )),
+        Some(boundary) => Ok((Some(boundary.clone()), &ALL_DOC_TYPES)),
+    }
+}
+
+/// The request as interpreted by bragi: its filters resolved (restricted by the api key),
+/// and the defaults applied to its parameters
+#[derive(Serialize, Debug, Clone)]
+pub struct RequestEcho {
+    pub q: String,
+    pub limit: u64,
+    pub offset: u64,
+    pub langs: Vec<String>,
+    pub date: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coord: Option<Coord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus_bbox: Option<[f64; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub within: Option<String>,
+    pub all_data: bool,
+    pub pt_datasets: Vec<String>,
+    pub poi_datasets: Vec<String>,
+    pub datasets: Vec<String>,
+    pub flags: Vec<String>,
+    pub types: Vec<String>,
+    pub zone_types: Vec<String>,
+    pub poi_types: Vec<String>,
+    pub exclude_ids: Vec<String>,
+    pub zone_types_blacklist: Vec<String>,
+    pub datasets_blacklist: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preference: Option<String>,
+}
+
+impl RequestEcho {
+    fn new(
+        params: &Params,
+        filters: &Filters<'_>,
+        q: &str,
+        langs: &[&str],
+        max_limit: u64,
+    ) -> Self {
+        let owned =
+            |values: &[&str]| -> Vec<String> { values.iter().map(|v| v.to_string()).collect() };
+        RequestEcho {
+            q: q.to_string(),
+            limit: params::clamp_limit(params.limit, max_limit),
+            offset: params.offset,
+            langs: owned(langs),
+            date: filters.date.clone(),
+            coord: filters.coord,
+            focus_bbox: filters
+                .focus_bbox
+                .map(|bbox| [bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y]),
+            within: filters.within.map(str::to_string),
+            all_data: filters.all_data,
+            pt_datasets: owned(&filters.pt_datasets),
+            poi_datasets: owned(&filters.poi_datasets),
+            datasets: owned(&filters.datasets),
+            flags: filters
+                .flags
+                .iter()
+                .map(|flag| match flag.value {
+                    Some(value) => format!("{}:{}", flag.name, value),
+                    None => flag.name.to_string(),
+                })
+                .collect(),
+            types: owned(&filters.types),
+            zone_types: owned(&filters.zone_types),
+            poi_types: owned(&filters.poi_types),
+            exclude_ids: owned(&filters.exclusions.ids),
+            zone_types_blacklist: owned(&filters.exclusions.zone_types),
+            datasets_blacklist: owned(&filters.exclusions.datasets),
+            preference: filters.preference.map(str::to_string),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JsonParams {
     shape: GeoJson,
 }
 
 impl JsonParams {
-    fn get_geometry(self) -> Result<Geometry, model::BragiError> {
-        match self.shape {
+    /// The geometry of the shape, validated within `max_time`
+    fn get_geometry(
+        self,
+        max_vertices: usize,
+        max_time: Duration,
+    ) -> Result<Geometry, model::BragiError> {
+        let started = Instant::now();
+        let geometry = match self.shape {
             GeoJson::Feature(f) => f.geometry.ok_or(BragiError::InvalidShape("no geometry")),
             _ => Err(BragiError::InvalidShape("only 'feature' is supported")),
+        }?;
+        // the shape is given to ES as is, so we check its complexity
+        // before it becomes a costly query
+        let nb_vertices = nb_vertices(&geometry.value);
+        if nb_vertices > max_vertices {
+            REJECTED_SHAPES_COUNTER
+                .with_label_values(&["too_complex"])
+                .inc();
+            return Err(BragiError::ShapeTooComplex {
+                nb_vertices,
+                max_vertices,
+            });
+        }
+        check_positions(&geometry.value, started, max_time).map_err(|e| {
+            if let BragiError::ShapeValidationTimeout { .. } = e {
+                REJECTED_SHAPES_COUNTER
+                    .with_label_values(&["too_slow"])
+                    .inc();
+            }
+            e
+        })?;
+        Ok(geometry)
+    }
+}
+
+// the time of the validation is checked once every this number of positions
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+// the positions of the shape must be coordinates, ES fails on the others
+fn check_positions(value: &Value, started: Instant, max_time: Duration) -> Result<(), BragiError> {
+    let positions: Vec<&Vec<f64>> = match value {
+        Value::Point(point) => vec![point],
+        Value::MultiPoint(points) | Value::LineString(points) => points.iter().collect(),
+        Value::MultiLineString(lines) | Value::Polygon(lines) => lines.iter().flatten().collect(),
+        Value::MultiPolygon(polygons) => polygons.iter().flatten().flatten().collect(),
+        Value::GeometryCollection(geometries) => {
+            return geometries
+                .iter()
+                .try_for_each(|g| check_positions(&g.value, started, max_time))
+        }
+    };
+    for (i, position) in positions.iter().enumerate() {
+        if i % DEADLINE_CHECK_INTERVAL == 0 && started.elapsed() >= max_time {
+            return Err(BragiError::ShapeValidationTimeout { max_time });
+        }
+        match position.as_slice() {
+            [lon, lat, ..] if (-180.0..=180.0).contains(lon) && (-90.0..=90.0).contains(lat) => {}
+            _ => {
+                return Err(BragiError::InvalidShape(
+                    "the positions of the shape should be [lon, lat] coordinates",
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// smallest and largest longitudes of a geometry
+fn lon_extent(value: &Value) -> Option<(f64, f64)> {
+    let positions: Vec<&Vec<f64>> = match value {
+        Value::Point(point) => vec![point],
+        Value::MultiPoint(points) | Value::LineString(points) => points.iter().collect(),
+        Value::MultiLineString(lines) | Value::Polygon(lines) => lines.iter().flatten().collect(),
+        Value::MultiPolygon(polygons) => polygons.iter().flatten().flatten().collect(),
+        Value::GeometryCollection(geometries) => {
+            return geometries.iter().filter_map(|g| lon_extent(&g.value)).fold(
+                None,
+                |extent, (min, max)| match extent {
+                    Some((min_lon, max_lon)) => Some((min.min(min_lon), max.max(max_lon))),
+                    None => Some((min, max)),
+                },
+            )
+        }
+    };
+    positions
+        .iter()
+        .filter_map(|position| position.first())
+        .fold(None, |extent, &lon| match extent {
+            Some((min_lon, max_lon)) => Some((lon.min(min_lon), lon.max(max_lon))),
+            None => Some((lon, lon)),
+        })
+}
+
+fn nb_vertices(value: &Value) -> usize {
+    match value {
+        Value::Point(_) => 1,
+        Value::MultiPoint(points) => points.len(),
+        Value::LineString(line) => line.len(),
+        Value::MultiLineString(lines) => lines.iter().map(Vec::len).sum(),
+        Value::Polygon(rings) => rings.iter().map(Vec::len).sum(),
+        Value::MultiPolygon(polygons) => polygons
+            .iter()
+            .flat_map(|rings| rings.iter().map(Vec::len))
+            .sum(),
+        Value::GeometryCollection(geometries) => {
+            geometries.iter().map(|g| nb_vertices(&g.value)).sum()
+        }
+    }
+}
+
+/// Configuration of the json body of the POST /autocomplete.
+///
+/// The body size is checked before its parsing (with the Content-Length if given),
+/// so that a huge shape is rejected without being read.
+pub fn json_config(max_shape_size: usize) -> JsonConfig {
+    Json::<JsonParams>::configure(|cfg| {
+        cfg.limit(max_shape_size)
+            .error_handler(move |err, _req| match err {
+                JsonPayloadError::Overflow => {
+                    REJECTED_SHAPES_COUNTER
+                        .with_label_values(&["too_large"])
+                        .inc();
+                    ActixError::PayloadTooLarge(format!(
+                        "the body should not exceed {} bytes",
+                        max_shape_size
+                    ))
+                    .into()
+                }
+                err => ActixError::InvalidJson(format!("{}", err)).into(),
+            })
+    })
+}
+
+fn accept_language(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|header| header.to_str().ok())
+}
+
+/// Body of an autocomplete response
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum AutocompleteBody {
+    Count(Count),
+    Clusters(Clusters),
+    Places(Autocomplete),
+    Envelope(EnvelopedAutocomplete),
+    Groups(GroupedAutocomplete),
+    GeocodeJson(GeocodeJson),
+    Suggestions(Suggestions),
+}
+
+/// An autocomplete response: its body, with the echo of its request if asked
+#[derive(Serialize, Debug)]
+pub struct AutocompleteResponse {
+    #[serde(flatten)]
+    pub body: AutocompleteBody,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub echo: Option<RequestEcho>,
+}
+
+impl AutocompleteBody {
+    /// Name of the experiment whose settings have been used, if any
+    fn experiment(&self) -> Option<&str> {
+        match self {
+            AutocompleteBody::Count(count) => count.experiment.as_deref(),
+            AutocompleteBody::Clusters(_) => None,
+            AutocompleteBody::Places(places) => places.experiment.as_deref(),
+            AutocompleteBody::Envelope(places) => places.experiment.as_deref(),
+            AutocompleteBody::Groups(groups) => groups.experiment.as_deref(),
+            AutocompleteBody::GeocodeJson(places) => places.experiment.as_deref(),
+            AutocompleteBody::Suggestions(suggestions) => suggestions.experiment.as_deref(),
+        }
+    }
+
+    /// Ids of the places of the response, in their order (None if it does not give the places)
+    fn place_ids(&self) -> Option<Vec<&str>> {
+        match self {
+            AutocompleteBody::Places(places) => Some(
+                places
+                    .features
+                    .iter()
+                    .map(|feature| feature.properties.geocoding.id.as_str())
+                    .collect(),
+            ),
+            AutocompleteBody::Envelope(places) => Some(
+                places
+                    .features
+                    .iter()
+                    .map(|hit| hit.source.properties.geocoding.id.as_str())
+                    .collect(),
+            ),
+            AutocompleteBody::GeocodeJson(places) => Some(
+                places
+                    .features
+                    .iter()
+                    .map(|feature| feature.properties.geocoding.id.as_str())
+                    .collect(),
+            ),
+            // the groups do not give the order of all the places
+            AutocompleteBody::Count(_)
+            | AutocompleteBody::Clusters(_)
+            | AutocompleteBody::Groups(_)
+            | AutocompleteBody::Suggestions(_) => None,
+        }
+    }
+
+    /// The non fatal problems of the request
+    fn warnings(&self) -> &[ApiWarning] {
+        match self {
+            AutocompleteBody::Count(count) => &count.warnings,
+            AutocompleteBody::Clusters(clusters) => &clusters.warnings,
+            AutocompleteBody::Places(places) => &places.warnings,
+            AutocompleteBody::Envelope(places) => &places.warnings,
+            AutocompleteBody::Groups(groups) => &groups.warnings,
+            AutocompleteBody::GeocodeJson(places) => &places.warnings,
+            AutocompleteBody::Suggestions(suggestions) => &suggestions.warnings,
+        }
+    }
+}
+
+// the requested datasets without any index, from the aliases of the indexes of each dataset
+// (named munin_<type>_<dataset>)
+fn unknown_datasets<'a>(
+    requested: &[&'a str],
+    aliases: &BTreeMap<String, Vec<String>>,
+) -> Vec<&'a str> {
+    let known = |dataset: &str| {
+        aliases.values().flatten().any(|alias| {
+            ALL_DOC_TYPES
+                .iter()
+                .any(|doc_type| *alias == format!("munin_{}_{}", doc_type.as_str(), dataset))
+        })
+    };
+    requested
+        .iter()
+        .copied()
+        .filter(|dataset| !known(dataset))
+        .collect()
+}
+
+// the warnings of the request itself, before searching its places
+fn request_warnings(
+    params: &Params,
+    state: &Context,
+    truncation: Option<ApiWarning>,
+) -> Vec<ApiWarning> {
+    let mut warnings: Vec<ApiWarning> = truncation.into_iter().collect();
+    let requested = params.requested_datasets();
+    if requested.is_empty() {
+        return warnings;
+    }
+    // the warnings are only a help, the search is not failed if the aliases cannot be read
+    if let Some(aliases) = state.get_known_datasets() {
+        let unknown = unknown_datasets(&requested, &aliases);
+        if !unknown.is_empty() {
+            warnings.push(ApiWarning::unknown_datasets(&unknown));
         }
     }
+    warnings
+}
+
+// gives the geometry of each place in its context
+fn add_geometries(mut places: Vec<mimir::Place>, tolerance: f64) -> Vec<mimir::Place> {
+    for place in &mut places {
+        let mut context = place.context().unwrap_or_default();
+        context.geometry = Some(place.geometry(tolerance));
+        place.set_context(context);
+    }
+    places
 }
 
 pub fn call_autocomplete(
     params: &Params,
     state: &Context,
     shape: Option<Geometry>,
-) -> Result<HttpResponse, model::BragiError> {
-    let langs = params.langs();
+    accept_language: Option<&str>,
+    profile: Option<&Profile>,
+    cancellation: &Cancellation,
+    suggest: bool,
+) -> Result<AutocompleteResponse, model::BragiError> {
     let rubber = state.get_rubber_for_autocomplete(params.timeout());
-    let mut query_settings = state.get_query_settings().clone();
+    // the successive requests of a user are in the same experiment if they have the same id
+    let request_id = params.request_id.clone().or_else(current_request_id);
+    // the problems of the request are all reported at once
+    let mut errors = ValidationError::default();
+    let experiment = errors
+        .check(
+            state
+                .get_experiments()
+                .select(request_id.as_deref(), params.experiment.as_deref()),
+        )
+        .flatten();
+    let query_settings = params
+        .query_settings(experiment.map_or_else(|| state.get_query_settings(), |e| &e.settings));
+    let experiment = experiment.map(|e| e.name.clone());
+    let langs = params.langs(accept_language, &query_settings.query.supported_langs);
+    let (q, truncation) = params.query(query_settings.query.max_query_length);
 
-    if let Some(scale) = params.proximity_scale {
-        query_settings.importance_query.proximity.gaussian.scale = scale;
+    if let Some(id) = &params.request_id {
+        trace!("routes::autocomplete by {} ({})", id, q);
     }
 
-    if let Some(offset) = params.proximity_offset {
-        query_settings.importance_query.proximity.gaussian.offset = offset;
+    let filters = errors.merge(Filters::new(params, profile));
+    let restricted_shape = errors.check(restrict_shape(shape, &params.shape_scope, profile));
+    let (filters, (shape, shape_scope)) = match (filters, restricted_shape) {
+        (Some(filters), Some(restricted_shape)) if errors.problems.is_empty() => {
+            (filters, restricted_shape)
+        }
+        _ => return Err(errors.into()),
+    };
+    let warnings = request_warnings(params, state, truncation);
+    // the suggestions have no geometry
+    let max_limit = if params.geometry && !suggest {
+        query_settings.query.max_limit_with_geometry
+    } else {
+        query_settings.query.max_limit
+    };
+    let echo = if params.echo.unwrap_or(false) {
+        Some(RequestEcho::new(params, &filters, q, &langs, max_limit))
+    } else {
+        None
+    };
+    let respond = move |body: AutocompleteBody| AutocompleteResponse { body, echo };
+
+    // the suggestions are only searched as a text, not looked up by id or coordinates
+    if suggest {
+        return state
+            .get_circuit_breakers()
+            .call(&index_types(&filters.types), || {
+                query::autocomplete_suggestions(
+                    q,
+                    &filters.pt_datasets,
+                    &filters.poi_datasets,
+                    filters.all_data,
+                    &filters.date,
+                    params.offset,
+                    params::clamp_limit(params.limit, max_limit),
+                    filters.coord,
+                    shape,
+                    shape_scope,
+                    filters.focus_bbox,
+                    &filters.exclusions,
+                    filters.within,
+                    &filters.datasets,
+                    &filters.flags,
+                    &filters.types,
+                    &filters.zone_types,
+                    &filters.poi_types,
+                    &langs,
+                    rubber,
+                    &query_settings,
+                    filters.preference,
+                )
+            })
+            .map(|suggestions| {
+                AutocompleteBody::Suggestions(Suggestions {
+                    suggestions: suggestions.into_iter().map(Suggestion::from).collect(),
+                    experiment,
+                    warnings,
+                })
+            })
+            .map(respond);
     }
 
-    if let Some(decay) = params.proximity_decay {
-        query_settings.importance_query.proximity.gaussian.decay = decay;
+    if params.cluster {
+        let precision = params.cluster_precision(filters.focus_bbox, shape.as_ref());
+        let lang = langs.first().copied();
+        return state
+            .get_circuit_breakers()
+            .call(&index_types(&filters.types), || {
+                query::autocomplete_clusters(
+                    &filters.pt_datasets,
+                    &filters.poi_datasets,
+                    filters.all_data,
+                    &filters.date,
+                    shape,
+                    shape_scope,
+                    &filters.exclusions,
+                    filters.within,
+                    &filters.datasets,
+                    &filters.flags,
+                    &filters.types,
+                    &filters.zone_types,
+                    &filters.poi_types,
+                    filters.focus_bbox,
+                    rubber,
+                    precision,
+                )
+            })
+            .map(|clusters| {
+                AutocompleteBody::Clusters(Clusters {
+                    clusters: clusters
+                        .into_iter()
+                        .map(|cluster| Cluster::from_with_lang(cluster, lang))
+                        .collect(),
+                    warnings,
+                })
+            })
+            .map(respond);
     }
 
-    if let Some(id) = &params.request_id {
-        trace!("routes::autocomplete by {} ({})", id, params.q);
+    if params.count_only.unwrap_or(false) {
+        return state
+            .get_circuit_breakers()
+            .call(&index_types(&filters.types), || {
+                query::autocomplete_count(
+                    q,
+                    &filters.pt_datasets,
+                    &filters.poi_datasets,
+                    filters.all_data,
+                    &filters.date,
+                    filters.coord,
+                    shape,
+                    shape_scope,
+                    &filters.exclusions,
+                    filters.within,
+                    &filters.datasets,
+                    &filters.flags,
+                    &filters.types,
+                    &filters.zone_types,
+                    &filters.poi_types,
+                    &langs,
+                    rubber,
+                    &query_settings,
+                )
+            })
+            .map(|count| {
+                AutocompleteBody::Count(Count {
+                    count,
+                    experiment,
+                    warnings,
+                })
+            })
+            .map(respond);
     }
 
-    let res = query::autocomplete(
-        &params.q,
-        &params
-            .pt_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &params
-            .poi_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        params.all_data,
-        params.offset,
-        params.limit,
-        params.coord()?,
-        shape,
-        &params.shape_scope,
-        &params.types_as_str(),
-        &params.zone_types_as_str(),
-        &params.poi_types_as_str(),
-        &langs,
-        rubber,
-        params.debug.unwrap_or(false),
-        &query_settings,
-        params.request_id.as_deref(),
-    );
-    res.map(|r| Autocomplete::from_with_lang(r, langs.into_iter().next()))
-        .map(|v| {
-            HttpResponse::Ok()
-                .set(CacheControl(vec![CacheDirective::MaxAge(
-                    state.http_cache_duration,
-                )]))
-                .json(v)
-        })
+    let lang = langs.first().copied();
+    let with_geometries = |places: Vec<mimir::Place>| {
+        if params.geometry {
+            add_geometries(places, query_settings.query.geometry_tolerance)
+        } else {
+            places
+        }
+    };
+    // the restricted api keys cannot use the reverse geocoding and the features
+    let unrestricted = profile.map_or(true, |profile| !profile.is_restricted());
+    // the ids pasted in the search box are looked up directly,
+    // and searched as a text if there is no such place
+    let by_id = params::detect_id(q)
+        .filter(|_| unrestricted)
+        .and_then(|id| {
+            let rubber = state.get_rubber_for_features(params.timeout());
+            let res = state.get_circuit_breakers().call(&INDEX_TYPES, || {
+                query::features(
+                    &filters.pt_datasets,
+                    &filters.poi_datasets,
+                    filters.all_data,
+                    id,
+                    rubber,
+                )
+            });
+            match res {
+                Err(model::BragiError::ObjectNotFound) => None,
+                res => Some(res.map(|mut places| {
+                    places.truncate(1);
+                    Autocomplete::from_with_lang(with_geometries(places), lang)
+                        .with_query_interpreted_as("id")
+                })),
+            }
+        });
+    // the coordinates pasted in the search box are reverse geocoded
+    let coord_query = params::detect_coord(q).filter(|_| unrestricted);
+    let res = match (by_id, coord_query, params.cursor.as_deref()) {
+        (Some(res), _, _) => res,
+        (None, Some(coord), _) => {
+            let mut rubber = rubber;
+            state
+                .get_circuit_breakers()
+                .call(&index_types(&["house", "street"]), || {
+                    rubber.get_address(&coord).map_err(model::BragiError::from)
+                })
+                .map(|r| {
+                    Autocomplete::from_with_lang(with_geometries(r), lang)
+                        .with_query_interpreted_as("coordinate")
+                })
+        }
+        (None, None, Some(cursor)) => {
+            // the cursor has been checked with the other parameters
+            let after = params::decode_cursor(cursor)?;
+            let limit = params::clamp_limit(params.limit, max_limit);
+            state
+                .get_circuit_breakers()
+                .call(&index_types(&filters.types), || {
+                    query::browse_after(
+                        &filters.pt_datasets,
+                        &filters.poi_datasets,
+                        filters.all_data,
+                        &filters.date,
+                        shape,
+                        shape_scope,
+                        &filters.exclusions,
+                        filters.within,
+                        &filters.datasets,
+                        &filters.flags,
+                        &filters.types,
+                        &filters.zone_types,
+                        &filters.poi_types,
+                        after.as_deref(),
+                        limit,
+                        params.geometry,
+                        rubber,
+                    )
+                })
+                .map(|places| {
+                    // a full page may be followed by another one
+                    let next_cursor = places
+                        .last()
+                        .filter(|_| places.len() as u64 == limit)
+                        .map(|place| params::encode_cursor(place.id()));
+                    Autocomplete::from_with_lang(with_geometries(places), lang)
+                        .with_cursor(next_cursor)
+                })
+        }
+        (None, None, None) => {
+            let line_code = query::detect_line_code(q, &query_settings.line_query);
+            state
+                .get_circuit_breakers()
+                .call(&index_types(&filters.types), || {
+                    query::autocomplete(
+                        q,
+                        &filters.pt_datasets,
+                        &filters.poi_datasets,
+                        filters.all_data,
+                        &filters.date,
+                        params.offset,
+                        params::clamp_limit(params.limit, max_limit),
+                        filters.coord,
+                        shape,
+                        shape_scope,
+                        filters.focus_bbox,
+                        &filters.exclusions,
+                        filters.within,
+                        &filters.datasets,
+                        &filters.flags,
+                        &filters.types,
+                        &filters.zone_types,
+                        &filters.poi_types,
+                        &langs,
+                        rubber,
+                        params.debug.unwrap_or(false),
+                        params.geometry,
+                        &query_settings,
+                        params.request_id.as_deref(),
+                        filters.preference,
+                        params.collapse_field,
+                        params.merge_streets,
+                        cancellation,
+                    )
+                })
+                .map(|(r, partial)| {
+                    Autocomplete::from_with_lang(with_geometries(r), lang).with_partial(&partial)
+                })
+                .map(|v| match line_code {
+                    Some(line_code) => v.with_matched_line(q, line_code),
+                    None => v,
+                })
+                .map(|v| v.with_matched_alt_name(q))
+        }
+    };
+    res.map(|v| {
+        if params.include_alt_names {
+            v
+        } else {
+            v.without_alt_names()
+        }
+    })
+    .map(|v| {
+        if params.include_bbox {
+            v.with_bbox()
+        } else {
+            v
+        }
+    })
+    .map(|v| {
+        if params.include_demographics {
+            v
+        } else {
+            v.without_demographics()
+        }
+    })
+    .map(|v| {
+        if params.include_score {
+            v.with_scores()
+        } else {
+            v
+        }
+    })
+    .map(|v| v.with_experiment(experiment).with_warnings(warnings))
+    .map(|v| {
+        if params.envelope {
+            AutocompleteBody::Envelope(v.into_envelope())
+        } else if params.group_by_type {
+            AutocompleteBody::Groups(v.into_groups())
+        } else if params.format == Format::GeocodeJson {
+            AutocompleteBody::GeocodeJson(v.into())
+        } else {
+            AutocompleteBody::Places(v)
+        }
+    })
+    .map(respond)
+}
+
+// Runs the autocomplete on a blocking thread, so that the worker notices when the client disconnects:
+// the future of the request is then dropped, cancelling the searches of the autocomplete.
+fn spawn_autocomplete(
+    params: Params,
+    state: Data<Context>,
+    shape: Option<Geometry>,
+    accept_language: Option<String>,
+    profile: Option<Arc<Profile>>,
+    suggest: bool,
+    query_string: Option<String>,
+) -> impl Future<Item = HttpResponse, Error = BragiError> {
+    let cancellation = Cancellation::default();
+    let guard = cancellation.guard();
+    let cache_duration = state.http_cache_duration;
+    // the logs of the blocking thread are tagged with the id of the request
+    let request_id = current_request_id();
+    web::block(move || {
+        let search = || {
+            let response = call_autocomplete(
+                &params,
+                &state,
+                shape,
+                accept_language.as_deref(),
+                profile.as_deref(),
+                &cancellation,
+                suggest,
+            )?;
+            // the places found are recorded, to be compared when the queries are replayed
+            if let (Some(recorder), Some(query_string), Some(ids)) = (
+                state.get_query_recorder(),
+                &query_string,
+                response.body.place_ids(),
+            ) {
+                recorder.record(query_string, ids);
+            }
+            Ok(response)
+        };
+        match &request_id {
+            Some(request_id) => with_request_id(request_id, search),
+            None => search(),
+        }
+    })
+    .then(move |res| {
+        guard.disarm();
+        match res {
+            Ok(body) => {
+                let mut response = HttpResponse::Ok();
+                response.set(CacheControl(vec![CacheDirective::MaxAge(cache_duration)]));
+                // the experiment is also given in a header, for the access logs
+                if let Some(experiment) = body.body.experiment() {
+                    response.header(EXPERIMENT_HEADER, experiment);
+                }
+                let warnings = body.body.warnings();
+                if !warnings.is_empty() {
+                    let codes: Vec<&str> = warnings.iter().map(|w| w.code.as_str()).collect();
+                    response.header(WARNINGS_HEADER, codes.join(","));
+                }
+                Ok(response.json(body))
+            }
+            Err(BlockingError::Error(e)) => Err(e),
+            Err(BlockingError::Canceled) => Err(BragiError::Cancelled),
+        }
+    })
 }
 
 pub fn autocomplete(
+    req: HttpRequest,
+    profile: ApiProfile,
+    params: BragiQuery<Params>,
+    state: Data<Context>,
+) -> impl Future<Item = HttpResponse, Error = BragiError> {
+    let accept_language = accept_language(&req).map(str::to_string);
+    spawn_autocomplete(
+        params.into_inner(),
+        state,
+        None,
+        accept_language,
+        profile.into_inner(),
+        false,
+        Some(req.query_string().to_string()),
+    )
+}
+
+/// Suggests the places matching the query while typing, with only their id, label, type and
+/// coordinates. The parameters are the ones of the autocomplete (except the ones of the format
+/// of its features), and the responses are cached and rate limited the same way.
+pub fn suggest(
+    req: HttpRequest,
+    profile: ApiProfile,
     params: BragiQuery<Params>,
     state: Data<Context>,
-) -> Result<HttpResponse, model::BragiError> {
-    call_autocomplete(&*params, &*state, None)
+) -> impl Future<Item = HttpResponse, Error = BragiError> {
+    let accept_language = accept_language(&req).map(str::to_string);
+    spawn_autocomplete(
+        params.into_inner(),
+        state,
+        None,
+        accept_language,
+        profile.into_inner(),
+        true,
+        None,
+    )
 }
 
 pub fn post_autocomplete(
+    req: HttpRequest,
+    profile: ApiProfile,
     params: BragiQuery<Params>,
     state: Data<Context>,
     json_params: Json<JsonParams>,
-) -> Result<HttpResponse, model::BragiError> {
-    call_autocomplete(
-        &*params,
-        &*state,
-        Some(json_params.into_inner().get_geometry()?),
-    )
+) -> impl Future<Item = HttpResponse, Error = BragiError> {
+    let accept_language = accept_language(&req).map(str::to_string);
+    let (max_vertices, max_time) = (state.max_shape_vertices, state.max_shape_validation_time);
+    // a large shape is costly to validate, it is done on the blocking threads, not on the worker
+    web::block(move || {
+        json_params
+            .into_inner()
+            .get_geometry(max_vertices, max_time)
+    })
+    .map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => BragiError::Cancelled,
+    })
+    .and_then(move |shape| {
+        spawn_autocomplete(
+            params.into_inner(),
+            state,
+            Some(shape),
+            accept_language,
+            profile.into_inner(),
+            false,
+            None,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    fn params(value: serde_json::Value) -> Params {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn cursors_are_only_used_to_browse() {
+        let problems = |value| Filters::try_from(&params(value)).unwrap_err().problems;
+        assert_eq!(
+            problems(json!({ "q": "paris", "type": ["house"], "cursor": "" })),
+            vec!["the 'cursor' parameter can only be used without 'q'"]
+        );
+        assert_eq!(
+            problems(json!({ "type": ["house"], "offset": 10, "cursor": "" })),
+            vec!["the 'cursor' parameter cannot be used with 'offset', 'cluster' or 'count_only'"]
+        );
+        assert_eq!(
+            problems(json!({ "type": ["house"], "cursor": "not a cursor" })),
+            vec!["the 'cursor' parameter is not a cursor of Bragi"]
+        );
+        let cursor = params::encode_cursor("addr:1");
+        assert!(Filters::try_from(&params(json!({ "type": ["house"], "cursor": cursor }))).is_ok());
+    }
+
+    #[test]
+    fn offset_is_within_the_result_window_of_es() {
+        let problems = |value| Filters::try_from(&params(value)).unwrap_err().problems;
+        assert!(Filters::try_from(&params(json!({ "q": "paris", "offset": 9999 }))).is_ok());
+        assert_eq!(
+            problems(json!({ "q": "paris", "offset": 10000 })),
+            vec!["the 'offset' parameter must be lower than 10000, use 'cursor' to page deeper"]
+        );
+    }
+
+    #[test]
+    fn all_the_invalid_params_are_reported() {
+        let params = params(json!({
+            "q": "paris",
+            "lon": 2.3,
+            "date": "01/02/2020",
+            "focus_bbox": "2.2,48.8,2.5",
+            "zone_type": ["city"],
+        }));
+        let errors = Filters::try_from(&params).unwrap_err();
+        assert_eq!(
+            errors.problems,
+            vec![
+                "the 'date' parameter should be formatted as YYYY-MM-DD",
+                "you should provide a 'lon' AND a 'lat' parameter if you provide one of them",
+                "a bbox should be formatted as 'min_lon,min_lat,max_lon,max_lat'",
+                "zone_type[] parameter requires to have 'type[]=zone'",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_are_matched_whatever_their_case() {
+        let params = params(json!({
+            "q": "paris",
+            "datasets": ["FR", "be"],
+            "pt_dataset": ["Dataset1"],
+            "type": ["Zone", "POI"],
+            "zone_type": ["City"],
+            "poi_type": ["poi_type:Amenity:Parking"],
+        }));
+        assert_eq!(params.datasets, vec!["fr", "be"]);
+        assert_eq!(params.pt_dataset, vec!["dataset1"]);
+        let filters = Filters::try_from(&params).unwrap();
+        assert_eq!(filters.types, vec!["zone", "poi"]);
+        assert_eq!(filters.zone_types, vec!["city"]);
+        assert_eq!(filters.poi_types, vec!["poi_type:amenity:parking"]);
+    }
+
+    #[test]
+    fn blacklisted_values_cannot_be_requested() {
+        let params = params(json!({
+            "q": "paris",
+            "datasets": ["fr", "test"],
+            "datasets_blacklist": ["Test"],
+            "type": ["zone"],
+            "zone_type": ["city", "city_district"],
+            "zone_types_blacklist": ["city_district", "suburb"],
+        }));
+        let errors = Filters::try_from(&params).unwrap_err();
+        assert_eq!(
+            errors.problems,
+            vec![
+                "the zone type 'city_district' cannot be both requested and excluded",
+                "the dataset 'test' cannot be both requested and excluded",
+            ]
+        );
+
+        let params = params(json!({
+            "q": "paris",
+            "datasets_blacklist": ["test"],
+            "zone_types_blacklist": ["city_district"],
+        }));
+        let filters = Filters::try_from(&params).unwrap();
+        assert_eq!(filters.exclusions.zone_types, vec!["city_district"]);
+        assert_eq!(filters.exclusions.datasets, vec!["test"]);
+        assert!(filters.datasets.is_empty());
+    }
+
+    #[test]
+    fn echo_gives_the_defaults_applied_to_the_request() {
+        let profile = Profile {
+            name: "partner".to_string(),
+            types: vec![],
+            datasets: vec!["fr".to_string()],
+            shape: None,
+            rate_limit: None,
+            daily_quota: None,
+        };
+        let defaulted = params(json!({ "q": "paris", "echo": true }));
+        let filters = Filters::new(&defaulted, Some(&profile)).unwrap();
+        let echo = RequestEcho::new(&defaulted, &filters, "paris", &["fr"], 100);
+        assert_eq!(echo.limit, 10);
+        assert_eq!(echo.datasets, vec!["fr"]);
+        assert_eq!(echo.date, Utc::today().format(DATE_FORMAT).to_string());
+
+        // the limit is clamped
+        let clamped = params(json!({ "q": "paris", "limit": 500 }));
+        let filters = Filters::new(&clamped, None).unwrap();
+        assert_eq!(
+            RequestEcho::new(&clamped, &filters, "paris", &[], 100).limit,
+            100
+        );
+
+        // the echo is added to the body of the response
+        let response = AutocompleteResponse {
+            body: AutocompleteBody::Places(Autocomplete::new("paris".to_string(), vec![])),
+            echo: Some(echo),
+        };
+        let response = serde_json::to_value(&response).unwrap();
+        assert_eq!(response["type"], "FeatureCollection");
+        assert_eq!(response["echo"]["limit"], 10);
+        assert_eq!(response["echo"]["datasets"], json!(["fr"]));
+        assert_eq!(response["echo"]["langs"], json!(["fr"]));
+    }
+
+    #[test]
+    fn filters_only_narrow_the_restrictions_of_the_api_key() {
+        let profile = Profile {
+            name: "partner".to_string(),
+            types: vec!["poi".to_string(), "street".to_string()],
+            datasets: vec!["fr".to_string()],
+            shape: None,
+            rate_limit: None,
+            daily_quota: None,
+        };
+        let unfiltered = params(json!({ "q": "paris" }));
+        let filters = Filters::try_from(&unfiltered)
+            .unwrap()
+            .restrict_to(&profile)
+            .unwrap();
+        assert_eq!(filters.types, vec!["poi", "street"]);
+        assert_eq!(filters.datasets, vec!["fr"]);
+
+        let narrowed = params(json!({ "q": "paris", "type": ["poi"] }));
+        let filters = Filters::try_from(&narrowed)
+            .unwrap()
+            .restrict_to(&profile)
+            .unwrap();
+        assert_eq!(filters.types, vec!["poi"]);
+
+        let widened = params(json!({ "q": "paris", "type": ["zone"], "datasets": ["be"] }));
+        let errors = Filters::try_from(&widened)
+            .unwrap()
+            .restrict_to(&profile)
+            .unwrap_err();
+        assert_eq!(
+            errors.problems,
+            vec![
+                "the type 'zone' is not allowed for this api key",
+                "the dataset 'be' is not allowed for this api key",
+            ]
+        );
+    }
+
+    #[test]
+    fn public_transport_datasets_are_restricted_by_the_api_key() {
+        let profile = Profile {
+            name: "partner".to_string(),
+            types: vec![],
+            datasets: vec!["fr".to_string()],
+            shape: None,
+            rate_limit: None,
+            daily_quota: None,
+        };
+        let allowed = params(json!({ "q": "gare", "pt_dataset": ["fr"] }));
+        let filters = Filters::new(&allowed, Some(&profile)).unwrap();
+        assert_eq!(filters.pt_datasets, vec!["fr"]);
+        assert!(filters.poi_datasets.is_empty());
+
+        let other = params(json!({
+            "q": "gare",
+            "pt_dataset": ["other"],
+            "poi_dataset": ["other"],
+            "_all_data": true,
+        }));
+        let errors = Filters::new(&other, Some(&profile)).unwrap_err();
+        assert_eq!(
+            errors.problems,
+            vec![
+                "the pt_dataset 'other' is not allowed for this api key",
+                "the poi_dataset 'other' is not allowed for this api key",
+                "the '_all_data' parameter is not allowed for this api key",
+            ]
+        );
+        // the keys without dataset restriction can search all the datasets
+        assert!(Filters::new(&other, None).is_ok());
+    }
+
+    #[test]
+    fn restrictions_of_the_api_key_are_reported_with_the_invalid_params() {
+        let profile = Profile {
+            name: "partner".to_string(),
+            types: vec!["poi".to_string()],
+            datasets: vec![],
+            shape: None,
+            rate_limit: None,
+            daily_quota: None,
+        };
+        let params = params(json!({ "q": "paris", "lat": 48.8, "type": ["zone"] }));
+        let errors = Filters::new(&params, Some(&profile)).unwrap_err();
+        assert_eq!(
+            errors.problems,
+            vec![
+                "you should provide a 'lon' AND a 'lat' parameter if you provide one of them",
+                "the type 'zone' is not allowed for this api key",
+            ]
+        );
+        assert_eq!(Filters::new(&params, None).unwrap_err().problems.len(), 1);
+    }
+
+    #[test]
+    fn preference_is_a_safe_string() {
+        let valid = params(json!({ "q": "paris", "preference": "session_42:a,b" }));
+        assert_eq!(
+            Filters::try_from(&valid).unwrap().preference,
+            Some("session_42:a,b")
+        );
+        let too_long = "a".repeat(65);
+        let invalid_preferences = [
+            "",
+            "a&size=1000",
+            "a b",
+            too_long.as_str(),
+            "_shards:99",
+            "_only_node:x",
+        ];
+        for preference in &invalid_preferences {
+            let invalid = params(json!({ "q": "paris", "preference": preference }));
+            assert!(Filters::try_from(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn settings_are_only_copied_when_overridden() {
+        let settings =
+            QuerySettings::new(include_str!("../../../../config/bragi-settings.toml")).unwrap();
+        let shared = params(json!({ "q": "paris" }));
+        assert!(matches!(shared.query_settings(&settings), Cow::Borrowed(_)));
+
+        let overridden = params(json!({ "q": "paris", "proximity_scale": 12.5 }));
+        let overridden_settings = overridden.query_settings(&settings);
+        assert!(matches!(overridden_settings, Cow::Owned(_)));
+        let gaussian = &overridden_settings.importance_query.proximity.gaussian;
+        assert_eq!(gaussian.scale, 12.5);
+        assert_eq!(
+            gaussian.decay,
+            settings.importance_query.proximity.gaussian.decay
+        );
+    }
+
+    #[test]
+    fn clusters_are_only_browsed() {
+        let valid = params(json!({ "q": "", "type": ["poi"], "cluster": true, "zoom": 11 }));
+        assert!(Filters::try_from(&valid).is_ok());
+        for invalid in &[
+            json!({ "q": "paris", "cluster": true }),
+            json!({ "type": ["poi"], "cluster": true, "zoom": 23 }),
+        ] {
+            assert!(Filters::try_from(&params(invalid.clone())).is_err());
+        }
+    }
+
+    #[test]
+    fn query_of_whitespaces_is_browsed() {
+        let spaces =
+            params(json!({ "q": " \u{00A0}\u{3000}\t", "type": ["poi"], "cluster": true }));
+        assert_eq!(spaces.q, "");
+        assert!(Filters::try_from(&spaces).is_ok());
+        let padded = params(json!({ "q": "\u{3000} rue  hector\u{00A0}malot " }));
+        assert_eq!(padded.q, "rue hector malot");
+    }
+
+    #[test]
+    fn long_queries_are_truncated() {
+        let short = params(json!({ "q": "rue hector malot" }));
+        assert_eq!(short.query(16), ("rue hector malot", None));
+        let long = params(json!({ "q": "rue hector malot" }));
+        let (q, warning) = long.query(11);
+        assert_eq!(q, "rue hector");
+        assert_eq!(warning.unwrap().code, "QUERY_TRUNCATED");
+        // the query is cut on a character, not on a byte
+        let accents = params(json!({ "q": "église éè" }));
+        assert_eq!(accents.query(8).0, "église é");
+        // nor between a letter and its vowel marks
+        let hebrew = params(json!({ "q": "שָׁלוֹם עֲלֵיכֶם" }));
+        assert_eq!(hebrew.query(5).0, "שָׁלוֹם");
+        let japanese = params(json!({ "q": "東京スカイツリー" }));
+        assert_eq!(japanese.query(2).0, "東京");
+    }
+
+    #[test]
+    fn unknown_datasets_are_found_from_the_aliases() {
+        let aliases = vec![
+            (
+                "munin_addr_fr_20200101_120000_000000000".to_string(),
+                vec!["munin_addr_fr".to_string(), "munin_geo_data".to_string()],
+            ),
+            (
+                "munin_stop_idfm_20200101_120000_000000000".to_string(),
+                vec!["munin_stop_idfm".to_string(), "munin".to_string()],
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let params = params(json!({
+            "q": "paris",
+            "datasets": ["fr", "be"],
+            "pt_dataset": ["idfm", "fr"],
+            "poi_dataset": ["geo_data"],
+        }));
+        assert_eq!(
+            params.requested_datasets(),
+            vec!["fr", "be", "idfm", "geo_data"]
+        );
+        assert_eq!(
+            unknown_datasets(&params.requested_datasets(), &aliases),
+            vec!["be", "geo_data"]
+        );
+    }
+
+    #[test]
+    fn zoom_wins_over_the_bbox_for_the_clusters() {
+        let shape = Geometry::new(Value::Polygon(vec![vec![
+            vec![2.6, 48.45],
+            vec![2.75, 48.45],
+            vec![2.75, 48.6],
+            vec![2.6, 48.45],
+        ]]));
+        let bbox = params::make_bbox("2.6,48.45,2.75,48.6").ok();
+        let cluster = params(json!({ "type": ["poi"], "cluster": true }));
+        assert_eq!(cluster.cluster_precision(None, None), 1);
+        assert_eq!(cluster.cluster_precision(bbox, None), 6);
+        assert_eq!(cluster.cluster_precision(None, Some(&shape)), 6);
+        let zoomed = params(json!({ "type": ["poi"], "cluster": true, "zoom": 1 }));
+        assert_eq!(zoomed.cluster_precision(bbox, Some(&shape)), 2);
+    }
+
+    #[test]
+    fn flags_are_names_with_an_optional_value() {
+        let valid = params(json!({ "q": "paris", "flags": ["verified", "source:open_data"] }));
+        assert_eq!(
+            Filters::try_from(&valid).unwrap().flags,
+            vec![
+                RequiredFlag {
+                    name: "verified",
+                    value: None
+                },
+                RequiredFlag {
+                    name: "source",
+                    value: Some("open_data")
+                },
+            ]
+        );
+        for flag in &["", ":ign", "a.b", "a b:c"] {
+            let invalid = params(json!({ "q": "paris", "flags": [flag] }));
+            assert!(Filters::try_from(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn lang_param_wins_over_accept_language() {
+        let supported = vec!["es".to_string(), "fr".to_string()];
+        let without_lang = params(json!({ "q": "paris" }));
+        assert_eq!(without_lang.langs(Some("fr"), &supported), vec!["fr"]);
+        assert!(without_lang.langs(Some("de"), &supported).is_empty());
+        assert!(without_lang.langs(None, &supported).is_empty());
+
+        // even if the language of the parameter is not supported
+        let with_lang = params(json!({ "q": "paris", "lang": "it" }));
+        assert_eq!(with_lang.langs(Some("fr"), &supported), vec!["it"]);
+    }
+
+    #[test]
+    fn fuzziness_is_overridden_by_the_request() {
+        let settings =
+            QuerySettings::new(include_str!("../../../../config/bragi-settings.toml")).unwrap();
+        assert_eq!(settings.fuzzy_query.fuzziness, None);
+        let overridden = params(json!({ "q": "paris", "fuzziness": 1 }));
+        assert!(Filters::try_from(&overridden).is_ok());
+        assert_eq!(
+            overridden.query_settings(&settings).fuzzy_query.fuzziness,
+            Some(1)
+        );
+
+        let invalid = params(json!({ "q": "paris", "fuzziness": 3 }));
+        assert_eq!(
+            Filters::try_from(&invalid).unwrap_err().problems,
+            vec!["the 'fuzziness' parameter should be 0, 1 or 2"]
+        );
+    }
+
+    // a polygon of 100k vertices around Paris
+    fn huge_shape() -> serde_json::Value {
+        let nb_vertices = 100_000;
+        let mut ring: Vec<[f64; 2]> = (1..nb_vertices)
+            .map(|i| {
+                let angle = 2. * std::f64::consts::PI * i as f64 / (nb_vertices - 1) as f64;
+                [2.35 + 0.1 * angle.cos(), 48.85 + 0.1 * angle.sin()]
+            })
+            .collect();
+        ring.push(ring[0]);
+        json!({
+            "shape": {
+                "type": "Feature",
+                "properties": {},
+                "geometry": { "type": "Polygon", "coordinates": [ring] }
+            }
+        })
+    }
+
+    #[test]
+    fn shapes_are_validated_within_a_time_budget() {
+        let shape = |value| serde_json::from_value::<JsonParams>(value).unwrap();
+        let minute = Duration::from_secs(60);
+        assert!(shape(huge_shape()).get_geometry(200_000, minute).is_ok());
+        assert!(matches!(
+            shape(huge_shape()).get_geometry(200_000, Duration::from_millis(0)),
+            Err(BragiError::ShapeValidationTimeout { .. })
+        ));
+        assert!(matches!(
+            shape(huge_shape()).get_geometry(10_000, minute),
+            Err(BragiError::ShapeTooComplex {
+                nb_vertices: 100_000,
+                ..
+            })
+        ));
+        let outside = json!({
+            "shape": {
+                "type": "Feature",
+                "properties": {},
+                "geometry": { "type": "Point", "coordinates": [200.0, 48.85] }
+            }
+        });
+        assert!(matches!(
+            shape(outside).get_geometry(10_000, minute),
+            Err(BragiError::InvalidShape(_))
+        ));
+    }
+
+    // a fake Elasticsearch finding nothing, slow to answer the searches of more than 1 MiB
+    // (the ones with a huge shape)
+    fn slow_on_huge_searches_backend(delay: Duration) -> String {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let backend = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", backend.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in backend.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut content_length = 0;
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        let lowercase = line.to_lowercase();
+                        if let Some(length) = lowercase.strip_prefix("content-length:") {
+                            content_length = length.trim().parse().unwrap();
+                        }
+                        line.clear();
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    if content_length > 1024 * 1024 {
+                        std::thread::sleep(delay);
+                    }
+                    let response = r#"{"took": 1, "timed_out": false, "_shards": {"total": 1, "successful": 1, "failed": 0}, "hits": {"total": 0, "max_score": null, "hits": []}}"#;
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response.len(),
+                        response
+                    );
+                });
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn huge_shapes_do_not_starve_the_other_requests() {
+        use actix_service::Service;
+        use actix_web::{test, App};
+
+        let es_delay = Duration::from_millis(500);
+        let max_shape_size = 16 * 1024 * 1024;
+        let ctx = Context::try_from(&crate::Args {
+            connection_string: format!("{}/munin", slow_on_huge_searches_backend(es_delay)),
+            max_shape_size: Some(max_shape_size),
+            max_shape_vertices: Some(200_000),
+            // the default time budget of the validation
+            max_shape_validation_time: None,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut app = test::init_service(
+            App::new().data(ctx).service(
+                web::resource("/autocomplete")
+                    .route(web::post().to_async(post_autocomplete))
+                    .data(json_config(max_shape_size)),
+            ),
+        );
+        let post = |body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/autocomplete?q=paris")
+                .set_json(&body)
+                .to_request()
+        };
+        // a small valid shape, searched right away
+        let small_shape = json!({
+            "shape": {
+                "type": "Feature",
+                "properties": {},
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[2.3, 48.8], [2.4, 48.8], [2.4, 48.9], [2.3, 48.8]]]
+                }
+            }
+        });
+
+        let started = Instant::now();
+        let huge = app
+            .call(post(huge_shape()))
+            .map(|response| (response, Instant::now()));
+        let small = app
+            .call(post(small_shape))
+            .map(|response| (response, Instant::now()));
+        let ((huge, huge_done), (small, small_done)) = test::block_on(huge.join(small)).unwrap();
+
+        // the small request gets its places without waiting for the validation nor the search
+        // of the huge shape
+        assert_eq!(small.status(), 200);
+        assert!(small_done < huge_done);
+        assert!(small_done.duration_since(started) < es_delay);
+        // the huge shape is either searched (slowly) or rejected by the time budget
+        match huge.status().as_u16() {
+            200 => assert!(huge_done.duration_since(started) >= es_delay),
+            400 => {
+                let body: serde_json::Value =
+                    serde_json::from_slice(&test::read_body(huge)).unwrap();
+                assert_eq!(body["code"], json!("SHAPE_VALIDATION_TIMEOUT"));
+            }
+            status => panic!("unexpected status {} for the huge shape", status),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn valid_filters_build_well_formed_queries(
+            lon in proptest::option::of(-200f64..200f64),
+            lat in proptest::option::of(-100f64..100f64),
+            focus_bbox in proptest::option::of("-?[0-9]{1,3}(\\.[0-9]{1,2})?(,-?[0-9]{1,3}(\\.[0-9]{1,2})?){1,4}"),
+            date in proptest::option::of("20[0-9]{2}-[0-9]{2}-[0-9]{2}"),
+            exclude_ids in proptest::collection::vec("[a-z]{1,5}:[0-9]{1,5}", 0..110),
+            datasets_blacklist in proptest::collection::vec("[a-z]{1,5}", 0..3),
+            types in proptest::collection::vec(
+                prop_oneof![Just("city"), Just("house"), Just("poi"), Just("street"), Just("zone")],
+                0..3,
+            ),
+            zone_types in proptest::collection::vec(prop_oneof![Just("city"), Just("suburb")], 0..2),
+            poi_types in proptest::collection::vec("[a-z]{1,10}", 0..2),
+        ) {
+            let params = params(json!({
+                "q": "paris",
+                "lon": lon,
+                "lat": lat,
+                "focus_bbox": focus_bbox,
+                "date": date,
+                "exclude_ids": exclude_ids,
+                "datasets_blacklist": datasets_blacklist,
+                "type": types,
+                "zone_type": zone_types,
+                "poi_type": poi_types,
+            }));
+            match Filters::try_from(&params) {
+                Ok(filters) => {
+                    let query_settings =
+                        QuerySettings::new(include_str!("../../../../config/bragi-settings.toml"))
+                            .unwrap();
+                    let query = query::build_query(
+                        &params.q,
+                        query::MatchType::Prefix,
+                        true,
+                        filters.coord,
+                        None,
+                        &[],
+                        filters.focus_bbox,
+                        &[],
+                        false,
+                        &filters.date,
+                        &[],
+                        &filters.zone_types,
+                        &filters.poi_types,
+                        &query_settings,
+                    );
+                    let query = query::build_exclusions_filter(query, &filters.exclusions);
+                    let json = serde_json::to_string(&query).unwrap();
+                    // a query with an invalid number (NaN or infinite) can not be read back
+                    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+                    let root = value.as_object().unwrap();
+                    prop_assert_eq!(root.len(), 1);
+                    prop_assert!(root.contains_key("bool") || root.contains_key("function_score"));
+                }
+                Err(errors) => prop_assert!(!errors.problems.is_empty()),
+            }
+        }
+    }
 }
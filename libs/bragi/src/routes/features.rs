@@ -1,4 +1,6 @@
-use crate::extractors::BragiQuery;
+use crate::circuit_breaker::INDEX_TYPES;
+use crate::extractors::{ApiProfile, BragiQuery};
+use crate::routes::params;
 use crate::{model, model::FromWithLang, query, Context};
 use actix_http::http::header::{CacheControl, CacheDirective};
 use actix_web::web::{Data, HttpResponse, Path};
@@ -7,44 +9,93 @@ use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Params {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
     pt_dataset: Vec<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
     poi_dataset: Vec<String>,
     #[serde(rename = "_all_data", default)]
     all_data: bool,
     /// timeout in milliseconds
     timeout: Option<u64>,
+    /// adds the alternative names of the place to the response
+    #[serde(default)]
+    include_alt_names: bool,
+    /// adds their bbox to the features of the zones, to zoom on them
+    #[serde(default)]
+    include_bbox: bool,
+    /// adds their population (if known) and their area to the features of the zones
+    #[serde(default)]
+    include_demographics: bool,
+    /// wraps each feature with its score, distance and confidence
+    /// (the flat features are returned by default for backward compatibility)
+    #[serde(default)]
+    envelope: bool,
 }
 
 pub fn features(
+    profile: ApiProfile,
     params: BragiQuery<Params>,
     state: Data<Context>,
     id: Path<String>,
 ) -> Result<HttpResponse, model::BragiError> {
+    // the restrictions of the api keys are only applied by the autocomplete
+    profile.check_unrestricted()?;
     let rubber = state.get_rubber_for_features(params.timeout.map(Duration::from_millis));
-    let features = query::features(
-        &params
-            .pt_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        &params
-            .poi_dataset
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>(),
-        params.all_data,
-        &*id,
-        rubber,
-    );
+    let features = state.get_circuit_breakers().call(&INDEX_TYPES, || {
+        query::features(
+            &params
+                .pt_dataset
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            &params
+                .poi_dataset
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            params.all_data,
+            &*id,
+            rubber,
+        )
+    });
     features
         .map(|r| model::Autocomplete::from_with_lang(r, None))
+        .map(|mut v| {
+            for feature in &mut v.features {
+                let geocoding = &mut feature.properties.geocoding;
+                if geocoding.id != *id {
+                    geocoding.moved_from = Some((*id).clone());
+                }
+            }
+            if params.include_alt_names {
+                v
+            } else {
+                v.without_alt_names()
+            }
+        })
+        .map(|v| {
+            if params.include_bbox {
+                v.with_bbox()
+            } else {
+                v
+            }
+        })
+        .map(|v| {
+            if params.include_demographics {
+                v
+            } else {
+                v.without_demographics()
+            }
+        })
         .map(|v| {
-            HttpResponse::Ok()
-                .set(CacheControl(vec![CacheDirective::MaxAge(
-                    state.http_cache_duration,
-                )]))
-                .json(v)
+            let mut response = HttpResponse::Ok();
+            response.set(CacheControl(vec![CacheDirective::MaxAge(
+                state.http_cache_duration,
+            )]));
+            if params.envelope {
+                response.json(v.into_envelope())
+            } else {
+                response.json(v)
+            }
         })
 }
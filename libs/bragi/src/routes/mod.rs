@@ -1,12 +1,22 @@
 mod autocomplete;
 mod entry_point;
 mod features;
+mod nearby;
 mod params;
+mod readiness;
+mod refresh;
 mod reverse;
+mod reverse_batch;
+mod settings;
 mod status;
 
-pub use autocomplete::{autocomplete, post_autocomplete, JsonParams};
+pub use autocomplete::{autocomplete, json_config, post_autocomplete, suggest};
 pub use entry_point::entry_point;
 pub use features::features;
+pub use nearby::nearby;
+pub use readiness::readiness;
+pub use refresh::refresh;
 pub use reverse::reverse;
+pub use reverse_batch::reverse_batch;
+pub use settings::{settings, EffectiveSettings};
 pub use status::status;
@@ -0,0 +1,105 @@
+use crate::extractors::{ApiProfile, BragiQuery};
+use crate::model::BragiError;
+use crate::routes::params;
+use crate::{model, model::FromWithLang, query, Context};
+use actix_http::http::header::{CacheControl, CacheDirective};
+use actix_web::web::{Data, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// maximum radius of the search in meters, to keep the geo distance filter cheap
+const MAX_RADIUS: f64 = 50_000.;
+
+fn default_radius() -> f64 {
+    1_000.
+}
+
+fn default_limit() -> u64 {
+    10
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Params {
+    lat: f64,
+    lon: f64,
+    /// radius of the search around the coordinate, in meters
+    #[serde(default = "default_radius")]
+    radius: f64,
+    /// only the pois of these poi types, all the pois if empty
+    #[serde(
+        default,
+        rename = "poi_type",
+        deserialize_with = "params::deserialize_canonical"
+    )]
+    poi_types: Vec<String>,
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
+    poi_dataset: Vec<String>,
+    #[serde(rename = "_all_data", default)]
+    all_data: bool,
+    /// only the pois imported with one of these datasets, all the datasets if empty
+    #[serde(default, deserialize_with = "params::deserialize_canonical")]
+    datasets: Vec<String>,
+    #[serde(default = "default_limit")]
+    limit: u64,
+    #[serde(default)]
+    offset: u64,
+    /// timeout in milliseconds
+    timeout: Option<u64>,
+    lang: Option<String>,
+    /// wraps each feature with its score, distance and confidence
+    /// (the flat features are returned by default for backward compatibility)
+    #[serde(default)]
+    envelope: bool,
+}
+
+fn as_str(values: &[String]) -> Vec<&str> {
+    values.iter().map(String::as_str).collect()
+}
+
+/// The pois around a coordinate, sorted by distance (eg the cafés near the user)
+pub fn nearby(
+    profile: ApiProfile,
+    params: BragiQuery<Params>,
+    state: Data<Context>,
+) -> Result<HttpResponse, BragiError> {
+    // the restrictions of the api keys are only applied by the autocomplete
+    profile.check_unrestricted()?;
+    let coord = params::make_coord(params.lon, params.lat)?;
+    if !(params.radius > 0. && params.radius <= MAX_RADIUS) {
+        return Err(BragiError::InvalidParam(
+            "radius should be a distance in meters between 0 and 50000",
+        ));
+    }
+    let rubber = state.get_rubber_for_reverse(params.timeout.map(Duration::from_millis));
+    let query_settings = state.get_query_settings();
+    let limit = params::clamp_limit(params.limit, query_settings.query.max_limit);
+    let tie_breakers = &query_settings.tie_breakers;
+    state
+        .get_circuit_breakers()
+        .call(&["poi"], || {
+            query::nearby(
+                &as_str(&params.poi_dataset),
+                params.all_data,
+                &coord,
+                params.radius,
+                &as_str(&params.poi_types),
+                &as_str(&params.datasets),
+                params.offset,
+                limit,
+                tie_breakers,
+                rubber,
+            )
+        })
+        .map(|places| model::Autocomplete::from_with_lang(places, params.lang.as_deref()))
+        .map(|v| {
+            let mut response = HttpResponse::Ok();
+            response.set(CacheControl(vec![CacheDirective::MaxAge(
+                state.http_cache_duration,
+            )]));
+            if params.envelope {
+                response.json(v.into_envelope())
+            } else {
+                response.json(v)
+            }
+        })
+}
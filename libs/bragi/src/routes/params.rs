@@ -1,5 +1,11 @@
 use crate::model::BragiError;
-use mimir::objects::Coord;
+use geo_types::{Coordinate, Rect};
+use mimir::objects::{canonical_case, Coord};
+use regex::Regex;
+use serde::de::value::StringDeserializer;
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer};
+use slog_scope::warn;
 
 pub fn make_coord(lon: f64, lat: f64) -> Result<Coord, BragiError> {
     if !(-90f64..=90f64).contains(&lat) {
@@ -10,3 +16,481 @@ pub fn make_coord(lon: f64, lat: f64) -> Result<Coord, BragiError> {
         Ok(Coord::new(lon, lat))
     }
 }
+
+/// Deserializes the values of a filter in their canonical case (see `mimir::objects::canonical_case`),
+/// so that `datasets=FR` matches the places of the dataset `fr`
+pub fn deserialize_canonical<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|value| {
+            let value: StringDeserializer<D::Error> = canonical_case(&value).into_deserializer();
+            T::deserialize(value)
+        })
+        .collect()
+}
+
+/// The query string, trimmed, with its runs of whitespace collapsed into a single space.
+///
+/// All the unicode whitespaces (like the no-break or the ideographic spaces) are spaces,
+/// and the invisible characters pasted with a text (zero width spaces, byte order marks)
+/// are removed, so that the query is searched (and logged) the same however it has been typed.
+pub fn normalize_query(q: &str) -> String {
+    q.split(char::is_whitespace)
+        .map(|word| word.replace(is_invisible, ""))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// the zero width joiners are kept, they are part of some emojis and scripts
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}')
+}
+
+/// Deserializes the query string normalized by `normalize_query`
+pub fn deserialize_query<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|q| normalize_query(&q))
+}
+
+lazy_static::lazy_static! {
+    // two decimal degrees, each with an optional hemisphere, separated by a comma or spaces
+    static ref COORD_QUERY: Regex = Regex::new(
+        r"(?i)^\s*([+-]?\d{1,3}\.\d+)\s*°?\s*([NSEW])?\s*(?:,|\s)\s*([+-]?\d{1,3}\.\d+)\s*°?\s*([NSEW])?\s*$"
+    )
+    .unwrap();
+}
+
+// a value of a coordinate, with its hemisphere if given
+struct Degrees {
+    value: f64,
+    hemisphere: Option<char>,
+}
+
+impl Degrees {
+    fn is_lat(&self) -> Option<bool> {
+        self.hemisphere.map(|h| h == 'N' || h == 'S')
+    }
+
+    // the value is signed by its hemisphere, a signed value with a hemisphere is invalid
+    fn signed(&self) -> Option<f64> {
+        match self.hemisphere {
+            Some(_) if self.value < 0. => None,
+            Some('S') | Some('W') => Some(-self.value),
+            _ => Some(self.value),
+        }
+    }
+}
+
+fn is_valid(lat: f64, lon: f64) -> bool {
+    (-90f64..=90f64).contains(&lat) && (-180f64..=180f64).contains(&lon)
+}
+
+/// The coordinate of a query made of a coordinate (as pasted from a map), like `48.8566, 2.3522`
+/// or `48.8566N 2.3522E`, in decimal degrees.
+///
+/// The order is given by the hemispheres if any. Without them, it is `lat, lon`,
+/// unless only `lon, lat` gives a valid coordinate. None if the query is not a valid coordinate.
+pub fn detect_coord(q: &str) -> Option<Coord> {
+    let captures = COORD_QUERY.captures(q)?;
+    let degrees = |value: usize, hemisphere: usize| {
+        Some(Degrees {
+            value: captures[value].parse().ok()?,
+            hemisphere: captures
+                .get(hemisphere)
+                .and_then(|h| h.as_str().chars().next())
+                .map(|h| h.to_ascii_uppercase()),
+        })
+    };
+    let (first, second) = (degrees(1, 2)?, degrees(3, 4)?);
+    let lat_first = match (first.is_lat(), second.is_lat()) {
+        (Some(first_is_lat), Some(second_is_lat)) if first_is_lat == second_is_lat => return None,
+        (Some(first_is_lat), _) => first_is_lat,
+        (None, Some(second_is_lat)) => !second_is_lat,
+        (None, None) => is_valid(first.value, second.value) || !is_valid(second.value, first.value),
+    };
+    let (lat, lon) = if lat_first {
+        (first.signed()?, second.signed()?)
+    } else {
+        (second.signed()?, first.signed()?)
+    };
+    if is_valid(lat, lon) {
+        Some(Coord::new(lon, lat))
+    } else {
+        None
+    }
+}
+
+// prefixes of the ids given by the importers to each type of place
+const ID_PREFIXES: &[&str] = &["admin:", "addr:", "street:", "poi:", "stop_area:"];
+
+/// The id of a query made of the id of a place (as pasted from another response),
+/// like `admin:osm:relation:71525` or `stop_area:SA:main_station`.
+///
+/// Only the prefixes of the ids of the places are recognized, so that the queries with a colon
+/// (like `gare: lyon`) are still searched as texts.
+pub fn detect_id(q: &str) -> Option<&str> {
+    let q = q.trim();
+    ID_PREFIXES
+        .iter()
+        .find(|prefix| q.starts_with(*prefix))
+        .filter(|prefix| q.len() > prefix.len() && !q.contains(char::is_whitespace))
+        .map(|_| q)
+}
+
+/// The opaque cursor of the page of browsed places ending with the place `last_id`
+/// (the hex of its id, to be given as is in the url of the next page)
+pub fn encode_cursor(last_id: &str) -> String {
+    last_id.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The id of the place after which the places are browsed with the given cursor,
+/// `None` for an empty cursor (the first page)
+pub fn decode_cursor(cursor: &str) -> Result<Option<String>, BragiError> {
+    let invalid = || BragiError::InvalidParam("the 'cursor' parameter is not a cursor of Bragi");
+    if cursor.is_empty() {
+        return Ok(None);
+    }
+    if cursor.len() % 2 != 0 || !cursor.is_ascii() {
+        return Err(invalid());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    String::from_utf8(bytes).map(Some).map_err(|_| invalid())
+}
+
+/// The number of results requested, clamped to the maximum of the settings so that
+/// no client can make ES fetch a huge number of documents
+pub fn clamp_limit(limit: u64, max_limit: u64) -> u64 {
+    if limit > max_limit {
+        warn!(
+            "the limit {} of the request is clamped to the maximum of {}",
+            limit, max_limit
+        );
+        max_limit
+    } else {
+        limit
+    }
+}
+
+/// Parses a bounding box formatted as `min_lon,min_lat,max_lon,max_lat`
+/// (the GeoJSON order)
+pub fn make_bbox(bbox: &str) -> Result<Rect<f64>, BragiError> {
+    let invalid = || {
+        BragiError::InvalidParam("a bbox should be formatted as 'min_lon,min_lat,max_lon,max_lat'")
+    };
+    let values = bbox
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values.as_slice() {
+        [min_lon, min_lat, max_lon, max_lat] => {
+            let min = make_coord(*min_lon, *min_lat)?;
+            let max = make_coord(*max_lon, *max_lat)?;
+            if min.lon() > max.lon() || min.lat() > max.lat() {
+                return Err(invalid());
+            }
+            Ok(Rect::new(
+                Coordinate {
+                    x: min.lon(),
+                    y: min.lat(),
+                },
+                Coordinate {
+                    x: max.lon(),
+                    y: max.lat(),
+                },
+            ))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Best of `supported_langs` for an `Accept-Language` header, like `de-CH, fr;q=0.8, *;q=0.1`.
+///
+/// The languages are tried by decreasing quality, a regional variant (`de-CH`) matches its language (`de`)
+/// and `*` matches any language not refused with `q=0`. The malformed languages are ignored.
+pub fn best_accept_language<'a>(header: &str, supported_langs: &'a [String]) -> Option<&'a str> {
+    let mut accepted: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let tag = parts.next().filter(|tag| {
+                !tag.is_empty()
+                    && tag
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '*')
+            })?;
+            let quality = match parts.next() {
+                None => 1.,
+                Some(param) => param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .filter(|q| (0. ..=1.).contains(q))?,
+            };
+            Some((tag, quality))
+        })
+        .collect();
+    // the sort is stable, the order of the header is kept between the languages of same quality
+    accepted.sort_by(|(_, q1), (_, q2)| q2.partial_cmp(q1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let matches = |tag: &str, lang: &str| {
+        let language = tag.split('-').next().unwrap_or(tag);
+        tag.eq_ignore_ascii_case(lang) || language.eq_ignore_ascii_case(lang)
+    };
+    let refused = |lang: &str| {
+        accepted
+            .iter()
+            .any(|&(tag, quality)| quality <= 0. && matches(tag, lang))
+    };
+    accepted
+        .iter()
+        .filter(|&&(_, quality)| quality > 0.)
+        .find_map(|&(tag, _)| {
+            supported_langs
+                .iter()
+                .map(String::as_str)
+                .find(|&lang| !refused(lang) && (tag == "*" || matches(tag, lang)))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_are_trimmed_and_collapsed() {
+        assert_eq!(
+            normalize_query("  rue   hector\tmalot \n"),
+            "rue hector malot"
+        );
+        assert_eq!(normalize_query("gare"), "gare");
+        assert_eq!(normalize_query(""), "");
+    }
+
+    #[test]
+    fn other_scripts_are_untouched() {
+        for q in &[
+            "شارع الملك فهد",
+            "יְרוּשָׁלַיִם",
+            "東京都庁",
+            "東京 tower",
+            // the zero width non-joiner is part of the persian words
+            "می\u{200C}خواهم",
+        ] {
+            assert_eq!(normalize_query(q), *q);
+        }
+        assert_eq!(normalize_query("東京\u{3000}タワー"), "東京 タワー");
+    }
+
+    #[test]
+    fn unicode_whitespaces_are_spaces() {
+        let whitespaces = [
+            '\u{0009}', // tab
+            '\u{000A}', // line feed
+            '\u{000B}', // vertical tab
+            '\u{000C}', // form feed
+            '\u{000D}', // carriage return
+            '\u{0085}', // next line
+            '\u{00A0}', // no-break space
+            '\u{1680}', // ogham space mark
+            '\u{2000}', // en quad
+            '\u{2003}', // em space
+            '\u{2007}', // figure space
+            '\u{2009}', // thin space
+            '\u{200A}', // hair space
+            '\u{2028}', // line separator
+            '\u{2029}', // paragraph separator
+            '\u{202F}', // narrow no-break space
+            '\u{205F}', // medium mathematical space
+            '\u{3000}', // ideographic space
+        ];
+        for whitespace in &whitespaces {
+            let q = format!("{0}{0}rue{0}hector {0} malot{0}", whitespace);
+            assert_eq!(normalize_query(&q), "rue hector malot", "{:?}", whitespace);
+        }
+        let all: String = whitespaces.iter().collect();
+        assert_eq!(normalize_query(&all), "");
+    }
+
+    #[test]
+    fn invisible_characters_are_removed() {
+        assert_eq!(
+            normalize_query("\u{FEFF}gare\u{200B} de\u{2060} lyon"),
+            "gare de lyon"
+        );
+        assert_eq!(normalize_query(" \u{200B} \u{FEFF} "), "");
+        // the zero width joiner is part of the emoji
+        assert_eq!(normalize_query(" 👩\u{200D}🚒 "), "👩\u{200D}🚒");
+    }
+
+    #[test]
+    fn only_the_queries_looking_like_an_id_are_looked_up() {
+        assert_eq!(
+            detect_id("admin:osm:relation:71525"),
+            Some("admin:osm:relation:71525")
+        );
+        assert_eq!(
+            detect_id(" stop_area:SA:main_station "),
+            Some("stop_area:SA:main_station")
+        );
+        assert_eq!(
+            detect_id("addr:2.68385;48.50539:3"),
+            Some("addr:2.68385;48.50539:3")
+        );
+        assert_eq!(detect_id("gare: lyon"), None);
+        assert_eq!(detect_id("poi: musée"), None);
+        assert_eq!(detect_id("street:"), None);
+        assert_eq!(detect_id("rue de la gare"), None);
+        assert_eq!(detect_id("metro:14"), None);
+    }
+
+    #[test]
+    fn cursors_are_decoded_back() {
+        let id = "addr:2.376379;48.846495:15";
+        assert_eq!(
+            decode_cursor(&encode_cursor(id)).unwrap().as_deref(),
+            Some(id)
+        );
+        assert_eq!(decode_cursor("").unwrap(), None);
+        assert!(decode_cursor("abc").is_err());
+        assert!(decode_cursor("zz").is_err());
+        assert!(decode_cursor("ff").is_err());
+    }
+
+    #[test]
+    fn valid_bbox() {
+        let bbox = make_bbox("2.2,48.8, 2.5,48.9").unwrap();
+        assert_eq!(bbox.min(), Coordinate { x: 2.2, y: 48.8 });
+        assert_eq!(bbox.max(), Coordinate { x: 2.5, y: 48.9 });
+    }
+
+    fn langs(langs: &[&str]) -> Vec<String> {
+        langs.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn accept_language_by_quality() {
+        let supported = langs(&["en", "fr", "de"]);
+        assert_eq!(best_accept_language("fr", &supported), Some("fr"));
+        assert_eq!(best_accept_language("it, de", &supported), Some("de"));
+        assert_eq!(
+            best_accept_language("en;q=0.5, fr;q=0.9, de;q=0.7", &supported),
+            Some("fr")
+        );
+        // the languages of same quality keep their order
+        assert_eq!(best_accept_language("de, fr", &supported), Some("de"));
+        // a regional variant matches its language, without case
+        assert_eq!(best_accept_language("DE-ch", &supported), Some("de"));
+        assert_eq!(best_accept_language("it", &supported), None);
+        assert_eq!(best_accept_language("it", &[]), None);
+    }
+
+    #[test]
+    fn accept_language_wildcard() {
+        let supported = langs(&["en", "fr"]);
+        assert_eq!(best_accept_language("it, *;q=0.1", &supported), Some("en"));
+        assert_eq!(best_accept_language("*, en;q=0", &supported), Some("fr"));
+        assert_eq!(best_accept_language("*;q=0", &supported), None);
+    }
+
+    #[test]
+    fn malformed_accept_language() {
+        let supported = langs(&["en", "fr"]);
+        for header in &[
+            "",
+            ",,",
+            ";q=1",
+            "fr;q=",
+            "fr;q=abc",
+            "fr;q=2",
+            "fr;x=1",
+            "f r",
+            "fr\"\u{e9}",
+        ] {
+            assert_eq!(best_accept_language(header, &supported), None, "{}", header);
+        }
+        // the malformed languages are skipped, not the whole header
+        assert_eq!(
+            best_accept_language("en;q=abc, fr;q=0.5", &supported),
+            Some("fr")
+        );
+    }
+
+    fn lon_lat(q: &str) -> Option<(f64, f64)> {
+        detect_coord(q).map(|coord| (coord.lon(), coord.lat()))
+    }
+
+    #[test]
+    fn coord_queries() {
+        assert_eq!(lon_lat("48.8566, 2.3522"), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat(" 48.8566 2.3522 "), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat("48.8566,2.3522"), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat("-33.8688, 151.2093"), Some((151.2093, -33.8688)));
+        assert_eq!(lon_lat("48.8566N 2.3522E"), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat("33.8688 s, 151.2093 e"), Some((151.2093, -33.8688)));
+        assert_eq!(lon_lat("48.8566° N, 2.3522° W"), Some((-2.3522, 48.8566)));
+    }
+
+    #[test]
+    fn coord_queries_order() {
+        // lat, lon by default
+        assert_eq!(lon_lat("2.3522, 48.8566"), Some((48.8566, 2.3522)));
+        // lon, lat if only this order is valid
+        assert_eq!(lon_lat("151.2093, -33.8688"), Some((151.2093, -33.8688)));
+        // the hemispheres give the order
+        assert_eq!(lon_lat("2.3522E 48.8566N"), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat("2.3522E, 48.8566"), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat("2.3522, 48.8566N"), Some((2.3522, 48.8566)));
+        assert_eq!(lon_lat("48.8566N 2.3522N"), None);
+        assert_eq!(lon_lat("2.3522E 48.8566W"), None);
+    }
+
+    #[test]
+    fn not_coord_queries() {
+        for q in &[
+            "",
+            "paris",
+            "12 rue hector malot",
+            "48 2",
+            "48.8566",
+            "48.8566, 2.3522, 3.5",
+            // out of range
+            "148.8566, 200.3522",
+            "48.8566, 190.1",
+            "95.1N 2.3E",
+            // a signed value cannot have a hemisphere
+            "-48.8566N 2.3522E",
+            // the degrees, minutes and seconds are not supported
+            "48°51'24\"N 2°21'8\"E",
+            "48°51.4'N, 2°21.1'E",
+            "48 51 24 N 2 21 8 E",
+        ] {
+            assert!(detect_coord(q).is_none(), "{}", q);
+        }
+    }
+
+    #[test]
+    fn limit_is_clamped() {
+        assert_eq!(clamp_limit(10_000, 100), 100);
+        assert_eq!(clamp_limit(100, 100), 100);
+        assert_eq!(clamp_limit(10, 100), 10);
+    }
+
+    #[test]
+    fn invalid_bbox() {
+        assert!(make_bbox("2.2,48.8,2.5").is_err());
+        assert!(make_bbox("2.2,48.8,2.5,foo").is_err());
+        // min and max are swapped
+        assert!(make_bbox("2.5,48.9,2.2,48.8").is_err());
+        assert!(make_bbox("2.2,48.8,2.5,98.9").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use super::autocomplete::DATE_FORMAT;
+use super::status::{get_type_statuses, missing_required_types};
+use crate::cancellation::Cancellation;
+use crate::circuit_breaker::{CircuitState, INDEX_TYPES};
+use crate::query::Exclusions;
+use crate::{query, Context};
+use actix_web::web::{Data, HttpResponse};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use slog_scope::warn;
+
+// the expected document must be in the first results of the canary query
+const CANARY_LIMIT: u64 = 10;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Readiness {
+    /// "ready" or "degraded"
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+// run the canary query of the settings, if any, and check that it finds the expected document
+fn check_canary(state: &Context) -> Result<(), String> {
+    let query_settings = state.get_query_settings();
+    let canary = match query_settings.readiness.canary {
+        Some(ref canary) => canary,
+        None => return Ok(()),
+    };
+    let (places, _) = state
+        .get_circuit_breakers()
+        .call(&INDEX_TYPES, || {
+            query::autocomplete(
+                &canary.query,
+                &[],
+                &[],
+                false,
+                &Utc::today().format(DATE_FORMAT).to_string(),
+                0,
+                CANARY_LIMIT,
+                None,
+                None,
+                &[],
+                None,
+                &Exclusions::default(),
+                None,
+                &[],
+                &[],
+                &[],
+                &[],
+                &[],
+                &[],
+                state.get_rubber_for_autocomplete(None),
+                false,
+                false,
+                query_settings,
+                None,
+                None,
+                None,
+                false,
+                &Cancellation::default(),
+            )
+        })
+        .map_err(|e| format!("the canary query failed: {}", e))?;
+    if places.iter().any(|p| p.id() == canary.expected_id) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} not found by the canary query '{}'",
+            canary.expected_id, canary.query
+        ))
+    }
+}
+
+// the types whose circuit breaker is open, among the required types (all of them if none is required)
+fn open_circuit_types(state: &Context) -> Vec<String> {
+    let required_types = &state.get_query_settings().service.required_types;
+    state
+        .get_circuit_breakers()
+        .states()
+        .into_iter()
+        .filter(|(index_type, circuit_state)| {
+            *circuit_state == CircuitState::Open
+                && (required_types.is_empty() || required_types.contains(index_type))
+        })
+        .map(|(index_type, _)| index_type)
+        .collect()
+}
+
+fn check_readiness(state: &Context) -> Result<(), String> {
+    let open_types = open_circuit_types(state);
+    if !open_types.is_empty() {
+        return Err(format!(
+            "the ES circuit breaker is open for: {}",
+            open_types.join(", ")
+        ));
+    }
+    // the optional types may have no index
+    let types = get_type_statuses(state)?;
+    let missing_types = missing_required_types(&types);
+    if !missing_types.is_empty() {
+        return Err(format!(
+            "no index for the required types: {}",
+            missing_types.join(", ")
+        ));
+    }
+    check_canary(state)
+}
+
+/// Readiness check: unlike /status, checks that the search works
+/// (with the required types of places in ES, and the canary query of the settings),
+/// and returns a 503 if it does not
+pub fn readiness(state: Data<Context>) -> HttpResponse {
+    match check_readiness(&state) {
+        Ok(()) => HttpResponse::Ok().json(Readiness {
+            status: "ready".to_string(),
+            reason: None,
+        }),
+        Err(reason) => {
+            warn!("bragi is not ready: {}", reason);
+            HttpResponse::ServiceUnavailable().json(Readiness {
+                status: "degraded".to_string(),
+                reason: Some(reason),
+            })
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::circuit_breaker::INDEX_TYPES;
+use crate::extractors::{ActixError, ApiProfile};
+use crate::model::BragiError;
+use crate::Context;
+use actix_web::web::{Data, HttpResponse};
+use actix_web::HttpRequest;
+use serde::{Deserialize, Serialize};
+
+// all the indexes of the places, published or not
+const REFRESHED_INDEXES: &str = "munin_*";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Refresh {
+    /// Pattern of the refreshed indexes
+    pub indexes: String,
+}
+
+/// Refreshes the indexes, so that the documents just indexed can be found.
+/// Only available with `--enable-refresh`, and not to the restricted api keys.
+pub fn refresh(
+    req: HttpRequest,
+    profile: ApiProfile,
+    state: Data<Context>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !state.is_refresh_enabled() {
+        return Err(ActixError::RouteNotFound(req.path().to_string()).into());
+    }
+    profile.check_unrestricted()?;
+    let rubber = state.get_rubber_for_autocomplete(None);
+    state.get_circuit_breakers().call(&INDEX_TYPES, || {
+        rubber
+            .refresh_indexes(REFRESHED_INDEXES)
+            .map_err(BragiError::from)
+    })?;
+    // the refreshed indexes may be new datasets
+    state.invalidate_known_datasets();
+    Ok(HttpResponse::Ok().json(Refresh {
+        indexes: REFRESHED_INDEXES.to_string(),
+    }))
+}
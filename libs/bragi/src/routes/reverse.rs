@@ -1,4 +1,6 @@
-use crate::extractors::BragiQuery;
+use crate::circuit_breaker::index_types;
+use crate::extractors::{ApiProfile, BragiQuery};
+use crate::geocodejson::{Format, GeocodeJson};
 use crate::routes::params;
 use crate::{model, model::FromWithLang, Context};
 use actix_http::http::header::{CacheControl, CacheDirective};
@@ -12,23 +14,46 @@ pub struct Params {
     lon: f64,
     /// timeout in milliseconds
     timeout: Option<u64>,
+    /// wraps each feature with its score, distance and confidence
+    /// (the flat features are returned by default for backward compatibility)
+    #[serde(default)]
+    envelope: bool,
+    /// format of the response, `geocodejson` to follow strictly the geocodejson spec
+    #[serde(default)]
+    format: Format,
 }
 
 pub fn reverse(
+    profile: ApiProfile,
     params: BragiQuery<Params>,
     state: Data<Context>,
 ) -> Result<HttpResponse, model::BragiError> {
+    // the restrictions of the api keys are only applied by the autocomplete
+    profile.check_unrestricted()?;
+    if params.envelope && params.format == Format::GeocodeJson {
+        return Err(model::BragiError::InvalidParam(
+            "the 'geocodejson' format cannot be used with 'envelope'",
+        ));
+    }
     let mut rubber = state.get_rubber_for_reverse(params.timeout.map(Duration::from_millis));
     let coord = params::make_coord(params.lon, params.lat)?;
-    rubber
-        .get_address(&coord)
-        .map_err(model::BragiError::from)
+    state
+        .get_circuit_breakers()
+        .call(&index_types(&["house", "street"]), || {
+            rubber.get_address(&coord).map_err(model::BragiError::from)
+        })
         .map(|r| model::Autocomplete::from_with_lang(r, None))
         .map(|v| {
-            HttpResponse::Ok()
-                .set(CacheControl(vec![CacheDirective::MaxAge(
-                    state.http_cache_duration,
-                )]))
-                .json(v)
+            let mut response = HttpResponse::Ok();
+            response.set(CacheControl(vec![CacheDirective::MaxAge(
+                state.http_cache_duration,
+            )]));
+            if params.envelope {
+                response.json(v.into_envelope())
+            } else if params.format == Format::GeocodeJson {
+                response.json(GeocodeJson::from(v))
+            } else {
+                response.json(v)
+            }
         })
 }
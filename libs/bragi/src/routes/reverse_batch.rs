@@ -0,0 +1,399 @@
+use crate::circuit_breaker::index_types;
+use crate::extractors::{ActixError, ApiProfile, BragiQuery};
+use crate::model::{Autocomplete, BragiError, Feature, FromWithLang, ValidationError};
+use crate::routes::params;
+use crate::search_pool::search_pool;
+use crate::Context;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::web::{Bytes, Data, HttpResponse, Payload};
+use actix_web::HttpRequest;
+use futures::sync::mpsc::{self, UnboundedReceiver};
+use futures::{future, Async, Future, Poll, Stream};
+use mimir::objects::Coord;
+use mimir::rubber::Rubber;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const NDJSON: &str = "application/x-ndjson";
+// the bodies are limited to this size by point, for the json of a point and its id
+const MAX_POINT_SIZE: usize = 256;
+const EARTH_RADIUS: f64 = 6_371_008.8;
+// error of the lookups which have panicked
+const LOOKUP_FAILED: &str = "the lookup has failed";
+
+#[derive(Deserialize, Debug)]
+pub struct Params {
+    /// timeout in milliseconds of each lookup
+    timeout: Option<u64>,
+    /// the points close to the point looked up before them reuse its result
+    #[serde(default)]
+    simplify: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Point {
+    lat: f64,
+    lon: f64,
+    /// id given by the caller, returned with the result of the point
+    #[serde(default)]
+    id: Option<Value>,
+}
+
+/// The features of a lookup, or its error
+type LookupResult = Result<Vec<Feature>, String>;
+
+/// Reverse geocodes a batch of points, given as a json array or as NDJSON (one point by line).
+///
+/// The results are streamed in the order of the points and in the format of the body,
+/// each one with the id of its point. The failed lookups give an error instead of the features,
+/// since the response has already started.
+pub fn reverse_batch(
+    req: HttpRequest,
+    profile: ApiProfile,
+    params: BragiQuery<Params>,
+    state: Data<Context>,
+    payload: Payload,
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let ndjson = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map_or(false, |content_type| content_type.starts_with(NDJSON));
+    let settings = state.get_query_settings().reverse_batch.clone();
+    let max_body_size = settings.max_size * MAX_POINT_SIZE;
+    // the restrictions of the api keys are only applied by the autocomplete
+    future::result(profile.check_unrestricted())
+        .from_err::<actix_web::Error>()
+        .and_then(move |()| {
+            payload.from_err::<actix_web::Error>().fold(
+                Vec::new(),
+                move |mut body, chunk: Bytes| {
+                    if body.len() + chunk.len() > max_body_size {
+                        return Err(ActixError::PayloadTooLarge(format!(
+                            "the body should not exceed {} bytes",
+                            max_body_size
+                        ))
+                        .into());
+                    }
+                    body.extend_from_slice(&chunk);
+                    Ok::<_, actix_web::Error>(body)
+                },
+            )
+        })
+        .and_then(move |body| -> Result<HttpResponse, actix_web::Error> {
+            let points = parse_points(&body, ndjson)?;
+            if points.len() > settings.max_size {
+                return Err(ActixError::PayloadTooLarge(format!(
+                    "the batch has {} points, the maximum is {}",
+                    points.len(),
+                    settings.max_size
+                ))
+                .into());
+            }
+            let coords = check_points(&points)?;
+            let simplify_distance = Some(settings.simplify_distance).filter(|_| params.simplify);
+            let (lookups, lookup_of_points) = plan_lookups(&coords, simplify_distance);
+            let rubber = state.get_rubber_for_reverse(params.timeout.map(Duration::from_millis));
+            let ids = points.into_iter().map(|point| point.id).collect();
+            let results = spawn_batch(
+                state,
+                rubber,
+                lookups,
+                ids,
+                lookup_of_points,
+                settings.concurrency,
+                ndjson,
+            );
+            Ok(HttpResponse::Ok()
+                .content_type(if ndjson { NDJSON } else { "application/json" })
+                .streaming(results.map_err(|()| BragiError::Cancelled)))
+        })
+}
+
+fn parse_points(body: &[u8], ndjson: bool) -> Result<Vec<Point>, BragiError> {
+    let mut errors = ValidationError::default();
+    let points = if ndjson {
+        body.split(|b| *b == b'\n')
+            .enumerate()
+            .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+            .filter_map(|(i, line)| match serde_json::from_slice(line) {
+                Ok(point) => Some(point),
+                Err(e) => {
+                    errors.problems.push(format!("line {}: {}", i + 1, e));
+                    None
+                }
+            })
+            .collect()
+    } else {
+        serde_json::from_slice(body).unwrap_or_else(|e| {
+            errors
+                .problems
+                .push(format!("the body is not an array of points: {}", e));
+            vec![]
+        })
+    };
+    if errors.problems.is_empty() {
+        Ok(points)
+    } else {
+        Err(errors.into())
+    }
+}
+
+fn check_points(points: &[Point]) -> Result<Vec<Coord>, BragiError> {
+    let mut errors = ValidationError::default();
+    let coords = points
+        .iter()
+        .enumerate()
+        .filter_map(
+            |(i, point)| match params::make_coord(point.lon, point.lat) {
+                Ok(coord) => Some(coord),
+                Err(e) => {
+                    errors.problems.push(format!("point {}: {}", i, e));
+                    None
+                }
+            },
+        )
+        .collect();
+    if errors.problems.is_empty() {
+        Ok(coords)
+    } else {
+        Err(errors.into())
+    }
+}
+
+fn distance(a: &Coord, b: &Coord) -> f64 {
+    let (lat_a, lat_b) = (a.lat().to_radians(), b.lat().to_radians());
+    let half_dlat = (lat_b - lat_a) / 2.;
+    let half_dlon = (b.lon() - a.lon()).to_radians() / 2.;
+    let h = half_dlat.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_dlon.sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// The coords to look up, and the lookup giving the result of each point.
+///
+/// With a `simplify_distance`, the points closer to the point looked up before them
+/// reuse its result: the distance is not taken from the previous point, so that a slow trace
+/// is still looked up every `simplify_distance` meters.
+fn plan_lookups(coords: &[Coord], simplify_distance: Option<f64>) -> (Vec<Coord>, Vec<usize>) {
+    let mut lookups: Vec<Coord> = vec![];
+    let lookup_of_points = coords
+        .iter()
+        .map(|coord| {
+            let reused = lookups.last().filter(|looked_up| {
+                simplify_distance.map_or(false, |max| distance(looked_up, coord) <= max)
+            });
+            if reused.is_none() {
+                lookups.push(*coord);
+            }
+            lookups.len() - 1
+        })
+        .collect();
+    (lookups, lookup_of_points)
+}
+
+/// Looks up the coords with `concurrency` jobs of the search pool,
+/// and streams the results of the points in order.
+///
+/// Once the client is gone, the lookups not started yet are abandoned.
+/// A lookup which panics gives an error, so that the response is always complete.
+fn spawn_batch(
+    state: Data<Context>,
+    rubber: Rubber,
+    lookups: Vec<Coord>,
+    ids: Vec<Option<Value>>,
+    lookup_of_points: Vec<usize>,
+    concurrency: usize,
+    ndjson: bool,
+) -> OrderedResults {
+    let lookups = Arc::new(lookups);
+    let next_lookup = Arc::new(AtomicUsize::new(0));
+    let (result_sender, result_receiver) = mpsc::unbounded::<(usize, LookupResult)>();
+    for _ in 0..concurrency.max(1).min(lookups.len()) {
+        let (lookups, next_lookup) = (lookups.clone(), next_lookup.clone());
+        let (state, mut rubber, result_sender) =
+            (state.clone(), rubber.clone(), result_sender.clone());
+        search_pool().execute(move || loop {
+            let i = next_lookup.fetch_add(1, Ordering::Relaxed);
+            if i >= lookups.len() {
+                break;
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                state
+                    .get_circuit_breakers()
+                    .call(&index_types(&["house", "street"]), || {
+                        rubber.get_address(&lookups[i]).map_err(BragiError::from)
+                    })
+                    .map(|places| Autocomplete::from_with_lang(places, None).features)
+                    .map_err(|e| e.to_string())
+            }))
+            .unwrap_or_else(|_| Err(LOOKUP_FAILED.to_string()));
+            // the receiver is gone with the client
+            if result_sender.unbounded_send((i, result)).is_err() {
+                break;
+            }
+        });
+    }
+    OrderedResults {
+        receiver: result_receiver,
+        received: HashMap::new(),
+        current: None,
+        ids,
+        lookup_of_points,
+        next_point: 0,
+        ndjson,
+        ended: false,
+    }
+}
+
+/// The results of the points of a batch, in the order of the points,
+/// whatever the order in which their lookups end
+struct OrderedResults {
+    receiver: UnboundedReceiver<(usize, LookupResult)>,
+    received: HashMap<usize, LookupResult>,
+    // the last lookup received, given to the following points reusing it
+    current: Option<(usize, LookupResult)>,
+    ids: Vec<Option<Value>>,
+    lookup_of_points: Vec<usize>,
+    next_point: usize,
+    ndjson: bool,
+    ended: bool,
+}
+
+impl Stream for OrderedResults {
+    type Item = Bytes;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, ()> {
+        let i = self.next_point;
+        if i >= self.ids.len() {
+            // the json array is closed once all the points are given
+            if self.ndjson || self.ended {
+                return Ok(Async::Ready(None));
+            }
+            self.ended = true;
+            let end = if i == 0 { "[]" } else { "]" };
+            return Ok(Async::Ready(Some(Bytes::from(end))));
+        }
+        let lookup = self.lookup_of_points[i];
+        while self.current.as_ref().map(|(looked_up, _)| *looked_up) != Some(lookup) {
+            if let Some(result) = self.received.remove(&lookup) {
+                self.current = Some((lookup, result));
+                break;
+            }
+            match self.receiver.poll()? {
+                Async::Ready(Some((looked_up, result))) => {
+                    self.received.insert(looked_up, result);
+                }
+                // the jobs are all gone without giving this lookup
+                Async::Ready(None) => {
+                    self.received.insert(lookup, Err(LOOKUP_FAILED.to_string()));
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        let (_, result) = self.current.as_ref().expect("the result has been received");
+        let id = self.ids[i].take();
+        let item = match result {
+            Ok(features) => json!({ "id": id, "features": features }),
+            Err(error) => json!({ "id": id, "error": error }),
+        };
+        let line = match (self.ndjson, i) {
+            (true, _) => format!("{}\n", item),
+            (false, 0) => format!("[{}", item),
+            (false, _) => format!(",{}", item),
+        };
+        self.next_point += 1;
+        Ok(Async::Ready(Some(Bytes::from(line))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_are_parsed_from_json_or_ndjson() {
+        let array =
+            br#"[{"lat": 48.8, "lon": 2.3, "id": "a"}, {"lat": 48.9, "lon": 2.4, "id": 2}]"#;
+        let points = parse_points(array, false).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].id, Some(json!(2)));
+
+        let lines = b"{\"lat\": 48.8, \"lon\": 2.3}\n\n{\"lat\": 48.9, \"lon\": 2.4}\n";
+        let points = parse_points(lines, true).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].id, None);
+
+        let err = parse_points(b"{\"lat\": 48.8, \"lon\": 2.3}\n{\"lat\": 48.9}", true);
+        match err {
+            Err(BragiError::InvalidParams(errors)) => {
+                assert_eq!(errors.problems.len(), 1);
+                assert!(errors.problems[0].starts_with("line 2:"));
+            }
+            _ => panic!("the second line has no lon"),
+        }
+    }
+
+    #[test]
+    fn results_are_given_in_order_and_always_closed() {
+        let (sender, receiver) = mpsc::unbounded();
+        let results = OrderedResults {
+            receiver,
+            received: HashMap::new(),
+            current: None,
+            ids: vec![
+                Some(json!("a")),
+                Some(json!("b")),
+                Some(json!("c")),
+                Some(json!("d")),
+            ],
+            // the second point reuses the first lookup, the third lookup is never given
+            lookup_of_points: vec![0, 0, 1, 2],
+            next_point: 0,
+            ndjson: false,
+            ended: false,
+        };
+        sender
+            .unbounded_send((1, Err("timeout".to_string())))
+            .unwrap();
+        sender.unbounded_send((0, Ok(vec![]))).unwrap();
+        drop(sender);
+        let body: Vec<u8> = results
+            .wait()
+            .map(Result::unwrap)
+            .flat_map(|bytes| bytes.to_vec())
+            .collect();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!([
+                { "id": "a", "features": [] },
+                { "id": "b", "features": [] },
+                { "id": "c", "error": "timeout" },
+                { "id": "d", "error": LOOKUP_FAILED },
+            ])
+        );
+    }
+
+    #[test]
+    fn close_points_reuse_the_previous_lookup() {
+        // a slow trace, the points are about 8m apart
+        let coords: Vec<Coord> = (0..6)
+            .map(|i| Coord::new(2.3, 48.8 + f64::from(i) * 0.000_07))
+            .collect();
+        let (lookups, lookup_of_points) = plan_lookups(&coords, None);
+        assert_eq!(lookups.len(), 6);
+        assert_eq!(lookup_of_points, vec![0, 1, 2, 3, 4, 5]);
+
+        // the distance is taken from the point looked up, not from the previous point
+        let (lookups, lookup_of_points) = plan_lookups(&coords, Some(20.));
+        assert_eq!(lookup_of_points, vec![0, 0, 0, 1, 1, 1]);
+        assert_eq!(lookups.len(), 2);
+        assert_eq!(lookups[1].lat(), coords[3].lat());
+    }
+}
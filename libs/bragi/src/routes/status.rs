@@ -1,20 +1,440 @@
+use crate::circuit_breaker::{CircuitState, INDEX_TYPES};
+use crate::query_settings::ServiceSettings;
+use crate::routes::settings::redact_url_password;
 use crate::Context;
-use actix_web::web::{Data, Json};
+use actix_web::http::header::{ETag, EntityTag, IfModifiedSince, IfNoneMatch, LastModified};
+use actix_web::web::Data;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use slog_scope::warn;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// set by the build script, empty if unknown
+const GIT_COMMIT: &str = env!("BRAGI_GIT_COMMIT");
+const BUILD_TIMESTAMP: &str = env!("BRAGI_BUILD_TIMESTAMP");
+const RUSTC_VERSION: &str = env!("BRAGI_RUSTC_VERSION");
+const FEATURES: &str = env!("BRAGI_FEATURES");
+
+const MS_PER_HOUR: i64 = 3_600_000;
+
+/// Information on the build of the running bragi
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Build {
+    pub commit: String,
+    /// UTC date of the build, formatted as RFC 3339
+    pub date: String,
+    pub rustc_version: String,
+    pub features: Vec<String>,
+}
+
+impl Build {
+    fn current() -> Self {
+        Build {
+            commit: GIT_COMMIT.to_string(),
+            date: BUILD_TIMESTAMP
+                .parse()
+                .ok()
+                .and_then(|timestamp| NaiveDateTime::from_timestamp_opt(timestamp, 0))
+                .map(|date| format!("{}Z", date.format("%Y-%m-%dT%H:%M:%S")))
+                .unwrap_or_default(),
+            rustc_version: RUSTC_VERSION.to_string(),
+            features: FEATURES
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// State of the indexes of a type of places
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexState {
+    Present,
+    /// No index for this type (like the stops of a deployment without NTFS)
+    Absent,
+    /// The most recent index of this type is older than `service.max_index_age`
+    Stale,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TypeStatus {
+    pub state: IndexState,
+    /// An absent required type degrades the readiness
+    pub required: bool,
+}
+
+// the types of places are always listed in the status
+fn status_types(settings: &ServiceSettings) -> BTreeSet<String> {
+    INDEX_TYPES
+        .iter()
+        .map(|t| t.to_string())
+        .chain(settings.required_types.iter().cloned())
+        .collect()
+}
+
+/// Creation date of the most recent index of a type
+fn last_creation(creation_dates: &BTreeMap<String, i64>, doc_type: &str) -> Option<i64> {
+    // the indexes of a type are named munin_<type>_<dataset>_<date>
+    let prefix = format!("munin_{}_", doc_type);
+    creation_dates
+        .iter()
+        .filter(|(index, _)| index.starts_with(&prefix))
+        .map(|(_, date)| *date)
+        .max()
+}
+
+/// Status of each type of places, from the creation dates of the indexes
+fn type_statuses(
+    creation_dates: &BTreeMap<String, i64>,
+    settings: &ServiceSettings,
+    now: i64,
+) -> BTreeMap<String, TypeStatus> {
+    status_types(settings)
+        .into_iter()
+        .map(|doc_type| {
+            let state = match (
+                last_creation(creation_dates, &doc_type),
+                settings.max_index_age,
+            ) {
+                (None, _) => IndexState::Absent,
+                (Some(date), Some(max_age)) if now - date > max_age as i64 * MS_PER_HOUR => {
+                    IndexState::Stale
+                }
+                _ => IndexState::Present,
+            };
+            let required = settings.required_types.contains(&doc_type);
+            (doc_type, TypeStatus { state, required })
+        })
+        .collect()
+}
+
+/// Creation dates (in milliseconds) of the indexes of the places found in ES
+fn get_creation_dates(state: &Context) -> Result<BTreeMap<String, i64>, String> {
+    state
+        .get_rubber_for_autocomplete(None)
+        .get_index_creation_dates("munin_*")
+        .map_err(|e| format!("impossible to get the indexes: {}", e))
+}
+
+/// Status of each type of places, from the indexes found in ES
+pub(crate) fn get_type_statuses(state: &Context) -> Result<BTreeMap<String, TypeStatus>, String> {
+    Ok(type_statuses(
+        &get_creation_dates(state)?,
+        &state.get_query_settings().service,
+        Utc::now().timestamp_millis(),
+    ))
+}
+
+/// Date of the last change of the types: the creation of the most recent index,
+/// or a type becoming stale since (its most recent index getting older than `max_index_age`).
+/// It is truncated to the second like the dates of the http headers.
+fn last_modified(
+    creation_dates: &BTreeMap<String, i64>,
+    settings: &ServiceSettings,
+    now: i64,
+) -> Option<SystemTime> {
+    let newest = *creation_dates.values().max()?;
+    let stale_since = settings.max_index_age.and_then(|max_age| {
+        status_types(settings)
+            .iter()
+            .filter_map(|doc_type| last_creation(creation_dates, doc_type))
+            .map(|date| date + max_age as i64 * MS_PER_HOUR)
+            .filter(|stale_since| *stale_since < now)
+            .max()
+    });
+    let date = stale_since.map_or(newest, |stale_since| stale_since.max(newest));
+    Some(UNIX_EPOCH + Duration::from_secs(date.max(0) as u64 / 1000))
+}
+
+/// Has the status not changed since the `If-Modified-Since` date of the request
+fn is_not_modified_since(if_modified_since: Option<SystemTime>, last_modified: SystemTime) -> bool {
+    if_modified_since.map_or(false, |since| last_modified <= since)
+}
+
+/// Entity tag of a status, from its serialization.
+///
+/// It changes with anything shown in the status: a type becoming stale or absent,
+/// a circuit breaker opening, new settings...
+fn entity_tag(status: &Status) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    // the types are in a BTreeMap, so the serialization is stable
+    serde_json::to_string(status)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    EntityTag::strong(format!("{:016x}", hasher.finish()))
+}
+
+/// Does the `If-None-Match` of the request match the current entity tag
+fn is_not_modified(if_none_match: Option<&IfNoneMatch>, etag: &EntityTag) -> bool {
+    match if_none_match {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+/// The required types without index
+pub(crate) fn missing_required_types(types: &BTreeMap<String, TypeStatus>) -> Vec<&str> {
+    types
+        .iter()
+        .filter(|(_, status)| status.required && status.state == IndexState::Absent)
+        .map(|(doc_type, _)| doc_type.as_str())
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Status {
     pub version: String,
     pub es: String,
     pub status: String,
+    /// State of the circuit breaker on the ES queries of each type of index
+    pub circuit_breakers: BTreeMap<String, CircuitState>,
+    /// Run mode selecting the settings overlay
+    pub run_mode: Option<String>,
+    pub build: Build,
+    /// Hash of the effective settings (without their secrets)
+    pub settings_hash: String,
+    /// State of the indexes of each type of places, empty if ES could not be queried
+    pub types: BTreeMap<String, TypeStatus>,
+    /// The endpoints enabled by the settings, besides the entry point and the status
+    pub endpoints: Vec<String>,
 }
 
-pub fn status(state: Data<Context>) -> Json<Status> {
-    Json(Status {
+/// The status of bragi, with an `ETag` computed from its content, and the date of the last change
+/// of the types in `Last-Modified`.
+///
+/// The requests with an `If-None-Match` matching the `ETag` get a 304: nothing has changed since,
+/// neither the state of the types (including their indexes becoming stale, or deleted)
+/// nor the circuit breakers.
+/// Without `If-None-Match`, the requests with an `If-Modified-Since` date after `Last-Modified`
+/// get a 304 if the circuit breakers are closed: no index has been created since, and no type
+/// has become stale (the deleted indexes are only seen with the `ETag`).
+pub fn status(req: HttpRequest, state: Data<Context>) -> HttpResponse {
+    // the status does not fail without ES, the types are only unknown
+    let creation_dates = get_creation_dates(&state).map_err(|e| warn!("{}", e)).ok();
+    let service = &state.get_query_settings().service;
+    let now = Utc::now().timestamp_millis();
+    let last_modified = creation_dates
+        .as_ref()
+        .and_then(|dates| last_modified(dates, service, now));
+    let status = Status {
         version: VERSION.to_string(),
-        es: state.cnx_string.clone(),
+        es: redact_url_password(&state.cnx_string),
         status: "good".to_string(),
-    })
+        circuit_breakers: state.get_circuit_breakers().states(),
+        run_mode: state.run_mode.clone(),
+        build: Build::current(),
+        settings_hash: state.settings_hash.clone(),
+        types: creation_dates
+            .map(|dates| type_statuses(&dates, service, now))
+            .unwrap_or_default(),
+        endpoints: service
+            .enabled_endpoints()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    };
+    let etag = entity_tag(&status);
+    let if_none_match = req.get_header::<IfNoneMatch>();
+    let not_modified = match (&if_none_match, last_modified) {
+        // the `If-Modified-Since` is ignored with an `If-None-Match`
+        (Some(_), _) => is_not_modified(if_none_match.as_ref(), &etag),
+        (None, Some(last_modified)) => {
+            let if_modified_since = req
+                .get_header::<IfModifiedSince>()
+                .map(|IfModifiedSince(since)| SystemTime::from(since));
+            status
+                .circuit_breakers
+                .values()
+                .all(|state| *state == CircuitState::Closed)
+                && is_not_modified_since(if_modified_since, last_modified)
+        }
+        (None, None) => false,
+    };
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.set(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.set(LastModified(last_modified.into()));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.json(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn build_is_known() {
+        let build = Build::current();
+        if Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../../.git")).exists() {
+            assert!(!build.commit.is_empty());
+        }
+        assert!(!build.date.is_empty());
+        assert!(build.rustc_version.starts_with("rustc "));
+    }
+
+    #[test]
+    fn only_the_missing_required_types_degrade() {
+        // a cluster with only admins and addresses
+        let creation_dates: BTreeMap<String, i64> = vec![
+            ("munin_admin_fr_20201012_101010_000000".to_string(), 0),
+            ("munin_addr_fr_20201012_101010_000000".to_string(), 0),
+        ]
+        .into_iter()
+        .collect();
+        let types = type_statuses(&creation_dates, &ServiceSettings::default(), 0);
+        let states: Vec<_> = types
+            .iter()
+            .map(|(doc_type, status)| (doc_type.as_str(), status.state, status.required))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("addr", IndexState::Present, true),
+                ("admin", IndexState::Present, true),
+                ("poi", IndexState::Absent, false),
+                ("stop", IndexState::Absent, false),
+                ("street", IndexState::Absent, true),
+            ]
+        );
+        assert_eq!(missing_required_types(&types), vec!["street"]);
+
+        // without street in the required types, the missing types are all optional
+        let settings = ServiceSettings {
+            required_types: vec!["admin".to_string(), "addr".to_string()],
+            ..ServiceSettings::default()
+        };
+        let types = type_statuses(&creation_dates, &settings, 0);
+        assert!(missing_required_types(&types).is_empty());
+    }
+
+    #[test]
+    fn old_indexes_are_stale() {
+        let creation_dates: BTreeMap<String, i64> = vec![
+            ("munin_admin_fr_20201012_101010_000000".to_string(), 0),
+            ("munin_addr_fr_20201012_101010_000000".to_string(), 0),
+            (
+                "munin_addr_fr_20201014_101010_000000".to_string(),
+                48 * MS_PER_HOUR,
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let settings = ServiceSettings {
+            max_index_age: Some(24),
+            ..ServiceSettings::default()
+        };
+        let types = type_statuses(&creation_dates, &settings, 50 * MS_PER_HOUR);
+        // only the most recent index of a type counts
+        assert_eq!(types["addr"].state, IndexState::Present);
+        assert_eq!(types["admin"].state, IndexState::Stale);
+        // a stale type is not missing
+        assert_eq!(missing_required_types(&types), vec!["street"]);
+    }
+
+    #[test]
+    fn status_is_last_modified_by_the_newest_index_or_a_type_becoming_stale() {
+        let creation_dates: BTreeMap<String, i64> = vec![
+            (
+                "munin_admin_fr_20201012_101010_000000".to_string(),
+                1_602_497_410_123,
+            ),
+            (
+                "munin_addr_fr_20201014_101010_000000".to_string(),
+                1_602_670_210_456,
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let now = 1_602_680_000_000;
+        let settings = ServiceSettings::default();
+        let date = last_modified(&creation_dates, &settings, now).unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(1_602_670_210));
+        assert!(last_modified(&BTreeMap::new(), &settings, now).is_none());
+
+        // the http dates are precise to the second
+        assert!(is_not_modified_since(Some(date), date));
+        assert!(is_not_modified_since(Some(SystemTime::now()), date));
+        assert!(!is_not_modified_since(
+            Some(date - Duration::from_secs(1)),
+            date
+        ));
+        assert!(!is_not_modified_since(None, date));
+
+        // the admins have become stale before the creation of the addresses
+        let settings = ServiceSettings {
+            max_index_age: Some(24),
+            ..ServiceSettings::default()
+        };
+        let newest = 1_602_670_210_456;
+        assert_eq!(
+            last_modified(&creation_dates, &settings, newest + MS_PER_HOUR),
+            Some(UNIX_EPOCH + Duration::from_secs(1_602_670_210))
+        );
+        // but the addresses have become stale since their creation
+        assert_eq!(
+            last_modified(&creation_dates, &settings, newest + 25 * MS_PER_HOUR),
+            Some(UNIX_EPOCH + Duration::from_secs(1_602_670_210 + 24 * 3600))
+        );
+    }
+
+    #[test]
+    fn status_is_modified_when_a_type_becomes_stale() {
+        let creation_dates: BTreeMap<String, i64> =
+            vec![("munin_admin_fr_20201012_101010_000000".to_string(), 0)]
+                .into_iter()
+                .collect();
+        let settings = ServiceSettings {
+            max_index_age: Some(24),
+            ..ServiceSettings::default()
+        };
+        let status = |now: i64, creation_dates: &BTreeMap<String, i64>| Status {
+            version: VERSION.to_string(),
+            es: "http://localhost:9200".to_string(),
+            status: "good".to_string(),
+            circuit_breakers: BTreeMap::new(),
+            run_mode: None,
+            build: Build::current(),
+            settings_hash: String::new(),
+            types: type_statuses(creation_dates, &settings, now),
+            endpoints: vec![],
+        };
+        let fresh = entity_tag(&status(MS_PER_HOUR, &creation_dates));
+        assert!(is_not_modified(
+            Some(&IfNoneMatch::Items(vec![fresh.clone()])),
+            &entity_tag(&status(2 * MS_PER_HOUR, &creation_dates))
+        ));
+        assert!(!is_not_modified(None, &fresh));
+
+        // no new index, but the admins are now stale
+        let stale = entity_tag(&status(25 * MS_PER_HOUR, &creation_dates));
+        assert!(!is_not_modified(
+            Some(&IfNoneMatch::Items(vec![fresh.clone()])),
+            &stale
+        ));
+
+        // the index of the admins has been deleted
+        let deleted = entity_tag(&status(MS_PER_HOUR, &BTreeMap::new()));
+        assert!(!is_not_modified(
+            Some(&IfNoneMatch::Items(vec![fresh])),
+            &deleted
+        ));
+    }
 }
@@ -0,0 +1,120 @@
+// Pool of threads running the searches made concurrently by a request (the searches of the types
+// of an autocomplete with deadlines, the lookups of a reverse batch).
+//
+// The pool is shared by all the requests, so that the concurrent requests do not multiply the
+// threads nor the load of Elasticsearch: once all its threads are busy, the searches wait for one
+// of them. Its size is given by the `BRAGI_SEARCH_THREADS` environment variable,
+// `DEFAULT_SEARCH_THREADS_PER_CPU` threads by cpu by default.
+
+use slog_scope::warn;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Number of threads of the pool by cpu if not given, the searches mostly wait for Elasticsearch
+pub const DEFAULT_SEARCH_THREADS_PER_CPU: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static::lazy_static! {
+    static ref SEARCH_POOL: SearchPool = SearchPool::new(
+        std::env::var("BRAGI_SEARCH_THREADS")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or_else(|| num_cpus::get() * DEFAULT_SEARCH_THREADS_PER_CPU)
+    );
+}
+
+/// The pool shared by the searches of all the requests
+pub fn search_pool() -> &'static SearchPool {
+    &SEARCH_POOL
+}
+
+pub struct SearchPool {
+    sender: Mutex<mpsc::Sender<Job>>,
+    size: usize,
+}
+
+impl SearchPool {
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..size {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("bragi-search-{}", i))
+                .spawn(move || loop {
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => return,
+                    };
+                    let job = match job {
+                        Ok(job) => job,
+                        // the pool is gone
+                        Err(_) => return,
+                    };
+                    // a panicking search must not take its thread with it
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        warn!("a search has panicked");
+                    }
+                })
+                .expect("failed to spawn a search thread");
+        }
+        SearchPool {
+            sender: Mutex::new(sender),
+            size,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Runs `job` on a thread of the pool, once one of them is free
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if let Ok(sender) = self.sender.lock() {
+            // the threads only stop with the pool
+            let _ = sender.send(Box::new(job));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn jobs_are_run_by_a_bounded_number_of_threads() {
+        let pool = SearchPool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..6 {
+            let (running, max_running, sender) =
+                (running.clone(), max_running.clone(), sender.clone());
+            pool.execute(move || {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                sender.send(()).unwrap();
+            });
+        }
+        for _ in 0..6 {
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn panicking_jobs_do_not_stop_the_pool() {
+        let pool = SearchPool::new(1);
+        pool.execute(|| panic!("the search has failed"));
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(42).unwrap());
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(42));
+    }
+}
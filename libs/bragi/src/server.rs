@@ -1,61 +1,127 @@
 use crate::extractors::ActixError;
 use crate::routes::{
-    autocomplete, entry_point, features, post_autocomplete, reverse, status, JsonParams,
+    autocomplete, entry_point, features, json_config, nearby, post_autocomplete, readiness,
+    refresh, reverse, reverse_batch, settings, status, suggest,
 };
-use crate::{Args, Context};
-use actix_web::FromRequest;
-use actix_web::{middleware, web, App, HttpRequest, HttpServer};
+use crate::{Args, Command, Context};
+use actix_web::{middleware, web, App, HttpRequest, HttpServer, Resource};
 use std::convert::TryInto;
+use std::time::Duration;
 use structopt::StructOpt;
 
+// the default format of the access logs of actix, followed by the experiment of the request
+// and the codes of its warnings (given by the `x-experiment` and `x-warnings` headers of the
+// response, "-" if none). The request line is the one of `RedactedRequestLine`, without api key.
+const ACCESS_LOG_FORMAT: &str = r#"%a "%{x-bragi-request-line}i" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-experiment}o %{x-warnings}o"#;
+
 pub fn default_404(req: HttpRequest) -> Result<web::Json<()>, ActixError> {
     Err(ActixError::RouteNotFound(req.path().to_string()))
 }
 
-pub fn configure_server(cfg: &mut web::ServiceConfig) {
+/// Mounts the endpoints enabled in the settings, the other ones are not found
+pub fn configure_server(cfg: &mut web::ServiceConfig, ctx: &Context) {
+    let service = &ctx.get_query_settings().service;
     cfg.service(
         web::resource("/")
             .name("/")
             .route(web::get().to(entry_point)),
     )
-    .service(
-        web::resource("/autocomplete")
-            .name("autocomplete")
-            .route(web::get().to(autocomplete))
-            .route(web::post().to(post_autocomplete))
-            .data(web::Json::<JsonParams>::configure(|cfg| {
-                cfg.error_handler(|err, _req| ActixError::InvalidJson(format!("{}", err)).into())
-            })),
-    )
     .service(
         web::resource("/status")
             .name("status")
             .route(web::get().to(status)),
-    )
-    .service(
-        web::resource("/features/{id}")
-            .name("features")
-            .route(web::get().to(features)),
-    )
-    .service(
-        web::resource("/reverse")
-            .name("reverse")
-            .route(web::get().to(reverse)),
     );
+    let mut mount = |name: &str, resource: Resource| {
+        if service.is_enabled(name) {
+            cfg.service(resource.name(name));
+        }
+    };
+    mount(
+        "autocomplete",
+        web::resource("/autocomplete")
+            .route(web::get().to_async(autocomplete))
+            .route(web::post().to_async(post_autocomplete))
+            .data(json_config(ctx.max_shape_size)),
+    );
+    mount(
+        "suggest",
+        web::resource("/autocomplete/suggest").route(web::get().to_async(suggest)),
+    );
+    mount(
+        "ready",
+        web::resource("/ready").route(web::get().to(readiness)),
+    );
+    mount(
+        "features",
+        web::resource("/features/{id}").route(web::get().to(features)),
+    );
+    mount(
+        "reverse",
+        web::resource("/reverse").route(web::get().to(reverse)),
+    );
+    mount(
+        "reverse_batch",
+        web::resource("/reverse/batch").route(web::post().to_async(reverse_batch)),
+    );
+    mount(
+        "nearby",
+        web::resource("/nearby").route(web::get().to(nearby)),
+    );
+    mount(
+        "settings",
+        web::resource("/settings").route(web::get().to(settings)),
+    );
+    mount(
+        "refresh",
+        web::resource("/admin/refresh").route(web::post().to(refresh)),
+    );
+}
+
+fn print_config(args: &Args) -> Result<(), String> {
+    let settings = crate::read_settings(args)?;
+    // the settings are checked, to fail like bragi would
+    crate::QuerySettings::from_toml(settings.clone())
+        .map_err(|err| format!("invalid settings: {}", err))?;
+    println!("# run mode: {}", args.run_mode.as_deref().unwrap_or("none"));
+    print!(
+        "{}",
+        toml::to_string(&settings).map_err(|e| format!("failed to print the settings: {}", e))?
+    );
+    Ok(())
 }
 
 pub fn runserver() -> Result<(), String> {
     let args = Args::from_args();
+    if let Some(Command::Config) = args.cmd {
+        return print_config(&args);
+    }
     let ctx: Context = (&args).try_into()?;
-    let prometheus = crate::prometheus_middleware::PrometheusMetrics::new("bragi", "/metrics");
+    if ctx.has_api_keys() {
+        crate::api_keys::reload_on_sighup()?;
+    }
+    crate::memory::log_usage_periodically(
+        ctx.get_memory_budget().clone(),
+        crate::memory::MEMORY_SUMMARY_INTERVAL,
+    )?;
+    let prometheus = crate::prometheus_middleware::PrometheusMetrics::new("bragi", "/metrics")
+        .with_slow_query_threshold(args.slow_query_threshold.map(Duration::from_millis));
+    let request_id =
+        crate::request_id_middleware::RequestId::new(args.request_id_header.as_deref())?;
+    let compression =
+        crate::compression_middleware::CompressionThreshold::new(args.compression_threshold());
     HttpServer::new(move || {
         App::new()
             .data(ctx.clone())
             // NOTE: if some middlewares are added, don't forget to add them in the tests too (in BragiHandler::new)
             .wrap(actix_cors::Cors::new().allowed_methods(vec!["GET"]))
             .wrap(prometheus.clone())
-            .wrap(middleware::Logger::default())
-            .configure(configure_server)
+            .wrap(middleware::Logger::new(ACCESS_LOG_FORMAT))
+            .wrap(crate::access_log_middleware::RedactedRequestLine)
+            .wrap(request_id.clone())
+            // the compression must be the outermost middleware, to only wrap the final responses
+            .wrap(compression.clone())
+            .wrap(middleware::Compress::default())
+            .configure(|cfg| configure_server(cfg, &ctx))
             .default_service(web::resource("").route(web::get().to(default_404)))
     })
     .bind(&args.bind)
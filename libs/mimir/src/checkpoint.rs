@@ -0,0 +1,200 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Checkpoints of the bulk indexing, to resume an interrupted import.
+//!
+//! While an index is filled, the number of documents of the source already indexed
+//! is regularly written in `{dir}/{alias}.checkpoint.json`, with the checksum of the source.
+//! When resuming, an import of the same source continues to fill the index of the checkpoint,
+//! skipping the documents already indexed: the documents are read in the same order,
+//! and the ones indexed twice are only replaced since they have an id.
+//! The checkpoint is removed once the index is published.
+
+use failure::{Error, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CHECKPOINT_EXTENSION: &str = ".checkpoint.json";
+
+#[derive(Debug, Clone)]
+pub struct CheckpointSettings {
+    /// directory of the checkpoint files
+    pub dir: PathBuf,
+    /// number of indexed documents between two checkpoints
+    pub every: usize,
+    /// if the imports are resumed from their checkpoint
+    pub resume: bool,
+    /// checksum of the source of the import, an index can only be resumed with the same source
+    pub source_checksum: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub index: String,
+    pub source_checksum: String,
+    /// number of documents of the source already indexed
+    pub nb_documents: usize,
+}
+
+pub fn checkpoint_path(dir: &Path, alias: &str) -> PathBuf {
+    dir.join(format!("{}{}", alias, CHECKPOINT_EXTENSION))
+}
+
+/// Read a checkpoint, `None` if there is no checkpoint
+pub fn read(path: &Path) -> Result<Option<Checkpoint>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|_| format!("impossible to read {}", path.display()))?;
+    let checkpoint = serde_json::from_str(&content)
+        .with_context(|_| format!("invalid checkpoint {}", path.display()))?;
+    Ok(Some(checkpoint))
+}
+
+/// Write a checkpoint, the previous checkpoint is only replaced once the new one is written
+pub fn write(path: &Path, checkpoint: &Checkpoint) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|_| format!("impossible to create {}", dir.display()))?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_string(checkpoint)?)
+        .with_context(|_| format!("impossible to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|_| format!("impossible to write {}", path.display()))?;
+    Ok(())
+}
+
+pub fn remove(path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        fs::remove_file(path)
+            .with_context(|_| format!("impossible to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Checkpoint of an index being filled
+#[derive(Debug, Clone)]
+pub struct IndexCheckpoint {
+    pub path: PathBuf,
+    pub every: usize,
+    pub checkpoint: Checkpoint,
+}
+
+/// Progress of the bulk indexing of an index.
+///
+/// The chunks of documents are indexed in parallel, so they can end out of order:
+/// only the documents of the chunks indexed without gap are counted in the checkpoint.
+pub struct Progress {
+    index_checkpoint: IndexCheckpoint,
+    // number of documents of the chunks indexed after a gap
+    pending_chunks: BTreeMap<usize, usize>,
+    next_chunk: usize,
+    last_written: usize,
+}
+
+impl Progress {
+    pub fn new(index_checkpoint: IndexCheckpoint) -> Self {
+        let last_written = index_checkpoint.checkpoint.nb_documents;
+        Progress {
+            index_checkpoint,
+            pending_chunks: BTreeMap::new(),
+            next_chunk: 0,
+            last_written,
+        }
+    }
+
+    /// Record that the chunk `chunk` (numbered from 0) of `nb_documents` documents is indexed,
+    /// and write the checkpoint if needed
+    pub fn chunk_indexed(&mut self, chunk: usize, nb_documents: usize) -> Result<(), Error> {
+        self.pending_chunks.insert(chunk, nb_documents);
+        while let Some(nb_documents) = self.pending_chunks.remove(&self.next_chunk) {
+            self.index_checkpoint.checkpoint.nb_documents += nb_documents;
+            self.next_chunk += 1;
+        }
+        if self.nb_documents() >= self.last_written + self.index_checkpoint.every {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    pub fn nb_documents(&self) -> usize {
+        self.index_checkpoint.checkpoint.nb_documents
+    }
+
+    pub fn write(&mut self) -> Result<(), Error> {
+        write(
+            &self.index_checkpoint.path,
+            &self.index_checkpoint.checkpoint,
+        )?;
+        self.last_written = self.nb_documents();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_chunks_without_gap_are_checkpointed() {
+        let dir = std::env::temp_dir().join("mimir_checkpoint_test");
+        let path = checkpoint_path(&dir, "munin_addr_fr");
+        let checkpoint = Checkpoint {
+            index: "munin_addr_fr_20201015_101010_000000".to_string(),
+            source_checksum: "42".to_string(),
+            nb_documents: 100,
+        };
+        write(&path, &checkpoint).unwrap();
+
+        let mut progress = Progress::new(IndexCheckpoint {
+            path: path.clone(),
+            every: 20,
+            checkpoint,
+        });
+        progress.chunk_indexed(1, 10).unwrap();
+        assert_eq!(progress.nb_documents(), 100);
+        progress.chunk_indexed(0, 10).unwrap();
+        assert_eq!(progress.nb_documents(), 120);
+        assert_eq!(read(&path).unwrap().unwrap().nb_documents, 120);
+
+        // the checkpoint is only written every 20 documents
+        progress.chunk_indexed(2, 10).unwrap();
+        assert_eq!(read(&path).unwrap().unwrap().nb_documents, 120);
+        progress.write().unwrap();
+        assert_eq!(read(&path).unwrap().unwrap().nb_documents, 130);
+
+        remove(&path).unwrap();
+        assert_eq!(read(&path).unwrap(), None);
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Storage of the indexes in NDJSON files instead of Elasticsearch,
+//! used by the importers in dry-run mode.
+//!
+//! Each index is written in `{index}.ndjson` (one document per line),
+//! and its publication in `{index}.meta.json`.
+//! The published indexes can then be loaded in Elasticsearch with `mimir_admin mimirload`.
+
+use failure::{Error, ResultExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use slog_scope::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::iter::Enumerate;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+const DOCUMENTS_EXTENSION: &str = ".ndjson";
+const METADATA_EXTENSION: &str = ".meta.json";
+
+/// What the publication of an index would have done in Elasticsearch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexMetadata {
+    pub dataset: String,
+    pub doc_type: String,
+    /// if the index is aliased to the global indexes
+    pub public: bool,
+}
+
+fn documents_path(dir: &Path, index: &str) -> PathBuf {
+    dir.join(format!("{}{}", index, DOCUMENTS_EXTENSION))
+}
+
+fn metadata_path(dir: &Path, index: &str) -> PathBuf {
+    dir.join(format!("{}{}", index, METADATA_EXTENSION))
+}
+
+/// Create (or truncate) the file of an index
+pub fn create_index(dir: &Path, index: &str) -> Result<(), Error> {
+    fs::create_dir_all(dir).with_context(|_| format!("impossible to create {}", dir.display()))?;
+    let path = documents_path(dir, index);
+    File::create(&path).with_context(|_| format!("impossible to create {}", path.display()))?;
+    Ok(())
+}
+
+/// Append the documents to the file of an index, returns the number of documents written
+pub fn write_documents<T, I>(dir: &Path, index: &str, documents: I) -> Result<usize, Error>
+where
+    T: Serialize,
+    I: Iterator<Item = T>,
+{
+    let path = documents_path(dir, index);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|_| format!("impossible to open {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let mut nb_documents = 0;
+    for document in documents {
+        serde_json::to_writer(&mut writer, &document)?;
+        writer.write_all(b"\n")?;
+        nb_documents += 1;
+    }
+    writer.flush()?;
+    Ok(nb_documents)
+}
+
+pub fn write_metadata(dir: &Path, index: &str, metadata: &IndexMetadata) -> Result<(), Error> {
+    let path = metadata_path(dir, index);
+    let file =
+        File::create(&path).with_context(|_| format!("impossible to create {}", path.display()))?;
+    serde_json::to_writer_pretty(file, metadata)?;
+    Ok(())
+}
+
+/// The published indexes of the directory, with their metadata
+pub fn published_indexes(dir: &Path) -> Result<Vec<(String, IndexMetadata)>, Error> {
+    let mut indexes = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|_| format!("impossible to read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let index = match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.ends_with(METADATA_EXTENSION))
+            .map(|name| &name[..name.len() - METADATA_EXTENSION.len()])
+        {
+            Some(index) => index.to_string(),
+            None => continue,
+        };
+        let file = File::open(&path)?;
+        let metadata = serde_json::from_reader(BufReader::new(file))
+            .with_context(|_| format!("invalid metadata file {}", path.display()))?;
+        indexes.push((index, metadata));
+    }
+    indexes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(indexes)
+}
+
+/// The documents of an index file, see `read_documents`
+pub struct Documents<T> {
+    path: PathBuf,
+    lines: Enumerate<Lines<BufReader<File>>>,
+    nb_invalid: usize,
+    finished: bool,
+    _type: PhantomData<T>,
+}
+
+impl<T> Documents<T> {
+    /// Number of invalid lines skipped so far
+    pub fn nb_invalid(&self) -> usize {
+        self.nb_invalid
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for Documents<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.finished {
+            return None;
+        }
+        for (i, line) in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    // the reading cannot go on after an io error
+                    self.nb_invalid += 1;
+                    warn!(
+                        "stop reading {} at line {}: {}",
+                        self.path.display(),
+                        i + 1,
+                        e
+                    );
+                    break;
+                }
+            };
+            match serde_json::from_str(&line) {
+                Ok(document) => return Some(document),
+                Err(e) => {
+                    self.nb_invalid += 1;
+                    warn!("ignoring line {} of {}: {}", i + 1, self.path.display(), e);
+                }
+            }
+        }
+        self.finished = true;
+        if self.nb_invalid > 0 {
+            warn!(
+                "{} invalid lines of {} have been ignored",
+                self.nb_invalid,
+                self.path.display()
+            );
+        }
+        None
+    }
+}
+
+/// Read the documents of an index.
+/// The invalid lines are skipped with a warning, and their number is logged at the end.
+pub fn read_documents<T: DeserializeOwned>(dir: &Path, index: &str) -> io::Result<Documents<T>> {
+    let path = documents_path(dir, index);
+    let file = File::open(&path)?;
+    Ok(Documents {
+        path,
+        lines: BufReader::new(file).lines().enumerate(),
+        nb_invalid: 0,
+        finished: false,
+        _type: PhantomData,
+    })
+}
+
+/// Read all the documents of the published indexes matching `index`,
+/// which is either the name of an index or an alias of all the indexes of a document type
+/// (eg `munin_admin`)
+pub fn read_aliased_documents<T: DeserializeOwned>(
+    dir: &Path,
+    index: &str,
+    doc_type: &str,
+) -> io::Result<Vec<T>> {
+    let type_alias = format!("munin_{}", doc_type);
+    let indexes = published_indexes(dir)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+        .into_iter()
+        .filter(|(name, metadata)| {
+            metadata.doc_type == doc_type
+                && (name == index || (metadata.public && index == type_alias))
+        });
+    let mut documents = Vec::new();
+    for (name, _) in indexes {
+        documents.extend(read_documents(dir, &name)?);
+    }
+    Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Doc {
+        id: u32,
+    }
+
+    #[test]
+    fn invalid_lines_are_counted() {
+        let dir = std::env::temp_dir().join(format!("dry_run_test_{}", std::process::id()));
+        create_index(&dir, "idx").unwrap();
+        fs::write(
+            documents_path(&dir, "idx"),
+            "{\"id\": 1}\nnot json\n{\"id\": 2}\n{\"other\": 3}\n",
+        )
+        .unwrap();
+
+        let mut documents = read_documents::<Doc>(&dir, "idx").unwrap();
+        let ids: Vec<u32> = documents.by_ref().map(|doc| doc.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(documents.nb_invalid(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
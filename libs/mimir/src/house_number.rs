@@ -0,0 +1,105 @@
+// Copyright © 2021, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Split of the house numbers between their leading number and their suffix,
+//! so that they can be sorted and compared as numbers.
+
+/// A house number split as `<numeric><suffix>`: `12 bis` is 12 and `bis`, `12a` is 12 and `a`,
+/// and a range like `3-5` is 3 and `-5`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HouseNumber {
+    /// Leading integer of the house number, if it starts with one
+    pub numeric: Option<u32>,
+    /// Rest of the house number after its leading integer, in lowercase and without spaces
+    pub suffix: Option<String>,
+}
+
+impl HouseNumber {
+    pub fn parse(house_number: &str) -> HouseNumber {
+        let house_number = house_number.trim();
+        let digits_end = house_number
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(house_number.len());
+        let suffix: String = house_number[digits_end..]
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .flat_map(char::to_lowercase)
+            .collect();
+        HouseNumber {
+            numeric: house_number[..digits_end].parse().ok(),
+            suffix: Some(suffix).filter(|suffix| !suffix.is_empty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(house_number: &str) -> (Option<u32>, Option<String>) {
+        let parsed = HouseNumber::parse(house_number);
+        (parsed.numeric, parsed.suffix)
+    }
+
+    #[test]
+    fn plain_numbers_have_no_suffix() {
+        assert_eq!(parse("12"), (Some(12), None));
+        assert_eq!(parse(" 7 "), (Some(7), None));
+    }
+
+    #[test]
+    fn french_suffixes_are_split() {
+        assert_eq!(parse("12 bis"), (Some(12), Some("bis".to_string())));
+        assert_eq!(parse("14ter"), (Some(14), Some("ter".to_string())));
+        assert_eq!(parse("3 Quater"), (Some(3), Some("quater".to_string())));
+        assert_eq!(parse("15B"), (Some(15), Some("b".to_string())));
+    }
+
+    #[test]
+    fn german_suffixes_are_split() {
+        assert_eq!(parse("12a"), (Some(12), Some("a".to_string())));
+        assert_eq!(parse("12 A"), (Some(12), Some("a".to_string())));
+    }
+
+    #[test]
+    fn ranges_are_numbered_by_their_start() {
+        assert_eq!(parse("3-5"), (Some(3), Some("-5".to_string())));
+        assert_eq!(parse("3 - 5"), (Some(3), Some("-5".to_string())));
+        assert_eq!(parse("10/12"), (Some(10), Some("/12".to_string())));
+    }
+
+    #[test]
+    fn numbers_without_leading_integer_have_no_numeric() {
+        assert_eq!(parse("B12"), (None, Some("b12".to_string())));
+        assert_eq!(parse(""), (None, None));
+        // too big to be a house number
+        assert_eq!(parse("99999999999"), (None, None));
+    }
+}
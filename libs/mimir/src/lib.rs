@@ -35,8 +35,14 @@
 // #[macro_use]
 // extern crate failure;
 
+pub mod checkpoint;
+pub mod dry_run;
+pub mod house_number;
 pub mod objects;
 pub mod rubber;
+pub mod synonyms;
+pub mod throttle;
+pub mod validation;
 
 pub use crate::objects::*;
 use slog::{self, o, slog_o, Drain, Never};
@@ -28,7 +28,8 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 use cosmogony::ZoneType;
-use geo_types::{Coordinate, MultiPolygon, Rect};
+use geo::algorithm::simplify::Simplify;
+use geo_types::{Coordinate, LineString, MultiLineString, MultiPolygon, Rect};
 use geojson::Geometry;
 use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde::ser::{SerializeStruct, Serializer};
@@ -96,6 +97,16 @@ impl Place {
         }
     }
 
+    pub fn id(&self) -> &str {
+        match *self {
+            Place::Admin(ref o) => &o.id,
+            Place::Street(ref o) => &o.id,
+            Place::Addr(ref o) => &o.id,
+            Place::Poi(ref o) => &o.id,
+            Place::Stop(ref o) => &o.id,
+        }
+    }
+
     pub fn label(&self) -> &str {
         match *self {
             Place::Admin(ref o) => o.label(),
@@ -146,6 +157,26 @@ impl Place {
         }
     }
 
+    pub fn score(&self) -> Option<f64> {
+        match *self {
+            Place::Admin(ref o) => o.score,
+            Place::Street(ref o) => o.score,
+            Place::Addr(ref o) => o.score,
+            Place::Poi(ref o) => o.score,
+            Place::Stop(ref o) => o.score,
+        }
+    }
+
+    pub fn set_score(&mut self, score: f64) {
+        match self {
+            Place::Admin(ref mut o) => o.score = Some(score),
+            Place::Street(ref mut o) => o.score = Some(score),
+            Place::Addr(ref mut o) => o.score = Some(score),
+            Place::Poi(ref mut o) => o.score = Some(score),
+            Place::Stop(ref mut o) => o.score = Some(score),
+        }
+    }
+
     pub fn coord(&self) -> &Coord {
         match self {
             Place::Admin(ref o) => &o.coord,
@@ -166,6 +197,36 @@ impl Place {
         }
     }
 
+    pub fn flags(&self) -> &Flags {
+        match self {
+            Place::Admin(ref o) => &o.flags,
+            Place::Street(ref o) => &o.flags,
+            Place::Addr(ref o) => &o.flags,
+            Place::Poi(ref o) => &o.flags,
+            Place::Stop(ref o) => &o.flags,
+        }
+    }
+
+    /// Geometry of the place: the boundary of the admins (simplified with `tolerance`, in degrees,
+    /// or as stored if it is 0), the line of the streets, or else the point of its coord
+    pub fn geometry(&self, tolerance: f64) -> Geometry {
+        let value = match self {
+            Place::Admin(ref o) => o.boundary.as_ref().map(|boundary| {
+                if tolerance > 0. {
+                    geojson::Value::from(&boundary.simplify(&tolerance))
+                } else {
+                    geojson::Value::from(boundary)
+                }
+            }),
+            Place::Street(ref o) => o.coord_line.as_ref().map(geojson::Value::from),
+            _ => None,
+        };
+        let coord = self.coord();
+        Geometry::new(
+            value.unwrap_or_else(|| geojson::Value::Point(vec![coord.lon(), coord.lat()])),
+        )
+    }
+
     /* We can afford to clone the context because we're in debug mode
      * and performance are less critical */
     pub fn context(&self) -> Option<Context> {
@@ -179,6 +240,17 @@ impl Place {
     }
 }
 
+/// Value of a flag given to the places of a dataset at their import
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FlagValue {
+    Bool(bool),
+    String(String),
+}
+
+/// Flags of the places of a dataset, by name
+pub type Flags = BTreeMap<String, FlagValue>;
+
 // This is a bit of a kludge to a get a string version for the doc_type.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -206,6 +278,7 @@ pub trait MimirObject: serde::Serialize {
     fn is_geo_data() -> bool;
     fn doc_type() -> &'static str; // provides the elasticsearch type name
     fn es_id(&self) -> Option<String>; // provides the elasticsearch id
+    fn coord(&self) -> &Coord; // the coordinates of the object, for the partial imports
 }
 
 pub trait Members {
@@ -213,6 +286,63 @@ pub trait Members {
     fn admins(&self) -> Vec<Arc<Admin>>;
 }
 
+/// What identifies a place independently of its id:
+/// two places with the same name, the same admin and close coordinates are the same place
+pub struct Identity<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub admin_id: Option<&'a str>,
+    pub coord: &'a Coord,
+}
+
+/// Object whose previous ids can be kept when its id changes between two imports
+/// (for example when an OSM way is split or merged)
+pub trait Aliasable {
+    fn identity(&self) -> Identity<'_>;
+    fn old_ids(&self) -> &[String];
+    fn set_old_ids(&mut self, old_ids: Vec<String>);
+}
+
+// the most precise admin of a place is used to identify it
+fn most_precise_admin_id(admins: &[Arc<Admin>]) -> Option<&str> {
+    admins.iter().max_by_key(|a| a.level).map(|a| a.id.as_str())
+}
+
+macro_rules! impl_aliasable {
+    ($type:ty, $admin_id:expr) => {
+        impl Aliasable for $type {
+            fn identity(&self) -> Identity<'_> {
+                Identity {
+                    id: &self.id,
+                    name: &self.name,
+                    admin_id: $admin_id(self),
+                    coord: &self.coord,
+                }
+            }
+            fn old_ids(&self) -> &[String] {
+                &self.old_ids
+            }
+            fn set_old_ids(&mut self, old_ids: Vec<String>) {
+                self.old_ids = old_ids;
+            }
+        }
+    };
+}
+
+impl_aliasable!(Admin, |a: &Admin| a.parent_id.as_deref());
+impl_aliasable!(Street, |s: &Street| most_precise_admin_id(
+    &s.administrative_regions
+));
+impl_aliasable!(Addr, |a: &Addr| most_precise_admin_id(
+    &a.street.administrative_regions
+));
+impl_aliasable!(Poi, |p: &Poi| most_precise_admin_id(
+    &p.administrative_regions
+));
+impl_aliasable!(Stop, |s: &Stop| most_precise_admin_id(
+    &s.administrative_regions
+));
+
 impl<'a, T: MimirObject> MimirObject for &'a T {
     fn is_geo_data() -> bool {
         T::is_geo_data()
@@ -292,6 +422,28 @@ pub struct Poi {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// Score given by ES to the place for the query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub score: Option<f64>,
+
+    /// Alternative names of the place (old names, abbreviations, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alt_names: Vec<String>,
+
+    /// Ids of the same place in the previous imports, when its id has changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub old_ids: Vec<String>,
+
+    /// Time (in ms since the epoch) of the last update of the poi in its source, if known,
+    /// so that the fresher pois of a dataset can be boosted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+
+    /// Flags of the dataset of the place (for example `verified`).
+    /// Not serialized as is because they are stamped on the documents of the index
+    #[serde(default, skip_serializing)]
+    pub flags: Flags,
 
     pub context: Option<Context>,
 }
@@ -305,7 +457,7 @@ pub struct PoiType {
 impl From<&navitia_poi_model::PoiType> for PoiType {
     fn from(poi_type: &navitia_poi_model::PoiType) -> PoiType {
         PoiType {
-            id: normalize_id("poi_type", &poi_type.id.clone()),
+            id: canonical_case(&normalize_id("poi_type", &poi_type.id)),
             name: poi_type.name.clone(),
         }
     }
@@ -321,6 +473,9 @@ impl MimirObject for Poi {
     fn es_id(&self) -> Option<String> {
         Some(self.id.clone())
     }
+    fn coord(&self) -> &Coord {
+        &self.coord
+    }
 }
 
 impl Members for Poi {
@@ -507,14 +662,56 @@ pub struct Stop {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// Score given by ES to the place for the query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub score: Option<f64>,
     #[serde(default)]
     pub lines: Vec<Line>,
     #[serde(default)]
     pub country_codes: Vec<String>,
+    /// Period during which the stop is served.
+    /// The stops without validity period are considered always served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validity_period: Option<ValidityPeriod>,
+
+    /// Ids of the same place in the previous imports, when its id has changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub old_ids: Vec<String>,
+
+    /// Closest address of the stop, if one is close enough when it is imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<Address>,
+
+    /// Flags of the dataset of the place (for example `verified`).
+    /// Not serialized as is because they are stamped on the documents of the index
+    #[serde(default, skip_serializing)]
+    pub flags: Flags,
 
     pub context: Option<Context>,
 }
 
+/// Period of validity of an object, the dates are formatted as YYYY-MM-DD
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ValidityPeriod {
+    pub start_date: String,
+    pub end_date: String,
+}
+
+impl ValidityPeriod {
+    /// The smallest period containing both periods.
+    /// Since an object without validity period is always valid, the union with `None` is `None`.
+    pub fn union(lhs: Option<Self>, rhs: Option<Self>) -> Option<Self> {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => Some(ValidityPeriod {
+                start_date: lhs.start_date.min(rhs.start_date),
+                end_date: lhs.end_date.max(rhs.end_date),
+            }),
+            _ => None,
+        }
+    }
+}
+
 impl MimirObject for Stop {
     fn is_geo_data() -> bool {
         false
@@ -525,6 +722,9 @@ impl MimirObject for Stop {
     fn es_id(&self) -> Option<String> {
         Some(self.id.clone())
     }
+    fn coord(&self) -> &Coord {
+        &self.coord
+    }
 }
 impl Members for Stop {
     fn label(&self) -> &str {
@@ -569,8 +769,18 @@ pub struct Admin {
 
     #[serde(default)]
     pub zone_type: Option<ZoneType>,
+    /// Weight of the zone type in the ranking (to favor the cities over the suburbs for example).
+    /// The admins without it have a weight of 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_type_weight: Option<f64>,
     #[serde(default)]
     pub parent_id: Option<String>, // id of the Admin's parent (from the cosmogony's hierarchy)
+    /// Population of the zone, only known for some of them (from the `population` tag of OSM)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub population: Option<u64>,
+    /// Area of the zone in km², computed from its boundary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub area: Option<f64>,
     #[serde(default)]
     pub country_codes: Vec<String>,
 
@@ -586,6 +796,23 @@ pub struct Admin {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// Score given by ES to the place for the query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub score: Option<f64>,
+
+    /// Alternative names of the place (old names, abbreviations, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alt_names: Vec<String>,
+
+    /// Ids of the same place in the previous imports, when its id has changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub old_ids: Vec<String>,
+
+    /// Flags of the dataset of the place (for example `verified`).
+    /// Not serialized as is because they are stamped on the documents of the index
+    #[serde(default, skip_serializing)]
+    pub flags: Flags,
 
     pub context: Option<Context>,
 }
@@ -705,12 +932,18 @@ impl MimirObject for Admin {
     fn es_id(&self) -> Option<String> {
         Some(self.id.clone())
     }
+    fn coord(&self) -> &Coord {
+        &self.coord
+    }
 }
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Street {
     pub id: String,
     #[serde(default)]
     pub name: String,
+    /// Alternative names of the street (old names, local names, translations, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alt_names: Vec<String>,
     pub administrative_regions: Vec<Arc<Admin>>,
     pub label: String,
     pub weight: f64,
@@ -719,6 +952,15 @@ pub struct Street {
     #[serde(skip_deserializing)]
     pub approx_coord: Option<Geometry>,
     pub coord: Coord,
+    /// Geometry of the street (simplified): a line by way of the street, used by the reverse
+    /// geocoding to snap the coord on the street. Missing in the streets of the older indexes.
+    #[serde(
+        serialize_with = "custom_multi_line_string_serialize",
+        deserialize_with = "custom_multi_line_string_deserialize",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub coord_line: Option<MultiLineString<f64>>,
     pub zip_codes: Vec<String>,
     #[serde(default)]
     pub country_codes: Vec<String>,
@@ -726,9 +968,160 @@ pub struct Street {
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// Distance (in meters) along the line of `coord_line` closest to the coord,
+    /// from its start to the coord, once snapped by the reverse geocoding
+    #[serde(default, skip)]
+    pub along_street_distance: Option<u32>,
+    /// Score given by ES to the place for the query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub score: Option<f64>,
+
+    /// Ids of the same place in the previous imports, when its id has changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub old_ids: Vec<String>,
+
+    /// Flags of the dataset of the place (for example `verified`).
+    /// Not serialized as is because they are stamped on the documents of the index
+    #[serde(default, skip_serializing)]
+    pub flags: Flags,
 
     pub context: Option<Context>,
 }
+impl Street {
+    /// Moves the coord of the street to the point of its geometry closest to `coord`,
+    /// and sets the distances to `coord` and along the street accordingly.
+    ///
+    /// The street is left as is if it has no geometry (older indexes).
+    pub fn snap_to(&mut self, coord: &Coord) {
+        use geo::algorithm::haversine_distance::HaversineDistance;
+
+        let (snapped, along) = match self
+            .coord_line
+            .as_ref()
+            .and_then(|lines| closest_point_on_lines(lines, coord))
+        {
+            Some(closest) => closest,
+            None => return,
+        };
+        let point: geo_types::Point<f64> = coord.0.into();
+        self.distance = Some(point.haversine_distance(&snapped.0.into()) as u32);
+        self.along_street_distance = Some(along as u32);
+        self.coord = snapped;
+        self.approx_coord = Some(snapped.into());
+    }
+}
+
+// mean radius of the earth, in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// The point of `line` closest to `coord`, with the distance (in meters) along `line`
+/// from its first point to the closest point.
+///
+/// The distances are computed on an equirectangular projection centered on `coord`,
+/// which is precise enough at the scale of a street.
+pub fn closest_point_on_line(line: &[Coordinate<f64>], coord: &Coord) -> Option<(Coord, f64)> {
+    let meters_per_lat_degree = EARTH_RADIUS * std::f64::consts::PI / 180.;
+    let meters_per_lon_degree = meters_per_lat_degree * coord.lat().to_radians().cos();
+    let project = |c: &Coordinate<f64>| {
+        (
+            (c.x - coord.lon()) * meters_per_lon_degree,
+            (c.y - coord.lat()) * meters_per_lat_degree,
+        )
+    };
+
+    if line.len() == 1 {
+        return Some((Coord(line[0]), 0.));
+    }
+    // (distance to coord, closest point, distance along the line)
+    let mut closest: Option<(f64, (f64, f64), f64)> = None;
+    let mut along = 0.;
+    for segment in line.windows(2) {
+        let (ax, ay) = project(&segment[0]);
+        let (bx, by) = project(&segment[1]);
+        let (dx, dy) = (bx - ax, by - ay);
+        let length = dx.hypot(dy);
+        // projection of coord (the origin) on the segment
+        let t = if length > 0. {
+            (-(ax * dx + ay * dy) / (length * length)).max(0.).min(1.)
+        } else {
+            0.
+        };
+        let (px, py) = (ax + t * dx, ay + t * dy);
+        let distance = px.hypot(py);
+        if closest.map_or(true, |(d, _, _)| distance < d) {
+            closest = Some((distance, (px, py), along + t * length));
+        }
+        along += length;
+    }
+    closest.map(|(_, (px, py), along)| {
+        (
+            Coord::new(
+                coord.lon() + px / meters_per_lon_degree,
+                coord.lat() + py / meters_per_lat_degree,
+            ),
+            along,
+        )
+    })
+}
+
+/// The point of the lines closest to `coord`, with the distance (in meters) along its line
+/// from the first point of this line to the closest point.
+pub fn closest_point_on_lines(lines: &MultiLineString<f64>, coord: &Coord) -> Option<(Coord, f64)> {
+    use geo::algorithm::haversine_distance::HaversineDistance;
+
+    let point: geo_types::Point<f64> = coord.0.into();
+    lines
+        .0
+        .iter()
+        .filter_map(|line| closest_point_on_line(&line.0, coord))
+        .map(|(closest, along)| (point.haversine_distance(&closest.0.into()), closest, along))
+        .min_by(|(a, _, _), (b, _, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, closest, along)| (closest, along))
+}
+
+fn custom_multi_line_string_serialize<S>(
+    multi_line_string_option: &Option<MultiLineString<f64>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    use geojson::{GeoJson, Value};
+
+    match *multi_line_string_option {
+        Some(ref multi_line_string) => {
+            GeoJson::Geometry(Geometry::new(Value::from(multi_line_string))).serialize(serializer)
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
+fn custom_multi_line_string_deserialize<'de, D>(
+    d: D,
+) -> Result<Option<MultiLineString<f64>>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    use std::convert::TryInto;
+
+    Option::<geojson::GeoJson>::deserialize(d).map(|option| {
+        option.and_then(|geojson| match geojson {
+            geojson::GeoJson::Geometry(geojson_geometry) => {
+                let res: Result<MultiLineString<f64>, _> = geojson_geometry.value.try_into();
+                match res {
+                    Ok(multi_line_string) => Some(multi_line_string),
+                    Err(err) => {
+                        warn!("Cannot deserialize into MultiLineString: {}", err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        })
+    })
+}
+
 impl Incr for Street {
     fn id(&self) -> &str {
         &self.id
@@ -748,6 +1141,9 @@ impl MimirObject for Street {
     fn es_id(&self) -> Option<String> {
         Some(self.id.clone())
     }
+    fn coord(&self) -> &Coord {
+        &self.coord
+    }
 }
 
 impl Members for Street {
@@ -765,6 +1161,12 @@ pub struct Addr {
     #[serde(default)]
     pub name: String,
     pub house_number: String,
+    /// Leading integer of `house_number` (see `HouseNumber`), to sort and compare them as numbers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub house_number_numeric: Option<u32>,
+    /// Rest of `house_number` after its leading integer (like `bis`, `a`, or `-5` for a range)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub house_number_suffix: Option<String>,
     pub street: Street,
     pub label: String,
     pub coord: Coord,
@@ -776,10 +1178,30 @@ pub struct Addr {
     pub zip_codes: Vec<String>,
     #[serde(default)]
     pub country_codes: Vec<String>,
+    /// Name of the city, as used to build the label.
+    /// With `house_number`, `street.name` and `zip_codes`, it lets clients format their own labels
+    #[serde(default)]
+    pub city: Option<String>,
+    /// Name of the country, as used to build the label
+    #[serde(default)]
+    pub country: Option<String>,
     /// Distance to the coord in query.
     /// Not serialized as is because it is returned in the `Feature` object
     #[serde(default, skip)]
     pub distance: Option<u32>,
+    /// Score given by ES to the place for the query.
+    /// Not serialized as is because it is returned in the `Feature` object
+    #[serde(default, skip)]
+    pub score: Option<f64>,
+
+    /// Ids of the same place in the previous imports, when its id has changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub old_ids: Vec<String>,
+
+    /// Flags of the dataset of the place (for example `verified`).
+    /// Not serialized as is because they are stamped on the documents of the index
+    #[serde(default, skip_serializing)]
+    pub flags: Flags,
 
     pub context: Option<Context>,
 }
@@ -794,6 +1216,9 @@ impl MimirObject for Addr {
     fn es_id(&self) -> Option<String> {
         Some(self.id.clone())
     }
+    fn coord(&self) -> &Coord {
+        &self.coord
+    }
 }
 
 impl Members for Addr {
@@ -957,10 +1382,17 @@ impl From<&navitia_poi_model::Coord> for Coord {
 
 /// Contextual information related to the query. It can be used to store information
 /// for monitoring performance, search relevance, ...
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Context {
     /// Elasticsearch explanation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub explanation: Option<Explanation>,
+    /// Names of the clauses of the query matched by the place (like `name`, `prefix` or `proximity`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub match_reasons: Vec<String>,
+    /// Geometry of the place, only given if requested (see `Place::geometry`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geometry: Option<Geometry>,
 }
 
 /// This structure is used when analyzing the result of an Elasticsearch 'explanation' query,
@@ -975,6 +1407,12 @@ pub struct Explanation {
     pub details: Vec<Explanation>,
 }
 
+/// Canonical case of the values on which bragi filters (the datasets and the poi types),
+/// they are stored in this case at the import and the filters of the requests are converted to it
+pub fn canonical_case(value: &str) -> String {
+    value.to_lowercase()
+}
+
 // This function reformat the id by removing spaces, and prepending a prefix
 pub fn normalize_id(prefix: &str, id: &str) -> String {
     match prefix {
@@ -998,3 +1436,198 @@ fn test_normalize_id() {
         "stop_area:SIN:SA:ABCDE:1234"
     );
 }
+
+#[cfg(test)]
+mod street_geometry_tests {
+    use super::*;
+
+    // an L shaped street in Paris: 0.01° east, then 0.01° north
+    fn l_shaped_line() -> Vec<Coordinate<f64>> {
+        vec![
+            Coordinate { x: 2.35, y: 48.85 },
+            Coordinate { x: 2.36, y: 48.85 },
+            Coordinate { x: 2.36, y: 48.86 },
+        ]
+    }
+
+    fn assert_close(value: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} is not {} (± {})",
+            value,
+            expected,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn coord_is_snapped_on_the_closest_segment() {
+        let (snapped, along) =
+            closest_point_on_line(&l_shaped_line(), &Coord::new(2.355, 48.851)).unwrap();
+        assert_close(snapped.lon(), 2.355, 1e-9);
+        assert_close(snapped.lat(), 48.85, 1e-9);
+        // 0.005° of longitude at this latitude
+        assert_close(along, 366., 1.);
+
+        let (snapped, along) =
+            closest_point_on_line(&l_shaped_line(), &Coord::new(2.361, 48.855)).unwrap();
+        assert_close(snapped.lon(), 2.36, 1e-9);
+        assert_close(snapped.lat(), 48.855, 1e-9);
+        // the first segment, and 0.005° of latitude
+        assert_close(along, 732. + 556., 2.);
+    }
+
+    #[test]
+    fn coord_is_snapped_on_the_ends_of_the_line() {
+        let (snapped, along) =
+            closest_point_on_line(&l_shaped_line(), &Coord::new(2.34, 48.849)).unwrap();
+        assert_close(snapped.lon(), 2.35, 1e-9);
+        assert_close(snapped.lat(), 48.85, 1e-9);
+        assert_close(along, 0., 1e-9);
+
+        let (snapped, along) =
+            closest_point_on_line(&l_shaped_line(), &Coord::new(2.37, 48.87)).unwrap();
+        assert_close(snapped.lon(), 2.36, 1e-9);
+        assert_close(snapped.lat(), 48.86, 1e-9);
+        assert_close(along, 731. + 1112., 3.);
+    }
+
+    #[test]
+    fn degenerate_lines() {
+        assert!(closest_point_on_line(&[], &Coord::new(2.35, 48.85)).is_none());
+        let point = Coordinate { x: 2.35, y: 48.85 };
+        let (snapped, along) = closest_point_on_line(&[point], &Coord::new(2.36, 48.86)).unwrap();
+        assert_close(snapped.lon(), 2.35, 1e-9);
+        assert_close(snapped.lat(), 48.85, 1e-9);
+        assert_close(along, 0., 1e-9);
+        // a segment of null length
+        let (snapped, _) =
+            closest_point_on_line(&[point, point], &Coord::new(2.36, 48.86)).unwrap();
+        assert_close(snapped.lon(), 2.35, 1e-9);
+    }
+
+    #[test]
+    fn streets_without_geometry_are_not_snapped() {
+        let coord = Coord::new(2.355, 48.851);
+        let mut street = Street {
+            coord: Coord::new(2.355, 48.86),
+            distance: Some(1000),
+            ..Default::default()
+        };
+        street.snap_to(&coord);
+        assert_close(street.coord.lat(), 48.86, 1e-9);
+        assert_eq!(street.distance, Some(1000));
+        assert_eq!(street.along_street_distance, None);
+
+        street.coord_line = Some(MultiLineString(vec![LineString(l_shaped_line())]));
+        street.snap_to(&coord);
+        assert_close(street.coord.lat(), 48.85, 1e-9);
+        // 0.001° of latitude
+        assert_eq!(street.distance, Some(111));
+        assert_eq!(street.along_street_distance, Some(365));
+    }
+
+    #[test]
+    fn coord_is_snapped_on_the_closest_way() {
+        // a street of two ways, the second one 0.005° north of the first one
+        let lines = MultiLineString(vec![
+            LineString(l_shaped_line()),
+            LineString::from(vec![(2.37, 48.855), (2.38, 48.855)]),
+        ]);
+        let (snapped, along) = closest_point_on_lines(&lines, &Coord::new(2.375, 48.854)).unwrap();
+        assert_close(snapped.lon(), 2.375, 1e-9);
+        assert_close(snapped.lat(), 48.855, 1e-9);
+        // along the second way, from its start
+        assert_close(along, 366., 1.);
+
+        let (snapped, _) = closest_point_on_lines(&lines, &Coord::new(2.355, 48.851)).unwrap();
+        assert_close(snapped.lat(), 48.85, 1e-9);
+
+        assert!(
+            closest_point_on_lines(&MultiLineString(vec![]), &Coord::new(2.35, 48.85)).is_none()
+        );
+    }
+
+    #[test]
+    fn coord_line_is_serialized_as_geojson() {
+        let lines = MultiLineString(vec![
+            LineString(l_shaped_line()),
+            LineString::from(vec![(2.37, 48.855), (2.38, 48.855)]),
+        ]);
+        let street = Street {
+            coord_line: Some(lines.clone()),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&street).unwrap();
+        assert_eq!(
+            value["coord_line"],
+            serde_json::json!({
+                "type": "MultiLineString",
+                "coordinates": [
+                    [[2.35, 48.85], [2.36, 48.85], [2.36, 48.86]],
+                    [[2.37, 48.855], [2.38, 48.855]]
+                ]
+            })
+        );
+        let street: Street = serde_json::from_value(value).unwrap();
+        assert_eq!(street.coord_line, Some(lines));
+
+        // the streets of the older indexes have no geometry
+        let value = serde_json::to_value(&Street::default()).unwrap();
+        assert!(value.get("coord_line").is_none());
+        let street: Street = serde_json::from_value(value).unwrap();
+        assert!(street.coord_line.is_none());
+    }
+
+    #[test]
+    fn geometry_of_the_places() {
+        let street = Place::Street(Street {
+            coord: Coord::new(2.355, 48.85),
+            coord_line: Some(MultiLineString(vec![LineString(l_shaped_line())])),
+            ..Default::default()
+        });
+        assert_eq!(
+            serde_json::to_value(street.geometry(0.001)).unwrap(),
+            serde_json::json!({
+                "type": "MultiLineString",
+                "coordinates": [[[2.35, 48.85], [2.36, 48.85], [2.36, 48.86]]]
+            })
+        );
+
+        // the boundaries are simplified
+        let square = vec![
+            Coordinate { x: 2.35, y: 48.85 },
+            Coordinate {
+                x: 2.355,
+                y: 48.85001,
+            },
+            Coordinate { x: 2.36, y: 48.85 },
+            Coordinate { x: 2.36, y: 48.86 },
+            Coordinate { x: 2.35, y: 48.86 },
+            Coordinate { x: 2.35, y: 48.85 },
+        ];
+        let admin = Place::Admin(Admin {
+            boundary: Some(MultiPolygon(vec![geo_types::Polygon::new(
+                LineString(square),
+                vec![],
+            )])),
+            ..Default::default()
+        });
+        let geometry = serde_json::to_value(admin.geometry(0.001)).unwrap();
+        assert_eq!(geometry["type"], "MultiPolygon");
+        assert_eq!(geometry["coordinates"][0][0].as_array().unwrap().len(), 5);
+        // unless there is no tolerance
+        let geometry = serde_json::to_value(admin.geometry(0.)).unwrap();
+        assert_eq!(geometry["coordinates"][0][0].as_array().unwrap().len(), 6);
+
+        // the places without geometry are their point
+        let admin = Place::Admin(Admin {
+            coord: Coord::new(2.35, 48.85),
+            ..Default::default()
+        });
+        assert_eq!(
+            serde_json::to_value(admin.geometry(0.001)).unwrap(),
+            serde_json::json!({ "type": "Point", "coordinates": [2.35, 48.85] })
+        );
+    }
+}
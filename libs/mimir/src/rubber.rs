@@ -28,9 +28,16 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
-use super::objects::{Admin, Context, Explanation, MimirObject};
-use super::objects::{AliasOperation, AliasOperations, AliasParameter, Coord, Place};
+use super::checkpoint::{self, Checkpoint, CheckpointSettings, IndexCheckpoint};
+use super::dry_run::{self, IndexMetadata};
+use super::objects::I18nProperties;
+use super::objects::{Addr, Admin, Aliasable, Context, Explanation, Flags, Identity, MimirObject};
+use super::objects::{AliasOperation, AliasOperations, AliasParameter, Coord, Place, Street};
+use super::synonyms;
+use super::throttle::AdaptiveConcurrency;
+use super::validation::{self, ValidationThresholds};
 use failure::{bail, format_err, Error, ResultExt};
+use futures_util::future;
 use prometheus::{exponential_buckets, histogram_opts, register_histogram, Histogram};
 use reqwest::StatusCode;
 use rs_es::error::EsError;
@@ -41,30 +48,30 @@ use rs_es::query::Query;
 use rs_es::units as rs_u;
 use rs_es::units::Duration;
 use rs_es::EsResponse;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::value::RawValue;
 use slog_scope::{debug, info, warn};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
 use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time;
 
-const SYNONYMS: [&str; 17] = [
-    "cc,centre commercial",
-    "hotel de ville,mairie",
-    "gare sncf,gare",
-    "chu,chr,hopital",
-    "ld,lieu-dit",
-    "st,saint",
-    "ste,sainte",
-    "bvd,bld,bd,boulevard",
-    "pt,pont",
-    "rle,ruelle",
-    "rte,route",
-    "vla,villa",
-    "grand-champ,grandchamp",
-    "fac,faculte,ufr,universite",
-    "embarcadere,gare maritime",
-    "cpam,securite sociale",
-    "anpe,pole emploi",
-];
+// max distance (in meters) between two places with the same name and admin
+// for them to be considered as the same place in two imports
+const MAX_ALIAS_DISTANCE: f64 = 200.;
+// by default, the address of a coord is searched within this distance (in meters)
+const DEFAULT_ADDRESS_RADIUS: f64 = 1000.;
+
+// number of documents fetched by each request of a scroll, and how long ES keeps the scroll alive
+const SCROLL_PAGE_SIZE: usize = 1000;
+const SCROLL_DURATION: &str = "1m";
+
+// interval between two checks of the abort of a search sent to ES
+const ABORT_CHECK_INTERVAL: time::Duration = time::Duration::from_millis(10);
 
 lazy_static::lazy_static! {
     static ref ES_REQ_HISTOGRAM: Histogram = register_histogram!(
@@ -73,6 +80,39 @@ lazy_static::lazy_static! {
         exponential_buckets(0.001, 1.5, 25).unwrap()
     )
     .unwrap();
+    // runtime sending the abortable searches, see `Rubber::with_abort`
+    static ref ABORTABLE_RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .thread_name("abortable-searches")
+        .build()
+        .expect("impossible to create the runtime of the abortable searches");
+    // documents indexed by the process for each document type, for the import reports
+    static ref INDEXED_DOCUMENTS: Mutex<BTreeMap<String, IndexedDocuments>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Number of documents of a type indexed during an import
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexedDocuments {
+    pub indexed: usize,
+    /// documents rejected by Elasticsearch
+    pub rejected: usize,
+}
+
+fn record_indexed_documents(doc_type: &str, indexed: usize, rejected: usize) {
+    let mut all_documents = INDEXED_DOCUMENTS.lock().unwrap_or_else(|e| e.into_inner());
+    let documents = all_documents.entry(doc_type.to_string()).or_default();
+    documents.indexed += indexed;
+    documents.rejected += rejected;
+}
+
+/// Number of documents indexed (or written with the dry run) by the process, by document type
+pub fn indexed_documents() -> BTreeMap<String, IndexedDocuments> {
+    INDEXED_DOCUMENTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
 }
 
 fn check_response(
@@ -82,26 +122,272 @@ fn check_response(
     let status = resp.status();
     match status {
         StatusCode::OK | StatusCode::CREATED | StatusCode::NOT_FOUND => Ok(resp),
-        _ => Err(EsError::from(&mut resp)),
+        _ => {
+            let mut body = String::new();
+            let _ = resp.read_to_string(&mut body);
+            Err(server_error(status, &body))
+        }
+    }
+}
+
+// an ES response with an error status, formatted like rs-es does
+fn server_error(status: StatusCode, body: &str) -> EsError {
+    EsError::EsServerError(format!("{} - {}", status, body))
+}
+
+/// The http status of the response of ES behind an error, if ES has answered.
+///
+/// rs-es only keeps it at the start of the message of its server errors (`400 Bad Request - <body>`),
+/// like the raw requests of the rubber.
+pub fn es_error_status(error: &EsError) -> Option<StatusCode> {
+    match error {
+        EsError::EsServerError(message) => message
+            .split_whitespace()
+            .next()
+            .and_then(|code| code.parse().ok())
+            .and_then(|code| StatusCode::from_u16(code).ok()),
+        _ => None,
+    }
+}
+
+// the bulk indexing logs its progress every this number of chunks
+const PROGRESS_LOG_EVERY: usize = 100;
+// number of times the documents of a chunk are sent again when Elasticsearch rejects them
+// with `429 Too Many Requests`, before failing
+const MAX_REJECTED_RETRIES: usize = 20;
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    items: Vec<BTreeMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize)]
+struct BulkItem {
+    #[serde(rename = "_id")]
+    id: String,
+    status: u16,
+    error: Option<serde_json::Value>,
+}
+
+// the action and the document of the bulk request indexing a document
+fn bulk_line<D: Serialize>(id: Option<String>, document: &D) -> Result<String, Error> {
+    let action = match id {
+        Some(id) => serde_json::json!({ "index": { "_id": id } }),
+        None => serde_json::json!({ "index": {} }),
+    };
+    Ok(format!(
+        "{}\n{}\n",
+        action,
+        serde_json::to_string(document)?
+    ))
+}
+
+// the delay given by the `Retry-After` header of a response (in seconds)
+fn retry_after(resp: &reqwest::blocking::Response) -> Option<time::Duration> {
+    resp.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(time::Duration::from_secs)
+}
+
+// send a bulk request of the documents of `lines`, the documents rejected with
+// `429 Too Many Requests` are sent again with a lower concurrency.
+// The number of documents not indexed is returned.
+fn send_bulk<T: MimirObject>(
+    http_client: &reqwest::blocking::Client,
+    url: &str,
+    mut lines: Vec<String>,
+    concurrency: &AdaptiveConcurrency,
+) -> Result<usize, Error> {
+    let mut nb_errors = 0;
+    for _ in 0..=MAX_REJECTED_RETRIES {
+        let permit = concurrency.acquire();
+        let resp = http_client.post(url).body(lines.concat()).send()?;
+        if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+            let limit = permit.rejected(retry_after(&resp));
+            warn!(
+                "Elasticsearch rejected a bulk request, at most {} bulk requests are now in flight",
+                limit
+            );
+            continue;
+        }
+        let retry_after = retry_after(&resp);
+        let resp: BulkResponse = check_response(resp)?.json()?;
+        let mut rejected = vec![];
+        for (line, item) in lines.into_iter().zip(resp.items) {
+            let item = match item.into_iter().next() {
+                Some((_, item)) => item,
+                None => continue,
+            };
+            if item.status == StatusCode::TOO_MANY_REQUESTS.as_u16() {
+                rejected.push(line);
+            } else if !(200..300).contains(&item.status) {
+                nb_errors += 1;
+                // We only display a warning if it brings some information, otherwise
+                // the log is distracting
+                if let Some(ref error) = item.error {
+                    warn!(
+                        "An error occured while importing {} '{}'. Status {}: {}",
+                        T::doc_type(),
+                        item.id,
+                        item.status,
+                        error
+                    );
+                }
+            }
+        }
+        if rejected.is_empty() {
+            permit.accepted();
+            return Ok(nb_errors);
+        }
+        let limit = permit.rejected(retry_after);
+        warn!(
+            "Elasticsearch rejected {} {} documents, at most {} bulk requests are now in flight",
+            rejected.len(),
+            T::doc_type(),
+            limit
+        );
+        lines = rejected;
+    }
+    bail!(
+        "Elasticsearch still rejects the bulk requests after {} retries",
+        MAX_REJECTED_RETRIES
+    )
+}
+
+fn search_path(indexes: &[&str], endpoint: &str, preference: Option<&str>) -> String {
+    let mut path = format!("{}/{}?ignore_unavailable=true", indexes.join(","), endpoint);
+    if let Some(preference) = preference {
+        path.push_str(&format!("&preference={}", preference));
+    }
+    path
+}
+
+// the time taken by ES to search and the number of hits, read from the response of a search
+#[derive(Deserialize)]
+struct SearchStats {
+    took: u64,
+    hits: SearchStatsHits,
+}
+
+#[derive(Deserialize)]
+struct SearchStatsHits {
+    total: u64,
+}
+
+// `s` truncated to at most `max_length` bytes, without splitting a character
+fn truncate_at_char_boundary(s: &str, max_length: usize) -> &str {
+    if s.len() <= max_length {
+        return s;
     }
+    let mut end = max_length;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
 }
 
 // Rubber is an wrapper around elasticsearch API
+/// Settings of the http connections to ES, to recycle the pooled connections
+/// before a load balancer drops them silently
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransportSettings {
+    /// Interval of the TCP keep-alive probes of the connections, disabled if not set
+    pub keep_alive: Option<time::Duration>,
+    /// Duration after which an idle connection of the pool is closed (90s if not set)
+    pub idle_timeout: Option<time::Duration>,
+}
+
+fn build_http_client(
+    timeout: Option<time::Duration>,
+    transport: &TransportSettings,
+) -> reqwest::blocking::Client {
+    let mut builder = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .tcp_keepalive(transport.keep_alive);
+    if let Some(idle_timeout) = transport.idle_timeout {
+        builder = builder.pool_idle_timeout(idle_timeout);
+    }
+    builder.build().unwrap()
+}
+
+// like `build_http_client`, for the abortable searches
+fn build_abortable_http_client(
+    timeout: Option<time::Duration>,
+    transport: &TransportSettings,
+) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().tcp_keepalive(transport.keep_alive);
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(idle_timeout) = transport.idle_timeout {
+        builder = builder.pool_idle_timeout(idle_timeout);
+    }
+    builder.build().unwrap()
+}
+
+// resolves once `abort` is set
+async fn aborted(abort: Arc<AtomicBool>) {
+    while !abort.load(Ordering::Relaxed) {
+        tokio::time::delay_for(ABORT_CHECK_INTERVAL).await;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Rubber {
     pub es_client: rs_es::Client,
     // some operation are not implemented in rs_es, we need to use a raw http client
     http_client: reqwest::blocking::Client,
+    // sends the searches of a rubber with an `abort` flag, the blocking client cannot abort them
+    abortable_http_client: reqwest::Client,
+    // if set, the searches are aborted as soon as it is true
+    abort: Option<Arc<AtomicBool>>,
     // Note: The timeout is used for the http client AND for the ES internal query
     pub timeout: Option<time::Duration>,
+    pub transport: TransportSettings,
+    // if not 0, the searches are logged at debug level with their body truncated to this length
+    pub query_log_length: usize,
     pub cnx_string: String,
     pub nb_insert_threads: usize,
+    // if set, maximum number of bulk requests in flight, else one per insert thread
+    max_bulk_requests: Option<usize>,
     max_bulk_errors: usize,
+    // if set, the indexes are written in NDJSON files of this directory instead of ES
+    dry_run_dir: Option<PathBuf>,
+    // in dry run, the addresses and the streets of `dry_run_dir`, read at the first `get_address`
+    dry_run_addresses: Option<Arc<Vec<Place>>>,
+    // directory of the synonym files, the default synonyms are used if not set
+    synonyms_dir: Option<PathBuf>,
+    // number of previous generations of an index kept at its publication
+    keep_previous: usize,
+    // if set, the progress of the bulk indexing is checkpointed to resume an interrupted import
+    checkpoint: Option<CheckpointSettings>,
+    // flags stamped on the documents of the indexes of each dataset
+    dataset_flags: BTreeMap<String, Flags>,
+    // settings of the indexes of some datasets, overriding the ones given to the importer
+    index_settings_overrides: BTreeMap<String, IndexSettingsOverride>,
+    // if set, the indexes are validated before being published
+    validation: Option<ValidationThresholds>,
+    // if set, only the documents in this bounding box are reimported,
+    // the documents outside of it are copied from the current index
+    bbox: Option<geo_types::Rect<f64>>,
+    // maximum distance in meters of the address found for a coord
+    address_radius: f64,
 }
 
 #[derive(Clone, Debug)]
 pub struct TypedIndex<T> {
     name: String,
+    // dataset stamped on the documents of the index
+    dataset: Option<String>,
+    // flags of the dataset stamped on the documents of the index
+    flags: Flags,
+    // checkpoint of the index while it is filled
+    checkpoint: Option<IndexCheckpoint>,
     _type: PhantomData<T>,
 }
 
@@ -109,9 +395,44 @@ impl<T> TypedIndex<T> {
     pub fn new(name: String) -> TypedIndex<T> {
         TypedIndex {
             name,
+            dataset: None,
+            flags: Flags::new(),
+            checkpoint: None,
             _type: PhantomData,
         }
     }
+
+    /// The documents indexed in this index have a `dataset` field set to `dataset`
+    pub fn with_dataset(mut self, dataset: &str) -> TypedIndex<T> {
+        self.dataset = Some(dataset.to_string());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dataset(&self) -> Option<&str> {
+        self.dataset.as_deref()
+    }
+
+    /// The documents indexed in this index have a `flags` field set to `flags`
+    pub fn with_flags(mut self, flags: Flags) -> TypedIndex<T> {
+        self.flags = flags;
+        self
+    }
+}
+
+// a document stamped with the dataset it has been imported from (and the flags of this dataset),
+// so that the places can be filtered by dataset or by flag
+#[derive(Serialize)]
+struct DatasetDocument<T> {
+    #[serde(flatten)]
+    document: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dataset: Option<String>,
+    #[serde(skip_serializing_if = "Flags::is_empty")]
+    flags: Flags,
 }
 
 /// Index Visibility
@@ -129,6 +450,33 @@ pub struct IndexSettings {
     pub nb_replicas: usize,
 }
 
+/// Settings of the indexes of a dataset, overriding the settings given to the importer
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct IndexSettingsOverride {
+    pub nb_shards: Option<usize>,
+    pub nb_replicas: Option<usize>,
+    /// Elasticsearch settings merged into the settings of the index (for example its analysis)
+    pub settings: Option<serde_json::Value>,
+}
+
+// merge the `overlay` json into `base`: the objects are merged recursively,
+// and the other values of `overlay` replace the ones of `base`
+fn merge_json(base: &mut serde_json::Value, overlay: &serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(key) {
+                    Some(base_value) => merge_json(base_value, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
 /// return the index associated to the given type and dataset
 /// this will be an alias over another real index
 pub fn get_main_type_and_dataset_index<T: MimirObject>(dataset: &str) -> String {
@@ -149,6 +497,26 @@ pub fn get_date_index_name(base_index_name: &str) -> String {
     )
 }
 
+/// Is the coord in the bbox (its borders included)
+pub fn is_in_bbox(coord: &Coord, bbox: &geo_types::Rect<f64>) -> bool {
+    bbox.min().x <= coord.lon()
+        && coord.lon() <= bbox.max().x
+        && bbox.min().y <= coord.lat()
+        && coord.lat() <= bbox.max().y
+}
+
+/// Split the previous generations of an index between the `keep_previous` most recent ones,
+/// and the older ones that can be deleted.
+/// The generations are ordered by the date in their name (see `get_date_index_name`).
+pub fn split_generations(
+    mut indexes: Vec<String>,
+    keep_previous: usize,
+) -> (Vec<String>, Vec<String>) {
+    indexes.sort_unstable_by(|a, b| b.cmp(a));
+    let older_indexes = indexes.split_off(keep_previous.min(indexes.len()));
+    (indexes, older_indexes)
+}
+
 pub fn get_indexes_by_type(a_type: &str) -> String {
     let doc_type = match a_type {
         "public_transport:stop_area" => "stop",
@@ -175,7 +543,15 @@ pub fn read_places(
         .hits
         .hits
         .into_iter()
-        .filter_map(|hit| make_place(hit.doc_type, hit.source, hit.explanation))
+        .filter_map(|hit| {
+            let score = hit.score;
+            make_place(hit.doc_type, hit.source, hit.explanation).map(|mut place| {
+                if let Some(score) = score {
+                    place.set_score(score);
+                }
+                place
+            })
+        })
         .map(|mut place| {
             if let Some(ref p) = point {
                 use geo::algorithm::haversine_distance::HaversineDistance;
@@ -187,6 +563,212 @@ pub fn read_places(
         .collect())
 }
 
+/// Places of the response of a raw search (see `Rubber::raw_search`).
+///
+/// The names of the clauses of the query matched by each place are given in its context.
+pub fn read_raw_places(
+    mut result: serde_json::Value,
+    coord: Option<&Coord>,
+) -> Result<Vec<Place>, EsError> {
+    let hits = match result["hits"]["hits"].take() {
+        serde_json::Value::Array(hits) => hits,
+        _ => return Err(EsError::EsError("no hits in the ES response".to_string())),
+    };
+    let point: Option<geo_types::Point<f64>> = coord.map(|c| c.0.into());
+    Ok(hits
+        .into_iter()
+        .filter_map(|mut hit| {
+            let doc_type = hit["_type"].as_str().unwrap_or_default().to_string();
+            let explanation = Some(hit["_explanation"].take()).filter(|e| !e.is_null());
+            let source = Some(Box::new(hit["_source"].take()));
+            let mut place = make_place(doc_type, source, explanation)?;
+            if let Some(score) = hit["_score"].as_f64() {
+                place.set_score(score);
+            }
+            if let Some(ref p) = point {
+                use geo::algorithm::haversine_distance::HaversineDistance;
+                let distance = p.haversine_distance(&place.coord().0.into()) as u32;
+                place.set_distance(distance);
+            }
+            let mut match_reasons: Vec<String> = hit["matched_queries"]
+                .as_array()
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|name| name.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default();
+            match_reasons.sort();
+            match_reasons.dedup();
+            let mut context = place.context().unwrap_or_default();
+            context.match_reasons = match_reasons;
+            place.set_context(context);
+            Some(place)
+        })
+        .collect())
+}
+
+/// Hits of the response of a search, borrowing their source from the body of the response
+#[derive(Deserialize)]
+struct RawSearchResponse<'a> {
+    took: u64,
+    #[serde(borrow)]
+    hits: RawHits<'a>,
+}
+
+#[derive(Deserialize)]
+struct RawHits<'a> {
+    total: u64,
+    #[serde(borrow)]
+    hits: Vec<RawHit<'a>>,
+}
+
+#[derive(Deserialize)]
+struct RawHit<'a> {
+    #[serde(rename = "_type")]
+    doc_type: &'a str,
+    #[serde(rename = "_score")]
+    score: Option<f64>,
+    #[serde(rename = "_source", borrow)]
+    source: Option<&'a RawValue>,
+}
+
+/// The streets found by `get_address` are snapped on their geometry,
+/// their coord can be far from the request.
+/// The geometry is not kept, for it not to be copied in the addresses of the pois
+fn snap_address(mut place: Place, coord: &Coord) -> Place {
+    if let Place::Street(ref mut street) = place {
+        street.snap_to(coord);
+        street.coord_line = None;
+    }
+    place
+}
+
+/// Places of the body of a search response (see `Rubber::raw_search_bytes`).
+///
+/// The source of each hit is deserialized directly from the body,
+/// without building a `serde_json::Value` of the response first.
+/// It gives the same places as `read_raw_places`, without their match reasons.
+pub fn read_places_from_slice(body: &[u8], coord: Option<&Coord>) -> Result<Vec<Place>, EsError> {
+    let response: RawSearchResponse<'_> = serde_json::from_slice(body)?;
+    debug!(
+        "{} documents found in {} ms",
+        response.hits.total, response.took
+    );
+    let point: Option<geo_types::Point<f64>> = coord.map(|c| c.0.into());
+    Ok(response
+        .hits
+        .hits
+        .into_iter()
+        .filter_map(|hit| {
+            let source = hit.source?;
+            let mut place = deserialize_place(
+                hit.doc_type,
+                &mut serde_json::Deserializer::from_str(source.get()),
+            )?;
+            if let Some(score) = hit.score {
+                place.set_score(score);
+            }
+            if let Some(ref p) = point {
+                use geo::algorithm::haversine_distance::HaversineDistance;
+                let distance = p.haversine_distance(&place.coord().0.into()) as u32;
+                place.set_distance(distance);
+            }
+            Some(place)
+        })
+        .collect())
+}
+
+/// A place reduced to what is shown while typing: its id, label, type and coordinates
+#[derive(Debug, Clone)]
+pub struct SuggestedPlace {
+    pub id: String,
+    /// The label in the language of the request, if the place has one
+    pub label: String,
+    /// The ES type of the place (`addr`, `street`, `admin`, `poi` or `stop`)
+    pub doc_type: String,
+    pub coord: Coord,
+}
+
+/// The only fields of the sources fetched for the suggestions,
+/// the labels being only fetched in the language of the request
+pub fn suggestion_source_fields(lang: Option<&str>) -> Vec<String> {
+    let mut fields: Vec<String> = vec!["id".into(), "label".into(), "coord".into()];
+    fields.extend(lang.map(|lang| format!("labels.{}", lang)));
+    fields
+}
+
+#[derive(Deserialize)]
+struct SuggestionSource {
+    id: String,
+    label: String,
+    #[serde(default)]
+    labels: I18nProperties,
+    coord: Coord,
+}
+
+/// Suggested places of the body of a search response fetching only the fields
+/// of `suggestion_source_fields` (see `Rubber::raw_search_bytes`).
+pub fn read_suggestions_from_slice(
+    body: &[u8],
+    lang: Option<&str>,
+) -> Result<Vec<SuggestedPlace>, EsError> {
+    let response: RawSearchResponse<'_> = serde_json::from_slice(body)?;
+    debug!(
+        "{} documents found in {} ms",
+        response.hits.total, response.took
+    );
+    Ok(response
+        .hits
+        .hits
+        .into_iter()
+        .filter_map(|hit| {
+            let source: SuggestionSource = serde_json::from_str(hit.source?.get())
+                .map_err(|err| warn!("Impossible to load ES result: {}", err))
+                .ok()?;
+            let label = match lang.and_then(|lang| source.labels.get(lang)) {
+                Some(label) => label.to_string(),
+                None => source.label,
+            };
+            Some(SuggestedPlace {
+                id: source.id,
+                label,
+                doc_type: hit.doc_type.to_string(),
+                coord: source.coord,
+            })
+        })
+        .collect())
+}
+
+// it uses the _type field of ES to know which type of the Place enum to fill
+fn deserialize_place<'de, D>(doc_type: &str, document: D) -> Option<Place>
+where
+    D: Deserializer<'de>,
+{
+    fn convert<'de, D, T>(document: D, f: fn(T) -> Place) -> Option<Place>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        T::deserialize(document)
+            .map_err(|err| warn!("Impossible to load ES result: {}", err))
+            .ok()
+            .map(f)
+    }
+    match doc_type {
+        "addr" => convert(document, Place::Addr),
+        "street" => convert(document, Place::Street),
+        "admin" => convert(document, Place::Admin),
+        "poi" => convert(document, Place::Poi),
+        "stop" => convert(document, Place::Stop),
+        _ => {
+            warn!("unknown ES return value, _type field = {}", doc_type);
+            None
+        }
+    }
+}
+
 /// takes a ES json blob and build a Place from it
 /// it uses the _type field of ES to know which type of the Place enum to fill
 pub fn make_place(
@@ -194,34 +776,14 @@ pub fn make_place(
     value: Option<Box<serde_json::Value>>,
     explanation: Option<serde_json::Value>,
 ) -> Option<Place> {
-    let place = value.and_then(|v| {
-        fn convert<T>(v: serde_json::Value, f: fn(T) -> Place) -> Option<Place>
-        where
-            for<'de> T: serde::Deserialize<'de>,
-        {
-            serde_json::from_value::<T>(v)
-                .map_err(|err| warn!("Impossible to load ES result: {}", err))
-                .ok()
-                .map(f)
-        }
-        match doc_type.as_ref() {
-            "addr" => convert(*v, Place::Addr),
-            "street" => convert(*v, Place::Street),
-            "admin" => convert(*v, Place::Admin),
-            "poi" => convert(*v, Place::Poi),
-            "stop" => convert(*v, Place::Stop),
-            _ => {
-                warn!("unknown ES return value, _type field = {}", doc_type);
-                None
-            }
-        }
-    });
+    let place = value.and_then(|v| deserialize_place(&doc_type, *v));
     match explanation {
         Some(explanation) => match serde_json::from_value::<Explanation>(explanation) {
             Ok(explanation) => match place {
                 Some(mut place) => {
                     place.set_context(Context {
                         explanation: Some(explanation),
+                        ..Default::default()
                     });
                     Some(place)
                 }
@@ -314,124 +876,679 @@ impl Rubber {
         let timeout = timeout.into();
         Rubber {
             es_client: rs_es::Client::init_with_timeout(&cnx, timeout).unwrap(),
-            http_client: reqwest::blocking::Client::builder()
-                .timeout(timeout)
-                .build()
-                .unwrap(),
+            http_client: build_http_client(timeout, &TransportSettings::default()),
+            abortable_http_client: build_abortable_http_client(
+                timeout,
+                &TransportSettings::default(),
+            ),
+            abort: None,
             cnx_string: cnx.to_owned(),
             timeout,
+            transport: TransportSettings::default(),
+            query_log_length: 0,
             nb_insert_threads: 1,
+            max_bulk_requests: None,
             max_bulk_errors: 0,
+            dry_run_dir: None,
+            dry_run_addresses: None,
+            synonyms_dir: None,
+            keep_previous: 0,
+            checkpoint: None,
+            dataset_flags: BTreeMap::new(),
+            index_settings_overrides: BTreeMap::new(),
+            validation: None,
+            bbox: None,
+            address_radius: DEFAULT_ADDRESS_RADIUS,
         }
     }
 
+    /// Keep-alive and idle timeout of the pooled connections to ES, used by all the searches.
+    ///
+    /// The rs_es client, only used for the maintenance of the indexes by the imports
+    /// (refresh, deletion, scan of the admins), keeps the default settings of its connections.
+    pub fn with_transport(mut self, transport: TransportSettings) -> Self {
+        self.http_client = build_http_client(self.timeout, &transport);
+        self.abortable_http_client = build_abortable_http_client(self.timeout, &transport);
+        self.transport = transport;
+        self
+    }
+
     /// Change the number of parallel threads used to insert data into Elasticsearch
     /// during `bulk_index`. Note that if too many insertions are performed in parallel,
     /// Elasticsearch may raise an error.
+    /// The searches (`raw_search` and `raw_search_bytes`) are aborted as soon as `abort` is set,
+    /// even the ones already sent to ES: their connection is closed without waiting for the response,
+    /// and they fail with an `EsError::EsError`.
+    /// ES 2 cannot cancel a running search, it ends at its ES timeout.
+    pub fn with_abort(mut self, abort: Arc<AtomicBool>) -> Self {
+        self.abort = Some(abort);
+        self
+    }
+
     pub fn with_nb_insert_threads(mut self, value: usize) -> Self {
         self.nb_insert_threads = value;
         self
     }
 
+    /// Bound the number of bulk requests in flight during `bulk_index`, whatever the number of
+    /// insert threads, so that a small Elasticsearch is not overwhelmed by the import.
+    /// By default, each insert thread has a bulk request in flight.
+    pub fn with_max_bulk_requests(mut self, value: usize) -> Self {
+        self.max_bulk_requests = Some(value);
+        self
+    }
+
     pub fn with_max_bulk_errors(mut self, value: usize) -> Self {
         self.max_bulk_errors = value;
         self
     }
 
-    pub fn get(&self, path: &str) -> Result<reqwest::blocking::Response, EsError> {
-        // Note: a bit duplicate on rs_es because some ES operations are not implemented
-        debug!("doing a get on {}", path);
-        let url = self.es_client.full_url(path);
-        let result = self.http_client.get(url).send()?;
-        check_response(result)
+    /// Write the indexes in NDJSON files of `dir` instead of Elasticsearch (see `mimir::dry_run`).
+    ///
+    /// Elasticsearch is not queried either: the admins, and the addresses and streets
+    /// of the reverse geocoding, are read from the files of `dir`.
+    pub fn with_dry_run(mut self, dir: PathBuf) -> Self {
+        self.dry_run_dir = Some(dir);
+        self
     }
-    fn put(&self, path: &str, body: &str) -> Result<reqwest::blocking::Response, EsError> {
-        // Note: a bit duplicate on rs_es because some ES operations are not implemented
-        debug!("doing a put on {} with {}", path, body);
-        let url = self.es_client.full_url(path);
-        let result = self.http_client.put(url).body(body.to_owned()).send()?;
-        check_response(result)
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run_dir.is_some()
     }
-    fn post(&self, path: &str, body: &str) -> Result<reqwest::blocking::Response, EsError> {
-        // Note: a bit duplicate on rs_es because some ES operations are not implemented
-        debug!("doing a post on {} with {}", path, body);
-        let url = self.es_client.full_url(path);
-        let result = self.http_client.post(url).body(body.to_owned()).send()?;
-        check_response(result)
+
+    /// Read the synonyms of the new indexes from the files of `dir` (see `mimir::synonyms`)
+    pub fn with_synonyms_dir(mut self, dir: PathBuf) -> Self {
+        self.synonyms_dir = Some(dir);
+        self
     }
 
-    pub fn make_index<T: MimirObject>(
-        &self,
-        dataset: &str,
-        index_settings: &IndexSettings,
-    ) -> Result<TypedIndex<T>, Error> {
-        let index_name = get_date_index_name(&get_main_type_and_dataset_index::<T>(dataset));
-        info!("creating index {}", index_name);
-        self.create_index(&index_name, index_settings)?;
-        Ok(TypedIndex::new(index_name))
+    /// Keep the `value` previous generations of an index when publishing a new one
+    /// (to be able to roll back), only the older ones are deleted.
+    pub fn with_keep_previous(mut self, value: usize) -> Self {
+        self.keep_previous = value;
+        self
     }
 
-    pub fn create_index(&self, name: &str, index_settings: &IndexSettings) -> Result<(), Error> {
-        debug!("creating index");
-        // Note: in rs_es it can be done with MappingOperation but for the moment I think
-        // storing the mapping in json is more convenient
-        let settings = include_str!("../../../config/settings.json");
+    /// Stamp the documents of the indexes of each dataset with its flags
+    /// (for example `verified` for an authoritative dataset)
+    pub fn with_dataset_flags(mut self, dataset_flags: BTreeMap<String, Flags>) -> Self {
+        self.dataset_flags = dataset_flags;
+        self
+    }
 
-        let mut settings_json_value = serde_json::from_str::<serde_json::Value>(&settings)
-            .map_err(|err| {
-                format_err!("Error occurred when creating index: {} err: {}", name, err)
-            })?;
+    /// Override the settings of the indexes of some datasets, given by `<doc_type>_<dataset>`
+    /// (for example `addr_fr`) or by `<dataset>` for all its indexes
+    pub fn with_index_settings_overrides(
+        mut self,
+        overrides: BTreeMap<String, IndexSettingsOverride>,
+    ) -> Self {
+        self.index_settings_overrides = overrides;
+        self
+    }
 
-        let synonyms: Vec<_> = SYNONYMS
-            .iter()
-            .map(|s| serde_json::Value::String((*s).to_string()))
-            .collect();
+    /// Validate the indexes before publishing them (see `mimir::validation`),
+    /// an invalid index is not published.
+    pub fn with_validation(mut self, thresholds: ValidationThresholds) -> Self {
+        self.validation = Some(thresholds);
+        self
+    }
 
-        *settings_json_value
-            .pointer_mut("/settings/analysis/filter/synonym_filter/synonyms")
-            .unwrap() = serde_json::Value::Array(synonyms);
+    /// Reimport only the documents in `bbox`: the new indexes are filled with the documents
+    /// of the current ones outside of it, then with the imported documents inside of it,
+    /// so the documents of the bbox missing from the import are removed.
+    /// There is no current index to copy in dry run.
+    pub fn with_bbox(mut self, bbox: geo_types::Rect<f64>) -> Self {
+        self.bbox = Some(bbox);
+        self
+    }
 
-        *settings_json_value
-            .pointer_mut("/settings/number_of_shards")
-            .unwrap() = serde_json::Value::from(index_settings.nb_shards);
-        *settings_json_value
-            .pointer_mut("/settings/number_of_replicas")
-            .unwrap() = serde_json::Value::from(index_settings.nb_replicas);
+    /// Search the address of a coord (see `get_address`) within `radius` meters
+    /// instead of 1km
+    pub fn with_address_radius(mut self, radius: f64) -> Self {
+        self.address_radius = radius;
+        self
+    }
 
-        self.put(name, &settings_json_value.to_string())
-            .map_err(|e| {
-                format_err!(
-                    "Error: {},  while creating new index {}, ",
-                    name,
-                    e.to_string()
-                )
-            })
-            .and_then(|res| {
-                if res.status() == StatusCode::OK {
-                    Ok(())
-                } else {
-                    Err(format_err!("cannot create index: {:?}", res))
-                }
-            })
+    /// Log the raw searches at debug level, with their indexes, their body truncated
+    /// to `length` bytes, and the time taken by ES and the number of hits of their response.
+    /// The searches are not logged if `length` is 0 (the default).
+    pub fn with_query_log_length(mut self, length: usize) -> Self {
+        self.query_log_length = length;
+        self
     }
 
-    pub fn create_template(&self, name: &str, settings: &str) -> Result<(), Error> {
-        debug!("creating template");
-        self.put(&format!("_template/{}", name), settings)
-            .map_err(|e| {
-                info!("Error while creating template {}", name);
-                format_err!("Error: {} while creating template {}", e.to_string(), name)
-            })
-            .and_then(|res| {
-                if res.status() == StatusCode::OK {
-                    Ok(())
-                } else {
-                    Err(format_err!("cannot create template: {:?}", res))
+    // Only the indexes and the body sent to ES are logged, never the parameters of the request
+    // (like its api key), and the response is only read if the search is logged.
+    fn log_search<F>(&self, indexes: &[&str], body: &str, stats: F)
+    where
+        F: FnOnce() -> Option<SearchStats>,
+    {
+        if self.query_log_length == 0 {
+            return;
+        }
+        let stats = stats();
+        debug!("ES search";
+            "indexes" => indexes.join(","),
+            "query" => truncate_at_char_boundary(body, self.query_log_length),
+            "query_length" => body.len(),
+            "took" => stats.as_ref().map(|stats| stats.took),
+            "hits" => stats.as_ref().map(|stats| stats.hits.total)
+        );
+    }
+
+    fn get_flags(&self, dataset: &str) -> Flags {
+        self.dataset_flags.get(dataset).cloned().unwrap_or_default()
+    }
+
+    // the overrides of the settings of the indexes of a type, the ones of its dataset if none
+    fn get_index_settings_override(
+        &self,
+        doc_type: &str,
+        dataset: &str,
+    ) -> Option<&IndexSettingsOverride> {
+        self.index_settings_overrides
+            .get(&format!("{}_{}", doc_type, dataset))
+            .or_else(|| self.index_settings_overrides.get(dataset))
+    }
+
+    // create the index of a dataset, with the overrides of its settings if any
+    fn create_dataset_index(
+        &self,
+        name: &str,
+        doc_type: &str,
+        dataset: &str,
+        index_settings: &IndexSettings,
+        synonyms: &[String],
+    ) -> Result<(), Error> {
+        let index_override = match self.get_index_settings_override(doc_type, dataset) {
+            Some(index_override) => index_override,
+            None => return self.create_index_with_synonyms(name, index_settings, synonyms),
+        };
+        let index_settings = IndexSettings {
+            nb_shards: index_override.nb_shards.unwrap_or(index_settings.nb_shards),
+            nb_replicas: index_override
+                .nb_replicas
+                .unwrap_or(index_settings.nb_replicas),
+        };
+        info!(
+            "creating index {} with the settings of {}: {:?}",
+            name, dataset, index_settings
+        );
+        self.create_index_with_overrides(
+            name,
+            &index_settings,
+            synonyms,
+            index_override.settings.as_ref(),
+        )
+    }
+
+    /// Checkpoint the progress of the bulk indexing, to resume an interrupted import
+    /// (see `mimir::checkpoint`). There is no checkpoint in dry run.
+    pub fn with_checkpoint(mut self, settings: CheckpointSettings) -> Self {
+        self.checkpoint = Some(settings);
+        self
+    }
+
+    /// The synonyms of the indexes of a document type and a dataset
+    /// (`None` for the indexes shared by all the datasets)
+    pub fn get_synonyms(
+        &self,
+        doc_type: &str,
+        dataset: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        synonyms::load_synonyms(self.synonyms_dir.as_deref(), doc_type, dataset)
+    }
+
+    pub fn get(&self, path: &str) -> Result<reqwest::blocking::Response, EsError> {
+        // Note: a bit duplicate on rs_es because some ES operations are not implemented
+        debug!("doing a get on {}", path);
+        let url = self.es_client.full_url(path);
+        let result = self.http_client.get(url).send()?;
+        check_response(result)
+    }
+
+    /// Refresh the indexes matching `pattern`, so that their last documents can be searched
+    /// without waiting for their refresh interval
+    pub fn refresh_indexes(&self, pattern: &str) -> Result<(), EsError> {
+        self.post(&format!("{}/_refresh", pattern), "")?;
+        Ok(())
+    }
+
+    /// Creation date (in ms since the epoch) of each index matching `pattern`
+    pub fn get_index_creation_dates(
+        &self,
+        pattern: &str,
+    ) -> Result<BTreeMap<String, i64>, EsError> {
+        let value: serde_json::Value = self
+            .get(&format!("{}/_settings/index.creation_date", pattern))?
+            .read_response()?;
+        Ok(value
+            .as_object()
+            .map(|indexes| {
+                indexes
+                    .iter()
+                    .filter_map(|(index, settings)| {
+                        let date = settings.pointer("/settings/index/creation_date")?;
+                        // ES gives the date as a string
+                        let date = date
+                            .as_str()
+                            .and_then(|d| d.parse().ok())
+                            .or_else(|| date.as_i64())?;
+                        Some((index.clone(), date))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Search the indexes with a raw json body,
+    /// for the searches rs_es does not support (like the aggregations or the named queries)
+    pub fn raw_search(
+        &self,
+        indexes: &[&str],
+        body: &serde_json::Value,
+        preference: Option<&str>,
+    ) -> Result<serde_json::Value, EsError> {
+        let body = body.to_string();
+        let path = search_path(indexes, "_search", preference);
+        let response: serde_json::Value = match self.abort {
+            Some(ref abort) => {
+                let bytes = self.abortable_post(&path, &body, abort.clone())?;
+                serde_json::from_slice(&bytes).map_err(|err| {
+                    EsError::EsError(format!("impossible to read the ES response: {}", err))
+                })?
+            }
+            None => self.post(&path, &body)?.read_response()?,
+        };
+        self.log_search(indexes, &body, || {
+            Some(SearchStats {
+                took: response["took"].as_u64()?,
+                hits: SearchStatsHits {
+                    total: response["hits"]["total"].as_u64()?,
+                },
+            })
+        });
+        Ok(response)
+    }
+
+    /// Like `raw_search`, but returns the body of the response as is,
+    /// to read its places with `read_places_from_slice`
+    pub fn raw_search_bytes(
+        &self,
+        indexes: &[&str],
+        body: &serde_json::Value,
+        preference: Option<&str>,
+    ) -> Result<Vec<u8>, EsError> {
+        let body = body.to_string();
+        let path = search_path(indexes, "_search", preference);
+        let bytes = match self.abort {
+            Some(ref abort) => self.abortable_post(&path, &body, abort.clone())?,
+            None => {
+                let mut res = self.post(&path, &body)?;
+                let mut bytes = Vec::new();
+                res.read_to_end(&mut bytes).map_err(|err| {
+                    EsError::EsError(format!("impossible to read the ES response: {}", err))
+                })?;
+                bytes
+            }
+        };
+        self.log_search(indexes, &body, || serde_json::from_slice(&bytes).ok());
+        Ok(bytes)
+    }
+
+    /// Search with the search template `template_id` stored in ES, rendered with `params`.
+    ///
+    /// Like `raw_search_bytes`, the body of the response is returned as is,
+    /// or None if there is no template `template_id` in ES.
+    pub fn search_template_bytes(
+        &self,
+        indexes: &[&str],
+        template_id: &str,
+        params: &serde_json::Value,
+        preference: Option<&str>,
+    ) -> Result<Option<Vec<u8>>, EsError> {
+        let body = serde_json::json!({ "id": template_id, "params": params }).to_string();
+        let path = search_path(indexes, "_search/template", preference);
+        let mut res = self.post(&path, &body)?;
+        // the indexes are never missing (they are ignored if unavailable), only the template is
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let mut bytes = Vec::new();
+        res.read_to_end(&mut bytes).map_err(|err| {
+            EsError::EsError(format!("impossible to read the ES response: {}", err))
+        })?;
+        self.log_search(indexes, &body, || serde_json::from_slice(&bytes).ok());
+        Ok(Some(bytes))
+    }
+
+    /// Store the search template `template_id` in ES, replacing the previous one if any.
+    ///
+    /// The template is the mustache source of the body of the searches.
+    pub fn put_search_template(&self, template_id: &str, template: &str) -> Result<(), EsError> {
+        let body = serde_json::json!({ "template": template });
+        self.post(
+            &format!("_search/template/{}", template_id),
+            &body.to_string(),
+        )
+        .map(|_| ())
+    }
+
+    fn put(&self, path: &str, body: &str) -> Result<reqwest::blocking::Response, EsError> {
+        // Note: a bit duplicate on rs_es because some ES operations are not implemented
+        debug!("doing a put on {} with {}", path, body);
+        let url = self.es_client.full_url(path);
+        let result = self.http_client.put(url).body(body.to_owned()).send()?;
+        check_response(result)
+    }
+    fn post(&self, path: &str, body: &str) -> Result<reqwest::blocking::Response, EsError> {
+        // Note: a bit duplicate on rs_es because some ES operations are not implemented
+        debug!("doing a post on {} with {}", path, body);
+        let url = self.es_client.full_url(path);
+        let result = self.http_client.post(url).body(body.to_owned()).send()?;
+        check_response(result)
+    }
+
+    // like `post`, reading the body of the response, but stops as soon as `abort` is set:
+    // the request is dropped, closing its connection
+    fn abortable_post(
+        &self,
+        path: &str,
+        body: &str,
+        abort: Arc<AtomicBool>,
+    ) -> Result<Vec<u8>, EsError> {
+        debug!("doing an abortable post on {} with {}", path, body);
+        let url = self.es_client.full_url(path);
+        let request = self.abortable_http_client.post(&url).body(body.to_owned());
+        let (sender, receiver) = mpsc::channel();
+        ABORTABLE_RUNTIME.spawn(async move {
+            let response = Box::pin(async {
+                let response = request.send().await?;
+                let status = response.status();
+                let bytes = response.bytes().await?;
+                Ok::<_, reqwest::Error>((status, bytes))
+            });
+            let res = match future::select(response, Box::pin(aborted(abort))).await {
+                future::Either::Left((response, _)) => Some(response),
+                future::Either::Right(_) => None,
+            };
+            // the receiver is gone if the search thread has panicked
+            let _ = sender.send(res);
+        });
+        match receiver.recv() {
+            Ok(Some(Ok((status, bytes)))) => match status {
+                StatusCode::OK | StatusCode::CREATED | StatusCode::NOT_FOUND => Ok(bytes.to_vec()),
+                _ => Err(server_error(status, &String::from_utf8_lossy(&bytes))),
+            },
+            Ok(Some(Err(err))) => Err(EsError::from(err)),
+            Ok(None) | Err(_) => Err(EsError::EsError("the search has been aborted".to_string())),
+        }
+    }
+
+    pub fn make_index<T: MimirObject>(
+        &self,
+        dataset: &str,
+        index_settings: &IndexSettings,
+    ) -> Result<TypedIndex<T>, Error> {
+        if let (Some(settings), false) = (&self.checkpoint, self.is_dry_run()) {
+            return self.make_checkpointed_index(dataset, index_settings, settings);
+        }
+        let index_name = if self.is_dry_run() {
+            // the files are overwritten at each import, there is no need for a new generation
+            get_main_type_and_dataset_index::<T>(dataset)
+        } else {
+            get_date_index_name(&get_main_type_and_dataset_index::<T>(dataset))
+        };
+        info!("creating index {}", index_name);
+        let synonyms = self.get_synonyms(T::doc_type(), Some(dataset))?;
+        self.create_dataset_index(
+            &index_name,
+            T::doc_type(),
+            dataset,
+            index_settings,
+            &synonyms,
+        )?;
+        if !self.is_dry_run() {
+            self.copy_outside_bbox::<T>(dataset, &index_name)?;
+        }
+        Ok(TypedIndex::new(index_name)
+            .with_dataset(dataset)
+            .with_flags(self.get_flags(dataset)))
+    }
+
+    // resume the index of the checkpoint if asked, otherwise create a new index and its checkpoint
+    fn make_checkpointed_index<T: MimirObject>(
+        &self,
+        dataset: &str,
+        index_settings: &IndexSettings,
+        settings: &CheckpointSettings,
+    ) -> Result<TypedIndex<T>, Error> {
+        let alias = get_main_type_and_dataset_index::<T>(dataset);
+        let path = checkpoint::checkpoint_path(&settings.dir, &alias);
+        let previous_checkpoint = if settings.resume {
+            checkpoint::read(&path)?
+        } else {
+            None
+        };
+        if let Some(checkpoint) = previous_checkpoint {
+            if checkpoint.source_checksum != settings.source_checksum {
+                bail!(
+                    "the source has changed since the checkpoint of {}, it cannot be resumed",
+                    checkpoint.index
+                );
+            }
+            let index_checksum = self.get_source_checksum(&checkpoint.index, T::doc_type())?;
+            if index_checksum.as_deref() != Some(settings.source_checksum.as_str()) {
+                bail!(
+                    "the index {} does not match its checkpoint, it cannot be resumed",
+                    checkpoint.index
+                );
+            }
+            info!(
+                "resuming index {} after {} documents",
+                checkpoint.index, checkpoint.nb_documents
+            );
+            return Ok(TypedIndex {
+                name: checkpoint.index.clone(),
+                dataset: Some(dataset.to_string()),
+                flags: self.get_flags(dataset),
+                checkpoint: Some(IndexCheckpoint {
+                    path,
+                    every: settings.every,
+                    checkpoint,
+                }),
+                _type: PhantomData,
+            });
+        }
+
+        let index_name = get_date_index_name(&alias);
+        info!("creating index {}", index_name);
+        let synonyms = self.get_synonyms(T::doc_type(), Some(dataset))?;
+        self.create_dataset_index(
+            &index_name,
+            T::doc_type(),
+            dataset,
+            index_settings,
+            &synonyms,
+        )?;
+        // the checksum of the source is also stored in the index, to check it when resuming
+        let meta = serde_json::json!({ "_meta": { "source_checksum": settings.source_checksum } });
+        self.put(
+            &format!("{}/_mapping/{}", index_name, T::doc_type()),
+            &meta.to_string(),
+        )
+        .with_context(|_| format!("Error occurred when updating the mapping of {}", index_name))?;
+        self.copy_outside_bbox::<T>(dataset, &index_name)?;
+
+        let checkpoint = Checkpoint {
+            index: index_name.clone(),
+            source_checksum: settings.source_checksum.clone(),
+            nb_documents: 0,
+        };
+        checkpoint::write(&path, &checkpoint)?;
+        Ok(TypedIndex {
+            name: index_name,
+            dataset: Some(dataset.to_string()),
+            flags: self.get_flags(dataset),
+            checkpoint: Some(IndexCheckpoint {
+                path,
+                every: settings.every,
+                checkpoint,
+            }),
+            _type: PhantomData,
+        })
+    }
+
+    // copy in the new index the documents of the current index of the dataset outside of the bbox
+    // (if there is one), the reimported documents are then added to them
+    fn copy_outside_bbox<T: MimirObject>(
+        &self,
+        dataset: &str,
+        index_name: &str,
+    ) -> Result<(), Error> {
+        let bbox = match self.bbox {
+            Some(bbox) => bbox,
+            None => return Ok(()),
+        };
+        let alias = get_main_type_and_dataset_index::<T>(dataset);
+        if self.get_all_aliased_index(&alias)?.is_empty() {
+            info!(
+                "no index {} to copy, only the documents in the bbox are imported",
+                alias
+            );
+            return Ok(());
+        }
+        info!(
+            "copying the documents of {} outside of the bbox into {}",
+            alias, index_name
+        );
+        let body = serde_json::json!({
+            "source": {
+                "index": alias,
+                "type": T::doc_type(),
+                "query": {
+                    "bool": {
+                        "must_not": {
+                            "geo_bounding_box": {
+                                "coord": {
+                                    "top_left": { "lat": bbox.max().y, "lon": bbox.min().x },
+                                    "bottom_right": { "lat": bbox.min().y, "lon": bbox.max().x }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "dest": { "index": index_name }
+        });
+        self.post("_reindex?refresh=true", &body.to_string())
+            .with_context(|_| {
+                format!("Error occurred when copying {} into {}", alias, index_name)
+            })?;
+        Ok(())
+    }
+
+    // the checksum of the source stored in the mapping of an index
+    fn get_source_checksum(&self, index: &str, doc_type: &str) -> Result<Option<String>, Error> {
+        let res = self
+            .get(&format!("{}/_mapping/{}", index, doc_type))
+            .with_context(|_| format!("Error occurred when getting the mapping of {}", index))?;
+        let value: serde_json::Value = res.read_response()?;
+        Ok(value
+            .pointer(&format!(
+                "/{}/mappings/{}/_meta/source_checksum",
+                index, doc_type
+            ))
+            .and_then(|s| s.as_str())
+            .map(|s| s.to_string()))
+    }
+
+    /// Create an index with the default synonyms
+    pub fn create_index(&self, name: &str, index_settings: &IndexSettings) -> Result<(), Error> {
+        self.create_index_with_synonyms(name, index_settings, &synonyms::default_synonyms())
+    }
+
+    pub fn create_index_with_synonyms(
+        &self,
+        name: &str,
+        index_settings: &IndexSettings,
+        synonyms: &[String],
+    ) -> Result<(), Error> {
+        self.create_index_with_overrides(name, index_settings, synonyms, None)
+    }
+
+    // create an index, with the `overrides` merged into the settings of the mimir indexes
+    fn create_index_with_overrides(
+        &self,
+        name: &str,
+        index_settings: &IndexSettings,
+        synonyms: &[String],
+        overrides: Option<&serde_json::Value>,
+    ) -> Result<(), Error> {
+        debug!("creating index");
+        if let Some(ref dir) = self.dry_run_dir {
+            return dry_run::create_index(dir, name);
+        }
+        // Note: in rs_es it can be done with MappingOperation but for the moment I think
+        // storing the mapping in json is more convenient
+        let settings = include_str!("../../../config/settings.json");
+
+        let mut settings_json_value = serde_json::from_str::<serde_json::Value>(&settings)
+            .map_err(|err| {
+                format_err!("Error occurred when creating index: {} err: {}", name, err)
+            })?;
+
+        *settings_json_value
+            .pointer_mut("/settings/analysis/filter/synonym_filter/synonyms")
+            .unwrap() = serde_json::Value::from(synonyms);
+
+        *settings_json_value
+            .pointer_mut("/settings/number_of_shards")
+            .unwrap() = serde_json::Value::from(index_settings.nb_shards);
+        *settings_json_value
+            .pointer_mut("/settings/number_of_replicas")
+            .unwrap() = serde_json::Value::from(index_settings.nb_replicas);
+        if let Some(overrides) = overrides {
+            merge_json(&mut settings_json_value["settings"], overrides);
+        }
+
+        self.put(name, &settings_json_value.to_string())
+            .map_err(|e| {
+                format_err!(
+                    "Error: {},  while creating new index {}, ",
+                    name,
+                    e.to_string()
+                )
+            })
+            .and_then(|res| {
+                if res.status() == StatusCode::OK {
+                    Ok(())
+                } else {
+                    Err(format_err!("cannot create index: {:?}", res))
+                }
+            })
+    }
+
+    pub fn create_template(&self, name: &str, settings: &str) -> Result<(), Error> {
+        debug!("creating template");
+        self.put(&format!("_template/{}", name), settings)
+            .map_err(|e| {
+                info!("Error while creating template {}", name);
+                format_err!("Error: {} while creating template {}", e.to_string(), name)
+            })
+            .and_then(|res| {
+                if res.status() == StatusCode::OK {
+                    Ok(())
+                } else {
+                    Err(format_err!("cannot create template: {:?}", res))
                 }
             })
     }
 
     pub fn initialize_templates(&self) -> Result<(), Error> {
+        if self.is_dry_run() {
+            return Ok(());
+        }
         self.create_template(
             &"template_addr",
             include_str!("../../../config/addr_settings.json"),
@@ -461,9 +1578,17 @@ impl Rubber {
         &self,
         base_index: &str,
     ) -> Result<BTreeMap<String, Vec<String>>, Error> {
-        let res = self
-            .get(&format!("{}_*/_aliases", base_index))
-            .with_context(|_| format!("Error occurred when getting {}*/_aliases", base_index))?;
+        Ok(self
+            .get_aliases(base_index)
+            .with_context(|_| format!("Error occurred when getting {}*/_aliases", base_index))?)
+    }
+
+    /// Like `get_all_aliased_index`, with the error of ES (see `es_error_status`)
+    pub fn get_aliases(&self, base_index: &str) -> Result<BTreeMap<String, Vec<String>>, EsError> {
+        if self.is_dry_run() {
+            return Ok(BTreeMap::new());
+        }
+        let res = self.get(&format!("{}_*/_aliases", base_index))?;
         match res.status() {
             StatusCode::OK => {
                 let value: serde_json::Value = res.read_response()?;
@@ -488,7 +1613,10 @@ impl Rubber {
                 info!("impossible to find alias {}", base_index);
                 Ok(BTreeMap::new())
             }
-            _ => Err(format_err!("invalid elasticsearch response: {:?}", res)),
+            _ => Err(EsError::EsError(format!(
+                "invalid elasticsearch response: {:?}",
+                res
+            ))),
         }
     }
 
@@ -510,7 +1638,32 @@ impl Rubber {
             .collect())
     }
 
+    /// Whether some addresses or streets are indexed, to be given to the pois and the stops
+    /// by `get_address`. In dry run, whether some are published in the dry run directory.
+    pub fn has_addresses(&self) -> bool {
+        if let Some(ref dir) = self.dry_run_dir {
+            return dry_run::published_indexes(dir).map_or(false, |indexes| {
+                indexes.iter().any(|(_, metadata)| {
+                    metadata.public
+                        && (metadata.doc_type == Addr::doc_type()
+                            || metadata.doc_type == Street::doc_type())
+                })
+            });
+        }
+        let indexes = [
+            get_main_type_index::<Addr>(),
+            get_main_type_index::<Street>(),
+        ];
+        indexes.iter().any(|index| {
+            self.get_all_aliased_index(index)
+                .map_or(false, |aliased| !aliased.is_empty())
+        })
+    }
+
     pub fn get_address(&mut self, coord: &Coord) -> Result<Vec<Place>, EsError> {
+        if self.is_dry_run() {
+            return self.get_dry_run_address(coord);
+        }
         let types = vec!["house", "street"];
         let indexes = get_indexes(false, &[], &[], &types);
         let indexes = indexes
@@ -518,7 +1671,7 @@ impl Rubber {
             .map(|index| index.as_str())
             .collect::<Vec<&str>>();
 
-        let distance = rs_u::Distance::new(1000., rs_u::DistanceUnit::Meter);
+        let distance = rs_u::Distance::new(self.address_radius, rs_u::DistanceUnit::Meter);
         let geo_distance =
             Query::build_geo_distance("coord", (coord.lat(), coord.lon()), distance).build();
         let query = Query::build_bool()
@@ -528,22 +1681,73 @@ impl Rubber {
 
         let timer = ES_REQ_HISTOGRAM.start_timer();
 
-        let timeout = self.timeout.map(|t| format!("{:?}", t));
-        let mut search_query = self.es_client.search_query();
-
-        let search_query = search_query
-            .with_ignore_unavailable(true)
-            .with_indexes(&indexes)
-            .with_query(&query)
-            .with_size(1);
-
-        if let Some(timeout) = &timeout {
-            search_query.with_timeout(timeout.as_str());
+        let mut body = serde_json::json!({
+            "query": serde_json::to_value(&query)?,
+            "size": 1,
+        });
+        if let Some(timeout) = self.timeout {
+            body["timeout"] = format!("{}ms", timeout.as_millis()).into();
         }
-        let result = search_query.send()?;
+        let response = self.raw_search_bytes(&indexes, &body, None)?;
 
         timer.observe_duration();
-        read_places(result, Some(coord))
+        let places = read_places_from_slice(&response, Some(coord))?;
+        Ok(places
+            .into_iter()
+            .map(|place| snap_address(place, coord))
+            .collect())
+    }
+
+    /// `get_address` on the addresses and streets published in the dry run directory:
+    /// the closest one within the address radius.
+    fn get_dry_run_address(&mut self, coord: &Coord) -> Result<Vec<Place>, EsError> {
+        let addresses = match self.dry_run_addresses {
+            Some(ref addresses) => addresses.clone(),
+            None => {
+                let dir = self.dry_run_dir.clone().unwrap_or_default();
+                let mut addresses: Vec<Place> = dry_run::read_aliased_documents::<Addr>(
+                    &dir,
+                    &get_main_type_index::<Addr>(),
+                    Addr::doc_type(),
+                )?
+                .into_iter()
+                .map(Place::Addr)
+                .collect();
+                addresses.extend(
+                    dry_run::read_aliased_documents::<Street>(
+                        &dir,
+                        &get_main_type_index::<Street>(),
+                        Street::doc_type(),
+                    )?
+                    .into_iter()
+                    .map(Place::Street),
+                );
+                let addresses = Arc::new(addresses);
+                self.dry_run_addresses = Some(addresses.clone());
+                addresses
+            }
+        };
+        let point: geo_types::Point<f64> = coord.0.into();
+        let closest = addresses
+            .iter()
+            .map(|place| {
+                use geo::algorithm::haversine_distance::HaversineDistance;
+                let place = snap_address(place.clone(), coord);
+                let distance = place
+                    .distance()
+                    .map(f64::from)
+                    .unwrap_or_else(|| point.haversine_distance(&place.coord().0.into()));
+                (distance, place)
+            })
+            .filter(|(distance, _)| *distance <= self.address_radius)
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(closest
+            .map(|(distance, mut place)| {
+                place.set_distance(distance as u32);
+                place
+            })
+            .into_iter()
+            .collect())
     }
 
     /// publish the index as the new index for this doc_type and this dataset
@@ -557,12 +1761,35 @@ impl Rubber {
         visibility: IndexVisibility,
     ) -> Result<(), Error> {
         debug!("publishing index");
+        if let Some(ref dir) = self.dry_run_dir {
+            return dry_run::write_metadata(
+                dir,
+                &index.name,
+                &IndexMetadata {
+                    dataset: dataset.to_string(),
+                    doc_type: T::doc_type().to_string(),
+                    public: matches!(visibility, IndexVisibility::Public),
+                },
+            );
+        }
+
+        // the synonyms are only read at the creation of the index,
+        // so it is not published if the synonym file has changed since
+        let synonyms = self.get_synonyms(T::doc_type(), Some(dataset))?;
+        self.check_synonyms(&index.name, &synonyms)?;
 
         // Refresh index before publishing
         self.es_client
             .refresh()
             .with_indexes(&[&index.name])
             .send()?;
+        if let Some(ref thresholds) = self.validation {
+            let report = validation::validate_index(self, &index.name)?;
+            info!("validation of {}: {}", index.name, report);
+            report
+                .check(thresholds)
+                .with_context(|_| format!("{} cannot be published", index.name))?;
+        }
         let last_indexes = self.get_last_index(&index, dataset)?;
 
         let dataset_index = get_main_type_and_dataset_index::<T>(dataset);
@@ -594,10 +1821,40 @@ impl Rubber {
             }
         }
 
-        for i in last_indexes {
+        let (kept_indexes, pruned_indexes) = split_generations(last_indexes, self.keep_previous);
+        if !kept_indexes.is_empty() {
+            info!("keeping the previous indexes {:?}", kept_indexes);
+        }
+        for i in pruned_indexes {
             self.delete_index(&i)
                 .with_context(|err| format!("Error occurred when deleting index {}: {}", i, err))?;
         }
+        if let Some(ref index_checkpoint) = index.checkpoint {
+            checkpoint::remove(&index_checkpoint.path)?;
+        }
+        Ok(())
+    }
+
+    // check that the analyzers of the index use the given synonyms
+    fn check_synonyms(&self, index: &str, synonyms: &[String]) -> Result<(), Error> {
+        let res = self
+            .get(&format!("{}/_settings", index))
+            .with_context(|_| format!("Error occurred when getting the settings of {}", index))?;
+        let value: serde_json::Value = res.read_response()?;
+        let index_synonyms = value
+            .pointer(&format!(
+                "/{}/settings/index/analysis/filter/synonym_filter/synonyms",
+                index
+            ))
+            .and_then(|s| s.as_array())
+            .map(|s| s.iter().filter_map(|s| s.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if index_synonyms != synonyms {
+            bail!(
+                "the synonyms have changed since the creation of {}, it cannot be published",
+                index
+            );
+        }
         Ok(())
     }
 
@@ -608,6 +1865,9 @@ impl Rubber {
             "for {}, adding alias {:?}, removing {:?}",
             alias, add, remove
         );
+        if self.is_dry_run() {
+            return Ok(());
+        }
         let add_operations = add.iter().map(|x| AliasOperation {
             remove: None,
             add: Some(AliasParameter {
@@ -637,6 +1897,9 @@ impl Rubber {
 
     pub fn delete_index(&mut self, index: &str) -> Result<(), Error> {
         debug!("deleting index {}", &index);
+        if self.is_dry_run() {
+            return Ok(());
+        }
         let res = self
             .es_client
             .delete_index(&index)
@@ -649,13 +1912,16 @@ impl Rubber {
         }
     }
 
+    /// Index the documents of `iter`, and return the number of documents indexed.
+    ///
+    /// If the index has a checkpoint, the documents already indexed are skipped
+    /// and the checkpoint is updated while the documents are indexed.
     pub fn bulk_index<T, I>(&mut self, index: &TypedIndex<T>, iter: I) -> Result<usize, Error>
     where
         T: MimirObject + std::marker::Send + 'static,
         I: Iterator<Item = T>,
     {
         use par_map::ParMap;
-        use rs_es::operations::bulk::Action;
 
         struct BulkResultCount {
             nb_ok: usize,
@@ -681,68 +1947,90 @@ impl Rubber {
             }
         }
 
+        // with a bbox, the documents outside of it are the ones of the current index
+        let bbox = self.bbox;
+        let iter = iter.filter(move |doc| bbox.map_or(true, |bbox| is_in_bbox(doc.coord(), &bbox)));
+
+        let dataset = index.dataset.clone();
+        let flags = index.flags.clone();
+        let stamp = move |document| DatasetDocument {
+            document,
+            dataset: dataset.clone(),
+            flags: flags.clone(),
+        };
+
+        if let Some(ref dir) = self.dry_run_dir {
+            let nb_documents = dry_run::write_documents(dir, &index.name, iter.map(stamp))?;
+            record_indexed_documents(T::doc_type(), nb_documents, 0);
+            return Ok(nb_documents);
+        }
+
         let chunk_size = 1000;
-        let index_name = index.name.to_owned();
-        let client = self.es_client.clone();
-
-        iter.pack(chunk_size)
-            .par_map(|v| {
-                v.into_iter()
-                    .map(|v| {
-                        v.es_id()
-                            .into_iter()
-                            .fold(Action::index(v), |action, id| action.with_id(id))
-                    })
-                    .collect::<Vec<_>>()
+        let url = self
+            .es_client
+            .full_url(&format!("{}/{}/_bulk", index.name, T::doc_type()));
+        let http_client = self.http_client.clone();
+        let concurrency = Arc::new(AdaptiveConcurrency::new(
+            self.max_bulk_requests.unwrap_or(self.nb_insert_threads),
+        ));
+        let chunk_concurrency = concurrency.clone();
+
+        // when resuming, the documents already indexed are skipped
+        let mut progress = index.checkpoint.clone().map(checkpoint::Progress::new);
+        let nb_indexed = progress.as_ref().map_or(0, |p| p.nb_documents());
+
+        let results = iter
+            .skip(nb_indexed)
+            .pack(chunk_size)
+            .enumerate()
+            .par_map(move |(chunk_id, v)| {
+                let chunk = v
+                    .into_iter()
+                    .map(|v| bulk_line(v.es_id(), &stamp(v)))
+                    .collect::<Result<Vec<_>, _>>();
+                (chunk_id, chunk)
             })
             .with_nb_threads(self.nb_insert_threads)
-            .par_map(move |chunk| {
-                let res = client
-                    .clone()
-                    .bulk(&chunk)
-                    .with_index(&index_name)
-                    .with_doc_type(T::doc_type())
-                    .send()?;
-
-                let mut nb_errors_in_chunk = 0;
-
-                if res.errors {
-                    res.items
-                        .iter()
-                        .filter(|action_res| action_res.inner.status != 200)
-                        .for_each(|action_res| {
-                            nb_errors_in_chunk += 1;
-                            // We only display a warning if it brings some information, otherwise
-                            // the log is distracting
-                            if let Some(ref error) = action_res.inner.error {
-                                let error = serde_json::to_string(error)
-                                    .unwrap_or_else(|_| String::from("Could not serialize error"));
-
-                                warn!(
-                                    "An error occured while importing {} '{}'. Status {}: {}",
-                                    T::doc_type(),
-                                    action_res.inner.id,
-                                    action_res.inner.status,
-                                    error
-                                );
-                            }
-                        });
-                }
-                Ok(BulkResultCount {
-                    nb_ok: chunk.len() - nb_errors_in_chunk,
-                    nb_errors: nb_errors_in_chunk,
-                })
-            })
-            .try_fold(BulkResultCount::zero(), |sum, res| {
-                let result = res.map(|chunk_len| sum + chunk_len);
-                if let Ok(ref counts) = result {
-                    if counts.nb_errors > self.max_bulk_errors {
-                        return Err(format_err!("too many errors during bulk_index"));
-                    }
-                }
-                result
-            })
-            .map(|counts| counts.nb_ok)
+            .par_map(move |(chunk_id, chunk)| {
+                let chunk = chunk?;
+                let nb_documents = chunk.len();
+                let nb_errors_in_chunk =
+                    send_bulk::<T>(&http_client, &url, chunk, &chunk_concurrency)?;
+                Ok::<_, Error>((
+                    chunk_id,
+                    BulkResultCount {
+                        nb_ok: nb_documents - nb_errors_in_chunk,
+                        nb_errors: nb_errors_in_chunk,
+                    },
+                ))
+            });
+
+        let mut counts = BulkResultCount::zero();
+        for res in results {
+            let (chunk_id, chunk_counts) = res?;
+            let chunk_len = chunk_counts.nb_ok + chunk_counts.nb_errors;
+            counts = counts + chunk_counts;
+            if counts.nb_errors > self.max_bulk_errors {
+                record_indexed_documents(T::doc_type(), counts.nb_ok, counts.nb_errors);
+                return Err(format_err!("too many errors during bulk_index"));
+            }
+            if let Some(ref mut progress) = progress {
+                progress.chunk_indexed(chunk_id, chunk_len)?;
+            }
+            if (chunk_id + 1) % PROGRESS_LOG_EVERY == 0 {
+                info!(
+                    "{} {} documents indexed, {} bulk requests in flight at most",
+                    counts.nb_ok,
+                    T::doc_type(),
+                    concurrency.limit()
+                );
+            }
+        }
+        if let Some(ref mut progress) = progress {
+            progress.write()?;
+        }
+        record_indexed_documents(T::doc_type(), counts.nb_ok, counts.nb_errors);
+        Ok(counts.nb_ok)
     }
 
     /// Shortcut to `index` for a public index
@@ -809,6 +2097,9 @@ impl Rubber {
     where
         for<'de> T: MimirObject + serde::de::Deserialize<'de> + std::fmt::Debug,
     {
+        if let Some(ref dir) = self.dry_run_dir {
+            return Ok(dry_run::read_aliased_documents(dir, index, T::doc_type())?);
+        }
         let mut result: Vec<T> = vec![];
         let mut scan: ScanResult<T> = self
             .es_client
@@ -833,6 +2124,314 @@ impl Rubber {
         scan.close(&mut self.es_client)?;
         Ok(result)
     }
+
+    /// Look for the objects of the previous import of the dataset that are still there
+    /// with a new id (same name, same admin, close coordinates), and keep their ids
+    /// in the `old_ids` of the new objects, so that the ids stored by the clients still resolve.
+    ///
+    /// Returns the number of objects that have been given old ids.
+    pub fn alias_old_ids<T>(&mut self, dataset: &str, objects: &mut [T]) -> Result<usize, Error>
+    where
+        T: MimirObject + Aliasable + DeserializeOwned + std::fmt::Debug + 'static,
+    {
+        let old_ids_by_id = self.find_old_ids(dataset, objects.iter())?;
+        for object in objects.iter_mut() {
+            let mut old_ids = match old_ids_by_id.get(object.identity().id) {
+                Some(old_ids) => old_ids.clone(),
+                None => continue,
+            };
+            old_ids.extend(object.old_ids().iter().cloned());
+            old_ids.sort();
+            old_ids.dedup();
+            object.set_old_ids(old_ids);
+        }
+        Ok(old_ids_by_id.len())
+    }
+
+    /// The ids of the previous import of the dataset to keep in the new `objects`
+    /// (see `alias_old_ids`), by id of the new objects.
+    ///
+    /// The previous objects are streamed (with a scroll, or from the files in dry run),
+    /// only the identities of the new objects are kept in memory.
+    pub fn find_old_ids<'a, T>(
+        &self,
+        dataset: &str,
+        objects: impl Iterator<Item = &'a T>,
+    ) -> Result<HashMap<String, Vec<String>>, Error>
+    where
+        T: MimirObject + Aliasable + DeserializeOwned + 'static,
+    {
+        use geo::algorithm::haversine_distance::HaversineDistance;
+
+        let index = get_main_type_and_dataset_index::<T>(dataset);
+        let identities: Vec<_> = objects.map(|o| o.identity()).collect();
+        let new_ids: HashSet<&str> = identities.iter().map(|identity| identity.id).collect();
+        let mut identities_by_key: HashMap<(&str, Option<&str>), Vec<&Identity<'_>>> =
+            HashMap::new();
+        for identity in &identities {
+            identities_by_key
+                .entry((identity.name, identity.admin_id))
+                .or_insert_with(Vec::new)
+                .push(identity);
+        }
+
+        let previous_objects = match self.previous_objects::<T>(dataset, &index) {
+            Ok(previous_objects) => previous_objects,
+            Err(e) => {
+                info!("no previous {} to get the old ids from: {}", index, e);
+                return Ok(HashMap::new());
+            }
+        };
+        let mut old_ids_by_id: HashMap<String, Vec<String>> = HashMap::new();
+        for previous in previous_objects {
+            let previous = previous?;
+            let identity = previous.identity();
+            // only the objects that have disappeared from the new import need an alias
+            if new_ids.contains(identity.id) {
+                continue;
+            }
+            let point: geo_types::Point<f64> = identity.coord.0.into();
+            let matching = identities_by_key
+                .get(&(identity.name, identity.admin_id))
+                .into_iter()
+                .flatten()
+                .filter(|new| point.haversine_distance(&new.coord.0.into()) < MAX_ALIAS_DISTANCE);
+            for new in matching {
+                old_ids_by_id
+                    .entry(new.id.to_string())
+                    .or_insert_with(Vec::new)
+                    .extend(
+                        std::iter::once(identity.id.to_string())
+                            .chain(previous.old_ids().iter().cloned()),
+                    );
+            }
+        }
+        info!(
+            "{} objects of {} have kept their old ids",
+            old_ids_by_id.len(),
+            index
+        );
+        Ok(old_ids_by_id)
+    }
+
+    // the objects of the dataset published by the previous import, read one by one
+    fn previous_objects<T>(
+        &self,
+        dataset: &str,
+        index: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<T, Error>> + '_>, Error>
+    where
+        T: MimirObject + DeserializeOwned + 'static,
+    {
+        if let Some(ref dir) = self.dry_run_dir {
+            let mut documents: Box<dyn Iterator<Item = Result<T, Error>>> =
+                Box::new(std::iter::empty());
+            for (name, metadata) in dry_run::published_indexes(dir)? {
+                if metadata.doc_type == T::doc_type() && metadata.dataset == dataset {
+                    documents =
+                        Box::new(documents.chain(dry_run::read_documents(dir, &name)?.map(Ok)));
+                }
+            }
+            return Ok(documents);
+        }
+        let options = ScrollOptions {
+            sorted_by_id: false,
+            ..Default::default()
+        };
+        Ok(Box::new(self.scroll(index, &options)?.map(|document| {
+            document.and_then(|document| serde_json::from_value(document).map_err(Error::from))
+        })))
+    }
+
+    /// Iterate over all the documents of an index, sorted by id.
+    ///
+    /// The documents are fetched page by page, so the index is never loaded in memory.
+    /// The boundaries are not fetched, since they can be huge.
+    pub fn scroll_sorted_by_id(&self, index: &str) -> Result<ScrolledDocuments<'_>, Error> {
+        self.scroll(index, &ScrollOptions::default())
+    }
+
+    /// The documents of an index whose `field` is one of `values`
+    /// (at most a page of a scroll of them).
+    ///
+    /// The boundaries are not fetched, since they can be huge.
+    pub fn find_documents(
+        &self,
+        index: &str,
+        field: &str,
+        values: &[&str],
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let body = serde_json::json!({
+            "size": SCROLL_PAGE_SIZE,
+            "query": { "terms": { field: values } },
+            "_source": { "exclude": ["boundary"] },
+        });
+        let res = self
+            .post(&format!("{}/_search", index), &body.to_string())
+            .with_context(|_| format!("Error occurred when searching {}", index))?;
+        if res.status() == StatusCode::NOT_FOUND {
+            bail!("impossible to find index {}", index);
+        }
+        let mut value: serde_json::Value = res.read_response()?;
+        take_sources(&mut value)
+    }
+
+    /// Iterate over all the documents of an index, fetched page by page with a scroll.
+    ///
+    /// The scroll is cleared once all the documents have been read, after an error,
+    /// or when the iterator is dropped.
+    pub fn scroll(
+        &self,
+        index: &str,
+        options: &ScrollOptions,
+    ) -> Result<ScrolledDocuments<'_>, Error> {
+        let mut body = serde_json::json!({
+            "size": options.page_size,
+            // without sort, the documents are read in the order of the index, the fastest
+            "sort": if options.sorted_by_id {
+                serde_json::json!([{ "id": "asc" }])
+            } else {
+                serde_json::json!(["_doc"])
+            },
+        });
+        if !options.with_boundaries {
+            body["_source"] = serde_json::json!({ "exclude": ["boundary"] });
+        }
+        let res = self
+            .post(
+                &format!("{}/_search?scroll={}", index, options.keep_alive),
+                &body.to_string(),
+            )
+            .with_context(|_| format!("Error occurred when scrolling {}", index))?;
+        if res.status() == StatusCode::NOT_FOUND {
+            bail!("impossible to find index {}", index);
+        }
+        let mut documents = ScrolledDocuments {
+            rubber: self,
+            keep_alive: options.keep_alive.clone(),
+            scroll_id: None,
+            page: Vec::new().into_iter(),
+            finished: false,
+        };
+        documents.read_page(res)?;
+        Ok(documents)
+    }
+}
+
+// the documents of the hits of a search response
+fn take_sources(response: &mut serde_json::Value) -> Result<Vec<serde_json::Value>, Error> {
+    let hits = match response.pointer_mut("/hits/hits") {
+        Some(serde_json::Value::Array(hits)) => std::mem::replace(hits, Vec::new()),
+        _ => bail!("invalid elasticsearch response: no hits"),
+    };
+    Ok(hits
+        .into_iter()
+        .filter_map(|mut hit| hit.get_mut("_source").map(serde_json::Value::take))
+        .collect())
+}
+
+/// How the documents of an index are scrolled, see `Rubber::scroll`
+#[derive(Debug, Clone)]
+pub struct ScrollOptions {
+    /// Number of documents fetched by each request
+    pub page_size: usize,
+    /// How long ES keeps the scroll alive between two requests (like `1m`)
+    pub keep_alive: String,
+    /// Sorts the documents by id, else they are read in the order of the index
+    pub sorted_by_id: bool,
+    /// Fetches the boundaries of the admins, which can be huge
+    pub with_boundaries: bool,
+}
+
+impl Default for ScrollOptions {
+    fn default() -> Self {
+        ScrollOptions {
+            page_size: SCROLL_PAGE_SIZE,
+            keep_alive: SCROLL_DURATION.to_string(),
+            sorted_by_id: true,
+            with_boundaries: false,
+        }
+    }
+}
+
+/// Documents of an index, see `Rubber::scroll`
+pub struct ScrolledDocuments<'a> {
+    rubber: &'a Rubber,
+    keep_alive: String,
+    scroll_id: Option<String>,
+    page: std::vec::IntoIter<serde_json::Value>,
+    finished: bool,
+}
+
+impl<'a> ScrolledDocuments<'a> {
+    fn read_page(&mut self, res: reqwest::blocking::Response) -> Result<(), Error> {
+        let mut value: serde_json::Value = res.read_response()?;
+        self.scroll_id = value
+            .get("_scroll_id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string);
+        self.page = take_sources(&mut value)?.into_iter();
+        Ok(())
+    }
+
+    fn next_page(&mut self) -> Result<(), Error> {
+        let scroll_id = match self.scroll_id {
+            Some(ref scroll_id) => scroll_id.clone(),
+            None => return Ok(()),
+        };
+        let body = serde_json::json!({ "scroll": self.keep_alive, "scroll_id": scroll_id });
+        let res = self
+            .rubber
+            .post("_search/scroll", &body.to_string())
+            .context("Error occurred when scrolling")?;
+        self.read_page(res)
+    }
+
+    // the scroll would expire anyway, but we free it as soon as possible
+    fn clear(&mut self) {
+        if let Some(scroll_id) = self.scroll_id.take() {
+            let url = self.rubber.es_client.full_url("_search/scroll");
+            let body = serde_json::json!({ "scroll_id": [scroll_id] });
+            if let Err(e) = self
+                .rubber
+                .http_client
+                .delete(url)
+                .body(body.to_string())
+                .send()
+            {
+                warn!("impossible to clear the scroll: {}", e);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for ScrolledDocuments<'a> {
+    type Item = Result<serde_json::Value, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(document) = self.page.next() {
+            return Some(Ok(document));
+        }
+        if self.finished {
+            return None;
+        }
+        let document = match self.next_page() {
+            Ok(()) => self.page.next().map(Ok),
+            // we stop the iteration after an error
+            Err(e) => Some(Err(e)),
+        };
+        if !matches!(document, Some(Ok(_))) {
+            self.finished = true;
+            self.clear();
+        }
+        document
+    }
+}
+
+impl<'a> Drop for ScrolledDocuments<'a> {
+    fn drop(&mut self) {
+        self.clear();
+    }
 }
 
 #[cfg(test)]
@@ -840,6 +2439,53 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn aborted_search_is_not_waited_for() {
+        // a backend accepting the connections, but never answering
+        let backend = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let abort = Arc::new(AtomicBool::new(false));
+        let rubber = Rubber::new(&format!("http://{}", backend.local_addr().unwrap()))
+            .with_abort(abort.clone());
+        let aborter = std::thread::spawn(move || {
+            std::thread::sleep(time::Duration::from_millis(50));
+            abort.store(true, Ordering::Relaxed);
+        });
+
+        let start = time::Instant::now();
+        let res = rubber.raw_search_bytes(&["munin"], &serde_json::json!({}), None);
+        aborter.join().unwrap();
+        assert!(matches!(res, Err(EsError::EsError(_))));
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn status_of_es_errors() {
+        let error = server_error(StatusCode::BAD_REQUEST, "{\"error\": \"parse_exception\"}");
+        assert_eq!(es_error_status(&error), Some(StatusCode::BAD_REQUEST));
+        let error = server_error(StatusCode::TOO_MANY_REQUESTS, "");
+        assert_eq!(es_error_status(&error), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(
+            es_error_status(&EsError::EsServerError("es_rejected_execution".to_string())),
+            None
+        );
+        assert_eq!(
+            es_error_status(&EsError::EsError("the search has been aborted".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn preference_is_given_in_the_search_path() {
+        assert_eq!(
+            search_path(&["munin_addr", "munin_poi"], "_search", Some("session_42")),
+            "munin_addr,munin_poi/_search?ignore_unavailable=true&preference=session_42"
+        );
+        assert_eq!(
+            search_path(&["munin"], "_search", None),
+            "munin/_search?ignore_unavailable=true"
+        );
+    }
+
     #[test]
     pub fn test_valid_url() {
         Rubber::new("http://localhost:9200");
@@ -847,12 +2493,345 @@ mod tests {
         Rubber::new("http://bob");
     }
 
+    // a fake Elasticsearch answering the bulk requests, and rejecting them with
+    // `429 Too Many Requests` when more than `max_in_flight` of them are in flight
+    struct FakeBulkServer {
+        port: u16,
+        accepted: Arc<std::sync::atomic::AtomicUsize>,
+        rejected: Arc<std::sync::atomic::AtomicUsize>,
+        // maximum number of bulk requests simultaneously in flight
+        peak_in_flight: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl FakeBulkServer {
+        fn start(max_in_flight: usize) -> Self {
+            use std::io::{BufRead, BufReader, Read, Write};
+            use std::sync::atomic::{AtomicUsize, Ordering};
+
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let in_flight = Arc::new(AtomicUsize::new(0));
+            let accepted = Arc::new(AtomicUsize::new(0));
+            let rejected = Arc::new(AtomicUsize::new(0));
+            let peak_in_flight = Arc::new(AtomicUsize::new(0));
+            let (server_accepted, server_rejected, server_peak) =
+                (accepted.clone(), rejected.clone(), peak_in_flight.clone());
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let (in_flight, accepted, rejected, peak) = (
+                        in_flight.clone(),
+                        server_accepted.clone(),
+                        server_rejected.clone(),
+                        server_peak.clone(),
+                    );
+                    std::thread::spawn(move || {
+                        let mut reader = BufReader::new(stream.try_clone().unwrap());
+                        loop {
+                            let mut content_length = 0;
+                            let mut line = String::new();
+                            loop {
+                                line.clear();
+                                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                    return;
+                                }
+                                let header = line.trim().to_lowercase();
+                                if header.is_empty() {
+                                    break;
+                                }
+                                if let Some(len) = header.strip_prefix("content-length:") {
+                                    content_length = len.trim().parse().unwrap();
+                                }
+                            }
+                            let mut body = vec![0; content_length];
+                            reader.read_exact(&mut body).unwrap();
+
+                            let nb_in_flight = in_flight.fetch_add(1, Ordering::SeqCst);
+                            peak.fetch_max(nb_in_flight + 1, Ordering::SeqCst);
+                            let response = if nb_in_flight >= max_in_flight {
+                                rejected.fetch_add(1, Ordering::SeqCst);
+                                "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\n\
+                                 Content-Length: 0\r\n\r\n"
+                                    .to_string()
+                            } else {
+                                std::thread::sleep(time::Duration::from_millis(5));
+                                accepted.fetch_add(1, Ordering::SeqCst);
+                                let nb_documents = body.split(|b| *b == b'\n').count() / 2;
+                                let items =
+                                    vec![r#"{"index":{"_id":"poi","status":201}}"#; nb_documents];
+                                let body = format!(
+                                    r#"{{"took":1,"errors":false,"items":[{}]}}"#,
+                                    items.join(",")
+                                );
+                                format!(
+                                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                                    body.len(),
+                                    body
+                                )
+                            };
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            stream.write_all(response.as_bytes()).unwrap();
+                        }
+                    });
+                }
+            });
+            FakeBulkServer {
+                port,
+                accepted,
+                rejected,
+                peak_in_flight,
+            }
+        }
+    }
+
+    #[test]
+    fn bulk_index_adapts_its_concurrency_to_the_rejections() {
+        use crate::objects::Poi;
+        use std::sync::atomic::Ordering;
+
+        let server = FakeBulkServer::start(2);
+        let mut rubber =
+            Rubber::new(&format!("http://127.0.0.1:{}", server.port)).with_nb_insert_threads(8);
+        let pois = (0..50_000).map(|i| Poi {
+            id: format!("poi:{}", i),
+            ..Default::default()
+        });
+        let nb_indexed = rubber
+            .bulk_index(&TypedIndex::new("munin_poi_test".to_string()), pois)
+            .unwrap();
+
+        // all the documents are indexed in the end, with fewer rejections than accepted requests
+        assert_eq!(nb_indexed, 50_000);
+        let rejected = server.rejected.load(Ordering::SeqCst);
+        assert!(rejected > 0);
+        assert!(rejected < server.accepted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn bulk_index_bounds_the_requests_in_flight() {
+        use crate::objects::Poi;
+        use std::sync::atomic::Ordering;
+
+        let server = FakeBulkServer::start(100);
+        let mut rubber = Rubber::new(&format!("http://127.0.0.1:{}", server.port))
+            .with_nb_insert_threads(8)
+            .with_max_bulk_requests(3);
+        let pois = (0..50_000).map(|i| Poi {
+            id: format!("poi:{}", i),
+            ..Default::default()
+        });
+        let nb_indexed = rubber
+            .bulk_index(&TypedIndex::new("munin_poi_test".to_string()), pois)
+            .unwrap();
+
+        assert_eq!(nb_indexed, 50_000);
+        assert_eq!(server.rejected.load(Ordering::SeqCst), 0);
+        let peak_in_flight = server.peak_in_flight.load(Ordering::SeqCst);
+        assert!(
+            peak_in_flight <= 3,
+            "{} bulk requests in flight",
+            peak_in_flight
+        );
+    }
+
+    #[test]
+    fn coords_in_bbox() {
+        let bbox = geo_types::Rect::new(
+            geo_types::Coordinate { x: 2.2, y: 48.8 },
+            geo_types::Coordinate { x: 2.4, y: 48.9 },
+        );
+        assert!(is_in_bbox(&Coord::new(2.3, 48.85), &bbox));
+        assert!(is_in_bbox(&Coord::new(2.2, 48.9), &bbox));
+        assert!(!is_in_bbox(&Coord::new(2.5, 48.85), &bbox));
+        assert!(!is_in_bbox(&Coord::new(2.3, 48.7), &bbox));
+    }
+
+    #[test]
+    fn index_settings_are_overridden_by_type_then_by_dataset() {
+        let overrides = |nb_shards| IndexSettingsOverride {
+            nb_shards: Some(nb_shards),
+            ..Default::default()
+        };
+        let rubber = Rubber::new("http://localhost:9200").with_index_settings_overrides(
+            vec![
+                ("fr".to_string(), overrides(3)),
+                ("addr_fr".to_string(), overrides(5)),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(
+            rubber.get_index_settings_override("addr", "fr"),
+            Some(&overrides(5))
+        );
+        assert_eq!(
+            rubber.get_index_settings_override("admin", "fr"),
+            Some(&overrides(3))
+        );
+        assert_eq!(rubber.get_index_settings_override("addr", "be"), None);
+    }
+
+    #[test]
+    fn index_settings_overrides_are_merged() {
+        let mut settings = serde_json::json!({
+            "number_of_shards": 1,
+            "analysis": { "filter": { "prefix_filter": { "min_gram": 1, "max_gram": 20 } } },
+        });
+        merge_json(
+            &mut settings,
+            &serde_json::json!({
+                "analysis": { "filter": { "prefix_filter": { "max_gram": 15 } } },
+                "refresh_interval": "30s",
+            }),
+        );
+        assert_eq!(
+            settings,
+            serde_json::json!({
+                "number_of_shards": 1,
+                "analysis": { "filter": { "prefix_filter": { "min_gram": 1, "max_gram": 15 } } },
+                "refresh_interval": "30s",
+            })
+        );
+    }
+
+    #[test]
+    fn transport_is_kept_by_the_rubber() {
+        let transport = TransportSettings {
+            keep_alive: Some(time::Duration::from_secs(30)),
+            idle_timeout: Some(time::Duration::from_secs(50)),
+        };
+        let rubber = Rubber::new("http://localhost:9200").with_transport(transport.clone());
+        assert_eq!(rubber.transport, transport);
+        assert_eq!(rubber.clone().transport, transport);
+    }
+
+    // a fake ES answering all the searches without hits, with the number of connections opened to it
+    fn fake_es() -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let nb_connections = Arc::new(AtomicUsize::new(0));
+        let counter = nb_connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    // the requests of the connection, until it is closed
+                    loop {
+                        let mut content_length = 0;
+                        loop {
+                            let mut line = String::new();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let line = line.trim_end().to_lowercase();
+                            if line.is_empty() {
+                                break;
+                            }
+                            if line.starts_with("content-length:") {
+                                content_length =
+                                    line["content-length:".len()..].trim().parse().unwrap_or(0);
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        if reader.read_exact(&mut body).is_err() {
+                            return;
+                        }
+                        let response =
+                            r#"{"took":1,"timed_out":false,"hits":{"total":0,"hits":[]}}"#;
+                        let written = write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            response.len(),
+                            response
+                        );
+                        if written.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (url, nb_connections)
+    }
+
+    #[test]
+    fn searches_use_the_pooled_connections_of_the_transport() {
+        use std::sync::atomic::Ordering;
+
+        let (url, nb_connections) = fake_es();
+        let coord = Coord::new(2.35, 48.85);
+
+        // the connection of the first search is reused by the next one
+        let mut rubber = Rubber::new(&url).with_transport(TransportSettings {
+            keep_alive: None,
+            idle_timeout: Some(time::Duration::from_secs(60)),
+        });
+        assert!(rubber.get_address(&coord).unwrap().is_empty());
+        std::thread::sleep(time::Duration::from_millis(50));
+        assert!(rubber.get_address(&coord).unwrap().is_empty());
+        assert_eq!(nb_connections.load(Ordering::SeqCst), 1);
+
+        // unless it has been idle for longer than the idle timeout of the transport
+        let mut rubber = Rubber::new(&url).with_transport(TransportSettings {
+            keep_alive: None,
+            idle_timeout: Some(time::Duration::from_millis(50)),
+        });
+        rubber.get_address(&coord).unwrap();
+        std::thread::sleep(time::Duration::from_millis(500));
+        rubber.get_address(&coord).unwrap();
+        assert_eq!(nb_connections.load(Ordering::SeqCst), 3);
+    }
+
     #[test]
     #[should_panic]
     pub fn test_invalid_url_no_port() {
         Rubber::new("localhost");
     }
 
+    #[test]
+    fn test_split_generations() {
+        let generations = || {
+            vec![
+                "munin_addr_fr_20201012_101010_000000".to_string(),
+                "munin_addr_fr_20201014_101010_000000".to_string(),
+                "munin_addr_fr_20201011_101010_000000".to_string(),
+                "munin_addr_fr_20201013_101010_000000".to_string(),
+            ]
+        };
+
+        let (kept, pruned) = split_generations(generations(), 0);
+        assert!(kept.is_empty());
+        assert_eq!(pruned.len(), 4);
+
+        let (kept, pruned) = split_generations(generations(), 2);
+        assert_eq!(
+            kept,
+            vec![
+                "munin_addr_fr_20201014_101010_000000",
+                "munin_addr_fr_20201013_101010_000000",
+            ]
+        );
+        assert_eq!(
+            pruned,
+            vec![
+                "munin_addr_fr_20201012_101010_000000",
+                "munin_addr_fr_20201011_101010_000000",
+            ]
+        );
+
+        let (kept, pruned) = split_generations(generations(), 10);
+        assert_eq!(kept.len(), 4);
+        assert!(pruned.is_empty());
+    }
+
     #[test]
     fn test_get_indexes_impl() {
         // all_data
@@ -936,4 +2915,299 @@ mod tests {
             vec!["munin_admin", "munin_street", "munin_addr", "munin_poi_mti"]
         );
     }
+
+    #[test]
+    fn places_read_from_the_body_are_the_ones_read_from_its_json() {
+        use crate::objects::{Poi, Street};
+        use std::sync::Arc;
+
+        let coord = Coord::new(2.376_379, 48.846_495);
+        let paris = Admin {
+            id: "admin:paris".to_string(),
+            name: "Paris".to_string(),
+            label: "Paris (75000-75116)".to_string(),
+            coord,
+            ..Default::default()
+        };
+        let street = Street {
+            id: "street:1".to_string(),
+            name: "Rue de Bercy".to_string(),
+            label: "Rue de Bercy (Paris)".to_string(),
+            coord,
+            administrative_regions: vec![Arc::new(paris.clone())],
+            ..Default::default()
+        };
+        let poi = Poi {
+            id: "poi:1".to_string(),
+            name: "Café \"du\" Marché".to_string(),
+            label: "Café \"du\" Marché (Paris)".to_string(),
+            coord,
+            administrative_regions: vec![Arc::new(paris.clone())],
+            ..Default::default()
+        };
+        let response = serde_json::json!({
+            "took": 3,
+            "hits": {
+                "total": 4,
+                "max_score": 2.5,
+                "hits": [
+                    { "_index": "munin_street", "_type": "street", "_id": "street:1", "_score": 2.5, "_source": street },
+                    { "_index": "munin_admin", "_type": "admin", "_id": "admin:paris", "_score": 1.5, "_source": paris },
+                    { "_index": "munin_poi", "_type": "poi", "_id": "poi:1", "_score": null, "_source": poi },
+                    { "_index": "munin_other", "_type": "other", "_id": "other:1", "_score": 1.0, "_source": { "id": "other:1" } },
+                ]
+            }
+        });
+
+        let expected: Vec<Place> = response["hits"]["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|hit| {
+                let doc_type = hit["_type"].as_str().unwrap().to_string();
+                let mut place = make_place(doc_type, Some(Box::new(hit["_source"].clone())), None)?;
+                if let Some(score) = hit["_score"].as_f64() {
+                    place.set_score(score);
+                }
+                Some(place)
+            })
+            .collect();
+        let places = read_places_from_slice(response.to_string().as_bytes(), None).unwrap();
+        assert_eq!(places.len(), 3);
+        assert_eq!(
+            serde_json::to_value(&places).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    // ES response with a place of each type, and hits without place
+    const GOLDEN_SEARCH_RESPONSE: &str =
+        include_str!("../../../tests/fixtures/es_search_response.json");
+
+    // the places, with what is not serialized
+    fn comparable_places(
+        places: Vec<Place>,
+    ) -> Vec<(serde_json::Value, Option<f64>, Option<u32>, Flags)> {
+        places
+            .into_iter()
+            .map(|mut place| {
+                // `read_raw_places` always sets the context, for the match reasons
+                place.set_context(place.context().unwrap_or_default());
+                let flags = place.flags().clone();
+                let value = serde_json::to_value(&place).unwrap();
+                (value, place.score(), place.distance(), flags)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn places_read_from_slice_are_the_ones_of_the_raw_search() {
+        let coord = Coord::new(2.3767, 48.8462);
+        for coord in [None, Some(&coord)].iter() {
+            let response: serde_json::Value = serde_json::from_str(GOLDEN_SEARCH_RESPONSE).unwrap();
+            let expected = read_raw_places(response, *coord).unwrap();
+            let places = read_places_from_slice(GOLDEN_SEARCH_RESPONSE.as_bytes(), *coord).unwrap();
+            assert_eq!(places.len(), 5);
+            assert_eq!(comparable_places(places), comparable_places(expected));
+        }
+    }
+
+    #[test]
+    fn places_of_the_golden_response_are_read_from_slice() {
+        use crate::objects::{Address, FlagValue};
+
+        let coord = Coord::new(2.3767, 48.8462);
+        let places =
+            read_places_from_slice(GOLDEN_SEARCH_RESPONSE.as_bytes(), Some(&coord)).unwrap();
+        // the hit without source, the invalid one and the one of an unknown type are skipped
+        let ids: Vec<_> = places.iter().map(Place::id).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "addr:2.376379;48.846495:20",
+                "street:75112_1045",
+                "admin:fr:75056",
+                "poi:osm:node:1234",
+                "stop_area:RAT:SA:GDLYO",
+            ]
+        );
+        let scores: Vec<_> = places.iter().map(Place::score).collect();
+        assert_eq!(
+            scores,
+            vec![Some(8.25), Some(7.5), Some(6.), None, Some(4.125)]
+        );
+        assert!(places.iter().all(|place| place.distance().is_some()));
+
+        match &places[0] {
+            Place::Addr(addr) => {
+                assert_eq!(addr.house_number_numeric, Some(20));
+                assert_eq!(
+                    addr.street.administrative_regions[0].name,
+                    "Paris 12e Arrondissement"
+                );
+            }
+            place => panic!("unexpected place {:?}", place),
+        }
+        assert_eq!(
+            places[0].flags().get("verified"),
+            Some(&FlagValue::Bool(true))
+        );
+        assert_eq!(
+            places[0].flags().get("source"),
+            Some(&FlagValue::String("bano".to_string()))
+        );
+        match &places[1] {
+            Place::Street(street) => {
+                assert_eq!(
+                    street.coord_line.as_ref().map(|lines| lines.0.len()),
+                    Some(2)
+                );
+                assert_eq!(street.old_ids, vec!["street:osm:way:4070427"]);
+            }
+            place => panic!("unexpected place {:?}", place),
+        }
+        match &places[2] {
+            Place::Admin(admin) => {
+                assert!(admin.boundary.is_some());
+                let bbox = admin.bbox.unwrap();
+                assert_eq!((bbox.min().x, bbox.max().y), (2.2241, 48.9022));
+                assert!(admin.is_city());
+                assert_eq!(admin.labels.get("ru"), Some("Париж, Франция"));
+                assert_eq!(
+                    admin.label,
+                    "Paris (75000-75116), \"Ville Lumière\", Île-de-France, France"
+                );
+            }
+            place => panic!("unexpected place {:?}", place),
+        }
+        match &places[3] {
+            Place::Poi(poi) => {
+                assert_eq!(poi.name, "Café \"du\" Marché\ttabac");
+                assert!(matches!(poi.address, Some(Address::Street(_))));
+                assert_eq!(poi.properties.len(), 2);
+            }
+            place => panic!("unexpected place {:?}", place),
+        }
+        match &places[4] {
+            Place::Stop(stop) => {
+                assert_eq!(stop.lines[0].code.as_deref(), Some("1"));
+                assert_eq!(stop.feed_publishers[0].name, "RATP");
+                assert_eq!(stop.timezone, "Europe/Paris");
+            }
+            place => panic!("unexpected place {:?}", place),
+        }
+    }
+
+    #[test]
+    fn suggestions_are_read_in_the_language_of_the_request() {
+        let response = serde_json::json!({
+            "took": 2,
+            "hits": {
+                "total": 2,
+                "max_score": 2.5,
+                "hits": [
+                    {
+                        "_index": "munin_admin", "_type": "admin", "_id": "admin:london", "_score": 2.5,
+                        "_source": {
+                            "id": "admin:london",
+                            "label": "London",
+                            "labels": { "fr": "Londres" },
+                            "coord": { "lat": 51.507, "lon": -0.128 }
+                        }
+                    },
+                    {
+                        "_index": "munin_street", "_type": "street", "_id": "street:1", "_score": 1.5,
+                        "_source": {
+                            "id": "street:1",
+                            "label": "Oxford Street (London)",
+                            "coord": { "lat": 51.515, "lon": -0.142 }
+                        }
+                    },
+                ]
+            }
+        });
+        let body = response.to_string();
+
+        let suggestions = read_suggestions_from_slice(body.as_bytes(), Some("fr")).unwrap();
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(suggestions[0].id, "admin:london");
+        assert_eq!(suggestions[0].label, "Londres");
+        assert_eq!(suggestions[0].doc_type, "admin");
+        assert_eq!(suggestions[0].coord.lat(), 51.507);
+        // the labels missing in the language of the request are the default ones
+        assert_eq!(suggestions[1].label, "Oxford Street (London)");
+
+        let suggestions = read_suggestions_from_slice(body.as_bytes(), None).unwrap();
+        assert_eq!(suggestions[0].label, "London");
+        assert_eq!(
+            suggestion_source_fields(Some("fr")),
+            vec!["id", "label", "coord", "labels.fr"]
+        );
+    }
+
+    // a logger keeping the key-values of the records logged
+    #[derive(Clone, Default)]
+    struct CapturingDrain(Arc<Mutex<Vec<HashMap<String, String>>>>);
+
+    struct CapturedValues(HashMap<String, String>);
+
+    impl slog::Serializer for CapturedValues {
+        fn emit_arguments(&mut self, key: slog::Key, val: &std::fmt::Arguments) -> slog::Result {
+            self.0.insert(key.to_string(), val.to_string());
+            Ok(())
+        }
+    }
+
+    impl slog::Drain for CapturingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            let mut values = CapturedValues(HashMap::new());
+            slog::KV::serialize(&record.kv(), record, &mut values).unwrap();
+            self.0.lock().unwrap().push(values.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn truncation_does_not_split_characters() {
+        assert_eq!(
+            truncate_at_char_boundary("gare de l'est", 50),
+            "gare de l'est"
+        );
+        assert_eq!(truncate_at_char_boundary("gare de l'est", 4), "gare");
+        // 'é' takes 2 bytes, it would be split at 3 bytes
+        assert_eq!(truncate_at_char_boundary("rué", 3), "ru");
+        assert_eq!(truncate_at_char_boundary("rué", 4), "rué");
+        assert_eq!(truncate_at_char_boundary("東京", 2), "");
+    }
+
+    #[test]
+    fn searches_are_logged_with_their_stats() {
+        let drain = CapturingDrain::default();
+        let logger = slog::Logger::root(slog::Drain::fuse(drain.clone()), slog::o!());
+        let body = r#"{"query":{"match":{"label":"rue de l'église"}}}"#;
+        let response = br#"{"took":12,"timed_out":false,"hits":{"total":42,"hits":[]}}"#;
+
+        slog_scope::scope(&logger, || {
+            let rubber = Rubber::new("http://localhost:9200");
+            // the searches are not logged by default
+            rubber.log_search(&["munin_addr"], body, || unreachable!());
+            let rubber = rubber.with_query_log_length(38);
+            rubber.log_search(&["munin_addr", "munin_poi"], body, || {
+                serde_json::from_slice(response).ok()
+            });
+        });
+
+        let records = drain.0.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record["indexes"], "munin_addr,munin_poi");
+        // the body is cut before the 'é', which would be split at 38 bytes
+        assert_eq!(record["query"], r#"{"query":{"match":{"label":"rue de l'"#);
+        assert_eq!(record["query_length"], body.len().to_string());
+        assert_eq!(record["took"], "12");
+        assert_eq!(record["hits"], "42");
+    }
 }
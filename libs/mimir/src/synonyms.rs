@@ -0,0 +1,147 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Synonyms injected in the analyzers of the indexes when they are created.
+//!
+//! The synonyms are read from files in the Solr format (one rule per line,
+//! `#` for the comments) of a directory:
+//! - `<doc_type>.txt` gives the synonyms of all the datasets of a document type,
+//! - `<doc_type>_<dataset>.txt` replaces them for a dataset,
+//!   so that each country can have its own synonyms.
+//!
+//! Without a file, the default synonyms are used.
+
+use failure::{bail, Error, ResultExt};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SYNONYMS: [&str; 17] = [
+    "cc,centre commercial",
+    "hotel de ville,mairie",
+    "gare sncf,gare",
+    "chu,chr,hopital",
+    "ld,lieu-dit",
+    "st,saint",
+    "ste,sainte",
+    "bvd,bld,bd,boulevard",
+    "pt,pont",
+    "rle,ruelle",
+    "rte,route",
+    "vla,villa",
+    "grand-champ,grandchamp",
+    "fac,faculte,ufr,universite",
+    "embarcadere,gare maritime",
+    "cpam,securite sociale",
+    "anpe,pole emploi",
+];
+
+pub fn default_synonyms() -> Vec<String> {
+    DEFAULT_SYNONYMS.iter().map(|s| (*s).to_string()).collect()
+}
+
+/// Parse the rules of a synonym file in the Solr format
+pub fn parse_synonyms(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The synonym file of the dataset if there is one, else the file of the document type
+pub fn synonyms_file(dir: &Path, doc_type: &str, dataset: Option<&str>) -> Option<PathBuf> {
+    dataset
+        .map(|dataset| dir.join(format!("{}_{}.txt", doc_type, dataset)))
+        .into_iter()
+        .chain(std::iter::once(dir.join(format!("{}.txt", doc_type))))
+        .find(|path| path.is_file())
+}
+
+/// Load the synonyms of a document type and a dataset (`None` for the indexes
+/// shared by all the datasets) from the synonym files of `dir`.
+pub fn load_synonyms(
+    dir: Option<&Path>,
+    doc_type: &str,
+    dataset: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let path = match dir.and_then(|dir| synonyms_file(dir, doc_type, dataset)) {
+        Some(path) => path,
+        None => return Ok(default_synonyms()),
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|_| format!("impossible to read the synonyms of {}", path.display()))?;
+    let synonyms = parse_synonyms(&content);
+    // ES rejects a synonym filter without synonyms
+    if synonyms.is_empty() {
+        bail!("no synonym in {}", path.display());
+    }
+    Ok(synonyms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_solr_synonyms() {
+        let content =
+            "# airports\ncdg, aeroport charles de gaulle\n\n  nyc,new york  \nny => new york\n";
+        assert_eq!(
+            parse_synonyms(content),
+            vec![
+                "cdg, aeroport charles de gaulle",
+                "nyc,new york",
+                "ny => new york"
+            ]
+        );
+    }
+
+    #[test]
+    fn dataset_file_overrides_doc_type_file() {
+        let dir = std::env::temp_dir().join("mimir_synonyms_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("poi.txt"), "nyc,new york\n").unwrap();
+        std::fs::write(dir.join("poi_fr.txt"), "cdg,aeroport charles de gaulle\n").unwrap();
+
+        let load =
+            |doc_type, dataset| load_synonyms(Some(dir.as_path()), doc_type, dataset).unwrap();
+        assert_eq!(
+            load("poi", Some("fr")),
+            vec!["cdg,aeroport charles de gaulle"]
+        );
+        assert_eq!(load("poi", Some("us")), vec!["nyc,new york"]);
+        assert_eq!(load("poi", None), vec!["nyc,new york"]);
+        assert_eq!(load("street", Some("fr")), default_synonyms());
+        assert_eq!(
+            load_synonyms(None, "poi", Some("fr")).unwrap(),
+            default_synonyms()
+        );
+    }
+}
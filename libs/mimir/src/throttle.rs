@@ -0,0 +1,195 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Adaptive concurrency of the bulk indexing.
+//!
+//! When its bulk queue is full, Elasticsearch rejects the bulk requests with
+//! `429 Too Many Requests`. Retrying them at once only makes it worse, so the number of bulk
+//! requests in flight is adapted AIMD-style: it is halved on a rejection (and the requests wait
+//! for the `Retry-After` delay of the response if given), and increased by one after as many
+//! accepted requests as the current limit, up to the configured maximum.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Delay before sending the next requests after a rejection without `Retry-After`
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+pub struct AdaptiveConcurrency {
+    max: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    limit: usize,
+    in_flight: usize,
+    // accepted requests since the last change of the limit
+    nb_accepted: usize,
+    // incremented at each decrease, the requests sent before a decrease don't decrease it again
+    generation: usize,
+    paused_until: Option<Instant>,
+}
+
+/// A request in flight, to give back with its outcome
+pub struct Permit<'a> {
+    concurrency: &'a AdaptiveConcurrency,
+    generation: usize,
+}
+
+impl AdaptiveConcurrency {
+    /// Start with `max` requests in flight
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        AdaptiveConcurrency {
+            max,
+            state: Mutex::new(State {
+                limit: max,
+                in_flight: 0,
+                nb_accepted: 0,
+                generation: 0,
+                paused_until: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// The current maximum number of requests in flight
+    pub fn limit(&self) -> usize {
+        self.lock().limit
+    }
+
+    /// Wait for a request to be allowed
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.lock();
+        loop {
+            if let Some(until) = state.paused_until {
+                let now = Instant::now();
+                if until > now {
+                    state = self
+                        .changed
+                        .wait_timeout(state, until - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                    continue;
+                }
+                state.paused_until = None;
+            }
+            if state.in_flight < state.limit {
+                state.in_flight += 1;
+                return Permit {
+                    concurrency: self,
+                    generation: state.generation,
+                };
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<'a> Permit<'a> {
+    /// The request has been accepted
+    pub fn accepted(self) {
+        let concurrency = self.concurrency;
+        let mut state = concurrency.lock();
+        state.nb_accepted += 1;
+        if state.nb_accepted >= state.limit && state.limit < concurrency.max {
+            state.limit += 1;
+            state.nb_accepted = 0;
+        }
+    }
+
+    /// The request has been rejected with `429 Too Many Requests`,
+    /// the new limit is returned
+    pub fn rejected(self, retry_after: Option<Duration>) -> usize {
+        let mut state = self.concurrency.lock();
+        if self.generation == state.generation {
+            state.limit = (state.limit / 2).max(1);
+            state.nb_accepted = 0;
+            state.generation += 1;
+        }
+        let until = Instant::now() + retry_after.unwrap_or(DEFAULT_BACKOFF);
+        if state
+            .paused_until
+            .map_or(true, |paused_until| paused_until < until)
+        {
+            state.paused_until = Some(until);
+        }
+        state.limit
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        self.concurrency.lock().in_flight -= 1;
+        self.concurrency.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrency_is_halved_and_increased_back() {
+        let concurrency = AdaptiveConcurrency::new(8);
+        let permits: Vec<_> = (0..8).map(|_| concurrency.acquire()).collect();
+        // only the first rejection of the requests in flight decreases the limit
+        for permit in permits {
+            permit.rejected(Some(Duration::from_millis(0)));
+        }
+        assert_eq!(concurrency.limit(), 4);
+
+        for _ in 0..4 {
+            concurrency.acquire().accepted();
+        }
+        assert_eq!(concurrency.limit(), 5);
+        for _ in 0..100 {
+            concurrency.acquire().accepted();
+        }
+        assert_eq!(concurrency.limit(), 8);
+    }
+
+    #[test]
+    fn requests_wait_for_the_retry_after_delay() {
+        let concurrency = AdaptiveConcurrency::new(2);
+        concurrency
+            .acquire()
+            .rejected(Some(Duration::from_millis(50)));
+        assert_eq!(concurrency.limit(), 1);
+        let start = Instant::now();
+        concurrency.acquire().accepted();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright © 2021, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Sanity checks of an index before it is published,
+//! to avoid swapping an alias to a broken index (eg with most of its labels missing).
+
+use crate::rubber::Rubber;
+use failure::{format_err, Error, ResultExt};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Number of addresses sampled to check that their admins are attached
+const ADDR_SAMPLE_SIZE: usize = 1000;
+
+/// Limits of a valid index
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationThresholds {
+    pub min_documents: u64,
+    /// Maximum ratio of documents without label
+    pub max_missing_label_ratio: f64,
+    /// Maximum ratio of documents without coord (or with the default `(0, 0)` coord)
+    pub max_missing_coord_ratio: f64,
+    /// Minimum ratio of the sampled addresses with an admin hierarchy
+    pub min_addr_with_admins_ratio: f64,
+}
+
+impl Default for ValidationThresholds {
+    fn default() -> Self {
+        ValidationThresholds {
+            min_documents: 1,
+            max_missing_label_ratio: 0.01,
+            max_missing_coord_ratio: 0.01,
+            min_addr_with_admins_ratio: 0.9,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub documents: u64,
+    pub missing_labels: u64,
+    pub missing_coords: u64,
+    pub addresses: u64,
+    pub sampled_addresses: u64,
+    /// sampled addresses with at least one admin
+    pub sampled_addresses_with_admins: u64,
+    /// documents whose id is shared with a previous document
+    pub duplicate_ids: u64,
+}
+
+fn ratio(nb: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.
+    } else {
+        nb as f64 / total as f64
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "documents: {}, missing labels: {} ({:.2}%), missing coords: {} ({:.2}%), \
+             addresses with admins: {}/{} sampled on {}, duplicate ids: {}",
+            self.documents,
+            self.missing_labels,
+            100. * ratio(self.missing_labels, self.documents),
+            self.missing_coords,
+            100. * ratio(self.missing_coords, self.documents),
+            self.sampled_addresses_with_admins,
+            self.sampled_addresses,
+            self.addresses,
+            self.duplicate_ids
+        )
+    }
+}
+
+impl ValidationReport {
+    pub fn check(&self, thresholds: &ValidationThresholds) -> Result<(), Error> {
+        let mut failures = Vec::new();
+        if self.documents < thresholds.min_documents {
+            failures.push(format!(
+                "{} documents (min {})",
+                self.documents, thresholds.min_documents
+            ));
+        }
+        for (kind, nb, max) in &[
+            (
+                "labels",
+                self.missing_labels,
+                thresholds.max_missing_label_ratio,
+            ),
+            (
+                "coords",
+                self.missing_coords,
+                thresholds.max_missing_coord_ratio,
+            ),
+        ] {
+            if ratio(*nb, self.documents) > *max {
+                failures.push(format!(
+                    "{} missing {} on {} documents (max ratio {})",
+                    nb, kind, self.documents, max
+                ));
+            }
+        }
+        // an index without address has nothing to check
+        if self.sampled_addresses > 0
+            && ratio(self.sampled_addresses_with_admins, self.sampled_addresses)
+                < thresholds.min_addr_with_admins_ratio
+        {
+            failures.push(format!(
+                "{} addresses with admins on {} sampled addresses (min ratio {})",
+                self.sampled_addresses_with_admins,
+                self.sampled_addresses,
+                thresholds.min_addr_with_admins_ratio
+            ));
+        }
+        if self.duplicate_ids > 0 {
+            failures.push(format!("{} duplicate ids", self.duplicate_ids));
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!("invalid index: {}", failures.join(", ")))
+        }
+    }
+}
+
+fn hits_total(result: &Value) -> u64 {
+    result
+        .pointer("/hits/total")
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+fn doc_count(result: &Value, aggregation: &str) -> u64 {
+    result
+        .pointer(&format!("/aggregations/{}/doc_count", aggregation))
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+fn has_admins(hit: &Value) -> bool {
+    hit.pointer("/_source/street/administrative_regions")
+        .and_then(Value::as_array)
+        .map_or(false, |admins| !admins.is_empty())
+}
+
+/// Run the checks on `index`.
+///
+/// The missing labels and coords are counted with aggregations, the admins are checked
+/// on a random sample of the addresses, and the duplicate ids are found by scrolling
+/// the index sorted by id.
+pub fn validate_index(rubber: &Rubber, index: &str) -> Result<ValidationReport, Error> {
+    let missing = |field: &str| json!({ "bool": { "must_not": { "exists": { "field": field } } } });
+    let counts = rubber
+        .raw_search(
+            &[index],
+            &json!({
+                "size": 0,
+                "aggs": {
+                    "missing_label": { "filter": missing("label") },
+                    "missing_coord": { "filter": { "bool": { "should": [
+                        missing("coord"),
+                        // the default coord of the documents without one
+                        { "geo_distance": { "distance": "1m", "coord": { "lat": 0, "lon": 0 } } },
+                    ] } } },
+                },
+            }),
+            None,
+        )
+        .with_context(|_| format!("impossible to count the documents of {}", index))?;
+
+    let addresses = rubber
+        .raw_search(
+            &[index],
+            &json!({
+                "size": ADDR_SAMPLE_SIZE,
+                "query": { "function_score": {
+                    "query": { "type": { "value": "addr" } },
+                    "random_score": {},
+                } },
+                "_source": ["street.administrative_regions.id"],
+            }),
+            None,
+        )
+        .with_context(|_| format!("impossible to sample the addresses of {}", index))?;
+    let sample = addresses
+        .pointer("/hits/hits")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let mut duplicate_ids = 0;
+    let mut previous_id = None;
+    for document in rubber.scroll_sorted_by_id(index)? {
+        let id = document?
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        if id.is_some() && id == previous_id {
+            duplicate_ids += 1;
+        }
+        previous_id = id;
+    }
+
+    Ok(ValidationReport {
+        documents: hits_total(&counts),
+        missing_labels: doc_count(&counts, "missing_label"),
+        missing_coords: doc_count(&counts, "missing_coord"),
+        addresses: hits_total(&addresses),
+        sampled_addresses: sample.len() as u64,
+        sampled_addresses_with_admins: sample.iter().filter(|hit| has_admins(hit)).count() as u64,
+        duplicate_ids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_report() -> ValidationReport {
+        ValidationReport {
+            documents: 1000,
+            missing_labels: 5,
+            missing_coords: 0,
+            addresses: 800,
+            sampled_addresses: 100,
+            sampled_addresses_with_admins: 95,
+            duplicate_ids: 0,
+        }
+    }
+
+    fn failure(report: ValidationReport) -> String {
+        report
+            .check(&ValidationThresholds::default())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn valid_report_passes() {
+        assert!(valid_report()
+            .check(&ValidationThresholds::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn each_check_fails() {
+        assert_eq!(
+            failure(ValidationReport {
+                documents: 0,
+                missing_labels: 0,
+                addresses: 0,
+                sampled_addresses: 0,
+                sampled_addresses_with_admins: 0,
+                ..valid_report()
+            }),
+            "invalid index: 0 documents (min 1)"
+        );
+        assert_eq!(
+            failure(ValidationReport {
+                missing_labels: 900,
+                ..valid_report()
+            }),
+            "invalid index: 900 missing labels on 1000 documents (max ratio 0.01)"
+        );
+        assert_eq!(
+            failure(ValidationReport {
+                missing_coords: 20,
+                ..valid_report()
+            }),
+            "invalid index: 20 missing coords on 1000 documents (max ratio 0.01)"
+        );
+        assert_eq!(
+            failure(ValidationReport {
+                sampled_addresses_with_admins: 10,
+                ..valid_report()
+            }),
+            "invalid index: 10 addresses with admins on 100 sampled addresses (min ratio 0.9)"
+        );
+        assert_eq!(
+            failure(ValidationReport {
+                duplicate_ids: 2,
+                ..valid_report()
+            }),
+            "invalid index: 2 duplicate ids"
+        );
+    }
+
+    #[test]
+    fn indexes_without_address_do_not_check_the_admins() {
+        let report = ValidationReport {
+            addresses: 0,
+            sampled_addresses: 0,
+            sampled_addresses_with_admins: 0,
+            ..valid_report()
+        };
+        assert!(report.check(&ValidationThresholds::default()).is_ok());
+    }
+}
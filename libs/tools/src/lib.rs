@@ -168,13 +168,21 @@ pub struct BragiHandler {
 
 impl BragiHandler {
     pub fn new(url: String) -> BragiHandler {
-        let ctx = bragi::Context::try_from(&bragi::Args {
+        Self::new_with_args(bragi::Args {
             connection_string: url,
             ..Default::default()
         })
-        .expect("failed to create bragi Context");
+    }
 
-        let prometheus = bragi::prometheus_middleware::PrometheusMetrics::new("bragi", "/metrics");
+    pub fn new_with_args(args: bragi::Args) -> BragiHandler {
+        let ctx = bragi::Context::try_from(&args).expect("failed to create bragi Context");
+        let prometheus = bragi::prometheus_middleware::PrometheusMetrics::new("bragi", "/metrics")
+            .with_slow_query_threshold(args.slow_query_threshold.map(Duration::from_millis));
+        let request_id =
+            bragi::request_id_middleware::RequestId::new(args.request_id_header.as_deref())
+                .expect("invalid request id header");
+        let compression =
+            bragi::compression_middleware::CompressionThreshold::new(args.compression_threshold());
         let srv = actix_http_test::TestServer::new(move || {
             actix_http::HttpService::new(
                 actix_web::App::new()
@@ -182,7 +190,10 @@ impl BragiHandler {
                     .wrap(actix_cors::Cors::new().allowed_methods(vec!["GET"]))
                     .wrap(prometheus.clone())
                     .wrap(actix_web::middleware::Logger::default())
-                    .configure(bragi::server::configure_server)
+                    .wrap(request_id.clone())
+                    .wrap(compression.clone())
+                    .wrap(actix_web::middleware::Compress::default())
+                    .configure(|cfg| bragi::server::configure_server(cfg, &ctx))
                     .default_service(
                         actix_web::web::resource("")
                             .route(actix_web::web::get().to(bragi::server::default_404)),
@@ -208,6 +219,31 @@ impl BragiHandler {
         (status, body)
     }
 
+    pub fn raw_get_with_headers(
+        &mut self,
+        query: &str,
+        headers: &[(&str, &str)],
+    ) -> (
+        actix_http::http::StatusCode,
+        actix_http::http::HeaderMap,
+        bytes::Bytes,
+    ) {
+        let query = url_encode(query);
+        let req = headers
+            .iter()
+            .fold(self.app.get(query), |req, (name, value)| {
+                req.header(*name, *value)
+            })
+            .timeout(Duration::from_secs(10));
+
+        let mut resp = self.app.block_on(req.send()).unwrap();
+
+        let status = resp.status();
+        let response_headers = resp.headers().clone();
+        let body = self.app.block_on(resp.body()).unwrap();
+        (status, response_headers, body)
+    }
+
     pub fn get_status(&mut self, q: &str) -> actix_http::http::StatusCode {
         let q = url_encode(q);
         let req = self.app.get(q);
@@ -237,7 +273,16 @@ impl BragiHandler {
     pub fn raw_post(
         &mut self,
         q: &str,
-        shape: &'static str,
+        shape: &str,
+    ) -> (actix_http::http::StatusCode, bytes::Bytes) {
+        self.raw_post_with_content_type(q, "application/json", shape)
+    }
+
+    pub fn raw_post_with_content_type(
+        &mut self,
+        q: &str,
+        content_type: &str,
+        body: &str,
     ) -> (actix_http::http::StatusCode, bytes::Bytes) {
         let q = url_encode(q);
         let mut r = self
@@ -245,8 +290,8 @@ impl BragiHandler {
             .block_on(
                 self.app
                     .post(q)
-                    .header(actix_web::http::header::CONTENT_TYPE, "application/json")
-                    .send_body(shape),
+                    .header(actix_web::http::header::CONTENT_TYPE, content_type)
+                    .send_body(body.to_owned()),
             )
             .unwrap_or_else(|e| panic!("impossible to query bragi: {}", e));
 
@@ -283,7 +328,9 @@ impl BragiHandler {
             .expect("features must be array")
             .iter()
             .map(|f| {
+                // the features can also be wrapped in an envelope (with `envelope=true`)
                 f.pointer("/properties/geocoding")
+                    .or_else(|| f.pointer("/source/properties/geocoding"))
                     .expect("no field in bragi response")
                     .as_object()
                     .unwrap()
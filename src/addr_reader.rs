@@ -1,5 +1,5 @@
 use crate::Error;
-use failure::ResultExt;
+use failure::{format_err, ResultExt};
 use flate2::read::GzDecoder;
 use mimir::rubber::{IndexSettings, IndexVisibility, Rubber};
 use mimir::Addr;
@@ -11,6 +11,62 @@ use std::fs::File;
 use std::io::Read;
 use std::marker::{Send, Sync};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The malformed records are skipped with a warning, unless the import is strict:
+/// the first one then stops the import, which fails before publishing the index.
+#[derive(Clone, Default)]
+struct MalformedRecords {
+    strict: bool,
+    first_error: Arc<Mutex<Option<Error>>>,
+}
+
+impl MalformedRecords {
+    fn new(strict: bool) -> Self {
+        MalformedRecords {
+            strict,
+            ..Default::default()
+        }
+    }
+
+    // the value of a well formed record, the error of a malformed one is kept if strict
+    fn check<T>(&self, record: Result<T, Error>) -> Option<T> {
+        match record {
+            Ok(value) => Some(value),
+            Err(e) if self.strict => {
+                let mut first_error = self.first_error.lock().unwrap_or_else(|e| e.into_inner());
+                first_error.get_or_insert(e);
+                None
+            }
+            Err(e) => {
+                warn!("{}, the record has been ignored", e);
+                None
+            }
+        }
+    }
+
+    // once a strict import has found a malformed record, the next records are not read
+    fn stopped(&self) -> bool {
+        self.strict
+            && self
+                .first_error
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_some()
+    }
+
+    fn into_result(self) -> Result<(), Error> {
+        match self
+            .first_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            Some(e) => Err(e.context("malformed record in strict mode").into()),
+            None => Ok(()),
+        }
+    }
+}
 
 fn import_addresses<T, F>(
     rubber: &mut Rubber,
@@ -19,6 +75,7 @@ fn import_addresses<T, F>(
     dataset: &str,
     addresses: impl IntoIterator<Item = T>,
     into_addr: F,
+    malformed: MalformedRecords,
 ) -> Result<(), Error>
 where
     F: Fn(T) -> Result<Addr, Error> + Send + Sync + 'static,
@@ -36,19 +93,15 @@ where
         .into_iter()
         .with_nb_threads(nb_threads)
         .par_map(into_addr)
-        .filter_map(|ra| match ra {
-            Ok(a) => {
+        .take_while(|_| !malformed.stopped())
+        .filter_map(|ra| {
+            malformed.check(ra.and_then(|a| {
                 if a.street.name.is_empty() {
-                    warn!("Address {} has no street name and has been ignored.", a.id);
-                    None
+                    Err(format_err!("Address {} has no street name", a.id))
                 } else {
-                    Some(a)
+                    Ok(a)
                 }
-            }
-            Err(err) => {
-                warn!("Address Error ignored: {}", err);
-                None
-            }
+            }))
         })
         .inspect(|addr| {
             let country_code = addr
@@ -67,6 +120,7 @@ where
     let nb = rubber
         .bulk_index(&addr_index, iter)
         .with_context(|err| format!("failed to bulk insert: {}", err))?;
+    malformed.into_result()?;
     info!("importing addresses: {} addresses added.", nb);
     rubber
         .publish_index(dataset, addr_index, IndexVisibility::Public)
@@ -83,9 +137,13 @@ where
     Ok(())
 }
 
+/// Imports the addresses of CSV streams.
+///
+/// With `strict`, the import fails on the first malformed record, else they are skipped.
 pub fn import_addresses_from_streams<T, F>(
     rubber: &mut Rubber,
     has_headers: bool,
+    strict: bool,
     nb_threads: usize,
     index_settings: IndexSettings,
     dataset: &str,
@@ -96,6 +154,7 @@ where
     F: Fn(T) -> Result<Addr, Error> + Send + Sync + 'static,
     T: DeserializeOwned + Send + 'static,
 {
+    let malformed = MalformedRecords::new(strict);
     let iter = streams
         .into_iter()
         .flat_map(|stream| {
@@ -104,17 +163,37 @@ where
                 .from_reader(stream)
                 .into_deserialize()
         })
-        .filter_map(|line| {
-            line.map_err(|e| warn!("Impossible to read line, error: {}", e))
-                .ok()
+        .take_while({
+            let malformed = malformed.clone();
+            move |_| !malformed.stopped()
+        })
+        .filter_map({
+            let malformed = malformed.clone();
+            move |line| {
+                malformed
+                    .check(line.map_err(|e| format_err!("Impossible to read line, error: {}", e)))
+            }
         });
 
-    import_addresses(rubber, nb_threads, index_settings, dataset, iter, into_addr)
+    import_addresses(
+        rubber,
+        nb_threads,
+        index_settings,
+        dataset,
+        iter,
+        into_addr,
+        malformed,
+    )
 }
 
+/// Imports the addresses of CSV files, gzipped if their extension is `.gz`.
+///
+/// With `strict`, the import fails on the first malformed record or unreadable file,
+/// else they are skipped.
 pub fn import_addresses_from_files<T, F>(
     rubber: &mut Rubber,
     has_headers: bool,
+    strict: bool,
     nb_threads: usize,
     index_settings: IndexSettings,
     dataset: &str,
@@ -125,6 +204,13 @@ where
     F: Fn(T) -> Result<Addr, Error> + Send + Sync + 'static,
     T: DeserializeOwned + Send + 'static,
 {
+    let files: Vec<PathBuf> = files.into_iter().collect();
+    if strict {
+        // the files are only opened once the previous ones are imported
+        for path in &files {
+            File::open(path).with_context(|_| format!("Impossible to read file {:?}", path))?;
+        }
+    }
     let streams = files.into_iter().filter_map(|path| {
         info!("importing {:?}...", &path);
 
@@ -150,6 +236,7 @@ where
     import_addresses_from_streams(
         rubber,
         has_headers,
+        strict,
         nb_threads,
         index_settings,
         dataset,
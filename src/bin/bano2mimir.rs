@@ -30,11 +30,13 @@
 
 use failure::ensure;
 use lazy_static::lazy_static;
+use mimir::house_number::HouseNumber;
 use mimir::objects::Admin;
-use mimir::rubber::{IndexSettings, Rubber};
+use mimir::rubber::IndexSettings;
 use mimirsbrunn::addr_reader::{import_addresses_from_files, import_addresses_from_streams};
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
-use mimirsbrunn::labels;
+use mimirsbrunn::labels::LabelFormatter;
+use mimirsbrunn::utils::{self, CheckpointArgs, DryRunArgs, IndexArgs, ReportArgs};
 use serde::{Deserialize, Serialize};
 use slog_scope::{info, warn};
 use std::collections::BTreeMap;
@@ -77,6 +79,7 @@ impl Bano {
         admins_from_insee: &AdminFromInsee,
         admins_geofinder: &AdminGeoFinder,
         use_old_index_format: bool,
+        label_formatter: &LabelFormatter,
     ) -> Result<mimir::Addr, mimirsbrunn::Error> {
         let street_id = format!("street:{}", self.fantoir()?.to_string());
         let mut admins = admins_geofinder.get(&geo::Coordinate {
@@ -103,38 +106,47 @@ impl Bano {
             .map(|a| a.deref())
             .chain(std::iter::once(&city));
 
-        let street_label = labels::format_street_label(
+        let street_label = label_formatter.format_street_label(
             &self.street,
             zones_for_label_formatting.clone(),
             &country_codes,
         );
-        let (addr_name, addr_label) = labels::format_addr_name_and_label(
+        let zip_codes: Vec<_> = self.zip.split(';').map(str::to_string).collect();
+        let (addr_name, addr_label) = label_formatter.format_addr_name_and_label(
             &self.nb,
             &self.street,
+            zip_codes.first().map(String::as_str),
             zones_for_label_formatting,
             &country_codes,
         );
+        let country = utils::find_country_name(admins.iter().map(|a| a.deref()));
 
         let weight = admins
             .iter()
             .find(|a| a.level == 8)
             .map_or(0., |a| a.weight);
 
-        let zip_codes: Vec<_> = self.zip.split(';').map(str::to_string).collect();
         let coord = mimir::Coord::new(self.lon, self.lat);
         let street = mimir::Street {
             id: street_id,
             name: self.street,
+            alt_names: vec![],
             label: street_label,
             administrative_regions: admins,
             weight,
             zip_codes: zip_codes.clone(),
             coord,
             approx_coord: None,
+            coord_line: None,
             distance: None,
+            along_street_distance: None,
+            score: None,
             country_codes: country_codes.clone(),
+            old_ids: vec![],
+            flags: Default::default(),
             context: None,
         };
+        let house_number = HouseNumber::parse(&self.nb);
         Ok(mimir::Addr {
             id: format!(
                 "addr:{};{}{}",
@@ -159,6 +171,8 @@ impl Bano {
             ),
             name: addr_name,
             label: addr_label,
+            house_number_numeric: house_number.numeric,
+            house_number_suffix: house_number.suffix,
             house_number: self.nb,
             street,
             coord,
@@ -166,7 +180,12 @@ impl Bano {
             weight,
             zip_codes,
             distance: None,
+            score: None,
             country_codes,
+            city: Some(self.city),
+            country,
+            old_ids: vec![],
+            flags: Default::default(),
             context: None,
         })
     }
@@ -194,7 +213,12 @@ struct Args {
     )]
     connection_string: String,
     /// Name of the dataset.
-    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    #[structopt(
+        short = "d",
+        long = "dataset",
+        default_value = "fr",
+        parse(from_str = mimir::canonical_case)
+    )]
     dataset: String,
     /// Number of threads to use
     #[structopt(
@@ -217,13 +241,36 @@ struct Args {
     /// therefore, different addresses with the same position will disappear.
     #[structopt(long = "use-old-index-format")]
     use_old_index_format: bool,
+    /// Fail the import on the first malformed record (or unreadable file), reporting it,
+    /// instead of skipping it with a warning.
+    #[structopt(long = "strict")]
+    strict: bool,
+    #[structopt(flatten)]
+    dry_run: DryRunArgs,
+    #[structopt(flatten)]
+    index: IndexArgs,
+    #[structopt(flatten)]
+    checkpoint: CheckpointArgs,
+    #[structopt(flatten)]
+    report: ReportArgs,
 }
 
 fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
+    let report = args.report.clone();
+    let dataset = Some(args.dataset.clone());
+    report.run("bano2mimir", dataset, || import(args))
+}
+
+fn import(args: Args) -> Result<(), mimirsbrunn::Error> {
     info!("importing bano into Mimir");
 
-    let mut rubber =
-        Rubber::new(&args.connection_string).with_nb_insert_threads(args.nb_insert_threads);
+    let label_formatter = args.index.label_formatter(&args.dataset)?;
+
+    let rubber = args.dry_run.make_rubber(&args.connection_string);
+    let rubber = args
+        .checkpoint
+        .configure(args.index.configure(rubber)?, args.input.as_deref())?;
+    let mut rubber = rubber.with_nb_insert_threads(args.nb_insert_threads);
 
     let index_settings = IndexSettings {
         nb_shards: args.nb_shards,
@@ -252,7 +299,14 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             .collect();
 
         let use_old_index_format = args.use_old_index_format;
-        move |b: Bano| b.into_addr(&admins_by_insee, &admins_geofinder, use_old_index_format)
+        move |b: Bano| {
+            b.into_addr(
+                &admins_by_insee,
+                &admins_geofinder,
+                use_old_index_format,
+                &label_formatter,
+            )
+        }
     };
 
     if let Some(input_path) = args.input {
@@ -262,6 +316,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             import_addresses_from_files(
                 &mut rubber,
                 false,
+                args.strict,
                 args.nb_threads,
                 index_settings,
                 &args.dataset,
@@ -272,6 +327,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             import_addresses_from_files(
                 &mut rubber,
                 false,
+                args.strict,
                 args.nb_threads,
                 index_settings,
                 &args.dataset,
@@ -284,6 +340,7 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
         import_addresses_from_streams(
             &mut rubber,
             false,
+            args.strict,
             args.nb_threads,
             index_settings,
             &args.dataset,
@@ -28,16 +28,19 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
-use cosmogony::{Zone, ZoneIndex};
+use cosmogony::{Zone, ZoneIndex, ZoneType};
 use failure::Error;
+use geo::bounding_rect::BoundingRect;
 use mimir::objects::Admin;
 use mimir::rubber::{IndexSettings, Rubber};
 use mimirsbrunn::osm_reader::admin;
 use mimirsbrunn::osm_reader::osm_utils;
-use mimirsbrunn::utils;
+use mimirsbrunn::utils::{self, CheckpointArgs, DryRunArgs, IndexArgs, ReportArgs};
 use slog_scope::{info, warn};
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::Path;
 use std::sync::Arc;
 use structopt::StructOpt;
 
@@ -52,17 +55,6 @@ trait IntoAdmin {
     ) -> Admin;
 }
 
-fn get_weight(tags: &osmpbfreader::Tags, center_tags: &osmpbfreader::Tags) -> f64 {
-    // to have an admin weight we use the osm 'population' tag to priorize
-    // the big zones over the small one.
-    // Note: this tags is not often filled , so only some zones
-    // will have a weight (but the main cities have it).
-    tags.get("population")
-        .and_then(|p| p.parse().ok())
-        .or_else(|| center_tags.get("population")?.parse().ok())
-        .unwrap_or(0.)
-}
-
 impl IntoAdmin for Zone {
     fn into_admin(
         self,
@@ -75,7 +67,10 @@ impl IntoAdmin for Zone {
         let insee = admin::read_insee(&self.tags).map(|s| s.to_owned());
         let zip_codes = admin::read_zip_codes(&self.tags);
         let label = self.label;
-        let weight = get_weight(&self.tags, &self.center_tags);
+        // to have an admin weight we use its population to priorize the big zones over the small
+        // ones. Only some zones have a population, so only them have a weight.
+        let population = admin::read_population(&self.tags, Some(&self.center_tags));
+        let weight = population.map_or(0., |p| p as f64);
         let center = self.center.map_or(mimir::Coord::default(), |c| {
             mimir::Coord::new(c.lng(), c.lat())
         });
@@ -92,6 +87,10 @@ impl IntoAdmin for Zone {
             .and_then(|id| zones_osm_id.get(&id))
             .map(|(id, insee)| format_id(id, insee.as_ref()));
         let codes = osm_utils::get_osm_codes_from_tags(&self.tags);
+        // the bbox is needed by the clients to zoom on the zone
+        let bbox = self
+            .bbox
+            .or_else(|| self.boundary.as_ref().and_then(|b| b.bounding_rect()));
         let mut admin = Admin {
             id: zones_osm_id
                 .get(&self.id)
@@ -103,11 +102,14 @@ impl IntoAdmin for Zone {
             name: self.name,
             zip_codes,
             weight: utils::normalize_weight(weight, max_weight),
-            bbox: self.bbox,
+            population,
+            area: self.boundary.as_ref().map(admin::compute_area),
+            bbox,
             boundary: self.boundary,
             coord: center,
             approx_coord: Some(center.into()),
             zone_type: self.zone_type,
+            zone_type_weight: None,
             parent_id: parent_osm_id,
             // Note: Since we do not really attach an admin to its hierarchy, for the moment an admin only have it's own coutry code,
             // not the country code of it's country from the hierarchy
@@ -121,6 +123,10 @@ impl IntoAdmin for Zone {
                 .filter(|(k, _)| langs.contains(&k))
                 .collect(),
             distance: None,
+            score: None,
+            alt_names: osm_utils::get_alt_names_from_tags(&self.tags),
+            old_ids: vec![],
+            flags: Default::default(),
             context: None,
             administrative_regions: Vec::new(),
         };
@@ -147,13 +153,27 @@ impl IntoAdmin for Zone {
     }
 }
 
+// parse a `<zone_type>=<weight>` argument, like `city=2`
+fn parse_zone_type_weight(arg: &str) -> Result<(ZoneType, f64), String> {
+    let mut parts = arg.splitn(2, '=');
+    let (zone_type, weight) = match (parts.next(), parts.next()) {
+        (Some(zone_type), Some(weight)) => (zone_type, weight),
+        _ => return Err(format!("{} is not <zone_type>=<weight>", arg)),
+    };
+    let zone_type = serde_json::from_value(serde_json::Value::String(zone_type.to_string()))
+        .map_err(|_| format!("unknown zone type {}", zone_type))?;
+    let weight = weight
+        .parse()
+        .map_err(|e| format!("invalid weight {}: {}", weight, e))?;
+    Ok((zone_type, weight))
+}
+
 fn send_to_es(
     admins: impl Iterator<Item = Admin>,
-    cnx_string: &str,
+    mut rubber: Rubber,
     dataset: &str,
     index_settings: IndexSettings,
 ) -> Result<(), Error> {
-    let mut rubber = Rubber::new(cnx_string);
     rubber.initialize_templates()?;
     let nb_admins = rubber.public_index(dataset, &index_settings, admins)?;
     info!("{} admins added.", nb_admins);
@@ -165,6 +185,12 @@ fn read_zones(input: &str) -> Result<impl Iterator<Item = Zone>, Error> {
         .filter_map(|r| r.map_err(|e| warn!("impossible to read zone: {}", e)).ok()))
 }
 
+fn run(args: Args) -> Result<(), Error> {
+    let report = args.report.clone();
+    let dataset = Some(args.dataset.clone());
+    report.run("cosmogony2mimir", dataset, || index_cosmogony(args))
+}
+
 fn index_cosmogony(args: Args) -> Result<(), Error> {
     info!("building maps");
     use cosmogony::ZoneType::City;
@@ -195,16 +221,40 @@ fn index_cosmogony(args: Args) -> Result<(), Error> {
         })
         .collect::<HashMap<_, _>>();
 
+    let rubber = args
+        .index
+        .configure(args.dry_run.make_rubber(&args.connection_string))?;
+
+    // the admins with boundaries are streamed, their old ids are found without them
+    let old_ids = if args.index.alias_old_ids.unwrap_or(false) {
+        info!("aliasing the old admin ids");
+        rubber.find_old_ids(
+            &args.dataset,
+            admins_without_boundaries.values().map(Deref::deref),
+        )?
+    } else {
+        HashMap::new()
+    };
+
     info!("importing cosmogony into Mimir");
 
+    let zone_type_weights = &args.zone_type_weights;
     let admins = read_zones(&args.input)?.map(|z| {
-        z.into_admin(
+        let mut admin = z.into_admin(
             &cosmogony_id_to_osm_id,
             &args.langs,
             args.french_id_retrocompatibility,
             max_weight,
             Some(&admins_without_boundaries),
-        )
+        );
+        admin.zone_type_weight = zone_type_weights
+            .iter()
+            .find(|(zone_type, _)| admin.zone_type == Some(*zone_type))
+            .map(|(_, weight)| *weight);
+        if let Some(old_ids) = old_ids.get(&admin.id) {
+            admin.old_ids = old_ids.clone();
+        }
+        admin
     });
 
     let index_settings = IndexSettings {
@@ -213,7 +263,8 @@ fn index_cosmogony(args: Args) -> Result<(), Error> {
     };
     send_to_es(
         admins,
-        &args.connection_string,
+        args.checkpoint
+            .configure(rubber, Some(Path::new(&args.input)))?,
         &args.dataset,
         index_settings,
     )?;
@@ -234,7 +285,12 @@ struct Args {
     )]
     connection_string: String,
     /// Name of the dataset.
-    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    #[structopt(
+        short = "d",
+        long = "dataset",
+        default_value = "fr",
+        parse(from_str = mimir::canonical_case)
+    )]
     dataset: String,
     /// Number of shards for the es index
     #[structopt(short = "s", long = "nb-shards", default_value = "1")]
@@ -250,8 +306,20 @@ struct Args {
     /// instead of 'admin:osm:{osm_id}'
     #[structopt(long = "french-id-retrocompatibility")]
     french_id_retrocompatibility: bool,
+    /// Weight of a zone type in the ranking, as `<zone_type>=<weight>` (e.g. `city=2`).
+    /// Can be repeated, the zone types without weight have a weight of 1.
+    #[structopt(long = "zone-type-weight", parse(try_from_str = parse_zone_type_weight))]
+    zone_type_weights: Vec<(ZoneType, f64)>,
+    #[structopt(flatten)]
+    dry_run: DryRunArgs,
+    #[structopt(flatten)]
+    index: IndexArgs,
+    #[structopt(flatten)]
+    checkpoint: CheckpointArgs,
+    #[structopt(flatten)]
+    report: ReportArgs,
 }
 
 fn main() {
-    mimirsbrunn::utils::launch_run(index_cosmogony);
+    mimirsbrunn::utils::launch_run(run);
 }
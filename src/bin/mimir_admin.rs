@@ -0,0 +1,238 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use mimir::rubber::{IndexSettings, Rubber, ScrollOptions};
+use mimir::validation::validate_index;
+use mimirsbrunn::export::export_index;
+use mimirsbrunn::index_diff::{diff_indexes, DiffThresholds};
+use mimirsbrunn::mimirload::load_indexes;
+use mimirsbrunn::replay::{replay_queries, search_bragi};
+use mimirsbrunn::utils::{IndexArgs, ValidationArgs};
+use slog_scope::info;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// Elasticsearch parameters.
+    #[structopt(
+        short = "c",
+        long = "connection-string",
+        default_value = "http://localhost:9200/"
+    )]
+    connection_string: String,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Compare two generations of an index (eg before swapping the alias to the new one).
+    /// Fails if the changes exceed the given thresholds.
+    #[structopt(name = "diff")]
+    Diff {
+        /// The old index.
+        index_a: String,
+        /// The new index.
+        index_b: String,
+        /// Directory where the added, removed and modified documents are written as NDJSON.
+        #[structopt(long = "output-dir", parse(from_os_str))]
+        output_dir: Option<PathBuf>,
+        /// Maximum number of added documents.
+        #[structopt(long = "max-added")]
+        max_added: Option<usize>,
+        /// Maximum number of removed documents.
+        #[structopt(long = "max-removed")]
+        max_removed: Option<usize>,
+        /// Maximum number of modified documents.
+        #[structopt(long = "max-modified")]
+        max_modified: Option<usize>,
+    },
+    /// Check that an index is sane before swapping an alias to it: enough documents,
+    /// few documents without label or coord, addresses attached to their admins
+    /// and no duplicate ids. Fails if one of the checks fails.
+    #[structopt(name = "validate")]
+    Validate {
+        index: String,
+        #[structopt(flatten)]
+        validation: ValidationArgs,
+    },
+    /// Load the NDJSON files written by the importers with --dry-run,
+    /// and publish their indexes.
+    #[structopt(name = "mimirload")]
+    Mimirload {
+        /// Directory of the NDJSON files.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Number of shards for the es indexes
+        #[structopt(short = "s", long = "nb-shards", default_value = "1")]
+        nb_shards: usize,
+        /// Number of replicas for the es indexes
+        #[structopt(short = "r", long = "nb-replicas", default_value = "1")]
+        nb_replicas: usize,
+        /// Number of threads to use to insert into Elasticsearch.
+        #[structopt(short = "T", long = "nb-insert-threads", default_value = "1")]
+        nb_insert_threads: usize,
+        #[structopt(flatten)]
+        index_args: IndexArgs,
+    },
+    /// Export all the documents of an index as NDJSON (eg for the analytics or the backups).
+    #[structopt(name = "export")]
+    Export {
+        index: String,
+        /// File where the documents are written, the standard output if not given.
+        #[structopt(long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Number of documents fetched by each request to Elasticsearch.
+        #[structopt(long = "batch-size", default_value = "1000")]
+        batch_size: usize,
+        /// How long Elasticsearch keeps the scroll alive between two batches.
+        #[structopt(long = "scroll-duration", default_value = "1m")]
+        scroll_duration: String,
+        /// Sort the documents by id (slower than the order of the index).
+        #[structopt(long = "sorted")]
+        sorted: bool,
+        /// Also export the boundaries of the admins, which can be huge.
+        #[structopt(long = "with-boundaries")]
+        with_boundaries: bool,
+    },
+    /// Replay the autocomplete queries recorded by bragi against another bragi
+    /// (eg with new settings), and compare the places found.
+    /// Fails if the first place of too many queries has changed.
+    #[structopt(name = "replay")]
+    Replay {
+        /// NDJSON file of the queries recorded by bragi.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Url of the bragi replaying the queries.
+        #[structopt(long = "bragi-url", default_value = "http://localhost:4000")]
+        bragi_url: String,
+        /// File where the queries whose places have changed are written as NDJSON.
+        #[structopt(long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Maximum number of queries whose first place has changed.
+        #[structopt(long = "max-top1-changes")]
+        max_top1_changes: Option<usize>,
+    },
+}
+
+fn run(args: Args) -> Result<(), failure::Error> {
+    let rubber = Rubber::new(&args.connection_string);
+    match args.command {
+        Command::Diff {
+            index_a,
+            index_b,
+            output_dir,
+            max_added,
+            max_removed,
+            max_modified,
+        } => {
+            info!("comparing {} with {}", index_a, index_b);
+            let summary = diff_indexes(&rubber, &index_a, &index_b, output_dir.as_deref())?;
+            println!("{}", summary);
+            summary.check(&DiffThresholds {
+                max_added,
+                max_removed,
+                max_modified,
+            })
+        }
+        Command::Validate { index, validation } => {
+            info!("validating {}", index);
+            let report = validate_index(&rubber, &index)?;
+            println!("{}", report);
+            report.check(&validation.thresholds())
+        }
+        Command::Mimirload {
+            input,
+            nb_shards,
+            nb_replicas,
+            nb_insert_threads,
+            index_args,
+        } => {
+            let mut rubber = index_args
+                .configure(rubber)?
+                .with_nb_insert_threads(nb_insert_threads);
+            let index_settings = IndexSettings {
+                nb_shards,
+                nb_replicas,
+            };
+            let nb_documents = load_indexes(&mut rubber, &input, &index_settings)?;
+            info!("{} documents loaded from {}", nb_documents, input.display());
+            Ok(())
+        }
+        Command::Export {
+            index,
+            output,
+            batch_size,
+            scroll_duration,
+            sorted,
+            with_boundaries,
+        } => {
+            let options = ScrollOptions {
+                page_size: batch_size,
+                keep_alive: scroll_duration,
+                sorted_by_id: sorted,
+                with_boundaries,
+            };
+            let nb_documents = match output {
+                Some(output) => {
+                    let file = File::create(&output)?;
+                    export_index(&rubber, &index, &options, BufWriter::new(file))?
+                }
+                None => {
+                    let stdout = io::stdout();
+                    export_index(&rubber, &index, &options, stdout.lock())?
+                }
+            };
+            info!("{} documents of {} exported", nb_documents, index);
+            Ok(())
+        }
+        Command::Replay {
+            input,
+            bragi_url,
+            output,
+            max_top1_changes,
+        } => {
+            info!("replaying {} with {}", input.display(), bragi_url);
+            let client = reqwest::blocking::Client::new();
+            let summary = replay_queries(&input, output.as_deref(), |query| {
+                search_bragi(&client, &bragi_url, query)
+            })?;
+            println!("{}", summary);
+            summary.check(max_top1_changes)
+        }
+    }
+}
+
+fn main() {
+    mimirsbrunn::utils::launch_run(run);
+}
@@ -31,6 +31,7 @@
 use failure::ResultExt;
 use mimir::rubber::IndexSettings;
 use mimirsbrunn::stops::*;
+use mimirsbrunn::utils::{DryRunArgs, IndexArgs, ReportArgs};
 use slog_scope::{info, warn};
 use std::cmp::Ordering;
 use std::collections::{hash_map::DefaultHasher, HashMap};
@@ -38,7 +39,7 @@ use std::hash::BuildHasherDefault;
 use std::path::PathBuf;
 use structopt::StructOpt;
 use transit_model::objects as navitia;
-use typed_index_collection::Idx;
+use typed_index_collection::{Idx, IdxSet};
 
 #[derive(Debug, StructOpt)]
 struct Args {
@@ -46,7 +47,12 @@ struct Args {
     #[structopt(short = "i", long = "input", parse(from_os_str), default_value = ".")]
     input: PathBuf,
     /// Name of the dataset.
-    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    #[structopt(
+        short = "d",
+        long = "dataset",
+        default_value = "fr",
+        parse(from_str = mimir::canonical_case)
+    )]
     dataset: String,
     /// Elasticsearch parameters.
     #[structopt(
@@ -64,6 +70,12 @@ struct Args {
     /// Number of replicas for the es index
     #[structopt(short = "r", long = "nb-replicas", default_value = "1")]
     nb_replicas: usize,
+    #[structopt(flatten)]
+    dry_run: DryRunArgs,
+    #[structopt(flatten)]
+    index: IndexArgs,
+    #[structopt(flatten)]
+    report: ReportArgs,
 }
 
 fn get_lines(idx: Idx<navitia::StopArea>, navitia: &transit_model::Model) -> Vec<mimir::Line> {
@@ -92,6 +104,38 @@ fn get_lines(idx: Idx<navitia::StopArea>, navitia: &transit_model::Model) -> Vec
     lines
 }
 
+// the validity period of a stop area is the period during which
+// at least one of its vehicle journeys is running
+fn get_validity_period(
+    idx: Idx<navitia::StopArea>,
+    navitia: &transit_model::Model,
+) -> Option<mimir::ValidityPeriod> {
+    let vehicle_journeys: IdxSet<navitia::VehicleJourney> = navitia.get_corresponding_from_idx(idx);
+    let dates = vehicle_journeys
+        .into_iter()
+        .filter_map(|vj_idx| {
+            navitia
+                .calendars
+                .get(&navitia.vehicle_journeys[vj_idx].service_id)
+        })
+        .flat_map(|calendar| {
+            calendar
+                .dates
+                .iter()
+                .next()
+                .into_iter()
+                .chain(calendar.dates.iter().next_back())
+        });
+    let (start_date, end_date) = dates.fold(None, |period, date| match period {
+        None => Some((date, date)),
+        Some((start, end)) => Some((start.min(date), end.max(date))),
+    })?;
+    Some(mimir::ValidityPeriod {
+        start_date: start_date.format("%Y-%m-%d").to_string(),
+        end_date: end_date.format("%Y-%m-%d").to_string(),
+    })
+}
+
 fn to_mimir(
     idx: Idx<navitia::StopArea>,
     stop_area: &navitia::StopArea,
@@ -183,6 +227,7 @@ fn to_mimir(
             })
             .collect(),
         feed_publishers,
+        validity_period: get_validity_period(idx, navitia),
         ..Default::default()
     }
 }
@@ -192,6 +237,12 @@ fn main() {
 }
 
 fn run(args: Args) -> Result<(), transit_model::Error> {
+    let report = args.report.clone();
+    let dataset = Some(args.dataset.clone());
+    report.run("ntfs2mimir", dataset, || import(args))
+}
+
+fn import(args: Args) -> Result<(), transit_model::Error> {
     info!("Launching ntfs2mimir...");
 
     if args.city_level.is_some() {
@@ -225,9 +276,12 @@ fn run(args: Args) -> Result<(), transit_model::Error> {
 
     import_stops(
         stops,
-        &args.connection_string,
+        args.index
+            .configure(args.dry_run.make_rubber(&args.connection_string))?,
         &args.dataset,
         index_settings,
+        &args.index.label_formatter(&args.dataset)?,
+        args.index.alias_old_ids.unwrap_or(false),
     )
     .with_context(|err| {
         format!(
@@ -247,6 +301,9 @@ fn test_bad_connection_string() {
         city_level: None,
         nb_replicas: 1,
         nb_shards: 1,
+        dry_run: Default::default(),
+        index: Default::default(),
+        report: Default::default(),
     };
     let causes = run(args)
         .unwrap_err()
@@ -271,6 +328,9 @@ fn test_bad_file() {
         city_level: None,
         nb_replicas: 1,
         nb_shards: 1,
+        dry_run: Default::default(),
+        index: Default::default(),
+        report: Default::default(),
     };
     let causes = run(args)
         .unwrap_err()
@@ -29,10 +29,12 @@
 // www.navitia.io
 
 use lazy_static::lazy_static;
-use mimir::rubber::{IndexSettings, Rubber};
+use mimir::house_number::HouseNumber;
+use mimir::rubber::IndexSettings;
 use mimirsbrunn::addr_reader::{import_addresses_from_files, import_addresses_from_streams};
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
-use mimirsbrunn::{labels, utils};
+use mimirsbrunn::labels::LabelFormatter;
+use mimirsbrunn::utils::{self, CheckpointArgs, DryRunArgs, IndexArgs, ReportArgs};
 use serde::{Deserialize, Serialize};
 use slog_scope::{info, warn};
 use std::io::stdin;
@@ -65,42 +67,65 @@ impl OpenAddress {
         admins_geofinder: &AdminGeoFinder,
         use_old_index_format: bool,
         id_precision: usize,
+        label_formatter: &LabelFormatter,
     ) -> Result<mimir::Addr, mimirsbrunn::Error> {
-        let street_id = format!("street:{}", self.id); // TODO check if thats ok
         let admins = admins_geofinder.get(&geo::Coordinate {
             x: self.lon,
             y: self.lat,
         });
+        // all the addresses of a street share the same street id,
+        // so that bragi can limit the number of results on the same street
+        let street_id = format!(
+            "street:{}:{}",
+            admins
+                .iter()
+                .find(|a| a.is_city())
+                .map_or(self.postcode.as_str(), |a| a.id.as_str()),
+            self.street
+                .to_lowercase()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join("_")
+        );
         let country_codes = utils::find_country_codes(admins.iter().map(|a| a.deref()));
 
         let weight = admins.iter().find(|a| a.is_city()).map_or(0., |a| a.weight);
         // Note: for openaddress, we don't trust the admin hierarchy much (compared to bano)
         // so we use for the label the admins that we find in the DB
-        let street_label = labels::format_street_label(
+        let street_label = label_formatter.format_street_label(
             &self.street,
             admins.iter().map(|a| a.deref()),
             &country_codes,
         );
-        let (addr_name, addr_label) = labels::format_addr_name_and_label(
+        let zip_codes: Vec<_> = self.postcode.split(';').map(str::to_string).collect();
+        let (addr_name, addr_label) = label_formatter.format_addr_name_and_label(
             &self.number,
             &self.street,
+            zip_codes.first().map(String::as_str),
             admins.iter().map(|a| a.deref()),
             &country_codes,
         );
+        let city = utils::find_city_name(admins.iter().map(|a| a.deref()));
+        let country = utils::find_country_name(admins.iter().map(|a| a.deref()));
 
-        let zip_codes: Vec<_> = self.postcode.split(';').map(str::to_string).collect();
         let coord = mimir::Coord::new(self.lon, self.lat);
         let street = mimir::Street {
             id: street_id,
             name: self.street,
+            alt_names: vec![],
             label: street_label,
             administrative_regions: admins,
             weight,
             zip_codes: zip_codes.clone(),
             coord,
             approx_coord: None,
+            coord_line: None,
             distance: None,
+            along_street_distance: None,
+            score: None,
             country_codes: country_codes.clone(),
+            old_ids: vec![],
+            flags: Default::default(),
             context: None,
         };
 
@@ -137,10 +162,13 @@ impl OpenAddress {
             }
         };
 
+        let house_number = HouseNumber::parse(&self.number);
         Ok(mimir::Addr {
             id,
             name: addr_name,
             label: addr_label,
+            house_number_numeric: house_number.numeric,
+            house_number_suffix: house_number.suffix,
             house_number: self.number,
             street,
             coord,
@@ -148,7 +176,12 @@ impl OpenAddress {
             weight,
             zip_codes,
             distance: None,
+            score: None,
             country_codes,
+            city,
+            country,
+            old_ids: vec![],
+            flags: Default::default(),
             context: None,
         })
     }
@@ -172,7 +205,12 @@ struct Args {
     )]
     connection_string: String,
     /// Name of the dataset.
-    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    #[structopt(
+        short = "d",
+        long = "dataset",
+        default_value = "fr",
+        parse(from_str = mimir::canonical_case)
+    )]
     dataset: String,
     /// Deprecated option.
     #[structopt(short = "C", long = "city-level")]
@@ -198,17 +236,40 @@ struct Args {
     /// therefore, different addresses with the same position will disappear.
     #[structopt(long = "use-old-index-format")]
     use_old_index_format: bool,
+    /// Fail the import on the first malformed record (or unreadable file), reporting it,
+    /// instead of skipping it with a warning.
+    #[structopt(long = "strict")]
+    strict: bool,
+    #[structopt(flatten)]
+    dry_run: DryRunArgs,
+    #[structopt(flatten)]
+    index: IndexArgs,
+    #[structopt(flatten)]
+    checkpoint: CheckpointArgs,
+    #[structopt(flatten)]
+    report: ReportArgs,
 }
 
 fn run(args: Args) -> Result<(), failure::Error> {
+    let report = args.report.clone();
+    let dataset = Some(args.dataset.clone());
+    report.run("openaddresses2mimir", dataset, || import(args))
+}
+
+fn import(args: Args) -> Result<(), failure::Error> {
     info!("importing open addresses into Mimir");
 
     if args.city_level.is_some() {
         warn!("city-level option is deprecated, it now has no effect.");
     }
 
-    let mut rubber =
-        Rubber::new(&args.connection_string).with_nb_insert_threads(args.nb_insert_threads);
+    let label_formatter = args.index.label_formatter(&args.dataset)?;
+
+    let rubber = args.dry_run.make_rubber(&args.connection_string);
+    let rubber = args
+        .checkpoint
+        .configure(args.index.configure(rubber)?, args.input.as_deref())?;
+    let mut rubber = rubber.with_nb_insert_threads(args.nb_insert_threads);
 
     let index_settings = IndexSettings {
         nb_shards: args.nb_shards,
@@ -229,7 +290,14 @@ fn run(args: Args) -> Result<(), failure::Error> {
         let use_old_index_format = args.use_old_index_format;
         let id_precision = args.id_precision;
 
-        move |a: OpenAddress| a.into_addr(&admins_geofinder, use_old_index_format, id_precision)
+        move |a: OpenAddress| {
+            a.into_addr(
+                &admins_geofinder,
+                use_old_index_format,
+                id_precision,
+                &label_formatter,
+            )
+        }
     };
 
     if let Some(input_path) = args.input {
@@ -254,6 +322,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
             import_addresses_from_files(
                 &mut rubber,
                 true,
+                args.strict,
                 args.nb_threads,
                 index_settings,
                 &args.dataset,
@@ -264,6 +333,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
             import_addresses_from_files(
                 &mut rubber,
                 true,
+                args.strict,
                 args.nb_threads,
                 index_settings,
                 &args.dataset,
@@ -276,6 +346,7 @@ fn run(args: Args) -> Result<(), failure::Error> {
         import_addresses_from_streams(
             &mut rubber,
             true,
+            args.strict,
             args.nb_threads,
             index_settings,
             &args.dataset,
@@ -29,7 +29,7 @@
 // www.navitia.io
 
 use failure::ResultExt;
-use mimir::rubber::{IndexSettings, Rubber};
+use mimir::rubber::IndexSettings;
 use mimirsbrunn::admin_geofinder::AdminGeoFinder;
 use mimirsbrunn::osm_reader::admin::read_administrative_regions;
 use mimirsbrunn::osm_reader::make_osm_reader;
@@ -39,17 +39,28 @@ use mimirsbrunn::settings::osm2mimir::{Args, Settings};
 use slog_scope::{debug, info};
 
 fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
+    let report = args.report.clone();
+    let dataset = args.dataset.clone();
+    report.run("osm2mimir", dataset, || import(args))
+}
+
+fn import(args: Args) -> Result<(), mimirsbrunn::Error> {
     let input = args.input.clone(); // we save the input, because args will be consumed by settings.
+    let dry_run = args.dry_run.clone();
+    let index_args = args.index.clone();
+    let checkpoint_args = args.checkpoint.clone();
     validate_args(&args)?;
     let settings = Settings::new(args)?;
 
     let mut osm_reader = make_osm_reader(&input)?;
     debug!("creation of indexes");
-    let mut rubber = Rubber::new(&settings.elasticsearch.connection_string)
-        .with_nb_insert_threads(settings.elasticsearch.insert_thread_count);
+    let rubber = dry_run.make_rubber(&settings.elasticsearch.connection_string);
+    let rubber = checkpoint_args.configure(index_args.configure(rubber)?, Some(&input))?;
+    let mut rubber = rubber.with_nb_insert_threads(settings.elasticsearch.insert_thread_count);
     rubber.initialize_templates()?;
 
     let settings = &settings;
+    let label_formatter = index_args.label_formatter(&settings.dataset)?;
     let admins = if settings
         .admin
         .as_ref()
@@ -79,11 +90,21 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
         .unwrap_or_else(|| false)
     {
         info!("Extracting streets from osm");
-        let mut streets = streets(&mut osm_reader, &admins_geofinder, &settings)?;
+        let mut streets = streets(
+            &mut osm_reader,
+            &admins_geofinder,
+            &label_formatter,
+            &settings,
+        )?;
 
         info!("computing street weight");
         compute_street_weight(&mut streets);
 
+        if settings.alias_old_ids {
+            info!("aliasing the old street ids");
+            rubber.alias_old_ids(&settings.dataset, &mut streets)?;
+        }
+
         let street_index_settings = IndexSettings {
             nb_shards: settings.elasticsearch.streets_shards,
             nb_replicas: settings.elasticsearch.streets_replicas,
@@ -113,12 +134,13 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             nb_shards: settings.elasticsearch.admins_shards,
             nb_replicas: settings.elasticsearch.admins_replicas,
         };
+        let mut admins: Vec<_> = admins_geofinder.admins().collect();
+        if settings.alias_old_ids {
+            info!("aliasing the old admin ids");
+            rubber.alias_old_ids(&settings.dataset, &mut admins)?;
+        }
         let nb_admins = rubber
-            .public_index(
-                &settings.dataset,
-                &admin_index_settings,
-                admins_geofinder.admins(),
-            )
+            .public_index(&settings.dataset, &admin_index_settings, admins.into_iter())
             .with_context(|err| {
                 format!(
                     "Error occurred when requesting admin number in {}: {}",
@@ -141,7 +163,12 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
             .unwrap_or_else(PoiConfig::default);
 
         info!("Extracting pois from osm");
-        let mut pois = pois(&mut osm_reader, &config, &admins_geofinder);
+        let mut pois = pois(
+            &mut osm_reader,
+            &config,
+            &admins_geofinder,
+            &label_formatter,
+        );
 
         info!("computing poi weight");
         compute_poi_weight(&mut pois);
@@ -149,6 +176,11 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
         info!("Adding address in poi");
         add_address(&mut pois, &mut rubber);
 
+        if settings.alias_old_ids {
+            info!("aliasing the old poi ids");
+            rubber.alias_old_ids(&settings.dataset, &mut pois)?;
+        }
+
         let poi_index_settings = IndexSettings {
             nb_shards: settings.elasticsearch.pois_shards,
             nb_replicas: settings.elasticsearch.pois_replicas,
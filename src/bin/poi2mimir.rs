@@ -34,13 +34,17 @@ use failure::format_err;
 use lazy_static::lazy_static;
 use mimir::objects::{Coord, I18nProperties, Poi, PoiType, Property};
 use mimir::rubber::{IndexSettings, IndexVisibility, Rubber, TypedIndex};
-use mimirsbrunn::{admin_geofinder::AdminGeoFinder, labels, utils};
+use mimirsbrunn::utils::{self, DryRunArgs, IndexArgs, ReportArgs};
+use mimirsbrunn::{admin_geofinder::AdminGeoFinder, labels::LabelFormatter};
 use navitia_poi_model::{Model as NavitiaModel, Poi as NavitiaPoi, PoiType as NavitiaPoiType};
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
+// property of the pois giving the time of their last update
+const UPDATED_AT_PROPERTY: &str = "updated_at";
+
 lazy_static! {
     static ref DEFAULT_NB_THREADS: String = num_cpus::get().to_string();
 }
@@ -52,6 +56,7 @@ fn into_mimir_poi(
     poi_types: &HashMap<String, NavitiaPoiType>,
     rubber: &mut Rubber,
     admins_geofinder: &AdminGeoFinder,
+    label_formatter: &LabelFormatter,
 ) -> Result<Poi, mimirsbrunn::Error> {
     let poi_type = poi_types
         .get(&poi.poi_type_id)
@@ -84,8 +89,13 @@ fn into_mimir_poi(
 
     let country_codes = utils::find_country_codes(admins.iter().map(|a| a.deref()));
 
-    let label =
-        labels::format_poi_label(&poi.name, admins.iter().map(|a| a.deref()), &country_codes);
+    let label = label_formatter.format_poi_label(
+        &poi.name,
+        admins.iter().map(|a| a.deref()),
+        &country_codes,
+    );
+
+    let updated_at = updated_at(&poi.properties);
 
     let poi = Poi {
         id: mimir::objects::normalize_id("poi", &poi.id),
@@ -103,17 +113,39 @@ fn into_mimir_poi(
         names: I18nProperties::default(),
         labels: I18nProperties::default(),
         distance: None,
+        score: None,
+        alt_names: vec![],
+        old_ids: vec![],
+        updated_at,
+        flags: Default::default(),
         context: None,
     };
 
     Ok(poi)
 }
 
+// The time of the last update of a poi, given by its `updated_at` property (in RFC 3339)
+fn updated_at(properties: &[navitia_poi_model::Property]) -> Option<i64> {
+    let property = properties.iter().find(|p| p.key == UPDATED_AT_PROPERTY)?;
+    chrono::DateTime::parse_from_rfc3339(&property.value)
+        .map(|date| date.timestamp_millis())
+        .map_err(|err| {
+            info!(
+                "Invalid {} property '{}': {}",
+                UPDATED_AT_PROPERTY, property.value, err
+            )
+        })
+        .ok()
+}
+
 fn import_pois(
     rubber: &mut Rubber,
     index: &TypedIndex<Poi>,
     admins_geofinder: AdminGeoFinder,
+    label_formatter: &LabelFormatter,
     file: &Path,
+    // the dataset whose previous ids are kept in the pois, if the old ids are aliased
+    alias_dataset: Option<&str>,
 ) -> Result<(), mimirsbrunn::Error>
 where
 {
@@ -123,16 +155,21 @@ where
     let poi_types = model.poi_types;
 
     // Note: We're ignoring those POIs that fail to be enriched.
-    let pois: Vec<_> = model
+    let mut pois: Vec<_> = model
         .pois
         .into_iter()
         .filter_map(|(id, poi)| {
-            into_mimir_poi(poi, &poi_types, rubber, &admins_geofinder)
+            into_mimir_poi(poi, &poi_types, rubber, &admins_geofinder, label_formatter)
                 .map_err(|err| info!("Could not extract information for POI '{}': {}", id, err))
                 .ok()
         })
         .collect(); // TODO Can we get rid of collect, and chain with the following rubber...?
 
+    if let Some(dataset) = alias_dataset {
+        info!("aliasing the old poi ids");
+        rubber.alias_old_ids(dataset, &mut pois)?;
+    }
+
     let count = rubber
         .bulk_index(&index, pois.into_iter())
         .map_err(|err| format_err!("Failed bulk insertion {}", err))?;
@@ -145,16 +182,16 @@ where
 /// This function initializes the ES context: It creates an index for this dataset,
 /// and then import the POIs in it.
 fn index_poi(
-    cnx_string: &str,
+    mut rubber: Rubber,
     dataset: &str,
     file: &Path,
     visibility: IndexVisibility,
     nb_shards: usize,
     nb_replicas: usize,
+    index_args: &IndexArgs,
 ) -> Result<(), mimirsbrunn::Error>
 where
 {
-    let mut rubber = Rubber::new(cnx_string);
     rubber.initialize_templates()?;
 
     let settings = IndexSettings {
@@ -170,7 +207,17 @@ where
     })?;
     let admins_geofinder = admins.into_iter().collect();
 
-    import_pois(&mut rubber, &index, admins_geofinder, file)?;
+    utils::warn_if_no_address(&rubber, "pois");
+    let label_formatter = index_args.label_formatter(dataset)?;
+    let alias_dataset = Some(dataset).filter(|_| index_args.alias_old_ids.unwrap_or(false));
+    import_pois(
+        &mut rubber,
+        &index,
+        admins_geofinder,
+        &label_formatter,
+        file,
+        alias_dataset,
+    )?;
 
     rubber
         .publish_index(dataset, index, visibility)
@@ -193,7 +240,12 @@ struct Args {
 
     /// Name of the dataset.
     /// A dataset is a label, that can be used for filtering the data.
-    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    #[structopt(
+        short = "d",
+        long = "dataset",
+        default_value = "fr",
+        parse(from_str = mimir::canonical_case)
+    )]
     dataset: String,
 
     /// Indicate if the POI dataset is private
@@ -215,9 +267,22 @@ struct Args {
     /// Number of replicas for the es index
     #[structopt(short = "r", long = "nb-replicas", default_value = "1")]
     nb_replicas: usize,
+
+    #[structopt(flatten)]
+    dry_run: DryRunArgs,
+    #[structopt(flatten)]
+    index: IndexArgs,
+    #[structopt(flatten)]
+    report: ReportArgs,
 }
 
 fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
+    let report = args.report.clone();
+    let dataset = Some(args.dataset.clone());
+    report.run("poi2mimir", dataset, || import(args))
+}
+
+fn import(args: Args) -> Result<(), mimirsbrunn::Error> {
     let visibility = if args.private {
         IndexVisibility::Private
     } else {
@@ -225,12 +290,14 @@ fn run(args: Args) -> Result<(), mimirsbrunn::Error> {
     };
 
     index_poi(
-        &args.connection_string,
+        args.index
+            .configure(args.dry_run.make_rubber(&args.connection_string))?,
         &args.dataset,
         &args.input,
         visibility,
         args.nb_shards,
         args.nb_replicas,
+        &args.index,
     )
 }
 fn main() {
@@ -31,6 +31,7 @@
 use failure::ResultExt;
 use mimir::rubber::IndexSettings;
 use mimirsbrunn::stops::*;
+use mimirsbrunn::utils::{DryRunArgs, IndexArgs, ReportArgs};
 use serde::Deserialize;
 use slog_scope::{info, warn};
 use std::collections::HashMap;
@@ -49,7 +50,12 @@ struct Args {
     #[structopt(short = "i", long = "input", parse(from_os_str))]
     input: PathBuf,
     /// Name of the dataset.
-    #[structopt(short = "d", long = "dataset", default_value = "fr")]
+    #[structopt(
+        short = "d",
+        long = "dataset",
+        default_value = "fr",
+        parse(from_str = mimir::canonical_case)
+    )]
     dataset: String,
     /// Elasticsearch parameters.
     #[structopt(
@@ -67,6 +73,12 @@ struct Args {
     /// Number of replicas for the es index
     #[structopt(short = "r", long = "nb-replicas", default_value = "1")]
     nb_replicas: usize,
+    #[structopt(flatten)]
+    dry_run: DryRunArgs,
+    #[structopt(flatten)]
+    index: IndexArgs,
+    #[structopt(flatten)]
+    report: ReportArgs,
 }
 
 #[derive(Deserialize, Debug)]
@@ -143,6 +155,12 @@ impl GtfsStop {
 }
 
 fn run(args: Args) -> Result<(), failure::Error> {
+    let report = args.report.clone();
+    let dataset = Some(args.dataset.clone());
+    report.run("stops2mimir", dataset, || import(args))
+}
+
+fn import(args: Args) -> Result<(), failure::Error> {
     info!("Launching stops2mimir...");
     if args.city_level.is_some() {
         warn!("city-level option is deprecated, it now has no effect.");
@@ -167,9 +185,12 @@ fn run(args: Args) -> Result<(), failure::Error> {
 
     import_stops(
         stops,
-        &args.connection_string,
+        args.index
+            .configure(args.dry_run.make_rubber(&args.connection_string))?,
         &args.dataset,
         index_settings,
+        &args.index.label_formatter(&args.dataset)?,
+        args.index.alias_old_ids.unwrap_or(false),
     )
     .context("Error while importing stops")?;
     Ok(())
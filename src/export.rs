@@ -0,0 +1,61 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Export of all the documents of an index as NDJSON, for the analytics or the backups.
+
+use failure::{Error, ResultExt};
+use mimir::rubber::{Rubber, ScrollOptions};
+use slog_scope::info;
+use std::io::Write;
+
+// the progress is logged every time this number of documents has been exported
+const PROGRESS_STEP: usize = 100_000;
+
+/// Writes the documents of `index` to `output`, one by line, scrolling the index with `options`.
+///
+/// Returns the number of exported documents.
+pub fn export_index<W: Write>(
+    rubber: &Rubber,
+    index: &str,
+    options: &ScrollOptions,
+    mut output: W,
+) -> Result<usize, Error> {
+    let mut nb_documents = 0;
+    for document in rubber.scroll(index, options)? {
+        let document = document.with_context(|_| format!("impossible to export {}", index))?;
+        writeln!(output, "{}", document)?;
+        nb_documents += 1;
+        if nb_documents % PROGRESS_STEP == 0 {
+            info!("{} documents of {} exported", nb_documents, index);
+        }
+    }
+    output.flush()?;
+    Ok(nb_documents)
+}
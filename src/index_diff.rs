@@ -0,0 +1,301 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Comparison of two generations of an index,
+//! to check what an import has changed before publishing it.
+
+use failure::{format_err, Error, ResultExt};
+use mimir::rubber::Rubber;
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The fields compared between the two versions of a document
+const COMPARED_FIELDS: [&str; 3] = ["name", "label", "coord"];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    /// documents found with a new id (through their `old_ids`)
+    pub renamed: usize,
+    /// documents whose compared fields have changed (renamed or not)
+    pub modified: usize,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "added: {}, removed: {}, renamed: {}, modified: {}, unchanged: {}",
+            self.added, self.removed, self.renamed, self.modified, self.unchanged
+        )
+    }
+}
+
+/// Maximum number of changes accepted for each kind of change
+#[derive(Debug, Default, Clone)]
+pub struct DiffThresholds {
+    pub max_added: Option<usize>,
+    pub max_removed: Option<usize>,
+    pub max_modified: Option<usize>,
+}
+
+impl DiffSummary {
+    pub fn check(&self, thresholds: &DiffThresholds) -> Result<(), Error> {
+        let exceeded: Vec<_> = [
+            ("added", self.added, thresholds.max_added),
+            ("removed", self.removed, thresholds.max_removed),
+            ("modified", self.modified, thresholds.max_modified),
+        ]
+        .iter()
+        .filter_map(|(kind, nb, max)| match max {
+            Some(max) if nb > max => Some(format!("{} {} documents (max {})", nb, kind, max)),
+            _ => None,
+        })
+        .collect();
+        if exceeded.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!("too many changes: {}", exceeded.join(", ")))
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// NDJSON files where the changed documents are written
+struct DiffFiles {
+    added: BufWriter<File>,
+    removed: BufWriter<File>,
+    modified: BufWriter<File>,
+}
+
+impl DiffFiles {
+    fn new(output_dir: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(output_dir)
+            .with_context(|_| format!("impossible to create {}", output_dir.display()))?;
+        let create = |name: &str| -> Result<BufWriter<File>, Error> {
+            let path = output_dir.join(name);
+            let file = File::create(&path)
+                .with_context(|_| format!("impossible to create {}", path.display()))?;
+            Ok(BufWriter::new(file))
+        };
+        Ok(DiffFiles {
+            added: create("added.ndjson")?,
+            removed: create("removed.ndjson")?,
+            modified: create("modified.ndjson")?,
+        })
+    }
+
+    fn write(&mut self, change: Change, document: &Value) -> Result<(), Error> {
+        let file = match change {
+            Change::Added => &mut self.added,
+            Change::Removed => &mut self.removed,
+            Change::Modified => &mut self.modified,
+        };
+        writeln!(file, "{}", document)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.added.flush()?;
+        self.removed.flush()?;
+        self.modified.flush()?;
+        Ok(())
+    }
+}
+
+fn document_id(document: &Value) -> &str {
+    document.get("id").and_then(Value::as_str).unwrap_or("")
+}
+
+// only the id, the old ids and the compared fields are written for the added
+// and removed documents
+fn compact(document: &Value) -> Value {
+    let fields = std::iter::once("id")
+        .chain(std::iter::once("old_ids"))
+        .chain(COMPARED_FIELDS.iter().copied())
+        .filter_map(|field| {
+            document
+                .get(field)
+                .map(|value| (field.to_string(), value.clone()))
+        })
+        .collect();
+    Value::Object(fields)
+}
+
+fn changed_fields(old: &Value, new: &Value) -> Map<String, Value> {
+    COMPARED_FIELDS
+        .iter()
+        .filter(|field| old.get(**field) != new.get(**field))
+        .map(|field| {
+            (
+                field.to_string(),
+                json!({ "old": old.get(*field), "new": new.get(*field) }),
+            )
+        })
+        .collect()
+}
+
+fn compare(
+    old: &Value,
+    new: &Value,
+    summary: &mut DiffSummary,
+    files: &mut Option<DiffFiles>,
+) -> Result<(), Error> {
+    let changes = changed_fields(old, new);
+    if changes.is_empty() {
+        summary.unchanged += 1;
+        return Ok(());
+    }
+    summary.modified += 1;
+    if let Some(files) = files {
+        let mut line = json!({ "id": document_id(new), "changes": changes });
+        if document_id(old) != document_id(new) {
+            line["old_id"] = json!(document_id(old));
+        }
+        files.write(Change::Modified, &line)?;
+    }
+    Ok(())
+}
+
+fn old_ids(document: &Value) -> Vec<&str> {
+    document
+        .get("old_ids")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+// the document of `index_b` having the id of `old`, removed from `index_a`, in its old ids
+fn find_renamed(rubber: &Rubber, index_b: &str, old: &Value) -> Result<Option<Value>, Error> {
+    let renamed = rubber.find_documents(index_b, "old_ids", &[document_id(old)])?;
+    Ok(renamed.into_iter().next())
+}
+
+// is `new`, added to `index_b`, a document removed from `index_a` with a new id
+// (it is then compared when the removed document is read)
+fn is_renamed(rubber: &Rubber, index_a: &str, index_b: &str, new: &Value) -> Result<bool, Error> {
+    let old_ids = old_ids(new);
+    if old_ids.is_empty() {
+        return Ok(false);
+    }
+    let previous = rubber.find_documents(index_a, "id", &old_ids)?;
+    let previous_ids: Vec<_> = previous.iter().map(document_id).collect();
+    if previous_ids.is_empty() {
+        return Ok(false);
+    }
+    let kept = rubber.find_documents(index_b, "id", &previous_ids)?;
+    Ok(kept.len() < previous_ids.len())
+}
+
+/// Compare the documents of `index_a` (the old generation) with the ones of `index_b` (the new one).
+///
+/// Both indexes are read sorted by id and merged, so they are never loaded in memory.
+/// The documents of `index_b` whose `old_ids` contain the id of a removed document are renamed documents,
+/// they are searched in the indexes for each added or removed document.
+/// If `output_dir` is given, the added, removed and modified documents are written
+/// in NDJSON files of this directory.
+pub fn diff_indexes(
+    rubber: &Rubber,
+    index_a: &str,
+    index_b: &str,
+    output_dir: Option<&Path>,
+) -> Result<DiffSummary, Error> {
+    let mut files = output_dir.map(DiffFiles::new).transpose()?;
+    let mut summary = DiffSummary::default();
+
+    let mut documents_a = rubber.scroll_sorted_by_id(index_a)?;
+    let mut documents_b = rubber.scroll_sorted_by_id(index_b)?;
+    let mut next_a = documents_a.next().transpose()?;
+    let mut next_b = documents_b.next().transpose()?;
+    loop {
+        let ordering = match (&next_a, &next_b) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a), Some(b)) => document_id(a).cmp(document_id(b)),
+        };
+        match ordering {
+            Ordering::Less => {
+                if let Some(a) = next_a.take() {
+                    match find_renamed(rubber, index_b, &a)? {
+                        Some(b) => {
+                            summary.renamed += 1;
+                            compare(&a, &b, &mut summary, &mut files)?;
+                        }
+                        None => {
+                            summary.removed += 1;
+                            if let Some(files) = &mut files {
+                                files.write(Change::Removed, &compact(&a))?;
+                            }
+                        }
+                    }
+                }
+                next_a = documents_a.next().transpose()?;
+            }
+            Ordering::Greater => {
+                if let Some(b) = next_b.take() {
+                    if !is_renamed(rubber, index_a, index_b, &b)? {
+                        summary.added += 1;
+                        if let Some(files) = &mut files {
+                            files.write(Change::Added, &compact(&b))?;
+                        }
+                    }
+                }
+                next_b = documents_b.next().transpose()?;
+            }
+            Ordering::Equal => {
+                if let (Some(a), Some(b)) = (next_a.take(), next_b.take()) {
+                    compare(&a, &b, &mut summary, &mut files)?;
+                }
+                next_a = documents_a.next().transpose()?;
+                next_b = documents_b.next().transpose()?;
+            }
+        }
+    }
+
+    if let Some(files) = &mut files {
+        files.flush()?;
+    }
+    Ok(summary)
+}
@@ -1,47 +1,289 @@
-/// for the moment we keep the old way of formating the labels, but this could change
-/// the current format is '{nice name} ({city})'
-/// the {nice name} being for addresses the housenumber and the street (correctly ordered)
-/// and for the rest of the objects, only their names
+/// Labels are built from per-country templates (see config/labels.toml)
+/// the default format is '{nice name} ({city})'
+/// the {nice name} being for addresses the housenumber and the street (correctly ordered
+/// by address_formatter, unless a template is configured) and for the rest of the objects,
+/// only their names
+use crate::Error;
+use failure::ResultExt;
+use serde::Deserialize;
 use slog_scope::warn;
+use std::collections::BTreeMap;
+use std::path::Path;
 
-fn format_label<'a>(
-    nice_name: String,
-    mut admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
-    _country_codes: &[String], // Note: for the moment the country code is not used, but this could change
-) -> String {
-    let city = admins.find(|adm| adm.is_city());
-    let city_name = city.map(|a| a.name.to_string());
+const DEFAULT_ADDRESS_NAME: &str = "{street} {housenumber}";
+const DEFAULT_LABEL: &str = "{name} ({city})";
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_FORMATTER: LabelFormatter =
+        LabelFormatter::new(include_str!("../config/labels.toml"))
+            .expect("invalid default label configuration");
+}
+
+/// Templates used to format the labels of the places of a country.
+/// A missing template falls back on the default one.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LabelFormat {
+    pub address_name: Option<String>,
+    pub address_label: Option<String>,
+    pub label: Option<String>,
+}
+
+/// Format the names and labels of the places depending on their country
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LabelFormatter {
+    #[serde(default)]
+    default: LabelFormat,
+    /// formats by lowercase ISO 3166-1 alpha-2 country code
+    #[serde(default)]
+    countries: BTreeMap<String, LabelFormat>,
+    /// formats by dataset, used before the ones of the countries for the dataset imported
+    #[serde(default)]
+    datasets: BTreeMap<String, LabelFormat>,
+    /// the dataset imported
+    #[serde(skip)]
+    dataset: Option<String>,
+}
 
-    match city_name {
-        Some(n) => format!("{} ({})", nice_name, n),
-        None => nice_name,
+/// Components of a place that can be used in a label template
+#[derive(Default)]
+struct Components<'a> {
+    housenumber: &'a str,
+    street: &'a str,
+    name: &'a str,
+    postcode: &'a str,
+    city: &'a str,
+    country: &'a str,
+}
+
+impl<'a> Components<'a> {
+    fn with_admins(
+        mut self,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        lang: Option<&str>,
+    ) -> Self {
+        let local_name = |adm: &'a mimir::Admin| {
+            lang.and_then(|l| adm.names.get(l))
+                .unwrap_or_else(|| adm.name.as_str())
+        };
+        self.city = admins
+            .clone()
+            .find(|adm| adm.is_city())
+            .map_or("", local_name);
+        self.country = admins
+            .clone()
+            .find(|adm| matches!(adm.zone_type, Some(cosmogony::ZoneType::Country)))
+            .map_or("", local_name);
+        self
+    }
+
+    fn render(&self, template: &str) -> String {
+        let formatted = template
+            .replace("{housenumber}", self.housenumber)
+            .replace("{street}", self.street)
+            .replace("{name}", self.name)
+            .replace("{postcode}", self.postcode)
+            .replace("{city}", self.city)
+            .replace("{country}", self.country);
+
+        // we remove the separators left by the missing components
+        let mut formatted = formatted
+            .replace("()", "")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace(" ,", ",");
+        while formatted.contains(",,") {
+            formatted = formatted.replace(",,", ",");
+        }
+        formatted
+            .trim_matches(|c: char| c == ',' || c.is_whitespace())
+            .to_string()
     }
 }
 
-// format a label for a given lang
-// Note: 2 distinct localisation information are needed for the label formatting
-// and it might be worth it to clarify this a bit:
-// * `country codes` [Where the place is]: (which is not yet used in this function, but could be)
-//      Used on how to format the label in order to find it when you're in the place's country
-// * `lang` [Who searches for the place]: lang of the user
-//      Used to use the names in the user's language
-fn format_i18n_label<'a>(
-    nice_name: &str,
-    mut admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
-    _country_codes: &[String], // Note: for the moment the country code is not used, but this could change
-    lang: &str,
-) -> String {
-    admins.find(|adm| adm.is_city()).map_or_else(
-        || nice_name.to_string(),
-        |adm| {
-            let local_admin_name = &adm.names.get(lang).unwrap_or(&adm.name);
-            format!("{} ({})", nice_name, local_admin_name)
-        },
-    )
+impl LabelFormatter {
+    /// build a formatter from the content of a toml configuration
+    pub fn new(config: &str) -> Result<Self, Error> {
+        Ok(toml::from_str::<Self>(config).context("invalid label configuration")?)
+    }
+
+    /// build a formatter from a toml configuration file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .with_context(|_| format!("impossible to read {}", path.display()))?;
+        Self::new(&config)
+    }
+
+    /// the formatter built from the default configuration (config/labels.toml)
+    pub fn default_formatter() -> &'static Self {
+        &DEFAULT_FORMATTER
+    }
+
+    /// use the formats of `dataset` (if it has some) before the ones of the countries
+    pub fn for_dataset(mut self, dataset: &str) -> Self {
+        self.dataset = Some(dataset.to_string());
+        self
+    }
+
+    fn template<'a>(
+        &'a self,
+        country_codes: &[String],
+        field: fn(&LabelFormat) -> Option<&String>,
+        fallback: &'a str,
+    ) -> &'a str {
+        self.configured_template(country_codes, field)
+            .unwrap_or(fallback)
+    }
+
+    // the template of the dataset, else of the country, else the default one
+    fn configured_template<'a>(
+        &'a self,
+        country_codes: &[String],
+        field: fn(&LabelFormat) -> Option<&String>,
+    ) -> Option<&'a str> {
+        self.dataset
+            .as_ref()
+            .and_then(|dataset| self.datasets.get(dataset))
+            .and_then(field)
+            .or_else(|| {
+                // we arbitrarily take the first country code
+                country_codes
+                    .iter()
+                    .next()
+                    .and_then(|code| self.countries.get(&code.to_lowercase()))
+                    .and_then(field)
+            })
+            .or_else(|| field(&self.default))
+            .map(String::as_str)
+    }
+
+    fn format_label<'a>(
+        &self,
+        name: &str,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        country_codes: &[String],
+        lang: Option<&str>,
+    ) -> String {
+        let template = self.template(country_codes, |f| f.label.as_ref(), DEFAULT_LABEL);
+        Components {
+            name,
+            ..Default::default()
+        }
+        .with_admins(admins, lang)
+        .render(template)
+    }
+
+    /// format a label for a Street
+    pub fn format_street_label<'a>(
+        &self,
+        name: &str,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        country_codes: &[String],
+    ) -> String {
+        self.format_label(name, admins, country_codes, None)
+    }
+
+    /// format a label for a Poi
+    pub fn format_poi_label<'a>(
+        &self,
+        name: &str,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        country_codes: &[String],
+    ) -> String {
+        self.format_label(name, admins, country_codes, None)
+    }
+
+    /// format a label for a Stop
+    pub fn format_stop_label<'a>(
+        &self,
+        name: &str,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        country_codes: &[String],
+    ) -> String {
+        self.format_label(name, admins, country_codes, None)
+    }
+
+    /// format a name and a label for an Address
+    ///
+    /// Without a configured `address_name` template, the name is formatted by
+    /// address_formatter, which knows the order of the housenumber and the street
+    /// in most countries.
+    pub fn format_addr_name_and_label<'a>(
+        &self,
+        house_number: &str,
+        street_name: &str,
+        postcode: Option<&str>,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        country_codes: &[String],
+    ) -> (String, String) {
+        let components = Components {
+            housenumber: house_number,
+            street: street_name,
+            postcode: postcode.unwrap_or(""),
+            ..Default::default()
+        }
+        .with_admins(admins.clone(), None);
+
+        let name = match self.configured_template(country_codes, |f| f.address_name.as_ref()) {
+            Some(template) => components.render(template),
+            None => get_short_addr_label(house_number, street_name, admins, country_codes)
+                .unwrap_or_else(|| components.render(DEFAULT_ADDRESS_NAME)),
+        };
+        let label_template =
+            self.template(country_codes, |f| f.address_label.as_ref(), DEFAULT_LABEL);
+        let label = Components {
+            name: &name,
+            ..components
+        }
+        .render(label_template);
+
+        (name, label)
+    }
+
+    /// create some international label for a poi
+    /// One label is created for each lang in the `langs` parameter
+    pub fn format_international_poi_label<'a>(
+        &self,
+        poi_names: &mimir::I18nProperties,
+        default_poi_name: &str,
+        default_poi_label: &str,
+        admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+        country_codes: &[String],
+        langs: &[String],
+    ) -> mimir::I18nProperties {
+        // Note: 2 distinct localisation information are needed for the label formatting:
+        // * `country codes` [Where the place is]:
+        //      Used on how to format the label in order to find it when you're in the place's country
+        // * `lang` [Who searches for the place]: lang of the user
+        //      Used to use the names in the user's language
+        let labels = langs
+            .iter()
+            .filter_map(|ref lang| {
+                let local_poi_name = poi_names.get(lang).unwrap_or(default_poi_name);
+                let i18n_poi_label = self.format_label(
+                    local_poi_name,
+                    admins.clone(),
+                    country_codes,
+                    Some(lang.as_str()),
+                );
+
+                if i18n_poi_label == default_poi_label {
+                    None
+                } else {
+                    Some(mimir::Property {
+                        key: (*lang).to_string(),
+                        value: i18n_poi_label,
+                    })
+                }
+            })
+            .collect();
+        mimir::I18nProperties(labels)
+    }
 }
 
-// Note: even if most of the format methods are the same for the moment,
-// I feel it's better to split them to make them easier to update
+// Note: the following functions use the default formatter, importers
+// needing a custom configuration should use a `LabelFormatter`
 
 /// format a label for a Street
 pub fn format_street_label<'a>(
@@ -49,7 +291,7 @@ pub fn format_street_label<'a>(
     admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
     country_codes: &[String],
 ) -> String {
-    format_label(name.to_owned(), admins, country_codes)
+    DEFAULT_FORMATTER.format_street_label(name, admins, country_codes)
 }
 
 /// format a label for a Poi
@@ -58,7 +300,7 @@ pub fn format_poi_label<'a>(
     admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
     country_codes: &[String],
 ) -> String {
-    format_label(name.to_owned(), admins, country_codes)
+    DEFAULT_FORMATTER.format_poi_label(name, admins, country_codes)
 }
 
 /// format a label for a Stop
@@ -67,23 +309,23 @@ pub fn format_stop_label<'a>(
     admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
     country_codes: &[String],
 ) -> String {
-    format_label(name.to_owned(), admins, country_codes)
+    DEFAULT_FORMATTER.format_stop_label(name, admins, country_codes)
 }
 
 /// format a name and a label for an Address
 pub fn format_addr_name_and_label<'a>(
     house_number: &str,
     street_name: &str,
+    postcode: Option<&str>,
     admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
     country_codes: &[String],
 ) -> (String, String) {
-    let place = FormatPlaceHolder::from_addr(house_number.to_owned(), street_name.to_owned());
-    let nice_name = get_short_addr_label(place, admins.clone(), country_codes)
-        .unwrap_or_else(|| default_name(house_number, street_name));
-
-    (
-        nice_name.clone(),
-        format_label(nice_name, admins, country_codes),
+    DEFAULT_FORMATTER.format_addr_name_and_label(
+        house_number,
+        street_name,
+        postcode,
+        admins,
+        country_codes,
     )
 }
 
@@ -97,37 +339,24 @@ pub fn format_international_poi_label<'a>(
     country_codes: &[String],
     langs: &[String],
 ) -> mimir::I18nProperties {
-    let labels = langs
-        .iter()
-        .filter_map(|ref lang| {
-            let local_poi_name = poi_names.get(lang).unwrap_or(default_poi_name);
-            let i18n_poi_label =
-                format_i18n_label(local_poi_name, admins.clone(), country_codes, lang);
-
-            if i18n_poi_label == default_poi_label {
-                None
-            } else {
-                Some(mimir::Property {
-                    key: (*lang).to_string(),
-                    value: i18n_poi_label,
-                })
-            }
-        })
-        .collect();
-    mimir::I18nProperties(labels)
-}
-
-fn default_name(house_number: &str, street: &str) -> String {
-    //default formating is "{street} {hn}" as it's the most common format (but not correct for france)
-    format!("{street} {hn}", street = street, hn = house_number)
+    DEFAULT_FORMATTER.format_international_poi_label(
+        poi_names,
+        default_poi_name,
+        default_poi_label,
+        admins,
+        country_codes,
+        langs,
+    )
 }
 
 fn get_short_addr_label<'a>(
-    place: FormatPlaceHolder,
-    admins: impl Iterator<Item = &'a mimir::Admin> + Clone,
+    house_number: &str,
+    street_name: &str,
+    admins: impl Iterator<Item = &'a mimir::Admin>,
     country_codes: &[String],
 ) -> Option<String> {
     let country_code = country_codes.iter().next().map(|c| c.to_string()); // we arbitrarily take the first country code
+    let place = FormatPlaceHolder::from_addr(house_number.to_owned(), street_name.to_owned());
     address_formatter::FORMATTER
         .short_addr_format_with_config(
             place.into_place(admins),
@@ -262,11 +491,54 @@ mod test {
         ]
     }
 
+    fn get_de_admins() -> Vec<mimir::Admin> {
+        vec![
+            mimir::Admin {
+                id: "admin:berlin".to_string(),
+                level: 4,
+                name: "Berlin".to_string(),
+                label: "Berlin, Deutschland".to_string(),
+                zone_type: Some(ZoneType::City),
+                ..Default::default()
+            },
+            mimir::Admin {
+                id: "admin:deutschland".to_string(),
+                level: 2,
+                name: "Deutschland".to_string(),
+                label: "Deutschland".to_string(),
+                zone_type: Some(ZoneType::Country),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn get_gb_admins() -> Vec<mimir::Admin> {
+        vec![
+            mimir::Admin {
+                id: "admin:london".to_string(),
+                level: 8,
+                name: "London".to_string(),
+                label: "London, England, United Kingdom".to_string(),
+                zone_type: Some(ZoneType::City),
+                ..Default::default()
+            },
+            mimir::Admin {
+                id: "admin:united_kingdom".to_string(),
+                level: 2,
+                name: "United Kingdom".to_string(),
+                label: "United Kingdom".to_string(),
+                zone_type: Some(ZoneType::Country),
+                ..Default::default()
+            },
+        ]
+    }
+
     #[test]
     fn nl_addr() {
         let (name, label) = format_addr_name_and_label(
             "573",
             "Herengracht",
+            Some("1016 BM"),
             get_nl_admins().iter(),
             &["nl".to_owned()],
         );
@@ -289,6 +561,7 @@ mod test {
         let (name, label) = format_addr_name_and_label(
             "20",
             "rue hector malot",
+            Some("75012"),
             get_fr_admins().iter(),
             &["fr".to_owned()],
         );
@@ -310,6 +583,140 @@ mod test {
         assert_eq!(label, "Le Rossli (Paris)");
     }
 
+    #[test]
+    fn de_addr() {
+        let (name, label) = format_addr_name_and_label(
+            "77",
+            "Unter den Linden",
+            Some("10117"),
+            get_de_admins().iter(),
+            &["de".to_owned()],
+        );
+        assert_eq!(name, "Unter den Linden 77");
+        assert_eq!(label, "Unter den Linden 77, 10117 Berlin");
+    }
+    #[test]
+    fn de_addr_without_postcode() {
+        let (_, label) = format_addr_name_and_label(
+            "77",
+            "Unter den Linden",
+            None,
+            get_de_admins().iter(),
+            &["de".to_owned()],
+        );
+        assert_eq!(label, "Unter den Linden 77, Berlin");
+    }
+    #[test]
+    fn de_street() {
+        let label = format_street_label(
+            "Unter den Linden",
+            get_de_admins().iter(),
+            &["de".to_owned()],
+        );
+        assert_eq!(label, "Unter den Linden, Berlin");
+    }
+
+    #[test]
+    fn gb_addr() {
+        let (name, label) = format_addr_name_and_label(
+            "10",
+            "Downing Street",
+            Some("SW1A 2AA"),
+            get_gb_admins().iter(),
+            &["gb".to_owned()],
+        );
+        assert_eq!(name, "10 Downing Street");
+        assert_eq!(label, "10 Downing Street, London SW1A 2AA");
+    }
+    #[test]
+    fn gb_addr_without_city() {
+        let (_, label) = format_addr_name_and_label(
+            "10",
+            "Downing Street",
+            Some("SW1A 2AA"),
+            std::iter::empty(),
+            &["GB".to_owned()],
+        );
+        assert_eq!(label, "10 Downing Street, SW1A 2AA");
+    }
+
+    #[test]
+    fn unknown_country_addr() {
+        // without country, the default formats are used
+        let (name, label) =
+            format_addr_name_and_label("12", "Main Street", None, get_gb_admins().iter(), &[]);
+        assert!(name.contains("12") && name.contains("Main Street"));
+        assert_eq!(label, format!("{} (London)", name));
+    }
+
+    #[test]
+    fn custom_formatter() {
+        let formatter = LabelFormatter::new(
+            r#"
+            [default]
+            address_label = "{name} - {city} - {country}"
+            [countries.de]
+            address_name = "{housenumber}, {street}"
+            "#,
+        )
+        .unwrap();
+        let (name, label) = formatter.format_addr_name_and_label(
+            "77",
+            "Unter den Linden",
+            Some("10117"),
+            get_de_admins().iter(),
+            &["de".to_owned()],
+        );
+        assert_eq!(name, "77, Unter den Linden");
+        assert_eq!(label, "77, Unter den Linden - Berlin - Deutschland");
+    }
+
+    #[test]
+    fn dataset_formatter() {
+        let config = r#"
+            [datasets.ban]
+            address_name = "{housenumber} {street}"
+            address_label = "{name}, {postcode} {city}"
+            [datasets.us_addresses]
+            address_name = "{street} {housenumber}"
+            address_label = "{name}, {city} {postcode}"
+            "#;
+        let format = |dataset| {
+            LabelFormatter::new(config)
+                .unwrap()
+                .for_dataset(dataset)
+                .format_addr_name_and_label(
+                    "10",
+                    "Rue Hector Malot",
+                    Some("75012"),
+                    get_fr_admins().iter(),
+                    &["fr".to_owned()],
+                )
+        };
+        assert_eq!(
+            format("ban"),
+            (
+                "10 Rue Hector Malot".to_owned(),
+                "10 Rue Hector Malot, 75012 Paris".to_owned()
+            )
+        );
+        assert_eq!(
+            format("us_addresses"),
+            (
+                "Rue Hector Malot 10".to_owned(),
+                "Rue Hector Malot 10, Paris 75012".to_owned()
+            )
+        );
+        // the datasets without format use the default ones
+        assert_eq!(
+            format("fr"),
+            (
+                "Rue Hector Malot 10".to_owned(),
+                "Rue Hector Malot 10 (Paris)".to_owned()
+            )
+        );
+    }
+
     #[test]
     fn nl_poi_in_russian() {
         // searching for the rembrandt museum (https://www.openstreetmap.org/node/250624673) in russian
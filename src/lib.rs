@@ -30,8 +30,12 @@
 
 pub mod addr_reader;
 pub mod admin_geofinder;
+pub mod export;
+pub mod index_diff;
 pub mod labels;
+pub mod mimirload;
 pub mod osm_reader;
+pub mod replay;
 pub mod settings;
 pub mod stops;
 pub mod utils;
@@ -0,0 +1,100 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Loading in Elasticsearch of the indexes written by the importers with `--dry-run`.
+
+use crate::stops;
+use failure::{bail, Error};
+use mimir::dry_run::{self, IndexMetadata};
+use mimir::rubber::{IndexSettings, IndexVisibility, Rubber};
+use mimir::{Addr, Admin, MimirObject, Poi, Street};
+use serde::de::DeserializeOwned;
+use slog_scope::info;
+use std::path::Path;
+
+fn load<T>(
+    rubber: &mut Rubber,
+    dir: &Path,
+    index: &str,
+    metadata: &IndexMetadata,
+    index_settings: &IndexSettings,
+) -> Result<usize, Error>
+where
+    T: MimirObject + DeserializeOwned + Send + 'static,
+{
+    let documents = dry_run::read_documents::<T>(dir, index)?;
+    let visibility = if metadata.public {
+        IndexVisibility::Public
+    } else {
+        IndexVisibility::Private
+    };
+    rubber.index(&metadata.dataset, visibility, index_settings, documents)
+}
+
+/// Load all the published indexes of `dir` in Elasticsearch,
+/// and publish them as the importers would have done.
+///
+/// Returns the number of loaded documents.
+pub fn load_indexes(
+    rubber: &mut Rubber,
+    dir: &Path,
+    index_settings: &IndexSettings,
+) -> Result<usize, Error> {
+    let indexes = dry_run::published_indexes(dir)?;
+    if indexes.is_empty() {
+        bail!("no index to load in {}", dir.display());
+    }
+    rubber.initialize_templates()?;
+
+    let mut nb_documents = 0;
+    for (index, metadata) in indexes {
+        info!(
+            "loading {} ({} of the dataset {})",
+            index, metadata.doc_type, metadata.dataset
+        );
+        let nb = match metadata.doc_type.as_str() {
+            "admin" => load::<Admin>(rubber, dir, &index, &metadata, index_settings)?,
+            "street" => load::<Street>(rubber, dir, &index, &metadata, index_settings)?,
+            "addr" => load::<Addr>(rubber, dir, &index, &metadata, index_settings)?,
+            "poi" => load::<Poi>(rubber, dir, &index, &metadata, index_settings)?,
+            // the stops also need to be merged in the global stop index
+            "stop" => stops::index_stops(
+                rubber,
+                dry_run::read_documents(dir, &index)?.collect(),
+                &metadata.dataset,
+                index_settings,
+            )?,
+            doc_type => bail!("unknown document type {} for {}", doc_type, index),
+        };
+        info!("{} documents loaded from {}", nb, index);
+        nb_documents += nb;
+    }
+    Ok(nb_documents)
+}
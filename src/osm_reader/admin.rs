@@ -28,10 +28,13 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 use super::OsmPbfReader;
-use crate::osm_reader::osm_utils::{get_osm_codes_from_tags, make_centroid};
+use crate::osm_reader::osm_utils::{
+    get_alt_names_from_tags, get_osm_codes_from_tags, make_centroid,
+};
 use crate::utils;
 use cosmogony::ZoneType;
 use geo::bounding_rect::BoundingRect;
+use geo_types::{LineString, MultiPolygon};
 use itertools::Itertools;
 use osm_boundaries_utils::build_boundary;
 use slog_scope::{info, warn};
@@ -39,6 +42,8 @@ use std::collections::BTreeSet;
 
 pub type StreetsVec = Vec<mimir::Street>;
 
+const EARTH_RADIUS_KM: f64 = 6_371.008_8;
+
 #[derive(Debug)]
 pub struct AdminMatcher {
     admin_levels: BTreeSet<u32>,
@@ -139,21 +144,15 @@ pub fn read_administrative_regions(
             let boundary = build_boundary(relation, &objects);
             let zone_type = get_zone_type(level, city_level);
 
-            let weight = relation
-                .tags
-                .get("population")
-                .and_then(|p| p.parse().ok())
-                .or_else(|| {
-                    let rel = relation.refs.iter().find(|r| &r.role == "admin_centre")?;
-                    objects
-                        .get(&rel.member)?
-                        .node()?
-                        .tags
-                        .get("population")?
-                        .parse()
-                        .ok()
-                })
-                .unwrap_or(0.);
+            let center_tags = relation
+                .refs
+                .iter()
+                .find(|r| &r.role == "admin_centre")
+                .and_then(|r| objects.get(&r.member))
+                .and_then(|o| o.node())
+                .map(|node| &node.tags);
+            let population = read_population(&relation.tags, center_tags);
+            let weight = population.map_or(0., |p| p as f64);
 
             let coord = coord_center.unwrap_or_else(|| make_centroid(&boundary));
             let codes = get_osm_codes_from_tags(&relation.tags);
@@ -165,17 +164,24 @@ pub fn read_administrative_regions(
                 label: format!("{}{}", name.to_string(), format_zip_codes(&zip_codes)),
                 zip_codes,
                 weight,
+                population,
+                area: boundary.as_ref().map(compute_area),
                 coord,
                 approx_coord: Some(coord.into()),
                 bbox: boundary.as_ref().and_then(|b| b.bounding_rect()),
                 boundary,
                 zone_type,
+                zone_type_weight: None,
                 parent_id: None,
                 country_codes: utils::get_country_code(&codes).into_iter().collect(),
                 codes,
                 names: mimir::I18nProperties::default(),
                 labels: mimir::I18nProperties::default(),
                 distance: None,
+                score: None,
+                alt_names: get_alt_names_from_tags(&relation.tags),
+                old_ids: vec![],
+                flags: Default::default(),
                 context: None,
                 administrative_regions: Vec::new(),
             };
@@ -225,6 +231,44 @@ pub fn read_insee(tags: &osmpbfreader::Tags) -> Option<&str> {
     tags.get("ref:INSEE").map(|v| v.as_str())
 }
 
+/// The `population` tag of an admin, else the one of its admin centre.
+/// Note: this tag is not often filled, but the main cities have it.
+pub fn read_population(
+    tags: &osmpbfreader::Tags,
+    center_tags: Option<&osmpbfreader::Tags>,
+) -> Option<u64> {
+    let parse = |tags: &osmpbfreader::Tags| {
+        tags.get("population")?
+            .parse::<f64>()
+            .ok()
+            .filter(|p| *p >= 0.)
+            .map(|p| p as u64)
+    };
+    parse(tags).or_else(|| parse(center_tags?))
+}
+
+/// Area of a boundary in km², computed on the sphere
+pub fn compute_area(boundary: &MultiPolygon<f64>) -> f64 {
+    let ring_area = |ring: &LineString<f64>| {
+        let area: f64 = ring
+            .0
+            .windows(2)
+            .map(|w| {
+                (w[1].x - w[0].x).to_radians()
+                    * (2. + w[0].y.to_radians().sin() + w[1].y.to_radians().sin())
+            })
+            .sum();
+        (area * EARTH_RADIUS_KM * EARTH_RADIUS_KM / 2.).abs()
+    };
+    boundary
+        .0
+        .iter()
+        .map(|polygon| {
+            ring_area(polygon.exterior()) - polygon.interiors().iter().map(ring_area).sum::<f64>()
+        })
+        .sum()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -237,4 +281,41 @@ mod tests {
         );
         assert_eq!(get_zone_type(2, 1), None);
     }
+
+    #[test]
+    fn population_is_read_from_the_admin_centre_if_missing() {
+        let tags = |v: &[(&str, &str)]| -> osmpbfreader::Tags {
+            v.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+        };
+        let center_tags = tags(&[("population", "39589")]);
+        assert_eq!(
+            read_population(&tags(&[("population", "1200")]), Some(&center_tags)),
+            Some(1200)
+        );
+        assert_eq!(read_population(&tags(&[]), Some(&center_tags)), Some(39589));
+        assert_eq!(
+            read_population(&tags(&[("population", "many")]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn area_is_computed_in_km2() {
+        // a square of 0.1° at the equator, about 11.1km wide, with a hole of a quarter of it
+        let square = |min: f64, max: f64| {
+            LineString::from(vec![
+                (min, min),
+                (max, min),
+                (max, max),
+                (min, max),
+                (min, min),
+            ])
+        };
+        let boundary = MultiPolygon(vec![geo_types::Polygon::new(
+            square(0., 0.1),
+            vec![square(0., 0.05)],
+        )]);
+        let area = compute_area(&boundary);
+        assert!((area - 123.6 * 0.75).abs() < 0.5, "{}", area);
+    }
 }
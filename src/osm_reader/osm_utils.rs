@@ -29,8 +29,9 @@
 // www.navitia.io
 
 use super::osm_store::Getter;
+use geo::algorithm::simplify::Simplify;
 use geo::centroid::Centroid;
-use geo::MultiPolygon;
+use geo::{LineString, MultiPolygon};
 use osmpbfreader::StoreObjs;
 
 pub fn get_way_coord<T: StoreObjs + Getter>(
@@ -55,6 +56,26 @@ pub fn get_way_coord<T: StoreObjs + Getter>(
         .unwrap_or_else(mimir::Coord::default)
 }
 
+// tolerance (in degrees, about 1m) of the simplification of the geometry of the ways
+const WAY_SIMPLIFICATION_EPSILON: f64 = 0.000_01;
+
+/// Returns the geometry of the way (simplified), if it has at least two nodes.
+pub fn get_way_line<T: StoreObjs + Getter>(
+    obj_map: &T,
+    way: &osmpbfreader::objects::Way,
+) -> Option<LineString<f64>> {
+    let line: LineString<f64> = way
+        .nodes
+        .iter()
+        .filter_map(|node_id| obj_map.get(&(*node_id).into()))
+        .filter_map(|obj| obj.node().map(|node| (node.lon(), node.lat())))
+        .collect();
+    if line.0.len() < 2 {
+        return None;
+    }
+    Some(line.simplify(&WAY_SIMPLIFICATION_EPSILON))
+}
+
 pub fn make_centroid(boundary: &Option<MultiPolygon<f64>>) -> mimir::Coord {
     let coord = boundary
         .as_ref()
@@ -95,3 +116,53 @@ pub fn get_names_from_tags(tags: &osmpbfreader::Tags, langs: &[String]) -> mimir
         .collect();
     mimir::I18nProperties(properties)
 }
+
+pub fn get_alt_names_from_tags(tags: &osmpbfreader::Tags) -> Vec<String> {
+    // the alternative names of an object, a tag can contain several names separated by ';'
+    const ALT_NAME_TAGS: [&str; 5] = [
+        "alt_name",
+        "old_name",
+        "short_name",
+        "official_name",
+        "loc_name",
+    ];
+
+    let values = ALT_NAME_TAGS
+        .iter()
+        .filter_map(|tag| tags.get(*tag))
+        .map(|value| value.as_str());
+    collect_alt_names(tags, values)
+}
+
+/// The alternative names of a street or a poi, with its names in the other languages
+/// (`name:<lang>`), since they are all searched
+pub fn get_searched_alt_names_from_tags(tags: &osmpbfreader::Tags) -> Vec<String> {
+    let mut alt_names = get_alt_names_from_tags(tags);
+    let translations = tags
+        .iter()
+        .filter(|(key, _)| key.starts_with("name:"))
+        .map(|(_, value)| value.as_str());
+    for name in collect_alt_names(tags, translations) {
+        if !alt_names.contains(&name) {
+            alt_names.push(name);
+        }
+    }
+    alt_names
+}
+
+fn collect_alt_names<'a>(
+    tags: &osmpbfreader::Tags,
+    values: impl Iterator<Item = &'a str>,
+) -> Vec<String> {
+    let mut alt_names: Vec<String> = Vec::new();
+    let names = values
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && tags.get("name").map_or(true, |n| n != name));
+    for name in names {
+        if !alt_names.iter().any(|n| n == name) {
+            alt_names.push(name.to_string());
+        }
+    }
+    alt_names
+}
@@ -28,11 +28,13 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::osm_utils::get_searched_alt_names_from_tags;
 use super::osm_utils::get_way_coord;
 use super::osm_utils::make_centroid;
 use super::OsmPbfReader;
 use crate::admin_geofinder::AdminGeoFinder;
-use crate::{labels, settings::osm2mimir::Settings, utils};
+use crate::labels::LabelFormatter;
+use crate::{settings::osm2mimir::Settings, utils};
 use mimir::{rubber, Poi, PoiType};
 use osm_boundaries_utils::build_boundary;
 use serde::{Deserialize, Serialize};
@@ -60,6 +62,33 @@ pub struct PoiConfig {
     #[serde(rename = "types")]
     pub poi_types: Vec<PoiType>,
     pub rules: Vec<Rule>,
+    /// Type of the objects having a key of the rules (like `amenity`) but matching none of them.
+    /// Without it, these objects are not pois.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_type: Option<String>,
+    /// The objects having one of these tags are never pois (like the disused shops).
+    /// A tag is a key (`construction`), a prefix of keys (`disused:*`) or a key and its value
+    /// (`amenity=shelter`).
+    #[serde(default = "default_blacklisted_tags")]
+    pub blacklisted_tags: Vec<String>,
+}
+
+// the tags of the objects which are not there anymore, or not yet
+fn default_blacklisted_tags() -> Vec<String> {
+    [
+        "disused:*",
+        "abandoned:*",
+        "demolished:*",
+        "removed:*",
+        "razed:*",
+        "was:*",
+        "construction",
+        "construction:*",
+        "proposed:*",
+    ]
+    .iter()
+    .map(|tag| tag.to_string())
+    .collect()
 }
 
 impl Default for PoiConfig {
@@ -86,19 +115,41 @@ impl PoiConfig {
     pub fn get_poi_id(&self, tags: &osmpbfreader::Tags) -> Option<&str> {
         self.get_poi_type(tags).map(|poi_type| poi_type.id.as_str())
     }
+    pub fn is_blacklisted(&self, tags: &osmpbfreader::Tags) -> bool {
+        self.blacklisted_tags.iter().any(|blacklisted| {
+            let mut key_value = blacklisted.splitn(2, '=');
+            let key = key_value.next().unwrap_or_default();
+            match (key_value.next(), key.strip_suffix('*')) {
+                (Some(value), _) => tags.get(key).map_or(false, |v| v == value),
+                (None, Some(prefix)) => tags.iter().any(|(k, _)| k.starts_with(prefix)),
+                (None, None) => tags.contains_key(key),
+            }
+        })
+    }
     pub fn get_poi_type(&self, tags: &osmpbfreader::Tags) -> Option<&PoiType> {
-        self.rules
+        if self.is_blacklisted(tags) {
+            return None;
+        }
+        let poi_type_id = self
+            .rules
             .iter()
             .find(|rule| {
                 rule.osm_tags_filters
                     .iter()
                     .all(|f| tags.get(f.key.as_str()).map_or(false, |v| v == &f.value))
             })
-            .and_then(|rule| {
-                self.poi_types
-                    .iter()
-                    .find(|poi_type| poi_type.id == rule.poi_type_id)
-            })
+            .map(|rule| &rule.poi_type_id)
+            .or_else(|| {
+                self.default_type.as_ref().filter(|_| {
+                    self.rules
+                        .iter()
+                        .flat_map(|rule| &rule.osm_tags_filters)
+                        .any(|f| tags.contains_key(f.key.as_str()))
+                })
+            })?;
+        self.poi_types
+            .iter()
+            .find(|poi_type| &poi_type.id == poi_type_id)
     }
     pub fn check(&self) -> Result<(), Box<dyn Error>> {
         use std::collections::BTreeSet;
@@ -115,6 +166,11 @@ impl PoiConfig {
                 );
             }
         }
+        if let Some(default_type) = &self.default_type {
+            if !ids.contains(default_type.as_str()) {
+                return Err(format!("default poi_type_id {:?} not declared", default_type).into());
+            }
+        }
         Ok(())
     }
 }
@@ -133,6 +189,7 @@ fn parse_poi(
     obj_map: &BTreeMap<osmpbfreader::OsmId, osmpbfreader::OsmObj>,
     matcher: &PoiConfig,
     admins_geofinder: &AdminGeoFinder,
+    label_formatter: &LabelFormatter,
 ) -> Option<mimir::Poi> {
     let poi_type = match matcher.get_poi_type(osmobj.tags()) {
         Some(poi_type) => poi_type,
@@ -180,19 +237,32 @@ fn parse_poi(
     Some(mimir::Poi {
         id,
         name: name.to_string(),
-        label: labels::format_poi_label(name, adms.iter().map(|a| a.deref()), &country_codes),
+        label: label_formatter.format_poi_label(
+            name,
+            adms.iter().map(|a| a.deref()),
+            &country_codes,
+        ),
         coord,
         approx_coord: Some(coord.into()),
         zip_codes,
         administrative_regions: adms,
         weight: 0.,
-        poi_type: poi_type.clone(),
+        poi_type: mimir::PoiType {
+            id: mimir::canonical_case(&poi_type.id),
+            name: poi_type.name.clone(),
+        },
         properties: make_properties(osmobj.tags()),
         address: None,
         names: mimir::I18nProperties::default(),
         labels: mimir::I18nProperties::default(),
         distance: None,
+        score: None,
         country_codes,
+        alt_names: get_searched_alt_names_from_tags(osmobj.tags()),
+        old_ids: vec![],
+        // the osm objects are read without their metadata
+        updated_at: None,
+        flags: Default::default(),
         context: None,
     })
 }
@@ -205,12 +275,13 @@ pub fn pois(
     pbf: &mut OsmPbfReader,
     matcher: &PoiConfig,
     admins_geofinder: &AdminGeoFinder,
+    label_formatter: &LabelFormatter,
 ) -> Vec<Poi> {
     let objects = pbf.get_objs_and_deps(|o| matcher.is_poi(o.tags())).unwrap();
     objects
         .iter()
         .filter(|&(_, obj)| matcher.is_poi(obj.tags()))
-        .filter_map(|(_, obj)| parse_poi(obj, &objects, matcher, admins_geofinder))
+        .filter_map(|(_, obj)| parse_poi(obj, &objects, matcher, admins_geofinder, label_formatter))
         .collect()
 }
 
@@ -226,6 +297,7 @@ pub fn compute_poi_weight(pois_vec: &mut [Poi]) {
 }
 
 pub fn add_address(pois_vec: &mut [Poi], rubber: &mut rubber::Rubber) {
+    utils::warn_if_no_address(rubber, "pois");
     for poi in pois_vec {
         poi.address = rubber
             .get_address(&poi.coord)
@@ -417,4 +489,90 @@ mod tests {
             ],))
         );
     }
+    #[test]
+    fn custom_config_file() {
+        let file = std::fs::File::open(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/poi_config.json"
+        ))
+        .unwrap();
+        let c = PoiConfig::from_reader(file).unwrap();
+        assert_eq!(
+            Some("poi_type:amenity:food"),
+            c.get_poi_id(&tags(&[("amenity", "restaurant")]))
+        );
+        assert_eq!(
+            Some("poi_type:amenity:food"),
+            c.get_poi_id(&tags(&[("amenity", "fast_food")]))
+        );
+        // the amenities of no rule have the default type
+        assert_eq!(
+            Some("poi_type:other"),
+            c.get_poi_id(&tags(&[("amenity", "bench")]))
+        );
+        // but not the objects without any key of the rules
+        assert_eq!(None, c.get_poi_id(&tags(&[("highway", "bus_stop")])));
+    }
+    #[test]
+    fn blacklisted_tags() {
+        let json = r#"{
+            "types": [{"id": "poi_type:shop", "name": "Shop"}],
+            "rules": [
+                {
+                    "osm_tags_filters": [{"key": "shop", "value": "bakery"}],
+                    "type": "poi_type:shop"
+                }
+            ]
+        }"#;
+        let c = from_str(json).unwrap();
+        assert_eq!(
+            Some("poi_type:shop"),
+            c.get_poi_id(&tags(&[("shop", "bakery"), ("name", "Chez Paul")]))
+        );
+        // the disused shops are blacklisted by default
+        assert_eq!(
+            None,
+            c.get_poi_id(&tags(&[("shop", "bakery"), ("disused:shop", "bakery")]))
+        );
+        assert_eq!(
+            None,
+            c.get_poi_id(&tags(&[("shop", "bakery"), ("construction", "yes")]))
+        );
+
+        let json = r#"{
+            "types": [{"id": "poi_type:shop", "name": "Shop"}],
+            "rules": [
+                {
+                    "osm_tags_filters": [{"key": "shop", "value": "bakery"}],
+                    "type": "poi_type:shop"
+                }
+            ],
+            "blacklisted_tags": ["access=private"]
+        }"#;
+        let c = from_str(json).unwrap();
+        assert_eq!(
+            None,
+            c.get_poi_id(&tags(&[("shop", "bakery"), ("access", "private")]))
+        );
+        assert_eq!(
+            Some("poi_type:shop"),
+            c.get_poi_id(&tags(&[("shop", "bakery"), ("access", "yes")]))
+        );
+        // the default blacklist is replaced
+        assert_eq!(
+            Some("poi_type:shop"),
+            c.get_poi_id(&tags(&[("shop", "bakery"), ("disused:shop", "bakery")]))
+        );
+    }
+    #[test]
+    fn undeclared_default_type() {
+        from_str(
+            r#"{
+            "types": [{"id": "poi_type:bob", "name": "Bob"}],
+            "rules": [],
+            "default_type": "poi_type:other"
+        }"#,
+        )
+        .unwrap_err();
+    }
 }
@@ -33,12 +33,15 @@
     clippy::never_loop,
     clippy::option_map_unit_fn
 )]
-use super::osm_utils::get_way_coord;
+use super::osm_utils::{get_searched_alt_names_from_tags, get_way_coord, get_way_line};
 use super::OsmPbfReader;
 use crate::admin_geofinder::AdminGeoFinder;
-use crate::{labels, settings, utils, Error};
+use crate::labels::LabelFormatter;
+use crate::{settings, utils, Error};
 use cosmogony::ZoneType;
 use failure::ResultExt;
+use geo::algorithm::haversine_distance::HaversineDistance;
+use geo::{Coordinate, LineString, MultiLineString, Point};
 use osmpbfreader::{OsmId, StoreObjs};
 use slog_scope::info;
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -47,6 +50,16 @@ use std::sync::Arc;
 
 use super::osm_store::{Getter, ObjWrapper};
 
+// Maximum distance (in meters) between the ends of the ways of two streets for them to be continuous
+const MERGE_TOLERANCE: f64 = 10.;
+
+// Size (in degrees) of the cells of the grid in which the ends of the streets are bucketed:
+// two ends closer than `MERGE_TOLERANCE` are in the same or in adjacent cells (below 84° of latitude)
+const MERGE_GRID_CELL: f64 = 0.001;
+
+// A hierarchy of admins of a street, with the geometry of the street in these admins
+type StreetAdmins = (Vec<Arc<mimir::Admin>>, Option<MultiLineString<f64>>);
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[allow(dead_code)]
 pub enum Kind {
@@ -58,6 +71,7 @@ pub enum Kind {
 pub fn streets(
     pbf: &mut OsmPbfReader,
     admins_geofinder: &AdminGeoFinder,
+    label_formatter: &LabelFormatter,
     settings: &settings::osm2mimir::Settings,
 ) -> Result<Vec<mimir::Street>, Error> {
     let invalid_highways = settings
@@ -79,6 +93,15 @@ pub fn streets(
 
     // For the object to be a valid street, it needs to be an osm highway of a valid type,
     // or a relation of type associatedStreet.
+    let merge_across_admins = settings
+        .street
+        .as_ref()
+        .map_or(false, |street| street.merge_across_admins);
+    let parent_admin_level = settings
+        .street
+        .as_ref()
+        .and_then(|street| street.parent_admin_level);
+
     let is_valid_obj = |obj: &osmpbfreader::OsmObj| -> bool {
         match *obj {
             osmpbfreader::OsmObj::Way(ref way) => {
@@ -112,32 +135,50 @@ pub fn streets(
     info!("reading pbf done.");
 
     // Builder for street object
-    let build_street =
-        |id: String, name: String, coord: mimir::Coord, admins: Vec<Arc<mimir::Admin>>| {
-            let admins_iter = admins.iter().map(Deref::deref);
-            let country_codes = utils::find_country_codes(admins_iter.clone());
-            mimir::Street {
-                id,
-                label: labels::format_street_label(&name, admins_iter, &country_codes),
-                name,
-                weight: 0.,
-                zip_codes: utils::get_zip_codes_from_admins(&admins),
-                administrative_regions: admins,
-                coord,
-                approx_coord: Some(coord.into()),
-                distance: None,
-                country_codes,
-                context: None,
-            }
-        };
+    let build_street = |id: String,
+                        name: String,
+                        alt_names: Vec<String>,
+                        coord: mimir::Coord,
+                        coord_line: Option<MultiLineString<f64>>,
+                        admins: Vec<Arc<mimir::Admin>>| {
+        let admins_iter = admins.iter().map(Deref::deref);
+        let country_codes = utils::find_country_codes(admins_iter.clone());
+        mimir::Street {
+            id,
+            label: label_formatter.format_street_label(&name, admins_iter, &country_codes),
+            name,
+            alt_names,
+            weight: 0.,
+            zip_codes: utils::get_zip_codes_from_admins(&admins),
+            administrative_regions: admins,
+            coord,
+            approx_coord: Some(coord.into()),
+            coord_line,
+            distance: None,
+            along_street_distance: None,
+            score: None,
+            country_codes,
+            old_ids: vec![],
+            flags: Default::default(),
+            context: None,
+        }
+    };
 
     // Return an iterator giving documents that will be inserted for a given
-    // street: one for each hierarchy of admins.
-    let build_streets_for_admins =
-        move |name: String, id, kind, mut all_admins: Vec<Vec<_>>, coord| {
-            let single_output = all_admins.len() <= 1;
-            all_admins.sort_unstable(); // sort admins to make id deterministic
-            all_admins.into_iter().enumerate().map(move |(i, admins)| {
+    // street: one for each hierarchy of admins, with the geometry of the street in it.
+    let build_streets_for_admins = move |name: String,
+                                         alt_names: Vec<String>,
+                                         id,
+                                         kind,
+                                         mut all_admins: Vec<StreetAdmins>,
+                                         coord| {
+        let single_output = all_admins.len() <= 1;
+        // sort admins to make id deterministic
+        all_admins.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        all_admins
+            .into_iter()
+            .enumerate()
+            .map(move |(i, (admins, coord_line))| {
                 let doc_id = {
                     if single_output {
                         format!("street:osm:{}:{}", kind, id)
@@ -146,9 +187,16 @@ pub fn streets(
                     }
                 };
 
-                build_street(doc_id, name.clone(), coord, admins)
+                build_street(
+                    doc_id,
+                    name.clone(),
+                    alt_names.clone(),
+                    coord,
+                    coord_line,
+                    admins,
+                )
             })
-        };
+    };
 
     // List of outputed streets
     let mut street_list = Vec::new();
@@ -174,21 +222,40 @@ pub fn streets(
                 .filter(OsmId::is_way),
         );
 
-        let rel_street = rel
+        let street_ways: Vec<_> = rel
             .refs
             .iter()
             .filter(|ref_obj| ref_obj.member.is_way() && &ref_obj.role == "street")
-            .filter_map(|ref_obj| {
-                let obj = objs_map.get(&ref_obj.member)?;
+            .filter_map(|ref_obj| objs_map.get(&ref_obj.member))
+            .collect();
+        // the geometry of the street is made of all its ways
+        let coord_line = multi_line(
+            street_ways
+                .iter()
+                .filter_map(|obj| get_way_line(&objs_map, obj.way()?))
+                .collect(),
+        );
+
+        let rel_street = street_ways
+            .iter()
+            .filter_map(|obj| {
                 let way = obj.way()?;
                 let coord = get_way_coord(&objs_map, &way);
-                let name = rel_name.or_else(|| way.tags.get("name"))?;
+                // the alternative names come with the name, from the relation or the way
+                let (name, tags) = match rel_name {
+                    Some(name) => (name, &rel.tags),
+                    None => (way.tags.get("name")?, &way.tags),
+                };
 
                 Some(build_streets_for_admins(
                     name.to_string(),
+                    get_searched_alt_names_from_tags(tags),
                     rel.id.0,
                     "relation",
-                    get_street_admin(admins_geofinder, &objs_map, &way),
+                    get_street_admin(admins_geofinder, &objs_map, &way, parent_admin_level)
+                        .into_iter()
+                        .map(|admins| (admins, coord_line.clone()))
+                        .collect(),
                     coord,
                 ))
             })
@@ -200,7 +267,8 @@ pub fn streets(
     });
 
     // We merge all the ways with same `way_name` and `admin list of level(=city_level)`
-    // We use a Map to keep track of the way of smallest Id for a given pair of "name + cities list"
+    // We use a Map to keep track of the way of smallest Id for a given pair of "name + cities list",
+    // and of the geometries of all these ways
     let mut name_admin_map = BTreeMap::new();
 
     objs_map.for_each_filter(Kind::Way, |obj| {
@@ -212,22 +280,22 @@ pub fn streets(
         }
 
         if let Some(name) = way.tags.get("name") {
-            for admins in get_street_admin(admins_geofinder, &objs_map, way) {
+            let line = get_way_line(&objs_map, way);
+            for admins in get_street_admin(admins_geofinder, &objs_map, way, parent_admin_level) {
                 // Discriminate ways with same names by city
                 if let Some(city) = admins
                     .iter()
                     .find(|admin| admin.is_city())
                     .map(|city| city.id.to_string())
                 {
-                    name_admin_map
+                    let (stored_id, stored_admins, lines) = name_admin_map
                         .entry((name.to_string(), city))
-                        .and_modify(|(stored_id, stored_admins)| {
-                            if *stored_id > osmid {
-                                *stored_id = std::cmp::min(*stored_id, osmid);
-                                *stored_admins = admins.clone();
-                            }
-                        })
-                        .or_insert((osmid, admins));
+                        .or_insert_with(|| (osmid, admins.clone(), Vec::new()));
+                    if *stored_id > osmid {
+                        *stored_id = osmid;
+                        *stored_admins = admins;
+                    }
+                    lines.extend(line.clone());
                 }
             }
         }
@@ -236,41 +304,200 @@ pub fn streets(
     // For each street, construct the list of admins it will be added in.
     // This step ensure that documents have distinguishable IDs if they are
     // added for the same street but different admins.
-    let mut all_admins_for_street = HashMap::new();
+    // The streets are sorted by id, for an interrupted import to be resumed in the same order.
+    let mut all_admins_for_street = BTreeMap::new();
 
-    for (_, (min_id, admins)) in name_admin_map {
+    for (_, (min_id, admins, lines)) in name_admin_map {
         all_admins_for_street
             .entry(min_id)
             .or_insert_with(Vec::new)
-            .push(admins);
+            .push((admins, multi_line(lines)));
     }
 
-    street_list.extend(
-        all_admins_for_street
-            .into_iter()
-            .filter_map(|(id, all_admins)| {
-                let obj = objs_map.get(&id)?;
-                let way = obj.way()?;
-
-                Some(build_streets_for_admins(
-                    way.tags.get("name")?.to_string(),
-                    way.id.0,
-                    "way",
-                    all_admins,
-                    get_way_coord(&objs_map, way),
-                ))
-            })
-            .flatten(),
-    );
+    let way_streets = all_admins_for_street
+        .into_iter()
+        .filter_map(|(id, all_admins)| {
+            let obj = objs_map.get(&id)?;
+            let way = obj.way()?;
+
+            Some(build_streets_for_admins(
+                way.tags.get("name")?.to_string(),
+                get_searched_alt_names_from_tags(&way.tags),
+                way.id.0,
+                "way",
+                all_admins,
+                get_way_coord(&objs_map, way),
+            ))
+        })
+        .flatten();
+
+    if merge_across_admins {
+        street_list.extend(merge_streets_across_admins(
+            way_streets.collect(),
+            label_formatter,
+        ));
+    } else {
+        street_list.extend(way_streets);
+    }
 
     Ok(street_list)
 }
 
+// The geometry of the lines of the ways of a street, if any
+fn multi_line(lines: Vec<LineString<f64>>) -> Option<MultiLineString<f64>> {
+    if lines.is_empty() {
+        None
+    } else {
+        Some(MultiLineString(lines))
+    }
+}
+
+fn street_city(street: &mimir::Street) -> Option<&mimir::Admin> {
+    street
+        .administrative_regions
+        .iter()
+        .map(Deref::deref)
+        .find(|admin| admin.is_city())
+}
+
+fn grid_cell(coord: &Coordinate<f64>) -> (i64, i64) {
+    (
+        (coord.x / MERGE_GRID_CELL).floor() as i64,
+        (coord.y / MERGE_GRID_CELL).floor() as i64,
+    )
+}
+
+/// Merges the streets of the same name in different cities whose ways are continuous
+/// (the lines of their ways have ends closer than `MERGE_TOLERANCE`), like a street crossing
+/// the boundary of two cities.
+///
+/// The merged street has the admins and the ways of all the cities, and the id, coord and label of
+/// the street of the largest city. The ids of the other streets are kept in its old ids.
+fn merge_streets_across_admins(
+    streets: Vec<mimir::Street>,
+    label_formatter: &LabelFormatter,
+) -> Vec<mimir::Street> {
+    let ends: Vec<Vec<Coordinate<f64>>> = streets
+        .iter()
+        .map(|street| {
+            street
+                .coord_line
+                .iter()
+                .flat_map(|lines| lines.0.iter())
+                .flat_map(|line| line.0.first().into_iter().chain(line.0.last()))
+                .cloned()
+                .collect()
+        })
+        .collect();
+
+    // the groups of continuous streets, each street being first its own group
+    let mut groups: Vec<usize> = (0..streets.len()).collect();
+    fn root(groups: &mut [usize], mut i: usize) -> usize {
+        while groups[i] != i {
+            groups[i] = groups[groups[i]];
+            i = groups[i];
+        }
+        i
+    }
+    // the ends are bucketed by street name and cell of the grid,
+    // so that each end is only compared to the ends of the same name around it
+    let mut grid: HashMap<(&str, i64, i64), Vec<(usize, Coordinate<f64>)>> = HashMap::new();
+    for (i, street) in streets.iter().enumerate() {
+        for end in &ends[i] {
+            let (x, y) = grid_cell(end);
+            grid.entry((&street.name, x, y))
+                .or_insert_with(Vec::new)
+                .push((i, *end));
+        }
+    }
+    for (i, street) in streets.iter().enumerate() {
+        let city = street_city(street).map(|c| &c.id);
+        for end in &ends[i] {
+            let (x, y) = grid_cell(end);
+            for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                let neighbours = match grid.get(&(street.name.as_str(), x + dx, y + dy)) {
+                    Some(neighbours) => neighbours,
+                    None => continue,
+                };
+                for &(j, other_end) in neighbours {
+                    let continuous = j > i
+                        && street_city(&streets[j]).map(|c| &c.id) != city
+                        && Point::from(*end).haversine_distance(&Point::from(other_end))
+                            <= MERGE_TOLERANCE;
+                    if continuous {
+                        let (root_i, root_j) = (root(&mut groups, i), root(&mut groups, j));
+                        groups[root_j] = root_i;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut merged: BTreeMap<usize, Vec<mimir::Street>> = BTreeMap::new();
+    for (i, street) in streets.into_iter().enumerate() {
+        let group = root(&mut groups, i);
+        merged.entry(group).or_insert_with(Vec::new).push(street);
+    }
+    merged
+        .into_iter()
+        .map(|(_, group)| merge(group, label_formatter))
+        .collect()
+}
+
+// merges the streets of a group in the street of its largest city
+fn merge(mut streets: Vec<mimir::Street>, label_formatter: &LabelFormatter) -> mimir::Street {
+    let weight = |street: &mimir::Street| street_city(street).map_or(0., |city| city.weight);
+    // the largest city first, the smallest id on a tie for a deterministic id
+    streets.sort_by(|a, b| {
+        weight(b)
+            .partial_cmp(&weight(a))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    let mut streets = streets.into_iter();
+    let mut street = streets.next().expect("a group has at least one street");
+    for other in streets {
+        for admin in other.administrative_regions {
+            if !street
+                .administrative_regions
+                .iter()
+                .any(|a| a.id == admin.id)
+            {
+                street.administrative_regions.push(admin);
+            }
+        }
+        for alt_name in other.alt_names {
+            if !street.alt_names.contains(&alt_name) {
+                street.alt_names.push(alt_name);
+            }
+        }
+        if let Some(other_lines) = other.coord_line {
+            match street.coord_line {
+                Some(ref mut lines) => lines.0.extend(other_lines.0),
+                None => street.coord_line = Some(other_lines),
+            }
+        }
+        street.old_ids.push(other.id);
+        street.old_ids.extend(other.old_ids);
+    }
+    if !street.old_ids.is_empty() {
+        let admins_iter = street.administrative_regions.iter().map(Deref::deref);
+        street.country_codes = utils::find_country_codes(admins_iter.clone());
+        street.label =
+            label_formatter.format_street_label(&street.name, admins_iter, &street.country_codes);
+        street.zip_codes = utils::get_zip_codes_from_admins(&street.administrative_regions);
+    }
+    street
+}
+
 /// Returns branches of admins encompassing the street `way`.
+///
+/// With a `parent_admin_level`, the branches start at the admins of this level or bigger.
 fn get_street_admin<T: StoreObjs + Getter>(
     admins_geofinder: &AdminGeoFinder,
     obj_map: &T,
     way: &osmpbfreader::objects::Way,
+    parent_admin_level: Option<u32>,
 ) -> Vec<Vec<Arc<mimir::Admin>>> {
     let nb_nodes = way.nodes.len();
 
@@ -297,15 +524,22 @@ fn get_street_admin<T: StoreObjs + Getter>(
             // all part of the output together with their parents. For
             // performance reasons, if the admin hierarchy is built of zones
             // bigger than cities, at most one result will belong to the output.
-            admins_geofinder.get_admins_if(&coord, |admin| {
-                admin
-                    .zone_type
-                    .map(|zt| zt <= ZoneType::City)
-                    .unwrap_or(false)
-            })
+            admins_geofinder
+                .get_admins_if(&coord, |admin| is_street_parent(admin, parent_admin_level))
         })
 }
 
+/// Whether the street can be parented to `admin`: a city or a smaller zone, and with a
+/// `parent_admin_level`, an admin of this OSM admin_level or lower (the boroughs of a city
+/// are then skipped, the streets being parented to the city itself).
+fn is_street_parent(admin: &mimir::Admin, parent_admin_level: Option<u32>) -> bool {
+    admin
+        .zone_type
+        .map(|zt| zt <= ZoneType::City)
+        .unwrap_or(false)
+        && parent_admin_level.map_or(true, |level| admin.level <= level)
+}
+
 pub fn compute_street_weight(streets: &mut Vec<mimir::Street>) {
     for st in streets {
         for admin in &mut st.administrative_regions {
@@ -316,3 +550,202 @@ pub fn compute_street_weight(streets: &mut Vec<mimir::Street>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(id: &str, name: &str, weight: f64) -> Arc<mimir::Admin> {
+        Arc::new(mimir::Admin {
+            id: id.to_string(),
+            name: name.to_string(),
+            zone_type: Some(ZoneType::City),
+            weight,
+            ..Default::default()
+        })
+    }
+
+    // a street of a single way, going from one end to the other
+    fn street(
+        id: &str,
+        name: &str,
+        city: &Arc<mimir::Admin>,
+        ends: [(f64, f64); 2],
+    ) -> mimir::Street {
+        mimir::Street {
+            id: id.to_string(),
+            name: name.to_string(),
+            label: format!("{} ({})", name, city.name),
+            administrative_regions: vec![city.clone()],
+            coord_line: Some(MultiLineString(vec![LineString::from(ends.to_vec())])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn street_crossing_two_cities_is_merged() {
+        let montreuil = city("admin:montreuil", "Montreuil", 0.1);
+        let bagnolet = city("admin:bagnolet", "Bagnolet", 0.05);
+        // the ways meet at the boundary, a few meters apart
+        let mut streets = vec![
+            street(
+                "street:osm:way:2",
+                "Rue de Paris",
+                &bagnolet,
+                [(2.420_02, 48.86), (2.41, 48.86)],
+            ),
+            street(
+                "street:osm:way:1",
+                "Rue de Paris",
+                &montreuil,
+                [(2.43, 48.86), (2.42, 48.86)],
+            ),
+        ];
+        streets[0].alt_names = vec!["Route de Paris".to_string()];
+
+        let merged = merge_streets_across_admins(streets, LabelFormatter::default_formatter());
+        assert_eq!(merged.len(), 1);
+        let street = &merged[0];
+        assert_eq!(street.id, "street:osm:way:1");
+        assert_eq!(street.old_ids, vec!["street:osm:way:2"]);
+        assert_eq!(street.label, "Rue de Paris (Montreuil)");
+        assert_eq!(street.alt_names, vec!["Route de Paris"]);
+        let admins: Vec<_> = street
+            .administrative_regions
+            .iter()
+            .map(|a| a.id.as_str())
+            .collect();
+        assert_eq!(admins, vec!["admin:montreuil", "admin:bagnolet"]);
+        // with the ways of both cities
+        assert_eq!(
+            street.coord_line,
+            Some(MultiLineString(vec![
+                LineString::from(vec![(2.43, 48.86), (2.42, 48.86)]),
+                LineString::from(vec![(2.420_02, 48.86), (2.41, 48.86)]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn streets_far_apart_are_not_merged() {
+        let montreuil = city("admin:montreuil", "Montreuil", 0.1);
+        let bagnolet = city("admin:bagnolet", "Bagnolet", 0.05);
+        let streets = vec![
+            street(
+                "street:osm:way:1",
+                "Rue de Paris",
+                &montreuil,
+                [(2.45, 48.86), (2.44, 48.86)],
+            ),
+            street(
+                "street:osm:way:2",
+                "Rue de Paris",
+                &bagnolet,
+                [(2.42, 48.86), (2.41, 48.86)],
+            ),
+            street(
+                "street:osm:way:3",
+                "Rue de la Gare",
+                &bagnolet,
+                [(2.44, 48.86), (2.43, 48.86)],
+            ),
+        ];
+
+        let merged = merge_streets_across_admins(streets, LabelFormatter::default_formatter());
+        let ids: Vec<_> = merged.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec!["street:osm:way:1", "street:osm:way:2", "street:osm:way:3"]
+        );
+        assert!(merged.iter().all(|s| s.old_ids.is_empty()));
+    }
+
+    #[test]
+    fn streets_meeting_across_cells_of_the_grid_are_merged() {
+        let montreuil = city("admin:montreuil", "Montreuil", 0.1);
+        let bagnolet = city("admin:bagnolet", "Bagnolet", 0.05);
+        // the ends are a few meters apart, on both sides of a corner of the grid
+        let streets = vec![
+            street(
+                "street:osm:way:1",
+                "Rue de Paris",
+                &montreuil,
+                [(2.419_99, 48.859_99), (2.41, 48.85)],
+            ),
+            street(
+                "street:osm:way:2",
+                "Rue de Paris",
+                &bagnolet,
+                [(2.420_01, 48.860_01), (2.43, 48.87)],
+            ),
+        ];
+
+        let merged = merge_streets_across_admins(streets, LabelFormatter::default_formatter());
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].old_ids, vec!["street:osm:way:2"]);
+    }
+
+    fn square_admin(
+        id: &str,
+        level: u32,
+        zone_type: ZoneType,
+        size: f64,
+        parent_id: Option<&str>,
+    ) -> mimir::Admin {
+        let boundary = geo_types::MultiPolygon(vec![geo_types::Polygon::new(
+            LineString::from(vec![
+                (0., 0.),
+                (size, 0.),
+                (size, size),
+                (0., size),
+                (0., 0.),
+            ]),
+            vec![],
+        )]);
+        mimir::Admin {
+            id: id.to_string(),
+            level,
+            zone_type: Some(zone_type),
+            boundary: Some(boundary),
+            parent_id: parent_id.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn streets_are_parented_to_the_configured_admin_level() {
+        let mut geofinder = AdminGeoFinder::default();
+        geofinder.insert(square_admin(
+            "admin:borough",
+            9,
+            ZoneType::CityDistrict,
+            1.,
+            Some("admin:city"),
+        ));
+        geofinder.insert(square_admin(
+            "admin:city",
+            8,
+            ZoneType::City,
+            2.,
+            Some("admin:state"),
+        ));
+        geofinder.insert(square_admin("admin:state", 4, ZoneType::State, 3., None));
+        let coord = Coordinate { x: 0.5, y: 0.5 };
+        let branches = |parent_admin_level| -> Vec<Vec<String>> {
+            geofinder
+                .get_admins_if(&coord, |admin| is_street_parent(admin, parent_admin_level))
+                .iter()
+                .map(|branch| branch.iter().map(|admin| admin.id.clone()).collect())
+                .collect()
+        };
+
+        // by default, the streets are parented to the smallest admin
+        assert_eq!(
+            branches(None),
+            vec![vec!["admin:borough", "admin:city", "admin:state"]]
+        );
+        assert_eq!(branches(Some(8)), vec![vec!["admin:city", "admin:state"]]);
+        // the admins bigger than the cities are never the parents of the streets
+        assert!(branches(Some(4)).is_empty());
+    }
+}
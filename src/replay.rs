@@ -0,0 +1,298 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+//! Replay of the autocomplete queries recorded by bragi (with `[query_recorder]`),
+//! to measure how a change of its settings changes the ranking of the places.
+
+use bragi::query_recorder::RecordedQuery;
+use failure::{format_err, Error, ResultExt};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Number of first places compared with the Jaccard index
+const TOP_N: usize = 3;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplaySummary {
+    pub queries: usize,
+    /// queries which could not be replayed
+    pub failed: usize,
+    /// queries whose first place has changed
+    pub top1_changed: usize,
+    /// queries which found places when recorded, and none when replayed
+    pub lost: usize,
+    /// sum of the Jaccard indexes of the first places of the replayed queries
+    top3_jaccard_sum: f64,
+}
+
+impl ReplaySummary {
+    /// Mean of the Jaccard indexes of the 3 first places, recorded and replayed
+    /// (1 if the queries find the same first places, whatever their order)
+    pub fn top3_jaccard(&self) -> f64 {
+        let replayed = self.queries - self.failed;
+        if replayed == 0 {
+            1.
+        } else {
+            self.top3_jaccard_sum / replayed as f64
+        }
+    }
+
+    pub fn check(&self, max_top1_changes: Option<usize>) -> Result<(), Error> {
+        match max_top1_changes {
+            Some(max) if self.top1_changed > max => Err(format_err!(
+                "too many changes: the first place of {} queries has changed (max {})",
+                self.top1_changed,
+                max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ReplaySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queries: {}, failed: {}, top-1 changed: {}, lost: {}, top-3 jaccard: {:.3}",
+            self.queries,
+            self.failed,
+            self.top1_changed,
+            self.lost,
+            self.top3_jaccard()
+        )
+    }
+}
+
+/// Jaccard index of the first places of two rankings, 1 if both are empty
+fn jaccard(a: &[String], b: &[String]) -> f64 {
+    let a: HashSet<&String> = a.iter().take(TOP_N).collect();
+    let b: HashSet<&String> = b.iter().take(TOP_N).collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        1.
+    } else {
+        a.intersection(&b).count() as f64 / union as f64
+    }
+}
+
+/// Ids of the places of an autocomplete response, with or without envelope
+pub fn response_ids(response: &Value) -> Vec<String> {
+    response["features"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|feature| {
+            let feature = feature.get("source").unwrap_or(feature);
+            feature
+                .pointer("/properties/geocoding/id")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// Searches the places of the recorded query string with the autocomplete of bragi
+pub fn search_bragi(
+    client: &reqwest::blocking::Client,
+    bragi_url: &str,
+    query: &str,
+) -> Result<Vec<String>, Error> {
+    let url = format!("{}/autocomplete?{}", bragi_url.trim_end_matches('/'), query);
+    let response: Value = client
+        .get(&url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        .with_context(|_| format!("impossible to search {}", url))?;
+    Ok(response_ids(&response))
+}
+
+/// Replays the queries recorded in `input` with `search`, and compares the places found.
+///
+/// The queries whose first places have changed are written as NDJSON to `output` if given,
+/// with the places recorded and replayed.
+pub fn replay_queries<F>(
+    input: &Path,
+    output: Option<&Path>,
+    mut search: F,
+) -> Result<ReplaySummary, Error>
+where
+    F: FnMut(&str) -> Result<Vec<String>, Error>,
+{
+    let file =
+        File::open(input).with_context(|_| format!("impossible to open {}", input.display()))?;
+    let mut changes = output
+        .map(|output| {
+            File::create(output)
+                .map(BufWriter::new)
+                .with_context(|_| format!("impossible to create {}", output.display()))
+        })
+        .transpose()?;
+    let mut summary = ReplaySummary::default();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let recorded: RecordedQuery = serde_json::from_str(&line)
+            .with_context(|_| format!("invalid recorded query: {}", line))?;
+        summary.queries += 1;
+        let replayed = match search(&recorded.query) {
+            Ok(ids) => ids,
+            Err(e) => {
+                slog_scope::warn!("impossible to replay `{}`: {}", recorded.query, e);
+                summary.failed += 1;
+                continue;
+            }
+        };
+        if recorded.ids.first() != replayed.first() {
+            summary.top1_changed += 1;
+        }
+        if !recorded.ids.is_empty() && replayed.is_empty() {
+            summary.lost += 1;
+        }
+        let similarity = jaccard(&recorded.ids, &replayed);
+        summary.top3_jaccard_sum += similarity;
+        if let Some(changes) = &mut changes {
+            if recorded.ids.first() != replayed.first() || similarity < 1. {
+                let change = json!({
+                    "query": recorded.query,
+                    "recorded": recorded.ids,
+                    "replayed": replayed,
+                });
+                writeln!(changes, "{}", change)?;
+            }
+        }
+    }
+    if let Some(changes) = &mut changes {
+        changes.flush()?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const QUERIES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/queries.ndjson");
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn the_same_places_are_not_changes() {
+        // the places found are the recorded ones
+        let summary = replay_queries(Path::new(QUERIES), None, |query| {
+            let line = std::fs::read_to_string(QUERIES)?
+                .lines()
+                .map(|line| serde_json::from_str::<RecordedQuery>(line).unwrap())
+                .find(|recorded| recorded.query == query)
+                .unwrap();
+            Ok(line.ids)
+        })
+        .unwrap();
+        assert_eq!(summary.queries, 4);
+        assert_eq!(summary.top1_changed, 0);
+        assert_eq!(summary.top3_jaccard(), 1.);
+        assert!(summary.check(Some(0)).is_ok());
+    }
+
+    #[test]
+    fn ranking_changes_are_reported() {
+        // the places found by the new settings
+        let places: HashMap<&str, Vec<String>> = vec![
+            (
+                "q=gare%20de%20lyon",
+                ids(&[
+                    "stop_area:gare_de_lyon",
+                    "poi:gare_de_lyon",
+                    "street:rue_de_lyon",
+                ]),
+            ),
+            (
+                "q=rue%20hector%20malot&lat=48.85&lon=2.38",
+                ids(&[
+                    "street:hector_malot",
+                    "addr:20_hector_malot",
+                    "addr:15_hector_malot",
+                ]),
+            ),
+            ("q=melun", ids(&[])),
+        ]
+        .into_iter()
+        .collect();
+        let output = std::env::temp_dir().join("mimir_replay_test_changes.ndjson");
+        let summary = replay_queries(Path::new(QUERIES), Some(&output), |query| {
+            places
+                .get(query)
+                .cloned()
+                .ok_or_else(|| format_err!("no backend for {}", query))
+        })
+        .unwrap();
+
+        assert_eq!(summary.queries, 4);
+        // the last query is not found by the backend
+        assert_eq!(summary.failed, 1);
+        // the first place of the street has changed, and melun is not found anymore
+        assert_eq!(summary.top1_changed, 2);
+        assert_eq!(summary.lost, 1);
+        // the 3 queries replayed: same places, 2 common places out of 4, nothing in common
+        assert!((summary.top3_jaccard() - (1. + 0.5 + 0.) / 3.).abs() < 1e-9);
+        assert!(summary.check(Some(1)).is_err());
+
+        let changes: Vec<Value> = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0]["query"],
+            "q=rue%20hector%20malot&lat=48.85&lon=2.38"
+        );
+        assert_eq!(changes[1]["replayed"], json!([]));
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn ids_are_read_with_or_without_envelope() {
+        let feature = json!({ "properties": { "geocoding": { "id": "admin:melun" } } });
+        let response = json!({ "features": [feature] });
+        assert_eq!(response_ids(&response), vec!["admin:melun"]);
+        let response = json!({ "features": [{ "source": feature, "score": 1.5 }] });
+        assert_eq!(response_ids(&response), vec!["admin:melun"]);
+    }
+}
@@ -8,6 +8,7 @@ use std::path::PathBuf;
 use structopt::StructOpt;
 
 use crate::osm_reader::poi;
+use crate::utils::{CheckpointArgs, DryRunArgs, IndexArgs, ReportArgs};
 use crate::Error;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -20,6 +21,13 @@ pub struct StreetExclusion {
 pub struct Street {
     pub import: bool,
     pub exclusion: StreetExclusion,
+    /// Merge the streets of the same name crossing the boundary of their admins
+    #[serde(default)]
+    pub merge_across_admins: bool,
+    /// OSM admin_level of the admins the streets are parented to: the smaller admins
+    /// (like the boroughs of a city) are ignored. By default, the smallest admin is used.
+    #[serde(default)]
+    pub parent_admin_level: Option<u32>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -57,6 +65,8 @@ pub struct Elasticsearch {
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub dataset: String,
+    /// Keep the ids of the previous import in the new objects when their id has changed
+    pub alias_old_ids: bool,
     #[cfg(feature = "db-storage")]
     pub database: Option<Database>,
     pub elasticsearch: Elasticsearch,
@@ -71,6 +81,7 @@ impl Settings {
     pub fn new(args: Args) -> Result<Self, Error> {
         let config_dir = args.config_dir.clone();
         let settings = args.settings.clone();
+        let poi_config = args.poi_config.clone();
 
         let mut config = Config::new();
         // let config_dir = config_dir.clone();
@@ -165,12 +176,36 @@ impl Settings {
             .with_context(|e| format!("Could not merge arguments into configuration: {}", e))?;
 
         // You can deserialize (and thus freeze) the entire configuration as
-        config.try_into().map_err(|e| {
+        let mut settings: Settings = config.try_into().map_err(|e| {
             failure::err_msg(format!(
                 "Could not generate settings from configuration: {}",
                 e
             ))
-        })
+        })?;
+        settings.dataset = mimir::canonical_case(&settings.dataset);
+
+        // The poi types of a file replace the ones of the settings
+        if let Some(path) = poi_config {
+            info!("using poi types from {}", path.display());
+            let config = std::fs::File::open(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| poi::PoiConfig::from_reader(file).map_err(|e| e.to_string()))
+                .map_err(|e| {
+                    failure::err_msg(format!(
+                        "Could not read poi types from {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+            settings
+                .poi
+                .get_or_insert(Poi {
+                    import: false,
+                    config: None,
+                })
+                .config = Some(config);
+        }
+        Ok(settings)
     }
 }
 
@@ -194,6 +229,13 @@ pub struct Args {
     /// Import admins.
     #[structopt(short = "a", long = "import-admin")]
     import_admin: Option<bool>,
+    /// Merge the streets of the same name crossing the boundary of their admins
+    /// into a single street (changes their ids, the previous ones are kept as old ids).
+    #[structopt(long = "merge-streets-across-admins")]
+    merge_streets_across_admins: Option<bool>,
+    /// OSM admin_level of the admins the streets are parented to (the smaller admins are ignored).
+    #[structopt(long = "street-parent-admin-level")]
+    street_parent_admin_level: Option<u32>,
     /// Import POIs.
     #[structopt(short = "p", long = "import-poi")]
     import_poi: Option<bool>,
@@ -250,6 +292,23 @@ pub struct Args {
     /// be set)
     #[structopt(short = "s", long = "settings")]
     settings: Option<String>,
+
+    /// JSON file of the poi types and of the rules giving them from the OSM tags,
+    /// replacing the ones of the settings (like `[poi.config]`).
+    #[structopt(long = "poi-config", parse(from_os_str))]
+    poi_config: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub dry_run: DryRunArgs,
+
+    #[structopt(flatten)]
+    pub index: IndexArgs,
+
+    #[structopt(flatten)]
+    pub checkpoint: CheckpointArgs,
+
+    #[structopt(flatten)]
+    pub report: ReportArgs,
 }
 
 impl Source for Args {
@@ -265,6 +324,13 @@ impl Source for Args {
             m.insert(String::from("dataset"), Value::new(None, dataset));
         }
 
+        if let Some(alias_old_ids) = self.index.alias_old_ids {
+            m.insert(
+                String::from("alias_old_ids"),
+                Value::new(None, alias_old_ids),
+            );
+        }
+
         // ADMIN
         if let Some(import_admin) = self.import_admin {
             m.insert(String::from("admin.import"), Value::new(None, import_admin));
@@ -308,6 +374,20 @@ impl Source for Args {
             m.insert(String::from("street.import"), Value::new(None, import_way));
         }
 
+        if let Some(merge) = self.merge_streets_across_admins {
+            m.insert(
+                String::from("street.merge_across_admins"),
+                Value::new(None, merge),
+            );
+        }
+
+        if let Some(level) = self.street_parent_admin_level {
+            m.insert(
+                String::from("street.parent_admin_level"),
+                Value::new(None, i64::from(level)),
+            );
+        }
+
         // POI
         if let Some(import_poi) = self.import_poi {
             m.insert(String::from("poi.import"), Value::new(None, import_poi));
@@ -29,10 +29,12 @@
 // www.navitia.io
 
 use crate::admin_geofinder::AdminGeoFinder;
-use crate::{labels, utils};
+use crate::labels::LabelFormatter;
+use crate::utils;
 use failure::format_err;
 use failure::{Error, ResultExt};
 use mimir::rubber::{IndexSettings, Rubber, TypedIndex};
+use mimir::MimirObject;
 use slog_scope::{info, warn};
 use std::collections::HashMap;
 use std::mem::replace;
@@ -59,15 +61,18 @@ pub fn initialize_weights<'a, It, S: ::std::hash::BuildHasher>(
 
 pub fn import_stops(
     mut stops: Vec<mimir::Stop>,
-    connection_string: &str,
+    mut rubber: Rubber,
     dataset: &str,
     index_settings: IndexSettings,
+    label_formatter: &LabelFormatter,
+    alias_old_ids: bool,
 ) -> Result<(), Error> {
     info!("creation of indexes");
-    let mut rubber = Rubber::new(connection_string);
     rubber.initialize_templates()?;
 
-    attach_stops_to_admins(stops.iter_mut(), &mut rubber);
+    attach_stops_to_admins(stops.iter_mut(), &mut rubber, label_formatter);
+
+    add_addresses(stops.iter_mut(), &mut rubber);
 
     for stop in &mut stops {
         stop.coverages.push(dataset.to_string());
@@ -87,23 +92,47 @@ pub fn import_stops(
         stop.weight = (stop.weight + admin_weight) / 2.0;
     }
 
-    let global_index =
-        update_global_stop_index(&mut rubber, stops.iter(), dataset, &index_settings)?;
+    if alias_old_ids {
+        info!("aliasing the old stop ids");
+        rubber.alias_old_ids(dataset, &mut stops)?;
+    }
+
+    index_stops(&mut rubber, stops, dataset, &index_settings)?;
+    Ok(())
+}
+
+/// Index the stops of the dataset, and merge them in the global stop index.
+///
+/// In dry-run, only the stops of the dataset are written:
+/// the global stop index is updated when they are loaded in Elasticsearch.
+pub fn index_stops(
+    rubber: &mut Rubber,
+    stops: Vec<mimir::Stop>,
+    dataset: &str,
+    index_settings: &IndexSettings,
+) -> Result<usize, Error> {
+    if rubber.is_dry_run() {
+        return rubber.public_index(dataset, index_settings, stops.into_iter());
+    }
+    let global_index = update_global_stop_index(rubber, stops.iter(), dataset, index_settings)?;
 
     info!("Importing {} stops into Mimir", stops.len());
-    let nb_stops = rubber.public_index(dataset, &index_settings, stops.into_iter())?;
+    let nb_stops = rubber.public_index(dataset, index_settings, stops.into_iter())?;
     info!("Nb of indexed stops: {}", nb_stops);
 
-    publish_global_index(&mut rubber, &global_index)
-        .context("Error while publishing global index")?;
-    Ok(())
+    publish_global_index(rubber, &global_index).context("Error while publishing global index")?;
+    Ok(nb_stops)
 }
 
-fn attach_stop(stop: &mut mimir::Stop, admins: Vec<Arc<mimir::Admin>>) {
+fn attach_stop(
+    stop: &mut mimir::Stop,
+    admins: Vec<Arc<mimir::Admin>>,
+    label_formatter: &LabelFormatter,
+) {
     let admins_iter = admins.iter().map(|a| a.deref());
     let country_codes = utils::find_country_codes(admins_iter.clone());
 
-    stop.label = labels::format_stop_label(&stop.name, admins_iter, &country_codes);
+    stop.label = label_formatter.format_stop_label(&stop.name, admins_iter, &country_codes);
     stop.zip_codes = utils::get_zip_codes_from_admins(&admins);
 
     stop.country_codes = country_codes;
@@ -118,6 +147,7 @@ fn attach_stop(stop: &mut mimir::Stop, admins: Vec<Arc<mimir::Admin>>) {
 fn attach_stops_to_admins<'a, It: Iterator<Item = &'a mut mimir::Stop>>(
     stops: It,
     rubber: &mut Rubber,
+    label_formatter: &LabelFormatter,
 ) {
     let admins = rubber.get_all_admins().unwrap_or_else(|_| {
         warn!("Administratives regions not found in elasticsearch db");
@@ -139,7 +169,7 @@ fn attach_stops_to_admins<'a, It: Iterator<Item = &'a mut mimir::Stop>>(
             nb_matched += 1;
         }
 
-        attach_stop(&mut stop, admins);
+        attach_stop(&mut stop, admins, label_formatter);
     }
 
     info!(
@@ -148,6 +178,29 @@ fn attach_stops_to_admins<'a, It: Iterator<Item = &'a mut mimir::Stop>>(
         nb_matched + nb_unmatched
     );
 }
+
+/// Give the stops their closest address, if there is one within the address radius of rubber
+fn add_addresses<'a, It: Iterator<Item = &'a mut mimir::Stop>>(stops: It, rubber: &mut Rubber) {
+    utils::warn_if_no_address(rubber, "stops");
+    let mut nb_without_address = 0u32;
+    let mut nb_stops = 0u32;
+    for stop in stops {
+        stop.address = rubber
+            .get_address(&stop.coord)
+            .ok()
+            .and_then(|places| places.into_iter().next())
+            .and_then(|place| place.address());
+        nb_stops += 1;
+        if stop.address.is_none() {
+            nb_without_address += 1;
+        }
+    }
+    info!(
+        "there are {}/{} stops without any address",
+        nb_without_address, nb_stops
+    );
+}
+
 fn merge_collection<T: Ord>(target: &mut Vec<T>, source: Vec<T>) {
     use std::collections::BTreeSet;
     let tmp = replace(target, vec![]);
@@ -173,6 +226,8 @@ fn merge_stops<It: IntoIterator<Item = mimir::Stop>>(
         let commercial_modes = replace(&mut stop.commercial_modes, vec![]);
         let properties = replace(&mut stop.properties, vec![]);
         let feed_publishers = replace(&mut stop.feed_publishers, vec![]);
+        let validity_period = stop.validity_period.clone();
+        let address = stop.address.take();
 
         let stop_in_map = stops_by_id.entry(stop.id.clone()).or_insert(stop);
 
@@ -182,6 +237,12 @@ fn merge_stops<It: IntoIterator<Item = mimir::Stop>>(
         merge_collection(&mut stop_in_map.coverages, cov);
         merge_collection(&mut stop_in_map.properties, properties);
         merge_collection(&mut stop_in_map.feed_publishers, feed_publishers);
+        stop_in_map.validity_period =
+            mimir::ValidityPeriod::union(stop_in_map.validity_period.take(), validity_period);
+        // the address of the first stop which has one is kept
+        if stop_in_map.address.is_none() {
+            stop_in_map.address = address;
+        }
     }
     stops_by_id.into_iter().map(|(_, v)| v)
 }
@@ -215,7 +276,8 @@ fn update_global_stop_index<'a, It: Iterator<Item = &'a mimir::Stop>>(
     let all_merged_stops = merge_stops(all_es_stops);
     let es_index_name = mimir::rubber::get_date_index_name(GLOBAL_STOP_INDEX_NAME);
 
-    rubber.create_index(&es_index_name, &index_settings)?;
+    let synonyms = rubber.get_synonyms(mimir::Stop::doc_type(), None)?;
+    rubber.create_index_with_synonyms(&es_index_name, &index_settings, &synonyms)?;
     let typed_index = TypedIndex::new(es_index_name.clone());
 
     let nb_stops_added = rubber.bulk_index(&typed_index, all_merged_stops)?;
@@ -28,10 +28,22 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use crate::labels::LabelFormatter;
 use crate::Error;
-use slog_scope::error;
+use failure::{bail, ResultExt};
+use mimir::checkpoint::CheckpointSettings;
+use mimir::objects::Flags;
+use mimir::rubber::{IndexSettingsOverride, IndexedDocuments, Rubber};
+use mimir::validation::ValidationThresholds;
+use serde::{Deserialize, Serialize};
+use slog_scope::{error, info, warn};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Instant;
 use structopt::StructOpt;
 
 pub fn get_zip_codes_from_admins(admins: &[Arc<mimir::Admin>]) -> Vec<String> {
@@ -99,6 +111,353 @@ where
     }
 }
 
+/// Arguments of the importers to write the documents in files instead of Elasticsearch
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct DryRunArgs {
+    /// Write the documents in NDJSON files (one per document type) instead of indexing them
+    /// in Elasticsearch. The files can then be loaded with `mimir_admin mimirload`.
+    #[structopt(long = "dry-run", requires = "out")]
+    pub dry_run: bool,
+    /// Directory where the NDJSON files are written with --dry-run.
+    #[structopt(long = "out", parse(from_os_str), requires = "dry_run")]
+    pub out: Option<PathBuf>,
+}
+
+impl DryRunArgs {
+    /// Rubber on the given Elasticsearch, or on the NDJSON files with --dry-run
+    pub fn make_rubber(&self, connection_string: &str) -> Rubber {
+        let rubber = Rubber::new(connection_string);
+        match self.out {
+            Some(ref out) if self.dry_run => rubber.with_dry_run(out.clone()),
+            _ => rubber,
+        }
+    }
+}
+
+/// Options of the indexes created by the importers
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct IndexArgs {
+    /// Directory of the synonym files (in the Solr format) injected in the analyzers of the
+    /// new indexes: `<doc_type>.txt`, replaced by `<doc_type>_<dataset>.txt` if it exists.
+    /// The default synonyms are used if not given.
+    #[structopt(long = "synonyms-dir", parse(from_os_str))]
+    pub synonyms_dir: Option<PathBuf>,
+    /// Number of previous generations of the indexes kept when publishing the new ones,
+    /// to be able to roll back. The older generations are deleted.
+    #[structopt(long = "keep-previous", default_value = "0")]
+    pub keep_previous: usize,
+    /// Maximum number of bulk requests in flight during the indexing, to tune the import to the
+    /// capacity of Elasticsearch. By default, each insert thread has a bulk request in flight.
+    #[structopt(long = "max-bulk-requests")]
+    pub max_bulk_requests: Option<usize>,
+    /// TOML file of the flags given to the places of each dataset, returned with them by Bragi
+    /// which can also filter on them. It has a table of flags (booleans or strings) per dataset,
+    /// for example `[fr]` followed by `verified = true`.
+    #[structopt(long = "flags-file", parse(from_os_str))]
+    pub flags_file: Option<PathBuf>,
+    /// TOML file of the settings of the indexes of some datasets, overriding the number of
+    /// shards and replicas given to the importer. It has a table per dataset (`[fr]`), or per
+    /// index type of a dataset (`[addr_fr]`), with `nb_shards`, `nb_replicas`, and `settings`:
+    /// Elasticsearch settings merged into the ones of the index (for example its analysis).
+    #[structopt(long = "index-settings-file", parse(from_os_str))]
+    pub index_settings_file: Option<PathBuf>,
+    /// Validate the new indexes before publishing them (see `mimir_admin validate`),
+    /// the import fails if one of them is invalid.
+    #[structopt(long = "validate")]
+    pub validate: bool,
+    #[structopt(flatten)]
+    pub validation: ValidationArgs,
+    /// Reimport only the documents in this bounding box, given as
+    /// `<min_lon>,<min_lat>,<max_lon>,<max_lat>`. The documents of the current indexes outside
+    /// of it are kept, the ones inside of it are replaced by the imported ones.
+    #[structopt(long = "bbox", parse(try_from_str = parse_bbox))]
+    pub bbox: Option<geo_types::Rect<f64>>,
+    /// Maximum distance in meters of the address given to the pois and the stops,
+    /// the ones without address within this distance have none. 1000 by default.
+    #[structopt(long = "address-radius")]
+    pub address_radius: Option<f64>,
+    /// Path to the configuration of the label formats by country and by dataset.
+    /// If not given, the default formats (config/labels.toml) are used.
+    #[structopt(long = "label-config-file", parse(from_os_str))]
+    pub label_config_file: Option<PathBuf>,
+    /// Keep the ids of the previous import of the dataset as aliases of the objects whose
+    /// id has changed (`--alias-old-ids=true`). Not done for the addresses, which are streamed.
+    #[structopt(long = "alias-old-ids")]
+    pub alias_old_ids: Option<bool>,
+}
+
+impl IndexArgs {
+    /// The formatter of the labels of the places of `dataset`
+    pub fn label_formatter(&self, dataset: &str) -> Result<LabelFormatter, Error> {
+        let formatter = match self.label_config_file {
+            Some(ref path) => LabelFormatter::from_file(path)?,
+            None => LabelFormatter::default_formatter().clone(),
+        };
+        Ok(formatter.for_dataset(dataset))
+    }
+
+    pub fn configure(&self, rubber: Rubber) -> Result<Rubber, Error> {
+        let rubber = rubber.with_keep_previous(self.keep_previous);
+        let rubber = match self.max_bulk_requests {
+            Some(max_bulk_requests) => rubber.with_max_bulk_requests(max_bulk_requests),
+            None => rubber,
+        };
+        let rubber = match self.bbox {
+            Some(bbox) => rubber.with_bbox(bbox),
+            None => rubber,
+        };
+        let rubber = match self.address_radius {
+            Some(radius) => rubber.with_address_radius(radius),
+            None => rubber,
+        };
+        let rubber = if self.validate {
+            rubber.with_validation(self.validation.thresholds())
+        } else {
+            rubber
+        };
+        let rubber = match self.flags_file {
+            Some(ref path) => rubber.with_dataset_flags(read_dataset_flags(path)?),
+            None => rubber,
+        };
+        let rubber = match self.index_settings_file {
+            Some(ref path) => {
+                rubber.with_index_settings_overrides(read_index_settings_overrides(path)?)
+            }
+            None => rubber,
+        };
+        Ok(match self.synonyms_dir {
+            Some(ref dir) => rubber.with_synonyms_dir(dir.clone()),
+            None => rubber,
+        })
+    }
+}
+
+/// Warn if there are no addresses to give to the `places`: the addresses have to be
+/// imported before the pois and the stops.
+pub fn warn_if_no_address(rubber: &Rubber, places: &str) {
+    if !rubber.has_addresses() {
+        warn!(
+            "no address has been imported, the {} will have none: \
+             the addresses should be imported before them",
+            places
+        );
+    }
+}
+
+// parse a `<min_lon>,<min_lat>,<max_lon>,<max_lat>` bbox, like `2.2,48.8,2.4,48.9`
+pub fn parse_bbox(arg: &str) -> Result<geo_types::Rect<f64>, String> {
+    let values = arg
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid bbox {}: {}", arg, e))?;
+    match values[..] {
+        [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+            Ok(geo_types::Rect::new(
+                geo_types::Coordinate {
+                    x: min_lon,
+                    y: min_lat,
+                },
+                geo_types::Coordinate {
+                    x: max_lon,
+                    y: max_lat,
+                },
+            ))
+        }
+        _ => Err(format!(
+            "{} is not <min_lon>,<min_lat>,<max_lon>,<max_lat>",
+            arg
+        )),
+    }
+}
+
+/// Thresholds of the validation of an index, the defaults of `ValidationThresholds` are used
+/// for the ones not given
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct ValidationArgs {
+    /// Minimum number of documents of a valid index.
+    #[structopt(long = "min-documents")]
+    pub min_documents: Option<u64>,
+    /// Maximum ratio (between 0 and 1) of the documents without label.
+    #[structopt(long = "max-missing-label-ratio")]
+    pub max_missing_label_ratio: Option<f64>,
+    /// Maximum ratio (between 0 and 1) of the documents without coord.
+    #[structopt(long = "max-missing-coord-ratio")]
+    pub max_missing_coord_ratio: Option<f64>,
+    /// Minimum ratio (between 0 and 1) of the addresses attached to an admin.
+    #[structopt(long = "min-addr-with-admins-ratio")]
+    pub min_addr_with_admins_ratio: Option<f64>,
+}
+
+impl ValidationArgs {
+    pub fn thresholds(&self) -> ValidationThresholds {
+        let default = ValidationThresholds::default();
+        ValidationThresholds {
+            min_documents: self.min_documents.unwrap_or(default.min_documents),
+            max_missing_label_ratio: self
+                .max_missing_label_ratio
+                .unwrap_or(default.max_missing_label_ratio),
+            max_missing_coord_ratio: self
+                .max_missing_coord_ratio
+                .unwrap_or(default.max_missing_coord_ratio),
+            min_addr_with_admins_ratio: self
+                .min_addr_with_admins_ratio
+                .unwrap_or(default.min_addr_with_admins_ratio),
+        }
+    }
+}
+
+/// Reads the flags of each dataset from a TOML file
+pub fn read_dataset_flags(path: &Path) -> Result<BTreeMap<String, Flags>, Error> {
+    let content = fs::read_to_string(path)
+        .with_context(|_| format!("impossible to read {}", path.display()))?;
+    let dataset_flags: BTreeMap<String, Flags> = toml::from_str(&content)
+        .with_context(|_| format!("invalid flags in {}", path.display()))?;
+    // the flags are fields of the documents, their names are used as is in the ES queries
+    for name in dataset_flags.values().flat_map(Flags::keys) {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!(
+                "invalid flag '{}' in {}, only letters, digits and '_' are allowed",
+                name,
+                path.display()
+            );
+        }
+    }
+    Ok(dataset_flags)
+}
+
+/// Read the overrides of the settings of the indexes, by dataset (or by type of a dataset)
+pub fn read_index_settings_overrides(
+    path: &Path,
+) -> Result<BTreeMap<String, IndexSettingsOverride>, Error> {
+    let content = fs::read_to_string(path)
+        .with_context(|_| format!("impossible to read {}", path.display()))?;
+    let overrides: BTreeMap<String, IndexSettingsOverride> = toml::from_str(&content)
+        .with_context(|_| format!("invalid index settings in {}", path.display()))?;
+    Ok(overrides)
+}
+
+/// Arguments of the importers to resume an interrupted import
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct CheckpointArgs {
+    /// Directory where the progress of the indexing is checkpointed, to be able to resume
+    /// an interrupted import with --resume. There is no checkpoint if not given.
+    #[structopt(long = "checkpoint-dir", parse(from_os_str))]
+    pub checkpoint_dir: Option<PathBuf>,
+    /// Number of documents indexed between two checkpoints.
+    #[structopt(long = "checkpoint-every", default_value = "100000")]
+    pub checkpoint_every: usize,
+    /// Resume the interrupted import of the same input from its checkpoint
+    /// (the import starts from scratch if there is no checkpoint). Needs --checkpoint-dir.
+    #[structopt(long = "resume")]
+    pub resume: bool,
+}
+
+impl CheckpointArgs {
+    /// Checkpoint the indexing of the documents read from `input` (`None` for the standard input)
+    pub fn configure(&self, rubber: Rubber, input: Option<&Path>) -> Result<Rubber, Error> {
+        let dir = match self.checkpoint_dir {
+            Some(ref dir) => dir,
+            None if self.resume => bail!("--resume needs a --checkpoint-dir"),
+            None => return Ok(rubber),
+        };
+        let input = match input {
+            Some(input) => input,
+            None => bail!("an import from the standard input cannot be checkpointed"),
+        };
+        info!("computing the checksum of {}", input.display());
+        Ok(rubber.with_checkpoint(CheckpointSettings {
+            dir: dir.clone(),
+            every: self.checkpoint_every,
+            resume: self.resume,
+            source_checksum: source_checksum(input)?,
+        }))
+    }
+}
+
+/// Arguments of the importers to write a JSON report of the import
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct ReportArgs {
+    /// Write a JSON summary of the import (number of documents per type, duration, errors)
+    /// in this file, whether the import succeeds or not.
+    #[structopt(long = "report", parse(from_os_str))]
+    pub report: Option<PathBuf>,
+}
+
+/// Machine readable summary of an import
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportReport {
+    pub importer: String,
+    pub dataset: Option<String>,
+    pub success: bool,
+    pub duration_secs: f64,
+    /// number of documents indexed by document type
+    pub documents: BTreeMap<String, IndexedDocuments>,
+    /// the error of a failed import, with its causes
+    pub errors: Vec<String>,
+}
+
+impl ReportArgs {
+    /// Runs the import, then writes its report in the --report file if given
+    pub fn run<F>(&self, importer: &str, dataset: Option<String>, import: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<(), Error>,
+    {
+        let start = Instant::now();
+        let res = import();
+        let path = match self.report {
+            Some(ref path) => path,
+            None => return res,
+        };
+        let report = ImportReport {
+            importer: importer.to_string(),
+            dataset,
+            success: res.is_ok(),
+            duration_secs: start.elapsed().as_secs_f64(),
+            documents: mimir::rubber::indexed_documents(),
+            errors: match res {
+                Ok(()) => vec![],
+                Err(ref err) => err.iter_chain().map(|cause| cause.to_string()).collect(),
+            },
+        };
+        // the error of the import is returned rather than the one of its report
+        res.and(write_report(path, &report))
+    }
+}
+
+fn write_report(path: &Path, report: &ImportReport) -> Result<(), Error> {
+    let file =
+        File::create(path).with_context(|_| format!("impossible to create {}", path.display()))?;
+    serde_json::to_writer_pretty(file, report)?;
+    info!("import report written in {}", path.display());
+    Ok(())
+}
+
+/// Checksum of a file, or of the files of a directory (with their relative paths)
+pub fn source_checksum(input: &Path) -> Result<String, Error> {
+    let mut crc = flate2::Crc::new();
+    let mut buffer = vec![0; 1 << 16];
+    for entry in walkdir::WalkDir::new(input).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if let Ok(relative_path) = entry.path().strip_prefix(input) {
+            crc.update(relative_path.to_string_lossy().as_bytes());
+        }
+        let mut file = File::open(entry.path())
+            .with_context(|_| format!("impossible to read {}", entry.path().display()))?;
+        loop {
+            let nb_bytes = file.read(&mut buffer)?;
+            if nb_bytes == 0 {
+                break;
+            }
+            crc.update(&buffer[..nb_bytes]);
+        }
+    }
+    Ok(format!("{:08x}-{}", crc.sum(), crc.amount()))
+}
+
 pub fn get_country_code(codes: &[mimir::Code]) -> Option<String> {
     codes
         .iter()
@@ -109,3 +468,13 @@ pub fn get_country_code(codes: &[mimir::Code]) -> Option<String> {
 pub fn find_country_codes<'a>(admins: impl Iterator<Item = &'a mimir::Admin>) -> Vec<String> {
     admins.filter_map(|a| get_country_code(&a.codes)).collect()
 }
+
+pub fn find_city_name<'a>(mut admins: impl Iterator<Item = &'a mimir::Admin>) -> Option<String> {
+    admins.find(|a| a.is_city()).map(|a| a.name.clone())
+}
+
+pub fn find_country_name<'a>(mut admins: impl Iterator<Item = &'a mimir::Admin>) -> Option<String> {
+    admins
+        .find(|a| matches!(a.zone_type, Some(cosmogony::ZoneType::Country)))
+        .map(|a| a.name.clone())
+}
@@ -123,3 +123,112 @@ pub fn bano2mimir_sample_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
         _ => panic!("expected an address"),
     }
 }
+
+/// The import report gives the number of documents indexed for each type
+pub fn bano2mimir_report_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    let report_path = Path::new(env!("OUT_DIR")).join("bano2mimir_report.json");
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+            format!("--report={}", report_path.display()),
+        ],
+        &es_wrapper,
+    );
+
+    let report: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(&report_path).unwrap()).unwrap();
+    assert_eq!(report["importer"], "bano2mimir");
+    assert_eq!(report["dataset"], "fr");
+    assert_eq!(report["success"], true);
+    assert_eq!(
+        report["documents"],
+        serde_json::json!({ "addr": { "indexed": 35, "rejected": 0 } })
+    );
+    assert_eq!(report["errors"], serde_json::json!([]));
+    assert!(report["duration_secs"].is_f64());
+    assert_eq!(get_nb_elements(&es_wrapper), 35);
+}
+
+/// With a bbox, only the addresses inside of it are reimported:
+/// the ones outside of it are kept even if they are not in the file anymore,
+/// and the ones inside of it not in the file anymore are removed
+pub fn bano2mimir_bbox_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+    assert_eq!(get_nb_elements(&es_wrapper), 35);
+
+    // the addresses of Colomiers (in the bbox) and of Nice (outside of it) are removed from the file
+    let input = Path::new(env!("OUT_DIR")).join("sample-bano-bbox.csv");
+    let content = std::fs::read_to_string("./tests/fixtures/sample-bano.csv").unwrap();
+    let content: Vec<_> = content
+        .lines()
+        .filter(|l| !l.contains(",31700,") && !l.contains("Rue Foncet"))
+        .collect();
+    std::fs::write(&input, content.join("\n")).unwrap();
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            format!("--input={}", input.display()),
+            format!("--connection-string={}", es_wrapper.host()),
+            "--bbox=1.3,43.6,1.4,43.7".into(),
+        ],
+        &es_wrapper,
+    );
+
+    assert_eq!(get_nb_elements(&es_wrapper), 33);
+    let res: Vec<_> = es_wrapper.search_and_filter("Mairie", |_| true).collect();
+    assert_eq!(res.len(), 0);
+    let res: Vec<_> = es_wrapper.search_and_filter("999", |_| true).collect();
+    assert_eq!(res.len(), 1);
+}
+
+/// With --strict, the malformed line of the file fails the import instead of being skipped
+pub fn bano2mimir_strict_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    let status = std::process::Command::new(&bano2mimir)
+        .args(&[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+            "--strict".into(),
+        ])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    es_wrapper.refresh();
+    // nothing has been published
+    assert!(es_wrapper
+        .rubber
+        .get_all_aliased_index("munin_addr")
+        .unwrap()
+        .is_empty());
+
+    // without it, the malformed line is skipped
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+    assert_eq!(get_nb_elements(&es_wrapper), 35);
+}
@@ -28,12 +28,14 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::bragi_with_settings;
 use super::get_values;
 use super::BragiHandler;
+use mimir::rubber::Rubber;
 use serde_json::json;
 use std::path::Path;
 
-pub fn bragi_bano_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+pub fn bragi_bano_test(mut es_wrapper: crate::ElasticSearchWrapper<'_>) {
     let mut bragi = BragiHandler::new(es_wrapper.host());
 
     // *********************************
@@ -58,6 +60,25 @@ pub fn bragi_bano_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     simple_bano_lon_lat_test(&mut bragi);
     long_bano_address_test(&mut bragi);
     reverse_bano_test(&mut bragi);
+    reverse_batch_bano_test(&mut bragi);
+    count_only_bano_test(&mut bragi);
+    suggest_bano_test(&mut bragi);
+    collapse_bano_test(&mut bragi);
+    diversity_bano_test(&mut bragi);
+    match_reasons_bano_test(&mut bragi);
+    scores_bano_test(&mut bragi);
+    request_id_bano_test(es_wrapper.host());
+    compression_bano_test(&mut bragi, es_wrapper.host());
+    api_keys_bano_test(es_wrapper.host());
+    max_limit_bano_test(es_wrapper.host());
+    deadlines_bano_test(es_wrapper.host());
+    cursor_bano_test(&mut bragi);
+    search_template_bano_test(es_wrapper.host());
+    experiments_bano_test(&mut bragi, es_wrapper.host());
+    warnings_bano_test(&mut bragi);
+    disabled_endpoints_bano_test(es_wrapper.host());
+    // (last, it adds a street)
+    strict_house_number_bano_test(&mut bragi, &mut es_wrapper);
 }
 
 fn status_test(bragi: &mut BragiHandler) {
@@ -65,6 +86,48 @@ fn status_test(bragi: &mut BragiHandler) {
         bragi.get_json("/status").pointer("/status"),
         Some(&json!("good"))
     );
+
+    // the status is tagged from its content
+    let (status, headers, _) = bragi.raw_get_with_headers("/status", &[]);
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+    let etag = headers
+        .get("ETag")
+        .and_then(|v| v.to_str().ok())
+        .unwrap()
+        .to_string();
+
+    // nothing has changed since
+    let (status, headers, body) =
+        bragi.raw_get_with_headers("/status", &[("If-None-Match", &etag)]);
+    assert_eq!(status, actix_web::http::StatusCode::NOT_MODIFIED);
+    assert_eq!(
+        headers.get("ETag").and_then(|v| v.to_str().ok()),
+        Some(etag.as_str())
+    );
+    assert!(body.is_empty());
+
+    // but another status has changed
+    let (status, _, _) =
+        bragi.raw_get_with_headers("/status", &[("If-None-Match", "\"0000000000000000\"")]);
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+
+    // the status is also last modified by the creation of the most recent index
+    assert!(headers.contains_key("last-modified"));
+
+    // nothing has changed since a recent date
+    let now = actix_web::http::header::HttpDate::from(std::time::SystemTime::now()).to_string();
+    let (status, headers, body) =
+        bragi.raw_get_with_headers("/status", &[("If-Modified-Since", &now)]);
+    assert_eq!(status, actix_web::http::StatusCode::NOT_MODIFIED);
+    assert!(headers.contains_key("last-modified"));
+    assert!(body.is_empty());
+
+    // but the indexes have been created since an older date
+    let (status, _, _) = bragi.raw_get_with_headers(
+        "/status",
+        &[("If-Modified-Since", "Mon, 12 Oct 2020 10:10:10 GMT")],
+    );
+    assert_eq!(status, actix_web::http::StatusCode::OK);
 }
 
 fn simple_bano_autocomplete_test(bragi: &mut BragiHandler) {
@@ -84,7 +147,7 @@ fn simple_bano_autocomplete_test(bragi: &mut BragiHandler) {
                     "properties": {
                         "geocoding": {
                             "administrative_regions": [],
-                            "city": null,
+                            "city": "Paris",
                             "citycode": null,
                             "country_codes": ["fr"],
                             "housenumber": "15",
@@ -158,7 +221,7 @@ fn simple_bano_shape_filter_test(bragi: &mut BragiHandler) {
                   "housenumber": "15",
                   "street": "Rue Hector Malot",
                   "postcode": "75012",
-                  "city": null,
+                  "city": "Paris",
                   "citycode": null,
                   "country_codes": ["fr"],
                   "administrative_regions": []
@@ -203,7 +266,7 @@ fn simple_bano_shape_filter_test(bragi: &mut BragiHandler) {
                   "housenumber": "18",
                   "street": "Rue Hector Malot",
                   "postcode": "75012",
-                  "city": null,
+                  "city": "Paris",
                   "citycode": null,
                   "country_codes": ["fr"],
                   "administrative_regions": []
@@ -300,4 +363,700 @@ fn reverse_bano_test(bragi: &mut BragiHandler) {
         get_values(&res, "label"),
         vec!["2 Rue des Pins (Beauzelle)"]
     );
+
+    // the coordinates typed in the search box are reverse geocoded
+    let res = bragi.get_json("/autocomplete?q=48.8468, 2.37716");
+    assert_eq!(
+        res["geocoding"]["query_interpreted_as"],
+        json!("coordinate")
+    );
+    assert_eq!(
+        res["features"][0]["properties"]["geocoding"]["label"],
+        json!("20 Rue Hector Malot (Paris)")
+    );
+    let res = bragi.get("/autocomplete?q=2.37716E 48.8468N");
+    assert_eq!(
+        get_values(&res, "label"),
+        vec!["20 Rue Hector Malot (Paris)"]
+    );
+
+    // but not the values out of range, searched as a text
+    let res = bragi.get_json("/autocomplete?q=148.8468, 200.37716");
+    assert!(res["geocoding"].get("query_interpreted_as").is_none());
+}
+
+fn reverse_batch_bano_test(bragi: &mut BragiHandler) {
+    let label = |result: &serde_json::Value| {
+        result["features"][0]["properties"]["geocoding"]["label"].clone()
+    };
+
+    // the results are given in the order of the points, with their ids
+    let (status, body) = bragi.raw_post(
+        "/reverse/batch",
+        r#"[
+            {"lat": 48.8468, "lon": 2.37716, "id": "a"},
+            {"lat": 43.6681995, "lon": 1.3787628, "id": 2},
+            {"lat": 48.84681, "lon": 2.37716}
+        ]"#,
+    );
+    assert!(status.is_success());
+    let res = bragi.as_json(body);
+    let res = res.as_array().unwrap();
+    assert_eq!(res.len(), 3);
+    assert_eq!(res[0]["id"], json!("a"));
+    assert_eq!(label(&res[0]), json!("20 Rue Hector Malot (Paris)"));
+    assert_eq!(res[1]["id"], json!(2));
+    assert_eq!(label(&res[1]), json!("2 Rue des Pins (Beauzelle)"));
+    assert_eq!(res[2]["id"], serde_json::Value::Null);
+    assert_eq!(label(&res[2]), json!("20 Rue Hector Malot (Paris)"));
+
+    // in NDJSON, the point close to the previous one reuses its result
+    let (status, body) = bragi.raw_post_with_content_type(
+        "/reverse/batch?simplify=true",
+        "application/x-ndjson",
+        "{\"lat\": 48.8468, \"lon\": 2.37716, \"id\": 1}\n\
+         {\"lat\": 48.84681, \"lon\": 2.37716, \"id\": 2}\n",
+    );
+    assert!(status.is_success());
+    let res: Vec<serde_json::Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(res.len(), 2);
+    assert_eq!(res[1]["id"], json!(2));
+    assert_eq!(label(&res[1]), json!("20 Rue Hector Malot (Paris)"));
+
+    // the invalid points are all given
+    let (status, body) = bragi.raw_post(
+        "/reverse/batch",
+        r#"[{"lat": 48.8468, "lon": 2.37716}, {"lat": 148.8, "lon": 2.3}]"#,
+    );
+    assert_eq!(status, 400);
+    assert_eq!(bragi.as_json(body)["problems"].as_array().unwrap().len(), 1);
+}
+
+fn count_only_bano_test(bragi: &mut BragiHandler) {
+    // the count must be the same as the number of results of the full query
+    let all_20 = bragi.get("/autocomplete?q=20 rue hector malot&limit=100");
+    let count = bragi.get_json("/autocomplete?q=20 rue hector malot&count_only=true");
+    assert_eq!(count, json!({ "count": all_20.len() }));
+    assert_eq!(count, json!({ "count": 2 }));
+
+    // the fuzzy fallback is also counted
+    let fuzzy = bragi.get("/autocomplete?q=20 rue hectore malott&limit=100");
+    let count = bragi.get_json("/autocomplete?q=20 rue hectore malott&count_only=true");
+    assert_eq!(count, json!({ "count": fuzzy.len() }));
+}
+
+fn suggest_bano_test(bragi: &mut BragiHandler) {
+    // the places are suggested like they are autocompleted, with only their id, label, type and coord
+    let places = bragi.get("/autocomplete?q=20 rue hector malot");
+    let response = bragi.get_json("/autocomplete/suggest?q=20 rue hector malot");
+    let suggestions = response["suggestions"].as_array().unwrap();
+    assert_eq!(suggestions.len(), places.len());
+    for (suggestion, place) in suggestions.iter().zip(&places) {
+        assert_eq!(suggestion["id"], place["id"]);
+        assert_eq!(suggestion["label"], place["label"]);
+        assert_eq!(suggestion["type"], "house");
+        let mut fields: Vec<_> = suggestion.as_object().unwrap().keys().collect();
+        fields.sort();
+        assert_eq!(fields, vec!["id", "label", "lat", "lon", "type"]);
+    }
+
+    // the fuzzy fallback is also used
+    let response = bragi.get_json("/autocomplete/suggest?q=20 rue hectore malott");
+    assert!(!response["suggestions"].as_array().unwrap().is_empty());
+}
+
+fn collapse_bano_test(bragi: &mut BragiHandler) {
+    // all the house numbers of the 'Rue Hector Malot' are found
+    let all_addr = bragi.get("/autocomplete?q=rue hector malot&limit=100");
+    assert!(all_addr.len() > 1);
+    assert!(get_values(&all_addr, "street")
+        .iter()
+        .all(|s| *s == "Rue Hector Malot"));
+
+    // but only one of them is kept when collapsing on the street
+    let collapsed = bragi.get("/autocomplete?q=rue hector malot&limit=100&collapse_field=street");
+    assert_eq!(collapsed.len(), 1);
+    assert_eq!(get_values(&collapsed, "street"), vec!["Rue Hector Malot"]);
+    // the best result is kept
+    assert_eq!(collapsed[0]["id"], all_addr[0]["id"]);
+
+    // only some fields can be used to collapse the results
+    let (status, _) =
+        bragi.get_unchecked_json("/autocomplete?q=rue hector malot&collapse_field=housenumber");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+fn match_reasons_bano_test(bragi: &mut BragiHandler) {
+    // the debug requests give the clauses of the query matched by each place
+    let res = bragi.get_json("/autocomplete?q=20 rue hector malot&lat=50.2&lon=2.0&_debug=true");
+    let features = res["features"].as_array().unwrap();
+    assert_eq!(
+        features[0]["properties"]["geocoding"]["label"],
+        "20 Rue Hector Malot (Trifouilli-les-Oies)"
+    );
+    let reasons = features[0]["context"]["match_reasons"].as_array().unwrap();
+    assert!(reasons.contains(&json!("prefix")));
+    assert!(reasons.contains(&json!("house_number")));
+    assert!(reasons.contains(&json!("proximity")));
+
+    // they are not given without debug
+    let res = bragi.get_json("/autocomplete?q=20 rue hector malot&lat=50.2&lon=2.0");
+    assert!(res["features"][0].get("context").is_none());
+}
+
+fn scores_bano_test(bragi: &mut BragiHandler) {
+    // the raw scores of ES are given on demand, sorted as the features
+    let res = bragi.get_json("/autocomplete?q=rue hector malot&include_score=true");
+    let scores: Vec<f64> = res["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["_score"].as_f64().unwrap())
+        .collect();
+    assert!(scores.len() > 1);
+    assert!(scores.iter().all(|score| *score > 0.));
+    assert!(scores.windows(2).all(|s| s[0] >= s[1]));
+
+    // but not by default
+    let res = bragi.get_json("/autocomplete?q=rue hector malot");
+    assert!(res["features"][0].get("_score").is_none());
+}
+
+fn request_id_bano_test(es_host: String) {
+    // the request ids are propagated by default
+    let mut bragi = BragiHandler::new(es_host.clone());
+
+    // the request id given by the client is sent back
+    let (status, headers, _) = bragi.raw_get_with_headers(
+        "/autocomplete?q=20 rue hector malot",
+        &[("X-Request-Id", "my-request-id")],
+    );
+    assert!(status.is_success());
+    assert_eq!(
+        headers.get("X-Request-Id").and_then(|v| v.to_str().ok()),
+        Some("my-request-id")
+    );
+
+    // and it is also given in the error bodies
+    let (status, headers, body) = bragi.raw_get_with_headers(
+        "/autocomplete?q=toto&limit=ABCD",
+        &[("X-Request-Id", "bob")],
+    );
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        headers.get("X-Request-Id").and_then(|v| v.to_str().ok()),
+        Some("bob")
+    );
+    assert_eq!(bragi.as_json(body)["request_id"], json!("bob"));
+
+    // if the client gives no request id, one is generated
+    let (_, headers, _) = bragi.raw_get_with_headers("/autocomplete?q=20 rue hector malot", &[]);
+    assert!(headers.get("X-Request-Id").is_some());
+
+    // the header can be renamed
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: es_host.clone(),
+        request_id_header: Some("X-Correlation-Id".to_string()),
+        ..Default::default()
+    });
+    let (_, headers, _) = bragi.raw_get_with_headers(
+        "/autocomplete?q=20 rue hector malot",
+        &[("X-Correlation-Id", "my-request-id")],
+    );
+    assert_eq!(
+        headers
+            .get("X-Correlation-Id")
+            .and_then(|v| v.to_str().ok()),
+        Some("my-request-id")
+    );
+    assert!(headers.get("X-Request-Id").is_none());
+
+    // or the propagation disabled
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: es_host,
+        request_id_header: Some("none".to_string()),
+        ..Default::default()
+    });
+    let (_, headers, _) = bragi.raw_get_with_headers(
+        "/autocomplete?q=20 rue hector malot",
+        &[("X-Request-Id", "my-request-id")],
+    );
+    assert!(headers.get("X-Request-Id").is_none());
+}
+
+fn compression_bano_test(bragi: &mut BragiHandler, es_host: String) {
+    let content_encoding = |headers: &actix_web::http::HeaderMap| {
+        headers
+            .get("Content-Encoding")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+
+    // the big responses are compressed for the clients accepting it
+    let (status, headers, body) = bragi.raw_get_with_headers(
+        "/autocomplete?q=rue hector malot&limit=10",
+        &[("Accept-Encoding", "gzip")],
+    );
+    assert!(status.is_success());
+    assert_eq!(content_encoding(&headers), Some("gzip".to_string()));
+    // (the body is decompressed by the client)
+    assert_eq!(bragi.as_json(body)["features"].as_array().unwrap().len(), 3);
+
+    // but not for the other clients
+    let (_, headers, _) = bragi.raw_get_with_headers(
+        "/autocomplete?q=rue hector malot&limit=10",
+        &[("Accept-Encoding", "identity")],
+    );
+    assert_eq!(content_encoding(&headers), None);
+
+    // the small responses are never compressed
+    let (status, headers, _) = bragi.raw_get_with_headers(
+        "/autocomplete?q=toto&limit=ABCD",
+        &[("Accept-Encoding", "gzip")],
+    );
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(content_encoding(&headers), None);
+
+    // and nothing is compressed when the compression is disabled
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: es_host,
+        disable_compression: true,
+        ..Default::default()
+    });
+    let (status, headers, _) = bragi.raw_get_with_headers(
+        "/autocomplete?q=rue hector malot&limit=10",
+        &[("Accept-Encoding", "gzip")],
+    );
+    assert!(status.is_success());
+    assert_eq!(content_encoding(&headers), None);
+}
+
+fn api_keys_bano_test(es_host: String) {
+    let keys_file = Path::new(env!("OUT_DIR")).join("api_keys.toml");
+    std::fs::write(
+        &keys_file,
+        r#"
+        [keys.internal-key]
+        name = "internal"
+
+        [keys.paris-key]
+        name = "paris"
+        types = ["House"]
+        shape = '{"type": "Polygon", "coordinates": [[[2.2, 48.8], [2.5, 48.8], [2.5, 48.95], [2.2, 48.95], [2.2, 48.8]]]}'
+
+        [keys.limited-key]
+        name = "limited"
+        daily_quota = 1
+        "#,
+    )
+    .unwrap();
+    let mut bragi = bragi_with_settings(
+        es_host,
+        "api_keys_settings.toml",
+        &format!("[api_keys]\nfile = {:?}\n", keys_file.display().to_string()),
+    );
+
+    // the requests without a valid key are rejected
+    let (status, body) = bragi.get_unchecked_json("/autocomplete?q=rue hector malot");
+    assert_eq!(status, actix_web::http::StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], json!("UNAUTHORIZED"));
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=rue hector malot&apikey=bob");
+    assert_eq!(status, actix_web::http::StatusCode::UNAUTHORIZED);
+
+    // the key can be given in the Authorization header
+    let (status, _, body) = bragi.raw_get_with_headers(
+        "/autocomplete?q=rue hector malot&limit=10",
+        &[("Authorization", "Bearer internal-key")],
+    );
+    assert!(status.is_success());
+    let cities: Vec<_> = bragi.as_json(body)["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["properties"]["geocoding"]["city"].clone())
+        .collect();
+    assert!(cities.contains(&json!("Trifouilli-les-Oies")));
+
+    // the restricted keys only get the places of their types in their shape
+    let res = bragi.get("/autocomplete?q=rue hector malot&limit=10&apikey=paris-key");
+    assert!(!res.is_empty());
+    assert!(get_values(&res, "city").iter().all(|c| *c == "Paris"));
+    assert!(get_values(&res, "type").iter().all(|t| *t == "house"));
+
+    // their filters can only narrow the restrictions of the key
+    let (status, body) =
+        bragi.get_unchecked_json("/autocomplete?q=rue hector malot&type[]=street&apikey=paris-key");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["problems"],
+        json!(["the type 'street' is not allowed for this api key"])
+    );
+
+    // and they cannot use the apis unable to apply their restrictions
+    let (status, body) = bragi.get_unchecked_json("/reverse?lat=48.8&lon=2.3&apikey=paris-key");
+    assert_eq!(status, actix_web::http::StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], json!("FORBIDDEN"));
+
+    // over its quota, a key is rejected
+    let (status, _) =
+        bragi.get_unchecked_json("/autocomplete?q=rue hector malot&apikey=limited-key");
+    assert!(status.is_success());
+    let (status, headers, body) =
+        bragi.raw_get_with_headers("/autocomplete?q=rue hector malot&apikey=limited-key", &[]);
+    assert_eq!(status, actix_web::http::StatusCode::TOO_MANY_REQUESTS);
+    assert!(headers.get("Retry-After").is_some());
+    assert_eq!(bragi.as_json(body)["code"], json!("QUOTA_EXCEEDED"));
+
+    // the usage of each key is in the metrics
+    let (_, metrics) = bragi.raw_get("/metrics");
+    let metrics = String::from_utf8_lossy(&metrics);
+    assert!(metrics
+        .lines()
+        .any(|l| l.starts_with("bragi_api_key_requests_total")
+            && l.contains(r#"profile="limited""#)
+            && l.contains(r#"outcome="over_quota""#)));
+}
+
+fn max_limit_bano_test(es_host: String) {
+    let mut bragi = bragi_with_settings(es_host, "max_limit.toml", "[query]\nmax_limit = 2\n");
+
+    // the limit asked by the client is clamped to the maximum of the settings
+    let res = bragi.get("/autocomplete?q=rue hector malot&limit=10000");
+    assert_eq!(res.len(), 2);
+}
+
+fn diversity_bano_test(bragi: &mut BragiHandler) {
+    // there are 31 house numbers on the 'Rue Hector Malot' in Paris, but with the default
+    // settings only 2 of them are given, and the next best result
+    // (the 'Rue Hector Malot' of Trifouilli-les-Oies) is given too
+    let res = bragi.get("/autocomplete?q=rue hector malot&limit=10");
+    let cities = get_values(&res, "city");
+    assert_eq!(cities.iter().filter(|c| **c == "Paris").count(), 2);
+    assert_eq!(
+        cities
+            .iter()
+            .filter(|c| **c == "Trifouilli-les-Oies")
+            .count(),
+        1
+    );
+    assert_eq!(res.len(), 3);
+
+    // the offset is applied on the diversified results
+    let res = bragi.get("/autocomplete?q=rue hector malot&limit=10&offset=2");
+    assert_eq!(res.len(), 1);
+}
+
+// bragi with the deadlines enabled, and the given budgets per type
+fn bragi_with_deadlines(es_host: String, name: &str, per_type: &str) -> BragiHandler {
+    bragi_with_settings(
+        es_host,
+        name,
+        &format!(
+            "[query.deadlines]\nenabled = true\n[query.deadlines.per_type]\n{}\n",
+            per_type
+        ),
+    )
+}
+
+fn deadlines_bano_test(es_host: String) {
+    let mut bragi = BragiHandler::new(es_host.clone());
+    let all_addr = bragi.get("/autocomplete?q=rue hector malot");
+    assert!(!all_addr.is_empty());
+
+    // within their budget, the searches by type give the same results as a single search
+    let mut bragi = bragi_with_deadlines(es_host.clone(), "deadlines.toml", "addr = 1000");
+    let response = bragi.get_json("/autocomplete?q=rue hector malot");
+    assert!(response.get("partial").is_none());
+    let addr = bragi.get("/autocomplete?q=rue hector malot");
+    assert_eq!(get_values(&addr, "id"), get_values(&all_addr, "id"));
+
+    // the addresses cannot be found within their budget, they are dropped from the response
+    let mut bragi = bragi_with_deadlines(es_host, "no_addr_deadlines.toml", "addr = 0");
+    let response = bragi.get_json("/autocomplete?q=rue hector malot");
+    assert_eq!(response["partial"], json!(["addr"]));
+    assert_eq!(response["features"], json!([]));
+
+    // the partial responses are counted by dropped type
+    let (status, body) = bragi.raw_get("/metrics");
+    assert!(status.is_success());
+    let metrics = String::from_utf8(body.to_vec()).unwrap();
+    assert!(metrics.contains(r#"bragi_partial_responses_total{dropped_type="addr"}"#));
+}
+
+// the suffixes of the house numbers match whether they are glued to the number or not,
+// abbreviated or not ("12bis", "12 bis" or "12b")
+pub fn bragi_house_number_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/bano-house_numbers.csv".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+
+    for q in &[
+        "12bis Rue Hector Malot",
+        "12 bis Rue Hector Malot",
+        "12 BIS rue hector malot",
+        "12b Rue Hector Malot",
+    ] {
+        let addresses = bragi.get(&format!("/autocomplete?q={}", q));
+        assert_eq!(get_values(&addresses, "housenumber"), vec!["12 bis"]);
+    }
+    let addresses = bragi.get("/autocomplete?q=14ter Rue Hector Malot");
+    assert_eq!(get_values(&addresses, "housenumber"), vec!["14 ter"]);
+    let addresses = bragi.get("/autocomplete?q=15 bis Rue Hector Malot");
+    assert_eq!(get_values(&addresses, "housenumber"), vec!["15B"]);
+
+    // a number without suffix also matches the same number with a suffix, after the exact match
+    let addresses = bragi.get("/autocomplete?q=12 Rue Hector Malot");
+    assert_eq!(get_values(&addresses, "housenumber"), vec!["12", "12 bis"]);
+}
+
+fn strict_house_number_bano_test(
+    bragi: &mut BragiHandler,
+    es_wrapper: &mut crate::ElasticSearchWrapper<'_>,
+) {
+    // the street of the addresses of the 'Rue Hector Malot' in Paris
+    let coord = mimir::Coord::new(2.376_379, 48.846_495);
+    let street = mimir::Street {
+        id: "street:751124517P".to_string(),
+        name: "Rue Hector Malot".to_string(),
+        label: "Rue Hector Malot (Paris)".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        zip_codes: vec!["75012".to_string()],
+        ..Default::default()
+    };
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    es_wrapper
+        .rubber
+        .public_index("fr", &index_settings, std::iter::once(street))
+        .unwrap();
+    es_wrapper.refresh();
+
+    // the house number of the query must match: 24 is first, its street (without house number)
+    // is still given below it, but 2 is not given for 24
+    let res = bragi.get("/autocomplete?q=24 rue hector malot");
+    let labels = get_values(&res, "label");
+    assert_eq!(
+        labels,
+        vec!["24 Rue Hector Malot (Paris)", "Rue Hector Malot (Paris)"]
+    );
+
+    // a house number missing from the street gives its other addresses
+    let res = bragi.get("/autocomplete?q=42 rue hector malot");
+    assert!(res.len() > 1);
+    assert!(get_values(&res, "street")
+        .iter()
+        .all(|street| *street == "Rue Hector Malot"));
+
+    // when not strict, the house number is only boosted
+    let mut bragi = bragi_with_settings(
+        es_wrapper.host(),
+        "lenient_house_number.toml",
+        "[query.address]\nstrict_housenumber = false\n",
+    );
+    let res = bragi.get("/autocomplete?q=24 rue hector malot&limit=50");
+    let labels = get_values(&res, "label");
+    assert_eq!(labels[0], "24 Rue Hector Malot (Paris)");
+    // 2 is ranked below 24, if given
+    assert!(labels
+        .iter()
+        .position(|label| *label == "2 Rue Hector Malot (Paris)")
+        .map_or(true, |position| position > 0));
+    let res = bragi.get("/autocomplete?q=42 rue hector malot");
+    assert!(res.len() > 1);
+}
+
+fn cursor_bano_test(bragi: &mut BragiHandler) {
+    // all the addresses are paged through with the cursors, without gap nor duplicate
+    let mut ids = vec![];
+    let mut cursor = String::new();
+    loop {
+        let res = bragi.get_json(&format!(
+            "/autocomplete?type[]=house&limit=7&cursor={}",
+            cursor
+        ));
+        ids.extend(res["features"].as_array().unwrap().iter().map(|f| {
+            f["properties"]["geocoding"]["id"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        }));
+        match res["cursor"].as_str() {
+            Some(next) => cursor = next.to_string(),
+            None => break,
+        }
+    }
+    let count = bragi.get_json("/autocomplete?type[]=house&count_only=true")["count"]
+        .as_u64()
+        .unwrap();
+    assert_eq!(ids.len() as u64, count);
+    // the places are sorted by id
+    let mut sorted_ids = ids.clone();
+    sorted_ids.sort();
+    sorted_ids.dedup();
+    assert_eq!(ids, sorted_ids);
+}
+
+fn bragi_with_templates(es_host: String, name: &str, templates: &str) -> BragiHandler {
+    bragi_with_settings(
+        es_host,
+        name,
+        &format!("[query.templates]\n{}\n", templates),
+    )
+}
+
+fn search_template_bano_test(es_host: String) {
+    // the rendered search is not valid json if a parameter of the template is not given
+    Rubber::new(&es_host)
+        .put_search_template(
+            "bragi_test_addr",
+            r#"{
+                "query": {"bool": {
+                    "must": {"match": {"label": {"query": "{{q}}", "operator": "and"}}},
+                    "filter": [
+                        {{{filter}}},
+                        {"geo_distance": {"distance": "1km", "coord": {"lat": {{lat}}, "lon": {{lon}}}}}
+                    ]
+                }},
+                "from": {{from}},
+                "size": {{size}}
+            }"#,
+        )
+        .unwrap();
+    let mut bragi = bragi_with_templates(
+        es_host.clone(),
+        "search_templates.toml",
+        r#"addr = "bragi_test_addr""#,
+    );
+    let res = bragi.get("/autocomplete?q=rue hector malot&lat=48.846&lon=2.376&limit=3");
+    assert_eq!(res.len(), 3);
+    assert!(get_values(&res, "street")
+        .iter()
+        .all(|street| *street == "Rue Hector Malot"));
+    let first_id = get_values(&res, "id")[0].to_string();
+
+    // the coordinates and the filters of the request are given to the template
+    let res = bragi.get("/autocomplete?q=rue hector malot&lat=45.0&lon=5.0");
+    assert!(res.is_empty());
+    let res = bragi.get(&format!(
+        "/autocomplete?q=rue hector malot&lat=48.846&lon=2.376&limit=100&exclude_ids[]={}",
+        first_id
+    ));
+    assert!(!res.is_empty());
+    assert!(!get_values(&res, "id").contains(&first_id.as_str()));
+
+    // a missing template is reported
+    let mut bragi = bragi_with_templates(
+        es_host,
+        "missing_search_templates.toml",
+        r#"addr = "missing_template""#,
+    );
+    let (status, res) =
+        bragi.get_unchecked_json("/autocomplete?q=rue hector malot&lat=48.846&lon=2.376");
+    assert_eq!(status, 500);
+    assert_eq!(res["code"], "SEARCH_TEMPLATE_MISSING");
+    assert_eq!(
+        res["long"],
+        "the search template 'missing_template' of the addr places is missing in ES"
+    );
+}
+
+fn experiments_bano_test(bragi: &mut BragiHandler, es_host: String) {
+    // without experiment, the responses are not tagged
+    let res = bragi.get_json("/autocomplete?q=rue hector malot&request_id=user-1");
+    assert!(res.get("experiment").is_none());
+
+    // all the requests with an id are in the experiment
+    let mut bragi = bragi_with_settings(
+        es_host,
+        "experiments.toml",
+        r#"
+        [[query.experiments]]
+        name = "lenient"
+        traffic = 100
+        [query.experiments.settings.query.address]
+        strict_housenumber = false
+        "#,
+    );
+    let res = bragi.get_json("/autocomplete?q=rue hector malot&request_id=user-1");
+    assert_eq!(res["experiment"], "lenient");
+    assert!(!res["features"].as_array().unwrap().is_empty());
+    let res = bragi.get_json("/autocomplete?q=rue hector malot&count_only=true&request_id=user-1");
+    assert_eq!(res["experiment"], "lenient");
+
+    // the experiment can be requested, if it exists
+    let res = bragi.get_json("/autocomplete?q=rue hector malot&experiment=lenient");
+    assert_eq!(res["experiment"], "lenient");
+    let status = bragi.get_status("/autocomplete?q=rue hector malot&experiment=unknown");
+    assert_eq!(status, 400);
+
+    // the requests of the experiment are counted
+    let (_, body) = bragi.raw_get("/metrics");
+    let metrics = String::from_utf8(body.to_vec()).unwrap();
+    assert!(metrics.contains(r#"bragi_experiment_requests_total{experiment="lenient"}"#));
+}
+
+fn warnings_bano_test(bragi: &mut BragiHandler) {
+    // without problem, there is no warning
+    let res = bragi.get_json("/autocomplete?q=rue hector malot&datasets[]=fr");
+    assert!(res.get("warnings").is_none());
+
+    // the datasets without index are reported, their places are just not found
+    let (status, headers, body) = bragi.raw_get_with_headers(
+        "/autocomplete?q=rue hector malot&datasets[]=fr&datasets[]=xx",
+        &[],
+    );
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+    let res: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(res["warnings"][0]["code"], "UNKNOWN_DATASET");
+    assert_eq!(res["warnings"][0]["message"], "unknown datasets: xx");
+    assert!(!res["features"].as_array().unwrap().is_empty());
+    // the codes of the warnings are also given in a header, for the access logs
+    assert_eq!(
+        headers.get("x-warnings").and_then(|v| v.to_str().ok()),
+        Some("UNKNOWN_DATASET")
+    );
+
+    // the queries too long are truncated
+    let long_query = format!("rue hector malot {}", "x".repeat(300));
+    let res = bragi.get_json(&format!("/autocomplete?q={}&count_only=true", long_query));
+    assert_eq!(res["warnings"][0]["code"], "QUERY_TRUNCATED");
+}
+
+fn disabled_endpoints_bano_test(es_host: String) {
+    let mut bragi = bragi_with_settings(
+        es_host,
+        "no_reverse.toml",
+        "[service]\nendpoints = { reverse = false, reverse_batch = false }\n",
+    );
+
+    // the disabled endpoints are not found, the other ones still work
+    assert_eq!(
+        bragi.get_status("/reverse?lon=2.37716&lat=48.84680"),
+        actix_web::http::StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        bragi.get_status("/autocomplete?q=rue hector malot"),
+        actix_web::http::StatusCode::OK
+    );
+    let status = bragi.get_json("/status");
+    let endpoints = status["endpoints"].as_array().unwrap();
+    assert!(endpoints.contains(&json!("autocomplete")));
+    assert!(!endpoints.contains(&json!("reverse")));
 }
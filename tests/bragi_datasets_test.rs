@@ -0,0 +1,173 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::get_values;
+use super::BragiHandler;
+use serde_json::json;
+use std::path::Path;
+
+fn import_bano(es: &crate::ElasticSearchWrapper<'_>, dataset: &str, args: &[String]) {
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es.host()),
+            format!("--dataset={}", dataset),
+        ]
+        .iter()
+        .chain(args)
+        .cloned()
+        .collect::<Vec<_>>(),
+        es,
+    );
+}
+
+// two imports of the same addresses with different datasets
+// are stamped with their dataset, and can be searched independently
+pub fn bragi_datasets_test(es: crate::ElasticSearchWrapper<'_>) {
+    import_bano(&es, "fr", &[]);
+    // the datasets are stored in lowercase
+    import_bano(&es, "BE", &[]);
+
+    for dataset in &["fr", "be"] {
+        let res = es.search_on_index(
+            format!("munin_addr_{}", dataset).as_str(),
+            &format!("dataset:{}", dataset),
+        );
+        assert_eq!(res["hits"]["total"], 35);
+    }
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    // without datasets, the address of both imports is found
+    let addresses = bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)");
+    assert_eq!(
+        get_values(&addresses, "id"),
+        vec!["addr:2.376379;48.846495:15", "addr:2.376379;48.846495:15"]
+    );
+
+    for dataset in &["fr", "be"] {
+        let addresses = bragi.get(&format!(
+            "/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]={}",
+            dataset
+        ));
+        assert_eq!(
+            get_values(&addresses, "id"),
+            vec!["addr:2.376379;48.846495:15"]
+        );
+    }
+
+    let addresses =
+        bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=fr&datasets[]=be");
+    assert_eq!(addresses.len(), 2);
+
+    // the datasets are matched whatever their case
+    let addresses =
+        bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=FR&datasets[]=Be");
+    assert_eq!(addresses.len(), 2);
+    let addresses =
+        bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=Fr&type[]=HOUSE");
+    assert_eq!(
+        get_values(&addresses, "id"),
+        vec!["addr:2.376379;48.846495:15"]
+    );
+
+    // an unknown dataset finds nothing
+    let addresses = bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=de");
+    assert!(addresses.is_empty());
+
+    // all the datasets but the excluded ones are searched
+    let addresses =
+        bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets_blacklist[]=BE");
+    assert_eq!(
+        get_values(&addresses, "id"),
+        vec!["addr:2.376379;48.846495:15"]
+    );
+    let addresses = bragi.get(
+        "/autocomplete?q=15 Rue Hector Malot (Paris)&datasets_blacklist[]=fr&datasets_blacklist[]=be",
+    );
+    assert!(addresses.is_empty());
+
+    // a dataset cannot be both requested and excluded
+    let (status, _) = bragi.get_unchecked_json(
+        "/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=fr&datasets_blacklist[]=fr",
+    );
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+// the places of a dataset are given the flags of this dataset at their import,
+// they are returned with them and can be filtered on them
+pub fn bragi_flags_test(es: crate::ElasticSearchWrapper<'_>) {
+    let flags_file = Path::new(env!("OUT_DIR")).join("flags.toml");
+    std::fs::write(&flags_file, "[fr]\nverified = true\nsource = \"ign\"\n").unwrap();
+    import_bano(
+        &es,
+        "fr",
+        &[format!("--flags-file={}", flags_file.display())],
+    );
+    import_bano(&es, "be", &[]);
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let response = bragi.get_json("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=fr");
+    assert_eq!(
+        response["features"][0]["properties"]["geocoding"]["flags"],
+        json!({ "verified": true, "source": "ign" })
+    );
+    let response = bragi.get_json("/autocomplete?q=15 Rue Hector Malot (Paris)&datasets[]=be");
+    assert_eq!(
+        response["features"][0]["properties"]["geocoding"].get("flags"),
+        None
+    );
+
+    // only the address of the flagged dataset is kept
+    for flags in &[
+        "flags[]=verified",
+        "flags[]=source:ign",
+        "flags[]=verified&flags[]=source:ign",
+    ] {
+        let addresses = bragi.get(&format!(
+            "/autocomplete?q=15 Rue Hector Malot (Paris)&{}",
+            flags
+        ));
+        assert_eq!(
+            get_values(&addresses, "id"),
+            vec!["addr:2.376379;48.846495:15"]
+        );
+    }
+    let addresses = bragi.get("/autocomplete?q=15 Rue Hector Malot (Paris)&flags[]=source:osm");
+    assert!(addresses.is_empty());
+
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=Paris&flags[]=a.b");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
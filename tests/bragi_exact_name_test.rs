@@ -0,0 +1,102 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::get_values;
+use super::BragiHandler;
+use cosmogony::ZoneType;
+use mimir::objects::{Admin, Coord, Poi, PoiType};
+use mimir::rubber::IndexSettings;
+
+fn make_city(id: &str, name: &str, weight: f64, coord: Coord) -> Admin {
+    Admin {
+        id: id.to_string(),
+        insee: id.to_string(),
+        level: 8,
+        name: name.to_string(),
+        label: name.to_string(),
+        weight,
+        coord,
+        approx_coord: Some(coord.into()),
+        zone_type: Some(ZoneType::City),
+        ..Default::default()
+    }
+}
+
+// the place named exactly as the query comes first, even if a place only containing
+// its words is more important
+pub fn bragi_exact_name_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let paris_coord = Coord::new(2.352_222, 48.856_614);
+    let paris = make_city("admin:paris", "Paris", 0.01, paris_coord);
+    let paris_l_hopital = make_city(
+        "admin:paris-l-hopital",
+        "Paris-l'Hôpital",
+        1.,
+        Coord::new(4.633_333, 46.916_667),
+    );
+    let cabaret = Poi {
+        id: "poi:cabaret-paris".to_string(),
+        label: "Cabaret Paris (Paris)".to_string(),
+        name: "Cabaret Paris".to_string(),
+        coord: paris_coord,
+        approx_coord: Some(paris_coord.into()),
+        weight: 1.,
+        poi_type: PoiType {
+            id: "poi_type:amenity:nightclub".to_string(),
+            name: "Nightclub".to_string(),
+        },
+        ..Default::default()
+    };
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    es.rubber
+        .public_index(
+            "fr",
+            &index_settings,
+            vec![paris, paris_l_hopital].into_iter(),
+        )
+        .unwrap();
+    es.rubber
+        .public_index("fr", &index_settings, vec![cabaret].into_iter())
+        .unwrap();
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let places = bragi.get("/autocomplete?q=Paris");
+    assert_eq!(
+        get_values(&places, "id"),
+        vec!["admin:paris", "admin:paris-l-hopital", "poi:cabaret-paris"]
+    );
+
+    // the exact match ignores the case and the diacritics
+    let places = bragi.get("/autocomplete?q=PARIS-L'HOPITAL");
+    assert_eq!(get_values(&places, "id")[0], "admin:paris-l-hopital");
+}
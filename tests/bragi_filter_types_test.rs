@@ -93,6 +93,9 @@ pub fn bragi_filter_types_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     stop_by_id_test(&mut bragi);
     stop_area_that_does_not_exists(&mut bragi);
     stop_area_invalid_index(&mut bragi);
+    id_in_query_test(&mut bragi);
+    exclude_ids_test(&mut bragi);
+    preference_test(&mut bragi);
 }
 
 fn no_type_no_dataset_test(bragi: &mut BragiHandler) {
@@ -229,6 +232,29 @@ fn stop_area_that_does_not_exists(bragi: &mut BragiHandler) {
     );
 }
 
+fn id_in_query_test(bragi: &mut BragiHandler) {
+    // the ids pasted in the search box are looked up directly
+    let res = bragi.get_json("/autocomplete?q=admin:fr:77288");
+    assert_eq!(res["geocoding"]["query_interpreted_as"], json!("id"));
+    assert_eq!(
+        bragi.get("/autocomplete?q=admin:fr:77288"),
+        bragi.get("/features/admin:fr:77288")
+    );
+    let response = bragi.get("/autocomplete?q=stop_area:SA:second_station&pt_dataset[]=dataset1");
+    assert_eq!(
+        get_values(&response, "id"),
+        vec!["stop_area:SA:second_station"]
+    );
+
+    // but searched as a text if there is no such place
+    let res = bragi.get_json("/autocomplete?q=stop_area:SA:second_station::AA");
+    assert!(res["geocoding"].get("query_interpreted_as").is_none());
+
+    // and the queries with a colon are not taken for ids
+    let res = bragi.get_json("/autocomplete?q=melun: rue du port");
+    assert!(res["geocoding"].get("query_interpreted_as").is_none());
+}
+
 fn stop_area_invalid_index(bragi: &mut BragiHandler) {
     // if the index does not exists, we get a 404 with "Unable to find object" too
     // it's not trivial to get a better error than a not found object (like a 'not found dataset' error)
@@ -246,3 +272,51 @@ fn stop_area_invalid_index(bragi: &mut BragiHandler) {
         )
     );
 }
+
+fn exclude_ids_test(bragi: &mut BragiHandler) {
+    let response = bragi.get("/autocomplete?q=Parking vélo Saint-Martin&type[]=poi");
+    let ids = get_values(&response, "id");
+    assert!(ids.len() > 1);
+    let top_hit = ids[0];
+
+    // the top hit is excluded, the other places are still found
+    let response = bragi.get(&format!(
+        "/autocomplete?q=Parking vélo Saint-Martin&type[]=poi&exclude_ids[]={}",
+        top_hit
+    ));
+    let excluded_ids = get_values(&response, "id");
+    assert!(!excluded_ids.contains(&top_hit));
+    assert_eq!(excluded_ids[0], ids[1]);
+
+    // the number of excluded ids is limited
+    let too_many_ids: String = (0..101)
+        .map(|i| format!("&exclude_ids[]=poi:{}", i))
+        .collect();
+    let (status, _) = bragi.get_unchecked_json(&format!(
+        "/autocomplete?q=Parking vélo Saint-Martin{}",
+        too_many_ids
+    ));
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+fn preference_test(bragi: &mut BragiHandler) {
+    // the same request with the same preference gives the same results
+    let url = "/autocomplete?q=Parking vélo Saint-Martin&type[]=poi&preference=session_42";
+    let ids = get_values(&bragi.get(url), "id")
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>();
+    assert!(!ids.is_empty());
+    assert_eq!(get_values(&bragi.get(url), "id"), ids);
+
+    // the routing directives of ES are not accepted as preference
+    let (status, _) = bragi.get_unchecked_json(
+        "/autocomplete?q=Parking vélo Saint-Martin&preference=_only_node:unknown_node",
+    );
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+
+    // an unsafe preference is not sent to ES
+    let (status, _) = bragi
+        .get_unchecked_json("/autocomplete?q=Parking vélo Saint-Martin&preference=a%26size%3D1");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
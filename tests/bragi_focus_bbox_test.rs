@@ -0,0 +1,174 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::bragi_with_settings;
+use super::get_values;
+use super::BragiHandler;
+use cosmogony::ZoneType;
+use mimir::objects::{Admin, Coord};
+use mimir::rubber::IndexSettings;
+
+fn make_city(id: &str, name: &str, weight: f64, coord: Coord) -> Admin {
+    Admin {
+        id: id.to_string(),
+        insee: id.to_string(),
+        level: 8,
+        name: name.to_string(),
+        label: name.to_string(),
+        weight,
+        coord,
+        approx_coord: Some(coord.into()),
+        zone_type: Some(ZoneType::City),
+        ..Default::default()
+    }
+}
+
+// an important city outside of the focus bbox is still found,
+// but after a modest match inside of it
+pub fn bragi_focus_bbox_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let paris = make_city(
+        "admin:paris",
+        "Paris",
+        1.,
+        Coord::new(2.352_222, 48.856_614),
+    );
+    let paris_l_hopital = make_city(
+        "admin:paris-l-hopital",
+        "Paris-l'Hôpital",
+        0.01,
+        Coord::new(4.633_333, 46.916_667),
+    );
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    es.rubber
+        .public_index(
+            "fr",
+            &index_settings,
+            vec![paris, paris_l_hopital].into_iter(),
+        )
+        .unwrap();
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    // without focus, the most important city comes first
+    let cities = bragi.get("/autocomplete?q=Paris");
+    assert_eq!(
+        get_values(&cities, "id"),
+        vec!["admin:paris", "admin:paris-l-hopital"]
+    );
+
+    // the viewport is around Paris-l'Hôpital, Paris is not filtered
+    let cities = bragi.get("/autocomplete?q=Paris&focus_bbox=4.5,46.8,4.8,47.0");
+    assert_eq!(
+        get_values(&cities, "id"),
+        vec!["admin:paris-l-hopital", "admin:paris"]
+    );
+
+    // a viewport around Paris keeps the usual order
+    let cities = bragi.get("/autocomplete?q=Paris&focus_bbox=2.2,48.8,2.5,48.9");
+    assert_eq!(
+        get_values(&cities, "id"),
+        vec!["admin:paris", "admin:paris-l-hopital"]
+    );
+
+    // the shape is applied before the boost: the places outside of the shape are filtered
+    // even if they are in the focus bbox
+    let shape = r#"{"shape": {"type": "Feature","properties":{},"geometry":{"type":"Polygon",
+        "coordinates":[[[2.2, 48.8],[2.5, 48.8],[2.5, 48.9],[2.2, 48.9],[2.2, 48.8]]]}}}"#;
+    let cities = bragi.post(
+        "/autocomplete?q=Paris&shape_scope[]=admin&focus_bbox=4.5,46.8,4.8,47.0",
+        shape,
+    );
+    assert_eq!(get_values(&cities, "id"), vec!["admin:paris"]);
+
+    // an invalid bbox is rejected
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=Paris&focus_bbox=4.5,46.8,4.8");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+// without coordinates in the request, the results lean toward the default focus point
+// of the settings, an explicit coordinate still wins
+pub fn bragi_default_coord_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    // two cities with the same name, the one in the north being the most important
+    let north = make_city(
+        "admin:saint-martin-nord",
+        "Saint-Martin",
+        1.,
+        Coord::new(3.057_256, 50.629_250),
+    );
+    let south = make_city(
+        "admin:saint-martin-sud",
+        "Saint-Martin",
+        0.5,
+        Coord::new(5.369_780, 43.296_482),
+    );
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    es.rubber
+        .public_index("fr", &index_settings, vec![north, south].into_iter())
+        .unwrap();
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let cities = bragi.get("/autocomplete?q=Saint-Martin");
+    assert_eq!(
+        get_values(&cities, "id"),
+        vec!["admin:saint-martin-nord", "admin:saint-martin-sud"]
+    );
+
+    // the default focus point is in the south
+    let mut bragi = bragi_with_settings(
+        format!("{}/munin", es.host()),
+        "default_coord.toml",
+        "[importance_query.proximity.default_coord]\nlon = 5.4\nlat = 43.3\n",
+    );
+
+    let cities = bragi.get("/autocomplete?q=Saint-Martin");
+    assert_eq!(
+        get_values(&cities, "id"),
+        vec!["admin:saint-martin-sud", "admin:saint-martin-nord"]
+    );
+    // the default focus point is not used for the distances
+    let response = bragi.get_json("/autocomplete?q=Saint-Martin");
+    let features = response["features"].as_array().unwrap();
+    assert!(features.iter().all(|f| f.get("distance").is_none()));
+
+    // the coordinates of the request override the default focus point
+    let cities = bragi.get("/autocomplete?q=Saint-Martin&lon=3.06&lat=50.63");
+    assert_eq!(
+        get_values(&cities, "id"),
+        vec!["admin:saint-martin-nord", "admin:saint-martin-sud"]
+    );
+}
@@ -29,6 +29,7 @@
 // www.navitia.io
 
 use super::get_value;
+use super::get_values;
 use super::BragiHandler;
 use serde_json::json;
 use std::path::Path;
@@ -49,6 +50,8 @@ pub fn bragi_ntfs_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     );
 
     gare_de_lyon(&mut bragi);
+    gare_de_lyon_out_of_service(&mut bragi);
+    metro_line(&mut bragi);
 
     let ntfs2mimir = out_dir.join("../../../ntfs2mimir").display().to_string();
     crate::launch_and_assert(
@@ -66,7 +69,8 @@ pub fn bragi_ntfs_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
 
 fn gare_de_lyon(bragi: &mut BragiHandler) {
     // with this query we should find only one response, a stop
-    let response = bragi.get("/autocomplete?q=gare de lyon&_all_data=true");
+    // (the stops of the fixtures are only served in 2018)
+    let response = bragi.get("/autocomplete?q=gare de lyon&_all_data=true&date=2018-06-01");
     assert_eq!(response.len(), 1);
     let stop = response.first().unwrap();
 
@@ -172,10 +176,66 @@ fn gare_de_lyon(bragi: &mut BragiHandler) {
     );
 }
 
+fn gare_de_lyon_out_of_service(bragi: &mut BragiHandler) {
+    // the stop is not served anymore after 2018
+    let response = bragi.get("/autocomplete?q=gare de lyon&_all_data=true&date=2019-06-01");
+    assert!(get_values(&response, "id")
+        .iter()
+        .all(|id| *id != "stop_area:GDL"));
+
+    // by default, only the stops served today are returned
+    let response = bragi.get("/autocomplete?q=gare de lyon&_all_data=true");
+    assert!(get_values(&response, "id")
+        .iter()
+        .all(|id| *id != "stop_area:GDL"));
+
+    assert_eq!(
+        bragi.get_unchecked_json("/autocomplete?q=gare de lyon&_all_data=true&date=01/06/2018"),
+        (
+            actix_web::http::StatusCode::BAD_REQUEST,
+            json!({
+                "short": "validation error",
+                "long": "Invalid parameters: the 'date' parameter should be formatted as YYYY-MM-DD",
+                "code": "INVALID_PARAMS",
+                "problems": ["the 'date' parameter should be formatted as YYYY-MM-DD"],
+            })
+        )
+    );
+}
+
+fn metro_line(bragi: &mut BragiHandler) {
+    // a bare number is not searched as a line
+    let response = bragi.get("/autocomplete?q=1&_all_data=true&date=2018-06-01");
+    assert!(get_values(&response, "id")
+        .iter()
+        .all(|id| *id != "stop_area:NAT"));
+    assert!(response
+        .iter()
+        .all(|place| place.get("matched_name").is_none()));
+
+    // with a mode keyword, the stops served by the line are found
+    let response = bragi.get("/autocomplete?q=metro 1&_all_data=true&date=2018-06-01");
+    let mut ids = get_values(&response, "id");
+    ids.sort();
+    assert_eq!(
+        ids,
+        vec![
+            "stop_area:CDG",
+            "stop_area:CHA",
+            "stop_area:GDL",
+            "stop_area:NAT"
+        ]
+    );
+    assert!(response
+        .iter()
+        .all(|place| get_value(place, "matched_name") == "Metro 1"));
+}
+
 fn gare_de_lyon_with_two_datasets(bragi: &mut BragiHandler) {
     // with this query we should find only one response, a stop
-    let response =
-        bragi.get("/autocomplete?q=gare de lyon&pt_dataset[]=dataset1&pt_dataset[]=dataset2");
+    let response = bragi.get(
+        "/autocomplete?q=gare de lyon&pt_dataset[]=dataset1&pt_dataset[]=dataset2&date=2018-06-01",
+    );
     assert_eq!(response.len(), 1);
     let stop = response.first().unwrap();
 
@@ -63,6 +63,7 @@ pub fn bragi_osm_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     zip_code_street_test(&mut bragi);
     zip_code_admin_test(&mut bragi);
     bbox_admin_test(&mut bragi);
+    include_bbox_test(&mut bragi);
     city_admin_test(&mut bragi);
     administrative_region_test(&mut bragi);
 }
@@ -139,6 +140,28 @@ fn bbox_admin_test(bragi: &mut BragiHandler) {
     }
 }
 
+// with include_bbox, the features of the zones have a GeoJSON bbox
+fn include_bbox_test(bragi: &mut BragiHandler) {
+    let response = bragi.get_json("/autocomplete?q=77000 Vaux-le-Pénil&include_bbox=true");
+    let features = response["features"].as_array().unwrap();
+    let (zones, others): (Vec<_>, Vec<_>) = features
+        .iter()
+        .partition(|f| f["properties"]["geocoding"]["type"] == "zone");
+    assert!(!zones.is_empty());
+    assert!(!others.is_empty());
+    for zone in zones {
+        assert!(zone["bbox"].is_array());
+        assert_eq!(zone["bbox"], zone["properties"]["geocoding"]["bbox"]);
+    }
+    // the other places (here the streets) have no bbox
+    assert!(others.iter().all(|f| f.get("bbox").is_none()));
+
+    // the bbox is only given if requested
+    let response = bragi.get_json("/autocomplete?q=77000 Vaux-le-Pénil");
+    let features = response["features"].as_array().unwrap();
+    assert!(features.iter().all(|f| f.get("bbox").is_none()));
+}
+
 fn city_admin_test(bragi: &mut BragiHandler) {
     let all_melun = bragi.get("/autocomplete?q=Melun Rp");
     let types = get_types(&all_melun);
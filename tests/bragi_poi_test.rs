@@ -28,6 +28,7 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::bragi_with_settings;
 use super::count_types;
 use super::filter_by;
 use super::get_poi_type_ids;
@@ -35,8 +36,9 @@ use super::get_types;
 use super::get_value;
 use super::get_values;
 use super::BragiHandler;
-use mimir::{MimirObject, Poi};
+use mimir::{MimirObject, Place, Poi};
 use serde_json::json;
+use std::collections::HashMap;
 use std::path::Path;
 
 pub fn bragi_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -87,6 +89,7 @@ pub fn bragi_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     poi_from_osm_with_address_addr_test(&mut bragi);
     poi_filter_poi_type_test(&mut bragi);
     poi_filter_error_message_test(&mut bragi);
+    poi_browse_test(&mut bragi, &es_wrapper);
 }
 
 pub fn bragi_private_poi_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -411,6 +414,210 @@ pub fn test_i18n_poi(mut es: crate::ElasticSearchWrapper<'_>) {
     assert_eq!(result["label"], "Colosseo (Roma)");
 }
 
+// test the alternative names are given only when requested
+pub fn test_alt_names_poi(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord(geo::Coordinate { x: 0.0, y: 0.0 });
+    let moma = mimir::Poi {
+        id: "poi:moma".to_string(),
+        label: "Museum of Modern Art (New York)".to_string(),
+        name: "Museum of Modern Art".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        poi_type: mimir::PoiType {
+            id: "poi_type:museum".to_string(),
+            name: "museum".to_string(),
+        },
+        alt_names: vec!["MoMA".to_string()],
+        ..Default::default()
+    };
+
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 2,
+        nb_replicas: 1,
+    };
+    let _result = es
+        .rubber
+        .public_index("munin_poi", &index_settings, std::iter::once(moma));
+
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    // by default, the alternative names are not given
+    let poi = bragi.get("/autocomplete?q=Museum of Modern Art");
+    let result = poi.first().unwrap();
+    assert_eq!(result["id"], "poi:moma");
+    assert!(result.get("alt_names").is_none());
+
+    let poi = bragi.get("/autocomplete?q=Museum of Modern Art&include_alt_names=true");
+    let result = poi.first().unwrap();
+    assert_eq!(result["id"], "poi:moma");
+    assert_eq!(result["alt_names"], json!(["MoMA"]));
+
+    let poi = bragi.get("/features/poi:moma");
+    assert!(poi.first().unwrap().get("alt_names").is_none());
+
+    let poi = bragi.get("/features/poi:moma?include_alt_names=true");
+    assert_eq!(poi.first().unwrap()["alt_names"], json!(["MoMA"]));
+}
+
+// test the streets are found through their alternative names, which are given as matched
+pub fn test_alt_names_street(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord(geo::Coordinate { x: 0.0, y: 0.0 });
+    let street = mimir::Street {
+        id: "street:osm:way:1".to_string(),
+        label: "Rue Neuve (Melun)".to_string(),
+        name: "Rue Neuve".to_string(),
+        alt_names: vec!["Rue des Tanneurs".to_string()],
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    };
+
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 2,
+        nb_replicas: 1,
+    };
+    let _result = es
+        .rubber
+        .public_index("munin_street", &index_settings, std::iter::once(street));
+
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    // only the old name matches
+    let streets = bragi.get("/autocomplete?q=Rue des Tanneurs");
+    let result = streets.first().unwrap();
+    assert_eq!(result["id"], "street:osm:way:1");
+    assert_eq!(result["name"], "Rue Neuve");
+    assert_eq!(result["matched_name"], "Rue des Tanneurs");
+    assert!(result.get("alt_names").is_none());
+
+    let streets = bragi.get("/autocomplete?q=Rue des Tann&include_alt_names=true");
+    let result = streets.first().unwrap();
+    assert_eq!(result["matched_name"], "Rue des Tanneurs");
+    assert_eq!(result["alt_names"], json!(["Rue des Tanneurs"]));
+
+    // the name matches
+    let streets = bragi.get("/autocomplete?q=Rue Neuve");
+    let result = streets.first().unwrap();
+    assert_eq!(result["id"], "street:osm:way:1");
+    assert!(result.get("matched_name").is_none());
+}
+
+// test the results with the same score always come in the same order
+pub fn test_tie_breaker_poi(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord(geo::Coordinate { x: 0.0, y: 0.0 });
+    // two pois that cannot be distinguished by the query
+    let make_poi = |id: &str| mimir::Poi {
+        id: id.to_string(),
+        label: "Boulangerie (Melun)".to_string(),
+        name: "Boulangerie".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        poi_type: mimir::PoiType {
+            id: "poi_type:shop:bakery".to_string(),
+            name: "bakery".to_string(),
+        },
+        ..Default::default()
+    };
+
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 2,
+        nb_replicas: 1,
+    };
+    let _result = es.rubber.public_index(
+        "munin_poi",
+        &index_settings,
+        vec![make_poi("poi:bakery:2"), make_poi("poi:bakery:1")].into_iter(),
+    );
+
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    for _ in 0..10 {
+        let pois = bragi.get("/autocomplete?q=Boulangerie");
+        assert_eq!(
+            get_values(&pois, "id"),
+            vec!["poi:bakery:1", "poi:bakery:2"]
+        );
+
+        // the pagination is stable too
+        let first_page = bragi.get("/autocomplete?q=Boulangerie&limit=1");
+        assert_eq!(get_values(&first_page, "id"), vec!["poi:bakery:1"]);
+        let second_page = bragi.get("/autocomplete?q=Boulangerie&limit=1&offset=1");
+        assert_eq!(get_values(&second_page, "id"), vec!["poi:bakery:2"]);
+    }
+}
+
+// test the most recently updated places of a dataset with a recency boost come first
+pub fn test_recency_poi(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord(geo::Coordinate { x: 0.0, y: 0.0 });
+    let now = chrono::Utc::now().timestamp_millis();
+    let hour = 3_600_000;
+    // a duplicated poi, updated since in its source
+    let make_poi = |id: &str, updated_at: i64| mimir::Poi {
+        id: id.to_string(),
+        label: "Boulangerie (Melun)".to_string(),
+        name: "Boulangerie".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        poi_type: mimir::PoiType {
+            id: "poi_type:shop:bakery".to_string(),
+            name: "bakery".to_string(),
+        },
+        updated_at: Some(updated_at),
+        ..Default::default()
+    };
+
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 1,
+    };
+    let index = es
+        .rubber
+        .make_index::<mimir::Poi>("live", &index_settings)
+        .unwrap();
+    // both are indexed at the same time, like in a full import of the dataset
+    es.rubber
+        .bulk_index(
+            &index,
+            vec![
+                make_poi("poi:bakery:1", now - 48 * hour),
+                make_poi("poi:bakery:2", now - hour),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+    es.rubber
+        .publish_index("live", index, mimir::rubber::IndexVisibility::Public)
+        .unwrap();
+
+    es.refresh();
+
+    // without recency boost, the tie is broken by the id
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let pois = bragi.get("/autocomplete?q=Boulangerie");
+    assert_eq!(
+        get_values(&pois, "id"),
+        vec!["poi:bakery:1", "poi:bakery:2"]
+    );
+
+    // with a half life of a day, the poi updated an hour ago is boosted more
+    let mut bragi = bragi_with_settings(
+        format!("{}/munin", es.host()),
+        "recency.toml",
+        "[importance_query.recency.live]\nhalf_life = 24.0\nweight = 10.0\n",
+    );
+    let pois = bragi.get("/autocomplete?q=Boulangerie");
+    assert_eq!(
+        get_values(&pois, "id"),
+        vec!["poi:bakery:2", "poi:bakery:1"]
+    );
+}
+
 fn poi_filter_poi_type_test(bragi: &mut BragiHandler) {
     let geocodings =
         bragi.get("/autocomplete?q=77000&type[]=poi&poi_type[]=poi_type:amenity:post_office");
@@ -433,7 +640,9 @@ fn poi_filter_error_message_test(bragi: &mut BragiHandler) {
             actix_web::http::StatusCode::BAD_REQUEST,
             json!({
                 "short": "validation error",
-                "long": "Invalid parameter: poi_type[] parameter requires to have 'type[]=poi'",
+                "long": "Invalid parameters: poi_type[] parameter requires to have 'type[]=poi'",
+                "code": "INVALID_PARAMS",
+                "problems": ["poi_type[] parameter requires to have 'type[]=poi'"],
             })
         )
     );
@@ -454,3 +663,224 @@ fn poi_filter_dataset_visibility_test(bragi: &mut BragiHandler) {
     let res = bragi.get("/autocomplete?q=Agence Keolis&type[]=poi&poi_dataset[]=effia");
     assert!(res.first().is_none());
 }
+
+fn poi_browse_test(bragi: &mut BragiHandler, es_wrapper: &crate::ElasticSearchWrapper<'_>) {
+    // without query string, all the pois are browsed, the heaviest first
+    let weights: HashMap<String, f64> = es_wrapper
+        .search_and_filter("_type:poi&size=1000", |_| true)
+        .filter_map(|place| match place {
+            Place::Poi(poi) => Some((poi.id, poi.weight)),
+            _ => None,
+        })
+        .collect();
+    let pois = bragi.get("/autocomplete?type[]=poi&limit=100");
+    assert_eq!(pois.len(), weights.len().min(100));
+    let poi_weights: Vec<f64> = get_values(&pois, "id")
+        .iter()
+        .map(|id| weights[*id])
+        .collect();
+    assert!(poi_weights.windows(2).all(|w| w[0] >= w[1]));
+
+    // the pagination is done on the sorted pois
+    let page_1 = bragi.get("/autocomplete?type[]=poi&limit=2&offset=0");
+    let page_2 = bragi.get("/autocomplete?type[]=poi&limit=2&offset=2");
+    assert_eq!(
+        get_values(&page_1, "id")
+            .into_iter()
+            .chain(get_values(&page_2, "id"))
+            .collect::<Vec<_>>(),
+        get_values(&pois, "id")[..4].to_vec()
+    );
+
+    // the pois can also be filtered by type
+    let post_offices =
+        bragi.get("/autocomplete?type[]=poi&poi_type[]=poi_type:amenity:post_office");
+    assert!(!post_offices.is_empty());
+    assert!(post_offices
+        .iter()
+        .all(|p| get_poi_type_ids(p) == vec!["poi_type:amenity:post_office"]));
+
+    // but the query string can only be omitted with a filter
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?limit=10");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+// the POIs of a grid are returned as clusters, whose precision depends on the zoom
+pub fn bragi_poi_clusters_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let out_dir = Path::new(env!("OUT_DIR"));
+    // the POIs are only imported with their admins
+    let cosmogony2mimir = out_dir
+        .join("../../../cosmogony2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &cosmogony2mimir,
+        &[
+            "--input=./tests/fixtures/cosmogony.json".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+    // 3 POIs in Melun, 2 in Livry-sur-Seine and 1 in Chartrettes
+    let poi2mimir = out_dir.join("../../../poi2mimir").display().to_string();
+    crate::launch_and_assert(
+        &poi2mimir,
+        &[
+            "--input=./tests/fixtures/poi/grid.poi".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+
+    let clusters = |response: &serde_json::Value| {
+        response["clusters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cluster| cluster["count"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let response = bragi.get_json("/autocomplete?type[]=poi&cluster=true&zoom=11");
+    assert_eq!(clusters(&response), vec![3, 2, 1]);
+    // the POI alone in its cell is returned inline
+    assert!(response["clusters"][0].get("feature").is_none());
+    assert_eq!(
+        response["clusters"][2]["feature"]["properties"]["geocoding"]["id"],
+        "poi:GRID:C:1"
+    );
+    assert_eq!(response["clusters"][2]["centroid"]["type"], json!("Point"));
+    assert_eq!(response["clusters"][0]["bbox"].as_array().unwrap().len(), 4);
+
+    // without zoom, the precision comes from the width of the bbox
+    let response =
+        bragi.get_json("/autocomplete?type[]=poi&cluster=true&focus_bbox=2.6,48.45,2.75,48.6");
+    assert_eq!(clusters(&response), vec![3, 2, 1]);
+
+    // the POIs outside of the bbox (the map displayed) are not clustered
+    let response = bragi
+        .get_json("/autocomplete?type[]=poi&cluster=true&zoom=11&focus_bbox=2.6,48.52,2.75,48.6");
+    assert_eq!(clusters(&response), vec![3]);
+
+    // all the POIs are in the same cluster when zoomed out
+    let response = bragi.get_json("/autocomplete?type[]=poi&cluster=true&zoom=1");
+    assert_eq!(clusters(&response), vec![6]);
+
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=parking&cluster=true");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+/// `/nearby` returns the pois of a poi type around a coordinate, sorted by distance
+pub fn bragi_nearby_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let make_poi = |id: &str, lon: f64, poi_type: &str| {
+        let coord = mimir::Coord::new(lon, 48.85);
+        mimir::Poi {
+            id: id.to_string(),
+            label: id.to_string(),
+            name: id.to_string(),
+            coord,
+            approx_coord: Some(coord.into()),
+            poi_type: mimir::PoiType {
+                id: poi_type.to_string(),
+                name: poi_type.to_string(),
+            },
+            ..Default::default()
+        }
+    };
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 2,
+        nb_replicas: 1,
+    };
+    let _result = es.rubber.public_index(
+        "munin_poi",
+        &index_settings,
+        vec![
+            // about 220m from the request
+            make_poi("poi:cafe:far", 2.353, "poi_type:amenity:cafe"),
+            // about 2km from the request, out of the radius
+            make_poi("poi:cafe:out", 2.378, "poi_type:amenity:cafe"),
+            // about 70m from the request
+            make_poi("poi:cafe:near", 2.351, "poi_type:amenity:cafe"),
+            make_poi("poi:bakery", 2.3505, "poi_type:shop:bakery"),
+        ]
+        .into_iter(),
+    );
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let cafes = bragi.get("/nearby?lat=48.85&lon=2.35&radius=500&poi_type[]=poi_type:amenity:cafe");
+    assert_eq!(
+        get_values(&cafes, "id"),
+        vec!["poi:cafe:near", "poi:cafe:far"]
+    );
+
+    // without poi type, all the pois of the radius
+    let pois = bragi.get("/nearby?lat=48.85&lon=2.35&radius=500");
+    assert_eq!(
+        get_values(&pois, "id"),
+        vec!["poi:bakery", "poi:cafe:near", "poi:cafe:far"]
+    );
+
+    let (status, _) = bragi.raw_get("/nearby?lat=48.85&lon=2.35&radius=100000");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+/// with `--enable-refresh`, the places just indexed can be found without waiting
+/// for the refresh interval of their index
+pub fn bragi_refresh_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord::new(2.35, 48.85);
+    let make_poi = |id: &str| mimir::Poi {
+        id: id.to_string(),
+        label: "Boulangerie (Paris)".to_string(),
+        name: "Boulangerie".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    };
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    let index = es
+        .rubber
+        .make_index::<Poi>("refresh", &index_settings)
+        .unwrap();
+    let index_name = index.name().to_string();
+    es.rubber
+        .bulk_index(&index, std::iter::once(make_poi("poi:bakery:1")))
+        .unwrap();
+    es.rubber
+        .publish_index("refresh", index, mimir::rubber::IndexVisibility::Public)
+        .unwrap();
+
+    // a poi is added to the published index, it is not found until the index is refreshed
+    es.rubber
+        .bulk_index(
+            &mimir::rubber::TypedIndex::<Poi>::new(index_name),
+            std::iter::once(make_poi("poi:bakery:2")),
+        )
+        .unwrap();
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: format!("{}/munin", es.host()),
+        enable_refresh: true,
+        ..Default::default()
+    });
+    assert_eq!(
+        bragi.get_status("/features/poi:bakery:2"),
+        actix_web::http::StatusCode::NOT_FOUND
+    );
+
+    let (status, body) = bragi.raw_post("/admin/refresh", "");
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+    assert_eq!(bragi.as_json(body), json!({ "indexes": "munin_*" }));
+    let poi = bragi.get("/features/poi:bakery:2");
+    assert_eq!(get_values(&poi, "id"), vec!["poi:bakery:2"]);
+
+    // the route does not exist without `--enable-refresh`
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let (status, _) = bragi.raw_post("/admin/refresh", "");
+    assert_eq!(status, actix_web::http::StatusCode::NOT_FOUND);
+}
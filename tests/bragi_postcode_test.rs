@@ -29,6 +29,7 @@
 // www.navitia.io
 
 use super::get_value;
+use super::get_values;
 use super::BragiHandler;
 use std::path::Path;
 
@@ -88,3 +89,30 @@ pub fn bragi_postcode_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
         .iter()
         .any(|res| get_value(res, "street") == "Rue des 18 Arpents"));
 }
+
+pub fn bragi_postcode_prefix_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+    let cosmogony2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../cosmogony2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &cosmogony2mimir,
+        &[
+            "--input=./tests/fixtures/cosmogony_uk_postcodes.json".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    // A partial postcode returns all the areas of the postcodes starting with it
+    let response = bragi.get("/autocomplete?q=SW1");
+    let mut postcodes = get_values(&response, "postcode");
+    postcodes.sort();
+    assert_eq!(postcodes, vec!["SW1A", "SW1P", "SW1V"]);
+
+    // and a complete postcode returns its area first
+    let response = bragi.get("/autocomplete?q=SW1P");
+    assert_eq!(get_value(&response[0], "name"), "Westminster");
+    assert_eq!(response.len(), 1);
+}
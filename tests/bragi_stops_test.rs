@@ -29,6 +29,7 @@
 // www.navitia.io
 
 use super::get_value;
+use super::get_values;
 use super::BragiHandler;
 use serde_json::json;
 use std::path::Path;
@@ -44,6 +45,7 @@ pub fn bragi_stops_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     // - bano-three_cities
     // - stops.txt
     // - stops_dataset2.txt
+    // the addresses of the stops of stops.txt are searched within 2km
     // ******************************************
     let osm2mimir = out_dir.join("../../../osm2mimir").display().to_string();
     crate::launch_and_assert(
@@ -74,6 +76,7 @@ pub fn bragi_stops_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
         &[
             "--input=./tests/fixtures/stops.txt".into(),
             "--dataset=dataset1".into(),
+            "--address-radius=2000".into(),
             format!("--connection-string={}", es_wrapper.host()),
         ],
         &es_wrapper,
@@ -121,6 +124,12 @@ fn stop_attached_to_admin_test(bragi: &mut BragiHandler) {
         .get("administrative_regions")
         .and_then(|a| a.as_array());
     assert_eq!(admins.map(|a| a.len()).unwrap_or(0), 1);
+
+    // the closest address is about 1.9km away from the stop
+    assert_eq!(
+        stop.get("address").and_then(|a| a.pointer("/label")),
+        Some(&json!("2 Rue de la Reine Blanche (Melun)"))
+    );
 }
 
 fn stop_no_admin_test(bragi: &mut BragiHandler) {
@@ -139,6 +148,9 @@ fn stop_no_admin_test(bragi: &mut BragiHandler) {
         .get("administrative_regions")
         .and_then(|a| a.as_array());
     assert_eq!(admins.map(|a| a.len()).unwrap_or(0), 0);
+
+    // there is no address close to this stop
+    assert_eq!(stop.get("address"), None);
 }
 
 fn stop_filtered_by_dataset_test(bragi: &mut BragiHandler) {
@@ -375,4 +387,115 @@ fn distance_test(bragi: &mut BragiHandler) {
         assert_eq!(r.pointer("/features/0/distance"), None);
         assert_eq!(r.pointer("/features/1/distance"), None);
     }
+
+    // with the features wrapped in an envelope
+    {
+        let r = bragi.get_json(
+            "/autocomplete?q=14 juillet&_all_data=true&lat=48.526578&lon=2.679347&envelope=true",
+        );
+        let features = r.pointer("/features").unwrap().as_array().unwrap();
+        assert_eq!(features.len(), 2);
+
+        for feature in features {
+            assert_eq!(feature.pointer("/distance"), Some(&json!(100)));
+            assert!(feature.pointer("/score").and_then(|s| s.as_f64()).unwrap() > 0.);
+            let confidence = feature.pointer("/confidence").and_then(|c| c.as_f64());
+            assert!(confidence.unwrap() <= 1.);
+            assert_eq!(feature.pointer("/source/distance"), None);
+            assert_eq!(
+                feature.pointer("/source/properties/geocoding/type"),
+                Some(&json!("public_transport:stop_area"))
+            );
+        }
+        assert_eq!(r.pointer("/features/0/confidence"), Some(&json!(1.)));
+    }
+}
+
+/// Only the stops served at the requested date (today by default) are returned
+pub fn bragi_stop_validity_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord::new(2.372_987, 48.844_746);
+    let make_stop = |id: &str, start_date: &str, end_date: &str| mimir::Stop {
+        id: id.to_string(),
+        label: "Gare de Lyon".to_string(),
+        name: "Gare de Lyon".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        coverages: vec!["validity".to_string()],
+        validity_period: Some(mimir::ValidityPeriod {
+            start_date: start_date.to_string(),
+            end_date: end_date.to_string(),
+        }),
+        ..Default::default()
+    };
+
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 1,
+    };
+    let _result = es.rubber.public_index(
+        "validity",
+        &index_settings,
+        vec![
+            make_stop("stop_area:expired", "2018-01-01", "2018-12-31"),
+            make_stop("stop_area:current", "2000-01-01", "2999-12-31"),
+        ]
+        .into_iter(),
+    );
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+
+    let response = bragi.get("/autocomplete?q=gare de lyon&pt_dataset[]=validity");
+    assert_eq!(get_values(&response, "id"), vec!["stop_area:current"]);
+
+    let mut ids = get_values(
+        &bragi.get("/autocomplete?q=gare de lyon&pt_dataset[]=validity&date=2018-06-01"),
+        "id",
+    )
+    .into_iter()
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, vec!["stop_area:current", "stop_area:expired"]);
+}
+
+/// The words of the query are searched in any order,
+/// but the places having them in the order of the query rank at least as high
+pub fn bragi_word_order_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = mimir::Coord::new(4.859_574, 45.760_585);
+    let stop = mimir::Stop {
+        id: "stop_area:part_dieu".to_string(),
+        label: "Gare Lyon Part Dieu".to_string(),
+        name: "Gare Lyon Part Dieu".to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        coverages: vec!["word_order".to_string()],
+        ..Default::default()
+    };
+    let index_settings = mimir::rubber::IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 1,
+    };
+    es.rubber
+        .public_index("word_order", &index_settings, vec![stop].into_iter())
+        .unwrap();
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(format!("{}/munin", es.host()));
+    let mut score = |q: &str| {
+        let response = bragi.get_json(&format!(
+            "/autocomplete?q={}&pt_dataset[]=word_order&include_score=true",
+            q
+        ));
+        assert_eq!(
+            response.pointer("/features/0/properties/geocoding/id"),
+            Some(&json!("stop_area:part_dieu")),
+            "{} does not find the stop",
+            q
+        );
+        response["features"][0]["_score"].as_f64().unwrap()
+    };
+    let in_order = score("gare lyon part dieu");
+    let reordered = score("lyon part dieu gare");
+    assert!(in_order >= reordered, "{} < {}", in_order, reordered);
 }
@@ -30,6 +30,8 @@
 
 use super::get_values;
 use super::BragiHandler;
+use mimir::rubber::{IndexSettings, IndexVisibility, Rubber};
+use mimir::{Coord, Poi, PoiType};
 use std::path::Path;
 
 pub fn bragi_synonyms_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -95,3 +97,76 @@ fn synonyms_test(bragi: &mut BragiHandler) {
         .iter()
         .all(|r| r.contains("Hôtel de Ville")));
 }
+
+fn make_poi(id: &str, name: &str, label: &str) -> Poi {
+    let coord = Coord::new(2.55, 49.0);
+    Poi {
+        id: id.to_string(),
+        name: name.to_string(),
+        label: label.to_string(),
+        coord,
+        approx_coord: Some(coord.into()),
+        poi_type: PoiType {
+            id: "poi_type:transport".to_string(),
+            name: "transport".to_string(),
+        },
+        ..Default::default()
+    }
+}
+
+/// Synonyms read from the synonym files of each document type and dataset
+pub fn bragi_index_synonyms_test(es: crate::ElasticSearchWrapper<'_>) {
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 1,
+    };
+    let mut rubber = Rubber::new(&es.host()).with_synonyms_dir("./tests/fixtures/synonyms".into());
+    rubber.initialize_templates().unwrap();
+
+    // the french pois use poi_fr.txt, the others poi.txt
+    let airport = make_poi(
+        "poi:cdg",
+        "Aéroport Charles de Gaulle",
+        "Aéroport Charles de Gaulle (Roissy-en-France)",
+    );
+    rubber
+        .public_index("fr", &index_settings, std::iter::once(airport))
+        .unwrap();
+    let times_square = make_poi(
+        "poi:times_square",
+        "Times Square",
+        "Times Square (New York)",
+    );
+    rubber
+        .public_index("us", &index_settings, std::iter::once(times_square))
+        .unwrap();
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(es.host());
+    let response = bragi.get("/autocomplete?q=CDG");
+    assert_eq!(get_values(&response, "id").first(), Some(&"poi:cdg"));
+
+    let response = bragi.get("/autocomplete?q=Times Square NYC");
+    assert_eq!(
+        get_values(&response, "id").first(),
+        Some(&"poi:times_square")
+    );
+
+    // an index is not published if its synonym file has changed since its creation
+    let synonyms_dir = Path::new(env!("OUT_DIR")).join("synonyms");
+    std::fs::create_dir_all(&synonyms_dir).unwrap();
+    std::fs::write(synonyms_dir.join("poi.txt"), "nyc,new york\n").unwrap();
+    let mut rubber = Rubber::new(&es.host()).with_synonyms_dir(synonyms_dir.clone());
+    let index = rubber.make_index::<Poi>("us", &index_settings).unwrap();
+    std::fs::write(synonyms_dir.join("poi.txt"), "nyc,new york city\n").unwrap();
+    assert!(rubber
+        .publish_index("us", index, IndexVisibility::Public)
+        .is_err());
+
+    // the published index is still the old one
+    let response = bragi.get("/autocomplete?q=Times Square NYC");
+    assert_eq!(
+        get_values(&response, "id").first(),
+        Some(&"poi:times_square")
+    );
+}
@@ -28,11 +28,13 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::bragi_with_settings;
 use super::count_types;
 use super::get_types;
 use super::get_value;
 use super::get_values;
 use super::BragiHandler;
+use serde_json::json;
 use std::path::Path;
 
 pub fn bragi_three_cities_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
@@ -83,6 +85,8 @@ pub fn bragi_three_cities_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     three_cities_zip_code_test(&mut bragi);
     three_cities_zip_code_address_test(&mut bragi);
     three_cities_shape_test(&mut bragi);
+    three_cities_geometry_test(&mut bragi);
+    three_cities_raw_geometry_test(&mut bragi, es_wrapper.host());
 }
 
 fn three_cities_housenumber_zip_code_test(bragi: &mut BragiHandler) {
@@ -260,4 +264,78 @@ fn three_cities_shape_test(bragi: &mut BragiHandler) {
         get_values(&geocodings, "label"),
         vec!["Rue du Four à Chaux (Livry-sur-Seine)"]
     );
+
+    // without a query string, the places of the shape are browsed
+    let geocodings = bragi.post("/autocomplete?q=&type[]=street", shape);
+    let labels = get_values(&geocodings, "label");
+    assert!(labels.contains(&"Rue du Port (Melun)"));
+    assert!(!labels.contains(&"Rue du Four à Chaux (Livry-sur-Seine)"));
+
+    // but an empty query string is rejected without any filter
+    let (status, _) = bragi.get_unchecked_json("/autocomplete?q=   ");
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+fn three_cities_geometry_test(bragi: &mut BragiHandler) {
+    // the geometries are heavy, they are not given by default
+    let (_, without_geometry) = bragi.raw_get("/autocomplete?q=Melun");
+    let res = bragi.get_json("/autocomplete?q=Melun");
+    let features = res["features"].as_array().unwrap();
+    assert!(!features.is_empty());
+    assert!(features.iter().all(|f| f.get("context").is_none()));
+
+    let (_, with_geometry) = bragi.raw_get("/autocomplete?q=Melun&geometry=true");
+    assert!(with_geometry.len() > without_geometry.len());
+    let res = bragi.get_json("/autocomplete?q=Melun&type[]=city&geometry=true");
+    let melun = &res["features"][0];
+    assert_eq!(melun["properties"]["geocoding"]["name"], json!("Melun"));
+    assert_eq!(melun["context"]["geometry"]["type"], json!("MultiPolygon"));
+    // the point of the feature is still given
+    assert_eq!(melun["geometry"]["type"], json!("Point"));
+
+    let res = bragi.get_json("/autocomplete?q=Rue du Port&type[]=street&geometry=true");
+    assert_eq!(
+        res["features"][0]["context"]["geometry"]["type"],
+        json!("LineString")
+    );
+    let res =
+        bragi.get_json("/autocomplete?q=2 Rue de la Reine Blanche&type[]=house&geometry=true");
+    assert_eq!(
+        res["features"][0]["context"]["geometry"]["type"],
+        json!("Point")
+    );
+
+    // and the limit is lower with the geometries
+    let res = bragi.get("/autocomplete?q=Rue&limit=100&geometry=true");
+    assert!(res.len() <= 10);
+}
+
+fn three_cities_raw_geometry_test(bragi: &mut BragiHandler, es_host: String) {
+    let nb_points = |res: &serde_json::Value| {
+        res["features"][0]["context"]["geometry"]["coordinates"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|polygon| polygon.as_array().unwrap())
+            .map(|ring| ring.as_array().unwrap().len())
+            .sum::<usize>()
+    };
+    let simplified = bragi.get_json("/autocomplete?q=Melun&type[]=city&geometry=true");
+
+    // without tolerance, the boundaries are given as stored
+    let mut bragi = bragi_with_settings(
+        es_host,
+        "raw_geometry.toml",
+        "[query]\ngeometry_tolerance = 0.0\n",
+    );
+    let raw = bragi.get_json("/autocomplete?q=Melun&type[]=city&geometry=true");
+    assert_eq!(
+        raw["features"][0]["properties"]["geocoding"]["name"],
+        json!("Melun")
+    );
+    assert_eq!(
+        raw["features"][0]["context"]["geometry"]["type"],
+        json!("MultiPolygon")
+    );
+    assert!(nb_points(&raw) > nb_points(&simplified));
 }
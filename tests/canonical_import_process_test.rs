@@ -30,6 +30,7 @@
 
 #![allow(clippy::cognitive_complexity)]
 
+use super::bragi_with_settings;
 use super::count_types;
 use super::get_poi_type_ids;
 use super::get_value;
@@ -37,6 +38,8 @@ use super::get_values;
 use super::BragiHandler;
 use serde_json::{self, json};
 use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
 
 /// Test the whole mimirsbrunn pipeline with all the import binary
 /// and test thourgh bragi in the end
@@ -88,14 +91,20 @@ pub fn canonical_import_process_test(es_wrapper: crate::ElasticSearchWrapper<'_>
 
     melun_test(&mut bragi);
     lang_test(&mut bragi);
+    accept_language_test(&es_wrapper);
     invalid_parameter_autocomplete_test(&mut bragi);
     wrong_shape_test(&mut bragi);
+    too_large_shape_test(&mut bragi);
+    too_complex_shape_test(&mut bragi);
     invalid_type_test(&mut bragi);
     invalid_route_test(&mut bragi);
     invalid_coord_test(&mut bragi);
     valid_timeout_test(&mut bragi);
     filter_zone_type_test(&mut bragi);
     zone_filter_error_message_test(&mut bragi);
+    within_admin_test(&mut bragi);
+    zone_types_blacklist_test(&mut bragi);
+    admin_demographics_test(&mut bragi);
 }
 
 fn melun_test(bragi: &mut BragiHandler) {
@@ -269,6 +278,190 @@ pub fn bragi_invalid_es_test(_es_wrapper: crate::ElasticSearchWrapper<'_>) {
     );
 }
 
+pub fn bragi_circuit_breaker_test(_es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: "http://invalid_es_url/munin".to_string(),
+        circuit_breaker_max_failures: Some(2),
+        circuit_breaker_cooldown: Some(60_000),
+        ..Default::default()
+    });
+
+    // the first failures are ES errors
+    for _ in 0..2 {
+        let (status, headers, _) = bragi.raw_get_with_headers("/autocomplete?q=toto", &[]);
+        assert_eq!(status, actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(headers.get("Retry-After").is_none());
+    }
+
+    // then the circuit breaker is open, and the requests are rejected without querying ES
+    for query in &[
+        "/autocomplete?q=toto",
+        "/features/toto",
+        "/reverse?lon=2.3&lat=48.8",
+    ] {
+        let (status, headers, body) = bragi.raw_get_with_headers(query, &[]);
+        assert_eq!(status, actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after: u64 = headers
+            .get("Retry-After")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .expect("no Retry-After header");
+        assert!(retry_after > 0 && retry_after <= 60);
+        assert_eq!(
+            bragi.as_json(body),
+            json!({
+                "short": "query error",
+                "long": "service unavailable",
+                "code": "ES_CIRCUIT_OPEN",
+            })
+        );
+    }
+
+    // the state of the circuit breaker of each type of index is given in the status
+    let status = bragi.get_json("/status");
+    assert_eq!(status["circuit_breakers"]["addr"], json!("open"));
+    assert_eq!(status["circuit_breakers"]["stop"], json!("open"));
+}
+
+// bragi with the default settings, but only the addresses as required types
+fn bragi_requiring_addr(
+    es_wrapper: &crate::ElasticSearchWrapper<'_>,
+    name: &str,
+    extra_settings: &str,
+) -> BragiHandler {
+    bragi_with_settings(
+        es_wrapper.host(),
+        &format!("{}.toml", name),
+        &format!("[service]\nrequired_types = [\"addr\"]\n{}", extra_settings),
+    )
+}
+
+// bragi with the default settings and a canary query
+fn bragi_with_canary(
+    es_wrapper: &crate::ElasticSearchWrapper<'_>,
+    expected_id: &str,
+) -> BragiHandler {
+    bragi_requiring_addr(
+        es_wrapper,
+        &format!("canary_{}", expected_id.replace(':', "_")),
+        &format!(
+            "[readiness.canary]\nquery = \"hector malot\"\nexpected_id = \"{}\"\n",
+            expected_id
+        ),
+    )
+}
+
+/// The readiness check fails if the canary query does not find the expected document
+pub fn bragi_readiness_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    // only the addresses are imported: with the default required types, bragi is degraded
+    let mut bragi = BragiHandler::new(es_wrapper.host());
+    let (status, readiness) = bragi.get_unchecked_json("/ready");
+    assert_eq!(status, actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        readiness["reason"],
+        json!("no index for the required types: admin, street")
+    );
+    // but the status lists the types without failing
+    let status = bragi.get_json("/status");
+    assert_eq!(status["status"], json!("good"));
+    assert_eq!(
+        status["types"]["addr"],
+        json!({ "state": "present", "required": true })
+    );
+    assert_eq!(
+        status["types"]["stop"],
+        json!({ "state": "absent", "required": false })
+    );
+
+    // without canary, bragi is ready as long as the circuit breaker is closed
+    // and the required types are found
+    let mut bragi = bragi_requiring_addr(&es_wrapper, "addr_required", "");
+    assert_eq!(
+        bragi.get_unchecked_json("/ready"),
+        (
+            actix_web::http::StatusCode::OK,
+            json!({ "status": "ready" })
+        )
+    );
+    let features = bragi.get("/autocomplete?q=hector malot");
+    let expected_id = get_value(features.first().unwrap(), "id").to_string();
+
+    let mut bragi = bragi_with_canary(&es_wrapper, &expected_id);
+    assert_eq!(
+        bragi.get_unchecked_json("/ready"),
+        (
+            actix_web::http::StatusCode::OK,
+            json!({ "status": "ready" })
+        )
+    );
+
+    // the canary document is missing: bragi is degraded
+    let mut bragi = bragi_with_canary(&es_wrapper, "addr:missing");
+    let (status, readiness) = bragi.get_unchecked_json("/ready");
+    assert_eq!(status, actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(readiness["status"], json!("degraded"));
+    assert_eq!(
+        readiness["reason"],
+        json!("addr:missing not found by the canary query 'hector malot'")
+    );
+}
+
+// without `lang`, the language of the labels is chosen from the `Accept-Language` header
+fn accept_language_test(es_wrapper: &crate::ElasticSearchWrapper<'_>) {
+    let config_dir = Path::new(env!("OUT_DIR")).join("accept_language_config");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("default.toml"),
+        include_str!("../config/bragi-settings.toml"),
+    )
+    .unwrap();
+    std::fs::write(
+        config_dir.join("langs.toml"),
+        "[query]\nsupported_langs = [\"fr\", \"es\"]\n",
+    )
+    .unwrap();
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: es_wrapper.host(),
+        config_dir: Some(config_dir),
+        run_mode: Some("langs".to_string()),
+        ..Default::default()
+    });
+    let mut autocomplete = |query: &str, accept_language: &str| {
+        let (status, _, body) =
+            bragi.raw_get_with_headers(query, &[("Accept-Language", accept_language)]);
+        assert!(status.is_success(), "invalid status: {}", status);
+        bragi.as_json(body)
+    };
+
+    let response = autocomplete("/autocomplete?q=Francia", "es-ES, en;q=0.8");
+    assert_eq!(response["geocoding"]["lang"], json!("es"));
+    assert_eq!(
+        response["features"][0]["properties"]["geocoding"]["label"],
+        json!("Francia")
+    );
+
+    // the languages not supported fall back to the default labels
+    let response = autocomplete("/autocomplete?q=Melun", "de");
+    assert_eq!(response["geocoding"].get("lang"), None);
+
+    // the parameter wins over the header
+    let response = autocomplete("/autocomplete?q=Melun&lang=fr", "es");
+    assert_eq!(response["geocoding"]["lang"], json!("fr"));
+}
+
 fn invalid_parameter_autocomplete_test(bragi: &mut BragiHandler) {
     // if a param is not correct, we should have a nice error
     // this error could be better, but that will do for the moment
@@ -329,13 +522,76 @@ fn wrong_shape_test(bragi: &mut BragiHandler) {
     );
 }
 
+// a closed polygon of `nb_vertices` vertices around Melun
+fn make_circle_shape(nb_vertices: usize) -> String {
+    let mut coords: Vec<_> = (0..nb_vertices - 1)
+        .map(|i| {
+            let angle = 2. * std::f64::consts::PI * i as f64 / (nb_vertices - 1) as f64;
+            format!(
+                "[{}, {}]",
+                2.65 + 0.1 * angle.cos(),
+                48.54 + 0.1 * angle.sin()
+            )
+        })
+        .collect();
+    coords.push(coords[0].clone());
+    format!(
+        r#"{{"shape":{{"type":"Feature","properties":{{}},"geometry":{{"type":"Polygon","coordinates":[[{}]]}}}}}}"#,
+        coords.join(",")
+    )
+}
+
+fn too_large_shape_test(bragi: &mut BragiHandler) {
+    // a 100k vertices shape is bigger than the default limit of 1MiB,
+    // it should be rejected before being parsed
+    let shape = make_circle_shape(100_000);
+    let start = Instant::now();
+    let (status, r) = bragi.raw_post("/autocomplete?q=Melun", &shape);
+
+    assert_eq!(status, actix_web::http::StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(
+        bragi.as_json(r),
+        json!({
+            "short": "payload too large",
+            "long": "payload too large: the body should not exceed 1048576 bytes",
+        })
+    );
+
+    // bragi is still able to answer the small queries
+    let res = bragi.get("/autocomplete?q=Melun");
+    assert!(!res.is_empty());
+}
+
+fn too_complex_shape_test(bragi: &mut BragiHandler) {
+    // the shape is small enough to be parsed, but has too many vertices
+    let shape = make_circle_shape(20_000);
+    let (status, r) = bragi.raw_post("/autocomplete?q=Melun", &shape);
+
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        bragi.as_json(r),
+        json!({
+            "short": "shape too complex",
+            "long": "shape too complex: 20000 vertices, the maximum is 10000",
+        })
+    );
+
+    // a reasonable shape is still accepted
+    let shape = make_circle_shape(100);
+    let (status, _) = bragi.raw_post("/autocomplete?q=Melun", &shape);
+    assert!(status.is_success(), "invalid status: {}", status);
+}
+
 fn invalid_coord_test(bragi: &mut BragiHandler) {
     assert_eq!(
         bragi.get_unchecked_json("/autocomplete?q=a&lat=12"),
         (
             actix_web::http::StatusCode::BAD_REQUEST,
             json!({
-                "long": "Invalid parameter: you should provide a 'lon' AND a 'lat' parameter if you provide one of them",
+                "long": "Invalid parameters: you should provide a 'lon' AND a 'lat' parameter if you provide one of them",
+                "code": "INVALID_PARAMS",
+                "problems": ["you should provide a 'lon' AND a 'lat' parameter if you provide one of them"],
                 "short": "validation error"
             })
         )
@@ -358,7 +614,9 @@ fn invalid_coord_test(bragi: &mut BragiHandler) {
         (
             actix_web::http::StatusCode::BAD_REQUEST,
             json!({
-                "long": "Invalid parameter: lon is not a valid longitude",
+                "long": "Invalid parameters: lon is not a valid longitude",
+                "code": "INVALID_PARAMS",
+                "problems": ["lon is not a valid longitude"],
                 "short": "validation error"
             })
         )
@@ -368,11 +626,26 @@ fn invalid_coord_test(bragi: &mut BragiHandler) {
         (
             actix_web::http::StatusCode::BAD_REQUEST,
             json!({
-                "long": "Invalid parameter: lat is not a valid latitude",
+                "long": "Invalid parameters: lat is not a valid latitude",
+                "code": "INVALID_PARAMS",
+                "problems": ["lat is not a valid latitude"],
                 "short": "validation error"
             })
         )
     );
+    // all the invalid parameters are reported at once
+    let (status, body) = bragi.get_unchecked_json(
+        "/autocomplete?q=a&lat=-1000&lon=-12&date=01/02/2020&experiment=unknown",
+    );
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["problems"],
+        json!([
+            "the 'experiment' parameter is not an experiment of the settings",
+            "the 'date' parameter should be formatted as YYYY-MM-DD",
+            "lat is not a valid latitude",
+        ])
+    );
 }
 
 // we just check that the timeout is correctly parser
@@ -405,8 +678,183 @@ fn zone_filter_error_message_test(bragi: &mut BragiHandler) {
             actix_web::http::StatusCode::BAD_REQUEST,
             json!({
                 "short": "validation error",
-                "long": "Invalid parameter: zone_type[] parameter requires to have 'type[]=zone'",
+                "long": "Invalid parameters: zone_type[] parameter requires to have 'type[]=zone'",
+                "code": "INVALID_PARAMS",
+                "problems": ["zone_type[] parameter requires to have 'type[]=zone'"],
             })
         )
     );
 }
+
+fn within_admin_test(bragi: &mut BragiHandler) {
+    let melun_id = "admin:osm:relation:80071";
+    let all_cities = bragi.get("/autocomplete?q=Rue du Port");
+    assert!(get_values(&all_cities, "city").contains(&"Chartrettes"));
+
+    // the places of the neighboring cities are not searched
+    let in_melun = bragi.get(&format!("/autocomplete?q=Rue du Port&within={}", melun_id));
+    assert!(get_values(&in_melun, "city").iter().all(|c| *c == "Melun"));
+
+    // the places inside an admin can be browsed without query string
+    let pois = bragi.get(&format!("/autocomplete?type[]=poi&within={}", melun_id));
+    assert!(!pois.is_empty());
+    assert!(get_values(&pois, "city").iter().all(|c| *c == "Melun"));
+
+    // the admins below an admin are inside it
+    let in_france =
+        bragi.get("/autocomplete?q=Melun&type[]=zone&within=admin:osm:relation:424256272");
+    assert_eq!(get_values(&in_france, "id"), vec![melun_id]);
+
+    // nothing is inside an unknown admin
+    assert!(bragi
+        .get("/autocomplete?q=Rue&within=admin:osm:relation:0")
+        .is_empty());
+}
+
+fn zone_types_blacklist_test(bragi: &mut BragiHandler) {
+    let melun = bragi.get("/autocomplete?q=Melun");
+    assert_eq!(melun[0]["id"], "admin:osm:relation:80071");
+
+    // the cities are excluded, not the places inside them
+    let melun = bragi.get("/autocomplete?q=Melun&zone_types_blacklist[]=city");
+    assert!(!get_values(&melun, "id").contains(&"admin:osm:relation:80071"));
+    assert_eq!(count_types(&get_values(&melun, "zone_type"), "city"), 0);
+    let rue_du_port = bragi.get("/autocomplete?q=Rue du Port&zone_types_blacklist[]=city");
+    assert!(get_values(&rue_du_port, "city").contains(&"Melun"));
+
+    // a zone type cannot be both requested and excluded
+    let (status, _) = bragi.get_unchecked_json(
+        "/autocomplete?q=Melun&type[]=zone&zone_type[]=city&zone_types_blacklist[]=city",
+    );
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+}
+
+fn admin_demographics_test(bragi: &mut BragiHandler) {
+    // the population and the area of the zones are only given on request
+    let melun = bragi.get("/autocomplete?q=Melun&type[]=zone");
+    assert_eq!(melun[0]["id"], "admin:osm:relation:80071");
+    assert_eq!(melun[0].get("population"), None);
+    assert_eq!(melun[0].get("area"), None);
+
+    let zones = bragi.get("/autocomplete?q=Melun&type[]=zone&include_demographics=true");
+    let melun = &zones[0];
+    // the population of the admin centre of Melun
+    assert_eq!(melun["population"], 39589);
+    let area = melun["area"].as_f64().unwrap();
+    assert!((area - 8.).abs() < 0.1, "area of Melun: {}", area);
+
+    // the zones without population have none, but they have an area
+    let seine_et_marne = "/features/admin:osm:relation:424253843?include_demographics=true";
+    let features = bragi.get(seine_et_marne);
+    assert_eq!(features.len(), 1);
+    assert_eq!(features[0].get("population"), None);
+    assert!(features[0]["area"].as_f64().unwrap() > 200.);
+}
+
+/// The settings of a run mode are merged into the default ones
+pub fn bragi_run_mode_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let config_dir = Path::new(env!("OUT_DIR")).join("run_mode_config");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("default.toml"),
+        include_str!("../config/bragi-settings.toml"),
+    )
+    .unwrap();
+    std::fs::write(
+        config_dir.join("staging.toml"),
+        "[elasticsearch.circuit_breaker]\nmax_failures = 1\n",
+    )
+    .unwrap();
+
+    let bragi_bin = Path::new(env!("OUT_DIR"))
+        .join("../../../bragi")
+        .display()
+        .to_string();
+    let run_config = |run_mode: &str| {
+        Command::new(&bragi_bin)
+            .args(&[
+                format!("--config-dir={}", config_dir.display()),
+                format!("--run-mode={}", run_mode),
+                "config".into(),
+            ])
+            .output()
+            .unwrap()
+    };
+
+    let output = run_config("staging");
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.starts_with("# run mode: staging\n"));
+    let settings: toml::Value = toml::from_str(&output).unwrap();
+    let circuit_breaker = &settings["elasticsearch"]["circuit_breaker"];
+    // overridden by the overlay
+    assert_eq!(circuit_breaker["max_failures"].as_integer(), Some(1));
+    // inherited from the default settings
+    assert_eq!(circuit_breaker["cooldown"].as_integer(), Some(10_000));
+
+    // the overlay of a run mode is required
+    assert!(!run_config("prod").status.success());
+
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        connection_string: es_wrapper.host(),
+        config_dir: Some(config_dir.clone()),
+        run_mode: Some("staging".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(bragi.get_json("/status")["run_mode"], json!("staging"));
+}
+
+// with --expose-settings, /settings returns the effective settings without their secrets
+pub fn bragi_settings_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let config_dir = Path::new(env!("OUT_DIR")).join("settings_config");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("default.toml"),
+        include_str!("../config/bragi-settings.toml"),
+    )
+    .unwrap();
+    std::fs::write(
+        config_dir.join("staging.toml"),
+        "[elasticsearch]\npassword = \"hunter2\"\n[elasticsearch.circuit_breaker]\nmax_failures = 1\n",
+    )
+    .unwrap();
+    let args = bragi::Args {
+        connection_string: es_wrapper.host().replacen("://", "://elastic:hunter2@", 1),
+        config_dir: Some(config_dir),
+        run_mode: Some("staging".to_string()),
+        ..Default::default()
+    };
+
+    // the settings are not exposed by default
+    let mut bragi = BragiHandler::new_with_args(args.clone());
+    let (status, _) = bragi.get_unchecked_json("/settings");
+    assert_eq!(status, actix_web::http::StatusCode::NOT_FOUND);
+    let hidden_settings_status = bragi.get_json("/status");
+
+    let mut bragi = BragiHandler::new_with_args(bragi::Args {
+        expose_settings: true,
+        ..args
+    });
+    let settings = bragi.get_json("/settings");
+    assert_eq!(settings["run_mode"], json!("staging"));
+    assert_eq!(
+        settings["connection_string"],
+        json!(es_wrapper
+            .host()
+            .replacen("://", "://elastic:<redacted>@", 1))
+    );
+    let elasticsearch = &settings["settings"]["elasticsearch"];
+    assert_eq!(elasticsearch["password"], json!("<redacted>"));
+    assert_eq!(elasticsearch["circuit_breaker"]["max_failures"], json!(1));
+    assert!(!settings.to_string().contains("hunter2"));
+
+    // the build and the settings of the running bragi are identified, without any secret
+    let status = bragi.get_json("/status");
+    assert!(!status["build"]["commit"].as_str().unwrap().is_empty());
+    assert!(!status["settings_hash"].as_str().unwrap().is_empty());
+    assert_eq!(
+        status["settings_hash"],
+        hidden_settings_status["settings_hash"]
+    );
+    assert!(!status.to_string().contains("hunter2"));
+}
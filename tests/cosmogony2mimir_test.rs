@@ -185,3 +185,38 @@ pub fn cosmogony2mimir_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
         _ => panic!("should be an admin"),
     }
 }
+
+/// import a cosmogony file with weights for some zone types
+pub fn cosmogony2mimir_zone_type_weight_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let cosmogony2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../cosmogony2mimir")
+        .display()
+        .to_string();
+    crate::launch_and_assert(
+        &cosmogony2mimir,
+        &[
+            "--input=./tests/fixtures/cosmogony.json".into(),
+            "--zone-type-weight=city=2".into(),
+            "--zone-type-weight=country=0.5".into(),
+            format!("--connection-string={}", es_wrapper.host()),
+        ],
+        &es_wrapper,
+    );
+
+    let admins: Vec<_> = es_wrapper
+        .search_and_filter("label:*", |_| true)
+        .filter_map(|p| match p {
+            mimir::Place::Admin(admin) => Some(admin),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(admins.len(), 7);
+    for admin in admins {
+        let expected_weight = match admin.zone_type {
+            Some(ZoneType::City) => Some(2.),
+            Some(ZoneType::Country) => Some(0.5),
+            _ => None,
+        };
+        assert_eq!(admin.zone_type_weight, expected_weight, "{}", admin.name);
+    }
+}
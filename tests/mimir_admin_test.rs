@@ -0,0 +1,405 @@
+// Copyright © 2020, Canal TP and/or its affiliates. All rights reserved.
+//
+// This file is part of Navitia,
+//     the software to build cool stuff with public transport.
+//
+// Hope you'll enjoy and contribute to this project,
+//     powered by Canal TP (www.canaltp.fr).
+// Help us simplify mobility and open public transport:
+//     a non ending quest to the responsive locomotion way of traveling!
+//
+// LICENCE: This program is free software; you can redistribute it
+// and/or modify it under the terms of the GNU Affero General Public
+// License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public
+// License along with this program. If not, see
+// <http://www.gnu.org/licenses/>.
+//
+// Stay tuned using
+// twitter @navitia
+// IRC #navitia on freenode
+// https://groups.google.com/d/forum/navitia
+// www.navitia.io
+
+use super::BragiHandler;
+use mimir::rubber::{IndexSettings, ScrollOptions, TypedIndex};
+use mimir::{Admin, Coord, Street};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::process::Command;
+
+fn index_streets(es: &mut crate::ElasticSearchWrapper<'_>, index: &str, streets: Vec<Street>) {
+    let index_settings = IndexSettings {
+        nb_shards: 2,
+        nb_replicas: 1,
+    };
+    es.rubber.create_index(index, &index_settings).unwrap();
+    let nb = es
+        .rubber
+        .bulk_index(&TypedIndex::new(index.to_string()), streets.into_iter())
+        .unwrap();
+    assert!(nb > 0);
+    es.refresh();
+}
+
+fn read_ndjson(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect()
+}
+
+/// Compare two generations of a street index with `mimir_admin diff`
+pub fn mimir_admin_diff_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = Coord::new(2.376_379, 48.846_495);
+    let make_street = |id: &str, label: &str| Street {
+        id: id.to_string(),
+        name: label.to_string(),
+        label: label.to_string(),
+        weight: 0.42,
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    };
+
+    index_streets(
+        &mut es,
+        "munin_street_diff_a",
+        vec![
+            make_street("street:1", "rue de Bercy"),
+            make_street("street:2", "rue de Charenton"),
+            make_street("street:3", "rue de Lyon"),
+            make_street("street:4", "quai de la Rapée"),
+        ],
+    );
+    index_streets(
+        &mut es,
+        "munin_street_diff_b",
+        vec![
+            make_street("street:1", "rue de Bercy"),
+            // the label has changed
+            make_street("street:2", "rue de Charenton (Paris)"),
+            // street:3 has been removed, street:4 has a new id
+            Street {
+                old_ids: vec!["street:4".to_string()],
+                flags: Default::default(),
+                ..make_street("street:5", "quai de la Rapée")
+            },
+            make_street("street:6", "avenue Daumesnil"),
+        ],
+    );
+
+    let out_dir = Path::new(env!("OUT_DIR"));
+    let mimir_admin = out_dir.join("../../../mimir_admin").display().to_string();
+    let diff_dir = out_dir.join("mimir_admin_diff");
+    crate::launch_and_assert(
+        &mimir_admin,
+        &[
+            format!("--connection-string={}", es.host()),
+            "diff".into(),
+            "munin_street_diff_a".into(),
+            "munin_street_diff_b".into(),
+            format!("--output-dir={}", diff_dir.display()),
+            "--max-added=1".into(),
+            "--max-removed=1".into(),
+        ],
+        &es,
+    );
+
+    let added = read_ndjson(&diff_dir.join("added.ndjson"));
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0]["id"], json!("street:6"));
+
+    let removed = read_ndjson(&diff_dir.join("removed.ndjson"));
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0]["id"], json!("street:3"));
+
+    // the renamed street has not changed, so only street:2 is modified
+    let modified = read_ndjson(&diff_dir.join("modified.ndjson"));
+    assert_eq!(
+        modified,
+        vec![json!({
+            "id": "street:2",
+            "changes": {
+                "name": {"old": "rue de Charenton", "new": "rue de Charenton (Paris)"},
+                "label": {"old": "rue de Charenton", "new": "rue de Charenton (Paris)"},
+            }
+        })]
+    );
+
+    // the command fails when the changes exceed the thresholds
+    let status = Command::new(&mimir_admin)
+        .args(&[
+            format!("--connection-string={}", es.host()),
+            "diff".into(),
+            "munin_street_diff_a".into(),
+            "munin_street_diff_b".into(),
+            "--max-modified=0".into(),
+        ])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+fn ids_and_labels(features: &[Map<String, Value>]) -> Vec<(String, String)> {
+    features
+        .iter()
+        .map(|f| {
+            (
+                crate::get_value(f, "id").to_string(),
+                crate::get_value(f, "label").to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Import addresses with `bano2mimir --dry-run`, load them with `mimir_admin mimirload`,
+/// and check that bragi returns the same results as with a direct import
+pub fn mimir_admin_mimirload_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let out_dir = Path::new(env!("OUT_DIR"));
+    let bano2mimir = out_dir.join("../../../bano2mimir").display().to_string();
+    let mimir_admin = out_dir.join("../../../mimir_admin").display().to_string();
+    let query = "/autocomplete?q=hector malot";
+
+    // direct import in ES
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es.host()),
+        ],
+        &es,
+    );
+    let mut bragi = BragiHandler::new(es.host());
+    let expected = ids_and_labels(&bragi.get(query));
+    assert!(!expected.is_empty());
+
+    es.init();
+
+    // the dry run does not write anything in ES
+    let dry_run_dir = out_dir.join("mimirload");
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es.host()),
+            "--dry-run".into(),
+            format!("--out={}", dry_run_dir.display()),
+        ],
+        &es,
+    );
+    assert!(es.rubber.get_all_aliased_index("munin").unwrap().is_empty());
+    assert!(dry_run_dir.join("munin_addr_fr.ndjson").exists());
+    assert!(dry_run_dir.join("munin_addr_fr.meta.json").exists());
+
+    crate::launch_and_assert(
+        &mimir_admin,
+        &[
+            format!("--connection-string={}", es.host()),
+            "mimirload".into(),
+            dry_run_dir.display().to_string(),
+        ],
+        &es,
+    );
+
+    let mut bragi = BragiHandler::new(es.host());
+    assert_eq!(ids_and_labels(&bragi.get(query)), expected);
+}
+
+// launch `mimir_admin validate` on `index`, and return its success and its logs
+fn validate(es: &crate::ElasticSearchWrapper<'_>, index: &str, args: &[&str]) -> (bool, String) {
+    let out_dir = Path::new(env!("OUT_DIR"));
+    let mimir_admin = out_dir.join("../../../mimir_admin");
+    let output = Command::new(&mimir_admin)
+        .arg(format!("--connection-string={}", es.host()))
+        .arg("validate")
+        .arg(index)
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// Validate sane and broken indexes with `mimir_admin validate`,
+/// and the publication of the imported indexes with `--validate`
+pub fn mimir_admin_validate_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = Coord::new(2.376_379, 48.846_495);
+    let make_street = |id: &str, label: &str| Street {
+        id: id.to_string(),
+        name: label.to_string(),
+        label: label.to_string(),
+        weight: 0.42,
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    };
+    let streets = || {
+        vec![
+            make_street("street:1", "rue de Bercy"),
+            make_street("street:2", "rue de Charenton"),
+            make_street("street:3", "rue de Lyon"),
+            make_street("street:4", "quai de la Rapée"),
+        ]
+    };
+
+    index_streets(&mut es, "munin_street_validate_ok", streets());
+    let (success, logs) = validate(&es, "munin_street_validate_ok", &[]);
+    assert!(success, "{}", logs);
+
+    // too few documents
+    let (success, logs) = validate(&es, "munin_street_validate_ok", &["--min-documents=10"]);
+    assert!(!success);
+    assert!(logs.contains("4 documents (min 10)"), "{}", logs);
+
+    // missing labels
+    let mut broken_streets = streets();
+    broken_streets[1].label = "".to_string();
+    broken_streets[2].label = "".to_string();
+    index_streets(&mut es, "munin_street_validate_labels", broken_streets);
+    let (success, logs) = validate(&es, "munin_street_validate_labels", &[]);
+    assert!(!success);
+    assert!(logs.contains("2 missing labels on 4 documents"), "{}", logs);
+    let (success, logs) = validate(
+        &es,
+        "munin_street_validate_labels",
+        &["--max-missing-label-ratio=0.5"],
+    );
+    assert!(success, "{}", logs);
+
+    // missing coords
+    let mut broken_streets = streets();
+    broken_streets[3].coord = Coord::default();
+    broken_streets[3].approx_coord = None;
+    index_streets(&mut es, "munin_street_validate_coords", broken_streets);
+    let (success, logs) = validate(&es, "munin_street_validate_coords", &[]);
+    assert!(!success);
+    assert!(logs.contains("1 missing coords on 4 documents"), "{}", logs);
+
+    // an admin with the id of a street
+    index_streets(&mut es, "munin_street_validate_ids", streets());
+    let admin = Admin {
+        id: "street:2".to_string(),
+        name: "Paris".to_string(),
+        label: "Paris".to_string(),
+        coord,
+        ..Default::default()
+    };
+    es.rubber
+        .bulk_index(
+            &TypedIndex::new("munin_street_validate_ids".to_string()),
+            std::iter::once(admin),
+        )
+        .unwrap();
+    es.refresh();
+    let (success, logs) = validate(&es, "munin_street_validate_ids", &[]);
+    assert!(!success);
+    assert!(logs.contains("1 duplicate ids"), "{}", logs);
+
+    // without admin in ES, the imported addresses are not attached to an admin
+    let bano2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../bano2mimir")
+        .display()
+        .to_string();
+    let status = Command::new(&bano2mimir)
+        .args(&[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es.host()),
+            "--validate".into(),
+        ])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(es
+        .rubber
+        .get_all_aliased_index("munin_addr")
+        .unwrap()
+        .is_empty());
+
+    crate::launch_and_assert(
+        &bano2mimir,
+        &[
+            "--input=./tests/fixtures/sample-bano.csv".into(),
+            format!("--connection-string={}", es.host()),
+            "--validate".into(),
+            "--min-addr-with-admins-ratio=0".into(),
+        ],
+        &es,
+    );
+    assert!(!es
+        .rubber
+        .get_all_aliased_index("munin_addr")
+        .unwrap()
+        .is_empty());
+}
+
+/// Export a street index with `mimir_admin export`, in several batches
+pub fn mimir_admin_export_test(mut es: crate::ElasticSearchWrapper<'_>) {
+    let coord = Coord::new(2.376_379, 48.846_495);
+    let streets: Vec<Street> = (1..=5)
+        .map(|i| Street {
+            id: format!("street:{}", i),
+            name: format!("rue {}", i),
+            label: format!("rue {}", i),
+            coord,
+            approx_coord: Some(coord.into()),
+            ..Default::default()
+        })
+        .collect();
+    index_streets(&mut es, "munin_street_export", streets);
+
+    // the scroll is read to completion, page by page
+    let options = ScrollOptions {
+        page_size: 2,
+        sorted_by_id: false,
+        ..Default::default()
+    };
+    let documents: Vec<Value> = es
+        .rubber
+        .scroll("munin_street_export", &options)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(documents.len(), 5);
+    // an unknown index is an error
+    assert!(es.rubber.scroll("munin_street_unknown", &options).is_err());
+
+    let out_dir = Path::new(env!("OUT_DIR"));
+    let mimir_admin = out_dir.join("../../../mimir_admin").display().to_string();
+    let export_file = out_dir.join("mimir_admin_export.ndjson");
+    crate::launch_and_assert(
+        &mimir_admin,
+        &[
+            format!("--connection-string={}", es.host()),
+            "export".into(),
+            "munin_street_export".into(),
+            format!("--output={}", export_file.display()),
+            "--batch-size=2".into(),
+            "--sorted".into(),
+        ],
+        &es,
+    );
+    let exported = read_ndjson(&export_file);
+    let ids: Vec<&Value> = exported.iter().map(|document| &document["id"]).collect();
+    assert_eq!(
+        ids,
+        vec![
+            &json!("street:1"),
+            &json!("street:2"),
+            &json!("street:3"),
+            &json!("street:4"),
+            &json!("street:5"),
+        ]
+    );
+    assert_eq!(exported[0]["label"], json!("rue 1"));
+}
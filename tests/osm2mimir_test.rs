@@ -82,6 +82,70 @@ pub fn osm2mimir_sample_test_sqlite(es_wrapper: crate::ElasticSearchWrapper<'_>)
     check_results(es_wrapper, "sqlite backend");
 }
 
+/// "Rue de Villiers" crosses the boundary of Neuilly-sur-Seine and Levallois-Perret:
+/// it gives a street in each city, or a single street in both cities with
+/// `--merge-streets-across-admins`
+pub fn osm2mimir_merge_streets_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
+    let osm2mimir = Path::new(env!("OUT_DIR"))
+        .join("../../../osm2mimir")
+        .display()
+        .to_string();
+    let import_villiers_streets = |merge: bool| -> Vec<mimir::Street> {
+        crate::launch_and_assert(
+            &osm2mimir,
+            &[
+                "--input=./tests/fixtures/osm_fixture.osm.pbf".into(),
+                "--import-way=true".into(),
+                "--import-admin=true".into(),
+                "--level=8".into(),
+                format!("--merge-streets-across-admins={}", merge),
+                format!("--connection-string={}", es_wrapper.host()),
+            ],
+            &es_wrapper,
+        );
+        es_wrapper
+            .search_and_filter("label:Rue de Villiers", |place| place.is_street())
+            .filter_map(|place| match place {
+                mimir::Place::Street(street) if street.name == "Rue de Villiers" => Some(street),
+                _ => None,
+            })
+            .collect()
+    };
+    let cities = |street: &mimir::Street| -> Vec<String> {
+        let mut cities: Vec<String> = street
+            .administrative_regions
+            .iter()
+            .filter(|admin| admin.is_city())
+            .map(|admin| admin.name.clone())
+            .collect();
+        cities.sort();
+        cities
+    };
+
+    let streets = import_villiers_streets(false);
+    assert_eq!(streets.len(), 2);
+    let mut all_cities: Vec<Vec<String>> = streets.iter().map(cities).collect();
+    all_cities.sort();
+    assert_eq!(
+        all_cities,
+        vec![vec!["Levallois-Perret"], vec!["Neuilly-sur-Seine"]]
+    );
+    assert!(streets.iter().all(|street| street.old_ids.is_empty()));
+    let nb_lines = |street: &mimir::Street| street.coord_line.as_ref().map_or(0, |l| l.0.len());
+    let nb_lines_by_city: usize = streets.iter().map(nb_lines).sum();
+
+    let streets = import_villiers_streets(true);
+    assert_eq!(streets.len(), 1);
+    assert_eq!(
+        cities(&streets[0]),
+        vec!["Levallois-Perret", "Neuilly-sur-Seine"]
+    );
+    assert_eq!(streets[0].old_ids.len(), 1);
+    // the geometry of the merged street has the ways of both cities
+    assert_eq!(nb_lines(&streets[0]), nb_lines_by_city);
+    assert!(nb_lines(&streets[0]) >= 2);
+}
+
 fn check_results(es_wrapper: crate::ElasticSearchWrapper<'_>, test_name: &str) {
     // Test: Import of Admin
     let res: Vec<_> = es_wrapper
@@ -143,6 +207,27 @@ fn check_results(es_wrapper: crate::ElasticSearchWrapper<'_>, test_name: &str) {
         test_name
     );
 
+    // The streets are indexed with the (simplified) geometry of their ways, going along their coord
+    if let mimir::Place::Street(ref street) = res[0] {
+        let lines = street
+            .coord_line
+            .as_ref()
+            .expect("the street has no geometry");
+        assert!(!lines.0.is_empty(), "{}", test_name);
+        assert!(
+            lines.0.iter().all(|line| line.0.len() >= 2),
+            "{}",
+            test_name
+        );
+        let (closest, _) = mimir::objects::closest_point_on_lines(lines, &street.coord).unwrap();
+        assert!(
+            (closest.lon() - street.coord.lon()).abs() < 1e-4
+                && (closest.lat() - street.coord.lat()).abs() < 1e-4,
+            "{}",
+            test_name
+        );
+    }
+
     // Test: Search for "Rue du Four à Chaux" in "Livry-sur-Seine"
     let place_filter = |place: &mimir::Place| {
         place.is_street()
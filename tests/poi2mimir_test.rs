@@ -160,7 +160,7 @@ pub fn poi2mimir_sample_test(es_wrapper: crate::ElasticSearchWrapper<'_>) {
     // We test that the poi_type has been normalized (ie its poi_type id has been prefixed by
     // 'poi_type'
     match agence_du_four {
-        mimir::Place::Poi(ref poi) => assert_eq!(poi.poi_type.id, "poi_type:TCL:AGE"),
+        mimir::Place::Poi(ref poi) => assert_eq!(poi.poi_type.id, "poi_type:tcl:age"),
         _ => panic!("should have been a poi"),
     }
 
@@ -28,9 +28,11 @@
 // https://groups.google.com/d/forum/navitia
 // www.navitia.io
 
+use super::BragiHandler;
 use cosmogony::ZoneType;
 use geo::prelude::BoundingRect;
-use mimir::rubber::{self, IndexSettings, Rubber};
+use mimir::checkpoint::{self, Checkpoint, CheckpointSettings};
+use mimir::rubber::{self, IndexSettings, IndexSettingsOverride, Rubber};
 use mimir::{Admin, Coord, MimirObject, Street};
 use serde_json::{json, Value};
 
@@ -288,6 +290,47 @@ pub fn rubber_ghost_index_cleanup(mut es: crate::ElasticSearchWrapper<'_>) {
     assert!(!get_munin_indexes(&es).contains(&old_idx_name.to_string()));
 }
 
+/// check that the previous generations of an index are kept with `keep_previous`
+pub fn rubber_keep_previous_generations(es: crate::ElasticSearchWrapper<'_>) {
+    let mut rubber = Rubber::new(&es.host()).with_keep_previous(1);
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    let coord = Coord::new(2.683_262_90f64, 48.511_072_2f64);
+    let mut generations = vec![];
+    for name in &["generation 1", "generation 2", "generation 3"] {
+        let street = Street {
+            id: "street:bob".to_string(),
+            name: name.to_string(),
+            label: name.to_string(),
+            weight: 0.42,
+            coord,
+            approx_coord: Some(coord.into()),
+            ..Default::default()
+        };
+        let index = rubber.make_index::<Street>("fr", &index_settings).unwrap();
+        generations.push(index.name().to_string());
+        rubber.bulk_index(&index, std::iter::once(street)).unwrap();
+        rubber
+            .publish_index("fr", index, rubber::IndexVisibility::Public)
+            .unwrap();
+    }
+
+    // only the oldest generation has been deleted
+    let mut street_indexes: Vec<_> = get_munin_indexes(&es)
+        .into_iter()
+        .filter(|i| i.starts_with("munin_street_fr_"))
+        .collect();
+    street_indexes.sort();
+    assert_eq!(street_indexes, generations[1..].to_vec());
+
+    // the alias points to the newest generation
+    let aliases = es.rubber.get_all_aliased_index("munin_street_fr").unwrap();
+    assert!(aliases[&generations[2]].contains(&"munin_street_fr".to_string()));
+    assert!(!aliases[&generations[1]].contains(&"munin_street_fr".to_string()));
+}
+
 // return the list of the munin indexes
 fn get_munin_indexes(es: &crate::ElasticSearchWrapper<'_>) -> Vec<String> {
     let client = reqwest::blocking::Client::new();
@@ -323,3 +366,197 @@ pub fn rubber_empty_bulk(mut es: crate::ElasticSearchWrapper<'_>) {
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 0); // we have indexed nothing, but it's ok
 }
+
+/// a street re-imported with a new id should keep its old id,
+/// and still be found by the features endpoint with its old id
+pub fn rubber_alias_old_ids(mut es: crate::ElasticSearchWrapper<'_>) {
+    info!("running rubber_alias_old_ids");
+    let dataset = "my_dataset";
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 1,
+    };
+    let coord = Coord::new(2.376_379, 48.846_495);
+    let make_street = |id: &str| Street {
+        id: id.to_string(),
+        name: "rue des Pirogues de Bercy".to_string(),
+        label: "rue des Pirogues de Bercy (Paris)".to_string(),
+        weight: 0.42,
+        coord,
+        approx_coord: Some(coord.into()),
+        ..Default::default()
+    };
+
+    let result = es.rubber.public_index(
+        dataset,
+        &index_settings,
+        std::iter::once(make_street("street:old")),
+    );
+    assert_eq!(result.unwrap(), 1);
+    es.refresh();
+
+    // the street is imported again, but with a new id
+    let mut streets = vec![make_street("street:new")];
+    let nb_aliased = es.rubber.alias_old_ids(dataset, &mut streets).unwrap();
+    assert_eq!(nb_aliased, 1);
+    assert_eq!(streets[0].old_ids, vec!["street:old".to_string()]);
+
+    let result = es
+        .rubber
+        .public_index(dataset, &index_settings, streets.into_iter());
+    assert_eq!(result.unwrap(), 1);
+    es.refresh();
+
+    let mut bragi = BragiHandler::new(es.host());
+    let features = bragi.get("/features/street:old");
+    assert_eq!(features.len(), 1);
+    assert_eq!(features[0]["id"], json!("street:new"));
+    assert_eq!(features[0]["moved_from"], json!("street:old"));
+
+    // with its new id, the street has not moved
+    let features = bragi.get("/features/street:new");
+    assert_eq!(features.len(), 1);
+    assert_eq!(features[0]["id"], json!("street:new"));
+    assert!(features[0].get("moved_from").is_none());
+}
+
+/// an import interrupted after a checkpoint is resumed from it,
+/// and gives the same documents as an uninterrupted import
+pub fn rubber_checkpoint_resume(mut es: crate::ElasticSearchWrapper<'_>) {
+    info!("running rubber_checkpoint_resume");
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    let coord = Coord::new(2.376_379, 48.846_495);
+    let make_streets = || {
+        (0..5000).map(move |i| Street {
+            id: format!("street:{}", i),
+            name: format!("rue {}", i),
+            label: format!("rue {} (Paris)", i),
+            weight: 0.42,
+            coord,
+            approx_coord: Some(coord.into()),
+            ..Default::default()
+        })
+    };
+    let checkpoint_dir = std::env::temp_dir().join("mimir_rubber_checkpoint_test");
+    let checkpoint_settings = |resume| CheckpointSettings {
+        dir: checkpoint_dir.clone(),
+        every: 1000,
+        resume,
+        source_checksum: "streets-v1".to_string(),
+    };
+    let checkpoint_path = checkpoint::checkpoint_path(&checkpoint_dir, "munin_street_resumed");
+
+    let nb_indexed = Rubber::new(&es.host())
+        .public_index("uninterrupted", &index_settings, make_streets())
+        .unwrap();
+    assert_eq!(nb_indexed, 5000);
+
+    // the import is interrupted after 2500 streets, the index is not published
+    let mut rubber = Rubber::new(&es.host()).with_checkpoint(checkpoint_settings(false));
+    let index = rubber
+        .make_index::<Street>("resumed", &index_settings)
+        .unwrap();
+    let nb_indexed = rubber
+        .bulk_index(&index, make_streets().take(2500))
+        .unwrap();
+    assert_eq!(nb_indexed, 2500);
+    let checkpoint = checkpoint::read(&checkpoint_path).unwrap().unwrap();
+    assert_eq!(checkpoint.index, index.name());
+    assert_eq!(checkpoint.nb_documents, 2500);
+
+    // as if the process had been killed before its last checkpoint:
+    // the 500 streets indexed after the checkpoint are indexed again when resuming
+    checkpoint::write(
+        &checkpoint_path,
+        &Checkpoint {
+            nb_documents: 2000,
+            ..checkpoint.clone()
+        },
+    )
+    .unwrap();
+
+    // the import of another source cannot be resumed
+    let other_source = Rubber::new(&es.host()).with_checkpoint(CheckpointSettings {
+        source_checksum: "streets-v2".to_string(),
+        ..checkpoint_settings(true)
+    });
+    assert!(other_source
+        .make_index::<Street>("resumed", &index_settings)
+        .is_err());
+
+    let mut rubber = Rubber::new(&es.host()).with_checkpoint(checkpoint_settings(true));
+    let index = rubber
+        .make_index::<Street>("resumed", &index_settings)
+        .unwrap();
+    assert_eq!(index.name(), checkpoint.index);
+    let nb_indexed = rubber.bulk_index(&index, make_streets()).unwrap();
+    assert_eq!(nb_indexed, 3000);
+    rubber
+        .publish_index("resumed", index, rubber::IndexVisibility::Public)
+        .unwrap();
+    // the checkpoint is removed once the index is published
+    assert_eq!(checkpoint::read(&checkpoint_path).unwrap(), None);
+    es.refresh();
+
+    let uninterrupted: Vec<Street> = es
+        .rubber
+        .get_all_objects_from_index("munin_street_uninterrupted")
+        .unwrap();
+    let resumed: Vec<Street> = es
+        .rubber
+        .get_all_objects_from_index("munin_street_resumed")
+        .unwrap();
+    assert_eq!(uninterrupted.len(), 5000);
+    assert_eq!(resumed.len(), uninterrupted.len());
+}
+
+/// the settings of the indexes of a dataset can be overridden,
+/// by type of document or for all the types of the dataset
+pub fn rubber_index_settings_overrides(es: crate::ElasticSearchWrapper<'_>) {
+    info!("running rubber_index_settings_overrides");
+    let index_settings = IndexSettings {
+        nb_shards: 1,
+        nb_replicas: 0,
+    };
+    let mut overrides = std::collections::BTreeMap::new();
+    overrides.insert(
+        "street_big".to_string(),
+        IndexSettingsOverride {
+            nb_shards: Some(3),
+            settings: Some(json!({ "max_result_window": 20000 })),
+            ..Default::default()
+        },
+    );
+    overrides.insert(
+        "big".to_string(),
+        IndexSettingsOverride {
+            nb_shards: Some(2),
+            ..Default::default()
+        },
+    );
+    let mut rubber = Rubber::new(&es.host()).with_index_settings_overrides(overrides);
+
+    let index_settings_of = |rubber: &mut Rubber, dataset: &str| {
+        let index = rubber
+            .make_index::<Street>(dataset, &index_settings)
+            .unwrap();
+        let index_info = get_index_info(&es, index.name());
+        index_info
+            .pointer(&format!("/{}/settings/index", index.name()))
+            .unwrap()
+            .clone()
+    };
+
+    let settings = index_settings_of(&mut rubber, "big");
+    assert_eq!(settings.get("number_of_shards"), Some(&json!("3")));
+    assert_eq!(settings.get("number_of_replicas"), Some(&json!("0")));
+    assert_eq!(settings.get("max_result_window"), Some(&json!("20000")));
+
+    // the other datasets keep the settings given to the import
+    let settings = index_settings_of(&mut rubber, "small");
+    assert_eq!(settings.get("number_of_shards"), Some(&json!("1")));
+    assert_eq!(settings.get("max_result_window"), None);
+}
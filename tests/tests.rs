@@ -37,7 +37,10 @@ extern crate assert_float_eq;
 
 mod bano2mimir_test;
 mod bragi_bano_test;
+mod bragi_datasets_test;
+mod bragi_exact_name_test;
 mod bragi_filter_types_test;
+mod bragi_focus_bbox_test;
 mod bragi_ntfs_test;
 mod bragi_osm_test;
 mod bragi_poi_test;
@@ -47,6 +50,7 @@ mod bragi_synonyms_test;
 mod bragi_three_cities_test;
 mod canonical_import_process_test;
 mod cosmogony2mimir_test;
+mod mimir_admin_test;
 mod openaddresses2mimir_test;
 mod osm2mimir_bano2mimir_test;
 mod osm2mimir_test;
@@ -125,6 +129,62 @@ fn get_first_index_aliases(indexes: &serde_json::Map<String, Value>) -> Vec<Stri
         .unwrap_or_else(Vec::new)
 }
 
+/// Bragi with the default settings (`config/bragi-settings.toml`) and `overrides` (in TOML)
+/// merged into them, written to `name` in `OUT_DIR`.
+///
+/// The values overridden must be in the default settings, so that a typo or a renamed setting
+/// fails the test instead of being ignored. Only whole tables can be added (like the optional
+/// sections commented out in the default settings).
+pub fn bragi_with_settings(es_host: String, name: &str, overrides: &str) -> BragiHandler {
+    let mut settings: toml::Value =
+        toml::from_str(include_str!("../config/bragi-settings.toml")).unwrap();
+    let overrides: toml::Value = toml::from_str(overrides)
+        .unwrap_or_else(|e| panic!("invalid settings overrides for `{}`: {}", name, e));
+    override_settings(&mut settings, overrides, "");
+    let settings_file = std::path::Path::new(env!("OUT_DIR")).join(name);
+    std::fs::write(&settings_file, toml::to_string(&settings).unwrap()).unwrap();
+    BragiHandler::new_with_args(bragi::Args {
+        connection_string: es_host,
+        weight_config_file: Some(settings_file.display().to_string()),
+        ..Default::default()
+    })
+}
+
+fn is_table(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Table(_) => true,
+        toml::Value::Array(values) => {
+            !values.is_empty() && values.iter().all(toml::Value::is_table)
+        }
+        _ => false,
+    }
+}
+
+// merges the tables recursively, the other values of `overrides` replace the settings
+fn override_settings(settings: &mut toml::Value, overrides: toml::Value, path: &str) {
+    let (settings, overrides) = match (settings, overrides) {
+        (toml::Value::Table(settings), toml::Value::Table(overrides)) => (settings, overrides),
+        (settings, overrides) => {
+            *settings = overrides;
+            return;
+        }
+    };
+    for (key, value) in overrides {
+        let key_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match settings.get_mut(&key) {
+            Some(setting) => override_settings(setting, value, &key_path),
+            None if is_table(&value) => {
+                settings.insert(key, value);
+            }
+            None => panic!("`{}` is not in the default settings", key_path),
+        }
+    }
+}
+
 /// Main test method (regroups all tests)
 /// All tests are done sequentially,
 /// and use the same docker in order to avoid multiple inits
@@ -136,7 +196,11 @@ fn all_tests() {
 
     // we call all tests here
     bano2mimir_test::bano2mimir_sample_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bano2mimir_test::bano2mimir_report_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bano2mimir_test::bano2mimir_bbox_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bano2mimir_test::bano2mimir_strict_test(ElasticSearchWrapper::new(&docker_wrapper));
     osm2mimir_test::osm2mimir_sample_test(ElasticSearchWrapper::new(&docker_wrapper));
+    osm2mimir_test::osm2mimir_merge_streets_test(ElasticSearchWrapper::new(&docker_wrapper));
 
     #[cfg(feature = "db-storage")]
     osm2mimir_test::osm2mimir_sample_test_sqlite(ElasticSearchWrapper::new(&docker_wrapper));
@@ -149,24 +213,58 @@ fn all_tests() {
     rubber_test::rubber_zero_downtime_test(ElasticSearchWrapper::new(&docker_wrapper));
     rubber_test::rubber_custom_id(ElasticSearchWrapper::new(&docker_wrapper));
     rubber_test::rubber_ghost_index_cleanup(ElasticSearchWrapper::new(&docker_wrapper));
+    rubber_test::rubber_keep_previous_generations(ElasticSearchWrapper::new(&docker_wrapper));
     rubber_test::rubber_empty_bulk(ElasticSearchWrapper::new(&docker_wrapper));
+    rubber_test::rubber_alias_old_ids(ElasticSearchWrapper::new(&docker_wrapper));
+    rubber_test::rubber_checkpoint_resume(ElasticSearchWrapper::new(&docker_wrapper));
+    rubber_test::rubber_index_settings_overrides(ElasticSearchWrapper::new(&docker_wrapper));
+    mimir_admin_test::mimir_admin_diff_test(ElasticSearchWrapper::new(&docker_wrapper));
+    mimir_admin_test::mimir_admin_mimirload_test(ElasticSearchWrapper::new(&docker_wrapper));
+    mimir_admin_test::mimir_admin_validate_test(ElasticSearchWrapper::new(&docker_wrapper));
+    mimir_admin_test::mimir_admin_export_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_bano_test::bragi_bano_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_bano_test::bragi_house_number_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_datasets_test::bragi_datasets_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_datasets_test::bragi_flags_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_osm_test::bragi_osm_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_poi_test::test_i18n_poi(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::test_alt_names_poi(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::test_alt_names_street(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::test_tie_breaker_poi(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::test_recency_poi(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_three_cities_test::bragi_three_cities_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_focus_bbox_test::bragi_focus_bbox_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_focus_bbox_test::bragi_default_coord_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_exact_name_test::bragi_exact_name_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_poi_test::bragi_poi_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_poi_test::bragi_private_poi_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::bragi_poi_clusters_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::bragi_nearby_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_poi_test::bragi_refresh_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_stops_test::bragi_stops_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_stops_test::bragi_stop_validity_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_stops_test::bragi_word_order_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_ntfs_test::bragi_ntfs_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_filter_types_test::bragi_filter_types_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_synonyms_test::bragi_synonyms_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_synonyms_test::bragi_index_synonyms_test(ElasticSearchWrapper::new(&docker_wrapper));
     bragi_postcode_test::bragi_postcode_test(ElasticSearchWrapper::new(&docker_wrapper));
+    bragi_postcode_test::bragi_postcode_prefix_test(ElasticSearchWrapper::new(&docker_wrapper));
     openaddresses2mimir_test::oa2mimir_simple_test(ElasticSearchWrapper::new(&docker_wrapper));
     cosmogony2mimir_test::cosmogony2mimir_test(ElasticSearchWrapper::new(&docker_wrapper));
+    cosmogony2mimir_test::cosmogony2mimir_zone_type_weight_test(ElasticSearchWrapper::new(
+        &docker_wrapper,
+    ));
     canonical_import_process_test::canonical_import_process_test(ElasticSearchWrapper::new(
         &docker_wrapper,
     ));
     canonical_import_process_test::bragi_invalid_es_test(ElasticSearchWrapper::new(
         &docker_wrapper,
     ));
+    canonical_import_process_test::bragi_circuit_breaker_test(ElasticSearchWrapper::new(
+        &docker_wrapper,
+    ));
+    canonical_import_process_test::bragi_readiness_test(ElasticSearchWrapper::new(&docker_wrapper));
+    canonical_import_process_test::bragi_run_mode_test(ElasticSearchWrapper::new(&docker_wrapper));
+    canonical_import_process_test::bragi_settings_test(ElasticSearchWrapper::new(&docker_wrapper));
 }